@@ -211,7 +211,7 @@ impl Interface {
                     let mut y = 0;
 
                     for glyph in glyph_buffer.iter() {
-                        let ix = ensure_glyph_is_in_alphabet_and_return_index(&mut builder, alphabet, buffer.font(), glyph.id);
+                        let ix = ensure_glyph_is_in_alphabet_and_return_index(&mut builder, alphabet, &buffer.font(), glyph.id);
                         flatland_group_items.push(FlatlandItem {
                             alphabet_entry_index: ix,
                             x_offset: x,
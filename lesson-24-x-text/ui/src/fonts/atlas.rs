@@ -0,0 +1,492 @@
+//! Packing of rasterized glyphs into texture pages.
+
+use metrohash::MetroHashMap;
+use super::{Fonts, Font, ContainerToken, GlyphBitmap, HintingOptions, SizeBucket};
+#[cfg(feature = "color-bitmaps")]
+use super::GlyphImage;
+
+/// Empty pixels kept between glyphs so that linear filtering does not bleed.
+const GLYPH_PADDING: u32 = 1;
+
+pub const DEFAULT_SUBPIXEL_PHASES: u32 = 4;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+struct AtlasKey {
+    font_id: usize,
+    glyph_id: u32,
+    size: SizeBucket,
+    phase: u32,
+}
+
+/// Memory use of the atlas, to tune page size and subpixel phases.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AtlasStats {
+    pub pages: usize,
+    pub page_bytes: usize,
+    /// Rasterized glyphs per subpixel phase.
+    pub entries_per_phase: Vec<usize>,
+    /// Bitmap pixels per subpixel phase, excluding padding.
+    pub pixels_per_phase: Vec<u64>,
+}
+
+/// Pixel layout of an atlas page.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PageFormat {
+    /// One coverage byte per pixel.
+    Alpha,
+    /// Four bytes per pixel, used for color glyph images.
+    Rgba,
+}
+
+impl PageFormat {
+    pub fn bytes_per_pixel(self) -> u32 {
+        match self {
+            PageFormat::Alpha => 1,
+            PageFormat::Rgba => 4,
+        }
+    }
+}
+
+/// Pixels of a glyph ready to be packed.
+struct GlyphPixels<'a> {
+    width: u32,
+    height: u32,
+    left: i32,
+    top: i32,
+    advance: f32,
+    format: PageFormat,
+    data: &'a [u8],
+}
+
+impl<'a> GlyphPixels<'a> {
+    fn from_bitmap(bitmap: &'a GlyphBitmap) -> GlyphPixels<'a> {
+        GlyphPixels {
+            width: bitmap.width,
+            height: bitmap.height,
+            left: bitmap.left,
+            top: bitmap.top,
+            advance: bitmap.advance,
+            format: PageFormat::Alpha,
+            data: &bitmap.data,
+        }
+    }
+
+    #[cfg(feature = "color-bitmaps")]
+    fn from_image(image: &'a GlyphImage) -> GlyphPixels<'a> {
+        GlyphPixels {
+            width: image.width,
+            height: image.height,
+            left: image.left,
+            top: image.top,
+            advance: image.advance,
+            format: PageFormat::Rgba,
+            data: &image.rgba,
+        }
+    }
+}
+
+/// Location of a glyph in the atlas.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AtlasEntry {
+    pub page: usize,
+    /// The glyph is a color image on an `Rgba` page.
+    pub color: bool,
+    /// `[u0, v0, u1, v1]` with the origin at the top left of the page.
+    pub uv_rect: [f32; 4],
+    /// Bitmap size in pixels.
+    pub size: (u32, u32),
+    /// Pixels from the glyph origin to the left and top edges of the bitmap, top is positive up.
+    pub bearing: (i32, i32),
+    /// Horizontal advance in pixels.
+    pub advance: f32,
+    /// Physical pixels per logical pixel the glyph was rasterized at, see `Fonts::set_scale_factor`.
+    ///
+    /// `size`, `bearing` and `advance` are in physical pixels.
+    pub scale_factor: f32,
+}
+
+/// Region of a page that changed since it was last uploaded.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DirtyRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl DirtyRect {
+    fn union(self, other: DirtyRect) -> DirtyRect {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        DirtyRect {
+            x,
+            y,
+            width: (self.x + self.width).max(other.x + other.width) - x,
+            height: (self.y + self.height).max(other.y + other.height) - y,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Shelf {
+    y: u32,
+    height: u32,
+    next_x: u32,
+}
+
+/// Texture filled with shelves of glyphs.
+pub struct AtlasPage {
+    size: u32,
+    format: PageFormat,
+    data: Vec<u8>,
+    shelves: Vec<Shelf>,
+    next_y: u32,
+    dirty: Option<DirtyRect>,
+    last_used: u64,
+}
+
+impl AtlasPage {
+    fn new(size: u32, format: PageFormat) -> AtlasPage {
+        AtlasPage {
+            size,
+            format,
+            data: vec![0; (size * size * format.bytes_per_pixel()) as usize],
+            shelves: Vec::new(),
+            next_y: 0,
+            dirty: None,
+            last_used: 0,
+        }
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    pub fn format(&self) -> PageFormat {
+        self.format
+    }
+
+    /// Row-major pixels, `size * size * format.bytes_per_pixel()` bytes.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn dirty_rect(&self) -> Option<DirtyRect> {
+        self.dirty
+    }
+
+    /// Returns the changed region and marks the page as uploaded.
+    pub fn take_dirty_rect(&mut self) -> Option<DirtyRect> {
+        self.dirty.take()
+    }
+
+    fn reset(&mut self, format: PageFormat) {
+        if format != self.format {
+            self.format = format;
+            self.data = vec![0; (self.size * self.size * format.bytes_per_pixel()) as usize];
+        }
+        for value in &mut self.data {
+            *value = 0;
+        }
+        self.shelves.clear();
+        self.next_y = 0;
+        self.dirty = Some(DirtyRect { x: 0, y: 0, width: self.size, height: self.size });
+    }
+
+    /// Finds space for a `width` x `height` rectangle, returns its top left corner.
+    fn allocate(&mut self, width: u32, height: u32) -> Option<(u32, u32)> {
+        let padded_width = width + GLYPH_PADDING;
+        let padded_height = height + GLYPH_PADDING;
+
+        let size = self.size;
+        let best = self.shelves.iter_mut()
+            .filter(|shelf| shelf.height >= padded_height && shelf.next_x + padded_width <= size)
+            .min_by_key(|shelf| shelf.height);
+
+        if let Some(shelf) = best {
+            let x = shelf.next_x;
+            shelf.next_x += padded_width;
+            return Some((x, shelf.y));
+        }
+
+        if self.next_y + padded_height > self.size || padded_width > self.size {
+            return None;
+        }
+
+        let y = self.next_y;
+        self.next_y += padded_height;
+        self.shelves.push(Shelf { y, height: padded_height, next_x: padded_width });
+        Some((0, y))
+    }
+
+    fn write(&mut self, x: u32, y: u32, glyph: &GlyphPixels) {
+        let bpp = self.format.bytes_per_pixel();
+        let row_len = (glyph.width * bpp) as usize;
+        for row in 0..glyph.height {
+            let src = (row * glyph.width * bpp) as usize;
+            let dst = (((y + row) * self.size + x) * bpp) as usize;
+            self.data[dst..dst + row_len].copy_from_slice(&glyph.data[src..src + row_len]);
+        }
+
+        let rect = DirtyRect { x, y, width: glyph.width, height: glyph.height };
+        self.dirty = Some(match self.dirty {
+            Some(dirty) => dirty.union(rect),
+            None => rect,
+        });
+    }
+}
+
+/// Rasterizes glyphs on demand and packs them into square pages.
+///
+/// When all pages are full, the least recently used page is cleared and its
+/// entries are dropped, so entries should be looked up again every frame.
+pub struct GlyphAtlas {
+    page_size: u32,
+    max_pages: usize,
+    subpixel_phases: u32,
+    pages: Vec<AtlasPage>,
+    entries: MetroHashMap<AtlasKey, Option<AtlasEntry>>,
+    tick: u64,
+    /// Container of the fonts of the entries, checked in debug builds since keys are font ids.
+    container: Option<ContainerToken>,
+}
+
+impl GlyphAtlas {
+    pub fn new(page_size: u32, max_pages: usize) -> GlyphAtlas {
+        GlyphAtlas {
+            page_size,
+            max_pages: max_pages.max(1),
+            subpixel_phases: DEFAULT_SUBPIXEL_PHASES,
+            pages: Vec::new(),
+            entries: MetroHashMap::default(),
+            tick: 0,
+            container: None,
+        }
+    }
+
+    /// Number of horizontal subpixel positions a glyph can be rasterized at.
+    pub fn subpixel_phases(&self) -> u32 {
+        self.subpixel_phases
+    }
+
+    /// Changes the number of subpixel phases, dropping all entries.
+    pub fn set_subpixel_phases(&mut self, phases: u32) {
+        self.subpixel_phases = phases.max(1);
+        self.clear();
+    }
+
+    pub fn stats(&self) -> AtlasStats {
+        let phases = self.subpixel_phases as usize;
+        let mut stats = AtlasStats {
+            pages: self.pages.len(),
+            page_bytes: self.pages.iter().map(|p| p.data.len()).sum(),
+            entries_per_phase: vec![0; phases],
+            pixels_per_phase: vec![0; phases],
+        };
+
+        for (key, entry) in &self.entries {
+            if let Some(entry) = entry {
+                let phase = (key.phase as usize).min(phases - 1);
+                stats.entries_per_phase[phase] += 1;
+                stats.pixels_per_phase[phase] += u64::from(entry.size.0) * u64::from(entry.size.1);
+            }
+        }
+
+        stats
+    }
+
+    pub fn pages(&self) -> &[AtlasPage] {
+        &self.pages
+    }
+
+    pub fn pages_mut(&mut self) -> &mut [AtlasPage] {
+        &mut self.pages
+    }
+
+    /// Returns the atlas entry of a glyph, rasterizing and packing it if missing.
+    ///
+    /// `px_size` is in logical pixels, glyphs are rasterized at the scale factor of the fonts
+    /// and keyed by physical size. Clear the atlas on `FontsEvent::ScaleFactorChanged` to
+    /// drop glyphs of the previous factor.
+    ///
+    /// Returns `None` for glyphs without an outline, fonts that are no longer loaded
+    /// and glyphs that do not fit into a page.
+    pub fn get(&mut self, fonts: &Fonts, font_id: usize, glyph_id: u32, px_size: f32) -> Option<AtlasEntry> {
+        self.get_with_phase(fonts, font_id, glyph_id, px_size, 0)
+    }
+
+    /// Same as `get`, rasterized with the origin shifted right by `phase / subpixel_phases` pixels.
+    pub fn get_with_phase(&mut self, fonts: &Fonts, font_id: usize, glyph_id: u32, px_size: f32, phase: u32) -> Option<AtlasEntry> {
+        match fonts.font_from_id(font_id) {
+            Some(font) => self.get_for_font(&font, glyph_id, px_size, phase),
+            None => {
+                self.invalidate_font(font_id);
+                None
+            }
+        }
+    }
+
+    /// Same as `get_with_phase` for an already resolved font.
+    pub fn get_for_font(&mut self, font: &Font, glyph_id: u32, px_size: f32, phase: u32) -> Option<AtlasEntry> {
+        if cfg!(debug_assertions) {
+            let token = font.container_token();
+            assert_eq!(*self.container.get_or_insert(token), token, "get_for_font: font belongs to a different Fonts container than the atlas glyphs");
+        }
+        self.tick += 1;
+
+        let phase = phase % self.subpixel_phases;
+        let scale_factor = font.scale_factor();
+        let size = font.size_bucket(px_size * scale_factor);
+        let key = AtlasKey { font_id: font.id, glyph_id, size, phase };
+
+        if let Some(entry) = self.entries.get(&key) {
+            if let Some(entry) = entry {
+                self.pages[entry.page].last_used = self.tick;
+            }
+            return *entry;
+        }
+
+        let px_size = size.px_size() / scale_factor;
+
+        // color images are not shifted by subpixel phases, so they are kept once, as phase 0
+        #[cfg(feature = "color-bitmaps")]
+        {
+            let image_key = AtlasKey { phase: 0, ..key };
+            match self.entries.get(&image_key) {
+                Some(&Some(entry)) if entry.color => {
+                    self.pages[entry.page].last_used = self.tick;
+                    return Some(entry);
+                }
+                Some(_) => {}
+                None => if let Some(image) = font.glyph_image(glyph_id, px_size) {
+                    let entry = self.insert(&GlyphPixels::from_image(&image), scale_factor);
+                    self.entries.insert(image_key, entry);
+                    return entry;
+                },
+            }
+        }
+
+        let origin_offset = (phase as f32 / self.subpixel_phases as f32, 0.0);
+        let entry = font.rasterize_glyph(glyph_id, px_size, HintingOptions::None, origin_offset)
+            .and_then(|bitmap| self.insert(&GlyphPixels::from_bitmap(&bitmap), scale_factor));
+        self.entries.insert(key, entry);
+        entry
+    }
+
+    /// Drops all entries of a font, call on `FontsEvent::FontEvicted` or when the font is
+    /// reloaded. A released font may be used again until it is evicted.
+    ///
+    /// Pixels stay in the pages until the page is evicted.
+    pub fn invalidate_font(&mut self, font_id: usize) {
+        self.entries.retain(|key, _| key.font_id != font_id);
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        for page in &mut self.pages {
+            let format = page.format;
+            page.reset(format);
+        }
+    }
+
+    fn insert(&mut self, glyph: &GlyphPixels, scale_factor: f32) -> Option<AtlasEntry> {
+        let (page, x, y) = self.allocate(glyph.width, glyph.height, glyph.format)?;
+        self.pages[page].write(x, y, glyph);
+        self.pages[page].last_used = self.tick;
+
+        let size = self.page_size as f32;
+        Some(AtlasEntry {
+            page,
+            color: glyph.format == PageFormat::Rgba,
+            uv_rect: [
+                x as f32 / size,
+                y as f32 / size,
+                (x + glyph.width) as f32 / size,
+                (y + glyph.height) as f32 / size,
+            ],
+            size: (glyph.width, glyph.height),
+            bearing: (glyph.left, glyph.top),
+            advance: glyph.advance,
+            scale_factor,
+        })
+    }
+
+    fn allocate(&mut self, width: u32, height: u32, format: PageFormat) -> Option<(usize, u32, u32)> {
+        if width + GLYPH_PADDING > self.page_size || height + GLYPH_PADDING > self.page_size {
+            return None;
+        }
+
+        for (index, page) in self.pages.iter_mut().enumerate().filter(|(_, page)| page.format == format) {
+            if let Some((x, y)) = page.allocate(width, height) {
+                return Some((index, x, y));
+            }
+        }
+
+        let index = if self.pages.len() < self.max_pages {
+            self.pages.push(AtlasPage::new(self.page_size, format));
+            self.pages.len() - 1
+        } else {
+            let index = self.pages.iter().enumerate()
+                .min_by_key(|(_, page)| page.last_used)
+                .map(|(index, _)| index)
+                .expect("allocate: atlas should have pages");
+            self.entries.retain(|_, entry| entry.map(|e| e.page != index).unwrap_or(true));
+            self.pages[index].reset(format);
+            index
+        };
+
+        let (x, y) = self.pages[index].allocate(width, height)?;
+        Some((index, x, y))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn bitmap(width: u32, height: u32) -> GlyphBitmap {
+        GlyphBitmap { width, height, left: 0, top: 0, advance: 0.0, data: vec![255; (width * height) as usize] }
+    }
+
+    fn insert(atlas: &mut GlyphAtlas, bitmap: &GlyphBitmap) -> Option<AtlasEntry> {
+        atlas.insert(&GlyphPixels::from_bitmap(bitmap), 1.0)
+    }
+
+    #[test]
+    fn should_pack_glyphs_into_shelves() {
+        let mut atlas = GlyphAtlas::new(16, 1);
+
+        let a = insert(&mut atlas, &bitmap(5, 5)).unwrap();
+        let b = insert(&mut atlas, &bitmap(5, 4)).unwrap();
+        let c = insert(&mut atlas, &bitmap(5, 7)).unwrap();
+
+        assert_eq!(a.uv_rect[0..2], [0.0, 0.0]);
+        assert_eq!(b.uv_rect[0..2], [6.0 / 16.0, 0.0]);
+        assert_eq!(c.uv_rect[0..2], [0.0, 6.0 / 16.0]);
+        assert_eq!(atlas.pages()[0].dirty_rect(), Some(DirtyRect { x: 0, y: 0, width: 11, height: 13 }));
+    }
+
+    #[test]
+    fn stats_should_count_entries_per_phase() {
+        let mut atlas = GlyphAtlas::new(16, 1);
+        let entry = insert(&mut atlas, &bitmap(2, 3));
+        atlas.entries.insert(AtlasKey { font_id: 0, glyph_id: 1, size: SizeBucket::new(16.0, 0.25), phase: 2 }, entry);
+
+        let stats = atlas.stats();
+        assert_eq!(stats.entries_per_phase, vec![0, 0, 1, 0]);
+        assert_eq!(stats.pixels_per_phase, vec![0, 0, 6, 0]);
+        assert_eq!(stats.page_bytes, 256);
+    }
+
+    #[test]
+    fn should_evict_least_recently_used_page_when_full() {
+        let mut atlas = GlyphAtlas::new(8, 2);
+
+        assert_eq!(insert(&mut atlas, &bitmap(7, 7)).unwrap().page, 0);
+        atlas.tick += 1;
+        assert_eq!(insert(&mut atlas, &bitmap(7, 7)).unwrap().page, 1);
+        atlas.tick += 1;
+        assert_eq!(insert(&mut atlas, &bitmap(7, 7)).unwrap().page, 0);
+        assert!(insert(&mut atlas, &bitmap(8, 8)).is_none());
+    }
+}
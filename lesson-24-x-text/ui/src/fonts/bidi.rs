@@ -0,0 +1,205 @@
+use std::ops::Range;
+use super::layout::paragraph_ranges;
+
+/// Text shaped in one direction, see `direction_runs`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirectionRun {
+    pub range: Range<usize>,
+    pub rtl: bool,
+}
+
+/// Direction of the paragraphs of a buffer, see `Buffer::set_base_direction`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum BaseDirection {
+    /// That of the first strong character of each paragraph, left to right without one.
+    FirstStrong,
+    Ltr,
+    Rtl,
+}
+
+impl Default for BaseDirection {
+    fn default() -> Self {
+        BaseDirection::FirstStrong
+    }
+}
+
+/// Strong right-to-left letters and marks of the Hebrew, Arabic, Syriac, Thaana, NKo,
+/// Samaritan and Mandaic blocks and their presentation forms; digits are not included.
+pub fn is_rtl_char(c: char) -> bool {
+    match c as u32 {
+        0x0590..=0x08FF | 0xFB1D..=0xFDFF | 0xFE70..=0xFEFF | 0x10800..=0x10FFF | 0x1E800..=0x1EFFF => !c.is_numeric(),
+        _ => false,
+    }
+}
+
+/// Direction of a strong character, `None` for neutrals such as spaces and brackets.
+fn strong_direction(c: char) -> Option<bool> {
+    if is_rtl_char(c) {
+        Some(true)
+    } else if c.is_alphanumeric() {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+/// Runs of `range` in visual order, each paragraph resolved with the whole paragraph as context.
+///
+/// A simplified Unicode bidi algorithm: the paragraph direction is `base`, or that of its first
+/// strong character with `BaseDirection::FirstStrong`, digits are left-to-right, and neutrals take the direction of the strong characters
+/// around them if both agree, or the paragraph direction otherwise. Runs of a right-to-left
+/// paragraph are reversed; paragraph separators get their own left-to-right run after the
+/// paragraph, so paragraphs stay in logical order.
+#[cfg_attr(feature = "simple-shaping", allow(dead_code))]
+pub fn direction_runs(text: &str, range: Range<usize>, base: BaseDirection) -> Vec<DirectionRun> {
+    let mut runs = Vec::new();
+    let paragraphs = paragraph_ranges(text);
+
+    for (i, paragraph) in paragraphs.iter().enumerate() {
+        let separator_end = paragraphs.get(i + 1).map_or(paragraph.end, |next| next.start);
+        if paragraph.start >= range.end || separator_end <= range.start {
+            continue;
+        }
+
+        let start = paragraph.start.max(range.start);
+        let end = paragraph.end.min(range.end);
+        if start < end {
+            paragraph_runs(text, paragraph.clone(), start..end, base, &mut runs);
+        }
+
+        let separator = paragraph.end.max(range.start)..separator_end.min(range.end);
+        if separator.start < separator.end {
+            runs.push(DirectionRun { range: separator, rtl: false });
+        }
+    }
+
+    runs
+}
+
+/// Whether the paragraph holding byte `offset` of `text` is right-to-left with `base`.
+pub fn paragraph_rtl(text: &str, offset: usize, base: BaseDirection) -> bool {
+    match base {
+        BaseDirection::Ltr => false,
+        BaseDirection::Rtl => true,
+        BaseDirection::FirstStrong => {
+            let paragraph = paragraph_ranges(text).into_iter()
+                .find(|paragraph| offset <= paragraph.end)
+                .unwrap_or(0..0);
+            first_strong_rtl(&text[paragraph])
+        }
+    }
+}
+
+fn first_strong_rtl(text: &str) -> bool {
+    text.chars().filter_map(strong_direction).next().unwrap_or(false)
+}
+
+fn paragraph_runs(text: &str, paragraph: Range<usize>, range: Range<usize>, base: BaseDirection, output: &mut Vec<DirectionRun>) {
+    let chars = text[paragraph.clone()].char_indices()
+        .map(|(i, c)| (paragraph.start + i, strong_direction(c)))
+        .collect::<Vec<_>>();
+
+    let base = match base {
+        BaseDirection::Ltr => false,
+        BaseDirection::Rtl => true,
+        BaseDirection::FirstStrong => first_strong_rtl(&text[paragraph.clone()]),
+    };
+
+    let mut next_strong = vec![base; chars.len()];
+    let mut next = base;
+    for (i, &(_, strong)) in chars.iter().enumerate().rev() {
+        next_strong[i] = next;
+        if let Some(strong) = strong {
+            next = strong;
+        }
+    }
+
+    let first_run = output.len();
+    let mut prev = base;
+
+    for (i, &(pos, strong)) in chars.iter().enumerate() {
+        let rtl = match strong {
+            Some(rtl) => rtl,
+            None if prev == next_strong[i] => prev,
+            None => base,
+        };
+        if let Some(strong) = strong {
+            prev = strong;
+        }
+
+        if pos < range.start || pos >= range.end {
+            continue;
+        }
+
+        let char_end = pos + text[pos..].chars().next().map_or(0, |c| c.len_utf8());
+        let continues_run = output.len() > first_run && output.last().map_or(false, |run| run.rtl == rtl);
+        if continues_run {
+            output.last_mut().expect("paragraph_runs: continued run should exist").range.end = char_end;
+        } else {
+            output.push(DirectionRun { range: pos..char_end, rtl });
+        }
+    }
+
+    if base {
+        output[first_run..].reverse();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn runs(text: &str) -> Vec<(&str, bool)> {
+        runs_with(text, BaseDirection::FirstStrong)
+    }
+
+    fn runs_with(text: &str, base: BaseDirection) -> Vec<(&str, bool)> {
+        direction_runs(text, 0..text.len(), base).into_iter()
+            .map(|run| (&text[run.range], run.rtl))
+            .collect()
+    }
+
+    #[test]
+    fn single_direction_text_should_be_one_run() {
+        assert_eq!(runs("hello, world"), vec![("hello, world", false)]);
+        assert_eq!(runs("مرحبا بالعالم"), vec![("مرحبا بالعالم", true)]);
+        assert_eq!(runs("..."), vec![("...", false)]);
+        assert_eq!(runs(""), vec![]);
+    }
+
+    #[test]
+    fn brackets_around_ltr_text_in_rtl_paragraph_should_be_rtl() {
+        assert_eq!(runs("قيمة (x)"), vec![(")", true), ("x", false), ("قيمة (", true)]);
+    }
+
+    #[test]
+    fn neutrals_between_different_directions_should_take_paragraph_direction() {
+        assert_eq!(runs("abc אבג (x)"), vec![("abc ", false), ("אבג", true), (" (x)", false)]);
+    }
+
+    #[test]
+    fn paragraphs_should_be_resolved_separately_and_stay_in_order() {
+        assert_eq!(runs("abc\nאבג x"), vec![("abc", false), ("\n", false), ("x", false), ("אבג ", true)]);
+    }
+
+    #[test]
+    fn subrange_should_use_whole_paragraph_as_context() {
+        let text = "אבג (x) דהו";
+        let runs = direction_runs(text, 6..8, BaseDirection::FirstStrong).into_iter()
+            .map(|run| (&text[run.range], run.rtl))
+            .collect::<Vec<_>>();
+        assert_eq!(runs, vec![(" (", true)]);
+    }
+
+    #[test]
+    fn forced_base_direction_should_resolve_neutrals_and_order_runs() {
+        assert_eq!(runs_with("abc אבג", BaseDirection::Rtl), vec![(" אבג", true), ("abc", false)]);
+        assert_eq!(runs_with("אבג abc", BaseDirection::Ltr), vec![("אבג", true), (" abc", false)]);
+        assert_eq!(runs_with("...", BaseDirection::Rtl), vec![("...", true)]);
+        assert_eq!(runs_with("...", BaseDirection::FirstStrong), vec![("...", false)]);
+
+        assert!(paragraph_rtl("abc\nאבג", 5, BaseDirection::FirstStrong));
+        assert!(!paragraph_rtl("abc\nאבג", 0, BaseDirection::FirstStrong));
+        assert!(paragraph_rtl("abc", 0, BaseDirection::Rtl));
+    }
+}
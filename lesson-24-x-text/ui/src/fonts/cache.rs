@@ -0,0 +1,163 @@
+use std::rc::Rc;
+use metrohash::MetroHashMap;
+use font_kit::hinting::HintingOptions;
+
+pub const DEFAULT_GLYPH_CACHE_CAPACITY: usize = 512;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+struct GlyphKey {
+    glyph_id: u32,
+    hinting: u8,
+    size_bits: u32,
+    variant: u64,
+}
+
+impl GlyphKey {
+    fn new(glyph_id: u32, hinting: HintingOptions, variant: u64) -> GlyphKey {
+        let (hinting, size) = match hinting {
+            HintingOptions::None => (0, 0.0),
+            HintingOptions::Vertical(size) => (1, size),
+            HintingOptions::VerticalSubpixel(size) => (2, size),
+            HintingOptions::Full(size) => (3, size),
+        };
+
+        GlyphKey {
+            glyph_id,
+            hinting,
+            size_bits: size.to_bits(),
+            variant,
+        }
+    }
+}
+
+struct GlyphEntry<T> {
+    value: Rc<T>,
+    last_used: u64,
+}
+
+/// Per-glyph data built once and shared, evicting the least recently used
+/// entry when over capacity.
+///
+/// Entries are keyed by glyph id, hinting and a caller defined variant,
+/// such as a size or tolerance bucket.
+pub struct GlyphCache<T> {
+    entries: MetroHashMap<GlyphKey, GlyphEntry<T>>,
+    capacity: usize,
+    tick: u64,
+}
+
+impl<T> GlyphCache<T> {
+    pub fn new(capacity: usize) -> GlyphCache<T> {
+        GlyphCache {
+            entries: MetroHashMap::default(),
+            capacity,
+            tick: 0,
+        }
+    }
+
+    pub fn get(&mut self, glyph_id: u32, hinting: HintingOptions, variant: u64) -> Option<Rc<T>> {
+        self.tick += 1;
+        let tick = self.tick;
+
+        let entry = self.entries.get_mut(&GlyphKey::new(glyph_id, hinting, variant))?;
+        entry.last_used = tick;
+        Some(entry.value.clone())
+    }
+
+    pub fn insert(&mut self, glyph_id: u32, hinting: HintingOptions, variant: u64, value: Rc<T>) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        self.tick += 1;
+        self.entries.insert(GlyphKey::new(glyph_id, hinting, variant), GlyphEntry { value, last_used: self.tick });
+        self.evict();
+    }
+
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.evict();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Approximate heap bytes of all entries, `size` gives the bytes of one value.
+    pub fn bytes<F: Fn(&T) -> usize>(&self, size: F) -> usize {
+        self.entries.values()
+            .map(|entry| ::std::mem::size_of::<(GlyphKey, GlyphEntry<T>)>() + size(&entry.value))
+            .sum()
+    }
+
+    /// Removes the least recently used entry, returning its bytes as counted by `bytes`.
+    pub fn remove_oldest<F: Fn(&T) -> usize>(&mut self, size: F) -> Option<usize> {
+        let oldest = self.entries.iter()
+            .min_by_key(|(_, entry)| entry.last_used)
+            .map(|(key, _)| *key)?;
+
+        let entry = self.entries.remove(&oldest)?;
+        Some(::std::mem::size_of::<(GlyphKey, GlyphEntry<T>)>() + size(&entry.value))
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    fn evict(&mut self) {
+        while self.entries.len() > self.capacity {
+            if self.remove_oldest(|_| 0).is_none() {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn value() -> Rc<u32> {
+        Rc::new(0)
+    }
+
+    #[test]
+    fn should_evict_least_recently_used() {
+        let mut cache = GlyphCache::new(2);
+        cache.insert(1, HintingOptions::None, 0, value());
+        cache.insert(2, HintingOptions::None, 0, value());
+        assert!(cache.get(1, HintingOptions::None, 0).is_some());
+
+        cache.insert(3, HintingOptions::None, 0, value());
+
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get(1, HintingOptions::None, 0).is_some());
+        assert!(cache.get(2, HintingOptions::None, 0).is_none());
+        assert!(cache.get(3, HintingOptions::None, 0).is_some());
+    }
+
+    #[test]
+    fn hinting_size_and_variant_should_be_part_of_the_key() {
+        let mut cache = GlyphCache::new(8);
+        cache.insert(1, HintingOptions::Full(12.0), 0, value());
+
+        assert!(cache.get(1, HintingOptions::Full(12.0), 0).is_some());
+        assert!(cache.get(1, HintingOptions::Full(16.0), 0).is_none());
+        assert!(cache.get(1, HintingOptions::None, 0).is_none());
+        assert!(cache.get(1, HintingOptions::Full(12.0), 1).is_none());
+    }
+
+    #[test]
+    fn remove_oldest_should_return_the_bytes_it_frees() {
+        let mut cache = GlyphCache::new(8);
+        cache.insert(1, HintingOptions::None, 0, value());
+        cache.insert(2, HintingOptions::None, 0, value());
+        let total = cache.bytes(|_| 10);
+
+        assert_eq!(cache.remove_oldest(|_| 10), Some(total / 2));
+        assert!(cache.get(1, HintingOptions::None, 0).is_none());
+        assert_eq!(cache.bytes(|_| 10), total / 2);
+        assert!(cache.remove_oldest(|_| 10).is_some());
+        assert_eq!(cache.remove_oldest(|_| 10), None);
+    }
+}
@@ -0,0 +1,131 @@
+//! Case mapping of the text before shaping, see `Buffer::set_case`.
+
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Case transform of a buffer, like CSS `text-transform`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Case {
+    /// Shape the text as is.
+    None,
+    Upper,
+    Lower,
+    /// Title case the first letter of each word, leaving the other letters as they are.
+    Title,
+}
+
+impl Default for Case {
+    fn default() -> Case {
+        Case::None
+    }
+}
+
+/// Turkish and Azerbaijani map dotted and dotless i to their own upper and lower case.
+fn is_turkic(locale: &str) -> bool {
+    let language = locale.split(|c| c == '-' || c == '_').next().unwrap_or("");
+    language.eq_ignore_ascii_case("tr") || language.eq_ignore_ascii_case("az")
+}
+
+fn push_upper(c: char, turkic: bool, output: &mut String) {
+    match c {
+        'i' if turkic => output.push('\u{130}'),
+        _ => output.extend(c.to_uppercase()),
+    }
+}
+
+/// Lower case of `c`; a capital sigma at the end of a word, after a letter, becomes the final
+/// form like `str::to_lowercase` does.
+fn push_lower(text: &str, at: usize, c: char, turkic: bool, output: &mut String) {
+    match c {
+        'I' if turkic => output.push('\u{131}'),
+        '\u{130}' if turkic => output.push('i'),
+        '\u{3A3}' => {
+            let preceded_by_letter = text[..at].chars().next_back().map_or(false, char::is_alphabetic);
+            let followed_by_letter = text[at + c.len_utf8()..].chars().next().map_or(false, char::is_alphabetic);
+            output.push(if preceded_by_letter && !followed_by_letter { '\u{3C2}' } else { '\u{3C3}' });
+        }
+        _ => output.extend(c.to_lowercase()),
+    }
+}
+
+/// Title case of `c`, which differs from upper case for digraphs and `ß`.
+fn push_title(c: char, turkic: bool, output: &mut String) {
+    match c {
+        '\u{1C4}'..='\u{1C6}' => output.push('\u{1C5}'),
+        '\u{1C7}'..='\u{1C9}' => output.push('\u{1C8}'),
+        '\u{1CA}'..='\u{1CC}' => output.push('\u{1CB}'),
+        '\u{1F1}'..='\u{1F3}' => output.push('\u{1F2}'),
+        '\u{DF}' => output.push_str("Ss"),
+        _ => push_upper(c, turkic, output),
+    }
+}
+
+/// `text` mapped to `case` with the rules of `locale`, and the byte of `text` each mapped
+/// byte comes from, followed by the length of `text`.
+///
+/// Bytes of a mapped char come from the bytes of its source char in order, the extra bytes
+/// of a longer mapping from its last byte.
+pub fn map_case(text: &str, case: Case, locale: Option<&str>) -> (String, Vec<u32>) {
+    let turkic = locale.map_or(false, is_turkic);
+    let mut mapped = String::with_capacity(text.len());
+    let mut offsets = Vec::with_capacity(text.len() + 1);
+    let mut words = text.split_word_bound_indices().map(|(i, _)| i).peekable();
+
+    for (i, c) in text.char_indices() {
+        let start = mapped.len();
+        match case {
+            Case::None => mapped.push(c),
+            Case::Upper => push_upper(c, turkic, &mut mapped),
+            Case::Lower => push_lower(text, i, c, turkic, &mut mapped),
+            Case::Title => {
+                while words.peek().map_or(false, |&word| word < i) {
+                    words.next();
+                }
+                if words.peek() == Some(&i) && c.is_alphabetic() {
+                    push_title(c, turkic, &mut mapped);
+                } else {
+                    mapped.push(c);
+                }
+            }
+        }
+        let last = c.len_utf8() - 1;
+        offsets.extend((0..mapped.len() - start).map(|k| (i + k.min(last)) as u32));
+    }
+    offsets.push(text.len() as u32);
+
+    (mapped, offsets)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn turkish_should_map_dotted_and_dotless_i() {
+        assert_eq!(map_case("istanbul", Case::Upper, Some("tr-TR")).0, "İSTANBUL");
+        assert_eq!(map_case("istanbul", Case::Upper, Some("en")).0, "ISTANBUL");
+        assert_eq!(map_case("IŞIK İZ", Case::Lower, Some("tr")).0, "ışık iz");
+        assert_eq!(map_case("izmir", Case::Title, Some("tr")).0, "İzmir");
+    }
+
+    #[test]
+    fn offsets_should_point_into_the_source_text() {
+        let (mapped, offsets) = map_case("straße", Case::Upper, None);
+        assert_eq!(mapped, "STRASSE");
+        assert_eq!(offsets, vec![0, 1, 2, 3, 4, 5, 6, 7]);
+
+        let (mapped, offsets) = map_case("ıi", Case::Upper, Some("az"));
+        assert_eq!(mapped, "Iİ");
+        assert_eq!(offsets, vec![0, 2, 2, 3]);
+    }
+
+    #[test]
+    fn title_case_should_start_each_word() {
+        assert_eq!(map_case("hello, wide world's end", Case::Title, None).0, "Hello, Wide World's End");
+        assert_eq!(map_case("ǆungla 3d", Case::Title, None).0, "ǅungla 3d");
+    }
+
+    #[test]
+    fn sigma_should_take_the_final_form_at_word_ends() {
+        assert_eq!(map_case("ΟΔΟΣ ΣΑΣ", Case::Lower, None).0, "οδος σας");
+    }
+}
@@ -0,0 +1,162 @@
+//! Terminal grid cells of clusters, see `Buffer::cells`.
+
+use std::ops::Range;
+
+/// Columns of a cluster on a terminal grid.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GridCell {
+    /// Bytes of the source text of the cluster.
+    pub byte_range: Range<usize>,
+    /// First column, counted from the start of the text.
+    pub column: usize,
+    /// Number of columns, 1 or 2.
+    pub width: usize,
+}
+
+/// Ranges of wide and fullwidth chars of Unicode East Asian Width, and emoji with default
+/// emoji presentation.
+const WIDE: &[(u32, u32)] = &[
+    (0x1100, 0x115F),
+    (0x231A, 0x231B),
+    (0x2329, 0x232A),
+    (0x23E9, 0x23EC),
+    (0x23F0, 0x23F0),
+    (0x23F3, 0x23F3),
+    (0x25FD, 0x25FE),
+    (0x2614, 0x2615),
+    (0x2648, 0x2653),
+    (0x267F, 0x267F),
+    (0x2693, 0x2693),
+    (0x26A1, 0x26A1),
+    (0x26AA, 0x26AB),
+    (0x26BD, 0x26BE),
+    (0x26C4, 0x26C5),
+    (0x26CE, 0x26CE),
+    (0x26D4, 0x26D4),
+    (0x26EA, 0x26EA),
+    (0x26F2, 0x26F3),
+    (0x26F5, 0x26F5),
+    (0x26FA, 0x26FA),
+    (0x26FD, 0x26FD),
+    (0x2705, 0x2705),
+    (0x270A, 0x270B),
+    (0x2728, 0x2728),
+    (0x274C, 0x274C),
+    (0x274E, 0x274E),
+    (0x2753, 0x2755),
+    (0x2757, 0x2757),
+    (0x2795, 0x2797),
+    (0x27B0, 0x27B0),
+    (0x27BF, 0x27BF),
+    (0x2B1B, 0x2B1C),
+    (0x2B50, 0x2B50),
+    (0x2B55, 0x2B55),
+    (0x2E80, 0x303E),
+    (0x3041, 0x33FF),
+    (0x3400, 0x4DBF),
+    (0x4E00, 0x9FFF),
+    (0xA000, 0xA4CF),
+    (0xA960, 0xA97F),
+    (0xAC00, 0xD7A3),
+    (0xF900, 0xFAFF),
+    (0xFE10, 0xFE19),
+    (0xFE30, 0xFE6F),
+    (0xFF00, 0xFF60),
+    (0xFFE0, 0xFFE6),
+    (0x16FE0, 0x18AFF),
+    (0x1B000, 0x1B2FF),
+    (0x1F004, 0x1F004),
+    (0x1F0CF, 0x1F0CF),
+    (0x1F18E, 0x1F18E),
+    (0x1F191, 0x1F19A),
+    (0x1F200, 0x1F251),
+    (0x1F300, 0x1F320),
+    (0x1F32D, 0x1F335),
+    (0x1F337, 0x1F37C),
+    (0x1F37E, 0x1F393),
+    (0x1F3A0, 0x1F3CA),
+    (0x1F3CF, 0x1F3D3),
+    (0x1F3E0, 0x1F3F0),
+    (0x1F3F4, 0x1F3F4),
+    (0x1F3F8, 0x1F43E),
+    (0x1F440, 0x1F440),
+    (0x1F442, 0x1F4FC),
+    (0x1F4FF, 0x1F53D),
+    (0x1F54B, 0x1F54E),
+    (0x1F550, 0x1F567),
+    (0x1F57A, 0x1F57A),
+    (0x1F595, 0x1F596),
+    (0x1F5A4, 0x1F5A4),
+    (0x1F5FB, 0x1F64F),
+    (0x1F680, 0x1F6C5),
+    (0x1F6CC, 0x1F6CC),
+    (0x1F6D0, 0x1F6D2),
+    (0x1F6D5, 0x1F6D7),
+    (0x1F6EB, 0x1F6EC),
+    (0x1F6F4, 0x1F6FC),
+    (0x1F7E0, 0x1F7EB),
+    (0x1F90C, 0x1F93A),
+    (0x1F93C, 0x1F945),
+    (0x1F947, 0x1F9FF),
+    (0x1FA70, 0x1FAFF),
+    (0x20000, 0x2FFFD),
+    (0x30000, 0x3FFFD),
+];
+
+/// Char of a wide or fullwidth East Asian character, or an emoji shown as one.
+pub fn is_wide(c: char) -> bool {
+    let c = c as u32;
+    WIDE.binary_search_by(|&(start, end)| {
+        if end < c {
+            ::std::cmp::Ordering::Less
+        } else if start > c {
+            ::std::cmp::Ordering::Greater
+        } else {
+            ::std::cmp::Ordering::Equal
+        }
+    }).is_ok()
+}
+
+/// Columns of a cluster of `text` advancing `advance` on a grid of `cell_width`: 2 if it has a
+/// wide char or advances more than one and a half cells, otherwise 1.
+pub fn cluster_width(text: &str, advance: i32, cell_width: i32) -> usize {
+    if text.chars().any(is_wide) || (cell_width > 0 && advance * 2 > cell_width * 3) {
+        2
+    } else {
+        1
+    }
+}
+
+/// Cells of clusters given in text order as source byte ranges and advances.
+pub fn grid_cells<I>(text: &str, clusters: I, cell_width: i32) -> Vec<GridCell>
+    where I: IntoIterator<Item=(Range<usize>, i32)> {
+    let mut column = 0;
+    clusters.into_iter()
+        .map(|(byte_range, advance)| {
+            let width = cluster_width(&text[byte_range.clone()], advance, cell_width);
+            let cell = GridCell { byte_range, column, width };
+            column += width;
+            cell
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn east_asian_wide_chars_should_take_two_cells() {
+        assert!(is_wide('漢') && is_wide('한') && is_wide('Ａ') && is_wide('😀'));
+        assert!(!is_wide('a') && !is_wide('─') && !is_wide('ｱ'));
+    }
+
+    #[test]
+    fn columns_should_follow_cell_widths() {
+        let text = "a漢b";
+        let cells = grid_cells(text, vec![(0..1, 600), (1..4, 1200), (4..5, 600)], 600);
+        assert_eq!(cells.iter().map(|c| (c.column, c.width)).collect::<Vec<_>>(), vec![(0, 1), (1, 2), (3, 1)]);
+        // a fallback glyph twice as wide as the cell
+        assert_eq!(cluster_width("→", 1200, 600), 2);
+    }
+}
@@ -0,0 +1,262 @@
+//! Fallback fonts for text the primary font has no glyphs for, see `FontChain`.
+
+use std::ops::Range;
+use unicode_segmentation::UnicodeSegmentation;
+use crate::na;
+use super::{Buffer, Feature, Font, Fonts, Metrics, SpannedText, TextMeasure};
+
+/// How `FontChain` sizes fallback fonts next to the primary font at the same px size.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Harmonize {
+    /// Fallback glyphs at the requested size on the shared baseline, the default.
+    None,
+    /// Fallback x-height scaled to the primary one, on the shared baseline.
+    MatchXHeight,
+    /// Fallback cap height scaled to the primary one, on the shared baseline.
+    MatchCapHeight,
+    /// Fallback ascent to descent extent scaled to the primary one, with the baseline moved
+    /// so both extents line up.
+    MatchEm,
+}
+
+impl Default for Harmonize {
+    fn default() -> Self {
+        Harmonize::None
+    }
+}
+
+/// Adjustment of the glyphs of a fallback font, see `FontChain::harmonization_factors`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct HarmonizationFactor {
+    /// Multiplier of the requested size.
+    pub scale: f32,
+    /// Shift of the baseline in ems of the requested size, up positive.
+    pub baseline_shift: f32,
+}
+
+impl Default for HarmonizationFactor {
+    fn default() -> Self {
+        HarmonizationFactor {
+            scale: 1.0,
+            baseline_shift: 0.0,
+        }
+    }
+}
+
+/// Vertical metrics in ems.
+#[derive(Debug, Copy, Clone)]
+struct EmMetrics {
+    ascent: f32,
+    descent: f32,
+    cap_height: f32,
+    x_height: f32,
+}
+
+impl EmMetrics {
+    fn new(metrics: &Metrics) -> EmMetrics {
+        let units_per_em = metrics.units_per_em.max(1) as f32;
+        EmMetrics {
+            ascent: metrics.ascent / units_per_em,
+            descent: metrics.descent / units_per_em,
+            cap_height: metrics.cap_height / units_per_em,
+            x_height: metrics.x_height / units_per_em,
+        }
+    }
+}
+
+/// Factor of a fallback font, no adjustment if the metrics it matches are missing.
+fn harmonize(primary: &EmMetrics, fallback: &EmMetrics, mode: Harmonize) -> HarmonizationFactor {
+    let ratio = |primary: f32, fallback: f32| {
+        let scale = primary / fallback;
+        if scale.is_finite() && scale > 0.0 { Some(scale) } else { None }
+    };
+    let adjusted = match mode {
+        Harmonize::None => None,
+        Harmonize::MatchXHeight => ratio(primary.x_height, fallback.x_height)
+            .map(|scale| HarmonizationFactor { scale, baseline_shift: 0.0 }),
+        Harmonize::MatchCapHeight => ratio(primary.cap_height, fallback.cap_height)
+            .map(|scale| HarmonizationFactor { scale, baseline_shift: 0.0 }),
+        Harmonize::MatchEm => ratio(primary.ascent - primary.descent, fallback.ascent - fallback.descent)
+            .map(|scale| HarmonizationFactor { scale, baseline_shift: primary.ascent - fallback.ascent * scale }),
+    };
+    adjusted.unwrap_or_default()
+}
+
+/// A primary font with fallback fonts, each grapheme of a text shaped with the first font of
+/// the chain that has glyphs for all its chars.
+///
+/// Fallbacks can be sized to match the primary font with `set_harmonization`. The adjustment
+/// applies to the glyphs, quads, layout and measurement of buffers created by the chain.
+#[derive(Clone)]
+pub struct FontChain {
+    primary: Font,
+    fallbacks: Vec<Font>,
+    harmonization: Harmonize,
+    /// Factors of `harmonization`, one per fallback.
+    computed: Vec<HarmonizationFactor>,
+    overrides: Vec<Option<HarmonizationFactor>>,
+}
+
+impl FontChain {
+    pub fn new(primary: &Font) -> FontChain {
+        FontChain {
+            primary: primary.clone(),
+            fallbacks: Vec::new(),
+            harmonization: Harmonize::None,
+            computed: Vec::new(),
+            overrides: Vec::new(),
+        }
+    }
+
+    /// Appends a fallback font, tried after the fonts before it.
+    pub fn with_fallback(mut self, font: &Font) -> FontChain {
+        assert!(font.container_token() == self.primary.container_token(),
+            "FontChain::with_fallback: the font belongs to a different Fonts container");
+        let factor = harmonize(&EmMetrics::new(&self.primary.metrics()), &EmMetrics::new(&font.metrics()), self.harmonization);
+        self.computed.push(factor);
+        self.overrides.push(None);
+        self.fallbacks.push(font.clone());
+        self
+    }
+
+    pub fn primary(&self) -> &Font {
+        &self.primary
+    }
+
+    pub fn fallbacks(&self) -> &[Font] {
+        &self.fallbacks
+    }
+
+    /// Computes the factors of the fallback fonts for `harmonization`. Factors set with
+    /// `set_harmonization_factor` are kept.
+    pub fn set_harmonization(&mut self, harmonization: Harmonize) {
+        let primary = EmMetrics::new(&self.primary.metrics());
+        self.computed = self.fallbacks.iter()
+            .map(|font| harmonize(&primary, &EmMetrics::new(&font.metrics()), harmonization))
+            .collect();
+        self.harmonization = harmonization;
+    }
+
+    pub fn harmonization(&self) -> Harmonize {
+        self.harmonization
+    }
+
+    /// Factor of each fallback font in chain order, the computed one unless overridden.
+    pub fn harmonization_factors(&self) -> Vec<HarmonizationFactor> {
+        self.computed.iter().zip(&self.overrides)
+            .map(|(computed, overridden)| overridden.unwrap_or(*computed))
+            .collect()
+    }
+
+    /// Overrides the factor of a fallback font, `None` to use the computed one again.
+    pub fn set_harmonization_factor(&mut self, fallback: usize, factor: Option<HarmonizationFactor>) {
+        assert!(fallback < self.fallbacks.len(), "FontChain::set_harmonization_factor: fallback {} out of {}", fallback, self.fallbacks.len());
+        self.overrides[fallback] = factor;
+    }
+
+    /// Index of the font for each run of graphemes of `text`, 0 for the primary font and
+    /// `n + 1` for fallback `n`. Graphemes no font has glyphs for use the primary font.
+    pub fn runs(&self, text: &str) -> Vec<(Range<usize>, usize)> {
+        let mut runs: Vec<(Range<usize>, usize)> = Vec::new();
+        for (start, grapheme) in text.grapheme_indices(true) {
+            let index = ::std::iter::once(&self.primary).chain(&self.fallbacks)
+                .position(|font| font.supports_chars(grapheme.chars()).is_complete())
+                .unwrap_or(0);
+            let end = start + grapheme.len();
+            if let Some(last) = runs.last_mut() {
+                if last.1 == index {
+                    last.0.end = end;
+                    continue;
+                }
+            }
+            runs.push((start..end, index));
+        }
+        runs
+    }
+
+    /// Spans of `text` with the fonts of the chain, fallback spans adjusted by their factors.
+    pub fn spanned(&self, text: &str) -> SpannedText {
+        let runs = self.runs(text);
+        if runs.is_empty() {
+            return SpannedText::new().push(text, &self.primary);
+        }
+        let factors = self.harmonization_factors();
+        runs.into_iter().fold(SpannedText::new(), |spanned, (range, index)| match index {
+            0 => spanned.push(&text[range], &self.primary),
+            _ => {
+                let factor = factors[index - 1];
+                spanned.push_adjusted(&text[range], &self.fallbacks[index - 1], factor.scale, factor.baseline_shift)
+            },
+        })
+    }
+
+    /// Creates a spanned buffer of `text` shaped with the fonts of the chain.
+    pub fn create_buffer(&self, text: &str, transform: Option<na::Projective3<f32>>, color: na::Vector4<u8>) -> Buffer {
+        let fonts = Fonts { container: self.primary.container.clone() };
+        fonts.create_spanned_buffer(&self.spanned(text), transform, color)
+            .expect("FontChain::create_buffer: spanned text has a span")
+    }
+
+    /// Size of `text` shaped with the fonts of the chain at `px_size`, without creating a
+    /// buffer, see `Font::measure_str`. Runs of different fonts are shaped apart, so there is
+    /// no kerning between them.
+    pub fn measure_str(&self, text: &str, px_size: f32, features: &[Feature]) -> TextMeasure {
+        let factors = self.harmonization_factors();
+        let primary = self.primary.metrics_for_size(px_size);
+        let empty = TextMeasure { width: 0.0, ascent: primary.ascent, descent: primary.descent, line_gap: primary.line_gap, glyph_count: 0 };
+        self.runs(text).into_iter().fold(empty, |total, (range, index)| {
+            let (font, factor) = match index {
+                0 => (&self.primary, HarmonizationFactor::default()),
+                _ => (&self.fallbacks[index - 1], factors[index - 1]),
+            };
+            let run = font.measure_str(&text[range], px_size * factor.scale, features);
+            let shift = factor.baseline_shift * px_size;
+            TextMeasure {
+                width: total.width + run.width,
+                ascent: total.ascent.max(run.ascent + shift),
+                descent: total.descent.min(run.descent + shift),
+                line_gap: total.line_gap.max(run.line_gap),
+                glyph_count: total.glyph_count + run.glyph_count,
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn latin() -> EmMetrics {
+        EmMetrics { ascent: 0.9, descent: -0.2, cap_height: 0.7, x_height: 0.5 }
+    }
+
+    fn cjk() -> EmMetrics {
+        EmMetrics { ascent: 1.1, descent: -0.3, cap_height: 0.75, x_height: 0.55 }
+    }
+
+    fn assert_factor(factor: HarmonizationFactor, scale: f32, baseline_shift: f32) {
+        assert!((factor.scale - scale).abs() < 1e-5, "scale {} != {}", factor.scale, scale);
+        assert!((factor.baseline_shift - baseline_shift).abs() < 1e-5, "shift {} != {}", factor.baseline_shift, baseline_shift);
+    }
+
+    #[test]
+    fn fallbacks_should_match_the_selected_primary_metric() {
+        assert_factor(harmonize(&latin(), &cjk(), Harmonize::None), 1.0, 0.0);
+        assert_factor(harmonize(&latin(), &cjk(), Harmonize::MatchXHeight), 0.5 / 0.55, 0.0);
+        assert_factor(harmonize(&latin(), &cjk(), Harmonize::MatchCapHeight), 0.7 / 0.75, 0.0);
+
+        // the scaled extent of the fallback covers the primary one exactly
+        let factor = harmonize(&latin(), &cjk(), Harmonize::MatchEm);
+        assert_factor(factor, 1.1 / 1.4, 0.9 - 1.1 * 1.1 / 1.4);
+        assert!((cjk().ascent * factor.scale + factor.baseline_shift - latin().ascent).abs() < 1e-5);
+        assert!((cjk().descent * factor.scale + factor.baseline_shift - latin().descent).abs() < 1e-5);
+    }
+
+    #[test]
+    fn missing_metrics_should_leave_fallbacks_unadjusted() {
+        let no_heights = EmMetrics { cap_height: 0.0, x_height: 0.0, ..cjk() };
+        assert_factor(harmonize(&latin(), &no_heights, Harmonize::MatchXHeight), 1.0, 0.0);
+        assert_factor(harmonize(&latin(), &no_heights, Harmonize::MatchCapHeight), 1.0, 0.0);
+        assert_factor(harmonize(&no_heights, &cjk(), Harmonize::MatchXHeight), 1.0, 0.0);
+    }
+}
@@ -0,0 +1,273 @@
+//! Embedded PNG glyph images from `sbix` and `CBDT`/`CBLC` tables.
+
+use png;
+use super::sfnt::{read_u16, read_u32, find_table};
+
+/// Color glyph image, rows from top to bottom.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GlyphImage {
+    pub width: u32,
+    pub height: u32,
+    /// Pixels from the glyph origin to the left edge of the image.
+    pub left: i32,
+    /// Pixels from the baseline up to the top edge of the image.
+    pub top: i32,
+    /// Horizontal advance in pixels.
+    pub advance: f32,
+    /// Non-premultiplied RGBA.
+    pub rgba: Vec<u8>,
+}
+
+fn read_u8(data: &[u8], offset: usize) -> Option<u8> {
+    data.get(offset).cloned()
+}
+
+fn read_i8(data: &[u8], offset: usize) -> Option<i8> {
+    read_u8(data, offset).map(|v| v as i8)
+}
+
+fn read_i16(data: &[u8], offset: usize) -> Option<i16> {
+    read_u16(data, offset).map(|v| v as i16)
+}
+
+/// PNG data and placement at the strike size.
+struct StrikeGlyph<'a> {
+    png: &'a [u8],
+    ppem: u16,
+    /// Left bearing in strike pixels.
+    left: i32,
+    /// Top of the image in strike pixels, or the bottom if `from_bottom`.
+    y: i32,
+    from_bottom: bool,
+    advance: Option<f32>,
+}
+
+/// Prefers the smallest strike at least as large as `px_size`, otherwise the largest.
+fn best_strike(sizes: &[u16], px_size: f32) -> Option<usize> {
+    let larger = sizes.iter().enumerate()
+        .filter(|(_, ppem)| f32::from(**ppem) >= px_size)
+        .min_by_key(|(_, ppem)| **ppem)
+        .map(|(i, _)| i);
+
+    larger.or_else(|| sizes.iter().enumerate().max_by_key(|(_, ppem)| **ppem).map(|(i, _)| i))
+}
+
+fn sbix_glyph<'a>(sbix: &'a [u8], num_glyphs: u16, glyph_id: u32, px_size: f32) -> Option<StrikeGlyph<'a>> {
+    if glyph_id >= u32::from(num_glyphs) {
+        return None;
+    }
+
+    let num_strikes = read_u32(sbix, 4)? as usize;
+    let strikes = (0..num_strikes)
+        .map(|i| read_u32(sbix, 8 + i * 4).map(|o| o as usize))
+        .collect::<Option<Vec<_>>>()?;
+    let sizes = strikes.iter()
+        .map(|o| read_u16(sbix, *o))
+        .collect::<Option<Vec<_>>>()?;
+
+    let strike = strikes[best_strike(&sizes, px_size)?];
+    let start = strike + read_u32(sbix, strike + 4 + glyph_id as usize * 4)? as usize;
+    let end = strike + read_u32(sbix, strike + 8 + glyph_id as usize * 4)? as usize;
+    if end <= start + 8 || sbix.get(start + 4..start + 8)? != b"png " {
+        return None;
+    }
+
+    // sbix origin offsets place the bottom left corner of the image
+    Some(StrikeGlyph {
+        png: sbix.get(start + 8..end)?,
+        ppem: read_u16(sbix, strike)?,
+        left: i32::from(read_i16(sbix, start)?),
+        y: i32::from(read_i16(sbix, start + 2)?),
+        from_bottom: true,
+        advance: None,
+    })
+}
+
+fn cbdt_glyph<'a>(cblc: &[u8], cbdt: &'a [u8], glyph_id: u32, px_size: f32) -> Option<StrikeGlyph<'a>> {
+    let num_sizes = read_u32(cblc, 4)? as usize;
+    let sizes = (0..num_sizes)
+        .map(|i| read_u8(cblc, 8 + i * 48 + 45).map(u16::from))
+        .collect::<Option<Vec<_>>>()?;
+    let size = 8 + best_strike(&sizes, px_size)? * 48;
+
+    let array_offset = read_u32(cblc, size)? as usize;
+    let num_subtables = read_u32(cblc, size + 8)? as usize;
+    let ppem = u16::from(read_u8(cblc, size + 45)?);
+
+    for i in 0..num_subtables {
+        let entry = array_offset + i * 8;
+        let first = u32::from(read_u16(cblc, entry)?);
+        let last = u32::from(read_u16(cblc, entry + 2)?);
+        if glyph_id < first || glyph_id > last {
+            continue;
+        }
+
+        let subtable = array_offset + read_u32(cblc, entry + 4)? as usize;
+        let index_format = read_u16(cblc, subtable)?;
+        let image_format = read_u16(cblc, subtable + 2)?;
+        let image_data = read_u32(cblc, subtable + 4)? as usize;
+        let index = (glyph_id - first) as usize;
+
+        let (offset, big_metrics) = match index_format {
+            1 => (image_data + read_u32(cblc, subtable + 8 + index * 4)? as usize, None),
+            3 => (image_data + read_u16(cblc, subtable + 8 + index * 2)? as usize, None),
+            2 => {
+                let image_size = read_u32(cblc, subtable + 8)? as usize;
+                (image_data + index * image_size, Some(subtable + 12))
+            }
+            _ => return None,
+        };
+
+        return match image_format {
+            17 => Some(StrikeGlyph {
+                png: cbdt.get(offset + 9..offset + 9 + read_u32(cbdt, offset + 5)? as usize)?,
+                ppem,
+                left: i32::from(read_i8(cbdt, offset + 2)?),
+                y: i32::from(read_i8(cbdt, offset + 3)?),
+                from_bottom: false,
+                advance: Some(f32::from(read_u8(cbdt, offset + 4)?)),
+            }),
+            18 => Some(StrikeGlyph {
+                png: cbdt.get(offset + 12..offset + 12 + read_u32(cbdt, offset + 8)? as usize)?,
+                ppem,
+                left: i32::from(read_i8(cbdt, offset + 2)?),
+                y: i32::from(read_i8(cbdt, offset + 3)?),
+                from_bottom: false,
+                advance: Some(f32::from(read_u8(cbdt, offset + 4)?)),
+            }),
+            19 => {
+                let metrics = big_metrics?;
+                Some(StrikeGlyph {
+                    png: cbdt.get(offset + 4..offset + 4 + read_u32(cbdt, offset)? as usize)?,
+                    ppem,
+                    left: i32::from(read_i8(cblc, metrics + 2)?),
+                    y: i32::from(read_i8(cblc, metrics + 3)?),
+                    from_bottom: false,
+                    advance: Some(f32::from(read_u8(cblc, metrics + 4)?)),
+                })
+            }
+            _ => None,
+        };
+    }
+
+    None
+}
+
+fn decode_png(data: &[u8]) -> Option<(u32, u32, Vec<u8>)> {
+    let mut decoder = png::Decoder::new(data);
+    decoder.set(png::Transformations::EXPAND);
+    let (info, mut reader) = decoder.read_info().ok()?;
+
+    let mut buffer = vec![0; info.buffer_size()];
+    reader.next_frame(&mut buffer).ok()?;
+
+    // 16-bit samples are big endian, the high byte is the 8-bit value
+    if info.bit_depth == png::BitDepth::Sixteen {
+        buffer = buffer.chunks(2).map(|sample| sample[0]).collect();
+    }
+
+    let rgba = match info.color_type {
+        png::ColorType::RGBA => buffer,
+        png::ColorType::RGB => buffer.chunks(3).flat_map(|p| vec![p[0], p[1], p[2], 255]).collect(),
+        png::ColorType::GrayscaleAlpha => buffer.chunks(2).flat_map(|p| vec![p[0], p[0], p[0], p[1]]).collect(),
+        png::ColorType::Grayscale => buffer.iter().flat_map(|v| vec![*v, *v, *v, 255]).collect(),
+        png::ColorType::Indexed => return None,
+    };
+
+    Some((info.width, info.height, rgba))
+}
+
+/// Bilinear resampling of straight alpha RGBA.
+fn resize(width: u32, height: u32, rgba: &[u8], new_width: u32, new_height: u32) -> Vec<u8> {
+    let mut output = Vec::with_capacity((new_width * new_height * 4) as usize);
+    let sx = width as f32 / new_width as f32;
+    let sy = height as f32 / new_height as f32;
+    let pixel = |x: u32, y: u32, c: usize| f32::from(rgba[((y * width + x) * 4) as usize + c]);
+
+    for y in 0..new_height {
+        let fy = ((y as f32 + 0.5) * sy - 0.5).max(0.0);
+        let (y0, ty) = (fy.floor() as u32, fy.fract());
+        let y1 = (y0 + 1).min(height - 1);
+        for x in 0..new_width {
+            let fx = ((x as f32 + 0.5) * sx - 0.5).max(0.0);
+            let (x0, tx) = (fx.floor() as u32, fx.fract());
+            let x1 = (x0 + 1).min(width - 1);
+            for c in 0..4 {
+                let top = pixel(x0, y0, c) * (1.0 - tx) + pixel(x1, y0, c) * tx;
+                let bottom = pixel(x0, y1, c) * (1.0 - tx) + pixel(x1, y1, c) * tx;
+                output.push((top * (1.0 - ty) + bottom * ty).round() as u8);
+            }
+        }
+    }
+
+    output
+}
+
+/// Color image of a glyph scaled to `px_size`, from the best matching strike.
+///
+/// `advance` is the outline advance in pixels, used when the strike has no metrics.
+pub fn glyph_image(font_data: &[u8], face_index: u32, glyph_id: u32, px_size: f32, advance: f32) -> Option<GlyphImage> {
+    let glyph = match find_table(font_data, face_index, b"sbix") {
+        Some(sbix) => {
+            let num_glyphs = read_u16(find_table(font_data, face_index, b"maxp")?, 4)?;
+            sbix_glyph(sbix, num_glyphs, glyph_id, px_size)?
+        }
+        None => {
+            let cblc = find_table(font_data, face_index, b"CBLC")?;
+            let cbdt = find_table(font_data, face_index, b"CBDT")?;
+            cbdt_glyph(cblc, cbdt, glyph_id, px_size)?
+        }
+    };
+
+    let (width, height, rgba) = decode_png(glyph.png)?;
+    if width == 0 || height == 0 {
+        return None;
+    }
+
+    let scale = px_size / f32::from(glyph.ppem.max(1));
+    let new_width = ((width as f32 * scale).round() as u32).max(1);
+    let new_height = ((height as f32 * scale).round() as u32).max(1);
+    let top = if glyph.from_bottom { glyph.y + height as i32 } else { glyph.y };
+
+    Some(GlyphImage {
+        width: new_width,
+        height: new_height,
+        left: (glyph.left as f32 * scale).round() as i32,
+        top: (top as f32 * scale).round() as i32,
+        advance: glyph.advance.map(|a| a * scale).unwrap_or(advance),
+        rgba: resize(width, height, &rgba, new_width, new_height),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_prefer_smallest_larger_strike() {
+        assert_eq!(best_strike(&[20, 64, 160], 32.0), Some(1));
+        assert_eq!(best_strike(&[20, 64, 160], 200.0), Some(2));
+        assert_eq!(best_strike(&[], 16.0), None);
+    }
+
+    #[test]
+    fn resize_should_keep_solid_color() {
+        let rgba = [10, 20, 30, 255].iter().cycle().take(4 * 4 * 4).cloned().collect::<Vec<u8>>();
+        let resized = resize(4, 4, &rgba, 2, 2);
+        assert_eq!(resized, [10, 20, 30, 255].iter().cycle().take(2 * 2 * 4).cloned().collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn sixteen_bit_images_should_decode_to_eight_bits() {
+        let mut data = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut data, 2, 1);
+            encoder.set(png::ColorType::RGBA).set(png::BitDepth::Sixteen);
+            let mut writer = encoder.write_header().expect("png header should be written");
+            writer.write_image_data(&[0x12, 0x34, 0xff, 0xff, 0, 0, 0x80, 0x01, 0xab, 0xcd, 0, 0, 0x40, 0, 0xff, 0xff])
+                .expect("png data should be written");
+        }
+
+        assert_eq!(decode_png(&data), Some((2, 1, vec![0x12, 0xff, 0, 0x80, 0xab, 0, 0x40, 0xff])));
+    }
+}
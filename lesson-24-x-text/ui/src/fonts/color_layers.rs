@@ -0,0 +1,181 @@
+//! Layered color glyphs from `COLR` version 0 tables and their `CPAL` palettes.
+
+use super::sfnt::{read_u16, read_u32};
+
+/// Palette entry index that stands for the text color.
+const FOREGROUND_INDEX: u16 = 0xFFFF;
+
+const PALETTE_LIGHT_BACKGROUND: u32 = 0x0001;
+const PALETTE_DARK_BACKGROUND: u32 = 0x0002;
+
+/// Color palette of a `CPAL` table, see `Font::color_palettes`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palette {
+    pub index: usize,
+    /// Non-premultiplied RGBA.
+    pub colors: Vec<[u8; 4]>,
+    /// Palette type flags, zero for version 0 tables.
+    pub flags: u32,
+}
+
+impl Palette {
+    /// The font designed the palette for light backgrounds.
+    pub fn for_light_background(&self) -> bool {
+        self.flags & PALETTE_LIGHT_BACKGROUND != 0
+    }
+
+    /// The font designed the palette for dark backgrounds, such as a dark mode palette.
+    pub fn for_dark_background(&self) -> bool {
+        self.flags & PALETTE_DARK_BACKGROUND != 0
+    }
+}
+
+/// Glyph drawn as one layer of a color glyph, layers are drawn bottom to top.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ColorLayer {
+    pub glyph_id: u32,
+    /// Non-premultiplied RGBA from the palette, `None` for the text color.
+    pub color: Option<[u8; 4]>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ColorLayersError {
+    /// The requested palette does not exist, `count` being the number of palettes.
+    PaletteOutOfRange { palette: usize, count: usize },
+}
+
+/// Palettes of a `CPAL` table.
+pub fn palettes(cpal: &[u8]) -> Option<Vec<Palette>> {
+    let version = read_u16(cpal, 0)?;
+    let entry_count = read_u16(cpal, 2)? as usize;
+    let palette_count = read_u16(cpal, 4)? as usize;
+    let records = read_u32(cpal, 8)? as usize;
+    let types = if version >= 1 {
+        read_u32(cpal, 12 + palette_count * 2)? as usize
+    } else {
+        0
+    };
+
+    (0..palette_count)
+        .map(|index| {
+            let first = read_u16(cpal, 12 + index * 2)? as usize;
+            let colors = (0..entry_count)
+                .map(|entry| {
+                    let record = records + (first + entry) * 4;
+                    let bgra = cpal.get(record..record + 4)?;
+                    Some([bgra[2], bgra[1], bgra[0], bgra[3]])
+                })
+                .collect::<Option<Vec<_>>>()?;
+            let flags = if types != 0 { read_u32(cpal, types + index * 4)? } else { 0 };
+            Some(Palette { index, colors, flags })
+        })
+        .collect()
+}
+
+/// Layer glyphs and palette entry indices of a glyph, empty if it is not a color glyph.
+fn glyph_layers(colr: &[u8], glyph_id: u32) -> Option<Vec<(u32, u16)>> {
+    let base_count = read_u16(colr, 2)? as usize;
+    let base_records = read_u32(colr, 4)? as usize;
+    let layer_records = read_u32(colr, 8)? as usize;
+
+    // base glyph records are sorted by glyph id
+    let (mut low, mut high) = (0, base_count);
+    while low < high {
+        let middle = (low + high) / 2;
+        let record = base_records + middle * 6;
+        let id = u32::from(read_u16(colr, record)?);
+        if id < glyph_id {
+            low = middle + 1;
+        } else if id > glyph_id {
+            high = middle;
+        } else {
+            let first = read_u16(colr, record + 2)? as usize;
+            let count = read_u16(colr, record + 4)? as usize;
+            return (first..first + count)
+                .map(|layer| {
+                    let record = layer_records + layer * 4;
+                    Some((u32::from(read_u16(colr, record)?), read_u16(colr, record + 2)?))
+                })
+                .collect();
+        }
+    }
+    Some(Vec::new())
+}
+
+/// Layers of a glyph with colors from `palette`, empty if the glyph is not a color glyph.
+///
+/// Entries outside the palette fall back to the text color like the foreground entry.
+pub fn color_layers(colr: Option<&[u8]>, cpal: Option<&[u8]>, glyph_id: u32, palette: usize) -> Result<Vec<ColorLayer>, ColorLayersError> {
+    let layers = colr.and_then(|colr| glyph_layers(colr, glyph_id)).unwrap_or_default();
+    if layers.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut palettes = cpal.and_then(palettes).unwrap_or_default();
+    let count = palettes.len();
+    if palette >= count {
+        return Err(ColorLayersError::PaletteOutOfRange { palette, count });
+    }
+    let colors = palettes.swap_remove(palette).colors;
+
+    Ok(layers.into_iter()
+        .map(|(glyph_id, entry)| ColorLayer {
+            glyph_id,
+            color: if entry == FOREGROUND_INDEX { None } else { colors.get(entry as usize).cloned() },
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Version 1 palettes of two entries, light: red and blue, dark: pink and cyan.
+    fn cpal() -> Vec<u8> {
+        let mut data = vec![0, 1, 0, 2, 0, 2, 0, 4, 0, 0, 0, 36, 0, 0, 0, 2, 0, 0, 0, 28, 0, 0, 0, 0, 0, 0, 0, 0];
+        data.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 2]);
+        data.extend_from_slice(&[0, 0, 255, 255, 255, 0, 0, 255]);
+        data.extend_from_slice(&[255, 128, 255, 255, 255, 255, 0, 255]);
+        data
+    }
+
+    /// Glyph 5 drawn as glyph 10 in entry 1 over glyph 11 in the text color, glyph 7 as glyph 12 in entry 0.
+    fn colr() -> Vec<u8> {
+        let mut data = vec![0, 0, 0, 2, 0, 0, 0, 14, 0, 0, 0, 26, 0, 3];
+        data.extend_from_slice(&[0, 5, 0, 0, 0, 2]);
+        data.extend_from_slice(&[0, 7, 0, 2, 0, 1]);
+        data.extend_from_slice(&[0, 10, 0, 1, 0, 11, 255, 255, 0, 12, 0, 0]);
+        data
+    }
+
+    #[test]
+    fn should_read_palettes_with_types() {
+        let palettes = palettes(&cpal()).unwrap();
+        assert_eq!(palettes, vec![
+            Palette { index: 0, colors: vec![[255, 0, 0, 255], [0, 0, 255, 255]], flags: 1 },
+            Palette { index: 1, colors: vec![[255, 128, 255, 255], [0, 255, 255, 255]], flags: 2 },
+        ]);
+        assert!(palettes[0].for_light_background() && !palettes[0].for_dark_background());
+        assert!(palettes[1].for_dark_background());
+    }
+
+    #[test]
+    fn layers_should_resolve_against_any_palette() {
+        let (colr, cpal) = (colr(), cpal());
+        let layers = |glyph_id, palette| color_layers(Some(&colr[..]), Some(&cpal[..]), glyph_id, palette);
+
+        assert_eq!(layers(5, 0), Ok(vec![
+            ColorLayer { glyph_id: 10, color: Some([0, 0, 255, 255]) },
+            ColorLayer { glyph_id: 11, color: None },
+        ]));
+        assert_eq!(layers(7, 1), Ok(vec![ColorLayer { glyph_id: 12, color: Some([255, 128, 255, 255]) }]));
+        assert_eq!(layers(6, 0), Ok(Vec::new()));
+        assert_eq!(layers(5, 2), Err(ColorLayersError::PaletteOutOfRange { palette: 2, count: 2 }));
+    }
+
+    #[test]
+    fn font_without_color_tables_should_have_no_layers() {
+        assert_eq!(color_layers(None, None, 5, 3), Ok(Vec::new()));
+        assert_eq!(color_layers(Some(&colr()[..]), None, 5, 0), Err(ColorLayersError::PaletteOutOfRange { palette: 0, count: 0 }));
+    }
+}
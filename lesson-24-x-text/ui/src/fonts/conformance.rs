@@ -0,0 +1,361 @@
+//! Shaping conformance corpora: strings shaped per script and compared with recorded
+//! `Buffer::debug_shape_string` output, see `run_conformance`.
+//!
+//! A corpus directory holds one `<name>.txt` file per corpus:
+//!
+//! ```text
+//! # comment
+//! font: ../fonts/DejaVuSans.ttf
+//! سلام
+//! ```
+//!
+//! The `font:` line is a font file relative to the directory or a CSS family list; every other
+//! line is a case. Expected output is in `<name>.expected`, a `font:` line with the PostScript name of
+//! the font it was recorded with followed by `text<TAB>output` lines, written by
+//! `record_conformance`. Corpora are skipped when their font is missing or differs from the
+//! recorded one, since output depends on the font version.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use super::{Font, Fonts, Properties};
+
+/// Result of one case of a corpus.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CaseOutcome {
+    Passed,
+    Failed { expected: String, actual: String },
+    /// There is no recorded output for the case.
+    Unrecorded { actual: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaseResult {
+    pub text: String,
+    pub outcome: CaseOutcome,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CorpusReport {
+    /// File name of the corpus without the extension.
+    pub name: String,
+    /// PostScript name of the font the corpus was shaped with.
+    pub font: Option<String>,
+    /// Why the corpus was not shaped, such as a missing font.
+    pub skipped: Option<String>,
+    pub cases: Vec<CaseResult>,
+}
+
+/// Outcome of all corpora of a directory, `Display` lists the failed cases with glyph diffs.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ConformanceReport {
+    pub corpora: Vec<CorpusReport>,
+}
+
+impl ConformanceReport {
+    /// No case failed; skipped corpora and unrecorded cases do not fail.
+    pub fn is_passing(&self) -> bool {
+        self.failures().next().is_none()
+    }
+
+    /// Every corpus was shaped and every case matched its recorded output, for corpora with
+    /// bundled fonts where a skip or a missing recording is an error too.
+    pub fn is_complete(&self) -> bool {
+        self.corpora.iter().all(|corpus| corpus.skipped.is_none())
+            && self.corpora.iter().flat_map(|corpus| corpus.cases.iter()).all(|case| case.outcome == CaseOutcome::Passed)
+    }
+
+    pub fn failures<'r>(&'r self) -> impl Iterator<Item=(&'r CorpusReport, &'r CaseResult)> + 'r {
+        self.corpora.iter()
+            .flat_map(|corpus| corpus.cases.iter().map(move |case| (corpus, case)))
+            .filter(|&(_, case)| match case.outcome {
+                CaseOutcome::Failed { .. } => true,
+                _ => false,
+            })
+    }
+
+    fn count(&self, passed: bool) -> usize {
+        self.corpora.iter()
+            .flat_map(|corpus| corpus.cases.iter())
+            .filter(|case| (case.outcome == CaseOutcome::Passed) == passed)
+            .count()
+    }
+}
+
+impl fmt::Display for ConformanceReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for corpus in &self.corpora {
+            if let Some(ref reason) = corpus.skipped {
+                writeln!(f, "{}: skipped, {}", corpus.name, reason)?;
+                continue;
+            }
+            let font = corpus.font.as_ref().map_or("unnamed font", |name| name.as_str());
+            let unrecorded = corpus.cases.iter().filter(|case| match case.outcome {
+                CaseOutcome::Unrecorded { .. } => true,
+                _ => false,
+            }).count();
+            writeln!(f, "{} ({}): {} cases, {} unrecorded", corpus.name, font, corpus.cases.len(), unrecorded)?;
+
+            for case in &corpus.cases {
+                if let CaseOutcome::Failed { ref expected, ref actual } = case.outcome {
+                    writeln!(f, "  {:?}", case.text)?;
+                    write_diff(f, expected, actual)?;
+                }
+            }
+        }
+        write!(f, "{} passed, {} not passed", self.count(true), self.count(false))
+    }
+}
+
+/// Glyphs of two `hb-shape` strings side by side, differing ones marked.
+fn write_diff(f: &mut fmt::Formatter, expected: &str, actual: &str) -> fmt::Result {
+    let glyphs = |s: &str| s.trim_start_matches('[').trim_end_matches(']').split('|').map(str::to_string).collect::<Vec<_>>();
+    let (expected, actual) = (glyphs(expected), glyphs(actual));
+    for i in 0..expected.len().max(actual.len()) {
+        let (e, a) = (expected.get(i).map_or("", |g| g.as_str()), actual.get(i).map_or("", |g| g.as_str()));
+        if e == a {
+            writeln!(f, "      {:3} {}", i, e)?;
+        } else {
+            writeln!(f, "    - {:3} {}", i, e)?;
+            writeln!(f, "    + {:3} {}", i, a)?;
+        }
+    }
+    Ok(())
+}
+
+struct Corpus {
+    name: String,
+    font: String,
+    cases: Vec<String>,
+}
+
+struct Expected {
+    font: String,
+    cases: Vec<(String, String)>,
+}
+
+fn font_line(line: &str) -> Option<&str> {
+    if line.starts_with("font:") {
+        Some(line["font:".len()..].trim())
+    } else {
+        None
+    }
+}
+
+fn read_corpus(path: &Path) -> io::Result<Corpus> {
+    let name = path.file_stem().map_or_else(String::new, |s| s.to_string_lossy().into_owned());
+    let mut font = None;
+    let mut cases = Vec::new();
+    for line in fs::read_to_string(path)?.lines() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match font_line(line) {
+            Some(value) => font = Some(value.to_string()),
+            None => cases.push(line.to_string()),
+        }
+    }
+    let font = font.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("{}: missing font: line", path.display())))?;
+    Ok(Corpus { name, font, cases })
+}
+
+fn read_expected(path: &Path) -> io::Result<Option<Expected>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let mut font = None;
+    let mut cases = Vec::new();
+    for line in fs::read_to_string(path)?.lines() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(value) = font_line(line) {
+            font = Some(value.to_string());
+            continue;
+        }
+        let mut parts = line.splitn(2, '\t');
+        match (parts.next(), parts.next()) {
+            (Some(text), Some(output)) => cases.push((text.to_string(), output.to_string())),
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{}: expected text<TAB>output in {:?}", path.display(), line))),
+        }
+    }
+    Ok(font.map(|font| Expected { font, cases }))
+}
+
+fn load_font(fonts: &Fonts, dir: &Path, spec: &str) -> Option<Font> {
+    let lower = spec.to_lowercase();
+    if lower.ends_with(".ttf") || lower.ends_with(".otf") || lower.ends_with(".ttc") {
+        let bytes = fs::read(dir.join(spec)).ok()?;
+        fonts.font_from_bytes(bytes, 0).ok()
+    } else {
+        fonts.find_best_match_css(spec, &Properties::new())
+    }
+}
+
+fn font_name(font: &Font) -> String {
+    font.postscript_name().unwrap_or_else(|| font.full_name())
+}
+
+/// Corpus files of `dir` in name order.
+fn corpora(dir: &Path) -> io::Result<Vec<Corpus>> {
+    let mut paths = fs::read_dir(dir)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    paths.retain(|path| path.extension().map_or(false, |e| e == "txt"));
+    paths.sort();
+    paths.iter().map(|path| read_corpus(path)).collect()
+}
+
+fn shape_corpus(fonts: &Fonts, dir: &Path, corpus: &Corpus, expected: Option<&Expected>) -> CorpusReport {
+    let mut report = CorpusReport { name: corpus.name.clone(), font: None, skipped: None, cases: Vec::new() };
+    let font = match load_font(fonts, dir, &corpus.font) {
+        Some(font) => font,
+        None => {
+            report.skipped = Some(format!("font {} not found", corpus.font));
+            return report;
+        }
+    };
+    let name = font_name(&font);
+    report.font = Some(name.clone());
+    if let Some(expected) = expected {
+        if expected.font != name {
+            report.skipped = Some(format!("recorded with {}, found {}", expected.font, name));
+            return report;
+        }
+    }
+
+    for text in &corpus.cases {
+        let actual = fonts.shape_debug(&font, text);
+        let recorded = expected.and_then(|e| e.cases.iter().find(|&&(ref t, _)| t == text)).map(|&(_, ref output)| output.clone());
+        let outcome = match recorded {
+            Some(ref expected) if *expected == actual => CaseOutcome::Passed,
+            Some(expected) => CaseOutcome::Failed { expected, actual },
+            None => CaseOutcome::Unrecorded { actual },
+        };
+        report.cases.push(CaseResult { text: text.clone(), outcome });
+    }
+    report
+}
+
+/// Shapes the corpora of `dir` with a new `Fonts` and compares them with the recorded output.
+pub fn run_conformance<P: AsRef<Path>>(dir: P) -> io::Result<ConformanceReport> {
+    let dir = dir.as_ref();
+    let fonts = Fonts::new();
+    let mut report = ConformanceReport::default();
+    for corpus in corpora(dir)? {
+        let expected = read_expected(&dir.join(format!("{}.expected", corpus.name)))?;
+        report.corpora.push(shape_corpus(&fonts, dir, &corpus, expected.as_ref()));
+    }
+    Ok(report)
+}
+
+/// Shapes the corpora of `dir` and writes the output as the expected one, replacing earlier
+/// recordings. Corpora without their font are left as they are.
+pub fn record_conformance<P: AsRef<Path>>(dir: P) -> io::Result<ConformanceReport> {
+    let dir = dir.as_ref();
+    let fonts = Fonts::new();
+    let mut report = ConformanceReport::default();
+    for corpus in corpora(dir)? {
+        let mut shaped = shape_corpus(&fonts, dir, &corpus, None);
+        if let (None, Some(font)) = (shaped.skipped.as_ref(), shaped.font.as_ref()) {
+            let mut contents = format!("font: {}\n", font);
+            for case in &mut shaped.cases {
+                if let CaseOutcome::Unrecorded { ref actual } = case.outcome {
+                    contents.push_str(&format!("{}\t{}\n", case.text, actual));
+                }
+                case.outcome = CaseOutcome::Passed;
+            }
+            fs::write(dir.join(format!("{}.expected", corpus.name)), contents)?;
+        }
+        report.corpora.push(shaped);
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn fixtures(dir: &str) -> ::std::path::PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures").join(dir)
+    }
+
+    /// Set `RECORD_CONFORMANCE` to record the output instead.
+    fn run_or_record(dir: &str) -> ConformanceReport {
+        if ::std::env::var_os("RECORD_CONFORMANCE").is_some() {
+            record_conformance(fixtures(dir))
+        } else {
+            run_conformance(fixtures(dir))
+        }.expect("conformance fixtures should be readable")
+    }
+
+    /// Corpora shaped with the fonts of `fixtures/fonts`.
+    #[test]
+    fn corpora_should_match_the_recorded_output() {
+        let report = run_or_record("conformance");
+
+        let names = report.corpora.iter().map(|c| c.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["arabic", "latin"]);
+        assert!(report.corpora.iter().all(|c| c.cases.len() > 4), "\n{}", report);
+        assert!(report.is_complete(), "\n{}", report);
+    }
+
+    /// Corpora of scripts without a bundled font, run with `--ignored` where Noto Sans
+    /// Devanagari and Noto Color Emoji are installed, after recording them there.
+    #[test]
+    #[ignore]
+    fn system_font_corpora_should_match_the_recorded_output() {
+        let report = run_or_record("conformance-system");
+
+        let names = report.corpora.iter().map(|c| c.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["devanagari", "emoji"]);
+        assert!(report.is_complete(), "\n{}", report);
+    }
+
+    #[test]
+    fn failures_should_show_the_differing_glyphs() {
+        let report = ConformanceReport {
+            corpora: vec![CorpusReport {
+                name: "latin".to_string(),
+                font: Some("Sans".to_string()),
+                skipped: None,
+                cases: vec![
+                    CaseResult { text: "ok".to_string(), outcome: CaseOutcome::Passed },
+                    CaseResult { text: "fi".to_string(), outcome: CaseOutcome::Failed {
+                        expected: "[fi=0+1100]".to_string(),
+                        actual: "[f=0+600|i=1+500]".to_string(),
+                    } },
+                ],
+            }],
+        };
+        assert!(!report.is_passing());
+        assert!(!report.is_complete());
+        assert_eq!(report.to_string(), [
+            "latin (Sans): 2 cases, 0 unrecorded",
+            "  \"fi\"",
+            "    -   0 fi=0+1100",
+            "    +   0 f=0+600",
+            "    -   1 ",
+            "    +   1 i=1+500",
+            "1 passed, 1 not passed",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn skipped_corpora_and_unrecorded_cases_should_not_be_complete() {
+        let corpus = |skipped: Option<&str>, outcome: CaseOutcome| CorpusReport {
+            name: "latin".to_string(),
+            font: Some("Sans".to_string()),
+            skipped: skipped.map(str::to_string),
+            cases: vec![CaseResult { text: "ok".to_string(), outcome }],
+        };
+        let report = |corpora: Vec<CorpusReport>| ConformanceReport { corpora };
+
+        assert!(report(vec![corpus(None, CaseOutcome::Passed)]).is_complete());
+        let unrecorded = report(vec![corpus(None, CaseOutcome::Unrecorded { actual: "[o=0+600]".to_string() })]);
+        assert!(unrecorded.is_passing() && !unrecorded.is_complete());
+        let skipped = report(vec![corpus(None, CaseOutcome::Passed), corpus(Some("font Sans not found"), CaseOutcome::Passed)]);
+        assert!(skipped.is_passing() && !skipped.is_complete());
+    }
+}
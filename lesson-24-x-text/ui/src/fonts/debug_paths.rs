@@ -0,0 +1,75 @@
+//! Overlay paths of a layout for debugging, see `TextLayout::debug_paths`.
+
+use lyon_path::default::{Path, Builder};
+use lyon_path::builder::FlatPathBuilder;
+use lyon_path::math::point;
+use super::layout::{Bounds, TextLayout};
+
+/// Outlines of layout records in layout space, one path per kind so each can be stroked in
+/// its own color with a lyon stroke tessellator.
+#[derive(Debug, Clone)]
+pub struct DebugPaths {
+    /// A segment along the baseline of each line, over the line width.
+    pub baselines: Path,
+    /// The box of each line, from its ascent to its descent, over the line width.
+    pub line_boxes: Path,
+    /// The advance of each glyph, between the line ascent and descent.
+    pub advances: Path,
+    /// A tick below the baseline at the start of each cluster and the end of each line.
+    pub cluster_ticks: Path,
+    /// The ink bounds of each glyph with an outline.
+    pub ink_bounds: Path,
+}
+
+fn rect(builder: &mut Builder, b: Bounds) {
+    builder.move_to(point(b.min_x, b.min_y));
+    builder.line_to(point(b.max_x, b.min_y));
+    builder.line_to(point(b.max_x, b.max_y));
+    builder.line_to(point(b.min_x, b.max_y));
+    builder.close();
+}
+
+fn segment(builder: &mut Builder, x0: f32, y0: f32, x1: f32, y1: f32) {
+    builder.move_to(point(x0, y0));
+    builder.line_to(point(x1, y1));
+}
+
+pub fn debug_paths(layout: &TextLayout) -> DebugPaths {
+    let origin = layout.origin();
+    let (mut baselines, mut line_boxes, mut advances, mut cluster_ticks, mut ink_bounds) =
+        (Path::builder(), Path::builder(), Path::builder(), Path::builder(), Path::builder());
+
+    for (index, line) in layout.lines().iter().enumerate() {
+        let glyphs = layout.line_glyphs(index);
+        let start = glyphs.first().map_or(line.x_offset, |g| g.x);
+        let end = glyphs.last().map_or(line.x_offset, |g| g.x + g.x_advance);
+        let top = line.baseline_y + origin.y(line.ascent);
+        let bottom = line.baseline_y + origin.y(line.descent);
+        let tick = line.baseline_y + origin.y(line.descent * 0.5);
+
+        segment(&mut baselines, start, line.baseline_y, end, line.baseline_y);
+        rect(&mut line_boxes, Bounds { min_x: start, min_y: top.min(bottom), max_x: end, max_y: top.max(bottom) });
+
+        let mut previous = None;
+        for glyph in glyphs {
+            rect(&mut advances, Bounds { min_x: glyph.x, min_y: top.min(bottom), max_x: glyph.x + glyph.x_advance, max_y: top.max(bottom) });
+            if previous != Some(glyph.cluster) {
+                segment(&mut cluster_ticks, glyph.x, line.baseline_y, glyph.x, tick);
+            }
+            previous = Some(glyph.cluster);
+        }
+        segment(&mut cluster_ticks, end, line.baseline_y, end, tick);
+    }
+
+    for ink in layout.glyph_ink.iter().filter_map(|ink| *ink) {
+        rect(&mut ink_bounds, origin.bounds(ink));
+    }
+
+    DebugPaths {
+        baselines: baselines.build(),
+        line_boxes: line_boxes.build(),
+        advances: advances.build(),
+        cluster_ticks: cluster_ticks.build(),
+        ink_bounds: ink_bounds.build(),
+    }
+}
@@ -0,0 +1,139 @@
+//! Harfbuzz text serialization of shaped glyphs, the output of `hb-shape`.
+
+use super::sfnt::{read_u16, read_u32};
+use super::GlyphPosition;
+use std::fmt::Write;
+
+/// Names of the standard Macintosh glyph order, used by `post` table indices below 258.
+const MAC_GLYPH_NAMES: [&str; 258] = [
+    ".notdef", ".null", "nonmarkingreturn", "space", "exclam", "quotedbl", "numbersign", "dollar",
+    "percent", "ampersand", "quotesingle", "parenleft", "parenright", "asterisk", "plus", "comma",
+    "hyphen", "period", "slash", "zero", "one", "two", "three", "four", "five", "six", "seven",
+    "eight", "nine", "colon", "semicolon", "less", "equal", "greater", "question", "at", "A", "B",
+    "C", "D", "E", "F", "G", "H", "I", "J", "K", "L", "M", "N", "O", "P", "Q", "R", "S", "T", "U",
+    "V", "W", "X", "Y", "Z", "bracketleft", "backslash", "bracketright", "asciicircum",
+    "underscore", "grave", "a", "b", "c", "d", "e", "f", "g", "h", "i", "j", "k", "l", "m", "n",
+    "o", "p", "q", "r", "s", "t", "u", "v", "w", "x", "y", "z", "braceleft", "bar", "braceright",
+    "asciitilde", "Adieresis", "Aring", "Ccedilla", "Eacute", "Ntilde", "Odieresis", "Udieresis",
+    "aacute", "agrave", "acircumflex", "adieresis", "atilde", "aring", "ccedilla", "eacute",
+    "egrave", "ecircumflex", "edieresis", "iacute", "igrave", "icircumflex", "idieresis", "ntilde",
+    "oacute", "ograve", "ocircumflex", "odieresis", "otilde", "uacute", "ugrave", "ucircumflex",
+    "udieresis", "dagger", "degree", "cent", "sterling", "section", "bullet", "paragraph",
+    "germandbls", "registered", "copyright", "trademark", "acute", "dieresis", "notequal", "AE",
+    "Oslash", "infinity", "plusminus", "lessequal", "greaterequal", "yen", "mu", "partialdiff",
+    "summation", "product", "pi", "integral", "ordfeminine", "ordmasculine", "Omega", "ae",
+    "oslash", "questiondown", "exclamdown", "logicalnot", "radical", "florin", "approxequal",
+    "Delta", "guillemotleft", "guillemotright", "ellipsis", "nonbreakingspace", "Agrave", "Atilde",
+    "Otilde", "OE", "oe", "endash", "emdash", "quotedblleft", "quotedblright", "quoteleft",
+    "quoteright", "divide", "lozenge", "ydieresis", "Ydieresis", "fraction", "currency",
+    "guilsinglleft", "guilsinglright", "fi", "fl", "daggerdbl", "periodcentered", "quotesinglbase",
+    "quotedblbase", "perthousand", "Acircumflex", "Ecircumflex", "Aacute", "Edieresis", "Egrave",
+    "Iacute", "Icircumflex", "Idieresis", "Igrave", "Oacute", "Ocircumflex", "apple", "Ograve",
+    "Uacute", "Ucircumflex", "Ugrave", "dotlessi", "circumflex", "tilde", "macron", "breve",
+    "dotaccent", "ring", "cedilla", "hungarumlaut", "ogonek", "caron", "Lslash", "lslash", "Scaron",
+    "scaron", "Zcaron", "zcaron", "brokenbar", "Eth", "eth", "Yacute", "yacute", "Thorn", "thorn",
+    "minus", "multiply", "onesuperior", "twosuperior", "threesuperior", "onehalf", "onequarter",
+    "threequarters", "franc", "Gbreve", "gbreve", "Idotaccent", "Scedilla", "scedilla", "Cacute",
+    "cacute", "Ccaron", "ccaron", "dcroat",
+];
+
+/// Name of a glyph from a version 1 or 2 `post` table, `None` for other versions such as
+/// the nameless version 3 of most CFF fonts.
+pub fn post_glyph_name(post: &[u8], glyph_id: u32) -> Option<String> {
+    match read_u32(post, 0)? {
+        0x0001_0000 => MAC_GLYPH_NAMES.get(glyph_id as usize).map(|name| name.to_string()),
+        0x0002_0000 => {
+            let count = read_u16(post, 32)? as usize;
+            if glyph_id as usize >= count {
+                return None;
+            }
+            let index = read_u16(post, 34 + glyph_id as usize * 2)? as usize;
+            if index < MAC_GLYPH_NAMES.len() {
+                return Some(MAC_GLYPH_NAMES[index].to_string());
+            }
+
+            // pascal strings follow the indices
+            let mut offset = 34 + count * 2;
+            for _ in 0..index - MAC_GLYPH_NAMES.len() {
+                offset += 1 + *post.get(offset)? as usize;
+            }
+            let len = *post.get(offset)? as usize;
+            post.get(offset + 1..offset + 1 + len).map(|name| String::from_utf8_lossy(name).into_owned())
+        }
+        _ => None,
+    }
+}
+
+/// Glyphs in the default `hb-shape` format, `[name=cluster@x_offset,y_offset+x_advance|...]`.
+///
+/// Offsets are left out when both are zero and the y advance when it is zero. Glyphs
+/// without a name are written as `gid` and the glyph id.
+pub fn serialize<N: Fn(&GlyphPosition) -> Option<String>>(glyphs: &[GlyphPosition], name: N) -> String {
+    let mut output = String::from("[");
+
+    for (i, glyph) in glyphs.iter().enumerate() {
+        if i > 0 {
+            output.push('|');
+        }
+        match name(glyph) {
+            Some(name) => output.push_str(&name),
+            None => write!(output, "gid{}", glyph.id).unwrap(),
+        }
+        write!(output, "={}", glyph.cluster).unwrap();
+        if glyph.x_offset != 0 || glyph.y_offset != 0 {
+            write!(output, "@{},{}", glyph.x_offset, glyph.y_offset).unwrap();
+        }
+        write!(output, "+{}", glyph.x_advance).unwrap();
+        if glyph.y_advance != 0 {
+            write!(output, ",{}", glyph.y_advance).unwrap();
+        }
+    }
+
+    output.push(']');
+    output
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::GlyphFlags;
+
+    fn glyph(id: u32, cluster: u32, x_advance: i32, x_offset: i32, y_offset: i32) -> GlyphPosition {
+        GlyphPosition {
+            id,
+            cluster,
+            byte_offset: cluster,
+            len: 1,
+            x_advance,
+            y_advance: 0,
+            x_offset,
+            y_offset,
+            span: 0,
+            flags: GlyphFlags::default(),
+        }
+    }
+
+    #[test]
+    fn glyphs_should_serialize_like_hb_shape() {
+        let glyphs = [glyph(36, 0, 1155, 0, 0), glyph(3, 1, 0, -20, 310), glyph(90, 3, 600, 0, 0)];
+        let name = |g: &GlyphPosition| if g.id == 90 { None } else { Some(MAC_GLYPH_NAMES[g.id as usize].to_string()) };
+        assert_eq!(serialize(&glyphs, name), "[A=0+1155|space=1@-20,310+0|gid90=3+600]");
+        assert_eq!(serialize(&[], name), "[]");
+    }
+
+    #[test]
+    fn post_names_should_come_from_the_mac_order_or_the_table() {
+        let mut post = vec![0, 2, 0, 0];
+        post.extend_from_slice(&[0; 28]);
+        post.extend_from_slice(&[0, 3, 0, 0, 1, 3, 1, 2]);
+        post.extend_from_slice(b"\x07uni0627\x03f_f");
+        assert_eq!(post_glyph_name(&post, 0).as_ref().map(|s| s.as_str()), Some(".notdef"));
+        assert_eq!(post_glyph_name(&post, 1).as_ref().map(|s| s.as_str()), Some("f_f"));
+        assert_eq!(post_glyph_name(&post, 2).as_ref().map(|s| s.as_str()), Some("uni0627"));
+        assert_eq!(post_glyph_name(&post, 3), None);
+
+        let mut post = vec![0, 3, 0, 0];
+        post.extend_from_slice(&[0; 28]);
+        assert_eq!(post_glyph_name(&post, 0), None);
+    }
+}
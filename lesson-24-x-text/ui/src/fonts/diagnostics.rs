@@ -0,0 +1,129 @@
+use std::cell::RefCell;
+use std::fmt;
+
+/// Structured report of a font loading problem or lifecycle change, see `Fonts::set_diagnostics`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FontsDiagnostic {
+    /// No font was found or it could not be loaded.
+    LoadFailed { family: String, reason: String },
+    /// Harfbuzz could not use face `index` of the font file at `path`.
+    FaceFailed { path: String, index: u32 },
+    OutlineFailed { glyph_id: u32, reason: String },
+    FontLoaded { name: String },
+    FontUnloaded { name: String },
+    /// A buffer feature range was outside the text and the feature was removed.
+    FeatureDropped { tag: String, range: ::std::ops::Range<usize> },
+    /// No font of the requested families was found, the auto fallback font is used instead.
+    FamilySubstituted { requested: String, substitute: String },
+    /// The file of a loaded font was deleted or its volume unmounted; the font keeps working
+    /// from its data in memory. Reported once per font.
+    SourceLost { font_id: usize, path: String },
+    /// A new font was set on a buffer with spans, its whole text is shaped with that font.
+    SpansDropped { buffer_id: usize, spans: usize },
+    /// A font or buffer reference of another `Fonts` container was passed to `call`, which
+    /// returned `None`, see `Fonts::ptr_eq`.
+    OtherContainer { call: String },
+}
+
+impl FontsDiagnostic {
+    pub fn is_error(&self) -> bool {
+        match *self {
+            FontsDiagnostic::LoadFailed { .. }
+            | FontsDiagnostic::FaceFailed { .. }
+            | FontsDiagnostic::OutlineFailed { .. }
+            | FontsDiagnostic::OtherContainer { .. } => true,
+            FontsDiagnostic::FontLoaded { .. }
+            | FontsDiagnostic::FontUnloaded { .. }
+            | FontsDiagnostic::FeatureDropped { .. }
+            | FontsDiagnostic::FamilySubstituted { .. }
+            | FontsDiagnostic::SourceLost { .. }
+            | FontsDiagnostic::SpansDropped { .. } => false,
+        }
+    }
+}
+
+impl fmt::Display for FontsDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FontsDiagnostic::LoadFailed { ref family, ref reason } => write!(f, "couldn't load {}: {}", family, reason),
+            FontsDiagnostic::FaceFailed { ref path, index } => write!(f, "couldn't load font face {} from {}", index, path),
+            FontsDiagnostic::OutlineFailed { glyph_id, ref reason } => write!(f, "couldn't load outline of glyph {}: {}", glyph_id, reason),
+            FontsDiagnostic::FontLoaded { ref name } => write!(f, "loaded font {}", name),
+            FontsDiagnostic::FontUnloaded { ref name } => write!(f, "unloaded font {}", name),
+            FontsDiagnostic::FeatureDropped { ref tag, ref range } => write!(f, "dropped feature {} outside the text at {}..{}", tag, range.start, range.end),
+            FontsDiagnostic::FamilySubstituted { ref requested, ref substitute } => write!(f, "using {} instead of {}", substitute, requested),
+            FontsDiagnostic::SourceLost { font_id, ref path } => write!(f, "font file {} of font {} is gone, using the loaded data", path, font_id),
+            FontsDiagnostic::SpansDropped { buffer_id, spans } => write!(f, "dropped {} spans of buffer {} for its new font", spans, buffer_id),
+            FontsDiagnostic::OtherContainer { ref call } => write!(f, "{} got a reference of another fonts container", call),
+        }
+    }
+}
+
+/// Sends diagnostics to the handler, or to the log if there is none.
+///
+/// Reports for the handler wait in a queue until `flush`, which runs once the fonts are no
+/// longer borrowed.
+pub struct Diagnostics {
+    handler: RefCell<Option<Box<dyn Fn(FontsDiagnostic)>>>,
+    queued: RefCell<Vec<FontsDiagnostic>>,
+}
+
+impl Diagnostics {
+    pub fn new() -> Diagnostics {
+        Diagnostics {
+            handler: RefCell::new(None),
+            queued: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub fn set_handler(&self, handler: Box<dyn Fn(FontsDiagnostic)>) {
+        *self.handler.borrow_mut() = Some(handler);
+    }
+
+    pub fn report(&self, diagnostic: FontsDiagnostic) {
+        match *self.handler.borrow() {
+            Some(_) => self.queued.borrow_mut().push(diagnostic),
+            None if diagnostic.is_error() => error!("{}", diagnostic),
+            None => debug!("{}", diagnostic),
+        }
+    }
+
+    pub fn has_queued(&self) -> bool {
+        !self.queued.borrow().is_empty()
+    }
+
+    /// Sends the queued reports to the handler, including reports made by the handler.
+    pub fn flush(&self) {
+        loop {
+            let queued = ::std::mem::replace(&mut *self.queued.borrow_mut(), Vec::new());
+            if queued.is_empty() {
+                return;
+            }
+            if let Some(ref handler) = *self.handler.borrow() {
+                queued.into_iter().for_each(|diagnostic| handler(diagnostic));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::rc::Rc;
+
+    #[test]
+    fn handler_should_receive_reports() {
+        let received = Rc::new(RefCell::new(Vec::new()));
+        let diagnostics = Diagnostics::new();
+        {
+            let received = received.clone();
+            diagnostics.set_handler(Box::new(move |d| received.borrow_mut().push(d.to_string())));
+        }
+
+        diagnostics.report(FontsDiagnostic::LoadFailed { family: "Fira Sans".into(), reason: "file not found".into() });
+        assert!(received.borrow().is_empty() && diagnostics.has_queued());
+        diagnostics.flush();
+
+        assert_eq!(*received.borrow(), vec!["couldn't load Fira Sans: file not found".to_string()]);
+    }
+}
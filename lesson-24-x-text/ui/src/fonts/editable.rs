@@ -0,0 +1,182 @@
+//! Text split into paragraph buffers for editors, see `EditableBuffer`.
+
+use std::ops::Range;
+use crate::na;
+use super::{Buffer, Font, GlyphPosition};
+use super::layout::{self, LayoutOptions, TextLayout};
+
+/// Editable text kept as one buffer per paragraph, so an edit reshapes only the paragraphs it
+/// touches instead of the whole document.
+///
+/// An edit inside a paragraph goes through `Buffer::splice`, which reshapes between the safe
+/// to break boundaries around it; edits adding or removing paragraph separators replace the
+/// buffers of the paragraphs involved. Byte indices are in the whole text, the read methods
+/// stitch the paragraph outputs together.
+pub struct EditableBuffer {
+    font: Font,
+    transform: Option<na::Projective3<f32>>,
+    color: na::Vector4<u8>,
+    /// Paragraphs with the separators ending them, the last one without a separator and
+    /// possibly empty.
+    chunks: Vec<Buffer>,
+    /// Byte offset of each chunk in the text.
+    starts: Vec<usize>,
+    len: usize,
+}
+
+impl EditableBuffer {
+    pub(crate) fn new(font: &Font, text: &str, transform: Option<na::Projective3<f32>>, color: na::Vector4<u8>) -> EditableBuffer {
+        let mut buffer = EditableBuffer {
+            font: font.clone(),
+            transform,
+            color,
+            chunks: Vec::new(),
+            starts: Vec::new(),
+            len: 0,
+        };
+        buffer.set_text(text);
+        buffer
+    }
+
+    pub fn font(&self) -> &Font {
+        &self.font
+    }
+
+    pub fn text(&self) -> String {
+        let mut text = String::with_capacity(self.len);
+        for chunk in &self.chunks {
+            chunk.with_text(|chunk_text| text.push_str(chunk_text));
+        }
+        text
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Paragraph buffers in text order, such as for drawing the ones in view.
+    pub fn chunks(&self) -> &[Buffer] {
+        &self.chunks
+    }
+
+    /// Byte range of a paragraph buffer in the text.
+    pub fn chunk_range(&self, index: usize) -> Range<usize> {
+        self.starts[index]..self.chunk_end(index)
+    }
+
+    /// Index of the paragraph buffer containing `byte_index`, the last one at the end of the text.
+    pub fn chunk_at(&self, byte_index: usize) -> usize {
+        match self.starts.binary_search(&byte_index) {
+            Ok(index) => index,
+            Err(index) => index - 1,
+        }
+    }
+
+    fn chunk_end(&self, index: usize) -> usize {
+        self.starts.get(index + 1).cloned().unwrap_or(self.len)
+    }
+
+    /// Replaces the whole text, shaping every paragraph.
+    pub fn set_text(&mut self, text: &str) {
+        self.chunks = layout::paragraph_chunks(text).into_iter()
+            .map(|range| self.font.create_buffer(&text[range], self.transform, self.color))
+            .collect();
+        self.update_starts(0);
+    }
+
+    pub fn insert(&mut self, byte_index: usize, text: &str) {
+        self.splice(byte_index..byte_index, text);
+    }
+
+    /// Replaces `byte_range` of the text, reshaping only the paragraphs the edit touches.
+    pub fn splice(&mut self, byte_range: Range<usize>, replacement: &str) {
+        assert!(byte_range.start <= byte_range.end && byte_range.end <= self.len, "splice: byte range out of the text");
+        let mut first = self.chunk_at(byte_range.start);
+        let last = self.chunk_at(byte_range.end);
+        // `\r` and `\n` on both sides of the edit become a single separator
+        if first > 0 && byte_range.start == self.starts[first] && self.chunks[first - 1].with_text(|text| text.ends_with('\r')) {
+            first -= 1;
+        }
+
+        let start = self.starts[first];
+        let mut text = self.chunks[first..last + 1].iter().map(Buffer::text).collect::<String>();
+        text.replace_range(byte_range.start - start..byte_range.end - start, replacement);
+
+        let mut ranges = layout::paragraph_chunks(&text);
+        // the text of a chunk before the last one ends with its separator
+        if last + 1 < self.chunks.len() {
+            ranges.pop();
+        }
+
+        if first == last && ranges.len() == 1 {
+            self.chunks[first].splice(byte_range.start - start..byte_range.end - start, replacement);
+        } else {
+            let chunks = ranges.into_iter()
+                .map(|range| self.font.create_buffer(&text[range], self.transform, self.color))
+                .collect::<Vec<_>>();
+            self.chunks.splice(first..last + 1, chunks);
+        }
+        self.update_starts(first);
+    }
+
+    /// Recomputes the chunk offsets from chunk `from` on, without shaping.
+    fn update_starts(&mut self, from: usize) {
+        self.starts.truncate(from);
+        let mut offset = if from == 0 { 0 } else { self.starts[from - 1] + self.chunks[from - 1].text_len() };
+        for chunk in &self.chunks[from..] {
+            self.starts.push(offset);
+            offset += chunk.text_len();
+        }
+        self.len = offset;
+    }
+
+    pub fn glyph_count(&self) -> usize {
+        self.chunks.iter().map(Buffer::glyph_count).sum()
+    }
+
+    /// Glyphs of all paragraphs with clusters and byte offsets in the whole text. Each
+    /// paragraph starts at pen position 0 like a line of a layout.
+    pub fn glyphs(&self, output: &mut Vec<GlyphPosition>) {
+        for (chunk, &start) in self.chunks.iter().zip(&self.starts) {
+            let from = output.len();
+            chunk.glyphs(output);
+            for glyph in &mut output[from..] {
+                glyph.cluster += start as u32;
+                glyph.byte_offset += start as u32;
+            }
+        }
+    }
+
+    /// Paragraph layouts stitched into a layout of the whole text, for wrapping, hit testing,
+    /// carets and selection across paragraphs.
+    pub fn layout(&self, options: &LayoutOptions) -> TextLayout {
+        // there is always a chunk, empty for an empty text
+        let mut layout = self.chunks[0].layout(options);
+        for (chunk, &start) in self.chunks.iter().zip(&self.starts).skip(1) {
+            layout.append(chunk.layout(options), start);
+        }
+        layout
+    }
+
+    /// Next caret position after `byte_index`, moving over whole graphemes.
+    pub fn next_cursor(&self, byte_index: usize) -> usize {
+        let index = self.chunk_at(byte_index.min(self.len));
+        let start = self.starts[index];
+        start + self.chunks[index].next_cursor(byte_index - start)
+    }
+
+    /// Previous caret position before `byte_index`, moving over whole graphemes.
+    pub fn prev_cursor(&self, byte_index: usize) -> usize {
+        let index = self.chunk_at(byte_index.min(self.len));
+        let start = self.starts[index];
+        if byte_index == start && index > 0 {
+            let previous = self.starts[index - 1];
+            return previous + self.chunks[index - 1].prev_cursor(start - previous);
+        }
+        start + self.chunks[index].prev_cursor(byte_index - start)
+    }
+}
@@ -0,0 +1,205 @@
+use metrohash::MetroHashMap;
+use super::BufferRef;
+
+/// Font and buffer lifecycle change, see `Fonts::drain_events`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FontsEvent {
+    FontLoaded(usize),
+    /// The last reference to the font was dropped. It may stay loaded and be used again, see
+    /// `Fonts::set_font_retention`.
+    FontReleased(usize),
+    /// The font was unloaded and its id and cached glyphs are gone.
+    FontEvicted(usize),
+    BufferCreated(BufferRef),
+    /// The shaped content changed, the buffer version was bumped.
+    BufferChanged(BufferRef),
+    BufferDeleted(BufferRef),
+    /// `Fonts::set_scale_factor` changed the factor, glyph atlases should be cleared.
+    ScaleFactorChanged,
+    /// `Fonts::set_metrics_policy` changed the vertical metrics of loaded fonts, layouts
+    /// should be redone.
+    MetricsChanged,
+}
+
+/// Undrained events, coalesced so the queue does not grow with the number of changes.
+///
+/// A buffer has at most one pending `BufferChanged`, which is not needed after a
+/// pending `BufferCreated`. Buffers and fonts both created and deleted since the last
+/// drain were never seen, so both events are dropped, along with a release in between.
+/// The queue holds at most a few events per buffer and font id, and pushing finds the
+/// pending events of an id through a map.
+pub struct EventQueue {
+    /// Events in push order, `None` where a later event made one unneeded.
+    events: Vec<Option<FontsEvent>>,
+    /// Number of `None` events, which are dropped once they are half of the queue.
+    removed: usize,
+    /// Index of the pending `BufferCreated` or `BufferChanged` of each buffer id.
+    buffers: MetroHashMap<usize, usize>,
+    /// Indices of the pending `FontLoaded` and `FontReleased` of each font id.
+    fonts: MetroHashMap<usize, PendingFont>,
+    scale_factor_changed: bool,
+    metrics_changed: bool,
+}
+
+#[derive(Default)]
+struct PendingFont {
+    loaded: Option<usize>,
+    released: Option<usize>,
+}
+
+impl EventQueue {
+    pub fn new() -> EventQueue {
+        EventQueue {
+            events: Vec::new(),
+            removed: 0,
+            buffers: MetroHashMap::default(),
+            fonts: MetroHashMap::default(),
+            scale_factor_changed: false,
+            metrics_changed: false,
+        }
+    }
+
+    pub fn push(&mut self, event: FontsEvent) {
+        let index = self.events.len();
+        match event {
+            FontsEvent::BufferCreated(buffer) => {
+                self.buffers.insert(buffer.id(), index);
+            },
+            FontsEvent::BufferChanged(buffer) => {
+                if self.buffers.contains_key(&buffer.id()) {
+                    return;
+                }
+                self.buffers.insert(buffer.id(), index);
+            },
+            FontsEvent::BufferDeleted(buffer) => {
+                if let Some(pending) = self.buffers.remove(&buffer.id()) {
+                    if let Some(FontsEvent::BufferCreated(_)) = self.events[pending].take() {
+                        self.removed(1);
+                        return;
+                    }
+                }
+            },
+            FontsEvent::FontLoaded(id) => {
+                self.fonts.entry(id).or_insert_with(PendingFont::default).loaded = Some(index);
+            },
+            FontsEvent::FontReleased(id) => {
+                let pending = self.fonts.entry(id).or_insert_with(PendingFont::default);
+                if pending.released.is_some() {
+                    return;
+                }
+                pending.released = Some(index);
+            },
+            FontsEvent::FontEvicted(id) => {
+                if let Some(pending) = self.fonts.remove(&id) {
+                    if let Some(loaded) = pending.loaded {
+                        self.events[loaded] = None;
+                        let mut removed = 1;
+                        if let Some(released) = pending.released {
+                            self.events[released] = None;
+                            removed += 1;
+                        }
+                        self.removed(removed);
+                        return;
+                    }
+                }
+            },
+            FontsEvent::ScaleFactorChanged => {
+                if self.scale_factor_changed {
+                    return;
+                }
+                self.scale_factor_changed = true;
+            },
+            FontsEvent::MetricsChanged => {
+                if self.metrics_changed {
+                    return;
+                }
+                self.metrics_changed = true;
+            },
+        }
+
+        self.events.push(Some(event));
+    }
+
+    pub fn drain_into(&mut self, output: &mut Vec<FontsEvent>) {
+        output.extend(self.events.drain(..).filter_map(|event| event));
+        self.removed = 0;
+        self.buffers.clear();
+        self.fonts.clear();
+        self.scale_factor_changed = false;
+        self.metrics_changed = false;
+    }
+
+    /// Counts removed events and pushes the remaining ones again once half are removed.
+    /// Pushing coalesced events keeps them all, at new indices.
+    fn removed(&mut self, count: usize) {
+        self.removed += count;
+        if self.removed * 2 > self.events.len() {
+            let mut events = Vec::new();
+            self.drain_into(&mut events);
+            for event in events {
+                self.push(event);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::ContainerToken;
+
+    fn buffer(id: usize) -> BufferRef {
+        BufferRef { _font_id: 0, _id: id, _container: ContainerToken(0) }
+    }
+
+    #[test]
+    fn repeated_changes_should_be_coalesced() {
+        let mut queue = EventQueue::new();
+        queue.push(FontsEvent::BufferChanged(buffer(1)));
+        queue.push(FontsEvent::BufferChanged(buffer(1)));
+        queue.push(FontsEvent::BufferCreated(buffer(2)));
+        queue.push(FontsEvent::BufferChanged(buffer(2)));
+
+        let mut events = Vec::new();
+        queue.drain_into(&mut events);
+        assert_eq!(events, vec![FontsEvent::BufferChanged(buffer(1)), FontsEvent::BufferCreated(buffer(2))]);
+
+        queue.drain_into(&mut events);
+        assert_eq!(events.len(), 2);
+    }
+
+    #[test]
+    fn unseen_buffers_and_fonts_should_leave_no_events() {
+        let mut queue = EventQueue::new();
+        queue.push(FontsEvent::FontLoaded(3));
+        queue.push(FontsEvent::BufferCreated(buffer(1)));
+        queue.push(FontsEvent::BufferChanged(buffer(1)));
+        queue.push(FontsEvent::BufferDeleted(buffer(1)));
+        queue.push(FontsEvent::FontReleased(3));
+        queue.push(FontsEvent::FontEvicted(3));
+        queue.push(FontsEvent::BufferChanged(buffer(2)));
+        queue.push(FontsEvent::BufferDeleted(buffer(2)));
+
+        let mut events = Vec::new();
+        queue.drain_into(&mut events);
+        assert_eq!(events, vec![FontsEvent::BufferDeleted(buffer(2))]);
+    }
+
+    #[test]
+    fn queue_should_not_grow_with_repeated_changes() {
+        let mut queue = EventQueue::new();
+        for _ in 0..1000 {
+            queue.push(FontsEvent::BufferCreated(buffer(1)));
+            queue.push(FontsEvent::BufferChanged(buffer(1)));
+            queue.push(FontsEvent::BufferDeleted(buffer(1)));
+            queue.push(FontsEvent::BufferChanged(buffer(2)));
+            queue.push(FontsEvent::FontReleased(3));
+            queue.push(FontsEvent::ScaleFactorChanged);
+        }
+        assert!(queue.events.len() <= 8);
+
+        let mut events = Vec::new();
+        queue.drain_into(&mut events);
+        assert_eq!(events, vec![FontsEvent::BufferChanged(buffer(2)), FontsEvent::FontReleased(3), FontsEvent::ScaleFactorChanged]);
+    }
+}
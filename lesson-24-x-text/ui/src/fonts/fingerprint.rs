@@ -0,0 +1,110 @@
+//! Keys identifying loaded fonts, used to find already loaded fonts and to match persisted
+//! shaping cache entries with fonts, see `Fonts::export_shaped_cache`.
+//!
+//! They only need to be stable, so a fast non-cryptographic hash is used unless the
+//! `sha1-fingerprints` feature selects SHA1. Each algorithm has its own format number.
+
+use byteorder::{ByteOrder, LittleEndian};
+#[cfg(not(feature = "sha1-fingerprints"))]
+use metrohash::MetroHash128;
+#[cfg(not(feature = "sha1-fingerprints"))]
+use std::hash::Hasher;
+#[cfg(feature = "sha1-fingerprints")]
+use sha1::{Digest, Sha1};
+
+pub type Fingerprint = [u8; 20];
+
+/// SHA1 of the inputs.
+pub const FORMAT_SHA1: u32 = 1;
+/// MetroHash128 of the length-prefixed inputs, followed by the format number.
+pub const FORMAT_METRO128: u32 = 2;
+
+/// Format of the fingerprints this build generates.
+#[cfg(feature = "sha1-fingerprints")]
+pub const FORMAT: u32 = FORMAT_SHA1;
+#[cfg(not(feature = "sha1-fingerprints"))]
+pub const FORMAT: u32 = FORMAT_METRO128;
+
+pub struct FingerprintHasher {
+    #[cfg(feature = "sha1-fingerprints")]
+    hasher: Sha1,
+    #[cfg(not(feature = "sha1-fingerprints"))]
+    hasher: MetroHash128,
+}
+
+impl FingerprintHasher {
+    pub fn new() -> FingerprintHasher {
+        FingerprintHasher {
+            #[cfg(feature = "sha1-fingerprints")]
+            hasher: Sha1::new(),
+            #[cfg(not(feature = "sha1-fingerprints"))]
+            hasher: MetroHash128::default(),
+        }
+    }
+
+    /// Adds `bytes`, prefixed with their length so that consecutive inputs can not run into
+    /// each other. SHA1 hashes them as they are, giving the same fingerprints as before
+    /// fingerprints had formats.
+    #[cfg(feature = "sha1-fingerprints")]
+    pub fn input(&mut self, bytes: &[u8]) {
+        self.hasher.input(bytes);
+    }
+
+    #[cfg(not(feature = "sha1-fingerprints"))]
+    pub fn input(&mut self, bytes: &[u8]) {
+        let mut len = [0; 8];
+        LittleEndian::write_u64(&mut len, bytes.len() as u64);
+        self.hasher.write(&len);
+        self.hasher.write(bytes);
+    }
+
+    pub fn input_u32(&mut self, value: u32) {
+        let mut bytes = [0; 4];
+        LittleEndian::write_u32(&mut bytes, value);
+        self.input(&bytes);
+    }
+
+    #[cfg(feature = "sha1-fingerprints")]
+    pub fn result(self) -> Fingerprint {
+        let mut output = [0; 20];
+        output.copy_from_slice(&self.hasher.result());
+        output
+    }
+
+    #[cfg(not(feature = "sha1-fingerprints"))]
+    pub fn result(self) -> Fingerprint {
+        let (high, low) = self.hasher.finish128();
+        let mut output = [0; 20];
+        LittleEndian::write_u64(&mut output[0..8], high);
+        LittleEndian::write_u64(&mut output[8..16], low);
+        LittleEndian::write_u32(&mut output[16..20], FORMAT);
+        output
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn fingerprint(inputs: &[&str]) -> Fingerprint {
+        let mut hasher = FingerprintHasher::new();
+        for input in inputs {
+            hasher.input(input.as_bytes());
+        }
+        hasher.result()
+    }
+
+    #[test]
+    fn fingerprints_should_be_stable_and_depend_on_all_inputs() {
+        assert_eq!(fingerprint(&["font", "data"]), fingerprint(&["font", "data"]));
+        assert_ne!(fingerprint(&["font", "data"]), fingerprint(&["font", "date"]));
+    }
+
+    #[test]
+    #[cfg(not(feature = "sha1-fingerprints"))]
+    fn inputs_should_not_run_into_each_other() {
+        assert_ne!(fingerprint(&["ab", "c"]), fingerprint(&["a", "bc"]));
+        assert_ne!(fingerprint(&["font"]), fingerprint(&["font", ""]));
+        assert_eq!(&fingerprint(&["x"])[16..], &[2, 0, 0, 0]);
+    }
+}
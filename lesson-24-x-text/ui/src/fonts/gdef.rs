@@ -0,0 +1,96 @@
+//! Glyph classes and ligature caret positions from the `GDEF` table.
+
+use super::sfnt::{read_u16, coverage_index, class_of};
+
+pub const GLYPH_CLASS_LIGATURE: u16 = 2;
+pub const GLYPH_CLASS_MARK: u16 = 3;
+
+fn read_i16(data: &[u8], offset: usize) -> Option<i16> {
+    read_u16(data, offset).map(|v| v as i16)
+}
+
+/// Class of a glyph in the glyph class definition, such as `GLYPH_CLASS_MARK`, `0` if it
+/// is not listed, `None` if the table has no class definition.
+pub fn glyph_class(gdef: &[u8], glyph_id: u32) -> Option<u16> {
+    let class_def = read_u16(gdef, 4)? as usize;
+    if class_def == 0 {
+        return None;
+    }
+    class_of(gdef.get(class_def..)?, glyph_id)
+}
+
+/// Caret x coordinates between the components of a ligature glyph in font units, `None` if
+/// the table has none for the glyph.
+///
+/// Carets placed on outline points are not resolved, the whole glyph then has no carets.
+pub fn ligature_carets(gdef: &[u8], glyph_id: u32) -> Option<Vec<i32>> {
+    let list = read_u16(gdef, 8)? as usize;
+    if list == 0 {
+        return None;
+    }
+
+    let coverage = list + read_u16(gdef, list)? as usize;
+    let index = coverage_index(gdef.get(coverage..)?, glyph_id)?;
+    if index >= read_u16(gdef, list + 2)? as usize {
+        return None;
+    }
+
+    let lig_glyph = list + read_u16(gdef, list + 4 + index * 2)? as usize;
+    let count = read_u16(gdef, lig_glyph)? as usize;
+    (0..count)
+        .map(|i| {
+            let caret = lig_glyph + read_u16(gdef, lig_glyph + 2 + i * 2)? as usize;
+            match read_u16(gdef, caret)? {
+                // format 3 adds a device table for hinted sizes, the coordinate is the same
+                1 | 3 => read_i16(gdef, caret + 2).map(i32::from),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Glyph 50 with carets at 300 and 600.
+    fn gdef() -> Vec<u8> {
+        let mut data = vec![0, 1, 0, 0, 0, 0, 0, 0, 0, 12, 0, 0];
+        data.extend_from_slice(&[0, 6, 0, 1, 0, 12]);
+        data.extend_from_slice(&[0, 1, 0, 1, 0, 50]);
+        data.extend_from_slice(&[0, 2, 0, 6, 0, 10]);
+        data.extend_from_slice(&[0, 1, 1, 44, 0, 3, 2, 88, 0, 0]);
+        data
+    }
+
+    #[test]
+    fn should_read_caret_coordinates() {
+        assert_eq!(ligature_carets(&gdef(), 50), Some(vec![300, 600]));
+        assert_eq!(ligature_carets(&gdef(), 51), None);
+    }
+
+    #[test]
+    fn should_read_glyph_classes() {
+        let mut data = vec![0, 1, 0, 0, 0, 12, 0, 0, 0, 0, 0, 0];
+        // glyphs 10 to 12 are ligatures, 20 to 29 marks
+        data.extend_from_slice(&[0, 2, 0, 2, 0, 10, 0, 12, 0, 2, 0, 20, 0, 29, 0, 3]);
+        assert_eq!(glyph_class(&data, 11), Some(GLYPH_CLASS_LIGATURE));
+        assert_eq!(glyph_class(&data, 25), Some(GLYPH_CLASS_MARK));
+        assert_eq!(glyph_class(&data, 15), Some(0));
+        assert_eq!(glyph_class(&gdef(), 50), None);
+    }
+
+    #[test]
+    fn table_without_caret_list_should_have_no_carets() {
+        let data = vec![0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert_eq!(ligature_carets(&data, 50), None);
+    }
+
+    #[test]
+    fn carets_on_outline_points_should_not_be_resolved() {
+        let mut data = gdef();
+        let last = data.len() - 6;
+        data[last + 1] = 2;
+        assert_eq!(ligature_carets(&data, 50), None);
+    }
+}
@@ -0,0 +1,166 @@
+//! Glyph geometry shared by all buffers of a `Fonts`, see `Fonts::geometry_store`.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use metrohash::MetroHashMap;
+use super::{FontRef, GlyphBitmap, SdfBitmap, SizeBucket};
+#[cfg(feature = "tessellation")]
+use super::GlyphMesh;
+
+/// Form a glyph is drawn with.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum GlyphRepresentation {
+    /// Triangle mesh in font units, see `Font::tessellate_glyph`.
+    #[cfg(feature = "tessellation")]
+    Mesh,
+    /// Signed distance field with `padding` pixels and a `spread` in whole pixels, see
+    /// `Font::glyph_sdf`.
+    Sdf { padding: u32, spread: u32 },
+    /// Alpha bitmap without hinting, see `Font::rasterize_glyph`.
+    Bitmap,
+}
+
+/// Stable id of a unique glyph geometry of a store; ids are never reused.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct GeometryId(pub u32);
+
+/// What a geometry is of. Sizes are bucketed like other caches, see `Fonts::set_size_bucketing`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct GeometryKey {
+    pub font: FontRef,
+    pub glyph_id: u32,
+    pub size: SizeBucket,
+    pub representation: GlyphRepresentation,
+}
+
+#[derive(Debug, Clone)]
+pub enum GlyphGeometry {
+    #[cfg(feature = "tessellation")]
+    Mesh(Rc<GlyphMesh>),
+    Sdf(Rc<SdfBitmap>),
+    Bitmap(Rc<GlyphBitmap>),
+}
+
+/// Geometry added to a store since the last `GeometryStore::take_new_entries`.
+#[derive(Debug, Clone)]
+pub struct GeometryEntry {
+    pub id: GeometryId,
+    pub key: GeometryKey,
+    pub geometry: GlyphGeometry,
+}
+
+/// Placement of a stored geometry, in pixels.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct GeometryInstance {
+    pub geometry: GeometryId,
+    /// Glyph origin, Y in the `Fonts::origin` convention.
+    pub offset: [f32; 2],
+    /// Geometry units to pixels: font units for meshes, physical pixels for bitmaps and fields.
+    pub scale: f32,
+}
+
+#[derive(Default)]
+struct StoreData {
+    /// `None` for glyphs without geometry, such as spaces.
+    ids: MetroHashMap<GeometryKey, Option<GeometryId>>,
+    new_entries: Vec<GeometryEntry>,
+    next_id: u32,
+}
+
+/// Maps glyphs to stable ids so that a renderer uploads each unique glyph once and draws
+/// buffers as instances. Clones share the entries.
+#[derive(Clone, Default)]
+pub struct GeometryStore {
+    data: Rc<RefCell<StoreData>>,
+}
+
+impl GeometryStore {
+    pub fn new() -> GeometryStore {
+        GeometryStore::default()
+    }
+
+    /// Id of the geometry of `key`, built with `geometry` the first time. `None` if the glyph
+    /// has no geometry.
+    pub fn get_or_insert<F>(&self, key: GeometryKey, geometry: F) -> Option<GeometryId>
+        where F: FnOnce() -> Option<GlyphGeometry> {
+        if let Some(&id) = self.data.borrow().ids.get(&key) {
+            return id;
+        }
+
+        // built without the store borrowed, it may use fonts that use the store
+        let geometry = geometry();
+        let mut data = self.data.borrow_mut();
+        let id = geometry.map(|geometry| {
+            let id = GeometryId(data.next_id);
+            data.next_id += 1;
+            data.new_entries.push(GeometryEntry { id, key, geometry });
+            id
+        });
+        data.ids.insert(key, id);
+        id
+    }
+
+    pub fn get(&self, key: &GeometryKey) -> Option<GeometryId> {
+        self.data.borrow().ids.get(key).cloned().and_then(|id| id)
+    }
+
+    /// Number of unique geometries.
+    pub fn len(&self) -> usize {
+        self.data.borrow().ids.values().filter(|id| id.is_some()).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Geometry added since the last call, in id order, to upload.
+    pub fn take_new_entries(&self) -> Vec<GeometryEntry> {
+        ::std::mem::replace(&mut self.data.borrow_mut().new_entries, Vec::new())
+    }
+
+    /// Forgets all geometry, such as after the renderer lost its uploads. Later entries get
+    /// new ids.
+    pub fn clear(&self) {
+        let mut data = self.data.borrow_mut();
+        data.ids.clear();
+        data.new_entries.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::ContainerToken;
+
+    fn key(glyph_id: u32) -> GeometryKey {
+        GeometryKey {
+            font: FontRef { _id: 1, _generation: 1, _container: ContainerToken(0) },
+            glyph_id,
+            size: SizeBucket::new(16.0, 0.25),
+            representation: GlyphRepresentation::Bitmap,
+        }
+    }
+
+    fn bitmap() -> Option<GlyphGeometry> {
+        Some(GlyphGeometry::Bitmap(Rc::new(GlyphBitmap { width: 1, height: 1, left: 0, top: 1, advance: 1.0, data: vec![255] })))
+    }
+
+    #[test]
+    fn glyphs_should_be_stored_once() {
+        let store = GeometryStore::new();
+        let a = store.get_or_insert(key(1), bitmap);
+        assert_eq!(store.get_or_insert(key(1), || panic!("stored geometry should not be rebuilt")), a);
+        assert_eq!(store.get_or_insert(key(2), || None), None);
+        let b = store.get_or_insert(key(3), bitmap);
+        assert!(a.is_some() && b.is_some() && a != b);
+
+        let entries = store.take_new_entries();
+        assert_eq!(entries.iter().map(|e| (e.id, e.key.glyph_id)).collect::<Vec<_>>(), vec![(a.unwrap(), 1), (b.unwrap(), 3)]);
+        assert!(store.take_new_entries().is_empty());
+        assert_eq!(store.len(), 2);
+
+        store.clear();
+        let c = store.get_or_insert(key(1), bitmap);
+        assert!(c != a && c != b);
+    }
+}
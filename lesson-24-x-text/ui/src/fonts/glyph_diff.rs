@@ -0,0 +1,199 @@
+use std::ops::Range;
+use super::GlyphPosition;
+
+/// Glyphs that changed with a text change, see `Buffer::set_text_diff`.
+///
+/// Replacing `old_range` of the old glyphs with `glyphs` gives the new glyphs. Glyphs after
+/// the range keep their pen positions; their clusters move by the change of the text length.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GlyphDiff {
+    pub old_range: Range<usize>,
+    pub glyphs: Vec<GlyphPosition>,
+}
+
+impl GlyphDiff {
+    /// Indices of `glyphs` in the new glyphs.
+    pub fn new_range(&self) -> Range<usize> {
+        self.old_range.start..self.old_range.start + self.glyphs.len()
+    }
+
+    pub fn is_unchanged(&self) -> bool {
+        self.old_range.start == self.old_range.end && self.glyphs.is_empty()
+    }
+}
+
+/// Bytes replaced between two texts, found from the common prefix and suffix.
+struct TextChange {
+    start: usize,
+    old_end: usize,
+    new_end: usize,
+}
+
+impl TextChange {
+    fn new(old: &str, new: &str) -> TextChange {
+        let boundary = |old_offset: usize, new_offset: usize| old.is_char_boundary(old_offset) && new.is_char_boundary(new_offset);
+
+        let mut start = old.bytes().zip(new.bytes()).take_while(|(a, b)| a == b).count();
+        while !boundary(start, start) {
+            start -= 1;
+        }
+
+        let max_suffix = old.len().min(new.len()) - start;
+        let mut suffix = old.bytes().rev().zip(new.bytes().rev()).take(max_suffix).take_while(|(a, b)| a == b).count();
+        while !boundary(old.len() - suffix, new.len() - suffix) {
+            suffix -= 1;
+        }
+
+        TextChange { start, old_end: old.len() - suffix, new_end: new.len() - suffix }
+    }
+
+    /// New offset of old bytes `offset..offset + len` that are outside the change; an
+    /// empty range at the change start counts as after an insertion.
+    fn map(&self, offset: u32, len: u32) -> Option<u32> {
+        if (offset + len.max(1)) as usize <= self.start {
+            Some(offset)
+        } else if offset as usize >= self.old_end {
+            Some((offset as usize - self.old_end + self.new_end) as u32)
+        } else {
+            None
+        }
+    }
+}
+
+/// The same glyph for unchanged text, with the cluster moved by the text change.
+fn same_glyph(old: &GlyphPosition, new: &GlyphPosition, clusters: &TextChange, bytes: &TextChange) -> bool {
+    old.id == new.id
+        && old.len == new.len
+        && old.x_advance == new.x_advance
+        && old.y_advance == new.y_advance
+        && old.x_offset == new.x_offset
+        && old.y_offset == new.y_offset
+        && old.span == new.span
+        && old.flags == new.flags
+        && clusters.map(old.cluster, 0) == Some(new.cluster)
+        && bytes.map(old.byte_offset, old.len) == Some(new.byte_offset)
+}
+
+/// Index `i` does not split the glyphs of a cluster.
+fn is_cluster_boundary(glyphs: &[GlyphPosition], i: usize) -> bool {
+    i == 0 || i >= glyphs.len() || glyphs[i].cluster != glyphs[i - 1].cluster
+}
+
+fn advance(glyphs: &[GlyphPosition]) -> (i32, i32) {
+    glyphs.iter().fold((0, 0), |(x, y), g| (x + g.x_advance, y + g.y_advance))
+}
+
+/// Compares glyphs from both ends inward, keeping the prefix and suffix of whole clusters
+/// that are the same glyphs at the same pen positions.
+///
+/// `shaped` texts are the ones glyph clusters refer to and `source` texts the ones byte
+/// offsets refer to, which differ with collapsed whitespace.
+pub fn diff_glyphs(old_shaped: &str, old_source: &str, old: &[GlyphPosition], new_shaped: &str, new_source: &str, new: &[GlyphPosition]) -> GlyphDiff {
+    let clusters = TextChange::new(old_shaped, new_shaped);
+    let bytes = TextChange::new(old_source, new_source);
+    let same = |a: &GlyphPosition, b: &GlyphPosition| same_glyph(a, b, &clusters, &bytes);
+
+    let mut prefix = old.iter().zip(new).take_while(|&(a, b)| same(a, b)).count();
+    while !is_cluster_boundary(old, prefix) || !is_cluster_boundary(new, prefix) {
+        prefix -= 1;
+    }
+
+    let max_suffix = old.len().min(new.len()) - prefix;
+    let mut suffix = old.iter().rev().zip(new.iter().rev()).take(max_suffix).take_while(|&(a, b)| same(a, b)).count();
+    while !is_cluster_boundary(old, old.len() - suffix) || !is_cluster_boundary(new, new.len() - suffix) {
+        suffix -= 1;
+    }
+
+    // the suffix only keeps its positions if the replaced glyphs advance as far
+    if advance(&old[prefix..old.len() - suffix]) != advance(&new[prefix..new.len() - suffix]) {
+        suffix = 0;
+    }
+
+    GlyphDiff {
+        old_range: prefix..old.len() - suffix,
+        glyphs: new[prefix..new.len() - suffix].to_vec(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::GlyphFlags;
+
+    /// One glyph per byte of ASCII `text`, the id being the byte and the advance `advance(byte)`.
+    fn glyphs<F: Fn(u8) -> i32>(text: &str, advance: F) -> Vec<GlyphPosition> {
+        text.bytes().enumerate()
+            .map(|(i, b)| GlyphPosition {
+                id: u32::from(b),
+                cluster: i as u32,
+                byte_offset: i as u32,
+                len: 1,
+                x_advance: advance(b),
+                y_advance: 0,
+                x_offset: 0,
+                y_offset: 0,
+                span: 0,
+                flags: GlyphFlags::default(),
+            })
+            .collect()
+    }
+
+    fn diff(old: &str, new: &str, advance: fn(u8) -> i32) -> GlyphDiff {
+        diff_glyphs(old, old, &glyphs(old, advance), new, new, &glyphs(new, advance))
+    }
+
+    fn tabular(_: u8) -> i32 {
+        10
+    }
+
+    fn proportional(b: u8) -> i32 {
+        i32::from(b)
+    }
+
+    #[test]
+    fn same_width_change_should_only_replace_changed_glyphs() {
+        let diff = diff("Score: 1520", "Score: 1530", tabular);
+        assert_eq!(diff.old_range, 9..10);
+        assert_eq!(diff.glyphs.iter().map(|g| g.id as u8 as char).collect::<String>(), "3");
+    }
+
+    #[test]
+    fn wider_change_should_include_the_moved_suffix() {
+        let diff = diff("Score: 1520", "Score: 1530", proportional);
+        assert_eq!(diff.old_range, 9..11);
+        assert_eq!(diff.new_range(), 9..11);
+    }
+
+    #[test]
+    fn insertion_should_keep_suffix_with_shifted_clusters_when_positions_match() {
+        let zero = |_| 0;
+        let old = glyphs("ab", zero);
+        let new = glyphs("axb", zero);
+        let inserted = diff_glyphs("ab", "ab", &old, "axb", "axb", &new);
+        assert_eq!(inserted.old_range, 1..1);
+        assert_eq!(inserted.glyphs.len(), 1);
+
+        // with an advance the inserted glyph moves the suffix
+        assert_eq!(diff("ab", "axb", tabular).old_range, 1..2);
+    }
+
+    #[test]
+    fn unrelated_texts_should_change_everything() {
+        let changed = diff("abc", "xyz", tabular);
+        assert_eq!(changed.old_range, 0..3);
+        assert_eq!(changed.glyphs.len(), 3);
+        assert!(diff("abc", "abc", tabular).is_unchanged());
+    }
+
+    #[test]
+    fn clusters_should_not_be_split() {
+        let mut old = glyphs("ffi", tabular);
+        let mut new = glyphs("ffl", tabular);
+        // a ligature cluster whose first two glyphs are the same
+        for glyph in old.iter_mut().chain(new.iter_mut()) {
+            glyph.cluster = 0;
+        }
+        let diff = diff_glyphs("ffi", "ffi", &old, "ffl", "ffl", &new);
+        assert_eq!(diff.old_range, 0..3);
+    }
+}
@@ -0,0 +1,218 @@
+//! Pair kerning from `GPOS` pair adjustment lookups and the `kern` table.
+
+use super::sfnt::{read_u16, read_u32, coverage_index, class_of};
+
+const LOOKUP_PAIR_ADJUSTMENT: u16 = 2;
+const LOOKUP_EXTENSION: u16 = 9;
+
+const VALUE_X_ADVANCE: u16 = 0x0004;
+
+fn read_i16(data: &[u8], offset: usize) -> Option<i16> {
+    read_u16(data, offset).map(|v| v as i16)
+}
+
+/// Byte size of a value record of `format`, two bytes per set bit.
+fn value_record_size(format: u16) -> usize {
+    format.count_ones() as usize * 2
+}
+
+/// X advance of the value record at `offset`, zero if the format has none.
+fn value_x_advance(data: &[u8], offset: usize, format: u16) -> Option<i32> {
+    if format & VALUE_X_ADVANCE == 0 {
+        return Some(0);
+    }
+    // placements come first
+    let skip = (format & 0x0003).count_ones() as usize * 2;
+    read_i16(data, offset + skip).map(i32::from)
+}
+
+/// Advance adjustment of the first glyph by a pair adjustment subtable, `None` if the
+/// subtable does not cover the pair.
+fn pair_adjustment(subtable: &[u8], left: u32, right: u32) -> Option<i32> {
+    let coverage = read_u16(subtable, 2)? as usize;
+    let index = coverage_index(subtable.get(coverage..)?, left)?;
+    let format1 = read_u16(subtable, 4)?;
+    let format2 = read_u16(subtable, 6)?;
+    let record_size = value_record_size(format1) + value_record_size(format2);
+
+    match read_u16(subtable, 0)? {
+        1 => {
+            let pair_set = read_u16(subtable, 10 + index * 2)? as usize;
+            let count = read_u16(subtable, pair_set)? as usize;
+            (0..count)
+                .map(|i| pair_set + 2 + i * (2 + record_size))
+                .find(|&record| read_u16(subtable, record).map(u32::from) == Some(right))
+                .and_then(|record| value_x_advance(subtable, record + 2, format1))
+        }
+        2 => {
+            let class1 = class_of(subtable.get(read_u16(subtable, 8)? as usize..)?, left)? as usize;
+            let class2 = class_of(subtable.get(read_u16(subtable, 10)? as usize..)?, right)? as usize;
+            let class1_count = read_u16(subtable, 12)? as usize;
+            let class2_count = read_u16(subtable, 14)? as usize;
+            if class1 >= class1_count || class2 >= class2_count {
+                return None;
+            }
+            value_x_advance(subtable, 16 + (class1 * class2_count + class2) * record_size, format1)
+        }
+        _ => None,
+    }
+}
+
+/// Lookup indices of the `kern` feature for the default language of the `latn` script, or
+/// else of the `DFLT` script, the script harfbuzz picks for Latin text.
+fn kern_lookups(gpos: &[u8]) -> Option<Vec<usize>> {
+    let scripts = read_u16(gpos, 4)? as usize;
+    let features = read_u16(gpos, 6)? as usize;
+
+    let script_count = read_u16(gpos, scripts)? as usize;
+    let script_offset = |tag: &[u8]| (0..script_count)
+        .map(|i| scripts + 2 + i * 6)
+        .find(|&record| gpos.get(record..record + 4) == Some(tag))
+        .and_then(|record| read_u16(gpos, record + 4));
+    let script = scripts + script_offset(b"latn").or_else(|| script_offset(b"DFLT"))? as usize;
+    let lang_sys = read_u16(gpos, script)? as usize;
+    if lang_sys == 0 {
+        return None;
+    }
+    let lang_sys = script + lang_sys;
+
+    let mut lookups = Vec::new();
+    for i in 0..read_u16(gpos, lang_sys + 4)? as usize {
+        let record = features + 2 + read_u16(gpos, lang_sys + 6 + i * 2)? as usize * 6;
+        if gpos.get(record..record + 4)? != b"kern" {
+            continue;
+        }
+        let feature = features + read_u16(gpos, record + 4)? as usize;
+        for k in 0..read_u16(gpos, feature + 2)? as usize {
+            lookups.push(read_u16(gpos, feature + 4 + k * 2)? as usize);
+        }
+    }
+    lookups.sort();
+    lookups.dedup();
+    Some(lookups)
+}
+
+/// Kerning between two glyphs from the `kern` feature of a `GPOS` table in font units,
+/// `None` if no lookup adjusts the pair.
+///
+/// The first subtable covering the pair applies in each lookup, adjustments of several
+/// lookups add up like in shaping.
+pub fn gpos_kerning(gpos: &[u8], left: u32, right: u32) -> Option<i32> {
+    let lookup_list = read_u16(gpos, 8)? as usize;
+    let mut total = None;
+
+    for index in kern_lookups(gpos)? {
+        let lookup = lookup_list + read_u16(gpos, lookup_list + 2 + index * 2)? as usize;
+        let lookup_type = read_u16(gpos, lookup)?;
+
+        for k in 0..read_u16(gpos, lookup + 4)? as usize {
+            let mut subtable = lookup + read_u16(gpos, lookup + 6 + k * 2)? as usize;
+            let subtable_type = if lookup_type == LOOKUP_EXTENSION {
+                let extension_type = read_u16(gpos, subtable + 2)?;
+                subtable += read_u32(gpos, subtable + 4)? as usize;
+                extension_type
+            } else {
+                lookup_type
+            };
+            if subtable_type != LOOKUP_PAIR_ADJUSTMENT {
+                continue;
+            }
+
+            if let Some(adjustment) = pair_adjustment(gpos.get(subtable..)?, left, right) {
+                total = Some(total.unwrap_or(0) + adjustment);
+                break;
+            }
+        }
+    }
+
+    total
+}
+
+/// Kerning between two glyphs from the horizontal format 0 subtables of a version 0 `kern`
+/// table in font units, `None` if there is no pair.
+pub fn kern_table_kerning(kern: &[u8], left: u32, right: u32) -> Option<i32> {
+    if read_u16(kern, 0)? != 0 {
+        return None;
+    }
+
+    let key = left << 16 | right;
+    let mut offset = 4;
+    let mut total = None;
+
+    for _ in 0..read_u16(kern, 2)? {
+        let length = read_u16(kern, offset + 2)? as usize;
+        let coverage = read_u16(kern, offset + 4)?;
+        // horizontal, not minimum values nor cross-stream, format 0
+        if coverage & 0x0007 == 0x0001 && coverage >> 8 == 0 {
+            let pairs = offset + 14;
+            let (mut low, mut high) = (0, read_u16(kern, offset + 6)? as usize);
+            while low < high {
+                let middle = (low + high) / 2;
+                let record = pairs + middle * 6;
+                let pair = read_u32(kern, record)?;
+                if pair < key {
+                    low = middle + 1;
+                } else if pair > key {
+                    high = middle;
+                } else {
+                    let value = i32::from(read_i16(kern, record + 4)?);
+                    total = Some(if coverage & 0x0008 != 0 { value } else { total.unwrap_or(0) + value });
+                    break;
+                }
+            }
+        }
+        offset += length;
+    }
+
+    total
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// `latn` script with the `kern` feature, lookup 0 pairing glyph 10 with 20 by -80 and
+    /// lookup 1 pairing class 1 glyphs 10 and 11 with class 1 glyph 21 by -30.
+    fn gpos() -> Vec<u8> {
+        let mut data = vec![0, 1, 0, 0, 0, 10, 0, 30, 0, 46];
+        // script list, latn script with a default language system of feature 0
+        data.extend_from_slice(&[0, 1, b'l', b'a', b't', b'n', 0, 8]);
+        data.extend_from_slice(&[0, 4, 0, 0, 0, 0, 255, 255, 0, 1, 0, 0]);
+        // feature list, kern with lookups 0 and 1
+        data.extend_from_slice(&[0, 1, b'k', b'e', b'r', b'n', 0, 8, 0, 0, 0, 2, 0, 0, 0, 1]);
+        // lookup list
+        data.extend_from_slice(&[0, 2, 0, 6, 0, 38]);
+        // lookup 0, pair positioning format 1
+        data.extend_from_slice(&[0, 2, 0, 0, 0, 1, 0, 8]);
+        data.extend_from_slice(&[0, 1, 0, 12, 0, 4, 0, 0, 0, 1, 0, 18]);
+        data.extend_from_slice(&[0, 1, 0, 1, 0, 10]);
+        data.extend_from_slice(&[0, 1, 0, 20, 255, 176]);
+        // lookup 1, pair positioning format 2
+        data.extend_from_slice(&[0, 2, 0, 0, 0, 1, 0, 8]);
+        data.extend_from_slice(&[0, 2, 0, 24, 0, 4, 0, 0, 0, 34, 0, 44, 0, 2, 0, 2]);
+        data.extend_from_slice(&[0, 0, 0, 0, 0, 0, 255, 226]);
+        data.extend_from_slice(&[0, 2, 0, 1, 0, 10, 0, 11, 0, 0]);
+        data.extend_from_slice(&[0, 1, 0, 10, 0, 2, 0, 1, 0, 1]);
+        data.extend_from_slice(&[0, 1, 0, 21, 0, 1, 0, 1]);
+        data
+    }
+
+    #[test]
+    fn gpos_pairs_and_classes_should_adjust_the_first_advance() {
+        let gpos = gpos();
+        assert_eq!(gpos_kerning(&gpos, 10, 20), Some(-80));
+        assert_eq!(gpos_kerning(&gpos, 11, 21), Some(-30));
+        assert_eq!(gpos_kerning(&gpos, 10, 21), Some(-30));
+        assert_eq!(gpos_kerning(&gpos, 11, 20), Some(0));
+        assert_eq!(gpos_kerning(&gpos, 12, 20), None);
+    }
+
+    #[test]
+    fn kern_table_pairs_should_be_found() {
+        let mut kern = vec![0, 0, 0, 1, 0, 0, 0, 26, 0, 1, 0, 2, 0, 12, 0, 1, 0, 0];
+        kern.extend_from_slice(&[0, 10, 0, 20, 255, 176, 0, 11, 0, 20, 0, 16]);
+        assert_eq!(kern_table_kerning(&kern, 10, 20), Some(-80));
+        assert_eq!(kern_table_kerning(&kern, 11, 20), Some(16));
+        assert_eq!(kern_table_kerning(&kern, 12, 20), None);
+    }
+}
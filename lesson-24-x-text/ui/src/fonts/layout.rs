@@ -0,0 +1,1439 @@
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::fmt;
+use std::ops::Range;
+use super::{GlyphPosition, Metrics};
+use super::bidi::{self, BaseDirection};
+use super::debug_paths::{self, DebugPaths};
+use unicode_segmentation::UnicodeSegmentation;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Align {
+    /// Left in left-to-right paragraphs and right in right-to-left ones, the default. See
+    /// `Buffer::set_base_direction`.
+    Start,
+    /// Right in left-to-right paragraphs and left in right-to-left ones.
+    End,
+    Left,
+    Center,
+    Right,
+    /// Stretch space clusters so that every line except the last fills `max_width`, or
+    /// elongate Arabic joins, see `LayoutOptions::justify`. The last line is aligned like
+    /// `Align::Start`.
+    Justify,
+}
+
+/// How `Align::Justify` fills lines.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Justify {
+    /// Stretch space clusters.
+    Spaces,
+    /// Insert tatweel (U+0640) glyphs at joins between Arabic letters, spread over the joins
+    /// of the line. Width less than a tatweel and lines without joins stretch spaces.
+    Kashida,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LineHeight {
+    /// Ascent, descent and line gap from the font metrics.
+    FontDefault,
+    /// Multiple of the font size.
+    Multiple(f32),
+    /// Distance between baselines in pixels.
+    Absolute(f32),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TabStops {
+    /// Stops repeated at a fixed interval, in pixels.
+    Every(f32),
+    /// Explicit stop positions in pixels; past the last stop, the interval
+    /// between the last two stops repeats.
+    Positions(Vec<f32>),
+}
+
+impl TabStops {
+    /// First stop past `x`.
+    pub fn next_stop(&self, x: f32) -> f32 {
+        fn every(from: f32, interval: f32, x: f32) -> f32 {
+            if interval <= 0.0 {
+                return x;
+            }
+            from + ((x - from) / interval + 0.001).floor() * interval + interval
+        }
+
+        match *self {
+            TabStops::Every(interval) => every(0.0, interval, x),
+            TabStops::Positions(ref positions) => {
+                if let Some(stop) = positions.iter().cloned().find(|p| *p > x + 0.001) {
+                    return stop;
+                }
+                match positions.as_slice() {
+                    [] => x,
+                    [last] => every(0.0, *last, x),
+                    [.., previous, last] => every(*last, *last - *previous, x),
+                }
+            }
+        }
+    }
+}
+
+/// Line drawn along text, see `TextLayout::decoration_quads`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Decoration {
+    Underline,
+    Strikethrough,
+}
+
+/// Options of `TextLayout::decoration_quads`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct DecorationOptions {
+    /// Breaks underlines where glyph ink crosses them, such as at descenders.
+    pub skip_ink: bool,
+    /// Pixels kept clear on each side of skipped ink.
+    pub skip_ink_gap: f32,
+}
+
+impl Default for DecorationOptions {
+    fn default() -> Self {
+        DecorationOptions {
+            skip_ink: false,
+            skip_ink_gap: 1.0,
+        }
+    }
+}
+
+/// Pixels per em of buffers until `Fonts::set_default_size` is called.
+pub const DEFAULT_SIZE_PX: f32 = 16.0;
+
+pub struct LayoutOptions {
+    /// Pixels per em, the size of the buffer when `0.0`, the default, see `Buffer::set_size_px`.
+    pub px_size: f32,
+    pub max_width: Option<f32>,
+    pub align: Align,
+    /// How `Align::Justify` fills lines, `Justify::Spaces` by default.
+    pub justify: Justify,
+    pub line_height: LineHeight,
+    /// Distance from the layout origin down to the first baseline.
+    ///
+    /// When `None`, the first line box starts at the origin and the baseline
+    /// sits below it by the ascent plus half of the extra leading.
+    pub first_line_offset: Option<f32>,
+    /// Extra vertical space inserted after each paragraph break, in pixels.
+    pub paragraph_spacing: f32,
+    /// Advance tabs to stop positions instead of using the tab glyph advance.
+    pub tab_stops: Option<TabStops>,
+    /// Permissible break offsets within a word, asked for words that overflow `max_width`.
+    ///
+    /// The word is broken at the last offset where the part before it, reshaped with a
+    /// hyphen appended, fits the line. Soft hyphens in the text are break offsets also
+    /// without a hyphenator.
+    pub hyphenator: Option<Box<dyn Fn(&str) -> Vec<usize>>>,
+}
+
+impl LayoutOptions {
+    /// Pixels per em of the layout, `buffer_px` unless `px_size` is set.
+    pub(crate) fn resolve_px_size(&self, buffer_px: f32) -> f32 {
+        if self.px_size > 0.0 { self.px_size } else { buffer_px }
+    }
+}
+
+impl fmt::Debug for LayoutOptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LayoutOptions")
+            .field("px_size", &self.px_size)
+            .field("max_width", &self.max_width)
+            .field("align", &self.align)
+            .field("justify", &self.justify)
+            .field("line_height", &self.line_height)
+            .field("first_line_offset", &self.first_line_offset)
+            .field("paragraph_spacing", &self.paragraph_spacing)
+            .field("tab_stops", &self.tab_stops)
+            .field("hyphenator", &self.hyphenator.is_some())
+            .finish()
+    }
+}
+
+impl Default for LayoutOptions {
+    fn default() -> Self {
+        LayoutOptions {
+            px_size: 0.0,
+            max_width: None,
+            align: Align::Start,
+            justify: Justify::Spaces,
+            line_height: LineHeight::FontDefault,
+            first_line_offset: None,
+            paragraph_spacing: 0.0,
+            tab_stops: None,
+            hyphenator: None,
+        }
+    }
+}
+
+/// Direction of the Y axis of glyph positions, bounds and carets, see `Fonts::set_origin`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Origin {
+    /// Y up from the baseline, as in font units and OpenGL clip space.
+    BottomLeftYUp,
+    /// Y down from the baseline, the values of `BottomLeftYUp` negated.
+    TopLeftYDown,
+}
+
+impl Default for Origin {
+    fn default() -> Self {
+        Origin::BottomLeftYUp
+    }
+}
+
+impl Origin {
+    /// Converts a Y-up coordinate to this convention, or back.
+    pub fn y(self, y: f32) -> f32 {
+        match self {
+            Origin::BottomLeftYUp => y,
+            Origin::TopLeftYDown => -y,
+        }
+    }
+
+    /// Converts Y-up bounds to this convention, `min_y` stays the smaller value.
+    pub fn bounds(self, b: Bounds) -> Bounds {
+        match self {
+            Origin::BottomLeftYUp => b,
+            Origin::TopLeftYDown => Bounds { min_y: -b.max_y, max_y: -b.min_y, ..b },
+        }
+    }
+}
+
+/// How pen positions of scaled glyphs snap to the pixel grid, see `Fonts::set_rounding`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RoundingMode {
+    /// Exact fractional positions.
+    None,
+    /// Every advance is rounded to whole pixels. Spacing is even, but the rounding errors
+    /// add up over a line.
+    RoundEach,
+    /// The pen position is rounded after every advance, so spacing varies by a pixel at most
+    /// and the line ends where the exact one does.
+    RoundAccumulated,
+}
+
+impl Default for RoundingMode {
+    fn default() -> Self {
+        RoundingMode::None
+    }
+}
+
+/// Pen moving along a line in pixels, snapped by a `RoundingMode` to the physical pixel grid.
+#[derive(Debug, Copy, Clone)]
+pub struct RoundedPen {
+    mode: RoundingMode,
+    scale_factor: f32,
+    exact: f32,
+    /// Current position.
+    pub x: f32,
+}
+
+impl RoundedPen {
+    pub fn new(mode: RoundingMode, scale_factor: f32) -> RoundedPen {
+        RoundedPen { mode, scale_factor, exact: 0.0, x: 0.0 }
+    }
+
+    fn round(&self, x: f32) -> f32 {
+        (x * self.scale_factor).round() / self.scale_factor
+    }
+
+    pub fn advance(&mut self, advance: f32) {
+        match self.mode {
+            RoundingMode::None => {
+                self.exact += advance;
+                self.x = self.exact;
+            }
+            RoundingMode::RoundEach => {
+                self.x += self.round(advance);
+                self.exact = self.x;
+            }
+            RoundingMode::RoundAccumulated => {
+                self.exact += advance;
+                self.x = self.round(self.exact);
+            }
+        }
+    }
+}
+
+/// Axis aligned bounds, y pointing up unless `Origin::TopLeftYDown` is selected.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Bounds {
+    pub min_x: f32,
+    pub min_y: f32,
+    pub max_x: f32,
+    pub max_y: f32,
+}
+
+impl Bounds {
+    pub fn width(&self) -> f32 {
+        self.max_x - self.min_x
+    }
+
+    pub fn height(&self) -> f32 {
+        self.max_y - self.min_y
+    }
+
+    pub fn union(&self, other: &Bounds) -> Bounds {
+        Bounds {
+            min_x: self.min_x.min(other.min_x),
+            min_y: self.min_y.min(other.min_y),
+            max_x: self.max_x.max(other.max_x),
+            max_y: self.max_y.max(other.max_y),
+        }
+    }
+}
+
+/// Glyph placed by the layout, in pixels.
+///
+/// `x` and `y` are the pen position of the glyph (line offset included),
+/// `x_advance` is the advance after justification.
+#[derive(Debug, Copy, Clone)]
+pub struct LayoutGlyph {
+    pub id: u32,
+    pub cluster: u32,
+    /// Byte length of the cluster this glyph belongs to.
+    pub len: u32,
+    pub span: u32,
+    pub x: f32,
+    pub y: f32,
+    pub x_advance: f32,
+    pub x_offset: f32,
+    pub y_offset: f32,
+    /// Glyph should not be drawn, its advance is still part of the line (tabs, soft hyphens).
+    pub visible: bool,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum LineBreak {
+    /// Line was wrapped to fit `max_width`.
+    Soft,
+    /// Line ended at U+2028 line separator.
+    Line,
+    /// Line ended at `\n`, `\r\n`, `\r` or U+2029 paragraph separator.
+    Paragraph,
+    /// Last line of the text.
+    End,
+}
+
+/// Line of a `TextLayout`, the record hit testing and carets are computed from.
+#[derive(Debug, Clone)]
+pub struct LineMetrics {
+    pub glyphs: Range<usize>,
+    /// Bytes of the text covered by the line, separators excluded.
+    pub bytes: Range<usize>,
+    /// Glyphs are in right-to-left visual order.
+    pub rtl: bool,
+    /// Pen origin of the line after alignment.
+    pub x_offset: f32,
+    pub baseline_y: f32,
+    /// Top of the line box above the baseline, half the leading included.
+    ///
+    /// `ascent` and `descent` are distances with Y up whatever the `Origin`, `descent` is
+    /// negative like in font metrics.
+    pub ascent: f32,
+    /// Bottom of the line box below the baseline.
+    pub descent: f32,
+    /// Width of the line without trailing whitespace, after justification.
+    pub width: f32,
+    /// Break that ended the line, `LineBreak::Soft` for wrapped lines.
+    pub line_break: LineBreak,
+}
+
+#[derive(Debug, Clone)]
+pub struct TextLayout {
+    pub(crate) glyphs: Vec<LayoutGlyph>,
+    pub(crate) lines: Vec<LineMetrics>,
+    pub(crate) line_height: f32,
+    pub(crate) paragraph_spacing_total: f32,
+    pub(crate) ink_bounds: Option<Bounds>,
+    /// Ink bounds of each glyph with Y up whatever the origin.
+    pub(crate) glyph_ink: Vec<Option<Bounds>>,
+    pub(crate) ligature_carets: Vec<LigatureCarets>,
+    pub(crate) metrics: ScaledMetrics,
+    pub(crate) origin: Origin,
+}
+
+/// Caret positions between the grapheme components of a ligature glyph.
+#[derive(Debug, Clone)]
+pub(crate) struct LigatureCarets {
+    cluster: usize,
+    /// Byte indices of the components after the first.
+    offsets: Vec<usize>,
+    /// Caret x positions from the glyph pen position, left to right.
+    xs: Vec<f32>,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct HitResult {
+    /// Start of the cluster under the point.
+    pub byte_index: usize,
+    /// Byte length of the cluster under the point.
+    pub cluster_len: usize,
+    /// The point is on the logically trailing half of the cluster.
+    pub is_trailing_edge: bool,
+    pub line: usize,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Caret {
+    pub x: f32,
+    pub baseline_y: f32,
+    pub height: f32,
+    /// The caret belongs to a right-to-left cluster.
+    pub rtl: bool,
+    /// Second valid position at a direction boundary, at the trailing edge of the previous cluster.
+    pub secondary_x: Option<f32>,
+}
+
+struct ClusterBox {
+    byte_index: usize,
+    len: usize,
+    start_x: f32,
+    end_x: f32,
+    rtl: bool,
+}
+
+impl ClusterBox {
+    /// X position at a logical fraction of the cluster, 0 being the leading edge.
+    fn edge_x(&self, fraction: f32) -> f32 {
+        let width = self.end_x - self.start_x;
+        if self.rtl {
+            self.end_x - width * fraction
+        } else {
+            self.start_x + width * fraction
+        }
+    }
+}
+
+impl HitResult {
+    /// Byte index where the caret should be placed for this hit.
+    pub fn caret_index(&self) -> usize {
+        if self.is_trailing_edge {
+            self.byte_index + self.cluster_len
+        } else {
+            self.byte_index
+        }
+    }
+}
+
+impl TextLayout {
+    pub fn glyphs(&self) -> &[LayoutGlyph] {
+        &self.glyphs
+    }
+
+    pub fn lines(&self) -> &[LineMetrics] {
+        &self.lines
+    }
+
+    pub fn line_glyphs(&self, line: usize) -> &[LayoutGlyph] {
+        &self.glyphs[self.lines[line].glyphs.clone()]
+    }
+
+    pub fn line_height(&self) -> f32 {
+        self.line_height
+    }
+
+    /// Height of all line boxes, `lines × line_height`, plus paragraph spacing.
+    pub fn height(&self) -> f32 {
+        self.lines.len() as f32 * self.line_height + self.paragraph_spacing_total
+    }
+
+    /// Width of the widest line, trailing whitespace excluded, and `height`.
+    pub fn total_size(&self) -> (f32, f32) {
+        let width = self.lines.iter().map(|l| l.width).fold(0.0, f32::max);
+        (width, self.height())
+    }
+
+    /// Line whose box contains `y`, `None` above the first line or below the last.
+    ///
+    /// Paragraph spacing belongs to the line after it.
+    pub fn line_at_y(&self, y: f32) -> Option<usize> {
+        let y = self.origin.y(y);
+        if self.lines.is_empty() || y > self.line_top(0) {
+            return None;
+        }
+        (0..self.lines.len()).find(|&line| y >= self.line_bottom(line))
+    }
+
+    /// Top of the line box with Y up.
+    fn line_top(&self, line: usize) -> f32 {
+        let l = &self.lines[line];
+        self.origin.y(l.baseline_y) + l.ascent
+    }
+
+    /// Bottom of the line box with Y up.
+    fn line_bottom(&self, line: usize) -> f32 {
+        let l = &self.lines[line];
+        self.origin.y(l.baseline_y) + l.descent
+    }
+
+    /// Y direction of the glyph positions, baselines, carets and bounds.
+    pub fn origin(&self) -> Origin {
+        self.origin
+    }
+
+    /// Appends the layout of the text after this one, laid out with the same options, below
+    /// the last line and with its bytes moved by `byte_offset`.
+    ///
+    /// The empty last line after a paragraph separator at the end of this text is replaced
+    /// by the appended lines, so paragraphs laid out one by one stitch into the layout of
+    /// the whole text.
+    pub(crate) fn append(&mut self, next: TextLayout, byte_offset: usize) {
+        let ends_paragraph = self.lines.len() > 1 && self.lines.last().map_or(false, |line| line.line_break == LineBreak::End && line.glyphs.start == line.glyphs.end);
+        if ends_paragraph {
+            self.lines.pop();
+        }
+
+        let dy_up = -(self.lines.len() as f32 * self.line_height + self.paragraph_spacing_total);
+        let dy = self.origin.y(dy_up);
+        let glyph_offset = self.glyphs.len();
+        let shift = |b: Bounds, dy: f32| Bounds { min_y: b.min_y + dy, max_y: b.max_y + dy, ..b };
+
+        self.glyphs.extend(next.glyphs.into_iter().map(|glyph| LayoutGlyph {
+            cluster: glyph.cluster + byte_offset as u32,
+            y: glyph.y + dy,
+            ..glyph
+        }));
+        self.lines.extend(next.lines.into_iter().map(|line| LineMetrics {
+            glyphs: line.glyphs.start + glyph_offset..line.glyphs.end + glyph_offset,
+            bytes: line.bytes.start + byte_offset..line.bytes.end + byte_offset,
+            baseline_y: line.baseline_y + dy,
+            ..line
+        }));
+        self.glyph_ink.extend(next.glyph_ink.into_iter().map(|ink| ink.map(|b| shift(b, dy_up))));
+        self.ink_bounds = match (self.ink_bounds, next.ink_bounds.map(|b| shift(b, dy))) {
+            (Some(a), Some(b)) => Some(a.union(&b)),
+            (a, b) => a.or(b),
+        };
+        self.ligature_carets.extend(next.ligature_carets.into_iter().map(|carets| LigatureCarets {
+            cluster: carets.cluster + byte_offset,
+            offsets: carets.offsets.into_iter().map(|offset| offset + byte_offset).collect(),
+            ..carets
+        }));
+        self.paragraph_spacing_total += next.paragraph_spacing_total;
+    }
+
+    /// Layout with Y coordinates in `origin`, as laid out they are Y-up.
+    pub(crate) fn with_origin(mut self, origin: Origin) -> TextLayout {
+        if origin == self.origin {
+            return self;
+        }
+        for glyph in &mut self.glyphs {
+            glyph.y = -glyph.y;
+            glyph.y_offset = -glyph.y_offset;
+        }
+        for line in &mut self.lines {
+            line.baseline_y = -line.baseline_y;
+        }
+        self.ink_bounds = self.ink_bounds.map(|b| Origin::TopLeftYDown.bounds(b));
+        self.origin = origin;
+        self
+    }
+
+    /// Finds the line under `y` (clamped to the first and last lines) and hit tests it at `x`.
+    pub fn hit_test(&self, x: f32, y: f32) -> HitResult {
+        let line = match self.line_at_y(y) {
+            Some(line) => line,
+            None if self.origin.y(y) > self.line_top(0) => 0,
+            None => self.lines.len() - 1,
+        };
+
+        self.hit_test_line(line, x)
+    }
+
+    /// Resolves the cluster under `x` on the specified line.
+    ///
+    /// Points before or past the line resolve to the logical start or end of the line.
+    pub fn hit_test_line(&self, line: usize, x: f32) -> HitResult {
+        let l = &self.lines[line];
+        let glyphs = &self.glyphs[l.glyphs.clone()];
+
+        let end_of_line = HitResult {
+            byte_index: l.bytes.end,
+            cluster_len: 0,
+            is_trailing_edge: false,
+            line,
+        };
+
+        let (first, last) = match (glyphs.first(), glyphs.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return HitResult { byte_index: l.bytes.start, ..end_of_line },
+        };
+
+        let line_start = HitResult {
+            byte_index: l.bytes.start,
+            cluster_len: 0,
+            is_trailing_edge: false,
+            line,
+        };
+
+        if x < first.x {
+            return if l.rtl { end_of_line } else { line_start };
+        }
+        if x >= last.x + last.x_advance {
+            return if l.rtl { line_start } else { end_of_line };
+        }
+
+        for cluster in self.cluster_boxes(line) {
+            if x < cluster.end_x {
+                let right_half = x >= (cluster.start_x + cluster.end_x) * 0.5;
+                return HitResult {
+                    byte_index: cluster.byte_index,
+                    cluster_len: cluster.len,
+                    is_trailing_edge: right_half != cluster.rtl,
+                    line,
+                };
+            }
+        }
+
+        end_of_line
+    }
+
+    /// Visual caret for a byte index; indices past the end of text map to the end of the last line.
+    pub fn caret_for_index(&self, byte_index: usize) -> Caret {
+        let line = self.line_for_index(byte_index);
+        let l = &self.lines[line];
+        let byte_index = byte_index.min(l.bytes.end);
+        let boxes = self.cluster_boxes(line);
+
+        let caret = |x: f32, rtl: bool| Caret {
+            x,
+            baseline_y: l.baseline_y,
+            height: l.ascent - l.descent,
+            rtl,
+            secondary_x: None,
+        };
+
+        let leading = boxes.iter()
+            .find(|b| b.byte_index <= byte_index && byte_index < b.byte_index + b.len);
+        let trailing = boxes.iter()
+            .find(|b| b.byte_index + b.len == byte_index);
+
+        match (leading, trailing) {
+            (Some(b), trailing) => {
+                let x = self.ligature_caret_x(b, byte_index).unwrap_or_else(|| {
+                    let fraction = (byte_index - b.byte_index) as f32 / b.len.max(1) as f32;
+                    b.edge_x(fraction)
+                });
+                let mut result = caret(x, b.rtl);
+                if let Some(t) = trailing {
+                    let trailing_x = t.edge_x(1.0);
+                    if byte_index == b.byte_index && (trailing_x - result.x).abs() > 0.001 {
+                        result.secondary_x = Some(trailing_x);
+                    }
+                }
+                result
+            }
+            (None, Some(t)) => caret(t.edge_x(1.0), t.rtl),
+            (None, None) => caret(if l.rtl { l.x_offset + l.width } else { l.x_offset }, l.rtl),
+        }
+    }
+
+    /// Caret at a component boundary inside a ligature.
+    fn ligature_caret_x(&self, b: &ClusterBox, byte_index: usize) -> Option<f32> {
+        let carets = self.ligature_carets.iter().find(|c| c.cluster == b.byte_index)?;
+        let k = carets.offsets.iter().position(|&offset| offset == byte_index)?;
+        // carets are left to right, components of right-to-left ligatures right to left
+        let k = if b.rtl { carets.xs.len() - 1 - k } else { k };
+        Some(b.start_x + carets.xs[k])
+    }
+
+    fn line_for_index(&self, byte_index: usize) -> usize {
+        self.lines.iter()
+            .position(|l| byte_index < l.bytes.end || (byte_index == l.bytes.end && l.line_break != LineBreak::Soft))
+            .unwrap_or(self.lines.len() - 1)
+    }
+
+    /// Groups glyphs of the same cluster on a line into visual boxes, left to right.
+    fn cluster_boxes(&self, line: usize) -> Vec<ClusterBox> {
+        let l = &self.lines[line];
+        let glyphs = &self.glyphs[l.glyphs.clone()];
+        let mut boxes: Vec<ClusterBox> = Vec::new();
+
+        for glyph in glyphs {
+            match boxes.last_mut() {
+                Some(b) if b.byte_index == glyph.cluster as usize => {
+                    b.end_x = glyph.x + glyph.x_advance;
+                    continue;
+                }
+                _ => {}
+            }
+
+            boxes.push(ClusterBox {
+                byte_index: glyph.cluster as usize,
+                len: glyph.len as usize,
+                start_x: glyph.x,
+                end_x: glyph.x + glyph.x_advance,
+                rtl: l.rtl,
+            });
+        }
+
+        // direction of each cluster follows the order of its visual neighbours
+        for i in 0..boxes.len() {
+            let next = boxes.get(i + 1).map(|n| n.byte_index < boxes[i].byte_index);
+            let prev = if i > 0 { Some(boxes[i - 1].byte_index > boxes[i].byte_index) } else { None };
+            if let Some(rtl) = next.or(prev) {
+                boxes[i].rtl = rtl;
+            }
+        }
+
+        boxes
+    }
+
+    /// Extents of the glyph outlines in layout space, `None` if nothing is drawn.
+    pub fn ink_bounds(&self) -> Option<Bounds> {
+        self.ink_bounds
+    }
+
+    /// Baselines, line boxes, glyph advances, cluster boundaries and ink bounds as paths in
+    /// layout space, to draw over the text when diagnosing layouts.
+    pub fn debug_paths(&self) -> DebugPaths {
+        debug_paths::debug_paths(self)
+    }
+
+    /// Rectangles of an underline or strikethrough in layout space, like the glyph positions,
+    /// one per run of the same direction on each line.
+    ///
+    /// Lines are decorated from their start to `LineMetrics::width`, so trailing whitespace
+    /// is not. Positions and thickness come from the metrics of the buffer font; fonts have
+    /// no strikethrough metrics, so it is centered on half the x-height.
+    pub fn decoration_quads(&self, kind: Decoration, options: &DecorationOptions) -> Vec<Bounds> {
+        let m = &self.metrics;
+        let thickness = if m.underline_thickness > 0.0 { m.underline_thickness } else { m.px_size / 14.0 };
+        let top = match kind {
+            Decoration::Underline => m.underline_position,
+            Decoration::Strikethrough => {
+                let x_height = if m.x_height > 0.0 { m.x_height } else { m.ascent * 0.5 };
+                (x_height + thickness) * 0.5
+            }
+        };
+
+        let mut quads = Vec::new();
+        for line in 0..self.lines.len() {
+            let l = &self.lines[line];
+            let (start, end) = (l.x_offset, l.x_offset + l.width);
+            let baseline = self.origin.y(l.baseline_y);
+            let (min_y, max_y) = (baseline + top - thickness, baseline + top);
+
+            let mut segments: Vec<(f32, f32)> = Vec::new();
+            let mut rtl = None;
+            for b in self.cluster_boxes(line) {
+                let (x0, x1) = (b.start_x.max(start), b.end_x.min(end));
+                if x1 <= x0 {
+                    continue;
+                }
+                match segments.last_mut() {
+                    Some(segment) if rtl == Some(b.rtl) => segment.1 = x1,
+                    _ => segments.push((x0, x1)),
+                }
+                rtl = Some(b.rtl);
+            }
+
+            if options.skip_ink && kind == Decoration::Underline {
+                let gap = options.skip_ink_gap;
+                for ink in self.glyph_ink[l.glyphs.clone()].iter().filter_map(|ink| *ink) {
+                    if ink.min_y < max_y + gap && ink.max_y > min_y - gap {
+                        cut_segments(&mut segments, ink.min_x - gap, ink.max_x + gap);
+                    }
+                }
+            }
+
+            quads.extend(segments.into_iter().map(|(min_x, max_x)| self.origin.bounds(Bounds { min_x, min_y, max_x, max_y })));
+        }
+        quads
+    }
+}
+
+/// Removes `start..end` from the x ranges of `segments`, splitting those it falls inside.
+fn cut_segments(segments: &mut Vec<(f32, f32)>, start: f32, end: f32) {
+    let mut kept = Vec::with_capacity(segments.len() + 1);
+    for &(x0, x1) in segments.iter() {
+        if end <= x0 || start >= x1 {
+            kept.push((x0, x1));
+            continue;
+        }
+        if start > x0 {
+            kept.push((x0, start));
+        }
+        if end < x1 {
+            kept.push((end, x1));
+        }
+    }
+    *segments = kept;
+}
+
+struct Word {
+    glyphs: Range<usize>,
+}
+
+impl Word {
+    /// Returns the width of the word and its trailing whitespace when it starts at `pen_x`.
+    fn measure(&self, text: &str, glyphs: &[GlyphPosition], pen_x: f32, scale: f32, tab_stops: Option<&TabStops>) -> (f32, f32) {
+        let mut x = pen_x;
+        let mut trailing_whitespace = 0.0;
+
+        for glyph in &glyphs[self.glyphs.clone()] {
+            let advance = glyph_advance(text, glyph, x, scale, tab_stops);
+            if is_whitespace_cluster(text, glyph.cluster) {
+                trailing_whitespace += advance;
+            }
+            x += advance;
+        }
+
+        (x - pen_x, trailing_whitespace)
+    }
+}
+
+fn cluster_char(text: &str, cluster: u32) -> Option<char> {
+    text.get(cluster as usize..).and_then(|s| s.chars().next())
+}
+
+fn is_whitespace_cluster(text: &str, cluster: u32) -> bool {
+    cluster_char(text, cluster).map_or(false, char::is_whitespace)
+}
+
+const TATWEEL: char = '\u{640}';
+
+/// Arabic combining marks, such as harakat, which do not affect joining.
+fn is_arabic_mark(c: char) -> bool {
+    match c {
+        '\u{64B}'..='\u{65F}' | '\u{670}' | '\u{6D6}'..='\u{6ED}' => true,
+        _ => false,
+    }
+}
+
+/// Arabic letters that connect to a preceding dual-joining letter, all but the hamza.
+fn is_arabic_letter(c: char) -> bool {
+    match c {
+        '\u{620}'..='\u{64A}' | '\u{66E}'..='\u{6D3}' | '\u{6EE}'..='\u{6FF}' => c != '\u{621}' && !is_arabic_mark(c) && c.is_alphabetic(),
+        _ => false,
+    }
+}
+
+/// Arabic letters that also connect to the following letter, the ones a kashida can follow.
+/// Right-joining letters such as alef, dal, reh and waw do not.
+fn joins_following(c: char) -> bool {
+    match c {
+        '\u{622}'..='\u{625}' | '\u{627}' | '\u{629}' | '\u{62F}'..='\u{632}' | '\u{648}'
+        | '\u{671}'..='\u{673}' | '\u{675}'..='\u{677}' | '\u{688}'..='\u{699}'
+        | '\u{6C0}' | '\u{6C3}'..='\u{6CB}' | '\u{6CD}' | '\u{6CF}' | '\u{6D2}' | '\u{6D3}'
+        | '\u{6EE}' | '\u{6EF}' => false,
+        c => is_arabic_letter(c),
+    }
+}
+
+/// Indices of the glyphs in a visual order line that a kashida can be put after, the left
+/// glyph of a pair of right-to-left clusters whose letters join.
+fn kashida_points(text: &str, glyphs: &[GlyphPosition]) -> Vec<usize> {
+    glyphs.windows(2).enumerate()
+        .filter(|&(_, pair)| pair[0].cluster > pair[1].cluster)
+        .filter(|&(_, pair)| {
+            let before = text.get(pair[1].cluster as usize..pair[0].cluster as usize)
+                .and_then(|cluster| cluster.chars().rev().find(|&c| !is_arabic_mark(c)));
+            let after = cluster_char(text, pair[0].cluster);
+            before.map_or(false, joins_following) && after.map_or(false, is_arabic_letter)
+        })
+        .map(|(i, _)| i)
+        .collect()
+}
+
+/// Whitespace that lines can wrap after, not no-break spaces such as in "12\u{A0}kg".
+fn is_breaking_space_cluster(text: &str, cluster: u32) -> bool {
+    match cluster_char(text, cluster) {
+        Some('\u{A0}') | Some('\u{2007}') | Some('\u{202F}') => false,
+        Some(c) => c.is_whitespace(),
+        None => false,
+    }
+}
+
+/// Word joiner or zero width no-break space, which forbid a break before them.
+fn is_word_joiner_cluster(text: &str, cluster: u32) -> bool {
+    match cluster_char(text, cluster) {
+        Some('\u{2060}') | Some('\u{FEFF}') => true,
+        _ => false,
+    }
+}
+
+fn is_tab_cluster(text: &str, cluster: u32) -> bool {
+    text.as_bytes().get(cluster as usize) == Some(&b'\t')
+}
+
+/// Advance of a glyph at pen position `x`, tabs advancing to the next tab stop.
+fn glyph_advance(text: &str, glyph: &GlyphPosition, x: f32, scale: f32, tab_stops: Option<&TabStops>) -> f32 {
+    match tab_stops {
+        Some(stops) if is_tab_cluster(text, glyph.cluster) => stops.next_stop(x) - x,
+        _ => glyph.x_advance as f32 * scale,
+    }
+}
+
+enum Separator {
+    None,
+    /// `\r` of a `\r\n` pair, dropped without breaking.
+    Skip,
+    Break(LineBreak),
+}
+
+fn separator_at(text: &str, cluster: u32) -> Separator {
+    let rest = match text.get(cluster as usize..) {
+        Some(rest) => rest,
+        None => return Separator::None,
+    };
+
+    let mut chars = rest.chars();
+    match chars.next() {
+        Some('\r') if chars.next() == Some('\n') => Separator::Skip,
+        Some('\n') | Some('\r') | Some('\u{2029}') => Separator::Break(LineBreak::Paragraph),
+        Some('\u{2028}') => Separator::Break(LineBreak::Line),
+        _ => Separator::None,
+    }
+}
+
+/// Byte ranges of the paragraphs of `text`, split at the same hard breaks as layout.
+///
+/// Separators are excluded from the ranges; U+2028 does not end a paragraph.
+pub fn paragraph_ranges(text: &str) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        let end = match c {
+            '\r' if chars.peek().map(|&(_, next)| next) == Some('\n') => {
+                chars.next();
+                i + 2
+            },
+            '\n' | '\r' | '\u{2029}' => i + c.len_utf8(),
+            _ => continue,
+        };
+        ranges.push(start..i);
+        start = end;
+    }
+
+    ranges.push(start..text.len());
+    ranges
+}
+
+/// Byte ranges of the paragraphs of `text` with the separators that end them, covering it
+/// without gaps. Text ending with a separator ends with an empty range.
+pub fn paragraph_chunks(text: &str) -> Vec<Range<usize>> {
+    let paragraphs = paragraph_ranges(text);
+    let ends = paragraphs.iter().skip(1).map(|paragraph| paragraph.start).chain(Some(text.len()));
+    paragraphs.iter().zip(ends).map(|(paragraph, end)| paragraph.start..end).collect()
+}
+
+struct Paragraph {
+    glyphs: Range<usize>,
+    line_break: LineBreak,
+    /// Byte offset after the previous separator.
+    byte_start: usize,
+}
+
+/// Splits glyphs at hard breaks, excluding separator glyphs from the returned ranges.
+fn split_paragraphs(text: &str, glyphs: &[GlyphPosition]) -> Vec<Paragraph> {
+    let mut paragraphs = Vec::new();
+    let mut start = 0;
+    let mut byte_start = 0;
+    let mut separator_start = None;
+
+    for (i, glyph) in glyphs.iter().enumerate() {
+        match separator_at(text, glyph.cluster) {
+            Separator::None => separator_start = None,
+            Separator::Skip => if separator_start.is_none() {
+                separator_start = Some(i);
+            },
+            Separator::Break(line_break) => {
+                paragraphs.push(Paragraph { glyphs: start..separator_start.unwrap_or(i), line_break, byte_start });
+                start = i + 1;
+                byte_start = (glyph.cluster + glyph.len) as usize;
+                separator_start = None;
+            }
+        }
+    }
+
+    paragraphs.push(Paragraph { glyphs: start..glyphs.len(), line_break: LineBreak::End, byte_start });
+    paragraphs
+}
+
+fn byte_range(glyphs: &[GlyphPosition], empty_at: usize) -> Range<usize> {
+    let start = glyphs.iter().map(|g| g.cluster as usize).min();
+    let end = glyphs.iter().map(|g| (g.cluster + g.len) as usize).max();
+    match (start, end) {
+        (Some(start), Some(end)) => start..end,
+        _ => empty_at..empty_at,
+    }
+}
+
+fn split_words(text: &str, range: Range<usize>, glyphs: &[GlyphPosition]) -> Vec<Word> {
+    let mut words = Vec::new();
+    let mut start = range.start;
+
+    for i in range.start + 1..range.end {
+        let (prev, glyph) = (glyphs[i - 1].cluster, glyphs[i].cluster);
+        if !is_whitespace_cluster(text, glyph) && !is_word_joiner_cluster(text, glyph) && is_breaking_space_cluster(text, prev) {
+            words.push(Word { glyphs: start..i });
+            start = i;
+        }
+    }
+
+    if start < range.end {
+        words.push(Word { glyphs: start..range.end });
+    }
+
+    words
+}
+
+/// Word split by the hyphenator, see `hyphenate`.
+struct Hyphenation {
+    /// Glyphs replacing the word: the part before the break with the hyphen, then the rest.
+    glyphs: Vec<GlyphPosition>,
+    /// Index of the first glyph after the hyphen.
+    break_index: usize,
+    /// Width of the glyphs before `break_index`.
+    width: f32,
+}
+
+const HYPHENS: [char; 2] = ['\u{2010}', '-'];
+
+/// Invisible break hint, shown as a hyphen only when a line breaks after it.
+pub const SOFT_HYPHEN: char = '\u{AD}';
+
+/// Breaks an overflowing word at the last hyphenation offset or soft hyphen where the part
+/// before it with a hyphen fits `available`, reshaping both parts with `shape_fragment`.
+/// `source_offset` maps clusters to the byte offsets of the glyphs.
+///
+/// Words with several spans, right-to-left glyph order or no fitting offset are not broken.
+fn hyphenate<S, M>(text: &str, glyphs: &[GlyphPosition], word: &Word, available: f32, scale: f32, hyphenator: Option<&dyn Fn(&str) -> Vec<usize>>, shape_fragment: &S, source_offset: &M) -> Option<Hyphenation>
+    where S: Fn(&str, u32) -> Vec<GlyphPosition>,
+          M: Fn(usize) -> usize
+{
+    let word_glyphs = &glyphs[word.glyphs.clone()];
+    let letter_count = word_glyphs.iter().take_while(|g| !is_breaking_space_cluster(text, g.cluster)).count();
+    let (letters, trailing) = word_glyphs.split_at(letter_count);
+
+    let span = letters.first()?.span;
+    if letters.iter().any(|g| g.span != span) || letters.windows(2).any(|w| w[0].cluster > w[1].cluster) {
+        return None;
+    }
+
+    // glyph lengths are in source bytes, so the letters end at the next cluster of the text
+    let start = letters[0].cluster as usize;
+    let last = letters.iter().map(|g| g.cluster).max()?;
+    let end = glyphs.iter().map(|g| g.cluster).filter(|&cluster| cluster > last).min().map_or(text.len(), |cluster| cluster as usize);
+    let word_text = text.get(start..end)?;
+
+    let mut offsets = hyphenator.map_or_else(Vec::new, |hyphenator| hyphenator(word_text));
+    offsets.extend(word_text.match_indices(SOFT_HYPHEN).map(|(i, _)| i + SOFT_HYPHEN.len_utf8()));
+    offsets.retain(|&offset| offset > 0 && offset < word_text.len() && word_text.is_char_boundary(offset));
+    offsets.sort();
+    offsets.dedup();
+
+    for &offset in offsets.iter().rev() {
+        let head_text = &word_text[..offset];
+        // a hyphen takes the place of a soft hyphen, keeping its byte position
+        let soft_hyphen = if head_text.ends_with(SOFT_HYPHEN) { Some(offset - SOFT_HYPHEN.len_utf8()) } else { None };
+        let mut head = if let Some(soft_hyphen) = soft_hyphen {
+            HYPHENS.iter()
+                .map(|&hyphen| shape_fragment(&format!("{}{}", &head_text[..soft_hyphen], hyphen), span))
+                .find(|head| head.iter().filter(|g| g.cluster as usize >= soft_hyphen).all(|g| g.id != 0))?
+        } else if head_text.ends_with(&HYPHENS[..]) {
+            // a break after an explicit hyphen needs no extra one
+            shape_fragment(head_text, span)
+        } else {
+            // fall back to the ASCII hyphen when the font has no glyph for U+2010
+            HYPHENS.iter()
+                .map(|&hyphen| shape_fragment(&format!("{}{}", head_text, hyphen), span))
+                .find(|head| head.iter().filter(|g| g.cluster as usize >= offset).all(|g| g.id != 0))?
+        };
+
+        let width = head.iter().map(|g| g.x_advance as f32 * scale).sum::<f32>();
+        if width > available {
+            continue;
+        }
+
+        // the hyphen joins the cluster before it, so that it is hit and selected with it
+        let last_cluster = head.iter().filter(|g| (g.cluster as usize) < offset).last().map(|g| (g.cluster, g.len));
+        for glyph in &mut head {
+            if soft_hyphen.map_or(false, |soft_hyphen| glyph.cluster as usize >= soft_hyphen) {
+                glyph.len = SOFT_HYPHEN.len_utf8() as u32;
+            } else if glyph.cluster as usize >= offset {
+                if let Some((cluster, len)) = last_cluster {
+                    glyph.cluster = cluster;
+                    glyph.len = len;
+                }
+            }
+            glyph.cluster += start as u32;
+        }
+
+        let tail = shape_fragment(&word_text[offset..], span).into_iter()
+            .map(|mut glyph| {
+                glyph.cluster += (start + offset) as u32;
+                glyph
+            });
+
+        let break_index = head.len();
+        head.extend(tail);
+        // fragment glyphs have lengths in the shaped text, the word glyphs in the source
+        for glyph in &mut head {
+            let byte_offset = source_offset(glyph.cluster as usize);
+            glyph.len = (source_offset((glyph.cluster + glyph.len) as usize) - byte_offset) as u32;
+            glyph.byte_offset = byte_offset as u32;
+        }
+        head.extend_from_slice(trailing);
+        return Some(Hyphenation { glyphs: head, break_index, width });
+    }
+
+    None
+}
+
+fn shifted(range: Range<usize>, delta: isize) -> Range<usize> {
+    (range.start as isize + delta) as usize..(range.end as isize + delta) as usize
+}
+
+/// Carets inside a glyph covering several graphemes, from the font or spread evenly.
+fn ligature_carets<C>(text: &str, glyph: &GlyphPosition, x_advance: f32, scale: f32, font_carets: &C) -> Option<LigatureCarets>
+    where C: Fn(u32, u32) -> Vec<i32>
+{
+    let start = glyph.cluster as usize;
+    let cluster_text = text.get(start..start + glyph.len as usize)?;
+    let offsets = cluster_text.grapheme_indices(true).skip(1).map(|(i, _)| start + i).collect::<Vec<_>>();
+    if offsets.is_empty() {
+        return None;
+    }
+
+    let carets = font_carets(glyph.span, glyph.id);
+    let xs = if carets.len() == offsets.len() {
+        carets.iter().map(|&caret| caret as f32 * scale).collect()
+    } else {
+        let components = offsets.len() + 1;
+        (1..components).map(|k| x_advance * k as f32 / components as f32).collect()
+    };
+    Some(LigatureCarets { cluster: start, offsets, xs })
+}
+
+/// Font metrics in pixels at a size, see `Font::metrics_for_size`.
+///
+/// Values are scaled but not rounded, callers that want whole pixels snap them. Like in the
+/// font, `descent` and `underline_position` are negative below the baseline.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ScaledMetrics {
+    pub px_size: f32,
+    /// Multiplier from font units to pixels.
+    pub scale: f32,
+    pub ascent: f32,
+    pub descent: f32,
+    pub line_gap: f32,
+    /// Ascent minus descent plus line gap, the line height of `LineHeight::FontDefault`.
+    pub line_height: f32,
+    pub underline_position: f32,
+    pub underline_thickness: f32,
+    pub cap_height: f32,
+    pub x_height: f32,
+}
+
+impl ScaledMetrics {
+    pub fn new(metrics: &Metrics, px_size: f32) -> ScaledMetrics {
+        let scale = px_size / metrics.units_per_em as f32;
+        ScaledMetrics {
+            px_size,
+            scale,
+            ascent: metrics.ascent * scale,
+            descent: metrics.descent * scale,
+            line_gap: metrics.line_gap * scale,
+            line_height: (metrics.ascent - metrics.descent + metrics.line_gap) * scale,
+            underline_position: metrics.underline_position * scale,
+            underline_thickness: metrics.underline_thickness * scale,
+            cap_height: metrics.cap_height * scale,
+            x_height: metrics.x_height * scale,
+        }
+    }
+
+    /// Metrics with the ascent, descent and line gap at least as large as the specified
+    /// ones, for lines mixing runs of several sizes.
+    pub fn max_vertical(self, ascent: f32, descent: f32, line_gap: f32) -> ScaledMetrics {
+        let (ascent, descent, line_gap) = (self.ascent.max(ascent), self.descent.min(descent), self.line_gap.max(line_gap));
+        ScaledMetrics {
+            ascent,
+            descent,
+            line_gap,
+            line_height: ascent - descent + line_gap,
+            ..self
+        }
+    }
+}
+
+/// Lays out shaped glyphs at the size of `metrics`; `shape_fragment` shapes parts of
+/// hyphenated words in a span's font, with clusters relative to the fragment.
+pub(crate) fn layout_glyphs<F, S, C, M>(text: &str, glyphs: &[GlyphPosition], metrics: &ScaledMetrics, options: &LayoutOptions, base: BaseDirection, glyph_bounds: F, shape_fragment: S, font_carets: C, source_offset: M) -> TextLayout
+    where F: Fn(u32, u32) -> Option<Bounds>,
+          S: Fn(&str, u32) -> Vec<GlyphPosition>,
+          C: Fn(u32, u32) -> Vec<i32>,
+          M: Fn(usize) -> usize
+{
+    let scale = metrics.scale;
+    let ascent = metrics.ascent;
+    let descent = metrics.descent;
+    let line_height = match options.line_height {
+        LineHeight::FontDefault => metrics.line_height,
+        LineHeight::Multiple(m) => metrics.px_size * m,
+        LineHeight::Absolute(h) => h,
+    };
+    let first_baseline = match options.first_line_offset {
+        Some(offset) => -offset,
+        None => -(ascent + (line_height - (ascent - descent)) * 0.5),
+    };
+
+    let tab_stops = options.tab_stops.as_ref();
+    let mut line_ranges: Vec<(Range<usize>, f32, LineBreak, usize)> = Vec::new();
+    // hyphenation replaces word glyphs, shifting the glyphs after them by `inserted`
+    let mut glyphs = Cow::Borrowed(glyphs);
+    let mut inserted = 0isize;
+
+    for Paragraph { glyphs: paragraph, line_break: paragraph_break, byte_start } in split_paragraphs(text, &glyphs) {
+        let mut paragraph = shifted(paragraph, inserted);
+        let mut line_start = paragraph.start;
+        let mut line_width = 0.0;
+        let mut line_trailing = 0.0;
+        let mut words = split_words(text, paragraph.clone(), &glyphs).into_iter().collect::<VecDeque<_>>();
+
+        while let Some(word) = words.pop_front() {
+            let (width, trailing) = word.measure(text, &glyphs, line_width, scale, tab_stops);
+
+            if let Some(max_width) = options.max_width {
+                if line_width + width - trailing > max_width {
+                    let hyphenator = options.hyphenator.as_ref().map(|hyphenator| &**hyphenator);
+                    let hyphenation = hyphenate(text, &glyphs, &word, max_width - line_width, scale, hyphenator, &shape_fragment, &source_offset);
+
+                    if let Some(Hyphenation { glyphs: replacement, break_index, width: head_width }) = hyphenation {
+                        let delta = replacement.len() as isize - word.glyphs.len() as isize;
+                        let break_index = word.glyphs.start + break_index;
+                        let word_end = (word.glyphs.end as isize + delta) as usize;
+                        glyphs.to_mut().splice(word.glyphs.clone(), replacement);
+
+                        for next in &mut words {
+                            next.glyphs = shifted(next.glyphs.clone(), delta);
+                        }
+                        paragraph.end = (paragraph.end as isize + delta) as usize;
+                        inserted += delta;
+
+                        line_ranges.push((line_start..break_index, line_width + head_width, LineBreak::Soft, byte_start));
+                        line_start = break_index;
+                        line_width = 0.0;
+                        line_trailing = 0.0;
+                        words.push_front(Word { glyphs: break_index..word_end });
+                        continue;
+                    }
+
+                    if word.glyphs.start > line_start {
+                        line_ranges.push((line_start..word.glyphs.start, line_width - line_trailing, LineBreak::Soft, byte_start));
+                        line_start = word.glyphs.start;
+                        line_width = 0.0;
+                        line_trailing = 0.0;
+                        // measured again at the line start, and hyphenated if it still overflows
+                        words.push_front(word);
+                        continue;
+                    }
+                }
+            }
+
+            line_width += width;
+            line_trailing = trailing;
+        }
+
+        line_ranges.push((line_start..paragraph.end, line_width - line_trailing, paragraph_break, byte_start));
+    }
+
+    let mut output = Vec::with_capacity(glyphs.len());
+    let mut lines = Vec::with_capacity(line_ranges.len());
+    let mut ligatures = Vec::new();
+    let mut paragraph_spacing_total = 0.0;
+    // lines of a paragraph share its direction
+    let mut last_paragraph: Option<(usize, bool)> = None;
+
+    for (line_index, (range, content_width, line_break, byte_start)) in line_ranges.into_iter().enumerate() {
+        let available = options.max_width.unwrap_or(content_width);
+        let baseline_y = first_baseline - line_index as f32 * line_height - paragraph_spacing_total;
+
+        // trailing whitespace does not take part in justification
+        let content_end = glyphs[range.clone()].iter()
+            .rposition(|g| !is_whitespace_cluster(text, g.cluster))
+            .map(|p| range.start + p + 1)
+            .unwrap_or(range.start);
+
+        let mut justify_extra = if options.align == Align::Justify && line_break == LineBreak::Soft && available > content_width {
+            available - content_width
+        } else {
+            0.0
+        };
+
+        // whole tatweels at joins, round robin, the rest of the width goes to spaces
+        let mut kashidas = vec![0usize; range.len()];
+        let mut tatweels: Vec<(u32, Option<GlyphPosition>)> = Vec::new();
+        let mut tatweel_count = 0;
+        if options.justify == Justify::Kashida && justify_extra > 0.0 {
+            let mut points = Vec::new();
+            for i in kashida_points(text, &glyphs[range.start..content_end]) {
+                let span = glyphs[range.start + i + 1].span;
+                if tatweels.iter().all(|&(s, _)| s != span) {
+                    let tatweel = shape_fragment(&TATWEEL.to_string(), span).into_iter().next()
+                        .filter(|tatweel| tatweel.id != 0 && tatweel.x_advance > 0);
+                    tatweels.push((span, tatweel));
+                }
+                if let Some(tatweel) = tatweels.iter().find(|&&(s, _)| s == span).and_then(|&(_, tatweel)| tatweel) {
+                    points.push((i, tatweel.x_advance as f32 * scale));
+                }
+            }
+
+            while !points.is_empty() {
+                let (i, advance) = points[tatweel_count % points.len()];
+                if advance > justify_extra {
+                    break;
+                }
+                kashidas[i] += 1;
+                justify_extra -= advance;
+                tatweel_count += 1;
+            }
+        }
+
+        let spaces = glyphs[range.start..content_end].iter()
+            .filter(|g| is_whitespace_cluster(text, g.cluster) && !is_tab_cluster(text, g.cluster))
+            .count();
+        let (space_extra, tatweel_extra) = match (spaces, tatweel_count) {
+            (0, 0) => (0.0, 0.0),
+            (0, count) => (0.0, justify_extra / count as f32),
+            (spaces, _) => (justify_extra / spaces as f32, 0.0),
+        };
+
+        let mut x = 0.0;
+        let first_glyph = output.len();
+        let mut content_output_end = first_glyph;
+
+        for (i, glyph) in glyphs[range.clone()].iter().enumerate() {
+            let is_tab = tab_stops.is_some() && is_tab_cluster(text, glyph.cluster);
+            let mut x_advance = glyph_advance(text, glyph, x, scale, tab_stops);
+            if !is_tab && range.start + i < content_end && is_whitespace_cluster(text, glyph.cluster) {
+                x_advance += space_extra;
+            }
+
+            output.push(LayoutGlyph {
+                id: glyph.id,
+                cluster: glyph.cluster,
+                len: glyph.len,
+                span: glyph.span,
+                x,
+                y: baseline_y,
+                x_advance,
+                x_offset: glyph.x_offset as f32 * scale,
+                y_offset: glyph.y_offset as f32 * scale,
+                visible: !is_tab && !glyph.flags.is_soft_hyphen(),
+            });
+
+            let line_glyphs = &glyphs[range.clone()];
+            let single = (i == 0 || line_glyphs[i - 1].cluster != glyph.cluster)
+                && line_glyphs.get(i + 1).map_or(true, |next| next.cluster != glyph.cluster);
+            if single && glyph.len > 1 {
+                ligatures.extend(ligature_carets(text, glyph, x_advance, scale, &font_carets));
+            }
+
+            x += x_advance;
+
+            if kashidas[i] > 0 {
+                // the elongation belongs to the letter on its right, the earlier one
+                let joined = &line_glyphs[i + 1];
+                let tatweel = tatweels.iter()
+                    .find(|&&(span, _)| span == joined.span)
+                    .and_then(|&(_, tatweel)| tatweel)
+                    .expect("kashidas are only put where the span has a tatweel");
+                let x_advance = tatweel.x_advance as f32 * scale + tatweel_extra;
+
+                for _ in 0..kashidas[i] {
+                    output.push(LayoutGlyph {
+                        id: tatweel.id,
+                        cluster: joined.cluster,
+                        len: joined.len,
+                        span: joined.span,
+                        x,
+                        y: baseline_y,
+                        x_advance,
+                        x_offset: 0.0,
+                        y_offset: 0.0,
+                        visible: true,
+                    });
+                    x += x_advance;
+                }
+            }
+
+            if range.start + i < content_end {
+                content_output_end = output.len();
+            }
+        }
+
+        let width = output[first_glyph..content_output_end].iter()
+            .map(|g| g.x_advance)
+            .sum::<f32>();
+
+        let paragraph_rtl = match last_paragraph {
+            Some((start, rtl)) if start == byte_start => rtl,
+            _ => bidi::paragraph_rtl(text, byte_start, base),
+        };
+        last_paragraph = Some((byte_start, paragraph_rtl));
+        let x_offset = match (options.align, paragraph_rtl) {
+            (Align::Left, _) | (Align::Start, false) | (Align::End, true) | (Align::Justify, false) => 0.0,
+            (Align::Center, _) => (available - width) * 0.5,
+            (Align::Right, _) | (Align::Start, true) | (Align::End, false) | (Align::Justify, true) => available - width,
+        };
+
+        for glyph in &mut output[first_glyph..] {
+            glyph.x += x_offset;
+        }
+
+        let line_glyphs = &glyphs[range.clone()];
+        let rtl = match (line_glyphs.first(), line_glyphs.last()) {
+            (Some(first), Some(last)) => first.cluster > last.cluster,
+            _ => false,
+        };
+
+        lines.push(LineMetrics {
+            glyphs: first_glyph..output.len(),
+            bytes: byte_range(line_glyphs, byte_start),
+            rtl,
+            x_offset,
+            baseline_y,
+            ascent: -first_baseline,
+            descent: -first_baseline - line_height,
+            width,
+            line_break,
+        });
+
+        if line_break == LineBreak::Paragraph {
+            paragraph_spacing_total += options.paragraph_spacing;
+        }
+    }
+
+    let glyph_ink = output.iter()
+        .map(|g| glyph_bounds(g.span, g.id).map(|b| Bounds {
+            min_x: g.x + g.x_offset + b.min_x * scale,
+            min_y: g.y + g.y_offset + b.min_y * scale,
+            max_x: g.x + g.x_offset + b.max_x * scale,
+            max_y: g.y + g.y_offset + b.max_y * scale,
+        }))
+        .collect::<Vec<_>>();
+    let ink_bounds = glyph_ink.iter()
+        .filter_map(|b| *b)
+        .fold(None, |acc: Option<Bounds>, b| Some(acc.map_or(b, |acc| acc.union(&b))));
+
+    TextLayout {
+        glyphs: output,
+        lines,
+        line_height,
+        paragraph_spacing_total,
+        ink_bounds,
+        glyph_ink,
+        ligature_carets: ligatures,
+        metrics: *metrics,
+        origin: Origin::BottomLeftYUp,
+    }
+}
@@ -0,0 +1,71 @@
+//! Borrows of the container shared by `Fonts`, `Font` and `Buffer` handles.
+//!
+//! Diagnostics and profiler callbacks reported while the container is borrowed are queued and
+//! run once the last borrow is released, so they can use the fonts API themselves.
+
+use std::cell::{Ref, RefCell, RefMut};
+use std::ops::{Deref, DerefMut};
+use super::shared::FontsContainer;
+
+/// Borrow of the container that runs queued callbacks when it is released.
+pub struct Locked<'a, B> {
+    container: &'a RefCell<FontsContainer>,
+    borrow: Option<B>,
+}
+
+pub type Shared<'a> = Locked<'a, Ref<'a, FontsContainer>>;
+pub type SharedMut<'a> = Locked<'a, RefMut<'a, FontsContainer>>;
+
+/// Borrows the container for `api`, the public function shown if the borrow is re-entrant.
+pub fn lock<'a>(container: &'a RefCell<FontsContainer>, api: &str) -> Shared<'a> {
+    match container.try_borrow() {
+        Ok(borrow) => Locked { container, borrow: Some(borrow) },
+        Err(_) => reentrant(api),
+    }
+}
+
+/// Mutably borrows the container for `api`, the public function shown if the borrow is re-entrant.
+pub fn lock_mut<'a>(container: &'a RefCell<FontsContainer>, api: &str) -> SharedMut<'a> {
+    match container.try_borrow_mut() {
+        Ok(borrow) => Locked { container, borrow: Some(borrow) },
+        Err(_) => reentrant(api),
+    }
+}
+
+fn reentrant(api: &str) -> ! {
+    panic!("{} was called while the fonts were in use, from a font source, resource loader or other \
+        callback run during another fonts call; check `Fonts::is_locked` before using the fonts from callbacks", api)
+}
+
+impl<'a, B: Deref<Target=FontsContainer>> Deref for Locked<'a, B> {
+    type Target = FontsContainer;
+
+    fn deref(&self) -> &FontsContainer {
+        self.borrow.as_ref().expect("Locked::deref: the borrow is only taken when dropped")
+    }
+}
+
+impl<'a, B: DerefMut<Target=FontsContainer>> DerefMut for Locked<'a, B> {
+    fn deref_mut(&mut self) -> &mut FontsContainer {
+        self.borrow.as_mut().expect("Locked::deref_mut: the borrow is only taken when dropped")
+    }
+}
+
+impl<'a, B> Drop for Locked<'a, B> {
+    fn drop(&mut self) {
+        self.borrow = None;
+        run_queued(self.container);
+    }
+}
+
+/// Runs the queued callbacks if the container is no longer borrowed.
+fn run_queued(container: &RefCell<FontsContainer>) {
+    let queued = match container.try_borrow_mut() {
+        Ok(shared) => shared.queued_callbacks(),
+        Err(_) => return,
+    };
+    if let Some((diagnostics, profiler)) = queued {
+        diagnostics.flush();
+        profiler.flush();
+    }
+}
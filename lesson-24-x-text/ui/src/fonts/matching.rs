@@ -0,0 +1,239 @@
+use font_kit::family_name::FamilyName;
+use font_kit::properties::{Properties, Style};
+use std::cmp::Ordering;
+
+/// Names and properties of a face a `FontSource` offers, read without loading it into `Fonts`.
+#[derive(Debug, Clone)]
+pub struct FaceInfo {
+    pub family_name: String,
+    pub full_name: String,
+    pub postscript_name: Option<String>,
+    pub properties: Properties,
+}
+
+/// Face ranked by `Fonts::find_matches`, load it with `Fonts::load_candidate`.
+#[derive(Debug, Clone)]
+pub struct MatchCandidate {
+    pub family_name: String,
+    pub full_name: String,
+    pub postscript_name: Option<String>,
+    pub properties: Properties,
+    /// Similarity to the query from 0 to 1, higher is closer.
+    pub score: f32,
+    /// Weight difference to the requested weight, 0 to 900.
+    pub weight_distance: f32,
+    pub style_match: bool,
+    /// Stretch difference to the requested stretch, in `Stretch` units.
+    pub stretch_distance: f32,
+    /// A face with the same PostScript name is already loaded.
+    pub loaded: bool,
+}
+
+/// Which requested properties the matched face has exactly, see `MatchDetails`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MatchQuality {
+    pub exact_weight: bool,
+    pub exact_style: bool,
+    pub exact_stretch: bool,
+}
+
+impl MatchQuality {
+    pub fn is_exact(&self) -> bool {
+        self.exact_weight && self.exact_style && self.exact_stretch
+    }
+}
+
+/// Properties a query asked for and those of the face it found, see `Fonts::match_details`.
+#[derive(Debug, Clone)]
+pub struct MatchDetails {
+    pub requested: Properties,
+    /// Properties of the face, without synthesized styles.
+    pub matched: Properties,
+    pub quality: MatchQuality,
+}
+
+impl MatchDetails {
+    pub fn new(requested: &Properties, matched: &Properties) -> MatchDetails {
+        MatchDetails {
+            requested: requested.clone(),
+            matched: matched.clone(),
+            quality: MatchQuality {
+                exact_weight: requested.weight.0 == matched.weight.0,
+                exact_style: requested.style == matched.style,
+                exact_stretch: requested.stretch.0 == matched.stretch.0,
+            },
+        }
+    }
+}
+
+/// How well a family name matches the list, requested families first, 0 if none does.
+fn family_score(family_names: &[FamilyName], family: &str) -> f32 {
+    let family = family.to_lowercase();
+    family_names.iter().enumerate()
+        .filter_map(|(i, name)| match *name {
+            FamilyName::Title(ref name) => {
+                let name = name.to_lowercase();
+                let position = 1.0 - 0.5 * i as f32 / family_names.len() as f32;
+                if name == family {
+                    Some(position)
+                } else if family.contains(&name) || name.contains(&family) {
+                    Some(position * 0.5)
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        })
+        .fold(0.0, f32::max)
+}
+
+fn style_score(requested: Style, style: Style) -> f32 {
+    match (requested, style) {
+        (a, b) if a == b => 1.0,
+        (Style::Italic, Style::Oblique) | (Style::Oblique, Style::Italic) => 0.5,
+        _ => 0.0,
+    }
+}
+
+/// Scores `faces` against the query and returns the best `limit`, ordered by descending
+/// score and then by name, so equal inputs always give the same order.
+pub fn rank<L: Fn(&FaceInfo) -> bool>(faces: &[FaceInfo], family_names: &[FamilyName], properties: &Properties, limit: usize, is_loaded: L) -> Vec<MatchCandidate> {
+    let mut candidates = faces.iter()
+        .map(|face| {
+            let weight_distance = (face.properties.weight.0 - properties.weight.0).abs();
+            let stretch_distance = (face.properties.stretch.0 - properties.stretch.0).abs();
+            let style = style_score(properties.style, face.properties.style);
+
+            // the family dominates, then weight, style and stretch
+            let score = 0.5 * family_score(family_names, &face.family_name)
+                + 0.25 * (1.0 - weight_distance / 900.0).max(0.0)
+                + 0.15 * style
+                + 0.1 * (1.0 - stretch_distance / 1.5).max(0.0);
+
+            MatchCandidate {
+                family_name: face.family_name.clone(),
+                full_name: face.full_name.clone(),
+                postscript_name: face.postscript_name.clone(),
+                properties: face.properties.clone(),
+                score,
+                weight_distance,
+                style_match: style == 1.0,
+                stretch_distance,
+                loaded: is_loaded(face),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    candidates.sort_by(|a, b| {
+        b.score.partial_cmp(&a.score).unwrap_or(Ordering::Equal)
+            .then_with(|| a.full_name.cmp(&b.full_name))
+            .then_with(|| a.postscript_name.cmp(&b.postscript_name))
+    });
+    candidates.truncate(limit);
+    candidates
+}
+
+/// Index of the face a query selects among `faces` alone: the first family with a face of
+/// that name decides, generic families accept any face, then the closest properties win.
+pub fn best_face(faces: &[FaceInfo], family_names: &[FamilyName], properties: &Properties) -> Option<usize> {
+    let best = family_names.iter()
+        .filter_map(|name| {
+            let family = faces.iter()
+                .filter(|face| match *name {
+                    FamilyName::Title(ref title) => title.to_lowercase() == face.family_name.to_lowercase(),
+                    _ => true,
+                })
+                .cloned()
+                .collect::<Vec<_>>();
+            rank(&family, &[], properties, 1, |_| false).pop()
+        })
+        .next()?;
+
+    faces.iter().position(|face| face.full_name == best.full_name && face.postscript_name == best.postscript_name)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use font_kit::properties::{Weight, Stretch};
+
+    fn face(family: &str, style_name: &str, weight: f32, style: Style) -> FaceInfo {
+        FaceInfo {
+            family_name: family.to_string(),
+            full_name: format!("{} {}", family, style_name),
+            postscript_name: Some(format!("{}-{}", family.replace(' ', ""), style_name)),
+            properties: Properties { weight: Weight(weight), style, stretch: Stretch::NORMAL },
+        }
+    }
+
+    fn faces() -> Vec<FaceInfo> {
+        vec![
+            face("Fira Sans", "Bold", 700.0, Style::Normal),
+            face("Fira Sans", "Regular", 400.0, Style::Normal),
+            face("Fira Sans", "Italic", 400.0, Style::Italic),
+            face("Fira Mono", "Regular", 400.0, Style::Normal),
+            face("DejaVu Serif", "Book", 400.0, Style::Normal),
+        ]
+    }
+
+    fn names(candidates: &[MatchCandidate]) -> Vec<&str> {
+        candidates.iter().map(|c| c.full_name.as_str()).collect()
+    }
+
+    #[test]
+    fn match_details_should_tell_which_properties_differ() {
+        let mut semibold = Properties::new();
+        semibold.weight(Weight::SEMIBOLD);
+        let details = MatchDetails::new(&semibold, &Properties::new());
+
+        assert_eq!(details.quality, MatchQuality { exact_weight: false, exact_style: true, exact_stretch: true });
+        assert!(!details.quality.is_exact());
+        assert_eq!(details.requested.weight, Weight::SEMIBOLD);
+        assert!(MatchDetails::new(&semibold, &semibold).quality.is_exact());
+    }
+
+    #[test]
+    fn closest_faces_should_rank_first() {
+        let query = [FamilyName::Title("fira sans".to_string())];
+        let ranked = rank(&faces(), &query, &Properties::new(), 10, |_| false);
+
+        // weight before style, other families tie and go by name
+        assert_eq!(names(&ranked), vec!["Fira Sans Regular", "Fira Sans Bold", "Fira Sans Italic", "DejaVu Serif Book", "Fira Mono Regular"]);
+        assert!(ranked[0].style_match && ranked[0].weight_distance == 0.0);
+        assert_eq!(ranked[1].weight_distance, 300.0);
+        assert!(!ranked[2].style_match);
+    }
+
+    #[test]
+    fn missing_family_should_rank_partial_names_and_properties() {
+        let query = [FamilyName::Title("Fira".to_string())];
+        let mut properties = Properties::new();
+        properties.weight = Weight::BOLD;
+        let ranked = rank(&faces(), &query, &properties, 2, |face| face.full_name.ends_with("Bold"));
+
+        assert_eq!(names(&ranked), vec!["Fira Sans Bold", "Fira Mono Regular"]);
+        assert!(ranked[0].loaded && !ranked[1].loaded);
+    }
+
+    #[test]
+    fn equal_scores_should_be_ordered_by_name() {
+        let mut reversed = faces();
+        reversed.reverse();
+        let query = [FamilyName::SansSerif];
+        let ranked = rank(&reversed, &query, &Properties::new(), 10, |_| false);
+        assert_eq!(names(&ranked), names(&rank(&faces(), &query, &Properties::new(), 10, |_| false)));
+        assert_eq!(&names(&ranked)[..3], &["DejaVu Serif Book", "Fira Mono Regular", "Fira Sans Regular"]);
+    }
+
+    #[test]
+    fn best_face_should_take_the_first_family_with_faces() {
+        let mut properties = Properties::new();
+        properties.style = Style::Italic;
+        let query = [FamilyName::Title("Noto Sans".to_string()), FamilyName::Title("fira sans".to_string()), FamilyName::Serif];
+        assert_eq!(best_face(&faces(), &query, &properties), Some(2));
+
+        // a generic family accepts any face, partial names do not match
+        assert_eq!(best_face(&faces(), &[FamilyName::Title("Fira".to_string()), FamilyName::Monospace], &Properties::new()), Some(4));
+        assert_eq!(best_face(&faces(), &[FamilyName::Title("Fira".to_string())], &Properties::new()), None);
+    }
+}
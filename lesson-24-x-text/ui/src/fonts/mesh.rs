@@ -0,0 +1,108 @@
+use std::rc::Rc;
+use lyon_path::default::Path;
+use lyon_tessellation::{BuffersBuilder, FillOptions, FillTessellator, FillVertex, StrokeOptions, StrokeTessellator, StrokeVertex, VertexBuffers};
+use super::GlyphLoadingError;
+use super::raster::Outline;
+
+/// Triangle mesh of a glyph outline in font units.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GlyphMesh {
+    pub vertices: Vec<[f32; 2]>,
+    pub indices: Vec<u32>,
+}
+
+impl GlyphMesh {
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+}
+
+#[derive(Debug)]
+pub enum GlyphMeshError {
+    Outline(GlyphLoadingError),
+    Tessellation,
+}
+
+impl From<GlyphLoadingError> for GlyphMeshError {
+    fn from(e: GlyphLoadingError) -> Self {
+        GlyphMeshError::Outline(e)
+    }
+}
+
+/// Placement of a shared glyph mesh, in pixels.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct GlyphInstance {
+    /// Index into `BufferMesh::meshes`.
+    pub mesh: usize,
+    pub offset: [f32; 2],
+    /// Font units to pixels.
+    pub scale: f32,
+}
+
+/// Meshes of the distinct glyphs of a buffer plus one instance per visible glyph.
+#[derive(Debug, Clone, Default)]
+pub struct BufferMesh {
+    pub meshes: Vec<Rc<GlyphMesh>>,
+    pub instances: Vec<GlyphInstance>,
+}
+
+/// Rounds tolerance to a bucket so that similar tolerances share cached meshes,
+/// four buckets per doubling.
+pub fn tolerance_bucket(tolerance: f32) -> (u64, f32) {
+    let bucket = (tolerance.max(0.001).log2() * 4.0).floor() as i32;
+    ((bucket + 1024) as u64, 2f32.powf(bucket as f32 / 4.0))
+}
+
+/// Cache variant of a stroke, the width is kept exact.
+pub fn stroke_variant(width: f32, tolerance: f32) -> (u64, f32) {
+    let (bucket, tolerance) = tolerance_bucket(tolerance);
+    (u64::from(width.to_bits()) << 32 | bucket, tolerance)
+}
+
+pub fn tessellate_path(path: &Path, tolerance: f32) -> Result<GlyphMesh, GlyphMeshError> {
+    let mut geometry: VertexBuffers<[f32; 2], u32> = VertexBuffers::new();
+    let mut tessellator = FillTessellator::new();
+
+    tessellator.tessellate_path(
+        path.path_iter(),
+        &FillOptions::default().with_tolerance(tolerance),
+        &mut BuffersBuilder::new(&mut geometry, |vertex: FillVertex| {
+            [vertex.position.x, vertex.position.y]
+        }),
+    ).map_err(|_| GlyphMeshError::Tessellation)?;
+
+    Ok(GlyphMesh {
+        vertices: geometry.vertices,
+        indices: geometry.indices,
+    })
+}
+
+/// Strokes the outline centered on its contours.
+///
+/// Paths without area, such as a single point, produce an empty mesh.
+pub fn stroke_path(path: &Path, width: f32, tolerance: f32) -> Result<GlyphMesh, GlyphMeshError> {
+    if width <= 0.0 || Outline::from_path(path, tolerance).is_none() {
+        return Ok(GlyphMesh { vertices: Vec::new(), indices: Vec::new() });
+    }
+
+    let mut geometry: VertexBuffers<[f32; 2], u32> = VertexBuffers::new();
+    let mut tessellator = StrokeTessellator::new();
+
+    let count = tessellator.tessellate_path(
+        path.path_iter(),
+        &StrokeOptions::tolerance(tolerance).with_line_width(width),
+        &mut BuffersBuilder::new(&mut geometry, |vertex: StrokeVertex| {
+            [vertex.position.x, vertex.position.y]
+        }),
+    );
+    // the stroke tessellator returns what it built instead of errors, and an outline with
+    // area always gives triangles
+    if count.indices == 0 {
+        return Err(GlyphMeshError::Tessellation);
+    }
+
+    Ok(GlyphMesh {
+        vertices: geometry.vertices,
+        indices: geometry.indices,
+    })
+}
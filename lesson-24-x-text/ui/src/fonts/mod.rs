@@ -0,0 +1,12071 @@
+use std::any::Any;
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use crate::na;
+#[cfg(not(feature = "simple-shaping"))]
+use harfbuzz_rs as hb;
+pub use font_kit::family_name::FamilyName;
+pub use font_kit::properties::{Properties, Weight, Style, Stretch};
+pub use font_kit::hinting::HintingOptions;
+pub use font_kit::error::{GlyphLoadingError, FontLoadingError};
+pub use font_kit::metrics::Metrics;
+pub use self::shared::{GlyphPosition, GlyphFlags, GlyphRangeInfo, GlyphInfo, ScaledGlyph, PackedGlyph};
+#[cfg(feature = "tessellation")]
+pub use self::mesh::{GlyphMesh, GlyphMeshError, GlyphInstance, BufferMesh};
+pub use self::raster::{SdfBitmap, GlyphBitmap};
+pub use self::quads::{layout_quads, GlyphQuad};
+pub use self::shaping_cache::ShapingCacheStats;
+pub use self::shaped_dump::{ShapedDump, ShapedDirection, ShapedDumpError};
+pub use self::events::FontsEvent;
+pub use self::diagnostics::FontsDiagnostic;
+pub use self::names::{parse_family_list, parse_fontconfig_pattern, FontconfigQuery};
+pub use self::synthesis::SynthesisOptions;
+pub use self::vertical_metrics::{MetricsPolicy, VerticalMetrics, RawVerticalMetrics};
+pub use self::whitespace::Whitespace;
+pub use self::case::Case;
+pub use self::validation::TextWarning;
+pub use self::profiling::{ShapeEvent, ProfiledOperation, ProfileTotals};
+pub use self::size_bucket::SizeBucket;
+pub use self::source::FontSource;
+pub use self::matching::{FaceInfo, MatchCandidate, MatchDetails, MatchQuality};
+pub use self::variations::{NamedInstance, VariationAxis};
+pub use self::glyph_diff::GlyphDiff;
+pub use self::color_layers::{Palette, ColorLayer, ColorLayersError};
+#[cfg(feature = "color-bitmaps")]
+pub use self::color_bitmaps::GlyphImage;
+pub use self::layout::{Align, Justify, LineHeight, LineBreak, LayoutOptions, TabStops, TextLayout, LayoutGlyph, LineMetrics, Bounds, HitResult, Caret, Origin, RoundingMode, ScaledMetrics, Decoration, DecorationOptions};
+pub use self::path_layout::{PathLayoutOptions, PathOverflow, PlacedGlyph};
+pub use self::debug_paths::DebugPaths;
+pub use self::resolved_shape::{ResolvedShape, VisualRun};
+pub use self::cells::GridCell;
+pub use self::bidi::BaseDirection;
+pub use self::retention::FontRetention;
+pub use self::geometry::{GeometryStore, GeometryId, GeometryKey, GeometryEntry, GeometryInstance, GlyphGeometry, GlyphRepresentation};
+pub use self::subset::SubsetError;
+pub use self::editable::EditableBuffer;
+pub use self::chain::{FontChain, Harmonize, HarmonizationFactor};
+use lyon_path::builder::PathBuilder;
+use resources::Resources;
+use self::variations::Fvar;
+use self::lock::{lock, lock_mut, Shared, SharedMut};
+
+mod layout;
+mod lock;
+mod editable;
+mod chain;
+mod path_layout;
+mod debug_paths;
+mod cache;
+#[cfg(feature = "tessellation")]
+mod mesh;
+mod raster;
+mod svg_export;
+mod quads;
+mod shaping_cache;
+mod shaped_dump;
+mod events;
+mod diagnostics;
+mod names;
+mod bidi;
+mod synthesis;
+mod whitespace;
+mod case;
+mod validation;
+mod profiling;
+mod size_bucket;
+mod source;
+mod matching;
+mod sfnt;
+mod variations;
+mod glyph_diff;
+mod color_layers;
+mod gdef;
+mod kerning;
+mod trak;
+mod vertical_metrics;
+mod debug_shape;
+mod fingerprint;
+mod words;
+mod refcount_audit;
+mod resolved_shape;
+mod cells;
+mod geometry;
+mod retention;
+mod subset;
+#[cfg(feature = "simple-shaping")]
+mod simple_shaping;
+#[cfg(feature = "color-bitmaps")]
+mod color_bitmaps;
+pub mod atlas;
+pub mod outline_builder;
+#[cfg(any(test, feature = "conformance"))]
+pub mod conformance;
+
+#[derive(Clone)]
+pub struct Fonts {
+    container: Rc<RefCell<shared::FontsContainer>>,
+}
+
+impl Fonts {
+    pub fn new() -> Fonts {
+        Fonts {
+            container: Rc::new(RefCell::new(shared::FontsContainer::new())),
+        }
+    }
+
+    /// Fonts that never touch the system fonts, for targets without them such as wasm.
+    ///
+    /// Load fonts with `font_from_bytes` or `font_from_resource`. `find_best_match` and
+    /// `find_by_name` then choose among the loaded fonts by family and properties, generic
+    /// families match any of them.
+    pub fn new_memory_only() -> Fonts {
+        Fonts {
+            container: Rc::new(RefCell::new(shared::FontsContainer::new_memory_only())),
+        }
+    }
+
+    /// Fonts that look up families and names in `source` instead of the system fonts.
+    pub fn with_source<S: FontSource + 'static>(source: S) -> Fonts {
+        let fonts = Fonts::new();
+        lock_mut(&fonts.container, "Fonts::with_source").set_font_source(Box::new(source));
+        fonts
+    }
+
+    /// Rebuilds the font source so fonts installed since it was created can be found.
+    ///
+    /// Queries of `find_best_match` that found nothing are run again; fonts they now load are
+    /// reported with `FontsEvent::FontLoaded` and kept loaded until the next refresh. Loaded
+    /// fonts keep their ids and data, also when their file is gone, which is reported with
+    /// `FontsDiagnostic::SourceLost`.
+    pub fn refresh_system_fonts(&self) {
+        lock_mut(&self.container, "Fonts::refresh_system_fonts").refresh_system_fonts();
+    }
+
+    /// Fonts that can also load font files by name with `loader`, see `font_from_resource`.
+    pub fn with_resource_loader<F>(loader: F) -> Fonts
+        where F: Fn(&str) -> Option<Vec<u8>> + 'static {
+        let fonts = Fonts::new();
+        lock_mut(&fonts.container, "Fonts::with_resource_loader").set_resource_loader(Box::new(loader));
+        fonts
+    }
+
+    /// Fonts that load font files from `resources`, the same way as other assets.
+    pub fn with_resources(resources: &Resources) -> Fonts {
+        let resources = resources.clone();
+        Fonts::with_resource_loader(move |name| resources.resource(name).get().ok())
+    }
+
+    /// Loads face `index` of the font file `name` with the resource loader.
+    ///
+    /// The font fingerprint includes the file bytes, so a modified resource is loaded as a new font.
+    pub fn font_from_resource(&self, name: &str, index: u32) -> Result<Font, FontResourceError> {
+        let id = lock_mut(&self.container, "Fonts::font_from_resource").load_resource_font(name, index)?;
+
+        Ok(Font {
+            id,
+            container: self.container.clone(),
+        })
+    }
+
+    /// Loads face `index` of font file data, the same data loads the same font.
+    pub fn font_from_bytes(&self, bytes: Vec<u8>, index: u32) -> Result<Font, FontResourceError> {
+        let id = lock_mut(&self.container, "Fonts::font_from_bytes").load_font_bytes("", bytes, index)?;
+
+        Ok(Font {
+            id,
+            container: self.container.clone(),
+        })
+    }
+
+    pub fn find_best_match(&self, family_names: &[FamilyName], properties: &Properties) -> Option<Font> {
+        let mut shared = lock_mut(&self.container, "Fonts::find_best_match");
+
+        shared.find_best_match(family_names, properties)
+            .map(|id| Font {
+                id,
+                container: self.container.clone(),
+            })
+    }
+
+    /// Faces of the font source closest to the query, best first, without loading any of them;
+    /// for offering close matches when a family is missing. See `load_candidate`.
+    ///
+    /// The faces of the source are listed on first use and again after `refresh_system_fonts`.
+    pub fn find_matches(&self, family_names: &[FamilyName], properties: &Properties, limit: usize) -> Vec<MatchCandidate> {
+        lock_mut(&self.container, "Fonts::find_matches").find_matches(family_names, properties, limit)
+    }
+
+    /// Loads a face found by `find_matches` like `find_best_match`.
+    pub fn load_candidate(&self, candidate: &MatchCandidate) -> Option<Font> {
+        self.find_best_match(&[FamilyName::Title(candidate.family_name.clone())], &candidate.properties)
+    }
+
+    /// Like `find_best_match`, also telling whether the font is the auto fallback font used
+    /// because no requested family was found.
+    pub fn find_best_match_detailed(&self, family_names: &[FamilyName], properties: &Properties) -> Option<FontMatch> {
+        let mut shared = lock_mut(&self.container, "Fonts::find_best_match_detailed");
+
+        shared.find_best_match_detailed(family_names, properties)
+            .map(|(id, substitution)| FontMatch {
+                details: shared.match_details(id).expect("find_best_match_detailed: match of a found font"),
+                font: Font {
+                    id,
+                    container: self.container.clone(),
+                },
+                substitution,
+            })
+    }
+
+    /// Properties the last `find_best_match` query that found `font` asked for and those of
+    /// its face, `None` for fonts not found by a query.
+    pub fn match_details(&self, font: &Font) -> Option<MatchDetails> {
+        assert!(Rc::ptr_eq(&self.container, &font.container), "match_details: font belongs to a different Fonts container");
+        lock(&self.container, "Fonts::match_details").match_details(font.id)
+    }
+
+    /// Which requested properties the face of `font` has exactly, such as to tell a Regular
+    /// face returned for a SemiBold query. See `match_details`.
+    pub fn match_quality(&self, font: &Font) -> Option<MatchQuality> {
+        self.match_details(font).map(|details| details.quality)
+    }
+
+    /// Makes `find_best_match` fall back to a sans-serif font, or the family set with
+    /// `set_auto_fallback_family`, when no requested family is found. Off by default.
+    ///
+    /// Each substitution is reported once as `FontsDiagnostic::FamilySubstituted`.
+    pub fn set_auto_fallback(&self, enabled: bool) {
+        lock_mut(&self.container, "Fonts::set_auto_fallback").set_auto_fallback(enabled);
+    }
+
+    /// Family tried by `set_auto_fallback`, usually a generic family.
+    pub fn set_auto_fallback_family(&self, family: FamilyName) {
+        lock_mut(&self.container, "Fonts::set_auto_fallback_family").set_auto_fallback_family(family);
+    }
+
+    /// Font for interface text from the families set with `set_default_families`, or else
+    /// common interface fonts of the platform, such as Segoe UI on Windows, then any
+    /// sans-serif font.
+    ///
+    /// Resolved like `find_best_match` with default properties, so the query is cached and
+    /// falls back to the family of `set_auto_fallback` if enabled.
+    pub fn default_ui_font(&self) -> Option<Font> {
+        let families = lock(&self.container, "Fonts::default_ui_font").default_ui_families();
+        self.find_best_match(&families, &Properties::new())
+    }
+
+    /// Like `default_ui_font` for code, from common monospace fonts of the platform then any
+    /// monospace font. `set_default_families` does not apply.
+    pub fn default_monospace_font(&self) -> Option<Font> {
+        self.find_best_match(&names::default_monospace_families(), &Properties::new())
+    }
+
+    /// Families tried first by `default_ui_font`, before any sans-serif font; an empty list
+    /// restores the platform fonts.
+    pub fn set_default_families(&self, families: &[FamilyName]) {
+        lock_mut(&self.container, "Fonts::set_default_families").set_default_families(families.to_vec());
+    }
+
+    /// Finds the best match for a CSS `font-family` list, see `parse_family_list`.
+    pub fn find_best_match_css(&self, list: &str, properties: &Properties) -> Option<Font> {
+        self.find_best_match(&parse_family_list(list), properties)
+    }
+
+    /// Finds the best match for a fontconfig pattern such as `DejaVu Sans:style=Bold:size=11`,
+    /// see `parse_fontconfig_pattern`, with the size of the pattern in pixels if it has one.
+    ///
+    /// A pattern without families looks for a sans-serif font like fontconfig.
+    pub fn find_fontconfig(&self, pattern: &str) -> Option<(Font, Option<f32>)> {
+        let mut query = parse_fontconfig_pattern(pattern);
+        if query.families.is_empty() {
+            query.families.push(FamilyName::SansSerif);
+        }
+        let font = self.find_best_match(&query.families, &query.properties)?;
+        Some((font, query.size_px))
+    }
+
+    /// Finds a font by a display name such as `Fira Sans SemiBold Italic`.
+    ///
+    /// Trailing style keywords select the weight, style and stretch, the closest available
+    /// face of the family is used. Falls back to the whole string as a family name and
+    /// then as a PostScript name.
+    pub fn find_by_name(&self, name: &str) -> Option<Font> {
+        let mut shared = lock_mut(&self.container, "Fonts::find_by_name");
+
+        shared.find_by_name(name)
+            .map(|id| Font {
+                id,
+                container: self.container.clone(),
+            })
+    }
+
+    #[cfg_attr(debug_assertions, track_caller)]
+    pub fn font_from_id(&self, id: usize) -> Option<Font> {
+        let mut shared = lock_mut(&self.container, "Fonts::font_from_id");
+
+        Some(Font {
+            container: self.container.clone(),
+            id: shared.get_and_inc_font(id)?,
+        })
+    }
+
+    /// The font of `font_ref` with one more reference, `None` if it was unloaded.
+    ///
+    /// A `font_ref` of a font of another container is `None` as well, and is reported as
+    /// `FontsDiagnostic::OtherContainer`, see `ptr_eq`.
+    #[cfg_attr(debug_assertions, track_caller)]
+    pub fn upgrade_font(&self, font_ref: FontRef) -> Option<Font> {
+        let mut shared = lock_mut(&self.container, "Fonts::upgrade_font");
+        if font_ref._container != shared.token() {
+            shared.report_other_container("Fonts::upgrade_font");
+            return None;
+        }
+        if !shared.font_alive(font_ref) {
+            return None;
+        }
+
+        Some(Font {
+            container: self.container.clone(),
+            id: shared.get_and_inc_font(font_ref._id)?,
+        })
+    }
+
+    /// The font of `font_ref` is still loaded, `false` for fonts of other containers.
+    pub fn font_alive(&self, font_ref: FontRef) -> bool {
+        lock(&self.container, "Fonts::font_alive").font_alive(font_ref)
+    }
+
+    /// The buffer of `buffer` with one more reference, `None` if it was dropped.
+    ///
+    /// A `buffer` of another container is `None` as well, and is reported as
+    /// `FontsDiagnostic::OtherContainer`, see `ptr_eq`.
+    #[cfg_attr(debug_assertions, track_caller)]
+    pub fn upgrade_buffer(&self, buffer: BufferRef) -> Option<Buffer> {
+        {
+            let shared = lock(&self.container, "Fonts::upgrade_buffer");
+            if buffer._container != shared.token() {
+                shared.report_other_container("Fonts::upgrade_buffer");
+                return None;
+            }
+        }
+        self.buffer_from_id(buffer.id())
+    }
+
+    /// A new handle of the buffer with `buffer_id` in this container.
+    ///
+    /// A bare id carries no container, so it is not checked: the id of a buffer of another
+    /// container gives the buffer of this container that has the same id, if any. Keep a
+    /// `BufferRef` and use `upgrade_buffer` when the buffer may be of another container.
+    #[cfg_attr(debug_assertions, track_caller)]
+    pub fn buffer_from_id(&self, buffer_id: usize) -> Option<Buffer> {
+        let mut shared = lock_mut(&self.container, "Fonts::buffer_from_id");
+
+        let (_, buffer_id) = shared.get_and_inc_buffer(buffer_id)?;
+
+        Some(Buffer {
+            container: self.container.clone(),
+            _id: Rc::new(Cell::new(buffer_id)),
+        })
+    }
+
+    pub fn glyphs(&self, _buffer: BufferRef) -> () {}
+
+    /// Number of shaped glyphs in a buffer, `None` if the buffer no longer exists or is of
+    /// another container.
+    pub fn glyph_count(&self, buffer: BufferRef) -> Option<usize> {
+        let mut shared = lock_mut(&self.container, "Fonts::glyph_count");
+        shared.buffer_of(buffer)?;
+        shared.ensure_buffer_shaped(buffer.id());
+        shared.buffer(buffer.id()).map(|b| b.glyphs().len())
+    }
+
+    /// Releases a buffer and keeps its allocations for the next `create_buffer`, once no
+    /// clone of it is left.
+    ///
+    /// Buffers created from recycled ones are the same as newly allocated ones.
+    pub fn recycle(&self, buffer: Buffer) {
+        assert!(Rc::ptr_eq(&self.container, &buffer.container), "recycle: buffer belongs to a different Fonts container");
+        buffer.unshare();
+        lock_mut(&self.container, "Fonts::recycle").mark_buffer_recycled(buffer.id());
+    }
+
+    /// Max number of recycled buffers kept for reuse, 64 by default.
+    pub fn set_buffer_pool_capacity(&self, capacity: usize) {
+        lock_mut(&self.container, "Fonts::set_buffer_pool_capacity").set_buffer_pool_capacity(capacity);
+    }
+
+    /// Max number of entries kept per font in each glyph cache, such as `Font::outline_cached`.
+    pub fn set_glyph_cache_capacity(&self, capacity: usize) {
+        lock_mut(&self.container, "Fonts::set_glyph_cache_capacity").set_glyph_cache_capacity(capacity);
+    }
+
+    /// Rounds the pixel sizes keying sized fonts, outlines at size, signed distance fields and
+    /// atlas glyphs to multiples of `step`, 1/4 pixel by default. A larger step keeps fewer
+    /// entries while sizes are animated, drawing sizes off by up to half a step.
+    ///
+    /// Outlines at size and distance fields cached with the old step are dropped; sized
+    /// fonts stay loaded while they are referenced. The entry counts are in `stats`.
+    pub fn set_size_bucketing(&self, step: f32) {
+        lock_mut(&self.container, "Fonts::set_size_bucketing").set_size_bucketing(step);
+    }
+
+    pub fn size_bucketing(&self) -> f32 {
+        lock(&self.container, "Fonts::size_bucketing").size_bucketing()
+    }
+
+    /// Store of glyph geometry shared by all buffers, filled by `Buffer::geometry_instances`.
+    /// Snapshots get an empty store.
+    pub fn geometry_store(&self) -> GeometryStore {
+        lock(&self.container, "Fonts::geometry_store").geometry_store()
+    }
+
+    /// Hit and miss counters of the cache of shaped single font texts.
+    pub fn shaping_cache_stats(&self) -> ShapingCacheStats {
+        lock(&self.container, "Fonts::shaping_cache_stats").shaping_cache_stats()
+    }
+
+    /// Glyph ids shown by the live buffers by font id, see `Font::weak_ref`, for example to
+    /// `Font::subset` the fonts of a document. Buffers with deferred shaping are shaped.
+    pub fn used_glyphs(&self) -> ::std::collections::HashMap<usize, ::std::collections::HashSet<u32>> {
+        lock_mut(&self.container, "Fonts::used_glyphs").used_glyphs()
+    }
+
+    /// Counts and approximate memory use of the container, cheap enough to log periodically.
+    pub fn stats(&self) -> FontsStats {
+        lock(&self.container, "Fonts::stats").stats()
+    }
+
+    /// Fonts and buffers still referenced, with the call sites that created them and took
+    /// their references. Always `None` without `debug_assertions`.
+    ///
+    /// The same report is logged as an error if the container is dropped with references left,
+    /// which only happens when handles are leaked.
+    pub fn leak_report(&self) -> Option<String> {
+        lock(&self.container, "Fonts::leak_report").leak_report()
+    }
+
+    /// Panics with the `leak_report` if any font or buffer is still referenced, for tests
+    /// after all handles were dropped.
+    pub fn assert_no_leaks(&self) {
+        if let Some(report) = self.leak_report() {
+            panic!("{}", report);
+        }
+    }
+
+    /// Writes the cached shaping results with the fingerprints of their fonts.
+    ///
+    /// Returns the number of written entries.
+    pub fn export_shaped_cache<W: ::std::io::Write>(&self, writer: &mut W) -> ::std::io::Result<usize> {
+        lock(&self.container, "Fonts::export_shaped_cache").export_shaped_cache(writer)
+    }
+
+    /// Seeds the shaping cache with entries written by `export_shaped_cache`, so matching
+    /// texts are not shaped again.
+    ///
+    /// Entries of fonts that are not loaded are skipped, and so are all entries written by a
+    /// build with other font fingerprints, see the `sha1-fingerprints` feature. The cache is
+    /// left unchanged if the data is malformed. Returns the number of imported entries.
+    pub fn import_shaped_cache<R: ::std::io::Read>(&self, reader: &mut R) -> ::std::io::Result<usize> {
+        lock_mut(&self.container, "Fonts::import_shaped_cache").import_shaped_cache(reader)
+    }
+
+    /// Sends font loading problems and lifecycle changes to `handler` instead of the log.
+    ///
+    /// The handler runs after the call that reported the diagnostic is done with the fonts, so
+    /// it can use `Fonts`, fonts and buffers.
+    pub fn set_diagnostics<F: Fn(FontsDiagnostic) + 'static>(&self, handler: F) {
+        lock(&self.container, "Fonts::set_diagnostics").set_diagnostics(Box::new(handler));
+    }
+
+    /// Sends the duration of each shape call, glyph outline and rasterized glyph to `profiler`,
+    /// and sums them up in `stats`. Nothing is timed without a profiler.
+    ///
+    /// Like diagnostics handlers, the profiler runs after the timed call is done with the fonts.
+    pub fn set_profiler<F: Fn(ShapeEvent) + 'static>(&self, profiler: F) {
+        lock(&self.container, "Fonts::set_profiler").set_profiler(Some(Box::new(profiler)));
+    }
+
+    /// Stops timing, the totals in `stats` are kept.
+    pub fn clear_profiler(&self) {
+        lock(&self.container, "Fonts::clear_profiler").set_profiler(None);
+    }
+
+    /// Whether a fonts call is in progress, as in a font source or resource loader. Using the
+    /// fonts then panics naming the re-entrant call.
+    pub fn is_locked(&self) -> bool {
+        self.container.try_borrow_mut().is_err()
+    }
+
+    /// Moves font and buffer lifecycle events since the last call to `output`.
+    ///
+    /// Meant to be called once per frame; pending events are coalesced, so skipping
+    /// calls does not grow the queue with every change.
+    pub fn drain_events(&self, output: &mut Vec<FontsEvent>) {
+        lock_mut(&self.container, "Fonts::drain_events").drain_events(output);
+    }
+
+    /// Every live buffer in id order, for finding leaked buffers.
+    pub fn debug_dump(&self) -> Vec<BufferDebugInfo> {
+        lock(&self.container, "Fonts::debug_dump").debug_dump()
+    }
+
+    /// Every loaded font in id order with its reference count.
+    pub fn debug_dump_fonts(&self) -> Vec<FontDebugInfo> {
+        lock(&self.container, "Fonts::debug_dump_fonts").debug_dump_fonts()
+    }
+
+    /// Drops all cached state, such as after losing the GL context.
+    ///
+    /// Shaping, glyph and font lookup caches are always emptied. Fonts and buffers are
+    /// unloaded when their last handle is dropped, so the ones still held by `Font` and
+    /// `Buffer` handles are kept, and handles stay valid. Returns the number of fonts and
+    /// buffers kept this way, `0` if the container is now empty.
+    pub fn clear(&self) -> usize {
+        lock_mut(&self.container, "Fonts::clear").clear()
+    }
+
+    /// Copy of the container to use on its own from here on, such as for a second window with
+    /// its own GL context.
+    ///
+    /// Fonts share their faces and font data with `self` and buffers their shaped glyphs, the
+    /// glyph and shaping caches start empty. Fonts and buffers keep their ids, so
+    /// `font_from_id` and `buffer_from_id` of the snapshot give the copies of handles of
+    /// `self`. Each copy with handles in `self` starts with one reference of the snapshot,
+    /// released by `clear`.
+    ///
+    /// The snapshot looks up fonts among the system fonts, or none if memory only. Font
+    /// sources, resource loaders, diagnostics handlers and buffer user data are not copied.
+    pub fn snapshot(&self) -> Fonts {
+        Fonts {
+            container: Rc::new(RefCell::new(lock_mut(&self.container, "Fonts::snapshot").snapshot())),
+        }
+    }
+
+    /// Both refer to the same container, snapshots are different containers. Fonts, buffers
+    /// and their references of one can be passed to the other; handles of other containers
+    /// panic or are not found.
+    pub fn ptr_eq(&self, other: &Fonts) -> bool {
+        Rc::ptr_eq(&self.container, &other.container)
+    }
+
+    /// Keeps the shaping cache and the glyph caches of all fonts under about `bytes`,
+    /// evicting least recently used entries. There is no limit by default.
+    ///
+    /// Buffers are not counted and never evicted. Fonts have no glyph bounds cache, bounds
+    /// are read from the font tables.
+    pub fn set_memory_budget(&self, bytes: usize) {
+        lock_mut(&self.container, "Fonts::set_memory_budget").set_memory_budget(Some(bytes));
+    }
+
+    /// Keeps fonts loaded for a while after their last handle is dropped, so that a font used
+    /// again soon, such as by labels recreated after a screen transition, is revived without
+    /// loading it again. Fonts are unloaded at once by default.
+    ///
+    /// Retention ends are checked on `end_frame` and when fonts are released. Glyph caches of
+    /// retained fonts count against `set_memory_budget`, and retained fonts are evicted before
+    /// glyph cache entries when over it. `FontsEvent::FontReleased` reports the last reference
+    /// and `FontsEvent::FontEvicted` the unload.
+    pub fn set_font_retention(&self, retention: FontRetention) {
+        lock_mut(&self.container, "Fonts::set_font_retention").set_font_retention(retention);
+    }
+
+    pub fn font_retention(&self) -> FontRetention {
+        lock(&self.container, "Fonts::font_retention").font_retention()
+    }
+
+    /// Counts a frame for `FontRetention::Frames` and evicts fonts whose retention ended.
+    pub fn end_frame(&self) {
+        lock_mut(&self.container, "Fonts::end_frame").end_frame();
+    }
+
+    /// Evicts the fonts kept by `set_font_retention` now.
+    pub fn flush_unused(&self) {
+        lock_mut(&self.container, "Fonts::flush_unused").flush_unused();
+    }
+
+    /// Approximate bytes used by the caches limited by `set_memory_budget`.
+    pub fn memory_usage(&self) -> usize {
+        lock(&self.container, "Fonts::memory_usage").memory_usage()
+    }
+
+    /// Max number of shaped texts kept for reuse, `0` disables the cache.
+    pub fn set_shaping_cache_capacity(&self, capacity: usize) {
+        lock_mut(&self.container, "Fonts::set_shaping_cache_capacity").set_shaping_cache_capacity(capacity);
+    }
+
+    /// Shapes single font texts word by word, caching each word and space on its own, so texts
+    /// assembled from the same words reuse their shaping. Off by default.
+    ///
+    /// This is an approximation: words are joined with the advance of the space between them,
+    /// so there is no kerning or other shaping across words. Texts with ranged features or
+    /// right-to-left characters are shaped whole. It applies to texts shaped afterwards,
+    /// see `ShapingCacheStats::word_hit_rate`.
+    pub fn set_word_cache(&self, enabled: bool) {
+        lock_mut(&self.container, "Fonts::set_word_cache").set_word_cache(enabled);
+    }
+
+    /// Makes `Font::create_buffer` return another handle to a live buffer created the same way,
+    /// with the same font, text, transform and color, like `Clone` does. Off by default.
+    ///
+    /// Changing a buffer handed out more than once, such as with `set_text` or `set_transform`,
+    /// first moves the other handles it was handed out as, and their clones, to a copy of it
+    /// as created, so they are not affected. The changed handle keeps its id and `BufferRef`s;
+    /// the moved handles get the id of the copy. Clones stay with the handle they were cloned
+    /// from, handles from `upgrade_buffer` with the id. Turning interning off forgets the interned
+    /// buffers; handles already sharing one are still split on change.
+    pub fn set_buffer_interning(&self, enabled: bool) {
+        lock_mut(&self.container, "Fonts::set_buffer_interning").set_buffer_interning(enabled);
+    }
+
+    /// Defers shaping of new buffers until their glyphs, measurement or layout are first needed.
+    ///
+    /// Buffers that are never read are never shaped. Existing buffers are not affected.
+    pub fn set_lazy_shaping(&self, lazy: bool) {
+        lock_mut(&self.container, "Fonts::set_lazy_shaping").set_lazy_shaping(lazy);
+    }
+
+    /// Asks for `Handle::Path` fonts loaded afterwards to be memory-mapped instead of copied.
+    ///
+    /// Mapping a file needs unsafe code, which this crate forbids, and font-kit 0.1 only loads
+    /// fonts from owned bytes, so fonts always take the copy path: they load, shape and hot-reload
+    /// exactly as with the option off.
+    #[cfg(feature = "mmap")]
+    pub fn set_mmap_loading(&self, enabled: bool) {
+        lock_mut(&self.container, "Fonts::set_mmap_loading").set_mmap_loading(enabled);
+    }
+
+    #[cfg(feature = "mmap")]
+    pub fn mmap_loading(&self) -> bool {
+        lock(&self.container, "Fonts::mmap_loading").mmap_loading()
+    }
+
+    /// OpenType features of every buffer, such as `calt` on and `liga` off for a whole
+    /// application. Ranges are ignored.
+    ///
+    /// A buffer feature with the same tag replaces the default for the whole text, or only
+    /// inside its range if it has one. Shaped buffers are reshaped when next read, with a
+    /// `FontsEvent::BufferChanged` each; buffers without their text keep their glyphs.
+    pub fn set_default_features(&self, features: &[Feature]) {
+        lock_mut(&self.container, "Fonts::set_default_features").set_default_features(features);
+    }
+
+    pub fn default_features(&self) -> Vec<Feature> {
+        lock(&self.container, "Fonts::default_features").default_features().to_vec()
+    }
+
+    /// Synthesizes bold and oblique for fonts found with `find_best_match` when the best face
+    /// lacks the requested weight or style. Off by default; see `Font::synthesis`.
+    pub fn set_synthesis(&self, synthesis: SynthesisOptions) {
+        lock_mut(&self.container, "Fonts::set_synthesis").set_synthesis(synthesis);
+    }
+
+    /// Pixels per em of buffers without a size of their own, 16 by default; see `Buffer::set_size_px`.
+    pub fn set_default_size(&self, px_size: f32) {
+        lock_mut(&self.container, "Fonts::set_default_size").set_default_size_px(px_size);
+    }
+
+    /// Physical pixels per logical pixel, 1 by default.
+    ///
+    /// Sizes and positions stay in logical pixels, while bitmaps, hinting and the subpixel
+    /// phases of `Buffer::glyphs_scaled` use the physical pixel grid. Changing the factor drops
+    /// sized outlines and distance fields and emits `FontsEvent::ScaleFactorChanged`.
+    ///
+    /// Panics unless the factor is finite and positive.
+    pub fn set_scale_factor(&self, scale_factor: f32) {
+        lock_mut(&self.container, "Fonts::set_scale_factor").set_scale_factor(scale_factor);
+    }
+
+    pub fn scale_factor(&self) -> f32 {
+        lock(&self.container, "Fonts::scale_factor").scale_factor()
+    }
+
+    /// Y direction of scaled and packed glyphs, ink bounds, layouts and quads,
+    /// `Origin::BottomLeftYUp` by default.
+    ///
+    /// Glyph meshes and outlines stay in font units with Y up. Changing the origin bumps the
+    /// transform version of every buffer.
+    pub fn set_origin(&self, origin: Origin) {
+        lock_mut(&self.container, "Fonts::set_origin").set_origin(origin);
+    }
+
+    pub fn origin(&self) -> Origin {
+        lock(&self.container, "Fonts::origin").origin()
+    }
+
+    /// Snapping of advances in `Buffer::glyphs_scaled`, `Buffer::glyphs_packed` and so quads,
+    /// `RoundingMode::None` by default. Rounding uses physical pixels.
+    ///
+    /// `Buffer::advance_width_px` and `Buffer::measure_trimmed` use the same mode, so measured
+    /// widths match rendered ones. Changing the mode bumps the transform version of every buffer.
+    pub fn set_rounding(&self, mode: RoundingMode) {
+        lock_mut(&self.container, "Fonts::set_rounding").set_rounding(mode);
+    }
+
+    pub fn rounding(&self) -> RoundingMode {
+        lock(&self.container, "Fonts::rounding").rounding()
+    }
+
+    /// Vertical metrics of `Font::metrics`, `Font::metrics_for_size` and layouts,
+    /// `MetricsPolicy::Hhea` by default. Fonts lacking the chosen set use the other one.
+    ///
+    /// A change that alters the metrics of a loaded font bumps the transform version of every
+    /// buffer and emits `FontsEvent::MetricsChanged`.
+    pub fn set_metrics_policy(&self, policy: MetricsPolicy) {
+        lock_mut(&self.container, "Fonts::set_metrics_policy").set_metrics_policy(policy);
+    }
+
+    pub fn metrics_policy(&self) -> MetricsPolicy {
+        lock(&self.container, "Fonts::metrics_policy").metrics_policy()
+    }
+
+    /// Clone of the user data of a buffer if it is a `T`, see `Buffer::set_user_data`. `None`
+    /// if the buffer no longer exists or is of another container.
+    pub fn user_data_for<T: Any + Clone>(&self, buffer: BufferRef) -> Option<T> {
+        let shared = lock(&self.container, "Fonts::user_data_for");
+        shared.buffer_of(buffer)?.user_data()?.downcast_ref::<T>().cloned()
+    }
+
+    /// Shaped content version of a buffer, `None` if the buffer no longer exists or is of
+    /// another container.
+    pub fn buffer_version(&self, buffer: BufferRef) -> Option<u64> {
+        let shared = lock(&self.container, "Fonts::buffer_version");
+        shared.buffer_of(buffer).map(|b| b.version())
+    }
+
+    /// Both handles share one container, so fonts, buffers and their references of one can be
+    /// passed to the other. The same as `ptr_eq`.
+    pub fn same_container(&self, other: &Fonts) -> bool {
+        self.ptr_eq(other)
+    }
+
+    /// Identity of the container, kept by `FontRef` and `BufferRef`.
+    pub fn container_token(&self) -> ContainerToken {
+        lock(&self.container, "Fonts::container_token").token()
+    }
+
+    /// Shapes `text` with `font` into the `hb-shape` format, see `Buffer::debug_shape_string`.
+    pub fn shape_debug(&self, font: &Font, text: &str) -> String {
+        assert!(Rc::ptr_eq(&self.container, &font.container), "shape_debug: font belongs to a different Fonts container");
+        font.create_buffer(text, None, [0, 0, 0, 255].into()).debug_shape_string()
+    }
+
+    /// Creates a buffer with the glyphs of `dump` without shaping the text.
+    ///
+    /// Fails if no loaded font has the dump font name or the dump does not pass
+    /// `ShapedDump::validate` with that font.
+    pub fn load_shaped_dump(&self, dump: &ShapedDump, transform: Option<na::Projective3<f32>>, color: na::Vector4<u8>) -> Result<Buffer, ShapedDumpError> {
+        let id = lock_mut(&self.container, "Fonts::load_shaped_dump").load_shaped_dump(dump, transform, color)?;
+
+        Ok(Buffer {
+            container: self.container.clone(),
+            _id: Rc::new(Cell::new(id)),
+        })
+    }
+
+    /// Creates a buffer with glyphs shaped elsewhere, such as offline, without shaping the text.
+    ///
+    /// The buffer measures and renders like a shaped one; `byte_offset` and `len` of the
+    /// glyphs are derived from the clusters. Text changes shape the new text with the font.
+    pub fn create_preshaped_buffer(&self, font: &Font, text: String, glyphs: Vec<GlyphPosition>, transform: Option<na::Projective3<f32>>, color: na::Vector4<u8>) -> Result<Buffer, PreshapedGlyphsError> {
+        assert!(Rc::ptr_eq(&self.container, &font.container), "create_preshaped_buffer: font belongs to a different Fonts container");
+        let id = lock_mut(&self.container, "Fonts::create_preshaped_buffer").create_preshaped_buffer(font.id, text, glyphs, transform, color)?;
+
+        Ok(Buffer {
+            container: self.container.clone(),
+            _id: Rc::new(Cell::new(id)),
+        })
+    }
+
+    /// Creates a single buffer from text where each span is shaped with its own font.
+    ///
+    /// The buffer font is the font of the first span. Returns `None` if there are no spans.
+    /// Spans with a px size keep their size relative to the default size at creation, see
+    /// `SpannedText::push_sized`.
+    pub fn create_spanned_buffer(&self, text: &SpannedText, transform: Option<na::Projective3<f32>>, color: na::Vector4<u8>) -> Option<Buffer> {
+        let font_id = text.spans.first()?.font.id;
+        assert!(text.spans.iter().all(|span| Rc::ptr_eq(&self.container, &span.font.container)),
+            "create_spanned_buffer: a span font belongs to a different Fonts container");
+
+        let id = {
+            let mut shared = lock_mut(&self.container, "Fonts::create_spanned_buffer");
+            let buffer_px = shared.default_size_px();
+            let spans = text.spans.iter()
+                .map(|span| {
+                    let size = span.px_size.map_or(1.0, |px| px / buffer_px);
+                    shared::SpanData {
+                        range: span.range.clone(),
+                        font_id: span.font.id,
+                        scale: size * span.scale,
+                        shift: span.baseline_shift * size,
+                    }
+                })
+                .collect();
+
+            shared.create_spanned_buffer(font_id, &text.text, spans, transform, color)
+        };
+
+        Some(Buffer {
+            container: self.container.clone(),
+            _id: Rc::new(Cell::new(id)),
+        })
+    }
+}
+
+/// Text where consecutive ranges are shaped with different fonts.
+#[derive(Clone)]
+pub struct SpannedText {
+    text: String,
+    spans: Vec<Span>,
+}
+
+#[derive(Clone)]
+pub struct Span {
+    pub range: ::std::ops::Range<usize>,
+    pub font: Font,
+    /// Size of the span, the buffer size when `None`.
+    pub px_size: Option<f32>,
+    /// Multiplier of the span size, 1 unless set with `SpannedText::push_adjusted`.
+    pub scale: f32,
+    /// Shift of the baseline in ems of the span size before `scale`, up positive.
+    pub baseline_shift: f32,
+}
+
+impl SpannedText {
+    pub fn new() -> SpannedText {
+        SpannedText {
+            text: String::new(),
+            spans: Vec::new(),
+        }
+    }
+
+    /// Appends text shaped with the specified font.
+    pub fn push(mut self, text: &str, font: &Font) -> SpannedText {
+        let start = self.text.len();
+        self.text.push_str(text);
+        self.spans.push(Span {
+            range: start..self.text.len(),
+            font: font.clone(),
+            px_size: None,
+            scale: 1.0,
+            baseline_shift: 0.0,
+        });
+        self
+    }
+
+    /// Appends text shaped with the specified font at its own size. Runs of all sizes share
+    /// the baseline and lines are as tall as the tallest run; the size scales along when the
+    /// buffer is drawn at another size than the default one.
+    pub fn push_sized(self, text: &str, font: &Font, px_size: f32) -> SpannedText {
+        let mut spanned = self.push(text, font);
+        spanned.spans.last_mut().expect("push_sized: a span was just pushed").px_size = Some(px_size);
+        spanned
+    }
+
+    /// Appends text shaped with the specified font scaled by `scale` and with the baseline
+    /// moved up by `baseline_shift` ems, as `FontChain` harmonizes fallback fonts. The
+    /// adjustment applies to the glyphs, quads, layout and measurement of the buffer.
+    pub fn push_adjusted(self, text: &str, font: &Font, scale: f32, baseline_shift: f32) -> SpannedText {
+        let mut spanned = self.push(text, font);
+        {
+            let span = spanned.spans.last_mut().expect("push_adjusted: a span was just pushed");
+            span.scale = scale;
+            span.baseline_shift = baseline_shift;
+        }
+        spanned
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn spans(&self) -> &[Span] {
+        &self.spans
+    }
+}
+
+/// OpenType feature value for `Buffer::set_features`, applied to the whole text or a byte range.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Feature {
+    pub tag: [u8; 4],
+    pub value: u32,
+    pub range: Option<::std::ops::Range<usize>>,
+}
+
+impl Feature {
+    pub fn new(tag: &[u8; 4], value: u32) -> Feature {
+        Feature {
+            tag: *tag,
+            value,
+            range: None,
+        }
+    }
+
+    /// Limits the feature to a byte range of the text, snapped outward to grapheme boundaries.
+    pub fn with_range(mut self, range: ::std::ops::Range<usize>) -> Feature {
+        self.range = Some(range);
+        self
+    }
+}
+
+pub struct Font {
+    id: usize,
+    container: Rc<RefCell<shared::FontsContainer>>,
+}
+
+impl Font {
+    pub fn full_name(&self) -> String {
+        let shared = lock(&self.container, "Font::full_name");
+        shared.get(self.id)
+            .expect("full_name: loaded font should exist")
+            .fk_font.full_name()
+    }
+
+    pub fn family_name(&self) -> String {
+        let shared = lock(&self.container, "Font::family_name");
+        shared.get(self.id)
+            .expect("family_name: loaded font should exist")
+            .family_name.clone()
+    }
+
+    /// PostScript name of the loaded face, if the font has one.
+    pub fn postscript_name(&self) -> Option<String> {
+        let shared = lock(&self.container, "Font::postscript_name");
+        shared.get(self.id)
+            .expect("postscript_name: loaded font should exist")
+            .postscript_name.clone()
+    }
+
+    /// Weight, style and stretch of the loaded face, which may differ from the query that found it.
+    pub fn properties(&self) -> Properties {
+        let shared = lock(&self.container, "Font::properties");
+        shared.get(self.id)
+            .expect("properties: loaded font should exist")
+            .properties
+    }
+
+    /// Bytes of the font file, shared by all callers; a collection file includes all its faces.
+    ///
+    /// Read once per font and kept while the font is loaded. `None` if the file can not be read.
+    pub fn copy_font_data(&self) -> Option<::std::sync::Arc<Vec<u8>>> {
+        let mut shared = lock_mut(&self.container, "Font::copy_font_data");
+        shared.get_mut(self.id)
+            .expect("copy_font_data: loaded font should exist")
+            .copy_font_data()
+    }
+
+    /// Bytes of the OpenType table `tag`, such as `*b"OS/2"`, `None` if the font has none.
+    ///
+    /// Tables are read once per font and shared between calls.
+    pub fn table(&self, tag: [u8; 4]) -> Option<::std::sync::Arc<[u8]>> {
+        let shared = lock(&self.container, "Font::table");
+        shared.get(self.id)
+            .expect("table: loaded font should exist")
+            .table(tag)
+    }
+
+    /// Font file of this face with only the outlines of `glyph_ids`, of the components of
+    /// composite glyphs and of `.notdef`, and the characters mapped to them, for embedding
+    /// fonts in saved files. Loads with `Fonts::font_from_bytes`.
+    ///
+    /// Glyph ids are unchanged and all other tables are kept, so text shapes the same with
+    /// the subset. Only fonts with TrueType outlines can be subset.
+    pub fn subset(&self, glyph_ids: &[u32]) -> Result<Vec<u8>, SubsetError> {
+        let data = self.copy_font_data().ok_or(SubsetError::NoFontData)?;
+        subset::subset(&data, self.font_index(), glyph_ids)
+    }
+
+    /// Caret x positions between the components of a ligature glyph from the `GDEF` table,
+    /// in font units, empty if the font has none for the glyph.
+    ///
+    /// `TextLayout::caret_for_index` uses them inside ligatures and otherwise spreads the
+    /// components evenly over the glyph advance.
+    pub fn ligature_carets(&self, glyph_id: u32) -> Vec<i32> {
+        let shared = lock(&self.container, "Font::ligature_carets");
+        shared.get(self.id)
+            .expect("ligature_carets: loaded font should exist")
+            .ligature_carets(glyph_id)
+    }
+
+    /// Advance adjustment of `left_glyph` when followed by `right_glyph` in font units,
+    /// zero if the font does not kern the pair.
+    ///
+    /// Like shaping, the `kern` feature of the `GPOS` table is used and the `kern` table only
+    /// when there is no `GPOS` table. Lookups are those for Latin text, contextual kerning is
+    /// not applied.
+    pub fn kerning(&self, left_glyph: u32, right_glyph: u32) -> i32 {
+        let shared = lock(&self.container, "Font::kerning");
+        shared.get(self.id)
+            .expect("kerning: loaded font should exist")
+            .kerning(left_glyph, right_glyph)
+    }
+
+    /// Name of a glyph from the `post` table, `None` if the font has no glyph names.
+    pub fn glyph_name(&self, glyph_id: u32) -> Option<String> {
+        let shared = lock(&self.container, "Font::glyph_name");
+        shared.get(self.id)
+            .expect("glyph_name: loaded font should exist")
+            .glyph_name(glyph_id)
+    }
+
+    /// Reference that does not keep the font loaded, for keying renderer side data.
+    pub fn weak_ref(&self) -> FontRef {
+        let shared = lock(&self.container, "Font::weak_ref");
+        FontRef {
+            _id: self.id,
+            _generation: shared.get(self.id).expect("weak_ref: loaded font should exist").generation,
+            _container: shared.token(),
+        }
+    }
+
+    /// Identity of the container of the font, see `Fonts::ptr_eq`.
+    pub fn container_token(&self) -> ContainerToken {
+        lock(&self.container, "Font::container_token").token()
+    }
+
+    /// Index of the face in the font file, `0` unless it is a collection.
+    pub fn font_index(&self) -> u32 {
+        let shared = lock(&self.container, "Font::font_index");
+        shared.get(self.id)
+            .expect("font_index: loaded font should exist")
+            .face_index
+    }
+
+    /// Variation axes from the `fvar` table, empty if the font is not variable.
+    pub fn variation_axes(&self) -> Vec<VariationAxis> {
+        self.fvar().map_or_else(Vec::new, |fvar| fvar.axes.clone())
+    }
+
+    /// Named instances from the `fvar` table, empty if the font is not variable.
+    ///
+    /// They are only listed: fonts always use the default instance, since neither the
+    /// font-kit outline loader nor the HarfBuzz binding can set variation coordinates, and
+    /// an instance whose shaping and outlines disagree would render wrongly.
+    pub fn named_instances(&self) -> Vec<NamedInstance> {
+        self.fvar().map_or_else(Vec::new, |fvar| fvar.instances.clone())
+    }
+
+    /// Passes the harfbuzz font to `f`, for queries the wrapper does not expose.
+    ///
+    /// The fonts container is borrowed while `f` runs, like in `Buffer::for_each_glyph`; the
+    /// font is shared by all buffers, so it can only be read and must not outlive `f`. Not
+    /// available with the `simple-shaping` feature.
+    #[cfg(not(feature = "simple-shaping"))]
+    pub fn with_raw_hb_font<R, F: FnOnce(&hb::Font<'static>) -> R>(&self, f: F) -> R {
+        let shared = lock(&self.container, "Font::with_raw_hb_font");
+        f(&shared.get(self.id).expect("with_raw_hb_font: loaded font should exist").hb_font)
+    }
+
+    fn fvar(&self) -> Option<Rc<Fvar>> {
+        let mut shared = lock_mut(&self.container, "Font::fvar");
+        shared.get_mut(self.id)
+            .expect("fvar: loaded font should exist")
+            .fvar()
+    }
+
+    /// Glyph id of a char in the character map, `None` if it is not mapped.
+    pub fn glyph_for_char(&self, c: char) -> Option<u32> {
+        let mut shared = lock_mut(&self.container, "Font::glyph_for_char");
+        shared.get_mut(self.id)
+            .expect("glyph_for_char: loaded font should exist")
+            .glyph_for_char_cached(c)
+    }
+
+    /// Appends the glyph id of each char of `text` to `output`, `None` for unmapped chars.
+    pub fn glyphs_for_str(&self, text: &str, output: &mut Vec<Option<u32>>) {
+        let mut shared = lock_mut(&self.container, "Font::glyphs_for_str");
+        let font = shared.get_mut(self.id)
+            .expect("glyphs_for_str: loaded font should exist");
+
+        output.extend(text.chars().map(|c| font.glyph_for_char_cached(c)));
+    }
+
+    /// All glyphs have the same advance, according to the font tables.
+    pub fn is_monospace(&self) -> bool {
+        let shared = lock(&self.container, "Font::is_monospace");
+        shared.get(self.id)
+            .expect("is_monospace: loaded font should exist")
+            .fk_font.is_monospace()
+    }
+
+    /// Advances in font units of the glyphs `sample` maps to, without shaping, to check that
+    /// a font advances by whole grid cells. See `AdvanceReport`.
+    pub fn advance_histogram<I: IntoIterator<Item=char>>(&self, sample: I) -> AdvanceReport {
+        let shared = lock(&self.container, "Font::advance_histogram");
+        let font = shared.get(self.id).expect("advance_histogram: loaded font should exist");
+
+        let mut report = AdvanceReport::default();
+        let mut advances = Vec::new();
+        for c in sample {
+            match font.fk_font.glyph_for_char(c) {
+                Some(glyph_id) if glyph_id != 0 => {
+                    let advance = font.fk_font.advance(glyph_id).map_or(0, |advance| (advance.x * font.units_scale).round() as i32);
+                    advances.push((c, advance));
+                }
+                _ => report.missing.push(c),
+            }
+        }
+
+        for &(_, advance) in &advances {
+            match report.histogram.iter_mut().find(|&&mut (a, _)| a == advance) {
+                Some(bucket) => bucket.1 += 1,
+                None => report.histogram.push((advance, 1)),
+            }
+        }
+        report.histogram.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        report.cell_width = report.histogram.first().map_or(0, |&(advance, _)| advance);
+        let cell_width = report.cell_width;
+        report.offending = advances.into_iter()
+            .filter(|&(c, advance)| advance != cell_width * if cells::is_wide(c) { 2 } else { 1 })
+            .collect();
+        report
+    }
+
+    /// Looks up each char in the character map, without shaping.
+    pub fn supports_chars<I: IntoIterator<Item=char>>(&self, chars: I) -> CoverageReport {
+        let shared = lock(&self.container, "Font::supports_chars");
+        let fk_font = &shared.get(self.id)
+            .expect("supports_chars: loaded font should exist")
+            .fk_font;
+
+        let mut report = CoverageReport::default();
+        for c in chars {
+            match fk_font.glyph_for_char(c) {
+                Some(glyph_id) if glyph_id != 0 => report.supported += 1,
+                _ => report.missing.push(c),
+            }
+        }
+        report
+    }
+
+    pub fn glyph_count(&self) -> u32 {
+        let shared = lock(&self.container, "Font::glyph_count");
+        shared.get(self.id)
+            .expect("glyph_count: loaded font should exist")
+            .fk_font.glyph_count()
+    }
+
+    pub fn outline<B>(&self, glyph_id: u32, hinting: HintingOptions, path_builder: &mut B)
+                      -> Result<(), GlyphLoadingError>
+        where B: PathBuilder {
+        if self.synthesis().is_any() || self.size_px().is_some() {
+            let path = self.outline_cached(glyph_id, hinting)?;
+            shared::copy_path(&path, 1.0, path_builder);
+            return Ok(());
+        }
+
+        let shared = lock(&self.container, "Font::outline");
+        shared.get(self.id)
+            .expect("outline: loaded font should exist")
+            .fk_font.outline(glyph_id, hinting, path_builder)
+    }
+
+    /// `outline` into a builder chosen at runtime, see `outline_builder::OutlineBuilder`.
+    pub fn outline_dyn(&self, glyph_id: u32, hinting: HintingOptions, builder: &mut dyn outline_builder::OutlineBuilder)
+                       -> Result<(), GlyphLoadingError> {
+        self.outline(glyph_id, hinting, &mut outline_builder::DynPathBuilder::new(builder))
+    }
+
+    /// Styles synthesized for this font because its face lacks them, none for regular fonts.
+    pub fn synthesis(&self) -> SynthesisOptions {
+        let shared = lock(&self.container, "Font::synthesis");
+        shared.get(self.id)
+            .expect("synthesis: loaded font should exist")
+            .synthesis
+    }
+
+    /// Pixels per em of a font from `at_size`, `None` for other fonts.
+    pub fn size_px(&self) -> Option<f32> {
+        let shared = lock(&self.container, "Font::size_px");
+        shared.get(self.id)
+            .expect("size_px: loaded font should exist")
+            .size_px
+    }
+
+    /// The same face with its font units set to 1/64 pixel at `px_size`, rounded to the
+    /// `Fonts::set_size_bucketing` step, so shaping gives 26.6 fixed point pixel positions and
+    /// harfbuzz uses the size for its ppem. Outlines, bounds, kerning and metrics are in the same units.
+    ///
+    /// Sized fonts are loaded once per font and size, and unloaded with their last reference
+    /// like other fonts.
+    #[cfg_attr(debug_assertions, track_caller)]
+    pub fn at_size(&self, px_size: f32) -> SizedFont {
+        let id = lock_mut(&self.container, "Font::at_size").sized_font(self.id, px_size);
+        let font = Font {
+            id,
+            container: self.container.clone(),
+        };
+        let px_size = font.size_px().unwrap_or(px_size);
+
+        SizedFont { font, px_size }
+    }
+
+    /// The same face with synthetic bold and oblique outlines and advances, shared by all
+    /// requests for the same options. Returns this font for no synthesis.
+    pub fn with_synthesis(&self, synthesis: SynthesisOptions) -> Font {
+        let id = lock_mut(&self.container, "Font::with_synthesis").synthesized_font(self.id, synthesis);
+
+        Font {
+            id,
+            container: self.container.clone(),
+        }
+    }
+
+    /// Cache key of `px_size` rounded to the `Fonts::set_size_bucketing` step.
+    pub fn size_bucket(&self, px_size: f32) -> SizeBucket {
+        lock(&self.container, "Font::size_bucket").size_bucket(px_size)
+    }
+
+    /// Glyph outline as a shared path, built once and kept in the font's outline cache.
+    pub fn outline_cached(&self, glyph_id: u32, hinting: HintingOptions) -> Result<Rc<lyon_path::default::Path>, GlyphLoadingError> {
+        lock_mut(&self.container, "Font::outline_cached").with_font_caches_profiled(self.id, ProfiledOperation::Outline, |font| font.outline_cached(glyph_id, hinting))
+    }
+
+    /// Glyph outline flattened to points in font units, with curves split until they are
+    /// within `tolerance` of the outline. `contours` gets the range of `out` of each contour.
+    ///
+    /// Uses the outline cache of `outline_cached`.
+    pub fn outline_segments(&self, glyph_id: u32, hinting: HintingOptions, tolerance: f32, out: &mut Vec<[f32; 2]>, contours: &mut Vec<::std::ops::Range<usize>>)
+                            -> Result<(), GlyphLoadingError> {
+        let path = self.outline_cached(glyph_id, hinting)?;
+        raster::flatten_contours(&path, tolerance, out, contours);
+        Ok(())
+    }
+
+    /// Triangulated glyph outline in font units, cached per tolerance bucket.
+    #[cfg(feature = "tessellation")]
+    pub fn tessellate_glyph(&self, glyph_id: u32, hinting: HintingOptions, tolerance: f32) -> Result<Rc<GlyphMesh>, GlyphMeshError> {
+        lock_mut(&self.container, "Font::tessellate_glyph").with_font_caches(self.id, |font| font.glyph_mesh(glyph_id, hinting, tolerance))
+    }
+
+    /// Triangulated stroke along the glyph outline, `stroke_width` and `tolerance` in font units.
+    #[cfg(feature = "tessellation")]
+    pub fn tessellate_glyph_stroke(&self, glyph_id: u32, stroke_width: f32, tolerance: f32) -> Result<Rc<GlyphMesh>, GlyphMeshError> {
+        lock_mut(&self.container, "Font::tessellate_glyph_stroke").with_font_caches(self.id, |font| font.glyph_stroke_mesh(glyph_id, HintingOptions::None, stroke_width, tolerance))
+    }
+
+    /// Signed distance field of a glyph at a size rounded to the `Fonts::set_size_bucketing` step.
+    ///
+    /// `padding` adds empty pixels around the glyph and `spread` is the distance in pixels
+    /// covered by the value range. Returns `None` for glyphs without an outline.
+    ///
+    /// The field is built at `px_size` times the scale factor, its pixels are physical.
+    pub fn glyph_sdf(&self, glyph_id: u32, px_size: f32, padding: u32, spread: f32) -> Option<Rc<SdfBitmap>> {
+        let mut shared = lock_mut(&self.container, "Font::glyph_sdf");
+        let size = shared.size_bucket(px_size * shared.scale_factor());
+        shared.with_font_caches_profiled(self.id, ProfiledOperation::Raster, |font| font.glyph_sdf(glyph_id, size, padding, spread))
+    }
+
+    /// Antialiased alpha bitmap of a glyph, using the hinted outline for `hinting`.
+    ///
+    /// `origin_offset` shifts the glyph within the pixel grid, in pixels, which allows
+    /// subpixel positioned variants. Returns `None` for glyphs without an outline.
+    ///
+    /// The glyph is rasterized at `px_size` times the scale factor, so the bitmap, its
+    /// bearings, advance and `origin_offset` are in physical pixels.
+    pub fn rasterize_glyph(&self, glyph_id: u32, px_size: f32, hinting: HintingOptions, origin_offset: (f32, f32)) -> Option<GlyphBitmap> {
+        let mut shared = lock_mut(&self.container, "Font::rasterize_glyph");
+        let px_size = px_size * shared.scale_factor();
+        shared.with_font_caches_profiled(self.id, ProfiledOperation::Raster, |font| font.rasterize_glyph(glyph_id, px_size, hinting, origin_offset))
+    }
+
+    /// Writes the outline hinted for `px_size` to `path_builder`, in pixels.
+    ///
+    /// The size of `hinting` is replaced with `px_size`. Outlines are cached per glyph and
+    /// size, rounded to the `Fonts::set_size_bucketing` step. Hinting uses the physical size,
+    /// the outline is written in logical pixels.
+    pub fn outline_at_size<B>(&self, glyph_id: u32, px_size: f32, hinting: HintingOptions, path_builder: &mut B)
+                              -> Result<(), GlyphLoadingError>
+        where B: PathBuilder {
+        let (path, scale_factor) = {
+            let mut shared = lock_mut(&self.container, "Font::outline_at_size");
+            let scale_factor = shared.scale_factor();
+            let size = shared.size_bucket(px_size * scale_factor);
+            let path = shared.with_font_caches(self.id, |font| font.outline_at_size(glyph_id, size, hinting))?;
+            (path, scale_factor)
+        };
+        shared::copy_path(&path, 1.0 / scale_factor, path_builder);
+        Ok(())
+    }
+
+    /// Embedded color image of a glyph from the `sbix` or `CBDT` strike closest to `px_size`,
+    /// scaled to `px_size`. Returns `None` if the font has no image for the glyph.
+    ///
+    /// Like `rasterize_glyph`, the image is in physical pixels.
+    #[cfg(feature = "color-bitmaps")]
+    pub fn glyph_image(&self, glyph_id: u32, px_size: f32) -> Option<GlyphImage> {
+        let mut shared = lock_mut(&self.container, "Font::glyph_image");
+        let px_size = px_size * shared.scale_factor();
+        shared.get_mut(self.id)
+            .expect("glyph_image: loaded font should exist")
+            .glyph_image(glyph_id, px_size)
+    }
+
+    /// Palettes of the `CPAL` table, such as light and dark ones, empty if the font has none.
+    pub fn color_palettes(&self) -> Vec<Palette> {
+        self.table(*b"CPAL").and_then(|cpal| color_layers::palettes(&cpal)).unwrap_or_default()
+    }
+
+    /// Layers of a `COLR` color glyph with colors from palette `palette` of `color_palettes`,
+    /// empty if the glyph is not a color glyph.
+    pub fn color_layers(&self, glyph_id: u32, palette: usize) -> Result<Vec<ColorLayer>, ColorLayersError> {
+        let (colr, cpal) = (self.table(*b"COLR"), self.table(*b"CPAL"));
+        color_layers::color_layers(colr.as_ref().map(|t| &t[..]), cpal.as_ref().map(|t| &t[..]), glyph_id, palette)
+    }
+
+    /// Size of `text` shaped with this font at `px_size`, without creating a buffer, for
+    /// deciding whether to create one at all. `features` are added to the container defaults
+    /// like `Buffer::set_features`.
+    ///
+    /// The text goes through the shaping cache, so creating a buffer of it afterwards does not
+    /// shape it again. Unlike a buffer it is shaped as given: whitespace, case and `trak`
+    /// tracking settings don't apply.
+    pub fn measure_str(&self, text: &str, px_size: f32, features: &[Feature]) -> TextMeasure {
+        lock_mut(&self.container, "Font::measure_str").measure_str(self.id, text, px_size, features)
+    }
+
+    /// Scale factor of the fonts this font was loaded by, see `Fonts::set_scale_factor`.
+    pub fn scale_factor(&self) -> f32 {
+        lock(&self.container, "Font::scale_factor").scale_factor()
+    }
+
+    /// Drops all cached outlines and other per-glyph data of this font.
+    pub fn clear_glyph_caches(&self) {
+        let mut shared = lock_mut(&self.container, "Font::clear_glyph_caches");
+        shared.get_mut(self.id)
+            .expect("clear_glyph_caches: loaded font should exist")
+            .clear_caches();
+    }
+
+    pub fn metrics(&self) -> Metrics {
+        let shared = lock(&self.container, "Font::metrics");
+        shared.get(self.id)
+            .expect("metrics: loaded font should exist")
+            .metrics
+    }
+
+    /// Both the `OS/2` typo and the `hhea` vertical metrics in font units, whichever
+    /// `Fonts::set_metrics_policy` selects.
+    pub fn raw_vertical_metrics(&self) -> RawVerticalMetrics {
+        let shared = lock(&self.container, "Font::raw_vertical_metrics");
+        shared.get(self.id)
+            .expect("raw_vertical_metrics: loaded font should exist")
+            .raw_vertical_metrics()
+    }
+
+    /// Metrics in pixels at `px_size` pixels per em, not rounded. Layouts use the same line
+    /// height for `LineHeight::FontDefault`.
+    pub fn metrics_for_size(&self, px_size: f32) -> ScaledMetrics {
+        ScaledMetrics::new(&self.metrics(), px_size)
+    }
+
+    /// Font units per em, the unit of glyph advances, offsets and outlines.
+    pub fn units_per_em(&self) -> u32 {
+        self.metrics().units_per_em
+    }
+
+    /// Multiplier from font units to pixels at `px_size` pixels per em.
+    pub fn scale_for_px(&self, px_size: f32) -> f32 {
+        px_size / self.units_per_em() as f32
+    }
+
+    /// Builder of buffers with features, whitespace and size set before their only shaping,
+    /// instead of reshaping after each setter.
+    pub fn buffer(&self) -> BufferBuilder {
+        BufferBuilder::new(self)
+    }
+
+    /// Creates a buffer from text, moving an owned `String` in without copying it.
+    #[cfg_attr(any(feature = "debug-origins", debug_assertions), track_caller)]
+    pub fn create_buffer<'a, P: Into<Cow<'a, str>>>(&self, text: P, transform: Option<na::Projective3<f32>>, color: na::Vector4<u8>) -> Buffer {
+        let buffer = Buffer::new(self, text, transform, color);
+        #[cfg(feature = "debug-origins")]
+        buffer.set_creation_origin(&::std::panic::Location::caller().to_string());
+        buffer
+    }
+
+    /// Creates editable text shaped paragraph by paragraph, see `EditableBuffer`.
+    pub fn create_editable_buffer(&self, text: &str, transform: Option<na::Projective3<f32>>, color: na::Vector4<u8>) -> EditableBuffer {
+        EditableBuffer::new(self, text, transform, color)
+    }
+
+    /// Creates a buffer for each text with the same transform and color, in input order.
+    ///
+    /// The container is borrowed once for the whole batch.
+    #[cfg_attr(any(feature = "debug-origins", debug_assertions), track_caller)]
+    pub fn create_buffers<I, S>(&self, texts: I, transform: Option<na::Projective3<f32>>, color: na::Vector4<u8>) -> Vec<Buffer>
+        where I: IntoIterator<Item=S>, S: AsRef<str> {
+        let ids = lock_mut(&self.container, "Font::create_buffers").create_buffers(self.id, texts, transform, color);
+        #[cfg(feature = "debug-origins")]
+        let origin = ::std::panic::Location::caller().to_string();
+
+        ids.into_iter()
+            .map(|id| {
+                let buffer = Buffer {
+                    container: self.container.clone(),
+                    _id: Rc::new(Cell::new(id)),
+                };
+                #[cfg(feature = "debug-origins")]
+                buffer.set_creation_origin(&origin);
+                buffer
+            })
+            .collect()
+    }
+}
+
+impl Clone for Font {
+    #[cfg_attr(debug_assertions, track_caller)]
+    fn clone(&self) -> Self {
+        let mut shared = lock_mut(&self.container, "Font::clone");
+        shared.inc_font(self.id);
+        Font {
+            id: self.id,
+            container: self.container.clone(),
+        }
+    }
+}
+
+impl PartialEq for Font {
+    fn eq(&self, other: &Font) -> bool {
+        self.id == other.id && Rc::ptr_eq(&self.container, &other.container)
+    }
+}
+
+impl Eq for Font {}
+
+impl Hash for Font {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+        (&*self.container as *const RefCell<shared::FontsContainer> as usize).hash(state);
+    }
+}
+
+impl fmt::Debug for Font {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut s = f.debug_struct("Font");
+        s.field("id", &self.id);
+        if let Ok(shared) = self.container.try_borrow() {
+            if let Some(data) = shared.get(self.id) {
+                s.field("count", &data.count);
+                s.field("name", &data.fk_font.full_name());
+            }
+        }
+        s.finish()
+    }
+}
+
+impl Drop for Font {
+    fn drop(&mut self) {
+        let mut shared = lock_mut(&self.container, "Font::drop");
+        shared.dec_font(self.id);
+    }
+}
+
+/// A font with pixel units at one size, see `Font::at_size`.
+///
+/// Glyph positions of its buffers are in 1/64 pixel, so `x_advance >> 6` is whole pixels.
+#[derive(Clone, PartialEq, Debug)]
+pub struct SizedFont {
+    font: Font,
+    px_size: f32,
+}
+
+impl SizedFont {
+    /// The sized font; its `units_per_em` is 64 times the pixel size.
+    pub fn font(&self) -> &Font {
+        &self.font
+    }
+
+    /// Pixel size after rounding to the `Fonts::set_size_bucketing` step.
+    pub fn px_size(&self) -> f32 {
+        self.px_size
+    }
+
+    /// Builder of buffers of the sized font with `size_px` set to its size.
+    pub fn buffer(&self) -> BufferBuilder {
+        self.font.buffer().size_px(self.px_size)
+    }
+
+    /// Creates a buffer with `size_px` set to the size of the font.
+    #[cfg_attr(any(feature = "debug-origins", debug_assertions), track_caller)]
+    pub fn create_buffer(&self, text: &str, transform: Option<na::Projective3<f32>>, color: na::Vector4<u8>) -> Buffer {
+        self.buffer().transform(transform).color(color).build_text(text)
+    }
+}
+
+/// Shaped text; the buffer data holds a reference to its font.
+pub struct Buffer {
+    container: Rc<RefCell<shared::FontsContainer>>,
+    /// Shared by the clones, so a copy-on-write split moves them together, see
+    /// `Fonts::set_buffer_interning`.
+    _id: Rc<Cell<usize>>,
+}
+
+impl Buffer {
+    #[cfg_attr(debug_assertions, track_caller)]
+    fn new<'a, P: Into<Cow<'a, str>>>(font: &Font, text: P, transform: Option<na::Projective3<f32>>, color: na::Vector4<u8>) -> Buffer {
+        let mut shared = lock_mut(&font.container, "Buffer::new");
+        let id = shared.create_buffer(font.id, text.into(), transform, color);
+
+        let buffer = Buffer {
+            container: font.container.clone(),
+            _id: Rc::new(Cell::new(id)),
+        };
+        shared.add_buffer_sharer(&buffer._id);
+        buffer
+    }
+
+    fn font_id(&self) -> usize {
+        lock(&self.container, "Buffer::font_id").buffer(self.id()).expect("font_id: buffer should exist").font_id()
+    }
+
+    /// Moves the other handles the intern table handed out for this buffer to a copy of it,
+    /// before changing it, see `Fonts::set_buffer_interning`.
+    fn unshare(&self) {
+        lock_mut(&self.container, "Buffer::unshare").unshare_buffer(&self._id);
+    }
+
+    /// Replaces the creation site shown by `Fonts::debug_dump` with a label.
+    #[cfg(feature = "debug-origins")]
+    pub fn set_debug_origin(&self, origin: &str) {
+        self.unshare();
+        lock_mut(&self.container, "Buffer::set_debug_origin").set_buffer_origin(self.id(), origin.to_string());
+    }
+
+    /// Records the creation site of a new buffer. A buffer handed out again by the intern
+    /// table keeps the site it was first created at and stays shared.
+    #[cfg(feature = "debug-origins")]
+    fn set_creation_origin(&self, origin: &str) {
+        lock_mut(&self.container, "Buffer::set_creation_origin").set_new_buffer_origin(self.id(), origin);
+    }
+
+    /// Borrows the container after shaping this buffer if shaping was deferred.
+    fn shaped(&self, api: &str) -> Shared {
+        lock_mut(&self.container, api).ensure_buffer_shaped(self.id());
+        lock(&self.container, api)
+    }
+
+    /// Mutably borrows the container after shaping this buffer if shaping was deferred.
+    fn shaped_mut(&self, api: &str) -> SharedMut {
+        let mut shared = lock_mut(&self.container, api);
+        shared.ensure_buffer_shaped(self.id());
+        shared
+    }
+
+    pub fn weak_ref(&self) -> BufferRef {
+        BufferRef {
+            _font_id: self.font_id(),
+            _id: self.id(),
+            _container: lock(&self.container, "Buffer::weak_ref").token(),
+        }
+    }
+
+    /// Font of the buffer, the font of the first span for spanned buffers.
+    ///
+    /// This is an owned handle rather than a `&Font`, since `set_font` can change the font
+    /// of the buffer and of all its clones.
+    pub fn font(&self) -> Font {
+        let mut shared = lock_mut(&self.container, "Buffer::font");
+        let font_id = shared.buffer(self.id()).expect("font: buffer should exist").font_id();
+        shared.inc_font(font_id);
+
+        Font {
+            id: font_id,
+            container: self.container.clone(),
+        }
+    }
+
+    /// Size of a span relative to the buffer size, 1 unless set with `SpannedText::push_sized`
+    /// or `SpannedText::push_adjusted`.
+    pub fn span_scale(&self, span: u32) -> f32 {
+        lock(&self.container, "Buffer::span_scale").buffer(self.id()).expect("span_scale: buffer should exist").span_scale(span)
+    }
+
+    /// Ascent, descent and line gap in pixels of each span at `px_size`, or the buffer size
+    /// when `None`; lines are as tall as the tallest span. The ascent and descent include the
+    /// baseline shift of the span.
+    pub fn span_metrics<P: Into<Option<f32>>>(&self, px_size: P) -> Vec<SpanMetrics> {
+        let shared = lock(&self.container, "Buffer::span_metrics");
+        let px_size = px_size.into().unwrap_or_else(|| shared.buffer_size_px(self.id()));
+        shared.span_metrics(self.id(), px_size)
+    }
+
+    /// Font a span was shaped with, the buffer font for single-font buffers.
+    pub fn span_font(&self, span: u32) -> Font {
+        let mut shared = lock_mut(&self.container, "Buffer::span_font");
+        let font_id = shared.buffer(self.id()).expect("span_font: buffer should exist").span_font_id(span);
+        shared.inc_font(font_id);
+
+        Font {
+            id: font_id,
+            container: self.container.clone(),
+        }
+    }
+
+    /// Shapes the text with a different font, keeping the buffer id and settings.
+    ///
+    /// Does nothing if the buffer already uses this font. Spans are dropped, the whole text
+    /// is shaped with `font`, and `FontsDiagnostic::SpansDropped` is reported.
+    pub fn set_font(&self, font: &Font) {
+        self.reshape_with(ShapeParams { font: Some(font.clone()), ..ShapeParams::default() });
+    }
+
+    /// Changes several shaping parameters and reshapes once, with one version change.
+    ///
+    /// Whitespace applies first, so ranged features refer to the new shaped text. A new font
+    /// drops the spans like `set_font`.
+    pub fn reshape_with(&self, params: ShapeParams) {
+        if let Some(ref font) = params.font {
+            assert!(Rc::ptr_eq(&self.container, &font.container), "reshape_with: font belongs to a different Fonts container");
+        }
+        let font_id = params.font.as_ref().map(|font| font.id);
+        self.unshare();
+        lock_mut(&self.container, "Buffer::reshape_with").reshape_buffer(self.id(), font_id, params.features, params.whitespace, params.base_direction);
+    }
+
+    /// Appends the glyphs in visual order, left to right: harfbuzz outputs the glyphs of
+    /// right-to-left text reversed, so their byte offsets decrease. See `glyphs_logical`.
+    pub fn glyphs(&self, output: &mut Vec<GlyphPosition>) {
+        let shared = self.shaped("Buffer::glyphs");
+        shared.buffer_glyphs(self.id(), output)
+    }
+
+    /// Appends the glyphs in logical order, by byte offset, and returns the index in `glyphs`
+    /// order of each appended glyph. Glyphs of one cluster keep their order.
+    pub fn glyphs_logical(&self, output: &mut Vec<GlyphPosition>) -> Vec<usize> {
+        let shared = self.shaped("Buffer::glyphs_logical");
+        let glyphs = shared.buffer(self.id()).expect("glyphs_logical: buffer should exist").glyphs();
+        let mut visual_indices = (0..glyphs.len()).collect::<Vec<_>>();
+        visual_indices.sort_by_key(|&i| glyphs[i].byte_offset);
+        output.extend(visual_indices.iter().map(|&i| glyphs[i]));
+        visual_indices
+    }
+
+    /// Terminal grid columns of each cluster in text order, for a monospace font advancing
+    /// `cell_width` font units per cell, see `Font::advance_histogram`. Clusters take two
+    /// columns if they have a wide East Asian char or advance more than one and a half cells.
+    pub fn cells(&self, cell_width: i32) -> Vec<GridCell> {
+        let mut glyphs = Vec::new();
+        self.glyphs_logical(&mut glyphs);
+
+        let mut clusters: Vec<(::std::ops::Range<usize>, i32)> = Vec::new();
+        for glyph in &glyphs {
+            let range = glyph.byte_offset as usize..(glyph.byte_offset + glyph.len) as usize;
+            if let Some(last) = clusters.last_mut() {
+                if last.0 == range {
+                    last.1 += glyph.x_advance;
+                    continue;
+                }
+            }
+            clusters.push((range, glyph.x_advance));
+        }
+        self.with_text(|text| cells::grid_cells(text, clusters, cell_width))
+    }
+
+    /// Glyph `index` in glyph order, `None` past the last glyph.
+    pub fn glyph_at(&self, index: usize) -> Option<GlyphPosition> {
+        self.shaped("Buffer::glyph_at").buffer(self.id()).expect("glyph_at: buffer should exist").glyphs().get(index).cloned()
+    }
+
+    /// Pen position in pixels before glyph `index`, as `glyphs_scaled` places it, or after the
+    /// last glyph for an `index` equal to the glyph count. `None` past that.
+    ///
+    /// Pen positions are computed for all glyphs on the first call after shaping or a size,
+    /// rounding or scale factor change, then looked up.
+    pub fn pen_position_at<P: Into<Option<f32>>>(&self, index: usize, px_size: P) -> Option<(f32, f32)> {
+        let px_size = self.resolve_size_px(px_size);
+        self.shaped("Buffer::pen_position_at").buffer_pen_position_at(self.id(), index, px_size)
+    }
+
+    /// Glyph meshes for this buffer scaled to `px_size`, each distinct glyph tessellated once.
+    ///
+    /// Glyphs without an outline, such as spaces, get no instance.
+    #[cfg(feature = "tessellation")]
+    pub fn tessellate<P: Into<Option<f32>>>(&self, px_size: P) -> Result<BufferMesh, GlyphMeshError> {
+        let px_size = self.resolve_size_px(px_size);
+        self.shaped_mut("Buffer::tessellate").tessellate_buffer(self.id(), px_size)
+    }
+
+    /// Stroke meshes for this buffer placed like `tessellate`, `stroke_width_px` is in pixels at `px_size`.
+    #[cfg(feature = "tessellation")]
+    pub fn tessellate_stroke<P: Into<Option<f32>>>(&self, px_size: P, stroke_width_px: f32) -> Result<BufferMesh, GlyphMeshError> {
+        let px_size = self.resolve_size_px(px_size);
+        self.shaped_mut("Buffer::tessellate_stroke").tessellate_buffer_stroke(self.id(), px_size, stroke_width_px)
+    }
+
+    /// Appends one instance per visible glyph to `output`, placed like `glyphs_scaled` at
+    /// `px_size`, or the buffer size when `None`. Glyphs missing from `store` are added as
+    /// `representation`, so that every unique glyph is uploaded once; glyphs without geometry,
+    /// such as spaces or failed outlines, get no instance.
+    pub fn geometry_instances<P: Into<Option<f32>>>(&self, px_size: P, representation: GlyphRepresentation, store: &GeometryStore, output: &mut Vec<GeometryInstance>) {
+        let px_size = self.resolve_size_px(px_size);
+        let mut glyphs: Vec<ScaledGlyph> = Vec::with_capacity(self.glyph_count());
+        self.glyphs_scaled(px_size, None, &mut glyphs);
+        let scale_factor = lock(&self.container, "Buffer::geometry_instances").scale_factor();
+        let mut fonts: Vec<Option<(Font, FontRef, f32)>> = Vec::new();
+
+        for glyph in &glyphs {
+            let span = glyph.span as usize;
+            if fonts.len() <= span {
+                fonts.resize(span + 1, None);
+            }
+            if fonts[span].is_none() {
+                let font = self.span_font(glyph.span);
+                let font_ref = font.weak_ref();
+                fonts[span] = Some((font, font_ref, px_size * self.span_scale(glyph.span)));
+            }
+            let (ref font, font_ref, glyph_px) = *fonts[span].as_ref().expect("geometry_instances: span font was just resolved");
+
+            let size = font.size_bucket(glyph_px * scale_factor);
+            let key = GeometryKey { font: font_ref, glyph_id: glyph.id, size, representation };
+            let bucket_px = size.px_size() / scale_factor;
+            let geometry = store.get_or_insert(key, || match representation {
+                #[cfg(feature = "tessellation")]
+                GlyphRepresentation::Mesh => {
+                    // half a pixel of error is not visible after antialiasing
+                    let tolerance = 0.5 * font.units_per_em() as f32 / bucket_px;
+                    font.tessellate_glyph(glyph.id, HintingOptions::None, tolerance).ok()
+                        .filter(|mesh| !mesh.is_empty())
+                        .map(GlyphGeometry::Mesh)
+                }
+                GlyphRepresentation::Sdf { padding, spread } => font.glyph_sdf(glyph.id, bucket_px, padding, spread as f32).map(GlyphGeometry::Sdf),
+                GlyphRepresentation::Bitmap => font.rasterize_glyph(glyph.id, bucket_px, HintingOptions::None, (0.0, 0.0))
+                    .map(|bitmap| GlyphGeometry::Bitmap(Rc::new(bitmap))),
+            });
+
+            if let Some(geometry) = geometry {
+                let scale = match representation {
+                    #[cfg(feature = "tessellation")]
+                    GlyphRepresentation::Mesh => glyph_px / font.units_per_em() as f32,
+                    _ => glyph_px / bucket_px / scale_factor,
+                };
+                output.push(GeometryInstance { geometry, offset: [glyph.x, glyph.y], scale });
+            }
+        }
+    }
+
+    /// Appends glyph positions scaled to `px_size` to `output`.
+    ///
+    /// With `subpixel_phases`, `x` is rounded down to a whole pixel and the remainder is
+    /// rounded to the nearest of that many phases, to pick a pre-shifted raster variant
+    /// such as `GlyphAtlas::get_with_phase`. Otherwise positions are exact and phase is `0`.
+    /// Rounding uses physical pixels, see `Fonts::set_scale_factor`.
+    pub fn glyphs_scaled<P: Into<Option<f32>>>(&self, px_size: P, subpixel_phases: Option<u32>, output: &mut Vec<ScaledGlyph>) {
+        let px_size = self.resolve_size_px(px_size);
+        self.shaped("Buffer::glyphs_scaled").buffer_glyphs_scaled(self.id(), px_size, subpixel_phases, output)
+    }
+
+    /// Appends glyphs scaled to `px_size` in a layout that can be copied to a GPU buffer directly.
+    pub fn glyphs_packed<P: Into<Option<f32>>>(&self, px_size: P, output: &mut Vec<PackedGlyph>) {
+        let px_size = self.resolve_size_px(px_size);
+        self.shaped("Buffer::glyphs_packed").buffer_glyphs_packed(self.id(), px_size, output)
+    }
+
+    /// Ligature and mark properties of glyph `glyph_index`, `None` past the last glyph.
+    ///
+    /// Glyph classes come from the `GDEF` table of the glyph font. Without one, a glyph alone
+    /// in a cluster of several graphemes is taken for a ligature of them, and glyphs without
+    /// advance after the first of their cluster for marks. Computed once per shaping.
+    pub fn glyph_info(&self, glyph_index: usize) -> Option<GlyphInfo> {
+        self.shaped("Buffer::glyph_info").buffer_glyph_info(self.id(), glyph_index)
+    }
+
+    /// Appends the glyphs of clusters overlapping `byte_range` to `output`.
+    ///
+    /// Clusters that straddle the range edges are included and flagged in the result,
+    /// which also holds the pen position of the first glyph so the slice can be
+    /// placed where it would be in the full buffer.
+    pub fn glyphs_in_range(&self, byte_range: ::std::ops::Range<usize>, output: &mut Vec<GlyphPosition>) -> GlyphRangeInfo {
+        let shared = self.shaped("Buffer::glyphs_in_range");
+        shared.buffer_glyphs_in_range(self.id(), byte_range, output)
+    }
+
+    /// Byte range of the whole cluster glyph `glyph_index` belongs to.
+    ///
+    /// All glyphs of a ligature or of a character expanded into several glyphs get the
+    /// same range, also in right-to-left text where glyphs are in visual order.
+    pub fn cluster_range(&self, glyph_index: usize) -> ::std::ops::Range<usize> {
+        self.shaped("Buffer::cluster_range").buffer_cluster_range(self.id(), glyph_index)
+    }
+
+    /// Appends the byte range and the summed advance of each cluster to `output`, in text
+    /// order also for right-to-left text, such as to align columns at a character.
+    ///
+    /// Marks and the other glyphs of a cluster add to its advance, so the advances add up to
+    /// `advance_width`.
+    pub fn cluster_advances(&self, output: &mut Vec<(::std::ops::Range<usize>, i32)>) {
+        self.shaped("Buffer::cluster_advances").buffer(self.id()).expect("cluster_advances: buffer should exist").cluster_advances(output);
+    }
+
+    /// Glyph indices from the first to the last glyph in output order whose cluster overlaps
+    /// `byte_range`, empty if none does.
+    ///
+    /// Mixed direction text can put glyphs of other clusters between them.
+    pub fn glyph_range_for_bytes(&self, byte_range: ::std::ops::Range<usize>) -> ::std::ops::Range<usize> {
+        self.shaped("Buffer::glyph_range_for_bytes").buffer_glyph_range_for_bytes(self.id(), byte_range)
+    }
+
+    /// Calls `f` for every shaped glyph without copying the glyph output.
+    ///
+    /// The fonts container is borrowed while `f` runs, so `f` must not create,
+    /// clone, drop or modify buffers and fonts; doing so panics.
+    pub fn for_each_glyph<F: FnMut(&GlyphPosition)>(&self, mut f: F) {
+        let shared = self.shaped("Buffer::for_each_glyph");
+        for glyph in shared.buffer_glyph_slice(self.id()) {
+            f(glyph);
+        }
+    }
+
+    /// Passes a harfbuzz glyph buffer of the text to `f`, for data the wrapper drops such
+    /// as the glyph infos and the serialized output.
+    ///
+    /// Shaped outputs are not kept after shaping, so this shapes the whole shaped text again in
+    /// one call with the buffer font and features. Unlike `glyphs`, the text is not split into
+    /// paragraphs and direction runs, harfbuzz guesses one direction for all of it, and span and
+    /// fallback fonts and synthetic advances are not applied, so the output only matches `glyphs`
+    /// for single direction text in one font. The container is borrowed like in
+    /// `for_each_glyph`, and the glyph buffer must not outlive `f`. Not available with the
+    /// `simple-shaping` feature.
+    #[cfg(not(feature = "simple-shaping"))]
+    pub fn with_raw_glyph_buffer<R, F: FnOnce(&hb::GlyphBuffer) -> R>(&self, f: F) -> R {
+        let shared = lock(&self.container, "Buffer::with_raw_glyph_buffer");
+        shared.with_buffer_raw_glyph_buffer(self.id(), f)
+    }
+
+    /// Passes the shaped glyph output to `f` without copying it.
+    ///
+    /// Same borrowing rules as `for_each_glyph` apply.
+    pub fn with_glyphs<R, F: FnOnce(&[GlyphPosition]) -> R>(&self, f: F) -> R {
+        let shared = self.shaped("Buffer::with_glyphs");
+        f(shared.buffer_glyph_slice(self.id()))
+    }
+
+    pub fn glyph_count(&self) -> usize {
+        self.shaped("Buffer::glyph_count").buffer_glyph_slice(self.id()).len()
+    }
+
+    /// Appends the flags of every glyph to `output`.
+    pub fn glyph_flags(&self, output: &mut Vec<GlyphFlags>) {
+        output.extend(self.shaped("Buffer::glyph_flags").buffer_glyph_slice(self.id()).iter().map(|g| g.flags));
+    }
+
+    /// The text can be split before glyph `glyph_index` and reshaped in parts with the same result.
+    ///
+    /// Always true at the start and the end of the glyphs.
+    pub fn safe_break_before(&self, glyph_index: usize) -> bool {
+        self.shaped("Buffer::safe_break_before").buffer_glyph_slice(self.id()).get(glyph_index)
+            .map_or(true, |g| glyph_index == 0 || !g.flags.unsafe_to_break())
+    }
+
+    /// Copy of the text the buffer was last shaped with, empty once dropped, see `retain_text`.
+    pub fn text(&self) -> String {
+        self.with_text(|text| text.to_string())
+    }
+
+    /// Copy of the text, `None` once dropped after shaping, see `retain_text`.
+    pub fn try_text(&self) -> Option<String> {
+        if self.has_text() { Some(self.text()) } else { None }
+    }
+
+    /// Whether to keep the text after shaping, true by default.
+    ///
+    /// Without it, the text is freed once shaped and glyphs, carets, measurement and rendering
+    /// keep working from the shaped output. `try_text` and `hit_test_x` return `None`,
+    /// `text` and `shaped_text` are empty, layouts do not see whitespace or separators and
+    /// `measure_trimmed` trims nothing. Changing the font, features or whitespace, splicing,
+    /// appending and splitting lines need the text and do nothing; `set_text` shapes a new
+    /// text, which is freed again. Keeping the text again takes effect from the next `set_text`.
+    pub fn retain_text(&self, retain: bool) {
+        self.unshare();
+        lock_mut(&self.container, "Buffer::retain_text").set_buffer_retain_text(self.id(), retain);
+    }
+
+    /// The text is available, it was not dropped after shaping.
+    pub fn has_text(&self) -> bool {
+        lock(&self.container, "Buffer::has_text").buffer(self.id()).expect("has_text: buffer should exist").has_text()
+    }
+
+    /// Passes the buffer text to `f` without copying it.
+    ///
+    /// The container is only borrowed for reading, so `f` may read glyphs of this or other buffers.
+    pub fn with_text<R, F: FnOnce(&str) -> R>(&self, f: F) -> R {
+        let shared = lock(&self.container, "Buffer::with_text");
+        f(shared.buffer(self.id()).expect("with_text: buffer should exist").text())
+    }
+
+    /// Attaches application data such as a GPU handle, replacing any previous data. It is
+    /// dropped with the buffer data, so there is no side table to clean up.
+    ///
+    /// Clones of the buffer share the slot since they share the buffer data; an interned
+    /// buffer gets a private copy first. Data dropped with the buffer is dropped while the
+    /// `Fonts` container is borrowed, so its `Drop` must not use fonts or buffers.
+    pub fn set_user_data(&self, data: Box<dyn Any>) {
+        self.unshare();
+        let _previous = lock_mut(&self.container, "Buffer::set_user_data").set_buffer_user_data(self.id(), Some(data));
+    }
+
+    /// Clone of the user data if it is a `T`.
+    pub fn user_data<T: Any + Clone>(&self) -> Option<T> {
+        self.with_user_data(T::clone)
+    }
+
+    /// Passes the user data to `f` if it is a `T`, without copying it.
+    pub fn with_user_data<T: Any, R, F: FnOnce(&T) -> R>(&self, f: F) -> Option<R> {
+        let shared = lock(&self.container, "Buffer::with_user_data");
+        shared.buffer(self.id()).expect("with_user_data: buffer should exist").user_data()?.downcast_ref::<T>().map(f)
+    }
+
+    /// Removes the user data from the slot shared by the clones and returns it.
+    pub fn take_user_data(&self) -> Option<Box<dyn Any>> {
+        self.unshare();
+        lock_mut(&self.container, "Buffer::take_user_data").set_buffer_user_data(self.id(), None)
+    }
+
+    /// Length of the text in bytes.
+    pub fn text_len(&self) -> usize {
+        self.with_text(|text| text.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.glyph_count() == 0
+    }
+
+    pub fn id(&self) -> usize {
+        self._id.get()
+    }
+
+    /// Buffer transform composed with the parent: the local transform is applied first,
+    /// then the parent one, `parent * local`, like everywhere else in the scene graph.
+    pub fn absolute_transform(&self, parent_absolute_transform: &na::Projective3<f32>) -> Option<na::Projective3<f32>> {
+        let shared = lock(&self.container, "Buffer::absolute_transform");
+        shared.get_buffer_transform(self.id()).map(|bt| compose_transform(parent_absolute_transform, &bt))
+    }
+
+    /// Transform in logical pixels; the scale factor is not part of it, so the projection
+    /// should map logical pixels to the framebuffer, see `Fonts::set_scale_factor`.
+    pub fn transform(&self) -> Option<na::Projective3<f32>> {
+        let shared = lock(&self.container, "Buffer::transform");
+        shared.get_buffer_transform(self.id())
+    }
+
+    pub fn color(&self) -> na::Vector4<u8> {
+        let shared = lock(&self.container, "Buffer::color");
+        shared.get_buffer_color(self.id())
+    }
+
+    /// Changes whenever the shaped glyphs change, not when the transform changes.
+    pub fn version(&self) -> u64 {
+        lock(&self.container, "Buffer::version").buffer(self.id()).expect("version: buffer should exist").version()
+    }
+
+    /// Changes whenever the transform or the size changes.
+    pub fn transform_version(&self) -> u64 {
+        lock(&self.container, "Buffer::transform_version").buffer(self.id()).expect("transform_version: buffer should exist").transform_version()
+    }
+
+    /// Changes whenever the highlights change, not when the glyphs do.
+    pub fn highlight_version(&self) -> u64 {
+        lock(&self.container, "Buffer::highlight_version").buffer(self.id()).expect("highlight_version: buffer should exist").highlight_version()
+    }
+
+    /// Y direction of the positions of this buffer, see `Fonts::set_origin`.
+    pub fn origin(&self) -> Origin {
+        lock(&self.container, "Buffer::origin").origin()
+    }
+
+    /// The glyphs were given to `Fonts::create_preshaped_buffer` and not shaped since.
+    pub fn is_preshaped(&self) -> bool {
+        lock(&self.container, "Buffer::is_preshaped").buffer(self.id()).expect("is_preshaped: buffer should exist").is_preshaped()
+    }
+
+    /// Pixels per em used by the methods taking a `px_size` when they get `None`.
+    ///
+    /// Glyphs are scaled when read, so this does not reshape; `transform_version` changes.
+    pub fn set_size_px(&self, px_size: f32) {
+        self.unshare();
+        lock_mut(&self.container, "Buffer::set_size_px").set_buffer_size_px(self.id(), px_size);
+    }
+
+    /// Size set with `set_size_px`, or the default of `Fonts::set_default_size`.
+    pub fn size_px(&self) -> f32 {
+        lock(&self.container, "Buffer::size_px").buffer_size_px(self.id())
+    }
+
+    /// Adds the tracking of the AAT `trak` table of the fonts at `size_px` to glyph advances,
+    /// the default. Points are taken as pixels like for hinting.
+    ///
+    /// Fonts without the table are unaffected. With the table, changing this or the size
+    /// reshapes the text, unless it was dropped after shaping.
+    pub fn set_optical_tracking(&self, enabled: bool) {
+        self.unshare();
+        lock_mut(&self.container, "Buffer::set_optical_tracking").set_buffer_optical_tracking(self.id(), enabled);
+    }
+
+    pub fn optical_tracking(&self) -> bool {
+        lock(&self.container, "Buffer::optical_tracking").buffer(self.id()).expect("optical_tracking: buffer should exist").optical_tracking
+    }
+
+    fn resolve_size_px<P: Into<Option<f32>>>(&self, px_size: P) -> f32 {
+        px_size.into().unwrap_or_else(|| self.size_px())
+    }
+
+    pub fn set_transform(&self, transform: Option<na::Projective3<f32>>) {
+        self.unshare();
+        lock_mut(&self.container, "Buffer::set_transform").set_buffer_transform(self.id(), transform);
+    }
+
+    /// Moves the buffer in the parent space, after the current transform.
+    pub fn translate(&self, x: f32, y: f32) {
+        let translation = na::convert::<_, na::Projective3<f32>>(na::Translation3::new(x, y, 0.0));
+        let transform = self.transform().unwrap_or_else(na::Projective3::identity);
+        self.set_transform(Some(translation * transform));
+    }
+
+    /// Scales the glyphs around the buffer origin, before the current transform.
+    pub fn scale(&self, scale: f32) {
+        let scaling = na::convert::<_, na::Projective3<f32>>(na::Similarity3::new(na::zero(), na::zero(), scale));
+        let transform = self.transform().unwrap_or_else(na::Projective3::identity);
+        self.set_transform(Some(transform * scaling));
+    }
+
+    /// Slants the glyphs by `degrees` from vertical, positive to the right, before the current
+    /// transform. The baseline stays in place: glyph positions are relative to the baseline at
+    /// the buffer origin, so the shear needs no ascent offset.
+    pub fn set_oblique(&self, degrees: f32) {
+        let mut shear = na::Matrix4::identity();
+        // a y down origin flips y, the slant looks the same
+        shear[(0, 1)] = self.origin().y(degrees.to_radians().tan());
+        let transform = self.transform().unwrap_or_else(na::Projective3::identity);
+        self.set_transform(Some(transform * na::Projective3::from_matrix_unchecked(shear)));
+    }
+
+    /// Rotates the glyphs counter-clockwise about the start of the baseline, before the current
+    /// transform.
+    pub fn rotate_about_baseline(&self, radians: f32) {
+        let angle = na::Vector3::z() * self.origin().y(radians);
+        let rotation = na::convert::<_, na::Projective3<f32>>(na::Isometry3::new(na::zero(), angle));
+        let transform = self.transform().unwrap_or_else(na::Projective3::identity);
+        self.set_transform(Some(transform * rotation));
+    }
+
+    /// Removes the transform, as `set_transform(None)`.
+    pub fn reset_transform(&self) {
+        self.set_transform(None);
+    }
+
+    pub fn measure(&self, glyphs: &mut Vec<GlyphPosition>) -> Option<Measurement> {
+        self.measure_with(MeasureOptions::default(), glyphs)
+    }
+
+    /// `measure` with the width leaving out whitespace at the ends of the text as `options`
+    /// selects. The glyphs are all output, hit testing and carets are not affected.
+    pub fn measure_with(&self, options: MeasureOptions, glyphs: &mut Vec<GlyphPosition>) -> Option<Measurement> {
+        self.shaped("Buffer::measure_with").measure(self.id(), options, glyphs)
+    }
+
+    /// Width in pixels at `px_size` without whitespace at the start and end of the text, such
+    /// as for centering a label. Whitespace only text is zero wide.
+    ///
+    /// The ends are those of the text, so in right-to-left text the trailing whitespace
+    /// trimmed is on the left.
+    pub fn measure_trimmed<P: Into<Option<f32>>>(&self, px_size: P) -> f32 {
+        let px_size = self.resolve_size_px(px_size);
+        let options = MeasureOptions { trim_leading: true, trim_trailing: true };
+        let shared = self.shaped("Buffer::measure_trimmed");
+        if shared.rounding() != RoundingMode::None {
+            return shared.buffer_trimmed_width_px(self.id(), px_size);
+        }
+        shared.measure(self.id(), options, &mut Vec::new())
+            .map_or(0.0, |measurement| measurement.width * shared.buffer_scale(self.id(), px_size))
+    }
+
+    /// Width of the text on a single line in font units, without copying glyphs.
+    ///
+    /// Sums the glyph advances, so adjustments applied to them are included. The result is
+    /// memoized until the buffer is reshaped.
+    pub fn advance_width(&self) -> i32 {
+        self.shaped("Buffer::advance_width").buffer(self.id()).expect("advance_width: buffer should exist").advance_width()
+    }
+
+    /// `advance_width` in pixels at `px_size`.
+    pub fn advance_width_px<P: Into<Option<f32>>>(&self, px_size: P) -> f32 {
+        let px_size = self.resolve_size_px(px_size);
+        self.shaped("Buffer::advance_width_px").buffer_advance_width_px(self.id(), px_size)
+    }
+
+    /// Appends text, reshaping only the tail of the previous text that can be affected.
+    pub fn append_text(&self, text: &str) {
+        self.unshare();
+        lock_mut(&self.container, "Buffer::append_text").append_buffer_text(self.id(), text);
+    }
+
+    /// Replaces `byte_range` of the text, reshaping only the glyphs around the edit.
+    ///
+    /// Panics if the range is out of bounds or not on char boundaries, like `String::replace_range`.
+    pub fn splice(&self, byte_range: ::std::ops::Range<usize>, replacement: &str) {
+        self.unshare();
+        lock_mut(&self.container, "Buffer::splice").splice_buffer_text(self.id(), byte_range, replacement);
+    }
+
+    /// Shows the uncommitted composition of an input method at `byte_index` of the committed
+    /// text, replacing the previous one, with the caret `cursor_in_preedit` bytes into it.
+    ///
+    /// The preedit is part of `text` and shaped with it, reshaping only the glyphs around it,
+    /// and its glyphs have `GlyphFlags::is_preedit` for underlining. Committed text before it
+    /// keeps its byte offsets, see `caret_index` for those after it. To commit, `splice` the
+    /// `preedit_range` with the committed text; other edits overlapping the preedit drop it,
+    /// and so does any edit with collapsed whitespace. An empty preedit clears it.
+    ///
+    /// Panics if `byte_index` is past the committed text or not on a char boundary.
+    pub fn set_preedit(&self, byte_index: usize, preedit: &str, cursor_in_preedit: usize) {
+        self.unshare();
+        lock_mut(&self.container, "Buffer::set_preedit").set_buffer_preedit(self.id(), byte_index, preedit, cursor_in_preedit);
+    }
+
+    /// Shapes only the paragraphs around `byte_range` of the text, with a margin, such as for
+    /// a very large text in a scrolled view.
+    ///
+    /// Glyphs, measurement and layout then cover `shaped_window`, with clusters and byte
+    /// offsets still in the whole text. Moving the range reshapes when it nears the window
+    /// edges; paragraphs still in the shaping cache are not shaped again. Texts with spans
+    /// are shaped whole.
+    pub fn set_visible_range(&self, byte_range: ::std::ops::Range<usize>) {
+        self.unshare();
+        lock_mut(&self.container, "Buffer::set_visible_range").set_buffer_visible_range(self.id(), Some(byte_range));
+    }
+
+    /// Shapes the whole text again after `set_visible_range`.
+    pub fn clear_visible_range(&self) {
+        self.unshare();
+        lock_mut(&self.container, "Buffer::clear_visible_range").set_buffer_visible_range(self.id(), None);
+    }
+
+    /// Byte range of the text the glyphs were shaped from, `None` if it is the whole text.
+    pub fn shaped_window(&self) -> Option<::std::ops::Range<usize>> {
+        self.shaped("Buffer::shaped_window").buffer_shaped_window(self.id())
+    }
+
+    /// Removes the preedit text, leaving the committed text.
+    pub fn clear_preedit(&self) {
+        self.unshare();
+        lock_mut(&self.container, "Buffer::clear_preedit").clear_buffer_preedit(self.id());
+    }
+
+    /// Byte range of the preedit in `text`.
+    pub fn preedit_range(&self) -> Option<::std::ops::Range<usize>> {
+        lock(&self.container, "Buffer::preedit_range").buffer(self.id()).expect("preedit_range: buffer should exist")
+            .preedit().map(|preedit| preedit.range.clone())
+    }
+
+    /// Caret position in `text` while composing, inside or at the ends of the preedit.
+    pub fn preedit_cursor(&self) -> Option<usize> {
+        lock(&self.container, "Buffer::preedit_cursor").buffer(self.id()).expect("preedit_cursor: buffer should exist")
+            .preedit().map(|preedit| preedit.range.start + preedit.cursor)
+    }
+
+    /// Position in `text` of the caret at `committed_index` of the committed text: the
+    /// preedit cursor where the preedit is, and moved past the preedit after it.
+    pub fn caret_index(&self, committed_index: usize) -> usize {
+        match self.preedit_range() {
+            Some(ref range) if committed_index == range.start => self.preedit_cursor().unwrap_or(committed_index),
+            Some(ref range) if committed_index > range.start => committed_index + range.len(),
+            _ => committed_index,
+        }
+    }
+
+    /// Text without the preedit.
+    pub fn committed_text(&self) -> String {
+        let mut text = self.text();
+        if let Some(range) = self.preedit_range() {
+            text.replace_range(range, "");
+        }
+        text
+    }
+
+    /// Creates one buffer per paragraph of the text, with the same font, spans, transform and color.
+    ///
+    /// The line buffers are shaped independently and do not follow later changes to this buffer.
+    pub fn split_lines(&self) -> Vec<Buffer> {
+        let ids = lock_mut(&self.container, "Buffer::split_lines").split_buffer_lines(self.id());
+        self.buffers_from_ids(ids)
+    }
+
+    /// Like `split_lines`, but text changes of this buffer re-split it: line buffers are updated
+    /// in place, and created or released when the number of paragraphs changes.
+    ///
+    /// Linked line buffers stay alive until `unlink_lines`; calling this again returns the current ones.
+    pub fn split_lines_linked(&self) -> Vec<Buffer> {
+        self.unshare();
+        let ids = {
+            let mut shared = lock_mut(&self.container, "Buffer::split_lines_linked");
+            let ids = shared.link_buffer_lines(self.id());
+            for &id in &ids {
+                shared.inc_buffer(id);
+            }
+            ids
+        };
+        self.buffers_from_ids(ids)
+    }
+
+    /// Stops updating the line buffers created by `split_lines_linked`.
+    pub fn unlink_lines(&self) {
+        self.unshare();
+        lock_mut(&self.container, "Buffer::unlink_lines").unlink_buffer_lines(self.id());
+    }
+
+    /// Paragraph index in the buffer this one was split from, `None` if it was not split.
+    pub fn line_index(&self) -> Option<usize> {
+        lock(&self.container, "Buffer::line_index").buffer(self.id()).expect("line_index: buffer should exist").line_index()
+    }
+
+    /// Handles for buffer ids that already hold a reference each.
+    fn buffers_from_ids(&self, ids: Vec<usize>) -> Vec<Buffer> {
+        ids.into_iter()
+            .map(|id| Buffer {
+                container: self.container.clone(),
+                _id: Rc::new(Cell::new(id)),
+            })
+            .collect()
+    }
+
+    /// Shapes the text as is, the default, or with whitespace collapsed like HTML.
+    ///
+    /// `text`, glyph byte offsets, cursor positions and `hit_test_x` keep referring to the text
+    /// as given. Glyph clusters, span and feature ranges and layouts refer to `shaped_text`;
+    /// `source_offset` maps them back.
+    pub fn set_whitespace(&self, whitespace: Whitespace) {
+        self.reshape_with(ShapeParams { whitespace: Some(whitespace), ..ShapeParams::default() });
+    }
+
+    pub fn whitespace(&self) -> Whitespace {
+        lock(&self.container, "Buffer::whitespace").buffer(self.id()).expect("whitespace: buffer should exist").whitespace()
+    }
+
+    /// Shapes the text mapped to upper, lower or title case with the rules of `locale`, a BCP 47
+    /// tag such as `tr` for Turkish dotted and dotless i, or as is with `Case::None`.
+    ///
+    /// Like with `set_whitespace`, `text` and glyph byte offsets keep referring to the text as
+    /// given, and `source_offset` maps offsets in `shaped_text` back to it.
+    pub fn set_case(&self, case: Case, locale: Option<&str>) {
+        self.unshare();
+        lock_mut(&self.container, "Buffer::set_case").set_buffer_case(self.id(), case, locale.map(str::to_string));
+    }
+
+    pub fn case(&self) -> Case {
+        lock(&self.container, "Buffer::case").buffer(self.id()).expect("case: buffer should exist").case()
+    }
+
+    /// Hazards in `text` that make shaping erratic: marks without a base, unpaired bidi
+    /// isolates and embeddings, private use characters, noncharacters and code points of
+    /// unassigned planes. In text order, with byte offsets in `text`.
+    pub fn validate(&self) -> Vec<TextWarning> {
+        self.with_text(validation::validate)
+    }
+
+    /// Shapes the text with a dotted circle before each mark without a base, unmatched PDF
+    /// and PDI removed and unclosed isolates and embeddings closed at the end of their
+    /// paragraph. Off by default.
+    ///
+    /// Like with `set_whitespace`, `text` and glyph byte offsets keep referring to the text as
+    /// given, and `source_offset` maps offsets in `shaped_text` back to it.
+    pub fn set_sanitize(&self, sanitize: bool) {
+        self.unshare();
+        lock_mut(&self.container, "Buffer::set_sanitize").set_buffer_sanitize(self.id(), sanitize);
+    }
+
+    pub fn sanitize(&self) -> bool {
+        lock(&self.container, "Buffer::sanitize").buffer(self.id()).expect("sanitize: buffer should exist").sanitize()
+    }
+
+    /// Shapes paragraphs in the given direction, or in that of their first strong character
+    /// with `BaseDirection::FirstStrong`, the default. The direction decides the order of runs
+    /// and of neutrals between them, and which side `Align::Start` lines start at.
+    pub fn set_base_direction(&self, base: BaseDirection) {
+        self.reshape_with(ShapeParams { base_direction: Some(base), ..ShapeParams::default() });
+    }
+
+    pub fn base_direction(&self) -> BaseDirection {
+        lock(&self.container, "Buffer::base_direction").buffer(self.id()).expect("base_direction: buffer should exist").base_direction()
+    }
+
+    /// Direction of the first paragraph, left to right for text without strong characters
+    /// unless forced with `set_base_direction`.
+    pub fn resolved_base_direction(&self) -> ShapedDirection {
+        let shared = lock(&self.container, "Buffer::resolved_base_direction");
+        let buffer = shared.buffer(self.id()).expect("resolved_base_direction: buffer should exist");
+        if bidi::paragraph_rtl(buffer.shaped_text(), 0, buffer.base_direction()) {
+            ShapedDirection::RightToLeft
+        } else {
+            ShapedDirection::LeftToRight
+        }
+    }
+
+    /// Tags byte ranges of the text, such as for syntax or search match colors; `visual_runs`
+    /// split at tag changes and glyphs and quads carry the tag of their cluster.
+    ///
+    /// A range ending inside a cluster tags the whole cluster, later ranges win where they
+    /// overlap. This does not reshape, `highlight_version` changes. Ranges are kept as they
+    /// are when the text changes.
+    pub fn set_highlights(&self, spans: &[(::std::ops::Range<usize>, u32)]) {
+        self.unshare();
+        lock_mut(&self.container, "Buffer::set_highlights").set_buffer_highlights(self.id(), spans);
+    }
+
+    pub fn highlights(&self) -> Vec<(::std::ops::Range<usize>, u32)> {
+        lock(&self.container, "Buffer::highlights").buffer(self.id()).expect("highlights: buffer should exist").highlights().to_vec()
+    }
+
+    /// Text passed to the shaper, differs from `text` with collapsed whitespace.
+    pub fn shaped_text(&self) -> String {
+        lock(&self.container, "Buffer::shaped_text").buffer(self.id()).expect("shaped_text: buffer should exist").shaped_text().to_string()
+    }
+
+    /// Byte offset in `text` of a byte offset in `shaped_text`, such as a glyph cluster.
+    pub fn source_offset(&self, shaped_offset: usize) -> usize {
+        lock(&self.container, "Buffer::source_offset").buffer(self.id()).expect("source_offset: buffer should exist").source_offset(shaped_offset)
+    }
+
+    /// Sets the OpenType features and reshapes the text.
+    ///
+    /// Ranged features outside the text are dropped with a diagnostic, here and on later text changes.
+    pub fn set_features(&self, features: &[Feature]) {
+        self.reshape_with(ShapeParams { features: Some(features.to_vec()), ..ShapeParams::default() });
+    }
+
+    /// Features of the buffer, with ranges snapped to grapheme boundaries.
+    pub fn features(&self) -> Vec<Feature> {
+        lock(&self.container, "Buffer::features").buffer(self.id()).expect("features: buffer should exist").features().to_vec()
+    }
+
+    /// Replaces the text and reshapes it with the same font, keeping the buffer id.
+    ///
+    /// Setting the same text again does nothing. An owned `String` is moved in without copying it.
+    pub fn set_text<'a, P: Into<Cow<'a, str>>>(&self, text: P) {
+        self.unshare();
+        lock_mut(&self.container, "Buffer::set_text").set_buffer_text(self.id(), text.into());
+    }
+
+    /// New buffer with the text of `other` after this text, shaped across the join so that
+    /// kerning and ligatures between the two apply.
+    ///
+    /// With different fonts each part keeps its font as a span. The transform, color and size
+    /// are those of this buffer, features of each part apply to its own text.
+    pub fn concat(&self, other: &Buffer) -> Buffer {
+        assert!(Rc::ptr_eq(&self.container, &other.container), "concat: buffers belong to different Fonts containers");
+        let id = lock_mut(&self.container, "Buffer::concat").concat_buffers(self.id(), other.id());
+
+        Buffer {
+            container: self.container.clone(),
+            _id: Rc::new(Cell::new(id)),
+        }
+    }
+
+    /// New buffer of the text of `byte_range` of `shaped_text` with the glyphs shaped here,
+    /// such as for a drag preview or a search result snippet. Letters at the ends keep the
+    /// forms they have in context, which shaping the fragment on its own would lose.
+    ///
+    /// The range is widened to the clusters it overlaps. Glyph advances and offsets are those
+    /// of this buffer, with clusters relative to the fragment. Like other preshaped buffers,
+    /// changing the text of the new buffer shapes it on its own, losing the contextual forms.
+    /// Without the text, see `retain_text`, the new buffer is empty.
+    pub fn extract_range(&self, byte_range: ::std::ops::Range<usize>) -> Buffer {
+        let id = lock_mut(&self.container, "Buffer::extract_range").extract_buffer_range(self.id(), byte_range);
+
+        Buffer {
+            container: self.container.clone(),
+            _id: Rc::new(Cell::new(id)),
+        }
+    }
+
+    /// Replaces the text like `set_text` and returns the glyphs that changed, so small edits
+    /// such as a counter can update only part of a mesh.
+    ///
+    /// Kept glyphs after the changed range have the same positions, and their clusters move by
+    /// the change of the text length. Unrelated texts give all glyphs as changed.
+    pub fn set_text_diff(&self, text: &str) -> GlyphDiff {
+        self.unshare();
+        lock_mut(&self.container, "Buffer::set_text_diff").set_buffer_text_diff(self.id(), text)
+    }
+
+    /// Lays out the shaped text; words broken by `LayoutOptions::hyphenator` are reshaped
+    /// in two parts with a hyphen glyph added to the first.
+    pub fn layout(&self, options: &LayoutOptions) -> TextLayout {
+        self.shaped("Buffer::layout").layout(self.id(), options)
+    }
+
+    /// Places the glyphs along the first contour of `path`, in pixels at `px_size`. Each glyph
+    /// sits at its pen distance along the path, rotated to the direction under its center.
+    ///
+    /// Clusters are kept for hit testing; `PlacedGlyph::distance` maps a point on the path
+    /// back to a glyph.
+    pub fn layout_on_path(&self, path: &::lyon_path::default::Path, px_size: f32, options: &PathLayoutOptions) -> Vec<PlacedGlyph> {
+        self.shaped("Buffer::layout_on_path").buffer_layout_on_path(self.id(), path, px_size, options)
+    }
+
+    /// Overlay paths of the single line layout at `px_size`, or the buffer size when `None`,
+    /// see `TextLayout::debug_paths`.
+    pub fn debug_paths<P: Into<Option<f32>>>(&self, px_size: P) -> DebugPaths {
+        self.layout(&LayoutOptions { px_size: px_size.into().unwrap_or(0.0), ..LayoutOptions::default() }).debug_paths()
+    }
+
+    /// Hit tests the first line of the text laid out without wrapping, `x` in pixels.
+    ///
+    /// The byte range of the result is in the text as given, also with collapsed whitespace.
+    /// `None` if the text was dropped, see `retain_text`.
+    pub fn hit_test_x<P: Into<Option<f32>>>(&self, x: f32, px_size: P) -> Option<HitResult> {
+        if !self.has_text() {
+            return None;
+        }
+        let mut hit = self.layout(&LayoutOptions { px_size: px_size.into().unwrap_or(0.0), ..LayoutOptions::default() })
+            .hit_test_line(0, x);
+        let range = lock(&self.container, "Buffer::hit_test_x").buffer_source_range(self.id(), hit.byte_index..hit.byte_index + hit.cluster_len);
+        hit.byte_index = range.start;
+        hit.cluster_len = range.end - range.start;
+        Some(hit)
+    }
+
+    /// Next caret position after `byte_index`, moving over whole graphemes.
+    pub fn next_cursor(&self, byte_index: usize) -> usize {
+        let shared = self.shaped("Buffer::next_cursor");
+        let positions = shared.buffer_cursor_positions(self.id());
+        positions.iter().cloned()
+            .find(|p| *p > byte_index)
+            .unwrap_or_else(|| *positions.last().expect("next_cursor: there is always an end position"))
+    }
+
+    /// Previous caret position before `byte_index`, moving over whole graphemes.
+    pub fn prev_cursor(&self, byte_index: usize) -> usize {
+        let shared = self.shaped("Buffer::prev_cursor");
+        shared.buffer_cursor_positions(self.id()).iter().cloned()
+            .rev()
+            .find(|p| *p < byte_index)
+            .unwrap_or(0)
+    }
+
+    /// All valid caret positions in the text, in logical order, including start and end.
+    pub fn cursor_positions(&self) -> impl Iterator<Item=usize> {
+        let shared = self.shaped("Buffer::cursor_positions");
+        shared.buffer_cursor_positions(self.id()).into_iter()
+    }
+
+    /// UAX #29 word segments of `text()` in logical order, covering it without gaps.
+    ///
+    /// Runs of whitespace and runs of punctuation are one segment each, so selecting the
+    /// segment under a comma selects just the comma.
+    pub fn word_boundaries(&self) -> impl Iterator<Item=::std::ops::Range<usize>> {
+        self.with_text(words::word_segments).into_iter()
+    }
+
+    /// Word segment containing `byte_index` for double click selection, the last segment at
+    /// the end of the text. `None` for an empty text or an index past its end.
+    pub fn word_at(&self, byte_index: usize) -> Option<::std::ops::Range<usize>> {
+        self.with_text(|text| words::word_at(text, byte_index))
+    }
+
+    /// Direction, script and language the text was shaped with, after harfbuzz guessed the
+    /// ones not set. With mixed direction text these are of the first run in text order, see
+    /// `resolved_runs`.
+    pub fn resolved_properties(&self) -> ResolvedShape {
+        resolved_shape::first_in_text_order(&self.resolved_runs())
+    }
+
+    /// Byte range and resolved properties of each shaped run, in visual order. Each direction
+    /// run is shaped separately and gets its own script guess.
+    pub fn resolved_runs(&self) -> Vec<(::std::ops::Range<usize>, ResolvedShape)> {
+        let shared = self.shaped("Buffer::resolved_runs");
+        shared.buffer(self.id()).expect("resolved_runs: buffer should exist").resolved_runs()
+    }
+
+    /// Glyphs grouped into runs of one font, direction, script and highlight, left to right also in
+    /// mixed direction text, for renderers that batch glyphs.
+    ///
+    /// Runs are computed once per shaping; text in one font and direction is a single run.
+    pub fn visual_runs(&self) -> impl Iterator<Item=VisualRun> {
+        let runs = self.shaped("Buffer::visual_runs").buffer(self.id()).expect("visual_runs: buffer should exist").visual_runs();
+        (0..runs.len()).map(move |i| runs[i].clone())
+    }
+
+    /// Extents of the shaped glyph outlines on a single line, in font units.
+    pub fn ink_bounds(&self) -> Option<Bounds> {
+        self.shaped("Buffer::ink_bounds").ink_bounds(self.id())
+    }
+
+    /// Text and glyph output of this buffer for recording, see `Fonts::load_shaped_dump`.
+    pub fn shaped_dump(&self) -> ShapedDump {
+        let shared = self.shaped("Buffer::shaped_dump");
+        let data = shared.buffer(self.id()).expect("shaped_dump: buffer should exist");
+        let font = shared.get(data.font_id()).expect("shaped_dump: buffer font should be loaded");
+
+        ShapedDump {
+            font: font.fk_font.full_name(),
+            text: data.shaped_text().to_string(),
+            direction: ShapedDirection::of_glyphs(data.glyphs()),
+            positions: data.glyphs().to_vec(),
+        }
+    }
+
+    /// Glyphs in the text format `hb-shape` prints, such as `[uni0627=0+1155|...]`, with
+    /// clusters and positions in font units, to compare shaping with upstream harfbuzz.
+    ///
+    /// Glyph names come from the `post` table, glyphs without one are written as `gid` and the
+    /// glyph id like `hb-shape --no-glyph-names` does.
+    pub fn debug_shape_string(&self) -> String {
+        self.shaped("Buffer::debug_shape_string").buffer_debug_shape_string(self.id())
+    }
+
+    /// One path with the outlines of all drawn glyphs at `px_size`, or the buffer size when
+    /// `None`, for boolean operations and path exports, see `outline_into`.
+    pub fn outline_path<P: Into<Option<f32>>>(&self, px_size: P) -> lyon_path::default::Path {
+        let mut builder = lyon_path::default::Path::builder();
+        self.outline_into(&mut builder, px_size);
+        lyon_path::builder::FlatPathBuilder::build(builder)
+    }
+
+    /// Writes the cached outline of every drawn glyph to `builder`, scaled to pixels and moved
+    /// to its pen position plus offsets from the buffer origin, in the `Fonts::origin`
+    /// convention. Whitespace and hidden glyphs add nothing.
+    ///
+    /// Contours keep their winding; with `Origin::TopLeftYDown` every contour is mirrored,
+    /// so holes still wind opposite to their outer contours.
+    pub fn outline_into<B: PathBuilder, P: Into<Option<f32>>>(&self, builder: &mut B, px_size: P) {
+        let px_size = self.resolve_size_px(px_size);
+        self.shaped_mut("Buffer::outline_into").buffer_outline_into(self.id(), px_size, builder)
+    }
+
+    /// Standalone SVG document with the glyph outlines of this buffer at `px_size`.
+    pub fn to_svg<P: Into<Option<f32>>>(&self, px_size: P) -> String {
+        let px_size = self.resolve_size_px(px_size);
+        self.shaped("Buffer::to_svg").buffer_to_svg(self.id(), px_size)
+    }
+}
+
+impl Clone for Buffer {
+    #[cfg_attr(debug_assertions, track_caller)]
+    fn clone(&self) -> Self {
+        let mut shared = lock_mut(&self.container, "Buffer::clone");
+        shared.inc_buffer(self.id());
+
+        Buffer {
+            _id: self._id.clone(),
+            container: self.container.clone(),
+        }
+    }
+}
+
+impl PartialEq for Buffer {
+    fn eq(&self, other: &Buffer) -> bool {
+        self.id() == other.id() && Rc::ptr_eq(&self.container, &other.container)
+    }
+}
+
+impl Eq for Buffer {}
+
+impl Hash for Buffer {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id().hash(state);
+        (&*self.container as *const RefCell<shared::FontsContainer> as usize).hash(state);
+    }
+}
+
+impl fmt::Debug for Buffer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut s = f.debug_struct("Buffer");
+        s.field("id", &self.id());
+        if let Ok(shared) = self.container.try_borrow() {
+            if let Some(data) = shared.buffer(self.id()) {
+                let preview = data.text().chars().take(32).collect::<String>();
+                s.field("font_id", &data.font_id());
+                s.field("count", &data.count());
+                s.field("text", &preview);
+            }
+        }
+        s.finish()
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        let mut shared = lock_mut(&self.container, "Buffer::drop");
+        shared.dec_buffer(self.id())
+    }
+}
+
+fn compose_transform(parent: &na::Projective3<f32>, local: &na::Projective3<f32>) -> na::Projective3<f32> {
+    parent * local
+}
+
+/// Identity of a `Fonts` container, telling apart ids of fonts and buffers of other containers.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ContainerToken(usize);
+
+impl ContainerToken {
+    /// A token no other container has.
+    fn next() -> ContainerToken {
+        static NEXT: AtomicUsize = AtomicUsize::new(1);
+        ContainerToken(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct BufferRef {
+    pub _font_id: usize,
+    pub _id: usize,
+    pub _container: ContainerToken,
+}
+
+impl BufferRef {
+    pub fn font_id(&self) -> usize {
+        self._font_id
+    }
+
+    pub fn id(&self) -> usize {
+        self._id
+    }
+}
+
+/// Font id that does not keep the font loaded, see `Font::weak_ref`.
+///
+/// The generation tells apart fonts loaded into the same recycled id.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct FontRef {
+    pub _id: usize,
+    pub _generation: u64,
+    pub _container: ContainerToken,
+}
+
+impl FontRef {
+    pub fn id(&self) -> usize {
+        self._id
+    }
+}
+
+/// Live buffer listed by `Fonts::debug_dump`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BufferDebugInfo {
+    pub id: usize,
+    /// Number of `Buffer` handles, plus one for each line buffer list holding it.
+    pub count: usize,
+    pub font_name: String,
+    /// First 32 chars of the text.
+    pub text: String,
+    /// `0` while shaping is deferred.
+    pub glyph_count: usize,
+    pub version: u64,
+    /// Creation site or label, with the `debug-origins` feature.
+    pub origin: Option<String>,
+}
+
+/// Loaded font listed by `Fonts::debug_dump_fonts`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FontDebugInfo {
+    pub id: usize,
+    /// References of `Font` handles, buffers and other fonts.
+    pub count: usize,
+    pub full_name: String,
+    /// Buffers with this font as their main font.
+    pub buffers: usize,
+}
+
+/// Result of `Font::advance_histogram`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AdvanceReport {
+    /// Advance in font units and number of sampled chars with it, most common first.
+    pub histogram: Vec<(i32, usize)>,
+    /// Most common advance, the cell width of a grid; 0 if no char has a glyph.
+    pub cell_width: i32,
+    /// Chars not advancing one cell, or two for wide East Asian chars, with their advance.
+    pub offending: Vec<(char, i32)>,
+    /// Chars without a glyph, in sample order.
+    pub missing: Vec<char>,
+}
+
+/// Result of `Font::supports_chars`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CoverageReport {
+    /// Number of chars with a glyph.
+    pub supported: usize,
+    /// Chars without a glyph, in query order.
+    pub missing: Vec<char>,
+}
+
+impl CoverageReport {
+    pub fn is_complete(&self) -> bool {
+        self.missing.is_empty()
+    }
+}
+
+/// Failure of `Fonts::font_from_resource`.
+#[derive(Debug)]
+pub enum FontResourceError {
+    /// Fonts were created without a resource loader.
+    NoLoader,
+    NotFound,
+    Load(FontLoadingError),
+    /// Harfbuzz could not use the font data.
+    Face,
+}
+
+impl From<FontLoadingError> for FontResourceError {
+    fn from(e: FontLoadingError) -> Self {
+        FontResourceError::Load(e)
+    }
+}
+
+/// Glyphs rejected by `Fonts::create_preshaped_buffer`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PreshapedGlyphsError {
+    /// The cluster of glyph `glyph` is past the end of the `len` bytes of text.
+    ClusterOutOfRange { glyph: usize, cluster: u32, len: usize },
+    /// The cluster of glyph `glyph` is inside a UTF-8 sequence.
+    ClusterInsideChar { glyph: usize, cluster: u32 },
+}
+
+/// Result of `Fonts::find_best_match_detailed`.
+#[derive(Clone)]
+pub struct FontMatch {
+    pub font: Font,
+    /// Set when the font is the auto fallback font, see `Fonts::set_auto_fallback`.
+    pub substitution: Option<Substitution>,
+    /// The requested and the matched properties.
+    pub details: MatchDetails,
+}
+
+/// Font used in place of requested families that were not found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Substitution {
+    /// The requested families, comma separated.
+    pub requested: String,
+    /// Full name of the font used instead.
+    pub substitute: String,
+}
+
+/// Shaping parameters changed together by `Buffer::reshape_with`, fields left `None` keep
+/// the current value.
+#[derive(Clone, Default)]
+pub struct ShapeParams {
+    pub font: Option<Font>,
+    pub features: Option<Vec<Feature>>,
+    pub whitespace: Option<Whitespace>,
+    pub base_direction: Option<BaseDirection>,
+}
+
+/// Settings of new buffers of a font, see `Font::buffer`.
+///
+/// The builder is kept by `build`, so it can build more buffers with the same settings,
+/// such as the items of a list with `build_text`. Run directions, scripts and language are
+/// not settings; shaping resolves them from the text and the paragraph direction, see
+/// `Buffer::resolved_properties`.
+#[derive(Clone)]
+pub struct BufferBuilder {
+    font: Font,
+    text: String,
+    transform: Option<na::Projective3<f32>>,
+    color: na::Vector4<u8>,
+    features: Vec<Feature>,
+    whitespace: Whitespace,
+    size_px: Option<f32>,
+    retain_text: bool,
+    lazy: Option<bool>,
+    base_direction: BaseDirection,
+}
+
+impl BufferBuilder {
+    fn new(font: &Font) -> BufferBuilder {
+        BufferBuilder {
+            font: font.clone(),
+            text: String::new(),
+            transform: None,
+            color: [0, 0, 0, 255].into(),
+            features: Vec::new(),
+            whitespace: Whitespace::Preserve,
+            size_px: None,
+            retain_text: true,
+            lazy: None,
+            base_direction: BaseDirection::FirstStrong,
+        }
+    }
+
+    pub fn text<S: Into<String>>(mut self, text: S) -> BufferBuilder {
+        self.text = text.into();
+        self
+    }
+
+    pub fn transform(mut self, transform: Option<na::Projective3<f32>>) -> BufferBuilder {
+        self.transform = transform;
+        self
+    }
+
+    /// Black by default.
+    pub fn color(mut self, color: na::Vector4<u8>) -> BufferBuilder {
+        self.color = color;
+        self
+    }
+
+    /// See `Buffer::set_features`.
+    pub fn features(mut self, features: &[Feature]) -> BufferBuilder {
+        self.features = features.to_vec();
+        self
+    }
+
+    /// See `Buffer::set_whitespace`.
+    pub fn whitespace(mut self, whitespace: Whitespace) -> BufferBuilder {
+        self.whitespace = whitespace;
+        self
+    }
+
+    /// See `Buffer::set_size_px`.
+    pub fn size_px(mut self, px_size: f32) -> BufferBuilder {
+        self.size_px = Some(px_size);
+        self
+    }
+
+    /// See `Buffer::set_base_direction`.
+    pub fn base_direction(mut self, base: BaseDirection) -> BufferBuilder {
+        self.base_direction = base;
+        self
+    }
+
+    /// See `Buffer::retain_text`.
+    pub fn retain_text(mut self, retain: bool) -> BufferBuilder {
+        self.retain_text = retain;
+        self
+    }
+
+    /// Defers shaping until the glyphs are first read, overriding `Fonts::set_lazy_shaping`.
+    pub fn lazy(mut self, lazy: bool) -> BufferBuilder {
+        self.lazy = Some(lazy);
+        self
+    }
+
+    /// Creates a buffer of the text with all settings, shaping it once unless lazy.
+    #[cfg_attr(any(feature = "debug-origins", debug_assertions), track_caller)]
+    pub fn build(&self) -> Buffer {
+        self.build_text(&self.text)
+    }
+
+    /// Like `build` with another text.
+    #[cfg_attr(any(feature = "debug-origins", debug_assertions), track_caller)]
+    pub fn build_text(&self, text: &str) -> Buffer {
+        let id = lock_mut(&self.font.container, "BufferBuilder::build_text").create_buffer_from(self, text);
+        let buffer = Buffer {
+            container: self.font.container.clone(),
+            _id: Rc::new(Cell::new(id)),
+        };
+        #[cfg(feature = "debug-origins")]
+        buffer.set_creation_origin(&::std::panic::Location::caller().to_string());
+        buffer
+    }
+}
+
+/// Snapshot of `Fonts::stats`; byte counts are approximate heap sizes.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct FontsStats {
+    pub fonts: usize,
+    pub buffers: usize,
+    /// Shaped glyphs in all buffers.
+    pub glyphs: usize,
+    pub text_bytes: usize,
+    /// Glyph output and segmentation data of all buffers.
+    pub glyph_bytes: usize,
+    /// Outline, mesh and SDF caches of all fonts.
+    pub glyph_cache_bytes: usize,
+    pub shape_calls: u64,
+    pub shaping_cache_hits: u64,
+    pub shaping_cache_misses: u64,
+    /// Recycled buffers waiting for reuse, see `Fonts::recycle`.
+    pub pooled_buffers: usize,
+    /// Entries of the glyph caches of all fonts, see `Fonts::set_size_bucketing`.
+    pub outline_entries: usize,
+    pub sized_outline_entries: usize,
+    pub sdf_entries: usize,
+    /// Fonts from `Font::at_size`.
+    pub sized_fonts: usize,
+    /// Shape calls timed while a profiler was set, see `Fonts::set_profiler`.
+    pub shaping: ProfileTotals,
+    pub outlines: ProfileTotals,
+    pub rasterization: ProfileTotals,
+    /// Text freed after shaping by buffers that do not retain it, see `Buffer::retain_text`.
+    pub dropped_text_bytes: usize,
+    /// Fonts without references kept by `Fonts::set_font_retention`, included in `fonts`.
+    pub retained_fonts: usize,
+}
+
+/// Whitespace at the ends of the text left out of the width by `Buffer::measure_with`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct MeasureOptions {
+    pub trim_leading: bool,
+    pub trim_trailing: bool,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Measurement {
+    pub ascent: f32,
+    pub descent: f32,
+    pub width: f32,
+    pub cap_height: f32,
+    pub x_height: f32,
+    pub line_gap: f32,
+    pub height: f32,
+    /// The width is extrapolated from the shaped window of a buffer shaping only its visible
+    /// range, see `Buffer::set_visible_range`.
+    pub estimated: bool,
+}
+
+/// Vertical metrics in pixels of a span of a buffer at its size, see `Buffer::span_metrics`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpanMetrics {
+    pub range: ::std::ops::Range<usize>,
+    pub px_size: f32,
+    pub ascent: f32,
+    pub descent: f32,
+    pub line_gap: f32,
+}
+
+/// Size of a text in pixels measured with `Font::measure_str`, descent negative like in
+/// `Metrics`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TextMeasure {
+    pub width: f32,
+    pub ascent: f32,
+    pub descent: f32,
+    pub line_gap: f32,
+    pub glyph_count: usize,
+}
+
+pub mod shared {
+    use crate::na;
+    #[cfg(not(feature = "simple-shaping"))]
+    use harfbuzz_rs as hb;
+
+    use slab::Slab;
+    use metrohash::{MetroHashMap, MetroHash64};
+    use super::fingerprint::{self, Fingerprint, FingerprintHasher};
+
+    use font_kit::source::SystemSource;
+    use font_kit::family_name::FamilyName;
+    use font_kit::properties::{Properties, Style};
+    use font_kit::handle::Handle;
+    use font_kit::metrics::Metrics;
+    use font_kit::font::Font as FontkitFont;
+    use byteorder::{LittleEndian, WriteBytesExt};
+    use super::{Measurement, SpanMetrics, TextMeasure};
+    use super::layout::{self, LayoutOptions, TabStops, TextLayout, Bounds, Origin, RoundingMode, RoundedPen, ScaledMetrics, DEFAULT_SIZE_PX};
+    use super::cache::{GlyphCache, DEFAULT_GLYPH_CACHE_CAPACITY};
+    use super::size_bucket::{SizeBucket, DEFAULT_SIZE_STEP};
+    use super::geometry::GeometryStore;
+    use super::retention::{FontRetention, RetainedFonts};
+    #[cfg(feature = "tessellation")]
+    use super::mesh::{self, GlyphMesh, GlyphMeshError, GlyphInstance, BufferMesh};
+    use super::raster::{self, SdfBitmap, GlyphBitmap};
+    use super::svg_export::SvgPathWriter;
+    use super::shaping_cache::{ShapingCache, ShapingCacheStats, ShapedGlyph, ShapedGlyphs, UnicodeBufferPool, DEFAULT_SHAPING_CACHE_CAPACITY};
+    #[cfg(feature = "color-bitmaps")]
+    use super::color_bitmaps::{self, GlyphImage};
+    use super::{HintingOptions, GlyphLoadingError, FontsStats, ShapedDump, FontResourceError, PreshapedGlyphsError, BufferRef, FontRef, ContainerToken, Feature};
+    use super::{BufferDebugInfo, FontDebugInfo, Substitution, MeasureOptions, PathLayoutOptions, PlacedGlyph, BufferBuilder};
+    use super::path_layout;
+    use super::refcount_audit::{RefcountAudit, Kind};
+    use super::resolved_shape::{self, ResolvedShape, VisualRun};
+    use super::events::{EventQueue, FontsEvent};
+    use super::diagnostics::{Diagnostics, FontsDiagnostic};
+    use super::profiling::{ProfiledOperation, Profiler, ShapeEvent};
+    use super::names::{self, split_style_keywords};
+    use super::bidi::{self, BaseDirection};
+    #[cfg(not(feature = "simple-shaping"))]
+    use super::bidi::direction_runs;
+    use super::synthesis::{self, SynthesisOptions};
+    use super::vertical_metrics::{MetricsPolicy, RawVerticalMetrics};
+    use super::whitespace::{Whitespace, CollapsedSource};
+    use super::case::{self, Case};
+    use super::validation;
+    use super::source::{FontSource, NoSource};
+    use super::matching::{self, FaceInfo, MatchCandidate, MatchDetails};
+    use super::glyph_diff::{self, GlyphDiff};
+    use super::gdef;
+    use super::kerning;
+    use super::trak;
+    use super::variations::Fvar;
+    #[cfg(feature = "simple-shaping")]
+    use super::sfnt;
+    use super::debug_shape;
+    use std::borrow::Cow;
+    use std::cell::{Cell, RefCell};
+    use std::io::{self, Read, Write};
+    use std::rc::{Rc, Weak};
+    use std::sync::Arc;
+    use std::any::Any;
+    use std::collections::{HashMap, HashSet};
+    use std::time::Instant;
+    use lyon_path::default::Path;
+    use lyon_path::builder::{FlatPathBuilder, PathBuilder};
+    use lyon_path::PathEvent;
+    use lyon_path::math::{Point, point};
+
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+    #[repr(C)]
+    /// Shaped glyph; advances and offsets are in font units, see `Font::scale_for_px`. Font
+    /// units of a `SizedFont` are 1/64 pixel. Glyphs of spans are in the units of the buffer
+    /// font whatever font they were shaped with.
+    pub struct GlyphPosition {
+        pub id: u32,
+        pub cluster: u32,
+        pub byte_offset: u32,
+        pub len: u32,
+        pub x_advance: i32,
+        pub y_advance: i32,
+        pub x_offset: i32,
+        pub y_offset: i32,
+        /// Index of the span this glyph was shaped in, `0` for single-font buffers.
+        pub span: u32,
+        pub flags: GlyphFlags,
+    }
+
+    /// Harfbuzz glyph flags of a `GlyphPosition`.
+    #[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+    #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+    #[repr(C)]
+    pub struct GlyphFlags(pub u32);
+
+    impl GlyphFlags {
+        pub const UNSAFE_TO_BREAK: GlyphFlags = GlyphFlags(GLYPH_FLAG_UNSAFE_TO_BREAK);
+        pub const LINE_BREAK: GlyphFlags = GlyphFlags(GLYPH_FLAG_LINE_BREAK);
+        pub const SOFT_HYPHEN: GlyphFlags = GlyphFlags(GLYPH_FLAG_SOFT_HYPHEN);
+        pub const PREEDIT: GlyphFlags = GlyphFlags(GLYPH_FLAG_PREEDIT);
+
+        /// The text can not be split before this glyph and shaped in two parts
+        /// without changing the result.
+        pub fn unsafe_to_break(self) -> bool {
+            self.0 & GLYPH_FLAG_UNSAFE_TO_BREAK != 0
+        }
+
+        /// Glyph of a hard line break character, with zero advance and not drawn.
+        pub fn is_line_break(self) -> bool {
+            self.0 & GLYPH_FLAG_LINE_BREAK != 0
+        }
+
+        /// Glyph of a U+00AD soft hyphen, with zero advance and not drawn; layout shows a
+        /// hyphen instead when it breaks the line there.
+        pub fn is_soft_hyphen(self) -> bool {
+            self.0 & GLYPH_FLAG_SOFT_HYPHEN != 0
+        }
+
+        /// Glyph of the input method composition, see `Buffer::set_preedit`.
+        pub fn is_preedit(self) -> bool {
+            self.0 & GLYPH_FLAG_PREEDIT != 0
+        }
+
+        /// Glyph that is not drawn, a line break or a soft hyphen.
+        pub fn is_hidden(self) -> bool {
+            self.0 & (GLYPH_FLAG_LINE_BREAK | GLYPH_FLAG_SOFT_HYPHEN) != 0
+        }
+    }
+
+    #[derive(Clone)]
+    pub struct GraphemeInfo {
+        pub start_byte: u32,
+        pub len: u32,
+    }
+
+    /// Glyph placed in pixels by `Buffer::glyphs_scaled`, Y in the `Fonts::origin` convention.
+    #[derive(Debug, Copy, Clone, PartialEq)]
+    pub struct ScaledGlyph {
+        pub id: u32,
+        pub span: u32,
+        pub x: f32,
+        pub y: f32,
+        /// Subpixel variant to rasterize, the glyph origin is `x + phase / phases`.
+        pub phase: u32,
+        /// Tag of the glyph cluster, see `Buffer::set_highlights`.
+        pub highlight: Option<u32>,
+    }
+
+    /// Glyph placed in pixels by `Buffer::glyphs_packed`, Y in the `Fonts::origin` convention, laid out to be uploaded
+    /// to a GPU buffer as is: 24 bytes with 4 byte alignment, no padding.
+    #[derive(Debug, Copy, Clone, PartialEq)]
+    #[repr(C)]
+    pub struct PackedGlyph {
+        /// Pen position before the glyph advance.
+        pub pen_x: f32,
+        pub pen_y: f32,
+        /// Glyph offset from the pen position.
+        pub offset_x: f32,
+        pub offset_y: f32,
+        pub id: u32,
+        pub cluster: u32,
+    }
+
+    #[allow(dead_code)]
+    const PACKED_GLYPH_SIZE: [(); 24] = [(); ::std::mem::size_of::<PackedGlyph>()];
+    #[allow(dead_code)]
+    const PACKED_GLYPH_ALIGN: [(); 4] = [(); ::std::mem::align_of::<PackedGlyph>()];
+
+    /// Result of `Buffer::glyph_info`.
+    #[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+    #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+    pub struct GlyphInfo {
+        pub is_ligature: bool,
+        /// Graphemes a ligature stands for, or carets plus one from the `GDEF` table, `1` for
+        /// other glyphs.
+        pub component_count: u32,
+        pub is_mark: bool,
+        /// First glyph of its cluster in glyph order.
+        pub is_cluster_start: bool,
+    }
+
+    /// Result of `Buffer::glyphs_in_range`.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct GlyphRangeInfo {
+        /// Pen position before the first selected glyph, in font units.
+        pub pen_x: i32,
+        pub pen_y: i32,
+        /// Requested range expanded to cluster boundaries.
+        pub byte_range: ::std::ops::Range<usize>,
+        /// The first selected cluster starts before the requested range.
+        pub clipped_start: bool,
+        /// The last selected cluster ends after the requested range.
+        pub clipped_end: bool,
+    }
+
+    /// Uncommitted input method text inside the buffer text, see `Buffer::set_preedit`.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Preedit {
+        pub range: ::std::ops::Range<usize>,
+        /// Caret offset into the preedit.
+        pub cursor: usize,
+    }
+
+    /// Byte range of the text shaped with a specific font.
+    #[derive(Debug, Clone)]
+    pub struct SpanData {
+        pub range: ::std::ops::Range<usize>,
+        pub font_id: usize,
+        /// Size relative to the buffer, glyph positions are scaled by it when segmenting.
+        pub scale: f32,
+        /// Baseline shift in ems of the buffer size, up positive, added to glyph offsets.
+        pub shift: f32,
+    }
+
+    /// Output of a single harfbuzz shape call, clusters relative to `byte_offset`.
+    ///
+    /// Only `glyphs` of the glyph buffer are part of the output, the rest was
+    /// replaced by a following run. The offset moves clusters of text after an edit.
+    #[derive(Clone)]
+    pub struct ShapedRun {
+        pub span: u32,
+        pub font_id: usize,
+        pub byte_offset: i32,
+        pub shaped: Rc<ShapedGlyphs>,
+        pub glyphs: ::std::ops::Range<usize>,
+    }
+
+    impl ShapedRun {
+        fn new(span: u32, font_id: usize, shaped: Rc<ShapedGlyphs>) -> ShapedRun {
+            ShapedRun {
+                span,
+                font_id,
+                byte_offset: 0,
+                glyphs: 0..shaped.len(),
+                shaped,
+            }
+        }
+
+        pub fn shaped_glyphs(&self) -> &[ShapedGlyph] {
+            &self.shaped.glyphs[self.glyphs.clone()]
+        }
+
+        /// Byte offset of a glyph cluster in the current text.
+        pub fn cluster(&self, glyph: &ShapedGlyph) -> u32 {
+            (glyph.cluster as i32 + self.byte_offset) as u32
+        }
+    }
+
+    /// Joins neighbouring runs of the same span into one, so that repeated edits do not
+    /// pile up runs. Pieces of one shaped output are joined in place, others are copied.
+    fn merge_runs(runs: Vec<ShapedRun>) -> Vec<ShapedRun> {
+        let mut merged: Vec<ShapedRun> = Vec::with_capacity(runs.len());
+        for run in runs {
+            let joins = merged.last().map_or(false, |previous| previous.span == run.span && previous.font_id == run.font_id);
+            if !joins {
+                merged.push(run);
+                continue;
+            }
+            let previous = merged.last_mut().expect("merge_runs: a run to join was just found");
+
+            if Rc::ptr_eq(&previous.shaped, &run.shaped) && previous.byte_offset == run.byte_offset && previous.glyphs.end == run.glyphs.start {
+                previous.glyphs.end = run.glyphs.end;
+                continue;
+            }
+
+            let rebase = |run: &ShapedRun| run.shaped_glyphs().iter().map(|glyph| ShapedGlyph { cluster: run.cluster(glyph), ..*glyph }).collect::<Vec<_>>();
+            let mut glyphs = rebase(previous);
+            glyphs.extend(rebase(&run));
+            *previous = ShapedRun::new(run.span, run.font_id, Rc::new(ShapedGlyphs { glyphs }));
+        }
+        merged
+    }
+
+    const GLYPH_FLAG_UNSAFE_TO_BREAK: u32 = 0x0000_0001;
+    const DEFAULT_BUFFER_POOL_CAPACITY: usize = 64;
+    /// Bytes shaped on each side of a visible range, see `Buffer::set_visible_range`.
+    const WINDOW_MARGIN: usize = 4096;
+    /// Our own flag, above the harfbuzz ones.
+    const GLYPH_FLAG_LINE_BREAK: u32 = 0x8000_0000;
+    const GLYPH_FLAG_SOFT_HYPHEN: u32 = 0x4000_0000;
+    const GLYPH_FLAG_PREEDIT: u32 = 0x2000_0000;
+
+    /// Hard break characters, the same that end lines in layout.
+    fn is_line_break_char(c: char) -> bool {
+        match c {
+            '\n' | '\r' | '\u{2028}' | '\u{2029}' => true,
+            _ => false,
+        }
+    }
+
+    /// Flags and zeroes the advance of a glyph of a line break or a soft hyphen, which fonts
+    /// map to .notdef or a visible glyph. `text` starts at the glyph cluster.
+    fn hide_control_glyph(glyph: &mut GlyphPosition, text: &str) {
+        let flag = match text.chars().next() {
+            Some(c) if is_line_break_char(c) => GLYPH_FLAG_LINE_BREAK,
+            Some(layout::SOFT_HYPHEN) => GLYPH_FLAG_SOFT_HYPHEN,
+            _ => return,
+        };
+        glyph.x_advance = 0;
+        glyph.y_advance = 0;
+        glyph.x_offset = 0;
+        glyph.y_offset = 0;
+        glyph.flags.0 |= flag;
+    }
+
+    /// Harfbuzz marks glyphs before which the text can not be split for shaping.
+    pub fn unsafe_to_break(glyph: &ShapedGlyph) -> bool {
+        glyph.mask & GLYPH_FLAG_UNSAFE_TO_BREAK != 0
+    }
+
+    /// Position in the text after `range` was replaced with `replacement_len` bytes;
+    /// positions inside the replaced range move to its start plus `inside`.
+    fn splice_position(position: usize, range: &::std::ops::Range<usize>, inside: usize, delta: i32) -> usize {
+        if position <= range.start {
+            position
+        } else if position >= range.end {
+            (position as i32 + delta) as usize
+        } else {
+            range.start + inside
+        }
+    }
+
+    /// Shapes `range` of `text` with the rest of the text as context, with an input buffer from `pool`.
+    fn shape_range(font: &FontData, pool: &mut UnicodeBufferPool, text: &str, range: ::std::ops::Range<usize>, features: &[ShapingFeature], base: BaseDirection) -> ShapedGlyphs {
+        let mut glyphs = shape_glyphs(font, pool, text, range, features, base);
+
+        let extra_advance = font.synthetic_advance();
+        if extra_advance != 0 {
+            for glyph in glyphs.iter_mut().filter(|glyph| glyph.x_advance != 0) {
+                glyph.x_advance += extra_advance;
+            }
+        }
+
+        ShapedGlyphs { glyphs }
+    }
+
+    /// Glyphs of `range` shaped with harfbuzz.
+    ///
+    /// Shape plans are not cached by this crate. harfbuzz_rs 0.3 has no shape plan type, and
+    /// creating or executing plans through `harfbuzz_sys` needs unsafe code, which this crate
+    /// forbids. `hb::shape` goes through `hb_shape_full`, which already reuses plans cached on
+    /// the face per direction, script, language and features, and harfbuzz drops them with the
+    /// face, so there is no plan state here to invalidate.
+    ///
+    /// Each direction run is shaped with its direction set, so harfbuzz mirrors brackets in
+    /// right-to-left runs; the output is in visual order for paragraphs in `base` direction.
+    #[cfg(not(feature = "simple-shaping"))]
+    fn shape_glyphs(font: &FontData, pool: &mut UnicodeBufferPool, text: &str, range: ::std::ops::Range<usize>, features: &[ShapingFeature], base: BaseDirection) -> Vec<ShapedGlyph> {
+        let mut glyphs = Vec::new();
+
+        for run in direction_runs(text, range, base) {
+            let direction = if run.rtl { hb::Direction::Rtl } else { hb::Direction::Ltr };
+            let unicode_buffer = add_str_with_context(pool.take(), text, run.range).set_direction(direction);
+            let output = hb::shape(&font.hb_font, unicode_buffer, features);
+            glyphs.extend(ShapedGlyphs::from_buffer(&output).glyphs);
+            pool.put(output);
+        }
+
+        glyphs
+    }
+
+    /// Glyphs of `range` from the character map, horizontal metrics and pair kerning, see
+    /// `simple_shaping`. Features, the surrounding text and the base direction are ignored, and
+    /// right-to-left text stays in logical order.
+    #[cfg(feature = "simple-shaping")]
+    fn shape_glyphs(font: &FontData, _pool: &mut UnicodeBufferPool, text: &str, range: ::std::ops::Range<usize>, _features: &[ShapingFeature], _base: BaseDirection) -> Vec<ShapedGlyph> {
+        simple_shaping::shape(&text[range.clone()], range.start,
+            |c| font.fk_font.glyph_for_char(c),
+            |glyph_id| font.fk_font.advance(glyph_id).map_or(0, |advance| (advance.x * font.units_scale).round() as i32),
+            |left, right| font.kerning(left, right))
+    }
+
+    /// Scales the advances and offsets of a glyph of a span with its own size.
+    fn scale_glyph(glyph: &mut GlyphPosition, scale: f32) {
+        let scaled = |v: i32| (v as f32 * scale).round() as i32;
+        glyph.x_advance = scaled(glyph.x_advance);
+        glyph.y_advance = scaled(glyph.y_advance);
+        glyph.x_offset = scaled(glyph.x_offset);
+        glyph.y_offset = scaled(glyph.y_offset);
+    }
+
+    /// Glyphs of a text shaped on its own, such as part of a hyphenated word, with byte
+    /// clusters relative to `text`.
+    fn shape_fragment(font: &FontData, text: &str, span: u32, features: &[ShapingFeature], base: BaseDirection) -> Vec<GlyphPosition> {
+        let shaped = shape_range(font, &mut UnicodeBufferPool::new(), text, 0..text.len(), features, base);
+        let mut clusters = shaped.glyphs.iter().map(|g| g.cluster).collect::<Vec<_>>();
+        clusters.sort();
+        clusters.dedup();
+
+        shaped.glyphs.iter()
+            .map(|shaped| {
+                let end = clusters.iter().cloned().find(|&c| c > shaped.cluster).unwrap_or(text.len() as u32);
+                let mut glyph = GlyphPosition {
+                    id: shaped.codepoint,
+                    cluster: shaped.cluster,
+                    byte_offset: shaped.cluster,
+                    len: end - shaped.cluster,
+                    x_advance: shaped.x_advance,
+                    y_advance: shaped.y_advance,
+                    x_offset: shaped.x_offset,
+                    y_offset: shaped.y_offset,
+                    span,
+                    flags: GlyphFlags(shaped.mask & GLYPH_FLAG_UNSAFE_TO_BREAK),
+                };
+                hide_control_glyph(&mut glyph, &text[shaped.cluster as usize..]);
+                glyph
+            })
+            .collect()
+    }
+
+    /// Harfbuzz font of a face, nothing with the simple shaper.
+    #[cfg(not(feature = "simple-shaping"))]
+    pub type ShapingFont = hb::Owned<hb::Font<'static>>;
+    #[cfg(feature = "simple-shaping")]
+    pub type ShapingFont = ();
+
+    /// Harfbuzz font of the same face as `font`, for a font derived from it.
+    #[cfg(not(feature = "simple-shaping"))]
+    fn shaping_font_of(font: &FontData) -> Result<ShapingFont, String> {
+        new_hb_font(font.hb_font.face())
+    }
+
+    /// Harfbuzz font of the face at `index` of font file `bytes`.
+    #[cfg(not(feature = "simple-shaping"))]
+    fn shaping_font_from_bytes(bytes: Arc<Vec<u8>>, index: u32) -> Result<ShapingFont, String> {
+        new_hb_font(hb::Face::new(hb::Blob::with_bytes_owned(bytes, |bytes: &Arc<Vec<u8>>| &bytes[..]), index))
+    }
+
+    /// Harfbuzz font of a font found by the system source, with the diagnostic to report if
+    /// harfbuzz can not use it.
+    #[cfg(not(feature = "simple-shaping"))]
+    fn shaping_font_for_handle(handle: &Handle, file_data: Option<Arc<Vec<u8>>>, fk_font: &FontkitFont) -> Result<ShapingFont, FontsDiagnostic> {
+        let face = match *handle {
+            // font-kit 0.1 copies the file into owned bytes; harfbuzz uses that copy
+            // rather than mapping the file, which faults once the file is deleted or
+            // unmounted, and only maps it when there is no copy
+            Handle::Path { font_index, .. } if file_data.is_some() => {
+                let bytes = file_data.expect("shaping_font_for_handle: file data was checked");
+                hb::Face::new(hb::Blob::with_bytes_owned(bytes, |bytes: &Arc<Vec<u8>>| &bytes[..]), font_index)
+            }
+            Handle::Path { ref path, font_index } => {
+                hb::Face::from_file(path, font_index)
+                    .map_err(|_| FontsDiagnostic::FaceFailed { path: path.to_string_lossy().into_owned(), index: font_index })?
+            }
+            Handle::Memory { ref bytes, font_index } => {
+                hb::Face::new(hb::Blob::with_bytes_owned(bytes.clone(), |bytes: &Arc<Vec<u8>>| &bytes[..]), font_index)
+            }
+        };
+        new_hb_font(face).map_err(|reason| FontsDiagnostic::LoadFailed { family: fk_font.full_name(), reason })
+    }
+
+    #[cfg(not(feature = "simple-shaping"))]
+    fn new_hb_font<F: Into<hb::Shared<hb::Face<'static>>>>(face: F) -> Result<ShapingFont, String> {
+        use harfbuzz_rs::rusttype::SetRustTypeFuncs;
+        let mut hb_font = hb::Font::new(face);
+        hb_font.set_rusttype_funcs().map_err(|e| format!("{:?}", e))?;
+        Ok(hb_font)
+    }
+
+    #[cfg(feature = "simple-shaping")]
+    fn shaping_font_of(_font: &FontData) -> Result<ShapingFont, String> {
+        Ok(())
+    }
+
+    #[cfg(feature = "simple-shaping")]
+    fn shaping_font_from_bytes(_bytes: Arc<Vec<u8>>, _index: u32) -> Result<ShapingFont, String> {
+        Ok(())
+    }
+
+    #[cfg(feature = "simple-shaping")]
+    fn shaping_font_for_handle(_handle: &Handle, _file_data: Option<Arc<Vec<u8>>>, _fk_font: &FontkitFont) -> Result<ShapingFont, FontsDiagnostic> {
+        Ok(())
+    }
+
+    /// Feature as given to the shaper.
+    #[cfg(not(feature = "simple-shaping"))]
+    type ShapingFeature = hb::Feature;
+    #[cfg(feature = "simple-shaping")]
+    type ShapingFeature = Feature;
+
+    /// Harfbuzz features, with clusters being byte offsets into the whole text the byte
+    /// ranges are already in cluster space.
+    #[cfg(not(feature = "simple-shaping"))]
+    fn hb_features(features: &[Feature]) -> Vec<ShapingFeature> {
+        features.iter()
+            .map(|feature| {
+                let tag = hb::Tag::new(feature.tag[0] as char, feature.tag[1] as char, feature.tag[2] as char, feature.tag[3] as char);
+                let range = feature.range.clone().unwrap_or(0..::std::u32::MAX as usize);
+                hb::Feature::new(tag, feature.value, range)
+            })
+            .collect()
+    }
+
+    /// The simple shaper ignores features.
+    #[cfg(feature = "simple-shaping")]
+    fn hb_features(features: &[Feature]) -> Vec<ShapingFeature> {
+        features.to_vec()
+    }
+
+    fn feature_outside(feature: &Feature, text_len: usize) -> bool {
+        feature.range.as_ref().map_or(false, |range| range.end > text_len)
+    }
+
+    /// Intern table key of a buffer created with these arguments, the font id and a hash of
+    /// the rest.
+    fn intern_key(font_id: usize, text: &str, transform: Option<&na::Projective3<f32>>, color: na::Vector4<u8>) -> (usize, u64) {
+        let mut hasher = MetroHash64::default();
+        text.hash(&mut hasher);
+        color.as_slice().hash(&mut hasher);
+        for value in transform.into_iter().flat_map(|transform| transform.matrix().iter()) {
+            value.to_bits().hash(&mut hasher);
+        }
+        (font_id, hasher.finish())
+    }
+
+    /// Slab with each value at its key, `placeholder` values filling the keys in between
+    /// until they are removed again. `entries` are in key order.
+    fn slab_with_keys<T, F: FnMut() -> T>(entries: Vec<(usize, T)>, mut placeholder: F) -> Slab<T> {
+        let mut slab = Slab::with_capacity(entries.last().map_or(0, |&(key, _)| key + 1));
+        let mut vacant = Vec::new();
+        for (key, value) in entries {
+            while slab.len() < key {
+                vacant.push(slab.insert(placeholder()));
+            }
+            slab.insert(value);
+        }
+        for key in vacant {
+            slab.remove(key);
+        }
+        slab
+    }
+
+    /// Shaping cache key of the features and base direction, empty without features and with
+    /// the first strong direction.
+    fn shaping_key(features: &[Feature], base: BaseDirection) -> Vec<u8> {
+        if features.is_empty() && base == BaseDirection::FirstStrong {
+            return Vec::new();
+        }
+
+        // written byte by byte rather than with `Hash`, as the key is exported with the cache
+        let mut bytes = Vec::new();
+        for feature in features {
+            bytes.extend_from_slice(&feature.tag);
+            bytes.write_u32::<LittleEndian>(feature.value).unwrap();
+            match feature.range {
+                Some(ref range) => {
+                    bytes.push(1);
+                    bytes.write_u64::<LittleEndian>(range.start as u64).unwrap();
+                    bytes.write_u64::<LittleEndian>(range.end as u64).unwrap();
+                }
+                None => bytes.push(0),
+            }
+        }
+        bytes.push(match base {
+            BaseDirection::FirstStrong => 0,
+            BaseDirection::Ltr => 1,
+            BaseDirection::Rtl => 2,
+        });
+        bytes
+    }
+
+    /// Container default features followed by the buffer features, without the defaults the
+    /// buffer sets for the whole text. Harfbuzz applies later features over earlier ones, so
+    /// ranged buffer features win over defaults inside their range.
+    pub fn merge_features(defaults: &[Feature], features: &[Feature]) -> Vec<Feature> {
+        defaults.iter()
+            .filter(|default| !features.iter().any(|f| f.tag == default.tag && f.range.is_none()))
+            .chain(features)
+            .cloned()
+            .collect()
+    }
+
+    /// Extends `range` to the grapheme boundaries around it.
+    fn snap_to_graphemes(text: &str, range: ::std::ops::Range<usize>) -> ::std::ops::Range<usize> {
+        use unicode_segmentation::UnicodeSegmentation;
+
+        let (mut start, mut end) = (0, text.len());
+        for (pos, _) in text.grapheme_indices(true) {
+            if pos <= range.start {
+                start = pos;
+            }
+            if pos >= range.end {
+                end = pos;
+                break;
+            }
+        }
+        start..end.max(start)
+    }
+
+    /// Adds `text[range]` to the buffer with the rest of `text` as shaping context.
+    ///
+    /// Clusters of the output are byte offsets into `text`.
+    #[cfg(not(feature = "simple-shaping"))]
+    fn add_str_with_context(buffer: hb::UnicodeBuffer, text: &str, range: ::std::ops::Range<usize>) -> hb::UnicodeBuffer {
+        buffer.add_str_item(text, &text[range])
+    }
+
+    /// Value derived from the glyphs of a buffer, recomputed when read at a newer
+    /// `BufferData::epoch`.
+    pub struct Memo<T> {
+        value: RefCell<Option<(u64, T)>>,
+    }
+
+    impl<T: Clone> Memo<T> {
+        fn new() -> Memo<T> {
+            Memo { value: RefCell::new(None) }
+        }
+
+        /// Value computed at `epoch`, computing it first if it is missing or stale.
+        fn get<F: FnOnce() -> T>(&self, epoch: u64, compute: F) -> T {
+            self.get_if(epoch, |_| true, compute)
+        }
+
+        /// Like `get`, also recomputing a value computed at `epoch` that `valid` rejects.
+        fn get_if<V: FnOnce(&T) -> bool, F: FnOnce() -> T>(&self, epoch: u64, valid: V, compute: F) -> T {
+            if let Some(value) = self.peek(epoch).filter(valid) {
+                return value;
+            }
+            let value = compute();
+            *self.value.borrow_mut() = Some((epoch, value.clone()));
+            value
+        }
+
+        /// Value if it was computed at `epoch`.
+        fn peek(&self, epoch: u64) -> Option<T> {
+            match *self.value.borrow() {
+                Some((computed, ref value)) if computed == epoch => Some(value.clone()),
+                _ => None,
+            }
+        }
+    }
+
+    pub struct BufferData {
+        text: String,
+        graphemes: Vec<GraphemeInfo>,
+        clusters: Vec<u32>,
+        glyphs: Vec<GlyphPosition>,
+        transform: Option<na::Projective3<f32>>,
+        spans: Vec<SpanData>,
+        runs: Vec<ShapedRun>,
+        font_id: usize,
+        count: usize,
+        color: na::Vector4<u8>,
+        /// Bumped whenever the shaped output changes.
+        version: u64,
+        /// Bumped whenever the transform or the size changes.
+        transform_version: u64,
+        /// Pixels per em, the container default when `None`.
+        size_px: Option<f32>,
+        /// Whether glyph advances include the `trak` table tracking at `tracking_px`.
+        pub optical_tracking: bool,
+        /// Resolved size of the buffer, kept up to date by the container.
+        tracking_px: f32,
+        /// A font of the last shaping has a `trak` table.
+        has_tracking: bool,
+        /// False until the text is first shaped, with lazy shaping.
+        shaped: bool,
+        /// Glyphs were given instead of shaped, so there are no runs to reshape parts of.
+        preshaped: bool,
+        /// Paragraph of the buffer this one was split from.
+        line_index: Option<usize>,
+        /// Line buffers re-split on text changes, each holding a reference.
+        linked_lines: Option<Vec<usize>>,
+        /// OpenType features, ranges on grapheme boundaries.
+        features: Vec<Feature>,
+        /// Container default features, merged with `features` for shaping.
+        default_features: Rc<Vec<Feature>>,
+        whitespace: Whitespace,
+        case: Case,
+        /// Shape the text with hazards fixed, see `Buffer::set_sanitize`.
+        sanitize: bool,
+        base_direction: BaseDirection,
+        /// Tagged byte ranges of `text`, see `Buffer::set_highlights`.
+        highlights: Vec<(::std::ops::Range<usize>, u32)>,
+        /// Bumped whenever the highlights change.
+        highlight_version: u64,
+        /// Locale of the case mapping.
+        case_locale: Option<String>,
+        /// Text as given with `Whitespace::Collapse` or a case, `text` is then the transformed text.
+        source: Option<CollapsedSource>,
+        /// Bumped whenever the glyphs change, memoized values derived from them compare it
+        /// with the epoch they were computed at.
+        epoch: u64,
+        /// Sum of the glyph advances.
+        advance_width: Memo<i32>,
+        /// Extents of the glyph outlines in font units.
+        ink_bounds: Memo<Option<Bounds>>,
+        /// Segment properties of the shaped runs, see `Buffer::resolved_runs`.
+        resolved: Memo<Vec<(::std::ops::Range<usize>, ResolvedShape)>>,
+        /// Ligature and mark properties of the glyphs, see `Buffer::glyph_info`.
+        glyph_infos: Memo<Rc<[GlyphInfo]>>,
+        /// Pen positions before each glyph, see `Buffer::pen_position_at`.
+        pen_positions: Memo<Rc<PenPositions>>,
+        /// Glyph runs in visual order, see `Buffer::visual_runs`.
+        visual_runs: Memo<Rc<[VisualRun]>>,
+        /// Creation site or label shown by `debug_dump`.
+        #[cfg(feature = "debug-origins")]
+        origin: Option<String>,
+        /// Allocations go to the buffer pool on deletion, see `Fonts::recycle`.
+        recycle: bool,
+        /// Key in the intern table while the buffer is as created, see `Fonts::set_buffer_interning`.
+        intern_key: Option<(usize, u64)>,
+        /// Id cells of the handles created while interned, each shared by the clones of one.
+        sharers: Vec<Weak<Cell<usize>>>,
+        /// Keep the text after shaping, see `Buffer::retain_text`.
+        retain_text: bool,
+        /// Length of the shaped text freed after shaping.
+        dropped_text_len: Option<usize>,
+        preedit: Option<Preedit>,
+        /// Range of `text` to shape the paragraphs around, see `Buffer::set_visible_range`.
+        visible_range: Option<::std::ops::Range<usize>>,
+        /// Range of the shaped text the glyphs come from with a visible range.
+        shaped_window: Option<::std::ops::Range<usize>>,
+        /// Application data, see `Buffer::set_user_data`.
+        user_data: Option<Box<dyn Any>>,
+    }
+
+    /// Pen positions in pixels with Y up before each glyph and after the last, for the size,
+    /// rounding and scale factor they were computed with.
+    pub struct PenPositions {
+        px_size: f32,
+        rounding: RoundingMode,
+        scale_factor: f32,
+        positions: Vec<(f32, f32)>,
+    }
+
+    /// Shaping settings a line buffer takes from the paragraph it was split from.
+    struct LineSettings {
+        features: Vec<Feature>,
+        whitespace: Whitespace,
+        case: Case,
+        case_locale: Option<String>,
+        sanitize: bool,
+        base_direction: BaseDirection,
+    }
+
+    /// Cleared allocations of a deleted buffer, reused by the next created buffer.
+    pub struct RecycledBuffer {
+        text: String,
+        graphemes: Vec<GraphemeInfo>,
+        clusters: Vec<u32>,
+        glyphs: Vec<GlyphPosition>,
+        spans: Vec<SpanData>,
+        runs: Vec<ShapedRun>,
+        features: Vec<Feature>,
+    }
+
+    impl BufferData {
+        /// Unshaped buffer data, `shape` must be called before reading glyphs.
+        fn new(font_id: usize, text: String, spans: Vec<SpanData>, transform: Option<na::Projective3<f32>>, color: na::Vector4<u8>) -> BufferData {
+            BufferData {
+                text,
+                graphemes: Vec::new(),
+                clusters: Vec::new(),
+                glyphs: Vec::new(),
+                transform,
+                spans,
+                runs: Vec::new(),
+                font_id,
+                count: 1,
+                color,
+                version: 0,
+                transform_version: 0,
+                size_px: None,
+                optical_tracking: true,
+                tracking_px: DEFAULT_SIZE_PX,
+                has_tracking: false,
+                shaped: false,
+                preshaped: false,
+                line_index: None,
+                linked_lines: None,
+                features: Vec::new(),
+                default_features: Rc::new(Vec::new()),
+                whitespace: Whitespace::Preserve,
+                case: Case::None,
+                sanitize: false,
+                base_direction: BaseDirection::FirstStrong,
+                highlights: Vec::new(),
+                highlight_version: 0,
+                case_locale: None,
+                source: None,
+                epoch: 0,
+                advance_width: Memo::new(),
+                ink_bounds: Memo::new(),
+                resolved: Memo::new(),
+                glyph_infos: Memo::new(),
+                pen_positions: Memo::new(),
+                visual_runs: Memo::new(),
+                #[cfg(feature = "debug-origins")]
+                origin: None,
+                recycle: false,
+                intern_key: None,
+                sharers: Vec::new(),
+                retain_text: true,
+                dropped_text_len: None,
+                preedit: None,
+                visible_range: None,
+                shaped_window: None,
+                user_data: None,
+            }
+        }
+
+        /// Unshaped buffer data like `new`, in the allocations of a recycled buffer.
+        fn from_recycled(recycled: RecycledBuffer, font_id: usize, text: &str, transform: Option<na::Projective3<f32>>, color: na::Vector4<u8>) -> BufferData {
+            let RecycledBuffer { text: mut buffer_text, graphemes, clusters, glyphs, spans, runs, features } = recycled;
+            buffer_text.push_str(text);
+
+            let mut data = BufferData::new(font_id, buffer_text, spans, transform, color);
+            data.graphemes = graphemes;
+            data.clusters = clusters;
+            data.glyphs = glyphs;
+            data.runs = runs;
+            data.features = features;
+            data
+        }
+
+        /// Clears the allocations for reuse, releasing shaped runs and any text.
+        fn into_recycled(self) -> RecycledBuffer {
+            let BufferData { mut text, mut graphemes, mut clusters, mut glyphs, mut spans, mut runs, mut features, .. } = self;
+            text.clear();
+            graphemes.clear();
+            clusters.clear();
+            glyphs.clear();
+            spans.clear();
+            runs.clear();
+            features.clear();
+            RecycledBuffer { text, graphemes, clusters, glyphs, spans, runs, features }
+        }
+
+        /// Copy for `FontsContainer::snapshot` sharing the shaped runs, without memoized
+        /// values and user data.
+        fn snapshot(&self) -> BufferData {
+            BufferData {
+                text: self.text.clone(),
+                graphemes: self.graphemes.clone(),
+                clusters: self.clusters.clone(),
+                glyphs: self.glyphs.clone(),
+                transform: self.transform,
+                spans: self.spans.clone(),
+                runs: self.runs.clone(),
+                font_id: self.font_id,
+                count: self.count,
+                color: self.color,
+                version: self.version,
+                transform_version: self.transform_version,
+                size_px: self.size_px,
+                optical_tracking: self.optical_tracking,
+                tracking_px: self.tracking_px,
+                has_tracking: self.has_tracking,
+                shaped: self.shaped,
+                preshaped: self.preshaped,
+                line_index: self.line_index,
+                linked_lines: self.linked_lines.clone(),
+                features: self.features.clone(),
+                default_features: self.default_features.clone(),
+                whitespace: self.whitespace,
+                case: self.case,
+                sanitize: self.sanitize,
+                base_direction: self.base_direction,
+                highlights: self.highlights.clone(),
+                highlight_version: self.highlight_version,
+                case_locale: self.case_locale.clone(),
+                source: self.source.clone(),
+                epoch: self.epoch,
+                advance_width: Memo::new(),
+                ink_bounds: Memo::new(),
+                resolved: Memo::new(),
+                glyph_infos: Memo::new(),
+                pen_positions: Memo::new(),
+                visual_runs: Memo::new(),
+                #[cfg(feature = "debug-origins")]
+                origin: self.origin.clone(),
+                recycle: self.recycle,
+                intern_key: self.intern_key,
+                sharers: Vec::new(),
+                retain_text: self.retain_text,
+                dropped_text_len: self.dropped_text_len,
+                preedit: self.preedit.clone(),
+                visible_range: self.visible_range.clone(),
+                shaped_window: self.shaped_window.clone(),
+                user_data: None,
+            }
+        }
+
+        /// Buffer data with already shaped glyphs, such as from a `ShapedDump`.
+        fn from_glyphs(font_id: usize, text: String, glyphs: Vec<GlyphPosition>, transform: Option<na::Projective3<f32>>, color: na::Vector4<u8>) -> BufferData {
+            let mut data = BufferData::new(font_id, text, Vec::new(), transform, color);
+            data.segment_graphemes();
+            data.clusters.extend(glyphs.iter().map(|g| g.cluster));
+            data.clusters.sort_unstable();
+            data.clusters.dedup();
+            data.glyphs = glyphs;
+            data.shaped = true;
+            data.preshaped = true;
+            data
+        }
+
+        fn segment_graphemes(&mut self) {
+            use unicode_segmentation::UnicodeSegmentation;
+
+            self.graphemes.clear();
+            self.graphemes.extend(self.text.grapheme_indices(true)
+                .map(|(pos, s)| GraphemeInfo { start_byte: pos as u32, len: s.len() as u32 }));
+        }
+
+        fn segment(&mut self, fonts: &MetroHashMap<usize, FontData>) {
+            self.segment_graphemes();
+
+            let mut clusters = ::std::mem::replace(&mut self.clusters, Vec::new());
+            clusters.clear();
+            for run in &self.runs {
+                clusters.extend(run.shaped_glyphs().iter().map(|glyph| self.grapheme_cluster(run.cluster(glyph))));
+            }
+            clusters.sort_unstable();
+            clusters.dedup();
+            self.clusters = clusters;
+
+            let mut glyphs = ::std::mem::replace(&mut self.glyphs, Vec::new());
+            glyphs.clear();
+            self.has_tracking = false;
+
+            for run in &self.runs {
+                let font = fonts.get(&run.font_id).expect("segment: run font should be loaded");
+                let tracking = font.tracking(self.tracking_px);
+                self.has_tracking |= tracking.is_some();
+                let tracking = if self.optical_tracking { tracking.unwrap_or(0) } else { 0 };
+                let scale = self.span_units(fonts, run.span);
+                let shift = self.span_shift_units(fonts, run.span);
+
+                for shaped in run.shaped_glyphs() {
+                    let cluster = self.grapheme_cluster(run.cluster(shaped));
+
+                    let cluster_end = cluster + self.cluster_len(cluster);
+                    let byte_offset = self.source_offset(cluster as usize) as u32;
+
+                    let mut glyph = GlyphPosition {
+                        id: shaped.codepoint,
+                        cluster,
+                        byte_offset,
+                        len: self.source_offset(cluster_end as usize) as u32 - byte_offset,
+                        x_advance: shaped.x_advance,
+                        y_advance: shaped.y_advance,
+                        x_offset: shaped.x_offset,
+                        y_offset: shaped.y_offset,
+                        span: run.span,
+                        flags: GlyphFlags(shaped.mask & GLYPH_FLAG_UNSAFE_TO_BREAK),
+                    };
+                    hide_control_glyph(&mut glyph, &self.text[cluster as usize..]);
+                    if glyph.x_advance != 0 {
+                        glyph.x_advance += tracking;
+                    }
+                    if scale != 1.0 {
+                        scale_glyph(&mut glyph, scale);
+                    }
+                    glyph.y_offset += shift;
+
+                    glyphs.push(glyph);
+                }
+            }
+
+            self.glyphs = glyphs;
+            self.mark_preedit();
+            self.epoch += 1;
+        }
+
+        pub fn preedit(&self) -> Option<&Preedit> {
+            self.preedit.as_ref()
+        }
+
+        /// Sets the preedit of text already in the buffer and flags its glyphs.
+        fn set_preedit(&mut self, preedit: Option<Preedit>) {
+            self.preedit = preedit;
+            self.mark_preedit();
+        }
+
+        /// Flags the glyphs whose text is in the preedit, by source offset since the preedit
+        /// range is in `text` even with collapsed whitespace.
+        fn mark_preedit(&mut self) {
+            let range = self.preedit.as_ref().map(|preedit| preedit.range.clone());
+            for glyph in &mut self.glyphs {
+                let offset = glyph.byte_offset as usize;
+                match range {
+                    Some(ref range) if offset >= range.start && offset < range.end => glyph.flags.0 |= GLYPH_FLAG_PREEDIT,
+                    _ => glyph.flags.0 &= !GLYPH_FLAG_PREEDIT,
+                }
+            }
+        }
+
+        /// Start of the grapheme `cluster` is in, so that fonts without a glyph for a ZWJ emoji
+        /// sequence or a modifier still give it one cluster. `\r\n` keeps a cluster per char,
+        /// which layout needs to tell the pair from a lone `\r`.
+        fn grapheme_cluster(&self, cluster: u32) -> u32 {
+            let i = match self.graphemes.binary_search_by_key(&cluster, |g| g.start_byte) {
+                Ok(_) => return cluster,
+                Err(i) => i,
+            };
+            match i.checked_sub(1).map(|i| self.graphemes[i].start_byte) {
+                Some(start) if !self.text[start as usize..].starts_with(is_line_break_char) => start,
+                _ => cluster,
+            }
+        }
+
+        /// Grapheme boundaries that are also cluster boundaries, so that the caret
+        /// never lands inside a ligature.
+        fn cursor_positions(&self) -> Vec<usize> {
+            let mut positions = self.graphemes.iter()
+                .map(|g| g.start_byte)
+                .filter(|start| self.clusters.binary_search(start).is_ok())
+                .map(|start| start as usize)
+                .collect::<Vec<_>>();
+
+            if positions.first() != Some(&0) {
+                positions.insert(0, 0);
+            }
+            positions.push(self.shaped_len());
+            for position in &mut positions {
+                *position = self.source_offset(*position);
+            }
+            if let Some(first) = positions.first_mut() {
+                *first = 0;
+            }
+            positions.dedup();
+            positions
+        }
+
+        /// Byte length of the text covered by the cluster starting at `cluster`.
+        fn cluster_len(&self, cluster: u32) -> u32 {
+            let end = match self.clusters.binary_search(&cluster) {
+                Ok(i) => self.clusters.get(i + 1).cloned(),
+                Err(i) => self.clusters.get(i).cloned(),
+            };
+
+            end.unwrap_or(self.shaped_len() as u32) - cluster
+        }
+
+        /// Length of the shaped text, also after it was dropped.
+        /// Graphemes in `cluster`.
+        fn cluster_graphemes(&self, cluster: u32) -> u32 {
+            let index = |byte: u32| match self.graphemes.binary_search_by_key(&byte, |g| g.start_byte) {
+                Ok(i) | Err(i) => i,
+            };
+            (index(cluster + self.cluster_len(cluster)) - index(cluster)) as u32
+        }
+
+        fn shaped_len(&self) -> usize {
+            self.dropped_text_len.unwrap_or(self.text.len())
+        }
+
+        pub fn has_text(&self) -> bool {
+            self.dropped_text_len.is_none()
+        }
+
+        /// Frees the text of a shaped buffer that does not retain it. Source offsets of
+        /// collapsed whitespace are kept for carets.
+        fn drop_unretained_text(&mut self) {
+            if self.retain_text || !self.shaped || !self.has_text() {
+                return;
+            }
+            // the properties are guessed from the text
+            self.resolved_runs();
+            self.dropped_text_len = Some(self.text.len());
+            self.text = String::new();
+            if let Some(ref mut source) = self.source {
+                source.text = String::new();
+            }
+        }
+
+        pub fn resolved_runs(&self) -> Vec<(::std::ops::Range<usize>, ResolvedShape)> {
+            let text = &self.text;
+            let base = self.base_direction;
+            self.resolved.get(self.epoch, || resolved_shape::resolve(text, base))
+        }
+
+        pub fn visual_runs(&self) -> Rc<[VisualRun]> {
+            self.visual_runs.get(self.epoch, || {
+                let resolved = self.resolved_runs();
+                Rc::from(resolved_shape::visual_runs(&self.glyphs, &resolved, |span| self.span_font_id(span), |glyph| self.highlight_of(glyph)))
+            })
+        }
+
+        /// Replaces the text and reshapes it, unless the text is unchanged.
+        ///
+        /// Returns the font ids of the discarded spans.
+        pub fn replace(&mut self, fonts: &MetroHashMap<usize, FontData>, cache: &mut ShapingCache, text: Cow<str>) -> Vec<usize> {
+            if self.has_text() && self.text() == text {
+                return Vec::new();
+            }
+
+            self.version += 1;
+            self.dropped_text_len = None;
+            self.preedit = None;
+            match (self.transforms_text(), text) {
+                (true, text) => {
+                    let (shaped, source) = self.shaping_input(text.into_owned());
+                    self.text = shaped;
+                    self.source = source;
+                },
+                (false, Cow::Borrowed(text)) => {
+                    self.text.clear();
+                    self.text.push_str(text);
+                },
+                (false, Cow::Owned(text)) => self.text = text,
+            }
+            self.snap_features();
+            let released = self.take_span_font_ids();
+            if self.shaped {
+                self.shape(fonts, cache);
+            }
+            released
+        }
+
+        /// Replaces `range` of the text, reshaping only the glyphs between the closest
+        /// safe-to-break boundaries around the edit.
+        ///
+        /// Buffers with spans or right-to-left glyph order are reshaped completely.
+        pub fn splice(&mut self, fonts: &MetroHashMap<usize, FontData>, cache: &mut ShapingCache, range: ::std::ops::Range<usize>, replacement: &str) {
+            if range.start == range.end && replacement.is_empty() {
+                return;
+            }
+
+            let delta = replacement.len() as i32 - (range.end - range.start) as i32;
+            let ranged_features = self.features.iter().any(|f| f.range.is_some());
+            // an edit can change the direction of text around it up to the paragraph ends, other
+            // paragraphs keep their glyph order
+            let paragraphs = self.edited_paragraphs(&range);
+            let has_rtl = self.text[paragraphs.clone()].chars().chain(replacement.chars()).any(bidi::is_rtl_char);
+            let window = if self.shaped && !self.preshaped && !cache.word_cache() && self.spans.is_empty() && self.visible_range.is_none() && !ranged_features && !has_rtl { self.splice_window(&range, &paragraphs) } else { None };
+
+            self.version += 1;
+            self.text.replace_range(range.clone(), replacement);
+            for span in &mut self.spans {
+                span.range.start = splice_position(span.range.start, &range, 0, delta);
+                span.range.end = splice_position(span.range.end, &range, replacement.len(), delta);
+            }
+            for feature_range in self.features.iter_mut().filter_map(|f| f.range.as_mut()) {
+                feature_range.start = splice_position(feature_range.start, &range, 0, delta);
+                feature_range.end = splice_position(feature_range.end, &range, replacement.len(), delta);
+            }
+            // replacing the preedit exactly commits it, other edits touching it drop it
+            self.preedit = match self.preedit.take() {
+                Some(ref preedit) if range == preedit.range => None,
+                Some(mut preedit) => {
+                    if range.end <= preedit.range.start {
+                        preedit.range = (preedit.range.start as i32 + delta) as usize..(preedit.range.end as i32 + delta) as usize;
+                        Some(preedit)
+                    } else if range.start >= preedit.range.end {
+                        Some(preedit)
+                    } else {
+                        None
+                    }
+                }
+                None => None,
+            };
+
+            let (start, end) = match window {
+                Some(window) => window,
+                None => {
+                    if self.shaped {
+                        self.shape(fonts, cache);
+                    }
+                    return;
+                },
+            };
+
+            let start_byte = self.glyphs.get(start).map_or(0, |g| g.cluster as usize);
+            let end_byte = match self.glyphs.get(end) {
+                Some(g) => (g.cluster as i32 + delta) as usize,
+                None => self.text.len(),
+            };
+
+            let font = &fonts.get(&self.font_id).expect("splice: buffer font should be loaded");
+            let features = hb_features(&self.shaping_features());
+            let shaped = {
+                let text = &self.text;
+                let base = self.base_direction;
+                cache.shape(self.font_id, end_byte - start_byte, |pool| shape_range(font, pool, text, start_byte..end_byte, &features, base))
+            };
+
+            let mut runs = self.run_pieces(0..start);
+            runs.push(ShapedRun::new(0, self.font_id, Rc::new(shaped)));
+            for mut run in self.run_pieces(end..self.glyphs.len()) {
+                run.byte_offset += delta;
+                runs.push(run);
+            }
+            self.runs = merge_runs(runs);
+
+            self.segment(fonts);
+        }
+
+        /// Glyph indices of the safe-to-break boundaries before and after an edit of `range`,
+        /// `None` if the glyphs are not in logical order.
+        fn splice_window(&self, range: &::std::ops::Range<usize>, paragraphs: &::std::ops::Range<usize>) -> Option<(usize, usize)> {
+            // only the glyphs of the edited paragraphs have to be in logical order, paragraphs
+            // are shaped on their own so their ends are always boundaries
+            let first = self.glyphs.iter().position(|g| g.cluster as usize >= paragraphs.start).unwrap_or(self.glyphs.len());
+            let last = first + self.glyphs[first..].iter().take_while(|g| (g.cluster as usize) < paragraphs.end).count();
+            if self.glyphs[first..last].windows(2).any(|w| w[0].cluster > w[1].cluster) {
+                return None;
+            }
+
+            let is_boundary = |i: usize| {
+                i == first || i == last
+                    || self.glyphs[i - 1].cluster != self.glyphs[i].cluster && !self.glyphs[i].flags.unsafe_to_break()
+            };
+
+            // glyphs next to the edit may join with the replacement, so both boundaries
+            // are taken outside the clusters that touch the edit
+            let start = (first..last).rev()
+                .filter(|&i| (self.glyphs[i].cluster as usize) < range.start && is_boundary(i))
+                .nth(1)
+                .unwrap_or(first);
+            let end = (start..last + 1)
+                .filter(|&i| i == last || self.glyphs[i].cluster as usize > range.end)
+                .filter(|&i| is_boundary(i))
+                .nth(1)
+                .unwrap_or(last);
+
+            Some((start, end))
+        }
+
+        /// Byte range of the paragraphs, with their separators, that an edit of `range` of the
+        /// text touches.
+        fn edited_paragraphs(&self, range: &::std::ops::Range<usize>) -> ::std::ops::Range<usize> {
+            let chunks = layout::paragraph_chunks(&self.text);
+            let start = chunks.iter().rev().find(|chunk| chunk.start <= range.start).map_or(0, |chunk| chunk.start);
+            let end = chunks.iter()
+                .find(|chunk| chunk.start >= start && chunk.end >= range.end)
+                .map_or(self.text.len(), |chunk| chunk.end);
+            start..end
+        }
+
+        /// Runs restricted to the output glyphs `range`, sharing the shaped glyphs.
+        fn run_pieces(&self, range: ::std::ops::Range<usize>) -> Vec<ShapedRun> {
+            let mut pieces = Vec::new();
+            let mut run_start = 0;
+
+            for run in &self.runs {
+                let run_end = run_start + run.glyphs.len();
+                let start = range.start.max(run_start);
+                let end = range.end.min(run_end);
+
+                if start < end {
+                    let mut piece = run.clone();
+                    piece.glyphs = run.glyphs.start + (start - run_start)..run.glyphs.start + (end - run_start);
+                    pieces.push(piece);
+                }
+
+                run_start = run_end;
+            }
+
+            pieces
+        }
+
+        /// Spans of the text, a single span of the buffer font if it has none.
+        fn span_list(&self) -> Vec<SpanData> {
+            if self.spans.is_empty() {
+                vec![SpanData { range: 0..self.text.len(), font_id: self.font_id, scale: 1.0, shift: 0.0 }]
+            } else {
+                self.spans.clone()
+            }
+        }
+
+        fn take_span_font_ids(&mut self) -> Vec<usize> {
+            self.spans.drain(..).map(|span| span.font_id).collect()
+        }
+
+        /// Shapes every span with its own font, or the whole text with the buffer font
+        /// if there are no spans.
+        pub fn shape(&mut self, fonts: &MetroHashMap<usize, FontData>, cache: &mut ShapingCache) {
+            self.shaped = true;
+            self.preshaped = false;
+            self.runs.clear();
+
+            let window = self.window_paragraphs();
+            self.shaped_window = window.as_ref().map(|paragraphs| match (paragraphs.first(), paragraphs.last()) {
+                (Some(first), Some(last)) => first.start..last.end,
+                _ => 0..0,
+            });
+
+            let text = &self.text;
+            let shaping_features = self.shaping_features();
+            let features = hb_features(&shaping_features);
+            let base = self.base_direction;
+
+            if let Some(paragraphs) = window {
+                // each paragraph is cached on its own, so scrolling back does not shape it again
+                let font = &fonts.get(&self.font_id).expect("shape: buffer font should be loaded");
+                let key = shaping_key(&shaping_features, base);
+                for paragraph in paragraphs {
+                    let paragraph_text = &text[paragraph.clone()];
+                    let shaped = cache.get_or_shape(self.font_id, paragraph_text, &key, |pool| shape_range(font, pool, paragraph_text, 0..paragraph_text.len(), &features, base));
+                    let mut run = ShapedRun::new(0, self.font_id, shaped);
+                    run.byte_offset = paragraph.start as i32;
+                    self.runs.push(run);
+                }
+            } else if self.spans.is_empty() {
+                let font = &fonts.get(&self.font_id).expect("shape: buffer font should be loaded");
+                let key = shaping_key(&shaping_features, base);
+                // words are joined in logical order, which is the visual one only in left-to-right
+                // paragraphs, so paragraphs with right-to-left text are shaped whole
+                let by_words = cache.word_cache() && shaping_features.iter().all(|f| f.range.is_none()) && base != BaseDirection::Rtl;
+                if by_words && text.chars().any(bidi::is_rtl_char) {
+                    for paragraph in layout::paragraph_chunks(text).into_iter().filter(|chunk| !chunk.is_empty()) {
+                        let paragraph_text = &text[paragraph.clone()];
+                        let shaped = if paragraph_text.chars().any(bidi::is_rtl_char) {
+                            cache.get_or_shape(self.font_id, paragraph_text, &key, |pool| shape_range(font, pool, paragraph_text, 0..paragraph_text.len(), &features, base))
+                        } else {
+                            cache.get_or_shape_words(self.font_id, paragraph_text, &key, |pool, word| shape_range(font, pool, word, 0..word.len(), &features, base))
+                        };
+                        let mut run = ShapedRun::new(0, self.font_id, shaped);
+                        run.byte_offset = paragraph.start as i32;
+                        self.runs.push(run);
+                    }
+                } else {
+                    let shaped = if by_words {
+                        cache.get_or_shape_words(self.font_id, text, &key, |pool, word| shape_range(font, pool, word, 0..word.len(), &features, base))
+                    } else {
+                        cache.get_or_shape(self.font_id, text, &key, |pool| shape_range(font, pool, text, 0..text.len(), &features, base))
+                    };
+                    self.runs.push(ShapedRun::new(0, self.font_id, shaped));
+                }
+            } else {
+                // spans are shaped with the surrounding text as context, so they are not cached
+                for (index, span) in self.spans.iter().enumerate() {
+                    let font = &fonts.get(&span.font_id).expect("shape: span font should be loaded");
+                    let shaped = cache.shape(span.font_id, span.range.len(), |pool| shape_range(font, pool, text, span.range.clone(), &features, base));
+                    self.runs.push(ShapedRun::new(index as u32, span.font_id, Rc::new(shaped)));
+                }
+            }
+
+            self.segment(fonts);
+        }
+
+        /// Paragraphs of the shaped text with their separators around the visible range and
+        /// `WINDOW_MARGIN` bytes on each side, `None` if the whole text is shaped.
+        fn window_paragraphs(&self) -> Option<Vec<::std::ops::Range<usize>>> {
+            let visible = self.visible_range.as_ref()?;
+            if !self.spans.is_empty() || !self.has_text() {
+                return None;
+            }
+            let source_len = self.text().len();
+            let start = self.shaped_offset(visible.start.min(source_len)).saturating_sub(WINDOW_MARGIN);
+            let end = self.shaped_offset(visible.end.min(source_len)) + WINDOW_MARGIN;
+
+            Some(layout::paragraph_chunks(&self.text).into_iter()
+                .filter(|paragraph| paragraph.start < end && paragraph.end > start)
+                .collect())
+        }
+
+        /// Whether the shaped window still has half the margin around the visible range, or
+        /// reaches the text end on that side.
+        fn visible_in_window(&self) -> bool {
+            match (self.visible_range.as_ref(), self.shaped_window.as_ref()) {
+                (Some(visible), Some(window)) => {
+                    let source_len = self.text().len();
+                    let start = self.shaped_offset(visible.start.min(source_len));
+                    let end = self.shaped_offset(visible.end.min(source_len));
+                    (window.start == 0 || start >= window.start + WINDOW_MARGIN / 2)
+                        && (window.end == self.text.len() || end + WINDOW_MARGIN / 2 <= window.end)
+                },
+                _ => false,
+            }
+        }
+
+        /// Sets the range to shape around, reshaping if the window has to move.
+        pub fn set_visible_range(&mut self, fonts: &MetroHashMap<usize, FontData>, cache: &mut ShapingCache, range: Option<::std::ops::Range<usize>>) {
+            self.visible_range = range;
+            let unchanged = self.visible_in_window() || self.visible_range.is_none() && self.shaped_window.is_none();
+            if unchanged || !self.shaped || self.preshaped || !self.spans.is_empty() {
+                return;
+            }
+            self.version += 1;
+            self.shape(fonts, cache);
+        }
+
+        pub fn user_data(&self) -> Option<&dyn Any> {
+            self.user_data.as_ref().map(|data| &**data)
+        }
+
+        /// Range of the shaped text the glyphs come from, `None` if it is the whole text.
+        pub fn shaped_window(&self) -> Option<::std::ops::Range<usize>> {
+            self.shaped_window.clone().filter(|window| *window != (0..self.text.len()))
+        }
+
+        /// Appends text, reshaping only from the last safe-to-break boundary before the end.
+        pub fn append(&mut self, fonts: &MetroHashMap<usize, FontData>, cache: &mut ShapingCache, text: &str) {
+            if text.is_empty() {
+                return;
+            }
+
+            // right-to-left text only changes the glyph order of its own paragraph
+            let paragraph_start = layout::paragraph_chunks(&self.text).into_iter().rev()
+                .find(|chunk| !chunk.is_empty())
+                .map_or(0, |chunk| chunk.start);
+
+            self.version += 1;
+            self.text.push_str(text);
+            if let Some(span) = self.spans.last_mut() {
+                span.range.end = self.text.len();
+            }
+            if !self.shaped {
+                return;
+            }
+
+            // words shaped on their own are kept whole-text consistent by shaping again
+            let has_rtl = self.text[paragraph_start..].chars().any(bidi::is_rtl_char);
+            let restart = match self.runs.last() {
+                Some(run) if !cache.word_cache() && self.visible_range.is_none() && !has_rtl => BufferData::append_restart_glyph(run, paragraph_start),
+                _ => None,
+            };
+
+            let restart = match restart {
+                Some(restart) => restart,
+                None => return self.shape(fonts, cache),
+            };
+
+            let (span, font_id, restart_byte) = {
+                let run = self.runs.last_mut().expect("append: restart glyph implies a run");
+                let restart_byte = run.cluster(&run.shaped.glyphs[restart]) as usize;
+                run.glyphs.end = restart;
+                (run.span, run.font_id, restart_byte)
+            };
+
+            let font = &fonts.get(&font_id).expect("append: run font should be loaded");
+            let features = hb_features(&self.shaping_features());
+            let shaped = {
+                let text = &self.text;
+                let base = self.base_direction;
+                cache.shape(font_id, text.len() - restart_byte, |pool| shape_range(font, pool, text, restart_byte..text.len(), &features, base))
+            };
+            self.runs.push(ShapedRun::new(span, font_id, Rc::new(shaped)));
+            self.runs = merge_runs(::std::mem::replace(&mut self.runs, Vec::new()));
+
+            self.segment(fonts);
+        }
+
+        /// Glyph index of the last cluster start in the left-to-right glyphs of a run from byte
+        /// `paragraph_start` on where shaping can restart, never the first glyph of the run.
+        fn append_restart_glyph(run: &ShapedRun, paragraph_start: usize) -> Option<usize> {
+            let glyphs = run.shaped_glyphs();
+            let first = glyphs.iter().position(|glyph| run.cluster(glyph) as usize >= paragraph_start).unwrap_or(glyphs.len());
+            if glyphs[first..].windows(2).any(|w| w[0].cluster > w[1].cluster) {
+                return None;
+            }
+
+            (first.max(1)..glyphs.len()).rev()
+                .find(|&i| glyphs[i - 1].cluster != glyphs[i].cluster && !unsafe_to_break(&glyphs[i]))
+                .map(|i| run.glyphs.start + i)
+        }
+
+        /// Horizontal pen advance of all glyphs, computed once per shaping.
+        pub fn advance_width(&self) -> i32 {
+            let glyphs = &self.glyphs;
+            self.advance_width.get(self.epoch, || glyphs.iter().map(|g| g.x_advance).sum())
+        }
+
+        /// Memoized `advance_width`, `None` until computed after the last shaping.
+        pub fn cached_advance_width(&self) -> Option<i32> {
+            self.advance_width.peek(self.epoch)
+        }
+
+        fn measure(&self, output: &mut Vec<GlyphPosition>) -> Option<(i32, i32)> {
+            output.extend_from_slice(&self.glyphs);
+            self.pen_end()
+        }
+
+        /// Advance of the glyphs of the whitespace at the ends of the text `options` trims, all
+        /// of them for whitespace only text.
+        fn trimmed_advance(&self, options: MeasureOptions) -> i32 {
+            if !self.has_text() {
+                return 0;
+            }
+            let text = &self.text;
+            let start = if options.trim_leading { text.len() - text.trim_start().len() } else { 0 };
+            let end = if options.trim_trailing { text.trim_end().len() } else { text.len() };
+
+            self.glyphs.iter()
+                .filter(|glyph| (glyph.cluster as usize) < start || glyph.cluster as usize >= end)
+                .map(|glyph| glyph.x_offset + glyph.x_advance)
+                .sum()
+        }
+
+        /// Pen position after the last glyph, `None` for empty output.
+        fn pen_end(&self) -> Option<(i32, i32)> {
+            let mut last_glyph_pos = None;
+
+            for position in &self.glyphs {
+                last_glyph_pos = match last_glyph_pos {
+                    None => Some((position.x_offset + position.x_advance, position.y_offset + position.y_advance)),
+                    Some((x, y)) => Some((position.x_offset + position.x_advance + x, position.y_offset + position.y_advance + y)),
+                };
+            }
+
+            last_glyph_pos
+        }
+
+        /// Text as given to the buffer.
+        pub fn text(&self) -> &str {
+            match self.source {
+                Some(ref source) => &source.text,
+                None => &self.text,
+            }
+        }
+
+        /// Text passed to the shaper, which glyph clusters, spans and layout offsets refer to.
+        pub fn shaped_text(&self) -> &str {
+            &self.text
+        }
+
+        /// Byte offset in `text` of a byte offset in `shaped_text`.
+        pub fn source_offset(&self, shaped: usize) -> usize {
+            match self.source {
+                Some(ref source) => source.source_offset(shaped),
+                None => shaped,
+            }
+        }
+
+        /// Byte offset in `shaped_text` of a byte offset in `text`.
+        pub fn shaped_offset(&self, source: usize) -> usize {
+            match self.source {
+                Some(ref source) => source.collapsed_offset(source),
+                None => source,
+            }
+        }
+
+        pub fn whitespace(&self) -> Whitespace {
+            self.whitespace
+        }
+
+        pub fn case(&self) -> Case {
+            self.case
+        }
+
+        pub fn sanitize(&self) -> bool {
+            self.sanitize
+        }
+
+        pub fn base_direction(&self) -> BaseDirection {
+            self.base_direction
+        }
+
+        pub fn highlights(&self) -> &[(::std::ops::Range<usize>, u32)] {
+            &self.highlights
+        }
+
+        pub fn highlight_version(&self) -> u64 {
+            self.highlight_version
+        }
+
+        /// Replaces the highlights, the visual runs are split again on next use.
+        pub fn set_highlights(&mut self, spans: &[(::std::ops::Range<usize>, u32)]) {
+            self.highlights = spans.to_vec();
+            self.highlight_version += 1;
+            self.visual_runs = Memo::new();
+        }
+
+        /// Tag of the last highlight overlapping the cluster of `glyph`.
+        pub fn highlight_of(&self, glyph: &GlyphPosition) -> Option<u32> {
+            let start = glyph.byte_offset as usize;
+            let end = (glyph.byte_offset + glyph.len) as usize;
+            self.highlights.iter().rev()
+                .find(|&&(ref range, _)| range.start < end.max(start + 1) && start < range.end)
+                .map(|&(_, tag)| tag)
+        }
+
+        /// Whether the shaped text is a transform of the text, see `shaping_input`.
+        pub fn transforms_text(&self) -> bool {
+            self.whitespace == Whitespace::Collapse || self.sanitize || self.case != Case::None
+        }
+
+        /// Text to shape for `text` with the whitespace handling, sanitizing and case of the
+        /// buffer, and its source if they change the text.
+        fn shaping_input(&self, text: String) -> (String, Option<CollapsedSource>) {
+            let (text, source) = match self.whitespace {
+                Whitespace::Collapse => {
+                    let (collapsed, source) = CollapsedSource::collapse(text);
+                    (collapsed, Some(source))
+                },
+                Whitespace::Preserve => (text, None),
+            };
+            let (text, source) = if self.sanitize {
+                let (sanitized, offsets) = validation::sanitize(&text);
+                let mut source = source.unwrap_or_else(|| CollapsedSource::identity(text));
+                source.remap(&offsets);
+                (sanitized, Some(source))
+            } else {
+                (text, source)
+            };
+            if self.case == Case::None {
+                return (text, source);
+            }
+
+            let (mapped, offsets) = case::map_case(&text, self.case, self.case_locale.as_ref().map(String::as_str));
+            let mut source = source.unwrap_or_else(|| CollapsedSource::identity(text));
+            source.remap(&offsets);
+            (mapped, Some(source))
+        }
+
+        /// Length of the shaped text for a new text.
+        fn shaped_len_of(&self, text: &str) -> usize {
+            if self.transforms_text() {
+                self.shaping_input(text.to_string()).0.len()
+            } else {
+                text.len()
+            }
+        }
+
+        /// Switches between shaping the text as is and with collapsed whitespace, moving
+        /// span and feature ranges to the new shaped text.
+        pub fn set_whitespace(&mut self, fonts: &MetroHashMap<usize, FontData>, cache: &mut ShapingCache, whitespace: Whitespace) {
+            if self.whitespace == whitespace {
+                return;
+            }
+            self.whitespace = whitespace;
+            self.update_shaping_input(fonts, cache);
+        }
+
+        /// Sets the case mapping of the shaped text, moving span and feature ranges to it.
+        pub fn set_case(&mut self, fonts: &MetroHashMap<usize, FontData>, cache: &mut ShapingCache, case: Case, locale: Option<String>) {
+            let locale = if case == Case::None { None } else { locale };
+            if self.case == case && self.case_locale == locale {
+                return;
+            }
+            self.case = case;
+            self.case_locale = locale;
+            self.update_shaping_input(fonts, cache);
+        }
+
+        /// Switches sanitizing of the shaped text, moving span and feature ranges to it.
+        pub fn set_sanitize(&mut self, fonts: &MetroHashMap<usize, FontData>, cache: &mut ShapingCache, sanitize: bool) {
+            if self.sanitize == sanitize {
+                return;
+            }
+            self.sanitize = sanitize;
+            self.update_shaping_input(fonts, cache);
+        }
+
+        /// Sets the direction of the paragraphs, reshaping if the buffer was shaped.
+        pub fn set_base_direction(&mut self, fonts: &MetroHashMap<usize, FontData>, cache: &mut ShapingCache, base: BaseDirection) {
+            if self.base_direction == base {
+                return;
+            }
+            self.base_direction = base;
+            self.version += 1;
+            if self.shaped {
+                self.shape(fonts, cache);
+            }
+        }
+
+        /// Transforms the text again after a whitespace, sanitizing or case change and reshapes it.
+        fn update_shaping_input(&mut self, fonts: &MetroHashMap<usize, FontData>, cache: &mut ShapingCache) {
+            let old = self.source.take();
+            let map_old = |offset: usize| old.as_ref().map_or(offset, |source| source.source_offset(offset));
+            let text = match old {
+                Some(ref source) => source.text.clone(),
+                None => self.text.clone(),
+            };
+
+            let (text, source) = self.shaping_input(text);
+            let map_new = |offset: usize| source.as_ref().map_or(offset, |source| source.collapsed_offset(offset));
+
+            for range in self.spans.iter_mut().map(|span| &mut span.range).chain(self.features.iter_mut().filter_map(|f| f.range.as_mut())) {
+                *range = map_new(map_old(range.start))..map_new(map_old(range.end));
+            }
+            if let Some(span) = self.spans.last_mut() {
+                span.range.end = text.len();
+            }
+
+            self.text = text;
+            self.source = source;
+            self.version += 1;
+            if self.shaped {
+                self.shape(fonts, cache);
+            }
+        }
+
+        pub fn count(&self) -> usize {
+            self.count
+        }
+
+        pub fn font_id(&self) -> usize {
+            self.font_id
+        }
+
+        pub fn version(&self) -> u64 {
+            self.version
+        }
+
+        pub fn transform_version(&self) -> u64 {
+            self.transform_version
+        }
+
+        pub fn line_index(&self) -> Option<usize> {
+            self.line_index
+        }
+
+        pub fn features(&self) -> &[Feature] {
+            &self.features
+        }
+
+        /// Features shaping uses, the buffer features over the container defaults.
+        pub fn shaping_features(&self) -> Vec<Feature> {
+            merge_features(&self.default_features, &self.features)
+        }
+
+        /// Sets the container defaults, marking the buffer for reshaping when next read if it
+        /// can be reshaped. Returns whether it was marked.
+        fn set_default_features(&mut self, defaults: Rc<Vec<Feature>>) -> bool {
+            self.default_features = defaults;
+            if !self.shaped || self.preshaped || !self.has_text() {
+                return false;
+            }
+            self.shaped = false;
+            self.version += 1;
+            true
+        }
+
+        /// Sets whether and at which size `trak` tracking applies, marking the buffer for
+        /// reshaping if its advances change. Returns whether they do.
+        fn set_tracking(&mut self, enabled: bool, px_size: f32) -> bool {
+            let changed = enabled != self.optical_tracking || (enabled && px_size != self.tracking_px);
+            self.optical_tracking = enabled;
+            self.tracking_px = px_size;
+            if !changed || !self.has_tracking || !self.shaped || self.preshaped || !self.has_text() {
+                return false;
+            }
+            self.shaped = false;
+            self.version += 1;
+            true
+        }
+
+        /// Runs `change` with reshaping deferred, then shapes once if the buffer was shaped and
+        /// counts any changes as one version.
+        pub fn batch<R, F>(&mut self, fonts: &MetroHashMap<usize, FontData>, cache: &mut ShapingCache, change: F) -> R
+            where F: FnOnce(&mut BufferData, &MetroHashMap<usize, FontData>, &mut ShapingCache) -> R
+        {
+            let (shaped, version) = (self.shaped, self.version);
+            self.shaped = false;
+            let result = change(self, fonts, cache);
+            self.shaped = shaped;
+
+            if self.version != version {
+                self.version = version + 1;
+                if shaped {
+                    self.shape(fonts, cache);
+                }
+            }
+            result
+        }
+
+        /// Replaces the features, reshaping if the buffer was shaped.
+        pub fn set_features(&mut self, fonts: &MetroHashMap<usize, FontData>, cache: &mut ShapingCache, features: Vec<Feature>) {
+            self.features = features;
+            self.snap_features();
+            self.version += 1;
+            if self.shaped {
+                self.shape(fonts, cache);
+            }
+        }
+
+        /// Removes ranged features that end past `text_len`, returning them.
+        pub fn take_features_outside(&mut self, text_len: usize) -> Vec<Feature> {
+            let (outside, inside): (Vec<_>, Vec<_>) = self.features.drain(..)
+                .partition(|f| feature_outside(f, text_len));
+            self.features = inside;
+            outside
+        }
+
+        fn snap_features(&mut self) {
+            let text = &self.text;
+            for range in self.features.iter_mut().filter_map(|f| f.range.as_mut()) {
+                *range = snap_to_graphemes(text, range.clone());
+            }
+        }
+
+        /// Spans overlapping `range`, clipped and relative to its start.
+        /// `range` of the shaped text widened to the clusters it overlaps.
+        fn cluster_range(&self, range: ::std::ops::Range<usize>) -> ::std::ops::Range<usize> {
+            let start = self.clusters.iter().rev().map(|&c| c as usize).find(|&c| c <= range.start).unwrap_or(0);
+            let end = self.clusters.iter().map(|&c| c as usize).find(|&c| c >= range.end).unwrap_or(self.text.len());
+            start..end.max(start)
+        }
+
+        /// Shaping settings of the line buffer of `range` of the shaped text, ranged features
+        /// clipped and relative to its start.
+        fn line_settings(&self, range: ::std::ops::Range<usize>) -> LineSettings {
+            let features = self.features.iter()
+                .filter_map(|feature| match feature.range {
+                    None => Some(feature.clone()),
+                    Some(ref r) if r.start < range.end && r.end > range.start => Some(Feature {
+                        range: Some(r.start.max(range.start) - range.start..r.end.min(range.end) - range.start),
+                        ..feature.clone()
+                    }),
+                    Some(_) => None,
+                })
+                .collect();
+
+            LineSettings {
+                features,
+                whitespace: self.whitespace,
+                case: self.case,
+                case_locale: self.case_locale.clone(),
+                sanitize: self.sanitize,
+                base_direction: self.base_direction,
+            }
+        }
+
+        /// Applies the settings of the paragraph this line buffer was split from, reshaping
+        /// once if any changed.
+        fn set_line_settings(&mut self, fonts: &MetroHashMap<usize, FontData>, cache: &mut ShapingCache, settings: LineSettings) {
+            self.batch(fonts, cache, |buffer, fonts, cache| {
+                buffer.set_whitespace(fonts, cache, settings.whitespace);
+                buffer.set_case(fonts, cache, settings.case, settings.case_locale);
+                buffer.set_sanitize(fonts, cache, settings.sanitize);
+                buffer.set_base_direction(fonts, cache, settings.base_direction);
+                if buffer.features != settings.features {
+                    buffer.set_features(fonts, cache, settings.features);
+                }
+            });
+        }
+
+        fn spans_in(&self, range: ::std::ops::Range<usize>) -> Vec<SpanData> {
+            self.spans.iter()
+                .filter(|span| span.range.start < range.end && span.range.end > range.start)
+                .map(|span| SpanData {
+                    range: span.range.start.max(range.start) - range.start..span.range.end.min(range.end) - range.start,
+                    font_id: span.font_id,
+                    scale: span.scale,
+                    shift: span.shift,
+                })
+                .collect()
+        }
+
+        pub fn is_shaped(&self) -> bool {
+            self.shaped
+        }
+
+        pub fn is_preshaped(&self) -> bool {
+            self.preshaped
+        }
+
+        /// Approximate heap bytes of the glyph output and segmentation data.
+        fn glyph_bytes(&self) -> usize {
+            use std::mem::size_of;
+
+            self.glyphs.capacity() * size_of::<GlyphPosition>()
+                + self.graphemes.capacity() * size_of::<GraphemeInfo>()
+                + self.clusters.capacity() * size_of::<u32>()
+                + self.runs.capacity() * size_of::<ShapedRun>()
+        }
+
+        /// Shapes the whole text with a different font, dropping any spans.
+        ///
+        /// Returns the font ids that are no longer referenced by this buffer.
+        pub fn set_font(&mut self, fonts: &MetroHashMap<usize, FontData>, cache: &mut ShapingCache, font_id: usize) -> Vec<usize> {
+            let mut released = self.take_span_font_ids();
+            released.push(self.font_id);
+            self.font_id = font_id;
+            self.version += 1;
+            if self.shaped {
+                self.shape(fonts, cache);
+            }
+            released
+        }
+
+        /// Shaped glyphs of all runs, in output order.
+        pub fn glyphs(&self) -> &[GlyphPosition] {
+            &self.glyphs
+        }
+
+        /// Appends glyphs whose clusters overlap `range` to `output`.
+        pub fn glyphs_in_range(&self, range: ::std::ops::Range<usize>, output: &mut Vec<GlyphPosition>) -> GlyphRangeInfo {
+            let mut info = GlyphRangeInfo {
+                pen_x: 0,
+                pen_y: 0,
+                byte_range: range.start..range.start,
+                clipped_start: false,
+                clipped_end: false,
+            };
+
+            let (mut pen_x, mut pen_y) = (0, 0);
+            let mut found = false;
+
+            for glyph in &self.glyphs {
+                let start = glyph.byte_offset as usize;
+                let end = start + glyph.len as usize;
+                let overlaps = start < range.end && end > range.start
+                    || glyph.len == 0 && start >= range.start && start < range.end;
+
+                if overlaps {
+                    if !found {
+                        found = true;
+                        info.pen_x = pen_x;
+                        info.pen_y = pen_y;
+                        info.byte_range = start..end;
+                    }
+                    info.byte_range.start = info.byte_range.start.min(start);
+                    info.byte_range.end = info.byte_range.end.max(end);
+                    output.push(*glyph);
+                }
+
+                pen_x += glyph.x_advance;
+                pen_y += glyph.y_advance;
+            }
+
+            info.clipped_start = info.byte_range.start < range.start;
+            info.clipped_end = info.byte_range.end > range.end;
+            info
+        }
+
+        pub fn cluster_range(&self, glyph_index: usize) -> ::std::ops::Range<usize> {
+            let glyph = self.glyphs.get(glyph_index).expect("cluster_range: glyph_index should be less than the glyph count");
+            glyph.byte_offset as usize..(glyph.byte_offset + glyph.len) as usize
+        }
+
+        pub fn cluster_advances(&self, output: &mut Vec<(::std::ops::Range<usize>, i32)>) {
+            let from = output.len();
+            output.extend(self.glyphs.iter().map(|g| (g.byte_offset as usize..(g.byte_offset + g.len) as usize, g.x_advance)));
+            output[from..].sort_by_key(|&(ref range, _)| range.start);
+
+            // glyphs of a cluster are next to each other after sorting
+            let mut merged = from;
+            for i in from..output.len() {
+                if merged > from && output[merged - 1].0.start == output[i].0.start {
+                    let (range, advance) = output[i].clone();
+                    let cluster = &mut output[merged - 1];
+                    cluster.0.end = cluster.0.end.max(range.end);
+                    cluster.1 += advance;
+                } else {
+                    output.swap(merged, i);
+                    merged += 1;
+                }
+            }
+            output.truncate(merged);
+        }
+
+        /// Glyph index range spanning the glyphs whose clusters overlap `range`, matching
+        /// them like `glyphs_in_range`.
+        pub fn glyph_range_for_bytes(&self, range: ::std::ops::Range<usize>) -> ::std::ops::Range<usize> {
+            let overlaps = |glyph: &GlyphPosition| {
+                let start = glyph.byte_offset as usize;
+                let end = start + glyph.len as usize;
+                start < range.end && end > range.start
+                    || glyph.len == 0 && start >= range.start && start < range.end
+            };
+
+            match self.glyphs.iter().position(|glyph| overlaps(glyph)) {
+                Some(first) => {
+                    let last = self.glyphs.iter().rposition(|glyph| overlaps(glyph)).expect("glyph_range_for_bytes: a glyph overlaps");
+                    first..last + 1
+                }
+                None => 0..0,
+            }
+        }
+
+        pub fn span_font_id(&self, span: u32) -> usize {
+            self.spans.get(span as usize).map(|s| s.font_id).unwrap_or(self.font_id)
+        }
+
+        pub fn span_scale(&self, span: u32) -> f32 {
+            self.spans.get(span as usize).map_or(1.0, |s| s.scale)
+        }
+
+        /// Multiplier from the font units of a span font to those of the buffer font, the
+        /// units of the glyph positions, with the span size applied.
+        pub fn span_units(&self, fonts: &MetroHashMap<usize, FontData>, span: u32) -> f32 {
+            let font_id = self.span_font_id(span);
+            if font_id == self.font_id {
+                return self.span_scale(span);
+            }
+            let units_per_em = |id: usize| fonts.get(&id).map_or(1.0, |font| font.metrics.units_per_em as f32);
+            self.span_scale(span) * units_per_em(self.font_id) / units_per_em(font_id)
+        }
+
+        /// Baseline shift of a span in the font units of the buffer font.
+        pub fn span_shift_units(&self, fonts: &MetroHashMap<usize, FontData>, span: u32) -> i32 {
+            let shift = self.spans.get(span as usize).map_or(0.0, |s| s.shift);
+            if shift == 0.0 {
+                return 0;
+            }
+            let units_per_em = fonts.get(&self.font_id).map_or(1.0, |font| font.metrics.units_per_em as f32);
+            (shift * units_per_em).round() as i32
+        }
+
+        pub fn span_font_ids<'r>(&'r self) -> impl Iterator<Item=usize> + 'r {
+            self.spans.iter().map(|s| s.font_id)
+        }
+    }
+
+    pub struct FontData {
+        pub fk_font: FontkitFont,
+        /// Harfbuzz font scaled to the font units, nothing with the simple shaper.
+        pub hb_font: ShapingFont,
+        pub metrics: Metrics,
+        pub family_name: String,
+        pub postscript_name: Option<String>,
+        pub properties: Properties,
+        pub count: usize,
+        /// Index of the face in a font collection file.
+        pub face_index: u32,
+        pub outlines: GlyphCache<Path>,
+        /// Outlines scaled to pixels, keyed by pixel size.
+        pub sized_outlines: GlyphCache<Path>,
+        #[cfg(feature = "tessellation")]
+        pub meshes: GlyphCache<GlyphMesh>,
+        #[cfg(feature = "tessellation")]
+        pub strokes: GlyphCache<GlyphMesh>,
+        pub sdfs: GlyphCache<Option<Rc<SdfBitmap>>>,
+        /// Character map lookups, cleared when it reaches the cache capacity.
+        char_glyphs: MetroHashMap<char, Option<u32>>,
+        cache_capacity: usize,
+        diagnostics: Rc<Diagnostics>,
+        /// Styles applied to outlines and advances because the face lacks them.
+        pub synthesis: SynthesisOptions,
+        /// Font file bytes, read on first use.
+        font_data: Option<Arc<Vec<u8>>>,
+        /// OpenType tables read on first use, `None` for missing ones.
+        tables: RefCell<MetroHashMap<[u8; 4], Option<Arc<[u8]>>>>,
+        /// Parsed `fvar` table, read on first use.
+        fvar: Option<Option<Rc<Fvar>>>,
+        /// Load order of the font, distinguishes fonts that reuse an id.
+        pub generation: u64,
+        /// Pixels per em of a sized font, whose units are 1/64 pixel, see `set_size_px`.
+        pub size_px: Option<f32>,
+        /// Font file units to units of this font, 1 unless sized.
+        pub units_scale: f32,
+        /// Vertical metrics set of `metrics`.
+        metrics_policy: MetricsPolicy,
+        /// How the face matched the last query that found this font.
+        pub last_match: Option<MatchDetails>,
+    }
+
+    impl FontData {
+        fn new(fk_font: FontkitFont, hb_font: ShapingFont, face_index: u32, cache_capacity: usize, diagnostics: Rc<Diagnostics>) -> FontData {
+            // shaping output is in font units regardless of the face defaults
+            #[cfg(not(feature = "simple-shaping"))]
+            let hb_font = {
+                let mut hb_font = hb_font;
+                let units_per_em = fk_font.metrics().units_per_em as i32;
+                hb_font.set_scale(units_per_em, units_per_em);
+                hb_font
+            };
+
+            FontData {
+                metrics: fk_font.metrics(),
+                family_name: fk_font.family_name(),
+                postscript_name: fk_font.postscript_name(),
+                properties: fk_font.properties(),
+                fk_font,
+                hb_font,
+                count: 1,
+                face_index,
+                outlines: GlyphCache::new(cache_capacity),
+                sized_outlines: GlyphCache::new(cache_capacity),
+                #[cfg(feature = "tessellation")]
+                meshes: GlyphCache::new(cache_capacity),
+                #[cfg(feature = "tessellation")]
+                strokes: GlyphCache::new(cache_capacity),
+                sdfs: GlyphCache::new(cache_capacity),
+                char_glyphs: MetroHashMap::default(),
+                cache_capacity,
+                diagnostics,
+                synthesis: SynthesisOptions::default(),
+                font_data: None,
+                tables: RefCell::new(MetroHashMap::default()),
+                fvar: None,
+                generation: 0,
+                size_px: None,
+                units_scale: 1.0,
+                metrics_policy: MetricsPolicy::default(),
+                last_match: None,
+            }
+        }
+
+        /// Copy for `FontsContainer::snapshot` over `hb_font` of the same face, sharing the font
+        /// data and tables, with empty glyph caches.
+        fn snapshot(&self, hb_font: ShapingFont, diagnostics: Rc<Diagnostics>) -> FontData {
+            let mut data = FontData::new(self.fk_font.clone(), hb_font, self.face_index, self.cache_capacity, diagnostics);
+            data.count = self.count;
+            data.synthesis = self.synthesis;
+            data.font_data = self.font_data.clone();
+            data.tables = RefCell::new(self.tables.borrow().clone());
+            data.fvar = self.fvar.clone();
+            data.generation = self.generation;
+            data.last_match = self.last_match.clone();
+            data.set_metrics_policy(self.metrics_policy);
+            if let Some(px_size) = self.size_px {
+                data.set_size_px(px_size);
+            }
+            data
+        }
+
+        /// Typo and hhea vertical metrics in the units of this font.
+        pub fn raw_vertical_metrics(&self) -> RawVerticalMetrics {
+            let os2 = self.table(*b"OS/2");
+            let hhea = self.table(*b"hhea");
+            RawVerticalMetrics::read(os2.as_ref().map(|t| &t[..]), hhea.as_ref().map(|t| &t[..])).scaled(self.units_scale)
+        }
+
+        /// Selects the vertical metrics of `metrics`, returns whether they changed.
+        pub fn set_metrics_policy(&mut self, policy: MetricsPolicy) -> bool {
+            let before = (self.metrics.ascent, self.metrics.descent, self.metrics.line_gap);
+            self.metrics_policy = policy;
+            self.update_metrics();
+            before != (self.metrics.ascent, self.metrics.descent, self.metrics.line_gap)
+        }
+
+        /// Metrics of the font file with the vertical metrics of the policy, in the units of
+        /// this font.
+        fn update_metrics(&mut self) {
+            let file_metrics = self.fk_font.metrics();
+            let vertical = self.raw_vertical_metrics().select(self.metrics_policy);
+            let scale = self.units_scale;
+
+            self.metrics = Metrics {
+                units_per_em: (file_metrics.units_per_em as f32 * scale).round() as u32,
+                ascent: vertical.map_or(file_metrics.ascent * scale, |v| v.ascent),
+                descent: vertical.map_or(file_metrics.descent * scale, |v| v.descent),
+                line_gap: vertical.map_or(file_metrics.line_gap * scale, |v| v.line_gap),
+                underline_position: file_metrics.underline_position * scale,
+                underline_thickness: file_metrics.underline_thickness * scale,
+                cap_height: file_metrics.cap_height * scale,
+                x_height: file_metrics.x_height * scale,
+                ..file_metrics
+            };
+        }
+
+        /// Makes the units of this font 1/64 pixel at `px_size`: the harfbuzz scale and ppem,
+        /// the metrics and the values read from the font file are converted.
+        pub fn set_size_px(&mut self, px_size: f32) {
+            let file_metrics = self.fk_font.metrics();
+            let units_per_em = (px_size * 64.0).round() as u32;
+            let scale = units_per_em as f32 / file_metrics.units_per_em as f32;
+
+            #[cfg(not(feature = "simple-shaping"))]
+            {
+                self.hb_font.set_scale(units_per_em as i32, units_per_em as i32);
+                let ppem = px_size.round().max(1.0) as u32;
+                self.hb_font.set_ppem(ppem, ppem);
+            }
+
+            self.units_scale = scale;
+            self.update_metrics();
+            self.size_px = Some(px_size);
+            self.clear_caches();
+        }
+
+        /// Synthesized or sized, not one of the faces fonts are matched against.
+        pub fn is_variant(&self) -> bool {
+            self.synthesis.is_any() || self.size_px.is_some()
+        }
+
+        /// Outline in the units of this font, without synthesis.
+        pub fn outline<B: PathBuilder>(&self, glyph_id: u32, hinting: HintingOptions, builder: &mut B) -> Result<(), GlyphLoadingError> {
+            if self.units_scale == 1.0 {
+                return self.fk_font.outline(glyph_id, hinting, builder);
+            }
+
+            let mut file_units = Path::builder();
+            self.fk_font.outline(glyph_id, hinting, &mut file_units)?;
+            copy_path(&FlatPathBuilder::build(file_units), self.units_scale, builder);
+            Ok(())
+        }
+
+        /// Font file bytes, copied from the loader once and shared afterwards.
+        pub fn copy_font_data(&mut self) -> Option<Arc<Vec<u8>>> {
+            if self.font_data.is_none() {
+                self.font_data = self.fk_font.copy_font_data();
+            }
+            self.font_data.clone()
+        }
+
+        /// Axes and named instances of the face, parsed once and shared afterwards.
+        pub fn fvar(&mut self) -> Option<Rc<Fvar>> {
+            if self.fvar.is_none() {
+                let face_index = self.face_index;
+                self.fvar = Some(self.copy_font_data().and_then(|data| Fvar::parse(&data, face_index)).map(Rc::new));
+            }
+            self.fvar.clone().and_then(|fvar| fvar)
+        }
+
+        /// Bytes of an OpenType table from the harfbuzz face, copied once and shared afterwards.
+        pub fn table(&self, tag: [u8; 4]) -> Option<Arc<[u8]>> {
+            if let Some(table) = self.tables.borrow().get(&tag) {
+                return table.clone();
+            }
+            let table = self.read_table(tag);
+            self.tables.borrow_mut().insert(tag, table.clone());
+            table
+        }
+
+        #[cfg(not(feature = "simple-shaping"))]
+        fn read_table(&self, tag: [u8; 4]) -> Option<Arc<[u8]>> {
+            let hb_tag = hb::Tag::new(tag[0] as char, tag[1] as char, tag[2] as char, tag[3] as char);
+            self.hb_font.face().table_with_tag(hb_tag)
+                .filter(|blob| !blob.is_empty())
+                .map(|blob| Arc::from(&blob[..]))
+        }
+
+        /// Reads the table from the font file, without harfbuzz.
+        #[cfg(feature = "simple-shaping")]
+        fn read_table(&self, tag: [u8; 4]) -> Option<Arc<[u8]>> {
+            let data = self.font_data.clone().or_else(|| self.fk_font.copy_font_data())?;
+            sfnt::find_table(&data, self.face_index, &tag)
+                .filter(|table| !table.is_empty())
+                .map(Arc::from)
+        }
+
+        /// Class of a glyph in the `GDEF` table, `None` if the font has no glyph classes.
+        pub fn glyph_class(&self, glyph_id: u32) -> Option<u16> {
+            self.table(*b"GDEF").and_then(|gdef| gdef::glyph_class(&gdef, glyph_id))
+        }
+
+        /// Caret positions inside a ligature glyph from the `GDEF` table, in font units.
+        pub fn ligature_carets(&self, glyph_id: u32) -> Vec<i32> {
+            let carets = self.table(*b"GDEF").and_then(|gdef| gdef::ligature_carets(&gdef, glyph_id)).unwrap_or_default();
+            if self.units_scale == 1.0 {
+                return carets;
+            }
+            carets.into_iter().map(|caret| (caret as f32 * self.units_scale).round() as i32).collect()
+        }
+
+        /// Pair kerning in font units, from `GPOS` or else the `kern` table.
+        pub fn kerning(&self, left: u32, right: u32) -> i32 {
+            let kerning = match self.table(*b"GPOS") {
+                Some(gpos) => kerning::gpos_kerning(&gpos, left, right),
+                None => self.table(*b"kern").and_then(|kern| kerning::kern_table_kerning(&kern, left, right)),
+            };
+            (kerning.unwrap_or(0) as f32 * self.units_scale).round() as i32
+        }
+
+        /// Optical tracking at `px_size` from the AAT `trak` table, `None` without the table.
+        pub fn tracking(&self, px_size: f32) -> Option<i32> {
+            let trak = self.table(*b"trak")?;
+            trak::tracking(&trak, px_size).map(|tracking| (tracking as f32 * self.units_scale).round() as i32)
+        }
+
+        /// Name of a glyph from the `post` table.
+        pub fn glyph_name(&self, glyph_id: u32) -> Option<String> {
+            self.table(*b"post").and_then(|post| debug_shape::post_glyph_name(&post, glyph_id))
+        }
+
+        /// Extra advance of synthetic bold in font units.
+        pub fn synthetic_advance(&self) -> i32 {
+            if self.synthesis.embolden {
+                synthesis::embolden_strength(self.metrics.units_per_em).round() as i32
+            } else {
+                0
+            }
+        }
+
+        /// Approximate heap bytes of all glyph caches of this font.
+        pub fn cache_bytes(&self) -> usize {
+            let mut bytes = self.outlines.bytes(path_bytes) + self.sized_outlines.bytes(path_bytes);
+            #[cfg(feature = "tessellation")]
+            {
+                bytes += self.meshes.bytes(mesh_bytes) + self.strokes.bytes(mesh_bytes);
+            }
+            bytes += self.sdfs.bytes(sdf_bytes);
+            bytes
+        }
+
+        /// Removes the least recently used entry of the glyph cache with the most entries,
+        /// returning its bytes as counted by `cache_bytes`.
+        pub fn evict_cached_glyph(&mut self) -> Option<usize> {
+            let largest = self.outlines.len().max(self.sized_outlines.len()).max(self.sdfs.len());
+            #[cfg(feature = "tessellation")]
+            let largest = largest.max(self.meshes.len()).max(self.strokes.len());
+            #[cfg(feature = "tessellation")]
+            {
+                if largest > 0 && self.meshes.len() == largest {
+                    return self.meshes.remove_oldest(mesh_bytes);
+                }
+                if largest > 0 && self.strokes.len() == largest {
+                    return self.strokes.remove_oldest(mesh_bytes);
+                }
+            }
+
+            if largest == 0 {
+                None
+            } else if self.outlines.len() == largest {
+                self.outlines.remove_oldest(path_bytes)
+            } else if self.sized_outlines.len() == largest {
+                self.sized_outlines.remove_oldest(path_bytes)
+            } else {
+                self.sdfs.remove_oldest(sdf_bytes)
+            }
+        }
+
+        pub fn outline_cached(&mut self, glyph_id: u32, hinting: HintingOptions) -> Result<Rc<Path>, GlyphLoadingError> {
+            if let Some(path) = self.outlines.get(glyph_id, hinting, 0) {
+                return Ok(path);
+            }
+
+            let mut builder = Path::builder();
+            self.outline(glyph_id, hinting, &mut builder)?;
+            let mut path = FlatPathBuilder::build(builder);
+            if self.synthesis.is_any() {
+                path = synthesis::synthesize_path(&path, self.synthesis, self.synthetic_advance() as f32);
+            }
+            let path = Rc::new(path);
+            self.outlines.insert(glyph_id, hinting, 0, path.clone());
+            Ok(path)
+        }
+
+        #[cfg(feature = "tessellation")]
+        pub fn glyph_mesh(&mut self, glyph_id: u32, hinting: HintingOptions, tolerance: f32) -> Result<Rc<GlyphMesh>, GlyphMeshError> {
+            let (bucket, tolerance) = mesh::tolerance_bucket(tolerance);
+            if let Some(mesh) = self.meshes.get(glyph_id, hinting, bucket) {
+                return Ok(mesh);
+            }
+
+            let path = self.outline_cached(glyph_id, hinting)?;
+            let mesh = Rc::new(mesh::tessellate_path(&path, tolerance)?);
+            self.meshes.insert(glyph_id, hinting, bucket, mesh.clone());
+            Ok(mesh)
+        }
+
+        #[cfg(feature = "tessellation")]
+        pub fn glyph_stroke_mesh(&mut self, glyph_id: u32, hinting: HintingOptions, width: f32, tolerance: f32) -> Result<Rc<GlyphMesh>, GlyphMeshError> {
+            let (variant, tolerance) = mesh::stroke_variant(width, tolerance);
+            if let Some(mesh) = self.strokes.get(glyph_id, hinting, variant) {
+                return Ok(mesh);
+            }
+
+            let path = self.outline_cached(glyph_id, hinting)?;
+            let mesh = Rc::new(mesh::stroke_path(&path, width, tolerance)?);
+            self.strokes.insert(glyph_id, hinting, variant, mesh.clone());
+            Ok(mesh)
+        }
+
+        pub fn glyph_sdf(&mut self, glyph_id: u32, size: SizeBucket, padding: u32, spread: f32) -> Option<Rc<SdfBitmap>> {
+            let variant = raster::sdf_variant(size, padding, spread);
+            if let Some(sdf) = self.sdfs.get(glyph_id, HintingOptions::None, variant) {
+                return (*sdf).clone();
+            }
+
+            let sdf = self.build_sdf(glyph_id, size.px_size(), padding, spread).map(Rc::new);
+            self.sdfs.insert(glyph_id, HintingOptions::None, variant, Rc::new(sdf.clone()));
+            sdf
+        }
+
+        fn build_sdf(&mut self, glyph_id: u32, px_size: f32, padding: u32, spread: f32) -> Option<SdfBitmap> {
+            let path = match self.outline_cached(glyph_id, HintingOptions::None) {
+                Ok(path) => path,
+                Err(e) => {
+                    self.diagnostics.report(FontsDiagnostic::OutlineFailed { glyph_id, reason: format!("{:?}", e) });
+                    return None;
+                }
+            };
+
+            let scale = px_size / self.metrics.units_per_em as f32;
+            let outline = raster::Outline::from_path(&path, 0.1 / scale)?;
+            let coverage = raster::rasterize(&outline, scale, (0.0, 0.0), padding);
+            let data = raster::signed_distance_field(&coverage, spread);
+
+            Some(SdfBitmap {
+                width: coverage.width,
+                height: coverage.height,
+                left: coverage.left,
+                top: coverage.top,
+                spread,
+                data,
+            })
+        }
+
+        pub fn rasterize_glyph(&mut self, glyph_id: u32, px_size: f32, hinting: HintingOptions, origin_offset: (f32, f32)) -> Option<GlyphBitmap> {
+            let path = match self.outline_cached(glyph_id, hinting) {
+                Ok(path) => path,
+                Err(e) => {
+                    self.diagnostics.report(FontsDiagnostic::OutlineFailed { glyph_id, reason: format!("{:?}", e) });
+                    return None;
+                }
+            };
+
+            let scale = px_size / self.metrics.units_per_em as f32;
+            let outline = raster::Outline::from_path(&path, 0.1 / scale)?;
+            let coverage = raster::rasterize(&outline, scale, origin_offset, 0);
+            let advance = self.fk_font.advance(glyph_id).map(|a| (a.x * self.units_scale + self.synthetic_advance() as f32) * scale).unwrap_or(0.0);
+
+            Some(GlyphBitmap {
+                width: coverage.width,
+                height: coverage.height,
+                left: coverage.left,
+                top: coverage.top,
+                advance,
+                data: coverage.to_alpha(),
+            })
+        }
+
+        /// Outline hinted for the size of `size` and scaled to pixels.
+        pub fn outline_at_size(&mut self, glyph_id: u32, size: SizeBucket, hinting: HintingOptions) -> Result<Rc<Path>, GlyphLoadingError> {
+            let px_size = size.px_size();
+            let hinting = hinting_for_size(hinting, px_size);
+            if let Some(path) = self.sized_outlines.get(glyph_id, hinting, size.key()) {
+                return Ok(path);
+            }
+
+            let outline = self.outline_cached(glyph_id, hinting)?;
+            let mut builder = Path::builder();
+            copy_path(&outline, px_size / self.metrics.units_per_em as f32, &mut builder);
+            let path = Rc::new(FlatPathBuilder::build(builder));
+            self.sized_outlines.insert(glyph_id, hinting, size.key(), path.clone());
+            Ok(path)
+        }
+
+        #[cfg(feature = "color-bitmaps")]
+        pub fn glyph_image(&mut self, glyph_id: u32, px_size: f32) -> Option<GlyphImage> {
+            let data = self.copy_font_data()?;
+            let scale = px_size / self.metrics.units_per_em as f32;
+            let advance = self.fk_font.advance(glyph_id).map(|a| (a.x * self.units_scale + self.synthetic_advance() as f32) * scale).unwrap_or(0.0);
+            color_bitmaps::glyph_image(&data, self.face_index, glyph_id, px_size, advance)
+        }
+
+        pub fn clear_caches(&mut self) {
+            self.outlines.clear();
+            self.sized_outlines.clear();
+            #[cfg(feature = "tessellation")]
+            self.meshes.clear();
+            #[cfg(feature = "tessellation")]
+            self.strokes.clear();
+            self.sdfs.clear();
+        }
+
+        /// Drops the caches of data built for a pixel size.
+        pub fn clear_sized_caches(&mut self) {
+            self.sized_outlines.clear();
+            self.sdfs.clear();
+        }
+
+        fn set_cache_capacity(&mut self, capacity: usize) {
+            self.outlines.set_capacity(capacity);
+            self.sized_outlines.set_capacity(capacity);
+            #[cfg(feature = "tessellation")]
+            self.meshes.set_capacity(capacity);
+            #[cfg(feature = "tessellation")]
+            self.strokes.set_capacity(capacity);
+            self.sdfs.set_capacity(capacity);
+            self.cache_capacity = capacity;
+            self.char_glyphs.clear();
+        }
+
+        /// Drops the caches keyed by size buckets.
+        fn clear_sized_caches(&mut self) {
+            self.sized_outlines.clear();
+            self.sdfs.clear();
+        }
+
+        /// Glyph id from the character map, `None` instead of the `.notdef` glyph.
+        pub fn glyph_for_char_cached(&mut self, c: char) -> Option<u32> {
+            if let Some(glyph) = self.char_glyphs.get(&c) {
+                return *glyph;
+            }
+
+            #[cfg(not(feature = "simple-shaping"))]
+            let glyph = self.hb_font.get_nominal_glyph(c);
+            #[cfg(feature = "simple-shaping")]
+            let glyph = self.fk_font.glyph_for_char(c);
+            let glyph = glyph.filter(|&glyph| glyph != 0);
+            if self.cache_capacity > 0 {
+                if self.char_glyphs.len() >= self.cache_capacity {
+                    self.char_glyphs.clear();
+                }
+                self.char_glyphs.insert(c, glyph);
+            }
+            glyph
+        }
+
+        pub fn glyph_bounds(&self, glyph_id: u32) -> Option<Bounds> {
+            let rect = self.fk_font.typographic_bounds(glyph_id).ok()?;
+            if rect.size.width <= 0.0 || rect.size.height <= 0.0 {
+                return None;
+            }
+
+            let s = self.units_scale;
+            let bounds = Bounds {
+                min_x: rect.origin.x * s,
+                min_y: rect.origin.y * s,
+                max_x: (rect.origin.x + rect.size.width) * s,
+                max_y: (rect.origin.y + rect.size.height) * s,
+            };
+            Some(synthesis::synthesize_bounds(bounds, self.synthesis, self.synthetic_advance() as f32))
+        }
+    }
+
+    /// Same hinting mode for a specific size; at 72 dpi points and pixels are the same.
+    fn hinting_for_size(hinting: HintingOptions, px_size: f32) -> HintingOptions {
+        match hinting {
+            HintingOptions::None => HintingOptions::None,
+            HintingOptions::Vertical(_) => HintingOptions::Vertical(px_size),
+            HintingOptions::VerticalSubpixel(_) => HintingOptions::VerticalSubpixel(px_size),
+            HintingOptions::Full(_) => HintingOptions::Full(px_size),
+        }
+    }
+
+    fn path_bytes(path: &Path) -> usize {
+        path.points().len() * ::std::mem::size_of::<Point>() + path.verbs().len() * ::std::mem::size_of::<lyon_path::Verb>()
+    }
+
+    #[cfg(feature = "tessellation")]
+    fn mesh_bytes(mesh: &GlyphMesh) -> usize {
+        mesh.vertices.len() * ::std::mem::size_of::<[f32; 2]>() + mesh.indices.len() * ::std::mem::size_of::<u32>()
+    }
+
+    fn sdf_bytes(sdf: &Option<Rc<SdfBitmap>>) -> usize {
+        sdf.as_ref().map_or(0, |sdf| sdf.data.len())
+    }
+
+    /// Replays `path` into `builder`, scaling all points.
+    pub fn copy_path<B: PathBuilder>(path: &Path, scale: f32, builder: &mut B) {
+        copy_path_at(path, scale, (0.0, 0.0), false, builder);
+    }
+
+    /// Copies `path` scaled, then mirrored vertically with `flip_y`, then moved by `offset`.
+    pub fn copy_path_at<B: PathBuilder>(path: &Path, scale: f32, offset: (f32, f32), flip_y: bool, builder: &mut B) {
+        let y_scale = if flip_y { -scale } else { scale };
+        let p = |p: Point| point(p.x * scale + offset.0, p.y * y_scale + offset.1);
+        let angle_sign = if flip_y { -1.0 } else { 1.0 };
+
+        for event in path.iter() {
+            match event {
+                PathEvent::MoveTo(to) => builder.move_to(p(to)),
+                PathEvent::LineTo(to) => builder.line_to(p(to)),
+                PathEvent::QuadraticTo(ctrl, to) => builder.quadratic_bezier_to(p(ctrl), p(to)),
+                PathEvent::CubicTo(ctrl1, ctrl2, to) => builder.cubic_bezier_to(p(ctrl1), p(ctrl2), p(to)),
+                PathEvent::Arc(center, radii, sweep_angle, x_rotation) => builder.arc(p(center), radii * scale, sweep_angle * angle_sign, x_rotation * angle_sign),
+                PathEvent::Close => builder.close(),
+            }
+        }
+    }
+
+    pub struct FontsContainer {
+        /// Identity kept by weak references, new for snapshots.
+        token: ContainerToken,
+        geometry: GeometryStore,
+        /// Fonts without references kept loaded, see `Fonts::set_font_retention`.
+        retained: RetainedFonts,
+        system_source: Box<dyn FontSource>,
+        /// Faces listed by the source for `find_matches`, on first use.
+        source_faces: Option<Vec<FaceInfo>>,
+        /// Fonts returned by `find_best_match` for each query, each entry without a reference.
+        font_queries: MetroHashMap<FontQuery, usize>,
+        /// Queries in `font_queries` answered with the auto fallback family.
+        query_substitutions: MetroHashMap<FontQuery, Substitution>,
+        auto_fallback: bool,
+        auto_fallback_family: FamilyName,
+        /// Recent `find_best_match` queries that found nothing, retried on refresh.
+        unresolved_queries: Vec<(FontQuery, Vec<FamilyName>, Properties)>,
+        /// Fonts resolved by the last refresh, each holding a reference.
+        refreshed_fonts: Vec<usize>,
+        /// Fonts and buffers with handles in the container this one is a snapshot of, each
+        /// holding a reference until `clear`.
+        snapshot_fonts: Vec<usize>,
+        snapshot_buffers: Vec<usize>,
+        /// Max bytes of the shaping and glyph caches, see `enforce_memory_budget`.
+        memory_budget: Option<usize>,
+        /// Generation of the last loaded font.
+        font_generation: u64,
+        /// Size of buffers without their own, see `Fonts::set_default_size`.
+        default_size_px: f32,
+        /// Physical pixels per logical pixel, see `Fonts::set_scale_factor`.
+        scale_factor: f32,
+        /// Y direction of positioned output, see `Fonts::set_origin`.
+        origin: Origin,
+        /// Snapping of scaled advances, see `Fonts::set_rounding`.
+        rounding: RoundingMode,
+        /// Vertical metrics of loaded fonts, see `Fonts::set_metrics_policy`.
+        metrics_policy: MetricsPolicy,
+        /// Queries choose among loaded fonts, see `Fonts::new_memory_only`.
+        memory_only: bool,
+        /// Families of `Fonts::default_ui_font`, the platform ones when `None`.
+        default_families: Option<Vec<FamilyName>>,
+
+        fonts: Slab<[u8; 20]>,
+        /// Handle fingerprints, several handles of the same face map to one id.
+        fonts_fingerprint_id: MetroHashMap<[u8; 20], usize>,
+        /// Fingerprints of the start of the font data of fonts loaded from handles.
+        fonts_content_id: MetroHashMap<[u8; 20], usize>,
+        /// Files of fonts loaded from path handles.
+        font_files: MetroHashMap<usize, FontFile>,
+        fonts_id_prop: MetroHashMap<usize, FontData>,
+
+        buffers: Slab<BufferData>,
+
+        glyph_cache_capacity: usize,
+        /// Step of the size buckets, see `Fonts::set_size_bucketing`.
+        size_step: f32,
+
+        shaping_cache: ShapingCache,
+        lazy_shaping: bool,
+        #[cfg(feature = "mmap")]
+        mmap_loading: bool,
+        synthesis: SynthesisOptions,
+
+        resource_loader: Option<Box<dyn Fn(&str) -> Option<Vec<u8>>>>,
+
+        /// Features of every buffer, see `Fonts::set_default_features`.
+        default_features: Rc<Vec<Feature>>,
+
+        /// Allocations of recycled buffers, see `Fonts::recycle`.
+        buffer_pool: Vec<RecycledBuffer>,
+        buffer_pool_capacity: usize,
+
+        /// Buffers `create_buffer` hands out again, see `Fonts::set_buffer_interning`.
+        buffer_interning: bool,
+        interned_buffers: HashMap<(usize, u64), usize>,
+
+        events: EventQueue,
+        diagnostics: Rc<Diagnostics>,
+        audit: RefcountAudit,
+    }
+
+    impl FontsContainer {
+        pub fn new() -> FontsContainer {
+            FontsContainer::with_source(Box::new(SystemSource::new()))
+        }
+
+        pub fn new_memory_only() -> FontsContainer {
+            let mut container = FontsContainer::with_source(Box::new(NoSource));
+            container.memory_only = true;
+            container
+        }
+
+        fn with_source(system_source: Box<dyn FontSource>) -> FontsContainer {
+            FontsContainer {
+                token: ContainerToken::next(),
+                geometry: GeometryStore::new(),
+                retained: RetainedFonts::new(),
+                system_source,
+                source_faces: None,
+                font_queries: MetroHashMap::default(),
+                query_substitutions: MetroHashMap::default(),
+                auto_fallback: false,
+                auto_fallback_family: FamilyName::SansSerif,
+                unresolved_queries: Vec::new(),
+                refreshed_fonts: Vec::new(),
+                snapshot_fonts: Vec::new(),
+                snapshot_buffers: Vec::new(),
+                memory_budget: None,
+                font_generation: 0,
+                default_size_px: DEFAULT_SIZE_PX,
+                scale_factor: 1.0,
+                origin: Origin::BottomLeftYUp,
+                rounding: RoundingMode::None,
+                metrics_policy: MetricsPolicy::default(),
+                memory_only: false,
+                default_families: None,
+
+                fonts: Slab::new(),
+                fonts_fingerprint_id: MetroHashMap::default(),
+                fonts_content_id: MetroHashMap::default(),
+                font_files: MetroHashMap::default(),
+                fonts_id_prop: MetroHashMap::default(),
+
+                buffers: Slab::new(),
+
+                glyph_cache_capacity: DEFAULT_GLYPH_CACHE_CAPACITY,
+                size_step: DEFAULT_SIZE_STEP,
+
+                shaping_cache: ShapingCache::new(DEFAULT_SHAPING_CACHE_CAPACITY),
+                lazy_shaping: false,
+                #[cfg(feature = "mmap")]
+                mmap_loading: false,
+                synthesis: SynthesisOptions::default(),
+
+                resource_loader: None,
+
+                default_features: Rc::new(Vec::new()),
+
+                buffer_pool: Vec::new(),
+                buffer_pool_capacity: DEFAULT_BUFFER_POOL_CAPACITY,
+                buffer_interning: false,
+                interned_buffers: HashMap::new(),
+
+                events: EventQueue::new(),
+                diagnostics: Rc::new(Diagnostics::new()),
+                audit: RefcountAudit::new(),
+            }
+        }
+
+        pub fn shaping_cache_stats(&self) -> ShapingCacheStats {
+            self.shaping_cache.stats()
+        }
+
+        pub fn set_shaping_cache_capacity(&mut self, capacity: usize) {
+            self.shaping_cache.set_capacity(capacity);
+        }
+
+        pub fn used_glyphs(&mut self) -> HashMap<usize, HashSet<u32>> {
+            let ids = self.buffers.iter().map(|(id, _)| id).collect::<Vec<_>>();
+            for id in ids {
+                self.ensure_buffer_shaped(id);
+            }
+
+            let mut used: HashMap<usize, HashSet<u32>> = HashMap::new();
+            for (_, buffer) in self.buffers.iter() {
+                for glyph in buffer.glyphs() {
+                    used.entry(buffer.span_font_id(glyph.span)).or_default().insert(glyph.id);
+                }
+            }
+            used
+        }
+
+        pub fn stats(&self) -> FontsStats {
+            let shaping = self.shaping_cache.stats();
+            let mut stats = FontsStats {
+                fonts: self.fonts_id_prop.len(),
+                buffers: self.buffers.len(),
+                retained_fonts: self.retained.len(),
+                shape_calls: shaping.shape_calls,
+                shaping_cache_hits: shaping.hits,
+                shaping_cache_misses: shaping.misses,
+                pooled_buffers: self.buffer_pool.len(),
+                shaping: self.shaping_cache.profiler().totals(ProfiledOperation::Shape),
+                outlines: self.shaping_cache.profiler().totals(ProfiledOperation::Outline),
+                rasterization: self.shaping_cache.profiler().totals(ProfiledOperation::Raster),
+                ..FontsStats::default()
+            };
+
+            for (_, buffer) in self.buffers.iter() {
+                stats.glyphs += buffer.glyphs.len();
+                stats.text_bytes += buffer.text.capacity();
+                stats.dropped_text_bytes += buffer.dropped_text_len.unwrap_or(0);
+                stats.glyph_bytes += buffer.glyph_bytes();
+            }
+
+            for font in self.fonts_id_prop.values() {
+                stats.glyph_cache_bytes += font.cache_bytes();
+                stats.outline_entries += font.outlines.len();
+                stats.sized_outline_entries += font.sized_outlines.len();
+                stats.sdf_entries += font.sdfs.len();
+                if font.size_px.is_some() {
+                    stats.sized_fonts += 1;
+                }
+            }
+
+            stats
+        }
+
+        pub fn leak_report(&self) -> Option<String> {
+            self.audit.report()
+        }
+
+        pub fn export_shaped_cache<W: Write>(&self, writer: &mut W) -> io::Result<usize> {
+            let fonts = &self.fonts;
+            self.shaping_cache.write_entries(writer, fingerprint::FORMAT, |font_id| fonts.get(font_id).cloned())
+        }
+
+        pub fn import_shaped_cache<R: Read>(&mut self, reader: &mut R) -> io::Result<usize> {
+            let entries = ShapingCache::read_entries(reader, fingerprint::FORMAT)?;
+
+            let mut imported = 0;
+            for entry in entries {
+                if let Some(&font_id) = self.fonts_fingerprint_id.get(&entry.fingerprint) {
+                    self.shaping_cache.insert(font_id, entry.text, entry.features, entry.glyphs);
+                    imported += 1;
+                }
+            }
+
+            Ok(imported)
+        }
+
+        pub fn set_diagnostics(&self, handler: Box<dyn Fn(FontsDiagnostic)>) {
+            self.diagnostics.set_handler(handler);
+        }
+
+        pub fn set_profiler(&self, profiler: Option<Box<dyn Fn(ShapeEvent)>>) {
+            self.shaping_cache.profiler().set_hook(profiler);
+        }
+
+        /// Diagnostics and profiler with callbacks queued while the container was borrowed.
+        pub fn queued_callbacks(&self) -> Option<(Rc<Diagnostics>, Rc<Profiler>)> {
+            let profiler = self.shaping_cache.profiler();
+            if self.diagnostics.has_queued() || profiler.has_queued() {
+                Some((self.diagnostics.clone(), profiler.clone()))
+            } else {
+                None
+            }
+        }
+
+        pub fn drain_events(&mut self, output: &mut Vec<FontsEvent>) {
+            self.events.drain_into(output);
+        }
+
+        fn buffer_ref(&self, buffer_id: usize) -> BufferRef {
+            BufferRef {
+                _font_id: self.buffers[buffer_id].font_id,
+                _id: buffer_id,
+                _container: self.token,
+            }
+        }
+
+        pub fn token(&self) -> ContainerToken {
+            self.token
+        }
+
+        /// Reports a reference of another container passed to `call`.
+        pub fn report_other_container(&self, call: &str) {
+            self.diagnostics.report(FontsDiagnostic::OtherContainer { call: call.to_string() });
+        }
+
+        /// The buffer of a reference if it is of this container and still exists.
+        pub fn buffer_of(&self, buffer: BufferRef) -> Option<&BufferData> {
+            if buffer._container != self.token {
+                return None;
+            }
+            self.buffers.get(buffer.id())
+        }
+
+        /// Records `BufferChanged` if the buffer version is no longer `version`.
+        fn buffer_changed_since(&mut self, buffer_id: usize, version: u64) {
+            if self.buffers[buffer_id].version != version {
+                let buffer = self.buffer_ref(buffer_id);
+                self.events.push(FontsEvent::BufferChanged(buffer));
+                // reshaping may have added shaping cache entries
+                self.enforce_memory_budget();
+            }
+        }
+
+        pub fn debug_dump(&self) -> Vec<BufferDebugInfo> {
+            self.buffers.iter()
+                .map(|(id, buffer)| BufferDebugInfo {
+                    id,
+                    count: buffer.count(),
+                    font_name: self.fonts_id_prop.get(&buffer.font_id()).map_or_else(String::new, |font| font.fk_font.full_name()),
+                    text: buffer.text().chars().take(32).collect(),
+                    glyph_count: buffer.glyphs().len(),
+                    version: buffer.version(),
+                    #[cfg(feature = "debug-origins")]
+                    origin: buffer.origin.clone(),
+                    #[cfg(not(feature = "debug-origins"))]
+                    origin: None,
+                })
+                .collect()
+        }
+
+        pub fn debug_dump_fonts(&self) -> Vec<FontDebugInfo> {
+            let mut fonts = self.fonts_id_prop.iter()
+                .map(|(&id, font)| FontDebugInfo {
+                    id,
+                    count: font.count,
+                    full_name: font.fk_font.full_name(),
+                    buffers: self.buffers.iter().filter(|&(_, buffer)| buffer.font_id() == id).count(),
+                })
+                .collect::<Vec<_>>();
+            fonts.sort_by_key(|font| font.id);
+            fonts
+        }
+
+        #[cfg(feature = "debug-origins")]
+        pub fn set_buffer_origin(&mut self, buffer_id: usize, origin: String) {
+            self.buffers.get_mut(buffer_id).expect("set_buffer_origin: self.buffers.get_mut(buffer_id)").origin = Some(origin);
+        }
+
+        /// Sets the origin of a buffer that has none yet, leaving that of an interned one.
+        #[cfg(feature = "debug-origins")]
+        pub fn set_new_buffer_origin(&mut self, buffer_id: usize, origin: &str) {
+            let buffer = self.buffers.get_mut(buffer_id).expect("set_new_buffer_origin: self.buffers.get_mut(buffer_id)");
+            if buffer.origin.is_none() {
+                buffer.origin = Some(origin.to_string());
+            }
+        }
+
+        pub fn clear(&mut self) -> usize {
+            let refreshed = ::std::mem::replace(&mut self.refreshed_fonts, Vec::new());
+            for id in refreshed {
+                self.dec_font(id);
+            }
+            self.release_snapshot_references();
+
+            self.unresolved_queries.clear();
+            self.font_queries.clear();
+            self.shaping_cache.clear();
+            self.flush_unused();
+            for font in self.fonts_id_prop.values_mut() {
+                font.clear_caches();
+            }
+
+            self.fonts_id_prop.len() + self.buffers.len()
+        }
+
+        /// Copy of the container for `Fonts::snapshot`, with the same font and buffer ids.
+        ///
+        /// Copies start with the references they hold among themselves, plus one held by the
+        /// copy for each font or buffer that also had handles here.
+        #[cfg_attr(debug_assertions, track_caller)]
+        pub fn snapshot(&mut self) -> FontsContainer {
+            let mut copy = if self.memory_only { FontsContainer::new_memory_only() } else { FontsContainer::new() };
+            copy.font_queries = self.font_queries.clone();
+            copy.query_substitutions = self.query_substitutions.clone();
+            copy.auto_fallback = self.auto_fallback;
+            copy.auto_fallback_family = self.auto_fallback_family.clone();
+            copy.unresolved_queries = self.unresolved_queries.clone();
+            copy.refreshed_fonts = self.refreshed_fonts.clone();
+            copy.memory_budget = self.memory_budget;
+            copy.font_generation = self.font_generation;
+            copy.default_size_px = self.default_size_px;
+            copy.scale_factor = self.scale_factor;
+            copy.origin = self.origin;
+            copy.rounding = self.rounding;
+            copy.metrics_policy = self.metrics_policy;
+            copy.default_families = self.default_families.clone();
+            copy.fonts_fingerprint_id = self.fonts_fingerprint_id.clone();
+            copy.fonts_content_id = self.fonts_content_id.clone();
+            copy.font_files = self.font_files.clone();
+            copy.glyph_cache_capacity = self.glyph_cache_capacity;
+            copy.size_step = self.size_step;
+            copy.shaping_cache.set_capacity(self.shaping_cache.capacity());
+            copy.shaping_cache.set_word_cache(self.shaping_cache.word_cache());
+            copy.lazy_shaping = self.lazy_shaping;
+            #[cfg(feature = "mmap")]
+            copy.mmap_loading = self.mmap_loading;
+            copy.synthesis = self.synthesis;
+            copy.default_features = self.default_features.clone();
+            copy.buffer_pool_capacity = self.buffer_pool_capacity;
+            copy.buffer_interning = self.buffer_interning;
+            copy.interned_buffers = self.interned_buffers.clone();
+            copy.retained = self.retained.clone();
+
+            // references held by fonts and buffers of the copy
+            let mut font_refs: HashMap<usize, usize> = HashMap::new();
+            let mut buffer_refs: HashMap<usize, usize> = HashMap::new();
+            for &id in &self.refreshed_fonts {
+                *font_refs.entry(id).or_insert(0) += 1;
+            }
+            for (_, buffer) in self.buffers.iter() {
+                for font_id in Some(buffer.font_id).into_iter().chain(buffer.span_font_ids()) {
+                    *font_refs.entry(font_id).or_insert(0) += 1;
+                }
+                for &line in buffer.linked_lines.iter().flat_map(|lines| lines) {
+                    *buffer_refs.entry(line).or_insert(0) += 1;
+                }
+            }
+
+            copy.fonts = slab_with_keys(self.fonts.iter().map(|(id, fingerprint)| (id, *fingerprint)).collect(), || [0; 20]);
+            for (&id, font) in &self.fonts_id_prop {
+                let hb_font = shaping_font_of(font).expect("snapshot: font funcs of a loaded face");
+                let mut data = font.snapshot(hb_font, copy.diagnostics.clone());
+                let internal = font_refs.get(&id).cloned().unwrap_or(0);
+                data.count = if data.count > internal { internal + 1 } else { internal };
+                if data.count > internal {
+                    copy.snapshot_fonts.push(id);
+                }
+                copy.audit.created(Kind::Font, id, data.fk_font.full_name());
+                if data.count > 1 {
+                    copy.audit.inc(Kind::Font, id, data.count - 1);
+                } else if data.count == 0 {
+                    // retained, like the original
+                    copy.audit.dec(Kind::Font, id);
+                }
+                copy.fonts_id_prop.insert(id, data);
+            }
+
+            let placeholder_color = na::Vector4::new(0, 0, 0, 0);
+            copy.buffers = slab_with_keys(self.buffers.iter().map(|(id, buffer)| (id, buffer.snapshot())).collect(),
+                || BufferData::new(0, String::new(), Vec::new(), None, placeholder_color));
+            let buffer_ids = copy.buffers.iter().map(|(id, _)| id).collect::<Vec<_>>();
+            for id in buffer_ids {
+                let internal = buffer_refs.get(&id).cloned().unwrap_or(0);
+                let count = {
+                    let buffer = &mut copy.buffers[id];
+                    buffer.count = if buffer.count > internal { internal + 1 } else { internal };
+                    buffer.count
+                };
+                if count > internal {
+                    copy.snapshot_buffers.push(id);
+                }
+                copy.audit_buffer_created(id);
+                if count > 1 {
+                    copy.audit.inc(Kind::Buffer, id, count - 1);
+                }
+            }
+
+            copy
+        }
+
+        fn release_snapshot_references(&mut self) {
+            for id in ::std::mem::replace(&mut self.snapshot_buffers, Vec::new()) {
+                self.dec_buffer(id);
+            }
+            for id in ::std::mem::replace(&mut self.snapshot_fonts, Vec::new()) {
+                self.dec_font(id);
+            }
+        }
+
+        pub fn set_memory_budget(&mut self, bytes: Option<usize>) {
+            self.memory_budget = bytes;
+            self.enforce_memory_budget();
+        }
+
+        /// Approximate bytes of the shaping cache and the glyph caches of all fonts.
+        pub fn memory_usage(&self) -> usize {
+            self.shaping_cache.bytes() + self.fonts_id_prop.values().map(|font| font.cache_bytes()).sum::<usize>()
+        }
+
+        /// Runs `f` with a font that may fill its glyph caches, then keeps the memory budget.
+        pub fn with_font_caches<R, F: FnOnce(&mut FontData) -> R>(&mut self, font_id: usize, f: F) -> R {
+            let result = f(self.fonts_id_prop.get_mut(&font_id).expect("with_font_caches: loaded font should exist"));
+            self.enforce_memory_budget();
+            result
+        }
+
+        /// `with_font_caches` timed as one `operation` on a glyph.
+        pub fn with_font_caches_profiled<R, F: FnOnce(&mut FontData) -> R>(&mut self, font_id: usize, operation: ProfiledOperation, f: F) -> R {
+            let profiler = self.shaping_cache.profiler().clone();
+            profiler.time(operation, font_id, || self.with_font_caches(font_id, f), |_| (0, 1))
+        }
+
+        /// Evicts least recently used entries from the largest cache until the usage is in budget.
+        ///
+        /// Buffers are never evicted; shaped glyphs they still use stay alive after their
+        /// shaping cache entry is evicted.
+        fn enforce_memory_budget(&mut self) {
+            let budget = match self.memory_budget {
+                Some(budget) => budget,
+                None => return,
+            };
+
+            let mut shaping_bytes = self.shaping_cache.bytes();
+            let mut font_bytes = self.fonts_id_prop.iter()
+                .map(|(&id, font)| (id, font.cache_bytes()))
+                .collect::<Vec<_>>();
+            let mut usage = shaping_bytes + font_bytes.iter().map(|&(_, bytes)| bytes).sum::<usize>();
+
+            // retained fonts go before the caches of fonts in use
+            while usage > budget {
+                let id = match self.retained.take_oldest() {
+                    Some(id) => id,
+                    None => break,
+                };
+                if let Some(position) = font_bytes.iter().position(|&(font_id, _)| font_id == id) {
+                    usage = usage.saturating_sub(font_bytes.remove(position).1);
+                }
+                self.delete_font(id);
+            }
+
+            while usage > budget {
+                let largest_font = font_bytes.iter_mut().max_by_key(|entry| entry.1);
+                let freed = match largest_font {
+                    Some(&mut (id, ref mut bytes)) if *bytes > shaping_bytes => {
+                        let freed = self.fonts_id_prop.get_mut(&id).and_then(|font| font.evict_cached_glyph());
+                        *bytes = bytes.saturating_sub(freed.unwrap_or(*bytes));
+                        freed
+                    },
+                    _ => {
+                        let freed = self.shaping_cache.remove_oldest();
+                        shaping_bytes = shaping_bytes.saturating_sub(freed.unwrap_or(shaping_bytes));
+                        freed
+                    },
+                };
+
+                match freed {
+                    Some(freed) => usage = usage.saturating_sub(freed),
+                    None => break,
+                }
+            }
+        }
+
+        pub fn set_resource_loader(&mut self, loader: Box<dyn Fn(&str) -> Option<Vec<u8>>>) {
+            self.resource_loader = Some(loader);
+        }
+
+        /// Loads a font from resource bytes, or returns the same font with one more reference
+        /// if the bytes did not change.
+        pub fn load_resource_font(&mut self, name: &str, index: u32) -> Result<usize, FontResourceError> {
+            let bytes = {
+                let loader = self.resource_loader.as_ref().ok_or(FontResourceError::NoLoader)?;
+                loader(name).ok_or(FontResourceError::NotFound)?
+            };
+            self.load_font_bytes(name, bytes, index)
+        }
+
+        /// Loads font file data, `name` only tells apart equal data loaded as different resources.
+        pub fn load_font_bytes(&mut self, name: &str, bytes: Vec<u8>, index: u32) -> Result<usize, FontResourceError> {
+            let fingerprint = generate_resource_fingerprint(name, index, &bytes);
+            if let Some(&id) = self.fonts_fingerprint_id.get(&fingerprint) {
+                self.inc_font(id);
+                return Ok(id);
+            }
+
+            let bytes = Arc::new(bytes);
+            let fk_font = FontkitFont::from_bytes(bytes.clone(), index)?;
+            let hb_font = match shaping_font_from_bytes(bytes, index) {
+                Ok(hb_font) => hb_font,
+                Err(_) => {
+                    self.diagnostics.report(FontsDiagnostic::FaceFailed { path: name.to_string(), index });
+                    return Err(FontResourceError::Face);
+                }
+            };
+
+            Ok(self.insert_font(fingerprint, fk_font, hb_font, index))
+        }
+
+        #[cfg_attr(debug_assertions, track_caller)]
+        fn insert_font(&mut self, fingerprint: [u8; 20], fk_font: FontkitFont, hb_font: ShapingFont, face_index: u32) -> usize {
+            self.diagnostics.report(FontsDiagnostic::FontLoaded { name: fk_font.full_name() });
+
+            let id = self.fonts.insert(fingerprint);
+            let mut data = FontData::new(fk_font, hb_font, face_index, self.glyph_cache_capacity, self.diagnostics.clone());
+            data.set_metrics_policy(self.metrics_policy);
+            self.font_generation += 1;
+            data.generation = self.font_generation;
+
+            self.audit.created(Kind::Font, id, data.fk_font.full_name());
+            self.fonts_fingerprint_id.insert(fingerprint, id);
+            self.fonts_id_prop.insert(id, data);
+            self.events.push(FontsEvent::FontLoaded(id));
+            id
+        }
+
+        pub fn set_word_cache(&mut self, enabled: bool) {
+            self.shaping_cache.set_word_cache(enabled);
+        }
+
+        pub fn set_lazy_shaping(&mut self, lazy: bool) {
+            self.lazy_shaping = lazy;
+        }
+
+        #[cfg(feature = "mmap")]
+        pub fn set_mmap_loading(&mut self, enabled: bool) {
+            self.mmap_loading = enabled;
+        }
+
+        #[cfg(feature = "mmap")]
+        pub fn mmap_loading(&self) -> bool {
+            self.mmap_loading
+        }
+
+        /// Shapes `text` through the shaping cache with the key a buffer of it would use,
+        /// without creating one.
+        pub fn measure_str(&mut self, font_id: usize, text: &str, px_size: f32, features: &[Feature]) -> TextMeasure {
+            let font = self.fonts_id_prop.get(&font_id).expect("measure_str: loaded font should exist");
+            let shaping_features = merge_features(&self.default_features, features);
+            let hb_features = hb_features(&shaping_features);
+            let base = BaseDirection::FirstStrong;
+            let key = shaping_key(&shaping_features, base);
+
+            let cache = &mut self.shaping_cache;
+            let by_words = cache.word_cache() && shaping_features.iter().all(|f| f.range.is_none()) && !text.chars().any(bidi::is_rtl_char);
+            let shaped = if by_words {
+                cache.get_or_shape_words(font_id, text, &key, |pool, word| shape_range(font, pool, word, 0..word.len(), &hb_features, base))
+            } else {
+                cache.get_or_shape(font_id, text, &key, |pool| shape_range(font, pool, text, 0..text.len(), &hb_features, base))
+            };
+
+            let scale = px_size / font.metrics.units_per_em as f32;
+            TextMeasure {
+                width: shaped.glyphs.iter().map(|glyph| glyph.x_advance).sum::<i32>() as f32 * scale,
+                ascent: font.metrics.ascent * scale,
+                descent: font.metrics.descent * scale,
+                line_gap: font.metrics.line_gap * scale,
+                glyph_count: shaped.len(),
+            }
+        }
+
+        pub fn default_features(&self) -> &[Feature] {
+            &self.default_features
+        }
+
+        pub fn set_default_features(&mut self, features: &[Feature]) {
+            let features = features.iter()
+                .map(|f| Feature { range: None, ..f.clone() })
+                .collect::<Vec<_>>();
+            if *self.default_features == features {
+                return;
+            }
+
+            self.default_features = Rc::new(features);
+            let mut changed = Vec::new();
+            for (id, buffer) in self.buffers.iter_mut() {
+                if buffer.set_default_features(self.default_features.clone()) {
+                    changed.push(id);
+                }
+            }
+            for id in changed {
+                let buffer = self.buffer_ref(id);
+                self.events.push(FontsEvent::BufferChanged(buffer));
+            }
+        }
+
+        pub fn set_synthesis(&mut self, synthesis: SynthesisOptions) {
+            self.synthesis = synthesis;
+            self.font_queries.clear();
+        }
+
+        pub fn set_font_source(&mut self, source: Box<dyn FontSource>) {
+            self.system_source = source;
+            self.source_faces = None;
+            self.font_queries.clear();
+        }
+
+        pub fn refresh_system_fonts(&mut self) {
+            self.check_font_files();
+            self.system_source.refresh();
+            self.source_faces = None;
+            self.font_queries.clear();
+
+            let previous = ::std::mem::replace(&mut self.refreshed_fonts, Vec::new());
+            let unresolved = ::std::mem::replace(&mut self.unresolved_queries, Vec::new());
+            for (_, family_names, properties) in unresolved {
+                // queries that still fail are tracked again
+                if let Some(id) = self.find_best_match(&family_names, &properties) {
+                    self.refreshed_fonts.push(id);
+                }
+            }
+
+            // released after resolving again, so fonts still resolved are not reloaded
+            for id in previous {
+                self.dec_font(id);
+            }
+        }
+
+        /// Reports loaded fonts whose file can no longer be read.
+        fn check_font_files(&mut self) {
+            let lost = self.font_files.iter()
+                .filter(|&(_, file)| !file.lost && ::std::fs::metadata(&file.path).is_err())
+                .map(|(&id, _)| id)
+                .collect::<Vec<_>>();
+            for id in lost {
+                self.report_lost_file(id);
+            }
+        }
+
+        /// Loaded font of face `font_index` of a file that can no longer be read, the last loaded
+        /// one if the file was overwritten before.
+        fn font_of_lost_file(&mut self, path: &::std::path::Path, font_index: u32) -> Option<usize> {
+            let id = {
+                let fonts = &self.fonts_id_prop;
+                self.font_files.iter()
+                    .filter(|&(_, file)| file.path == path && file.font_index == font_index)
+                    .max_by_key(|&(id, _)| fonts.get(id).map_or(0, |font| font.generation))
+                    .map(|(&id, _)| id)?
+            };
+            self.report_lost_file(id);
+            Some(id)
+        }
+
+        fn report_lost_file(&mut self, id: usize) {
+            if let Some(file) = self.font_files.get_mut(&id) {
+                if !file.lost {
+                    file.lost = true;
+                    self.diagnostics.report(FontsDiagnostic::SourceLost { font_id: id, path: file.path.to_string_lossy().into_owned() });
+                }
+            }
+        }
+
+        fn track_unresolved_query(&mut self, query: FontQuery, family_names: &[FamilyName], properties: &Properties) {
+            if self.unresolved_queries.iter().any(|&(ref q, _, _)| *q == query) {
+                return;
+            }
+            if self.unresolved_queries.len() >= MAX_UNRESOLVED_QUERIES {
+                self.unresolved_queries.remove(0);
+            }
+            self.unresolved_queries.push((query, family_names.to_vec(), properties.clone()));
+        }
+
+        /// Font of the same face with `synthesis` applied, loaded once per options.
+        ///
+        /// Moves the caller's reference from `font_id` to the returned font.
+        pub fn synthesized_font(&mut self, font_id: usize, synthesis: SynthesisOptions) -> usize {
+            let base = self.fonts_id_prop.get(&font_id).expect("synthesized_font: self.fonts_id_prop.get(&font_id)");
+            if base.synthesis == synthesis {
+                return font_id;
+            }
+
+            let fingerprint = generate_synthesis_fingerprint(&self.fonts[font_id], synthesis);
+            if let Some(&id) = self.fonts_fingerprint_id.get(&fingerprint) {
+                self.inc_font(id);
+                self.dec_font(font_id);
+                return id;
+            }
+
+            let fk_font = base.fk_font.clone();
+            let face_index = base.face_index;
+            let size_px = base.size_px;
+            let hb_font = match shaping_font_of(base) {
+                Ok(hb_font) => hb_font,
+                Err(reason) => {
+                    self.diagnostics.report(FontsDiagnostic::LoadFailed { family: fk_font.full_name(), reason });
+                    return font_id;
+                }
+            };
+
+            let id = self.insert_font(fingerprint, fk_font, hb_font, face_index);
+            let data = self.fonts_id_prop.get_mut(&id).expect("synthesized_font: inserted font");
+            data.synthesis = synthesis;
+            if let Some(px_size) = size_px {
+                data.set_size_px(px_size);
+            }
+            self.dec_font(font_id);
+            id
+        }
+
+        /// Font of the same face with units of 1/64 pixel at `px_size` rounded to a quarter
+        /// pixel, loaded once per size. Returns a new reference.
+        #[cfg_attr(debug_assertions, track_caller)]
+        pub fn sized_font(&mut self, font_id: usize, px_size: f32) -> usize {
+            let bucket = self.size_bucket(px_size);
+            let px_size = bucket.px_size();
+            let base = self.fonts_id_prop.get(&font_id).expect("sized_font: self.fonts_id_prop.get(&font_id)");
+            if base.size_px == Some(px_size) {
+                self.inc_font(font_id);
+                return font_id;
+            }
+
+            let fingerprint = generate_size_fingerprint(&self.fonts[font_id], bucket);
+            if let Some(&id) = self.fonts_fingerprint_id.get(&fingerprint) {
+                self.inc_font(id);
+                return id;
+            }
+
+            let fk_font = base.fk_font.clone();
+            let face_index = base.face_index;
+            let synthesis = base.synthesis;
+            let hb_font = match shaping_font_of(base) {
+                Ok(hb_font) => hb_font,
+                Err(reason) => {
+                    self.diagnostics.report(FontsDiagnostic::LoadFailed { family: fk_font.full_name(), reason });
+                    self.inc_font(font_id);
+                    return font_id;
+                }
+            };
+
+            let id = self.insert_font(fingerprint, fk_font, hb_font, face_index);
+            let data = self.fonts_id_prop.get_mut(&id).expect("sized_font: inserted font");
+            data.synthesis = synthesis;
+            data.set_size_px(px_size);
+            id
+        }
+
+        /// Shapes a buffer created with lazy shaping, does nothing if it is already shaped.
+        pub fn ensure_buffer_shaped(&mut self, buffer_id: usize) {
+            let fonts = &self.fonts_id_prop;
+            let buffer = self.buffers.get_mut(buffer_id).expect("ensure_buffer_shaped: self.buffers.get_mut(buffer_id)");
+            if !buffer.is_shaped() {
+                buffer.shape(fonts, &mut self.shaping_cache);
+                buffer.drop_unretained_text();
+                self.enforce_memory_budget();
+            }
+        }
+
+        pub fn set_buffer_retain_text(&mut self, buffer_id: usize, retain: bool) {
+            let buffer = self.buffers.get_mut(buffer_id).expect("set_buffer_retain_text: self.buffers.get_mut(buffer_id)");
+            buffer.retain_text = retain;
+            buffer.drop_unretained_text();
+        }
+
+        #[cfg_attr(debug_assertions, track_caller)]
+        fn insert_buffer(&mut self, buffer: BufferData) -> usize {
+            let lazy = self.lazy_shaping;
+            self.insert_buffer_with(buffer, lazy)
+        }
+
+        #[cfg_attr(debug_assertions, track_caller)]
+        fn insert_buffer_with(&mut self, mut buffer: BufferData, lazy: bool) -> usize {
+            buffer.default_features = self.default_features.clone();
+            buffer.tracking_px = buffer.size_px.unwrap_or(self.default_size_px);
+            if !lazy {
+                buffer.shape(&self.fonts_id_prop, &mut self.shaping_cache);
+                self.enforce_memory_budget();
+            }
+            let id = self.buffers.insert(buffer);
+            self.audit_buffer_created(id);
+            let buffer = self.buffer_ref(id);
+            self.events.push(FontsEvent::BufferCreated(buffer));
+            id
+        }
+
+        /// Sets the max number of cached entries per font and cache kind, evicting the excess.
+        pub fn set_glyph_cache_capacity(&mut self, capacity: usize) {
+            self.glyph_cache_capacity = capacity;
+            for font in self.fonts_id_prop.values_mut() {
+                font.set_cache_capacity(capacity);
+            }
+        }
+
+        pub fn size_bucketing(&self) -> f32 {
+            self.size_step
+        }
+
+        pub fn set_size_bucketing(&mut self, step: f32) {
+            assert!(step > 0.0 && step.is_finite(), "set_size_bucketing: step should be positive");
+            if step == self.size_step {
+                return;
+            }
+            self.size_step = step;
+            for font in self.fonts_id_prop.values_mut() {
+                font.clear_sized_caches();
+            }
+        }
+
+        pub fn size_bucket(&self, px_size: f32) -> SizeBucket {
+            SizeBucket::new(px_size, self.size_step)
+        }
+
+        pub fn geometry_store(&self) -> GeometryStore {
+            self.geometry.clone()
+        }
+
+        #[cfg_attr(debug_assertions, track_caller)]
+        fn audit_buffer_created(&mut self, id: usize) {
+            let label = self.buffers[id].text().chars().take(32).collect::<String>();
+            self.audit.created(Kind::Buffer, id, label);
+        }
+
+        #[cfg_attr(debug_assertions, track_caller)]
+        pub fn create_buffer(&mut self, font_id: usize, text: Cow<str>, transform: Option<na::Projective3<f32>>, color: na::Vector4<u8>) -> usize {
+            assert!(self.get(font_id).is_some(), "FontsContainer::create_buffer - self.get(font_id)");
+            let key = if self.buffer_interning {
+                let key = intern_key(font_id, &text, transform.as_ref(), color);
+                if let Some(id) = self.interned_buffer(key, font_id, &text, transform, color) {
+                    self.inc_buffer(id);
+                    return id;
+                }
+                Some(key)
+            } else {
+                None
+            };
+
+            self.inc_font(font_id);
+            let mut buffer = self.new_buffer_data(font_id, &text, transform, color);
+            buffer.intern_key = key;
+
+            let id = self.insert_buffer(buffer);
+            if let Some(key) = key {
+                self.interned_buffers.insert(key, id);
+            }
+            id
+        }
+
+        /// Interned buffer at `key` if it was created with these arguments, not just ones with
+        /// the same hash.
+        fn interned_buffer(&self, key: (usize, u64), font_id: usize, text: &str, transform: Option<na::Projective3<f32>>, color: na::Vector4<u8>) -> Option<usize> {
+            let id = *self.interned_buffers.get(&key)?;
+            let buffer = self.buffers.get(id)?;
+            let same = buffer.intern_key == Some(key) && buffer.font_id == font_id && buffer.text() == text
+                && buffer.transform == transform && buffer.color == color;
+            if same { Some(id) } else { None }
+        }
+
+        pub fn set_buffer_interning(&mut self, enabled: bool) {
+            self.buffer_interning = enabled;
+            if !enabled {
+                self.interned_buffers.clear();
+            }
+        }
+
+        /// Records the id cell of a new handle of an interned buffer, see `unshare_buffer`.
+        pub fn add_buffer_sharer(&mut self, handle: &Rc<Cell<usize>>) {
+            let buffer = self.buffers.get_mut(handle.get()).expect("add_buffer_sharer: self.buffers.get_mut(handle.get())");
+            if buffer.intern_key.is_some() {
+                buffer.sharers.retain(|sharer| sharer.upgrade().is_some());
+                buffer.sharers.push(Rc::downgrade(handle));
+            }
+        }
+
+        /// Takes the buffer of `handle` out of the intern table before it changes. The other
+        /// handles created for it while interned move to a copy taking over their references,
+        /// so the buffer keeps its id.
+        pub fn unshare_buffer(&mut self, handle: &Rc<Cell<usize>>) {
+            let buffer_id = handle.get();
+            let (key, sharers) = {
+                let buffer = self.buffers.get_mut(buffer_id).expect("unshare_buffer: self.buffers.get_mut(buffer_id)");
+                let key = match buffer.intern_key.take() {
+                    Some(key) => key,
+                    None => return,
+                };
+                let sharers = buffer.sharers.drain(..)
+                    .filter_map(|sharer| sharer.upgrade())
+                    .filter(|sharer| !Rc::ptr_eq(sharer, handle))
+                    .collect::<Vec<_>>();
+                (key, sharers)
+            };
+            self.forget_interned(key, buffer_id);
+            if sharers.is_empty() {
+                return;
+            }
+
+            // handles of each sharer, without the upgraded cell
+            let moved = sharers.iter().map(|sharer| Rc::strong_count(sharer) - 1).sum::<usize>();
+            let mut copy = self.buffers[buffer_id].snapshot();
+            copy.count = moved;
+            copy.intern_key = Some(key);
+            copy.sharers = sharers.iter().map(Rc::downgrade).collect();
+            self.inc_font(copy.font_id);
+            let id = self.buffers.insert(copy);
+            self.audit_buffer_created(id);
+            if moved > 1 {
+                self.audit.inc(Kind::Buffer, id, moved - 1);
+            }
+            let buffer_ref = self.buffer_ref(id);
+            self.events.push(FontsEvent::BufferCreated(buffer_ref));
+
+            self.buffers[buffer_id].count -= moved;
+            for _ in 0..moved {
+                self.audit.dec(Kind::Buffer, buffer_id);
+            }
+            if self.buffer_interning {
+                self.interned_buffers.insert(key, id);
+            }
+            for sharer in sharers {
+                sharer.set(id);
+            }
+        }
+
+        fn forget_interned(&mut self, key: (usize, u64), buffer_id: usize) {
+            if self.interned_buffers.get(&key) == Some(&buffer_id) {
+                self.interned_buffers.remove(&key);
+            }
+        }
+
+        /// Creates a buffer with the settings of `builder` applied before shaping it once,
+        /// or not at all if lazy.
+        #[cfg_attr(debug_assertions, track_caller)]
+        pub fn create_buffer_from(&mut self, builder: &BufferBuilder, text: &str) -> usize {
+            let font_id = builder.font.id;
+            self.inc_font(font_id);
+            let mut buffer = self.new_buffer_data(font_id, text, builder.transform, builder.color);
+
+            let dropped = {
+                let fonts = &self.fonts_id_prop;
+                let cache = &mut self.shaping_cache;
+                buffer.set_whitespace(fonts, cache, builder.whitespace);
+                buffer.set_base_direction(fonts, cache, builder.base_direction);
+                let text_len = buffer.shaped_text().len();
+                let (dropped, features): (Vec<_>, Vec<_>) = builder.features.iter().cloned().partition(|f| feature_outside(f, text_len));
+                buffer.set_features(fonts, cache, features);
+                dropped
+            };
+            self.report_dropped_features(dropped);
+            // settings of a new buffer are not changes of it
+            buffer.version = 0;
+            buffer.size_px = builder.size_px;
+            buffer.retain_text = builder.retain_text;
+
+            let lazy = builder.lazy.unwrap_or(self.lazy_shaping);
+            let id = self.insert_buffer_with(buffer, lazy);
+            self.buffers[id].drop_unretained_text();
+            id
+        }
+
+        /// Unshaped buffer data in the allocations of a recycled buffer if there is one.
+        fn new_buffer_data(&mut self, font_id: usize, text: &str, transform: Option<na::Projective3<f32>>, color: na::Vector4<u8>) -> BufferData {
+            match self.buffer_pool.pop() {
+                Some(recycled) => BufferData::from_recycled(recycled, font_id, text, transform, color),
+                None => BufferData::new(font_id, text.to_string(), Vec::new(), transform, color),
+            }
+        }
+
+        pub fn mark_buffer_recycled(&mut self, buffer_id: usize) {
+            self.buffers.get_mut(buffer_id).expect("mark_buffer_recycled: self.buffers.get_mut(buffer_id)").recycle = true;
+        }
+
+        pub fn set_buffer_pool_capacity(&mut self, capacity: usize) {
+            self.buffer_pool_capacity = capacity;
+            self.buffer_pool.truncate(capacity);
+        }
+
+        /// Creates one buffer per text, each holding its own reference to the font.
+        #[cfg_attr(debug_assertions, track_caller)]
+        pub fn create_buffers<I, S>(&mut self, font_id: usize, texts: I, transform: Option<na::Projective3<f32>>, color: na::Vector4<u8>) -> Vec<usize>
+            where I: IntoIterator<Item=S>, S: AsRef<str> {
+            let buffers = texts.into_iter()
+                .map(|text| self.new_buffer_data(font_id, text.as_ref(), transform, color))
+                .collect::<Vec<_>>();
+
+            self.fonts_id_prop.get_mut(&font_id).expect("FontsContainer::create_buffers - self.fonts_id_prop.get_mut(&font_id)")
+                .count += buffers.len();
+            self.audit.inc(Kind::Font, font_id, buffers.len());
+            self.buffers.reserve(buffers.len());
+
+            // a loop rather than a closure, which would hide the caller from the audit
+            let mut ids = Vec::with_capacity(buffers.len());
+            for buffer in buffers {
+                ids.push(self.insert_buffer(buffer));
+            }
+            ids
+        }
+
+        /// Creates a buffer from dumped glyphs with the loaded font of the same full name.
+        pub fn load_shaped_dump(&mut self, dump: &ShapedDump, transform: Option<na::Projective3<f32>>, color: na::Vector4<u8>) -> Result<usize, ShapedDumpError> {
+            let (font_id, glyph_count) = self.fonts_id_prop.iter()
+                .find(|(_, font)| font.fk_font.full_name() == dump.font)
+                .map(|(id, font)| (*id, font.fk_font.glyph_count()))
+                .ok_or_else(|| ShapedDumpError::FontNotLoaded(dump.font.clone()))?;
+            dump.validate(glyph_count)?;
+
+            self.inc_font(font_id);
+            let mut buffer = BufferData::from_glyphs(font_id, dump.text.clone(), dump.positions.clone(), transform, color);
+            buffer.default_features = self.default_features.clone();
+
+            let id = self.buffers.insert(buffer);
+            self.audit_buffer_created(id);
+            let buffer = self.buffer_ref(id);
+            self.events.push(FontsEvent::BufferCreated(buffer));
+            Ok(id)
+        }
+
+        /// Creates a buffer from glyphs shaped elsewhere, without going through `insert_buffer`
+        /// which would shape the text.
+        pub fn create_preshaped_buffer(&mut self, font_id: usize, text: String, mut glyphs: Vec<GlyphPosition>, transform: Option<na::Projective3<f32>>, color: na::Vector4<u8>) -> Result<usize, PreshapedGlyphsError> {
+            assert!(self.get(font_id).is_some(), "FontsContainer::create_preshaped_buffer - self.get(font_id)");
+            for (index, glyph) in glyphs.iter().enumerate() {
+                if glyph.cluster as usize > text.len() {
+                    return Err(PreshapedGlyphsError::ClusterOutOfRange { glyph: index, cluster: glyph.cluster, len: text.len() });
+                }
+                if !text.is_char_boundary(glyph.cluster as usize) {
+                    return Err(PreshapedGlyphsError::ClusterInsideChar { glyph: index, cluster: glyph.cluster });
+                }
+            }
+
+            for glyph in &mut glyphs {
+                glyph.byte_offset = glyph.cluster;
+            }
+            let mut buffer = BufferData::from_glyphs(font_id, text, glyphs, transform, color);
+            for i in 0..buffer.glyphs.len() {
+                let len = buffer.cluster_len(buffer.glyphs[i].cluster);
+                buffer.glyphs[i].len = len;
+            }
+
+            buffer.default_features = self.default_features.clone();
+            self.inc_font(font_id);
+            let id = self.buffers.insert(buffer);
+            self.audit_buffer_created(id);
+            let buffer = self.buffer_ref(id);
+            self.events.push(FontsEvent::BufferCreated(buffer));
+            Ok(id)
+        }
+
+        /// Creates a preshaped buffer of the text of `range` of the shaped text, widened to the
+        /// clusters it overlaps, with the glyphs of the buffer for it. Spans, the size and whole
+        /// text features are kept for later reshaping.
+        pub fn extract_buffer_range(&mut self, buffer_id: usize, range: ::std::ops::Range<usize>) -> usize {
+            self.ensure_buffer_shaped(buffer_id);
+            let buffer = {
+                let parent = self.buffers.get(buffer_id).expect("extract_buffer_range: self.buffers.get(buffer_id)");
+                let range = if parent.has_text() { parent.cluster_range(range) } else { 0..0 };
+                let source_start = parent.source_offset(range.start) as u32;
+                let spans = parent.spans_in(range.clone());
+                // spans before the range are left out, so later glyph span indices move down
+                let span_index = |span: u32| parent.spans[..span as usize].iter()
+                    .filter(|s| s.range.start < range.end && s.range.end > range.start)
+                    .count() as u32;
+
+                let glyphs = parent.glyphs().iter()
+                    .filter(|glyph| (glyph.cluster as usize) >= range.start && (glyph.cluster as usize) < range.end)
+                    .map(|glyph| {
+                        let cluster = glyph.cluster - range.start as u32;
+                        let span = if spans.is_empty() { 0 } else { span_index(glyph.span) };
+                        GlyphPosition { cluster, byte_offset: glyph.byte_offset - source_start, span, ..*glyph }
+                    })
+                    .collect();
+
+                let mut buffer = BufferData::from_glyphs(parent.font_id, parent.shaped_text()[range.clone()].to_string(), glyphs, parent.transform, parent.color);
+                buffer.spans = spans;
+                buffer.size_px = parent.size_px;
+                buffer.optical_tracking = parent.optical_tracking;
+                buffer.base_direction = parent.base_direction;
+                buffer.features = parent.features.iter().filter(|f| f.range.is_none()).cloned().collect();
+                // the glyphs keep their offsets into the source text of the range
+                buffer.source = parent.source.as_ref().map(|source| source.slice(range.clone()));
+                buffer.whitespace = parent.whitespace;
+                buffer.case = parent.case;
+                buffer.case_locale = parent.case_locale.clone();
+                buffer.sanitize = parent.sanitize;
+                buffer
+            };
+
+            buffer.default_features = self.default_features.clone();
+            self.inc_font(buffer.font_id);
+            for span in &buffer.spans {
+                self.inc_font(span.font_id);
+            }
+            let id = self.buffers.insert(buffer);
+            self.audit_buffer_created(id);
+            let buffer = self.buffer_ref(id);
+            self.events.push(FontsEvent::BufferCreated(buffer));
+            id
+        }
+
+        /// Creates a buffer of the shaped texts of two buffers, spans and features of the second
+        /// moved by the length of the first.
+        pub fn concat_buffers(&mut self, first_id: usize, second_id: usize) -> usize {
+            let buffer = {
+                let first = self.buffers.get(first_id).expect("concat_buffers: self.buffers.get(first_id)");
+                let second = self.buffers.get(second_id).expect("concat_buffers: self.buffers.get(second_id)");
+                let offset = first.shaped_text().len();
+                let text = [first.shaped_text(), second.shaped_text()].concat();
+
+                let spans = if first.spans.is_empty() && second.spans.is_empty() && first.font_id == second.font_id {
+                    Vec::new()
+                } else {
+                    first.span_list().into_iter()
+                        .chain(second.span_list().into_iter()
+                            .map(|span| SpanData { range: span.range.start + offset..span.range.end + offset, ..span }))
+                        .collect()
+                };
+
+                // whole text features of both parts stay whole, the others are limited to their part
+                let mut features = Vec::new();
+                for feature in &first.features {
+                    let range = match feature.range {
+                        Some(ref range) => Some(range.clone()),
+                        None if second.features.contains(feature) => None,
+                        None => Some(0..offset),
+                    };
+                    features.push(Feature { range, ..feature.clone() });
+                }
+                for feature in &second.features {
+                    let range = match feature.range {
+                        Some(ref range) => Some(range.start + offset..range.end + offset),
+                        None if first.features.contains(feature) => continue,
+                        None => Some(offset..text.len()),
+                    };
+                    features.push(Feature { range, ..feature.clone() });
+                }
+
+                let mut buffer = BufferData::new(first.font_id, text, spans, first.transform, first.color);
+                buffer.size_px = first.size_px;
+                buffer.optical_tracking = first.optical_tracking;
+                buffer.features = features;
+                buffer.snap_features();
+                buffer
+            };
+
+            self.inc_font(buffer.font_id);
+            for span in &buffer.spans {
+                self.inc_font(span.font_id);
+            }
+            self.insert_buffer(buffer)
+        }
+
+        /// Creates a buffer shaping each span with its own font; every span holds a reference to its font.
+        pub fn create_spanned_buffer(&mut self, font_id: usize, text: &str, spans: Vec<SpanData>, transform: Option<na::Projective3<f32>>, color: na::Vector4<u8>) -> usize {
+            self.inc_font(font_id);
+            for span in &spans {
+                self.inc_font(span.font_id);
+            }
+
+            let buffer = BufferData::new(font_id, text.to_string(), spans, transform, color);
+
+            self.insert_buffer(buffer)
+        }
+
+        pub fn buffer(&self, buffer_id: usize) -> Option<&BufferData> {
+            self.buffers.get(buffer_id)
+        }
+
+        pub fn buffer_glyph_slice(&self, buffer_id: usize) -> &[GlyphPosition] {
+            self.buffers.get(buffer_id).expect("buffer_glyph_slice: self.buffers.get(buffer_id)")
+                .glyphs()
+        }
+
+        #[cfg(feature = "tessellation")]
+        pub fn tessellate_buffer(&mut self, buffer_id: usize, px_size: f32) -> Result<BufferMesh, GlyphMeshError> {
+            // half a pixel of error is not visible after antialiasing
+            self.tessellate_buffer_with(buffer_id, px_size, |font, glyph_id, scale| {
+                font.glyph_mesh(glyph_id, HintingOptions::None, 0.5 / scale)
+            })
+        }
+
+        #[cfg(feature = "tessellation")]
+        pub fn tessellate_buffer_stroke(&mut self, buffer_id: usize, px_size: f32, stroke_width_px: f32) -> Result<BufferMesh, GlyphMeshError> {
+            self.tessellate_buffer_with(buffer_id, px_size, |font, glyph_id, scale| {
+                font.glyph_stroke_mesh(glyph_id, HintingOptions::None, stroke_width_px / scale, 0.5 / scale)
+            })
+        }
+
+        /// Places one mesh per distinct glyph, `mesh` gets the font, glyph id and
+        /// the font units to pixels scale.
+        #[cfg(feature = "tessellation")]
+        fn tessellate_buffer_with<F>(&mut self, buffer_id: usize, px_size: f32, mut mesh: F) -> Result<BufferMesh, GlyphMeshError>
+            where F: FnMut(&mut FontData, u32, f32) -> Result<Rc<GlyphMesh>, GlyphMeshError> {
+            let buffer = self.buffers.get(buffer_id).expect("tessellate_buffer_with: self.buffers.get(buffer_id)");
+            let scale = self.buffer_scale(buffer_id, px_size);
+            let fonts = &mut self.fonts_id_prop;
+
+            let mut output = BufferMesh::default();
+            let mut mesh_ids: MetroHashMap<(usize, u32), Option<usize>> = MetroHashMap::default();
+            let (mut pen_x, mut pen_y) = (0, 0);
+
+            for glyph in buffer.glyphs() {
+                if glyph.flags.is_hidden() {
+                    continue;
+                }
+                let font_id = buffer.span_font_id(glyph.span);
+                let font = fonts.get_mut(&font_id).expect("tessellate_buffer_with: fonts.get_mut(&font_id)");
+                let glyph_scale = px_size * buffer.span_scale(glyph.span) / font.metrics.units_per_em as f32;
+
+                let mesh_id = match mesh_ids.get(&(font_id, glyph.id)) {
+                    Some(mesh_id) => *mesh_id,
+                    None => {
+                        let mesh = mesh(font, glyph.id, glyph_scale)?;
+                        let mesh_id = if mesh.is_empty() {
+                            None
+                        } else {
+                            output.meshes.push(mesh);
+                            Some(output.meshes.len() - 1)
+                        };
+                        mesh_ids.insert((font_id, glyph.id), mesh_id);
+                        mesh_id
+                    }
+                };
+
+                if let Some(mesh) = mesh_id {
+                    output.instances.push(GlyphInstance {
+                        mesh,
+                        offset: [(pen_x + glyph.x_offset) as f32 * scale, (pen_y + glyph.y_offset) as f32 * scale],
+                        scale: glyph_scale,
+                    });
+                }
+
+                pen_x += glyph.x_advance;
+                pen_y += glyph.y_advance;
+            }
+
+            self.enforce_memory_budget();
+            Ok(output)
+        }
+
+        pub fn buffer_glyphs_scaled(&self, buffer_id: usize, px_size: f32, subpixel_phases: Option<u32>, output: &mut Vec<ScaledGlyph>) {
+            let buffer = self.buffers.get(buffer_id).expect("buffer_glyphs_scaled: self.buffers.get(buffer_id)");
+            let scale = self.buffer_scale(buffer_id, px_size);
+            let mut pen_x = RoundedPen::new(self.rounding, self.scale_factor);
+            let mut pen_y = 0;
+
+            for glyph in buffer.glyphs() {
+                if glyph.flags.is_hidden() {
+                    continue;
+                }
+                let x = pen_x.x + glyph.x_offset as f32 * scale;
+                let y = self.origin.y((pen_y + glyph.y_offset) as f32 * scale);
+
+                let (x, phase) = match subpixel_phases {
+                    Some(phases) if phases > 1 => {
+                        let x = x * self.scale_factor;
+                        let whole = x.floor();
+                        let phase = ((x - whole) * phases as f32).round() as u32;
+                        let (whole, phase) = if phase >= phases { (whole + 1.0, 0) } else { (whole, phase) };
+                        (whole / self.scale_factor, phase)
+                    }
+                    _ => (x, 0),
+                };
+
+                let highlight = buffer.highlight_of(glyph);
+                output.push(ScaledGlyph { id: glyph.id, span: glyph.span, x, y, phase, highlight });
+
+                pen_x.advance(glyph.x_advance as f32 * scale);
+                pen_y += glyph.y_advance;
+            }
+        }
+
+        pub fn buffer_glyphs_packed(&self, buffer_id: usize, px_size: f32, output: &mut Vec<PackedGlyph>) {
+            let buffer = self.buffers.get(buffer_id).expect("buffer_glyphs_packed: self.buffers.get(buffer_id)");
+            let scale = self.buffer_scale(buffer_id, px_size);
+            let mut pen_x = RoundedPen::new(self.rounding, self.scale_factor);
+            let mut pen_y = 0;
+
+            for glyph in buffer.glyphs() {
+                if glyph.flags.is_hidden() {
+                    continue;
+                }
+
+                output.push(PackedGlyph {
+                    pen_x: pen_x.x,
+                    pen_y: self.origin.y(pen_y as f32 * scale),
+                    offset_x: glyph.x_offset as f32 * scale,
+                    offset_y: self.origin.y(glyph.y_offset as f32 * scale),
+                    id: glyph.id,
+                    cluster: glyph.cluster,
+                });
+
+                pen_x.advance(glyph.x_advance as f32 * scale);
+                pen_y += glyph.y_advance;
+            }
+        }
+
+        pub fn buffer_glyphs_in_range(&self, buffer_id: usize, range: ::std::ops::Range<usize>, output: &mut Vec<GlyphPosition>) -> GlyphRangeInfo {
+            self.buffers.get(buffer_id).expect("buffer_glyphs_in_range: self.buffers.get(buffer_id)")
+                .glyphs_in_range(range, output)
+        }
+
+        pub fn buffer_cluster_range(&self, buffer_id: usize, glyph_index: usize) -> ::std::ops::Range<usize> {
+            self.buffers.get(buffer_id).expect("buffer_cluster_range: self.buffers.get(buffer_id)")
+                .cluster_range(glyph_index)
+        }
+
+        pub fn buffer_glyph_range_for_bytes(&self, buffer_id: usize, range: ::std::ops::Range<usize>) -> ::std::ops::Range<usize> {
+            self.buffers.get(buffer_id).expect("buffer_glyph_range_for_bytes: self.buffers.get(buffer_id)")
+                .glyph_range_for_bytes(range)
+        }
+
+        pub fn buffer_glyphs(&self, buffer_id: usize, output: &mut Vec<GlyphPosition>) {
+            self.buffers.get(buffer_id).expect("buffer_glyph_ids: self.buffers.get(buffer_id)")
+                .measure(output);
+        }
+
+        pub fn buffer_advance_width_px(&self, buffer_id: usize, px_size: f32) -> f32 {
+            let buffer = self.buffers.get(buffer_id).expect("buffer_advance_width_px: self.buffers.get(buffer_id)");
+            if self.rounding != RoundingMode::None {
+                return self.rounded_width_px(buffer_id, px_size, 0..usize::max_value());
+            }
+            let font = self.fonts_id_prop.get(&buffer.font_id).expect("buffer_advance_width_px: self.fonts_id_prop.get(&buffer.font_id)");
+            buffer.advance_width() as f32 * px_size / font.metrics.units_per_em as f32
+        }
+
+        /// Width in pixels of the glyphs with clusters in `range` as `buffer_glyphs_scaled`
+        /// places them, from the start of the first to the pen after the last.
+        fn rounded_width_px(&self, buffer_id: usize, px_size: f32, range: ::std::ops::Range<usize>) -> f32 {
+            let buffer = self.buffers.get(buffer_id).expect("rounded_width_px: self.buffers.get(buffer_id)");
+            let scale = self.buffer_scale(buffer_id, px_size);
+            let mut pen = RoundedPen::new(self.rounding, self.scale_factor);
+            let mut extent: Option<(f32, f32)> = None;
+
+            for glyph in buffer.glyphs() {
+                if glyph.flags.is_hidden() {
+                    continue;
+                }
+                let start = pen.x;
+                pen.advance(glyph.x_advance as f32 * scale);
+                if range.start <= glyph.cluster as usize && (glyph.cluster as usize) < range.end {
+                    let (min, max) = extent.unwrap_or((start, pen.x));
+                    extent = Some((min.min(start), max.max(pen.x)));
+                }
+            }
+
+            extent.map_or(0.0, |(min, max)| max - min)
+        }
+
+        /// Width in pixels without whitespace at the ends of the text, rounded as rendered.
+        pub fn buffer_trimmed_width_px(&self, buffer_id: usize, px_size: f32) -> f32 {
+            let buffer = self.buffers.get(buffer_id).expect("buffer_trimmed_width_px: self.buffers.get(buffer_id)");
+            if !buffer.has_text() {
+                return self.rounded_width_px(buffer_id, px_size, 0..usize::max_value());
+            }
+            let text = buffer.shaped_text();
+            let start = text.len() - text.trim_start().len();
+            self.rounded_width_px(buffer_id, px_size, start..text.trim_end().len())
+        }
+
+        /// Multiplier from the font units of a buffer to pixels at `px_size`.
+        pub fn buffer_scale(&self, buffer_id: usize, px_size: f32) -> f32 {
+            let buffer = self.buffers.get(buffer_id).expect("buffer_scale: self.buffers.get(buffer_id)");
+            let font = self.fonts_id_prop.get(&buffer.font_id).expect("buffer_scale: self.fonts_id_prop.get(&buffer.font_id)");
+            px_size / font.metrics.units_per_em as f32
+        }
+
+        pub fn measure(&self, buffer_id: usize, options: MeasureOptions, glyphs: &mut Vec<GlyphPosition>) -> Option<Measurement> {
+            let buffer = self.buffers.get(buffer_id).expect("get_buffer_size: self.buffers.get(buffer_id)");
+            let font = self.fonts_id_prop.get(&buffer.font_id).expect("get_buffer_size: self.fonts_id_prop.get(&buffer.font_id)");
+            if let Some(last_glyph_pos) = buffer.measure(glyphs) {
+                let width = (last_glyph_pos.0 - buffer.trimmed_advance(options)) as f32;
+                let window = buffer.shaped_window();
+                let width = match window {
+                    Some(ref window) => width * buffer.shaped_text().len() as f32 / window.len().max(1) as f32,
+                    None => width,
+                };
+                // taller spans in the font units of the buffer font
+                let units_per_em = font.metrics.units_per_em as f32;
+                let (ascent, descent, line_gap) = self.span_metrics(buffer_id, units_per_em).iter()
+                    .fold((font.metrics.ascent, font.metrics.descent, font.metrics.line_gap), |(ascent, descent, line_gap), span| {
+                        (ascent.max(span.ascent), descent.min(span.descent), line_gap.max(span.line_gap))
+                    });
+                Some(Measurement {
+                    ascent,
+                    descent,
+                    width,
+                    cap_height: font.metrics.cap_height,
+                    x_height: font.metrics.x_height,
+                    line_gap,
+                    height: line_gap + ascent - descent,
+                    estimated: window.is_some(),
+                })
+            } else {
+                None
+            }
+        }
+
+        pub fn buffer_layout_on_path(&self, buffer_id: usize, path: &Path, px_size: f32, options: &PathLayoutOptions) -> Vec<PlacedGlyph> {
+            let buffer = self.buffers.get(buffer_id).expect("buffer_layout_on_path: self.buffers.get(buffer_id)");
+            path_layout::place_on_path(buffer.glyphs(), self.buffer_scale(buffer_id, px_size), px_size, self.origin(), path, options)
+        }
+
+        pub fn layout(&self, buffer_id: usize, options: &LayoutOptions) -> TextLayout {
+            let buffer = self.buffers.get(buffer_id).expect("layout: self.buffers.get(buffer_id)");
+            let font = self.fonts_id_prop.get(&buffer.font_id).expect("layout: self.fonts_id_prop.get(&buffer.font_id)");
+
+            let features = hb_features(&buffer.shaping_features().into_iter().filter(|f| f.range.is_none()).collect::<Vec<_>>());
+            let px_size = options.resolve_px_size(self.buffer_size_px(buffer_id));
+
+            let metrics = self.span_metrics(buffer_id, px_size).iter()
+                .fold(ScaledMetrics::new(&font.metrics, px_size), |metrics, span| metrics.max_vertical(span.ascent, span.descent, span.line_gap));
+
+            let base = buffer.base_direction();
+            let fonts = &self.fonts_id_prop;
+            layout::layout_glyphs(&buffer.text, buffer.glyphs(), &metrics, options, base, |span, glyph_id| {
+                let scale = buffer.span_units(fonts, span);
+                self.fonts_id_prop.get(&buffer.span_font_id(span)).and_then(|f| f.glyph_bounds(glyph_id))
+                    .map(|b| Bounds { min_x: b.min_x * scale, min_y: b.min_y * scale, max_x: b.max_x * scale, max_y: b.max_y * scale })
+            }, |text, span| {
+                let font = self.fonts_id_prop.get(&buffer.span_font_id(span)).expect("layout: span font should be loaded");
+                let mut glyphs = shape_fragment(font, text, span, &features, base);
+                let scale = buffer.span_units(fonts, span);
+                if scale != 1.0 {
+                    glyphs.iter_mut().for_each(|glyph| scale_glyph(glyph, scale));
+                }
+                let shift = buffer.span_shift_units(fonts, span);
+                glyphs.iter_mut().for_each(|glyph| glyph.y_offset += shift);
+                glyphs
+            }, |span, glyph_id| {
+                let scale = buffer.span_units(fonts, span);
+                self.fonts_id_prop.get(&buffer.span_font_id(span)).map_or_else(Vec::new, |f| f.ligature_carets(glyph_id))
+                    .into_iter().map(|caret| (caret as f32 * scale).round() as i32).collect()
+            }, |offset| buffer.source_offset(offset)).with_origin(self.origin)
+        }
+
+        /// Vertical metrics of the spans of a buffer with their own size at `px_size`.
+        pub fn span_metrics(&self, buffer_id: usize, px_size: f32) -> Vec<SpanMetrics> {
+            let buffer = self.buffers.get(buffer_id).expect("span_metrics: self.buffers.get(buffer_id)");
+            buffer.span_list().into_iter()
+                .map(|span| {
+                    let font = self.fonts_id_prop.get(&span.font_id).expect("span_metrics: span font should be loaded");
+                    let shift = span.shift * px_size;
+                    let px_size = px_size * span.scale;
+                    let scale = px_size / font.metrics.units_per_em as f32;
+                    SpanMetrics {
+                        range: span.range,
+                        px_size,
+                        ascent: font.metrics.ascent * scale + shift,
+                        descent: font.metrics.descent * scale + shift,
+                        line_gap: font.metrics.line_gap * scale,
+                    }
+                })
+                .collect()
+        }
+
+        pub fn buffer_debug_shape_string(&self, buffer_id: usize) -> String {
+            let buffer = self.buffers.get(buffer_id).expect("buffer_debug_shape_string: self.buffers.get(buffer_id)");
+            debug_shape::serialize(buffer.glyphs(), |glyph| {
+                self.fonts_id_prop.get(&buffer.span_font_id(glyph.span)).and_then(|font| font.glyph_name(glyph.id))
+            })
+        }
+
+        pub fn buffer_outline_into<B: PathBuilder>(&mut self, buffer_id: usize, px_size: f32, builder: &mut B) {
+            let buffer = self.buffers.get(buffer_id).expect("buffer_outline_into: self.buffers.get(buffer_id)");
+            let scale = {
+                let font = self.fonts_id_prop.get(&buffer.font_id).expect("buffer_outline_into: self.fonts_id_prop.get(&buffer.font_id)");
+                px_size / font.metrics.units_per_em as f32
+            };
+            let flip_y = self.origin == Origin::TopLeftYDown;
+            let (mut x, mut y) = (0, 0);
+
+            for glyph in buffer.glyphs() {
+                let (pen_x, pen_y) = (x + glyph.x_offset, y + glyph.y_offset);
+                x += glyph.x_advance;
+                y += glyph.y_advance;
+                if glyph.flags.is_hidden() {
+                    continue;
+                }
+
+                let font = self.fonts_id_prop.get_mut(&buffer.span_font_id(glyph.span)).expect("buffer_outline_into: span font should be loaded");
+                let outline = match font.outline_cached(glyph.id, HintingOptions::None) {
+                    Ok(outline) => outline,
+                    Err(e) => {
+                        self.diagnostics.report(FontsDiagnostic::OutlineFailed { glyph_id: glyph.id, reason: format!("{:?}", e) });
+                        continue;
+                    }
+                };
+                let glyph_scale = px_size * buffer.span_scale(glyph.span) / font.metrics.units_per_em as f32;
+                let offset = (pen_x as f32 * scale, self.origin.y(pen_y as f32 * scale));
+                copy_path_at(&outline, glyph_scale, offset, flip_y, builder);
+            }
+
+            self.enforce_memory_budget();
+        }
+
+        pub fn buffer_to_svg(&self, buffer_id: usize, px_size: f32) -> String {
+            let buffer = self.buffers.get(buffer_id).expect("buffer_to_svg: self.buffers.get(buffer_id)");
+            let font = self.fonts_id_prop.get(&buffer.font_id).expect("buffer_to_svg: self.fonts_id_prop.get(&buffer.font_id)");
+            let scale = px_size / font.metrics.units_per_em as f32;
+
+            let mut writer = SvgPathWriter::new(scale);
+            let (mut x, mut y) = (0, 0);
+
+            for glyph in buffer.glyphs() {
+                if glyph.flags.is_hidden() {
+                    continue;
+                }
+                let font = self.fonts_id_prop.get(&buffer.span_font_id(glyph.span)).expect("buffer_to_svg: span font should be loaded");
+                writer.set_offset((x + glyph.x_offset) as f32, (y + glyph.y_offset) as f32);
+                writer.set_glyph_scale(buffer.span_units(&self.fonts_id_prop, glyph.span));
+                if let Err(e) = font.outline(glyph.id, HintingOptions::None, &mut writer) {
+                    self.diagnostics.report(FontsDiagnostic::OutlineFailed { glyph_id: glyph.id, reason: format!("{:?}", e) });
+                }
+
+                x += glyph.x_advance;
+                y += glyph.y_advance;
+            }
+
+            let view_box = match self.ink_bounds_y_up(buffer_id) {
+                Some(b) => format!("{} {} {} {}", b.min_x * scale, -b.max_y * scale, b.width() * scale, b.height() * scale),
+                None => "0 0 0 0".to_string(),
+            };
+
+            let data = writer.data().trim_end();
+            if data.is_empty() {
+                format!("<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"{}\"></svg>", view_box)
+            } else {
+                format!("<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"{}\"><path d=\"{}\"/></svg>", view_box, data)
+            }
+        }
+
+        pub fn ink_bounds(&self, buffer_id: usize) -> Option<Bounds> {
+            self.ink_bounds_y_up(buffer_id).map(|b| self.origin.bounds(b))
+        }
+
+        pub fn buffer_pen_position_at(&self, buffer_id: usize, index: usize, px_size: f32) -> Option<(f32, f32)> {
+            let buffer = self.buffers.get(buffer_id).expect("buffer_pen_position_at: self.buffers.get(buffer_id)");
+            let (rounding, scale_factor) = (self.rounding, self.scale_factor);
+            let valid = |pens: &Rc<PenPositions>| pens.px_size == px_size && pens.rounding == rounding && pens.scale_factor == scale_factor;
+            let pens = buffer.pen_positions.get_if(buffer.epoch, valid, || {
+                let scale = self.buffer_scale(buffer_id, px_size);
+                let mut pen_x = RoundedPen::new(rounding, scale_factor);
+                let mut pen_y = 0.0;
+                let mut positions = Vec::with_capacity(buffer.glyphs().len() + 1);
+                for glyph in buffer.glyphs() {
+                    positions.push((pen_x.x, pen_y));
+                    // like `buffer_glyphs_scaled`, hidden glyphs do not move the pen
+                    if glyph.flags.is_hidden() {
+                        continue;
+                    }
+                    pen_x.advance(glyph.x_advance as f32 * scale);
+                    pen_y += glyph.y_advance as f32 * scale;
+                }
+                positions.push((pen_x.x, pen_y));
+                Rc::new(PenPositions { px_size, rounding, scale_factor, positions })
+            });
+            pens.positions.get(index).map(|&(x, y)| (x, self.origin.y(y)))
+        }
+
+        pub fn buffer_glyph_info(&self, buffer_id: usize, glyph_index: usize) -> Option<GlyphInfo> {
+            let buffer = self.buffers.get(buffer_id).expect("buffer_glyph_info: self.buffers.get(buffer_id)");
+            let infos = buffer.glyph_infos.get(buffer.epoch, || {
+                let glyphs = buffer.glyphs();
+                let mut infos = Vec::with_capacity(glyphs.len());
+                let mut start = 0;
+                while start < glyphs.len() {
+                    let cluster = glyphs[start].cluster;
+                    let end = start + glyphs[start..].iter().take_while(|g| g.cluster == cluster).count();
+                    let graphemes = buffer.cluster_graphemes(cluster);
+
+                    for (i, glyph) in glyphs[start..end].iter().enumerate() {
+                        let font = self.fonts_id_prop.get(&buffer.span_font_id(glyph.span)).expect("buffer_glyph_info: span font should be loaded");
+                        let (is_ligature, is_mark) = match font.glyph_class(glyph.id) {
+                            Some(class) => (class == gdef::GLYPH_CLASS_LIGATURE, class == gdef::GLYPH_CLASS_MARK),
+                            None => (end - start == 1 && graphemes > 1, i > 0 && glyph.x_advance == 0),
+                        };
+                        let component_count = if is_ligature {
+                            match font.ligature_carets(glyph.id).len() {
+                                0 => graphemes.max(1),
+                                carets => carets as u32 + 1,
+                            }
+                        } else {
+                            1
+                        };
+                        infos.push(GlyphInfo { is_ligature, component_count, is_mark, is_cluster_start: i == 0 });
+                    }
+                    start = end;
+                }
+                Rc::from(infos)
+            });
+            infos.get(glyph_index).cloned()
+        }
+
+        /// Ink bounds in font units with Y up, whatever the origin.
+        fn ink_bounds_y_up(&self, buffer_id: usize) -> Option<Bounds> {
+            let buffer = self.buffers.get(buffer_id).expect("ink_bounds: self.buffers.get(buffer_id)");
+
+            // glyph bounds of a loaded font never change, only the glyphs do
+            buffer.ink_bounds.get(buffer.epoch, || {
+                let mut bounds: Option<Bounds> = None;
+                let (mut x, mut y) = (0, 0);
+
+                for glyph in buffer.glyphs() {
+                    if glyph.flags.is_hidden() {
+                        continue;
+                    }
+                    let font = self.fonts_id_prop.get(&buffer.span_font_id(glyph.span)).expect("ink_bounds: span font should be loaded");
+                    if let Some(b) = font.glyph_bounds(glyph.id) {
+                        let gx = (x + glyph.x_offset) as f32;
+                        let gy = (y + glyph.y_offset) as f32;
+                        let units = buffer.span_units(&self.fonts_id_prop, glyph.span);
+                        let b = Bounds {
+                            min_x: gx + b.min_x * units,
+                            min_y: gy + b.min_y * units,
+                            max_x: gx + b.max_x * units,
+                            max_y: gy + b.max_y * units,
+                        };
+                        bounds = Some(bounds.map_or(b, |acc| acc.union(&b)));
+                    }
+
+                    x += glyph.x_advance;
+                    y += glyph.y_advance;
+                }
+
+                bounds
+            })
+        }
+
+        pub fn buffer_cursor_positions(&self, buffer_id: usize) -> Vec<usize> {
+            self.buffers.get(buffer_id).expect("buffer_cursor_positions: self.buffers.get(buffer_id)")
+                .cursor_positions()
+        }
+
+        pub fn set_buffer_text(&mut self, buffer_id: usize, text: Cow<str>) {
+            let version = self.buffers.get(buffer_id).expect("set_buffer_text: self.buffers.get(buffer_id)").version;
+            let shaped_len = self.buffers[buffer_id].shaped_len_of(&text);
+            let dropped = self.buffers[buffer_id].take_features_outside(shaped_len);
+            self.report_dropped_features(dropped);
+            let released = {
+                let fonts = &self.fonts_id_prop;
+                self.buffers[buffer_id].replace(fonts, &mut self.shaping_cache, text)
+            };
+            self.buffer_changed_since(buffer_id, version);
+            for font_id in released {
+                self.dec_font(font_id);
+            }
+            self.sync_buffer_lines(buffer_id);
+            self.buffers[buffer_id].drop_unretained_text();
+        }
+
+        #[cfg(not(feature = "simple-shaping"))]
+        pub fn with_buffer_raw_glyph_buffer<R, F: FnOnce(&hb::GlyphBuffer) -> R>(&self, buffer_id: usize, f: F) -> R {
+            let buffer = self.buffers.get(buffer_id).expect("with_buffer_raw_glyph_buffer: self.buffers.get(buffer_id)");
+            let font = self.get(buffer.font_id).expect("with_buffer_raw_glyph_buffer: buffer font should exist");
+            let unicode_buffer = hb::UnicodeBuffer::new().add_str(buffer.shaped_text());
+            f(&hb::shape(&font.hb_font, unicode_buffer, &hb_features(&buffer.shaping_features())))
+        }
+
+        pub fn set_buffer_text_diff(&mut self, buffer_id: usize, text: &str) -> GlyphDiff {
+            self.ensure_buffer_shaped(buffer_id);
+            let (old_shaped, old_source, old_glyphs) = {
+                let buffer = self.buffers.get(buffer_id).expect("set_buffer_text_diff: self.buffers.get(buffer_id)");
+                (buffer.shaped_text().to_string(), buffer.text().to_string(), buffer.glyphs().to_vec())
+            };
+
+            self.set_buffer_text(buffer_id, Cow::Borrowed(text));
+            self.ensure_buffer_shaped(buffer_id);
+
+            let buffer = &self.buffers[buffer_id];
+            glyph_diff::diff_glyphs(&old_shaped, &old_source, &old_glyphs, buffer.shaped_text(), buffer.text(), buffer.glyphs())
+        }
+
+        pub fn set_buffer_whitespace(&mut self, buffer_id: usize, whitespace: Whitespace) {
+            self.reshape_buffer(buffer_id, None, None, Some(whitespace), None);
+        }
+
+        pub fn set_buffer_sanitize(&mut self, buffer_id: usize, sanitize: bool) {
+            let version = self.buffers.get(buffer_id).expect("set_buffer_sanitize: self.buffers.get(buffer_id)").version;
+            if !self.buffers[buffer_id].has_text() {
+                return;
+            }
+            {
+                let fonts = &self.fonts_id_prop;
+                self.buffers[buffer_id].set_sanitize(fonts, &mut self.shaping_cache, sanitize);
+            }
+            self.buffer_changed_since(buffer_id, version);
+            self.sync_buffer_lines(buffer_id);
+            self.buffers[buffer_id].drop_unretained_text();
+        }
+
+        pub fn set_buffer_case(&mut self, buffer_id: usize, case: Case, locale: Option<String>) {
+            let version = self.buffers.get(buffer_id).expect("set_buffer_case: self.buffers.get(buffer_id)").version;
+            if !self.buffers[buffer_id].has_text() {
+                return;
+            }
+            {
+                let fonts = &self.fonts_id_prop;
+                self.buffers[buffer_id].set_case(fonts, &mut self.shaping_cache, case, locale);
+            }
+            self.buffer_changed_since(buffer_id, version);
+            self.sync_buffer_lines(buffer_id);
+            self.buffers[buffer_id].drop_unretained_text();
+        }
+
+        /// Byte range in the buffer text of a byte range in the shaped text.
+        pub fn buffer_source_range(&self, buffer_id: usize, range: ::std::ops::Range<usize>) -> ::std::ops::Range<usize> {
+            let buffer = self.buffers.get(buffer_id).expect("buffer_source_range: self.buffers.get(buffer_id)");
+            buffer.source_offset(range.start)..buffer.source_offset(range.end)
+        }
+
+        pub fn set_buffer_features(&mut self, buffer_id: usize, features: Vec<Feature>) {
+            self.reshape_buffer(buffer_id, None, Some(features), None, None);
+        }
+
+        fn report_dropped_features(&self, features: Vec<Feature>) {
+            for feature in features {
+                self.diagnostics.report(FontsDiagnostic::FeatureDropped {
+                    tag: String::from_utf8_lossy(&feature.tag).into_owned(),
+                    range: feature.range.unwrap_or(0..0),
+                });
+            }
+        }
+
+        pub fn set_buffer_font(&mut self, buffer_id: usize, font_id: usize) {
+            self.reshape_buffer(buffer_id, Some(font_id), None, None, None);
+        }
+
+        /// Applies the given shaping parameters and shapes once, see `Buffer::reshape_with`.
+        pub fn reshape_buffer(&mut self, buffer_id: usize, font_id: Option<usize>, features: Option<Vec<Feature>>, whitespace: Option<Whitespace>, base_direction: Option<BaseDirection>) {
+            let buffer = self.buffers.get(buffer_id).expect("reshape_buffer: self.buffers.get(buffer_id)");
+            if !buffer.has_text() {
+                return;
+            }
+            let version = buffer.version;
+            let settings_changed = features.is_some() || whitespace.is_some() || base_direction.is_some();
+            let font_id = font_id.filter(|&id| id != buffer.font_id);
+            if let Some(font_id) = font_id {
+                self.inc_font(font_id);
+            }
+
+            let (released, dropped) = {
+                let fonts = &self.fonts_id_prop;
+                self.buffers[buffer_id].batch(fonts, &mut self.shaping_cache, |buffer, fonts, cache| {
+                    if let Some(whitespace) = whitespace {
+                        buffer.set_whitespace(fonts, cache, whitespace);
+                    }
+                    if let Some(base) = base_direction {
+                        buffer.set_base_direction(fonts, cache, base);
+                    }
+                    let dropped = match features {
+                        Some(features) => {
+                            let text_len = buffer.shaped_text().len();
+                            let (dropped, features): (Vec<_>, Vec<_>) = features.into_iter().partition(|f| feature_outside(f, text_len));
+                            buffer.set_features(fonts, cache, features);
+                            dropped
+                        }
+                        None => Vec::new(),
+                    };
+                    let released = match font_id {
+                        Some(font_id) => buffer.set_font(fonts, cache, font_id),
+                        None => Vec::new(),
+                    };
+                    (released, dropped)
+                })
+            };
+            self.report_dropped_features(dropped);
+            // the previous buffer font is released along with the span fonts
+            if released.len() > 1 {
+                self.diagnostics.report(FontsDiagnostic::SpansDropped { buffer_id, spans: released.len() - 1 });
+            }
+            self.buffer_changed_since(buffer_id, version);
+            for font_id in released {
+                self.dec_font(font_id);
+            }
+
+            if let Some(font_id) = font_id {
+                let lines = self.buffers[buffer_id].linked_lines.clone().unwrap_or_default();
+                for id in lines {
+                    self.set_buffer_font(id, font_id);
+                }
+            }
+            if settings_changed {
+                self.sync_buffer_lines(buffer_id);
+            }
+        }
+
+        pub fn splice_buffer_text(&mut self, buffer_id: usize, range: ::std::ops::Range<usize>, replacement: &str) {
+            let version = self.buffers.get(buffer_id).expect("splice_buffer_text: self.buffers.get(buffer_id)").version;
+            if !self.buffers[buffer_id].has_text() {
+                return;
+            }
+            if self.buffers[buffer_id].transforms_text() {
+                // an edit can join or split whitespace runs anywhere in the collapsed text, and
+                // change the case of the text around it
+                let mut text = self.buffers[buffer_id].text().to_string();
+                text.replace_range(range, replacement);
+                return self.set_buffer_text(buffer_id, Cow::Owned(text));
+            }
+            {
+                let fonts = &self.fonts_id_prop;
+                self.buffers[buffer_id].splice(fonts, &mut self.shaping_cache, range, replacement);
+            }
+            self.buffer_changed_since(buffer_id, version);
+            self.sync_buffer_lines(buffer_id);
+        }
+
+        /// Replaces the preedit, keeping the committed text, see `Buffer::set_preedit`.
+        pub fn set_buffer_preedit(&mut self, buffer_id: usize, byte_index: usize, preedit: &str, cursor: usize) {
+            let old = {
+                let buffer = self.buffers.get(buffer_id).expect("set_buffer_preedit: self.buffers.get(buffer_id)");
+                if !buffer.has_text() {
+                    return;
+                }
+                let old = buffer.preedit.clone();
+                let committed_len = buffer.text().len() - old.as_ref().map_or(0, |old| old.range.len());
+                assert!(byte_index <= committed_len, "set_buffer_preedit: byte_index {} past the committed text of {} bytes", byte_index, committed_len);
+                if let Some(ref old) = old {
+                    if old.range.start == byte_index && &buffer.text()[old.range.clone()] == preedit {
+                        let cursor = cursor.min(preedit.len());
+                        self.buffers[buffer_id].preedit = Some(Preedit { range: old.range.clone(), cursor });
+                        return;
+                    }
+                }
+                old
+            };
+
+            match old {
+                Some(ref old) if old.range.start == byte_index => self.splice_buffer_text(buffer_id, old.range.clone(), preedit),
+                Some(ref old) => {
+                    self.splice_buffer_text(buffer_id, old.range.clone(), "");
+                    self.splice_buffer_text(buffer_id, byte_index..byte_index, preedit);
+                }
+                None => self.splice_buffer_text(buffer_id, byte_index..byte_index, preedit),
+            }
+
+            let preedit = if preedit.is_empty() {
+                None
+            } else {
+                Some(Preedit { range: byte_index..byte_index + preedit.len(), cursor: cursor.min(preedit.len()) })
+            };
+            self.buffers[buffer_id].set_preedit(preedit);
+        }
+
+        /// Replaces the user data of a buffer, returning the previous data to drop after the
+        /// container borrow ends.
+        pub fn set_buffer_user_data(&mut self, buffer_id: usize, data: Option<Box<dyn Any>>) -> Option<Box<dyn Any>> {
+            let buffer = self.buffers.get_mut(buffer_id).expect("set_buffer_user_data: self.buffers.get_mut(buffer_id)");
+            ::std::mem::replace(&mut buffer.user_data, data)
+        }
+
+        pub fn set_buffer_visible_range(&mut self, buffer_id: usize, range: Option<::std::ops::Range<usize>>) {
+            let version = self.buffers.get(buffer_id).expect("set_buffer_visible_range: self.buffers.get(buffer_id)").version;
+            {
+                let fonts = &self.fonts_id_prop;
+                self.buffers[buffer_id].set_visible_range(fonts, &mut self.shaping_cache, range);
+            }
+            self.buffer_changed_since(buffer_id, version);
+        }
+
+        /// Byte range in the buffer text of the shaped window, see `Buffer::shaped_window`.
+        pub fn buffer_shaped_window(&self, buffer_id: usize) -> Option<::std::ops::Range<usize>> {
+            let buffer = self.buffers.get(buffer_id).expect("buffer_shaped_window: self.buffers.get(buffer_id)");
+            buffer.shaped_window().map(|window| buffer.source_offset(window.start)..buffer.source_offset(window.end))
+        }
+
+        pub fn clear_buffer_preedit(&mut self, buffer_id: usize) {
+            let range = self.buffers.get(buffer_id).expect("clear_buffer_preedit: self.buffers.get(buffer_id)")
+                .preedit.as_ref().map(|preedit| preedit.range.clone());
+            if let Some(range) = range {
+                self.splice_buffer_text(buffer_id, range, "");
+                self.buffers[buffer_id].set_preedit(None);
+            }
+        }
+
+        pub fn append_buffer_text(&mut self, buffer_id: usize, text: &str) {
+            let version = self.buffers.get(buffer_id).expect("append_buffer_text: self.buffers.get(buffer_id)").version;
+            if !self.buffers[buffer_id].has_text() {
+                return;
+            }
+            if self.buffers[buffer_id].transforms_text() {
+                let text = self.buffers[buffer_id].text().to_string() + text;
+                return self.set_buffer_text(buffer_id, Cow::Owned(text));
+            }
+            {
+                let fonts = &self.fonts_id_prop;
+                self.buffers[buffer_id].append(fonts, &mut self.shaping_cache, text);
+            }
+            self.buffer_changed_since(buffer_id, version);
+            self.sync_buffer_lines(buffer_id);
+        }
+
+        /// Creates one buffer per paragraph of the buffer text.
+        pub fn split_buffer_lines(&mut self, buffer_id: usize) -> Vec<usize> {
+            let buffer = self.buffers.get(buffer_id).expect("split_buffer_lines: self.buffers.get(buffer_id)");
+            if !buffer.has_text() {
+                return Vec::new();
+            }
+            let ranges = layout::paragraph_ranges(buffer.shaped_text());
+
+            ranges.into_iter()
+                .enumerate()
+                .map(|(line_index, range)| self.create_line_buffer(buffer_id, line_index, range))
+                .collect()
+        }
+
+        /// Splits the buffer into line buffers kept in sync with its text, or returns the
+        /// current ones if it is already linked.
+        pub fn link_buffer_lines(&mut self, buffer_id: usize) -> Vec<usize> {
+            if let Some(ref lines) = self.buffers.get(buffer_id).expect("link_buffer_lines: self.buffers.get(buffer_id)").linked_lines {
+                return lines.clone();
+            }
+
+            let lines = self.split_buffer_lines(buffer_id);
+            self.buffers[buffer_id].linked_lines = Some(lines.clone());
+            lines
+        }
+
+        pub fn unlink_buffer_lines(&mut self, buffer_id: usize) {
+            let lines = self.buffers.get_mut(buffer_id).expect("unlink_buffer_lines: self.buffers.get_mut(buffer_id)").linked_lines.take();
+            for id in lines.into_iter().flat_map(|lines| lines) {
+                self.dec_buffer(id);
+            }
+        }
+
+        fn create_line_buffer(&mut self, buffer_id: usize, line_index: usize, range: ::std::ops::Range<usize>) -> usize {
+            let (mut buffer, settings) = {
+                let parent = &self.buffers[buffer_id];
+                let spans = parent.spans_in(range.clone());
+                let settings = parent.line_settings(range.clone());
+                (BufferData::new(parent.font_id, parent.text[range].to_string(), spans, parent.transform, parent.color), settings)
+            };
+            buffer.line_index = Some(line_index);
+            buffer.size_px = self.buffers[buffer_id].size_px;
+            buffer.optical_tracking = self.buffers[buffer_id].optical_tracking;
+            buffer.set_line_settings(&self.fonts_id_prop, &mut self.shaping_cache, settings);
+
+            self.inc_font(buffer.font_id);
+            for span in &buffer.spans {
+                self.inc_font(span.font_id);
+            }
+
+            self.insert_buffer(buffer)
+        }
+
+        /// Re-splits a linked buffer after a text or shaping settings change, updating line
+        /// buffers in place and creating or releasing buffers for a different number of paragraphs.
+        fn sync_buffer_lines(&mut self, buffer_id: usize) {
+            let lines = match self.buffers[buffer_id].linked_lines.take() {
+                Some(lines) => lines,
+                None => return,
+            };
+
+            let ranges = layout::paragraph_ranges(&self.buffers[buffer_id].text);
+            let mut synced = Vec::with_capacity(ranges.len());
+
+            for (line_index, range) in ranges.into_iter().enumerate() {
+                match lines.get(line_index) {
+                    Some(&id) => {
+                        let (text, settings) = {
+                            let parent = &self.buffers[buffer_id];
+                            (parent.text[range.clone()].to_string(), parent.line_settings(range))
+                        };
+                        // settings first, so features of the new line range are not dropped
+                        let version = self.buffers[id].version;
+                        {
+                            let fonts = &self.fonts_id_prop;
+                            self.buffers[id].set_line_settings(fonts, &mut self.shaping_cache, settings);
+                        }
+                        self.buffer_changed_since(id, version);
+                        self.set_buffer_text(id, Cow::Owned(text));
+                        synced.push(id);
+                    },
+                    None => synced.push(self.create_line_buffer(buffer_id, line_index, range)),
+                }
+            }
+
+            self.buffers[buffer_id].linked_lines = Some(synced.clone());
+            for &id in lines.iter().skip(synced.len()) {
+                self.dec_buffer(id);
+            }
+        }
+
+        /// Transform in logical pixels, not scaled by `scale_factor`.
+        pub fn get_buffer_transform(&self, buffer_id: usize) -> Option<na::Projective3<f32>> {
+            self.buffers[buffer_id].transform
+        }
+
+        pub fn get_buffer_color(&self, buffer_id: usize) -> na::Vector4<u8> {
+            self.buffers[buffer_id].color
+        }
+
+        pub fn set_buffer_highlights(&mut self, buffer_id: usize, spans: &[(::std::ops::Range<usize>, u32)]) {
+            self.buffers.get_mut(buffer_id).expect("set_buffer_highlights: self.buffers.get_mut(buffer_id)").set_highlights(spans);
+        }
+
+        pub fn set_buffer_transform(&mut self, buffer_id: usize, transform: Option<na::Projective3<f32>>) {
+            let buffer = &mut self.buffers[buffer_id];
+            buffer.transform = transform;
+            buffer.transform_version += 1;
+        }
+
+        pub fn set_buffer_size_px(&mut self, buffer_id: usize, px_size: f32) {
+            let buffer = self.buffers.get_mut(buffer_id).expect("set_buffer_size_px: self.buffers.get_mut(buffer_id)");
+            if buffer.size_px != Some(px_size) {
+                buffer.size_px = Some(px_size);
+                buffer.transform_version += 1;
+                let enabled = buffer.optical_tracking;
+                self.update_buffer_tracking(buffer_id, enabled);
+            }
+        }
+
+        pub fn set_buffer_optical_tracking(&mut self, buffer_id: usize, enabled: bool) {
+            self.update_buffer_tracking(buffer_id, enabled);
+        }
+
+        /// Applies the tracking settings and current size of a buffer, notifying listeners
+        /// if its advances change.
+        fn update_buffer_tracking(&mut self, buffer_id: usize, enabled: bool) {
+            let default_size_px = self.default_size_px;
+            let buffer = self.buffers.get_mut(buffer_id).expect("update_buffer_tracking: self.buffers.get_mut(buffer_id)");
+            let px_size = buffer.size_px.unwrap_or(default_size_px);
+            if buffer.set_tracking(enabled, px_size) {
+                let buffer = self.buffer_ref(buffer_id);
+                self.events.push(FontsEvent::BufferChanged(buffer));
+            }
+        }
+
+        pub fn buffer_size_px(&self, buffer_id: usize) -> f32 {
+            let buffer = self.buffers.get(buffer_id).expect("buffer_size_px: self.buffers.get(buffer_id)");
+            buffer.size_px.unwrap_or(self.default_size_px)
+        }
+
+        pub fn scale_factor(&self) -> f32 {
+            self.scale_factor
+        }
+
+        pub fn set_scale_factor(&mut self, scale_factor: f32) {
+            assert!(scale_factor > 0.0 && scale_factor.is_finite(), "set_scale_factor: factor should be positive");
+            if self.scale_factor == scale_factor {
+                return;
+            }
+            self.scale_factor = scale_factor;
+            for font in self.fonts_id_prop.values_mut() {
+                font.clear_sized_caches();
+            }
+            self.events.push(FontsEvent::ScaleFactorChanged);
+        }
+
+        pub fn origin(&self) -> Origin {
+            self.origin
+        }
+
+        pub fn rounding(&self) -> RoundingMode {
+            self.rounding
+        }
+
+        pub fn set_rounding(&mut self, mode: RoundingMode) {
+            if self.rounding == mode {
+                return;
+            }
+            self.rounding = mode;
+            for (_, buffer) in self.buffers.iter_mut() {
+                buffer.transform_version += 1;
+            }
+        }
+
+        pub fn metrics_policy(&self) -> MetricsPolicy {
+            self.metrics_policy
+        }
+
+        pub fn set_metrics_policy(&mut self, policy: MetricsPolicy) {
+            if self.metrics_policy == policy {
+                return;
+            }
+            self.metrics_policy = policy;
+
+            let mut changed = false;
+            for font in self.fonts_id_prop.values_mut() {
+                changed |= font.set_metrics_policy(policy);
+            }
+            if changed {
+                for (_, buffer) in self.buffers.iter_mut() {
+                    buffer.transform_version += 1;
+                }
+                self.events.push(FontsEvent::MetricsChanged);
+            }
+        }
+
+        pub fn set_origin(&mut self, origin: Origin) {
+            if self.origin == origin {
+                return;
+            }
+            self.origin = origin;
+            for (_, buffer) in self.buffers.iter_mut() {
+                buffer.transform_version += 1;
+            }
+        }
+
+        pub fn default_size_px(&self) -> f32 {
+            self.default_size_px
+        }
+
+        pub fn set_default_size_px(&mut self, px_size: f32) {
+            if self.default_size_px == px_size {
+                return;
+            }
+            self.default_size_px = px_size;
+            let mut unsized_ids = Vec::new();
+            for (id, buffer) in self.buffers.iter_mut() {
+                if buffer.size_px.is_none() {
+                    buffer.transform_version += 1;
+                    unsized_ids.push((id, buffer.optical_tracking));
+                }
+            }
+            for (id, enabled) in unsized_ids {
+                self.update_buffer_tracking(id, enabled);
+            }
+        }
+
+        #[cfg_attr(debug_assertions, track_caller)]
+        pub fn get_and_inc_buffer(&mut self, id: usize) -> Option<(usize, usize)> {
+            let buffer_data = self.buffers.get_mut(id)?;
+            buffer_data.count += 1;
+            self.audit.inc(Kind::Buffer, id, 1);
+            Some((buffer_data.font_id, id))
+        }
+
+        #[cfg_attr(debug_assertions, track_caller)]
+        pub fn inc_buffer(&mut self, id: usize) {
+            let data = self.buffers.get_mut(id).expect("inc_buffer: self.buffers.get_mut(id)");
+            data.count += 1;
+            self.audit.inc(Kind::Buffer, id, 1);
+        }
+
+        pub fn dec_buffer(&mut self, id: usize) {
+            let delete = {
+                let data = self.buffers.get_mut(id).expect("dec_buffer: self.buffers.get_mut(id)");
+                data.count -= 1;
+                data.count <= 0
+            };
+            self.audit.dec(Kind::Buffer, id);
+
+            if delete {
+                self.delete_buffer(id);
+            }
+        }
+
+        pub fn delete_buffer(&mut self, id: usize) {
+            let buffer_ref = self.buffer_ref(id);
+            let buffer = self.buffers.remove(id);
+            self.audit.removed(Kind::Buffer, id);
+            if let Some(key) = buffer.intern_key {
+                self.forget_interned(key, id);
+            }
+            self.events.push(FontsEvent::BufferDeleted(buffer_ref));
+
+            for line in buffer.linked_lines.iter().flat_map(|lines| lines) {
+                self.dec_buffer(*line);
+            }
+
+            for font_id in buffer.span_font_ids() {
+                self.dec_font(font_id);
+            }
+            self.dec_font(buffer.font_id);
+
+            if buffer.recycle && self.buffer_pool.len() < self.buffer_pool_capacity {
+                self.buffer_pool.push(buffer.into_recycled());
+            }
+        }
+
+        #[cfg_attr(debug_assertions, track_caller)]
+        pub fn inc_font(&mut self, id: usize) {
+            let data = self.fonts_id_prop.get_mut(&id).expect("inc_font: self.fonts_id_prop.get_mut(&id)");
+            if data.count == 0 {
+                self.retained.revive(id);
+            }
+            data.count += 1;
+            self.audit.inc(Kind::Font, id, 1);
+        }
+
+        pub fn font_alive(&self, font_ref: FontRef) -> bool {
+            font_ref._container == self.token && self.fonts_id_prop.get(&font_ref._id).map_or(false, |font| font.generation == font_ref._generation)
+        }
+
+        #[cfg_attr(debug_assertions, track_caller)]
+        pub fn get_and_inc_font(&mut self, id: usize) -> Option<usize> {
+            let data = self.fonts_id_prop.get_mut(&id)?;
+            if data.count == 0 {
+                self.retained.revive(id);
+            }
+            data.count += 1;
+            self.audit.inc(Kind::Font, id, 1);
+            Some(id)
+        }
+
+        pub fn dec_font(&mut self, id: usize) {
+            let delete = {
+                let data = self.fonts_id_prop.get_mut(&id).expect("dec_font: self.fonts.get_mut(id)");
+                data.count -= 1;
+                data.count <= 0
+            };
+            self.audit.dec(Kind::Font, id);
+
+            if delete {
+                self.events.push(FontsEvent::FontReleased(id));
+                if self.retained.retain(id, Instant::now()) {
+                    self.evict_expired_fonts();
+                    self.enforce_memory_budget();
+                } else {
+                    self.delete_font(id);
+                }
+            }
+        }
+
+        pub fn font_retention(&self) -> FontRetention {
+            self.retained.retention()
+        }
+
+        pub fn set_font_retention(&mut self, retention: FontRetention) {
+            self.retained.set_retention(retention);
+            self.evict_expired_fonts();
+        }
+
+        pub fn end_frame(&mut self) {
+            self.retained.next_frame();
+            self.evict_expired_fonts();
+        }
+
+        pub fn flush_unused(&mut self) {
+            for id in self.retained.take_all() {
+                self.delete_font(id);
+            }
+        }
+
+        fn evict_expired_fonts(&mut self) {
+            for id in self.retained.take_expired(Instant::now()) {
+                self.delete_font(id);
+            }
+        }
+
+        /// Resolves the query with the font source once, later identical queries take
+        /// another reference to the same font.
+        pub fn find_best_match(&mut self, family_names: &[FamilyName], properties: &Properties) -> Option<usize> {
+            self.find_best_match_detailed(family_names, properties).map(|(id, _)| id)
+        }
+
+        pub fn find_best_match_detailed(&mut self, family_names: &[FamilyName], properties: &Properties) -> Option<(usize, Option<Substitution>)> {
+            if self.memory_only {
+                return self.match_loaded(family_names, properties).map(|id| (id, None));
+            }
+
+            let query = FontQuery::new(family_names, properties);
+            if let Some(&id) = self.font_queries.get(&query) {
+                let substitution = self.query_substitutions.get(&query).cloned();
+                let id = self.get_and_inc_font(id)?;
+                let details = MatchDetails::new(properties, &self.fonts_id_prop[&id].properties);
+                self.fonts_id_prop.get_mut(&id).expect("find_best_match_detailed: cached font").last_match = Some(details);
+                return Some((id, substitution));
+            }
+
+            let font_handle = match self.system_source.select_best_match(family_names, properties) {
+                Ok(handle) => handle,
+                Err(e) => {
+                    self.diagnostics.report(FontsDiagnostic::LoadFailed { family: family_list(family_names), reason: format!("{:?}", e) });
+                    self.track_unresolved_query(query.clone(), family_names, properties);
+                    return self.auto_fallback_match(query, family_names, properties);
+                }
+            };
+
+            let id = self.load_matched_handle(font_handle, family_names, properties)?;
+            self.query_substitutions.remove(&query);
+            self.font_queries.insert(query, id);
+            Some((id, None))
+        }
+
+        /// Loads a face selected for a query, synthesizing the styles it lacks.
+        fn load_matched_handle(&mut self, font_handle: Handle, family_names: &[FamilyName], properties: &Properties) -> Option<usize> {
+            let id = self.load_handle(font_handle, &family_list(family_names))?;
+            Some(self.finish_match(id, properties))
+        }
+
+        /// Synthesizes the styles the face of `font_id` lacks for `properties`, moving the
+        /// reference, and keeps the match for `Fonts::match_details`.
+        fn finish_match(&mut self, font_id: usize, properties: &Properties) -> usize {
+            let details = MatchDetails::new(properties, &self.fonts_id_prop[&font_id].properties);
+            let missing = self.synthesis.missing(&details);
+            let id = if missing.is_any() { self.synthesized_font(font_id, missing) } else { font_id };
+            self.fonts_id_prop.get_mut(&id).expect("finish_match: matched font").last_match = Some(details);
+            id
+        }
+
+        pub fn match_details(&self, font_id: usize) -> Option<MatchDetails> {
+            self.fonts_id_prop.get(&font_id).and_then(|font| font.last_match.clone())
+        }
+
+        /// Chooses among the loaded fonts without synthesized styles for memory only fonts,
+        /// synthesizing the styles the chosen one lacks. Not cached, later loads can match.
+        fn match_loaded(&mut self, family_names: &[FamilyName], properties: &Properties) -> Option<usize> {
+            let mut loaded = self.fonts_id_prop.iter()
+                .filter(|&(_, font)| !font.is_variant())
+                .map(|(&id, font)| (id, FaceInfo {
+                    family_name: font.family_name.clone(),
+                    full_name: font.fk_font.full_name(),
+                    postscript_name: font.postscript_name.clone(),
+                    properties: font.properties.clone(),
+                }))
+                .collect::<Vec<_>>();
+            loaded.sort_by_key(|&(id, _)| id);
+            let faces = loaded.iter().map(|(_, face)| face.clone()).collect::<Vec<_>>();
+
+            let id = match matching::best_face(&faces, family_names, properties) {
+                Some(index) => loaded[index].0,
+                None => {
+                    self.diagnostics.report(FontsDiagnostic::LoadFailed { family: family_list(family_names), reason: "no loaded font matches".to_string() });
+                    return None;
+                }
+            };
+            self.inc_font(id);
+            Some(self.finish_match(id, properties))
+        }
+
+        /// Answers a failed query with the auto fallback family, if enabled.
+        fn auto_fallback_match(&mut self, query: FontQuery, family_names: &[FamilyName], properties: &Properties) -> Option<(usize, Option<Substitution>)> {
+            if !self.auto_fallback {
+                return None;
+            }
+            let fallback = [self.auto_fallback_family.clone()];
+            let font_handle = self.system_source.select_best_match(&fallback, properties).ok()?;
+            let id = self.load_matched_handle(font_handle, &fallback, properties)?;
+
+            let substitution = Substitution {
+                requested: family_list(family_names),
+                substitute: self.fonts_id_prop[&id].fk_font.full_name(),
+            };
+            self.diagnostics.report(FontsDiagnostic::FamilySubstituted { requested: substitution.requested.clone(), substitute: substitution.substitute.clone() });
+            self.query_substitutions.insert(query.clone(), substitution.clone());
+            self.font_queries.insert(query, id);
+            Some((id, Some(substitution)))
+        }
+
+        pub fn default_ui_families(&self) -> Vec<FamilyName> {
+            match self.default_families {
+                Some(ref families) => families.iter().cloned().chain(Some(FamilyName::SansSerif)).collect(),
+                None => names::default_ui_families(),
+            }
+        }
+
+        pub fn set_default_families(&mut self, families: Vec<FamilyName>) {
+            self.default_families = if families.is_empty() { None } else { Some(families) };
+        }
+
+        pub fn set_auto_fallback(&mut self, enabled: bool) {
+            self.auto_fallback = enabled;
+            self.forget_substitutions();
+        }
+
+        pub fn set_auto_fallback_family(&mut self, family: FamilyName) {
+            self.auto_fallback_family = family;
+            self.forget_substitutions();
+        }
+
+        /// Drops cached queries answered with the fallback family, so they are resolved again.
+        fn forget_substitutions(&mut self) {
+            for (query, _) in self.query_substitutions.drain() {
+                self.font_queries.remove(&query);
+            }
+        }
+
+        pub fn find_matches(&mut self, family_names: &[FamilyName], properties: &Properties, limit: usize) -> Vec<MatchCandidate> {
+            if self.source_faces.is_none() {
+                self.source_faces = Some(self.system_source.faces());
+            }
+            let faces = self.source_faces.as_ref().expect("find_matches: faces were listed");
+            let fonts = &self.fonts_id_prop;
+            matching::rank(faces, family_names, properties, limit, |face| {
+                face.postscript_name.is_some() && fonts.values().any(|font| font.postscript_name == face.postscript_name)
+            })
+        }
+
+        pub fn find_by_name(&mut self, name: &str) -> Option<usize> {
+            let (family, properties) = split_style_keywords(name);
+
+            if self.memory_only {
+                let by_postscript_name = self.fonts_id_prop.iter()
+                    .find(|&(_, font)| !font.is_variant() && font.postscript_name.as_ref().map(|n| n.as_str()) == Some(name))
+                    .map(|(&id, _)| id);
+                if let Some(id) = by_postscript_name {
+                    self.inc_font(id);
+                    return Some(id);
+                }
+                return self.match_loaded(&[FamilyName::Title(family)], &properties);
+            }
+
+            let font_handle = self.system_source.select_best_match(&[FamilyName::Title(family)], &properties)
+                .or_else(|_| self.system_source.select_best_match(&[FamilyName::Title(name.to_string())], &Properties::new()))
+                .or_else(|_| self.system_source.select_by_postscript_name(name));
+
+            match font_handle {
+                Ok(handle) => self.load_handle(handle, name),
+                Err(e) => {
+                    self.diagnostics.report(FontsDiagnostic::LoadFailed { family: name.to_string(), reason: format!("{:?}", e) });
+                    None
+                }
+            }
+        }
+
+        /// Loads the font of a handle, or returns the already loaded one with one more reference.
+        ///
+        /// A handle of a face that is already loaded through a different handle, such as a path
+        /// and a memory handle of the same file, resolves to the loaded font.
+        pub fn load_handle(&mut self, font_handle: Handle, family: &str) -> Option<usize> {
+            if let Handle::Path { ref path, font_index } = font_handle {
+                // a loaded font keeps working from memory after its file is gone
+                if ::std::fs::metadata(path).is_err() {
+                    if let Some(id) = self.font_of_lost_file(path, font_index) {
+                        self.inc_font(id);
+                        return Some(id);
+                    }
+                }
+            }
+
+            let fingerprint = generate_fingerprint(&font_handle);
+
+            let mut id = self.fonts_fingerprint_id.get(&fingerprint).map(|v| *v);
+
+            let content = match id {
+                None => generate_content_fingerprint(&font_handle),
+                Some(_) => None,
+            };
+            if let Some(content_id) = content.and_then(|c| self.fonts_content_id.get(&c).cloned()) {
+                self.fonts_fingerprint_id.insert(fingerprint, content_id);
+                self.inc_font(content_id);
+                return Some(content_id);
+            }
+
+            match id {
+                None => {
+                    match font_handle.load() {
+                        Err(e) => {
+                            self.diagnostics.report(FontsDiagnostic::LoadFailed { family: family.to_string(), reason: format!("{:?}", e) });
+                            return None;
+                        }
+                        Ok(fk_font) => {
+                            let face_index = match font_handle {
+                                Handle::Path { font_index, .. } | Handle::Memory { font_index, .. } => font_index,
+                            };
+                            let file = match font_handle {
+                                Handle::Path { ref path, font_index } => Some(FontFile { path: path.clone(), font_index, lost: false }),
+                                Handle::Memory { .. } => None,
+                            };
+                            let file_data = file.as_ref().and_then(|_| fk_font.copy_font_data());
+
+                            let hb_font = match shaping_font_for_handle(&font_handle, file_data.clone(), &fk_font) {
+                                Ok(hb_font) => hb_font,
+                                Err(diagnostic) => {
+                                    self.diagnostics.report(diagnostic);
+                                    return None;
+                                }
+                            };
+
+                            let new_id = self.insert_font(fingerprint, fk_font, hb_font, face_index);
+                            if let Some(content) = content {
+                                self.fonts_content_id.insert(content, new_id);
+                            }
+                            if let Some(file) = file {
+                                self.font_files.insert(new_id, file);
+                            }
+                            if let Some(bytes) = file_data {
+                                self.fonts_id_prop.get_mut(&new_id).expect("load_handle: inserted font").font_data = Some(bytes);
+                            }
+                            id = Some(new_id);
+                        }
+                    };
+                }
+                Some(id) => {
+                    self.inc_font(id);
+                }
+            }
+
+            return id;
+        }
+
+        pub fn delete_font(&mut self, id: usize) {
+            self.diagnostics.report(FontsDiagnostic::FontUnloaded { name: self.fonts_id_prop[&id].fk_font.full_name() });
+
+            self.fonts_id_prop.remove(&id);
+            self.audit.removed(Kind::Font, id);
+            self.shaping_cache.invalidate_font(id);
+            self.events.push(FontsEvent::FontEvicted(id));
+            self.fonts.remove(id);
+            self.fonts_fingerprint_id.retain(|_, font_id| *font_id != id);
+            self.fonts_content_id.retain(|_, font_id| *font_id != id);
+            self.font_files.remove(&id);
+            self.font_queries.retain(|_, font_id| *font_id != id);
+        }
+
+        pub fn get(&self, id: usize) -> Option<&FontData> {
+            self.fonts_id_prop.get(&id)
+        }
+
+        pub fn get_mut(&mut self, id: usize) -> Option<&mut FontData> {
+            self.fonts_id_prop.get_mut(&id)
+        }
+    }
+
+    impl Drop for FontsContainer {
+        fn drop(&mut self) {
+            // copies never taken over by handles are not leaks, nor are retained fonts
+            self.release_snapshot_references();
+            self.flush_unused();
+            if let Some(report) = self.audit.report() {
+                error!("fonts container dropped with leaked references, {}", report);
+            }
+        }
+    }
+
+    /// Bytes of font data hashed by `generate_content_fingerprint`.
+    const CONTENT_FINGERPRINT_BYTES: u64 = 64 * 1024;
+
+    /// Fingerprint of the face index and the start of the font data, the same for
+    /// different handles of the same face. `None` if the font file can not be read.
+    fn generate_content_fingerprint(handle: &Handle) -> Option<Fingerprint> {
+        use std::io::Read;
+
+        let mut hasher = FingerprintHasher::new();
+        let font_index = match *handle {
+            Handle::Path { ref path, font_index } => {
+                let mut bytes = Vec::new();
+                ::std::fs::File::open(path).ok()?
+                    .take(CONTENT_FINGERPRINT_BYTES)
+                    .read_to_end(&mut bytes).ok()?;
+                hasher.input(&bytes);
+                font_index
+            }
+            Handle::Memory { ref bytes, font_index } => {
+                let len = bytes.len().min(CONTENT_FINGERPRINT_BYTES as usize);
+                hasher.input(&bytes[..len]);
+                font_index
+            }
+        };
+        hasher.input_u32(font_index);
+
+        Some(hasher.result())
+    }
+
+    /// Failed queries kept for `refresh_system_fonts`, the oldest is dropped first.
+    const MAX_UNRESOLVED_QUERIES: usize = 32;
+
+    /// File a font was loaded from, see `FontsDiagnostic::SourceLost`.
+    #[derive(Debug, Clone)]
+    struct FontFile {
+        path: ::std::path::PathBuf,
+        font_index: u32,
+        /// The file could not be read since, which was reported.
+        lost: bool,
+    }
+
+    /// `find_best_match` arguments, family names compared case-insensitively.
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    struct FontQuery {
+        families: Vec<String>,
+        weight: u32,
+        style: u8,
+        stretch: u32,
+    }
+
+    impl FontQuery {
+        fn new(family_names: &[FamilyName], properties: &Properties) -> FontQuery {
+            FontQuery {
+                families: family_names.iter()
+                    .map(|name| match *name {
+                        FamilyName::Title(ref title) => format!("\"{}\"", title.trim().to_lowercase()),
+                        ref generic => format!("{:?}", generic),
+                    })
+                    .collect(),
+                weight: properties.weight.0.to_bits(),
+                style: match properties.style {
+                    Style::Normal => 0,
+                    Style::Italic => 1,
+                    Style::Oblique => 2,
+                },
+                stretch: properties.stretch.0.to_bits(),
+            }
+        }
+    }
+
+    /// Requested family names for diagnostics, such as `Fira Sans, SansSerif`.
+    fn family_list(family_names: &[FamilyName]) -> String {
+        family_names.iter()
+            .map(|name| match *name {
+                FamilyName::Title(ref title) => title.clone(),
+                ref generic => format!("{:?}", generic),
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Fingerprint of a resource font, changes when the resource bytes change.
+    fn generate_resource_fingerprint(name: &str, font_index: u32, bytes: &[u8]) -> Fingerprint {
+        let mut hasher = FingerprintHasher::new();
+        hasher.input(name.as_bytes());
+        hasher.input_u32(font_index);
+        hasher.input(bytes);
+        hasher.result()
+    }
+
+    /// Fingerprint of a synthesized variant of the font with `fingerprint`.
+    fn generate_synthesis_fingerprint(fingerprint: &Fingerprint, synthesis: SynthesisOptions) -> Fingerprint {
+        let mut hasher = FingerprintHasher::new();
+        hasher.input(&fingerprint[..]);
+        hasher.input(&[synthesis.embolden as u8, synthesis.oblique as u8]);
+        hasher.result()
+    }
+
+    /// Fingerprint of a sized variant of the font with `fingerprint`; the step is left out
+    /// for quarter pixels, as before buckets had a step.
+    fn generate_size_fingerprint(fingerprint: &Fingerprint, size: SizeBucket) -> Fingerprint {
+        let mut hasher = FingerprintHasher::new();
+        hasher.input(&fingerprint[..]);
+        hasher.input(b"size");
+        hasher.input_u32(size.index());
+        if size.step() != DEFAULT_SIZE_STEP {
+            hasher.input_u32(size.step().to_bits());
+        }
+        hasher.result()
+    }
+
+    fn generate_fingerprint(handle: &Handle) -> Fingerprint {
+        let mut hasher = FingerprintHasher::new();
+        match *handle {
+            Handle::Path { ref path, font_index } => {
+                hasher.input(path.to_string_lossy().as_bytes());
+
+                // a file overwritten in place gets a new fingerprint and loads again
+                if let Ok(metadata) = ::std::fs::metadata(path) {
+                    let mut bytes = [0u8; 20];
+                    {
+                        let mut cursor = ::std::io::Cursor::new(&mut bytes[..]);
+                        cursor.write_u64::<LittleEndian>(metadata.len()).unwrap();
+                        if let Some(modified) = metadata.modified().ok().and_then(|m| m.duration_since(::std::time::UNIX_EPOCH).ok()) {
+                            cursor.write_u64::<LittleEndian>(modified.as_secs()).unwrap();
+                            cursor.write_u32::<LittleEndian>(modified.subsec_nanos()).unwrap();
+                        }
+                    }
+                    hasher.input(&bytes);
+                }
+                hasher.input_u32(font_index);
+            }
+            Handle::Memory { ref bytes, font_index } => {
+                hasher.input(&**bytes);
+                hasher.input_u32(font_index);
+            }
+        }
+        hasher.result()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::{BTreeMap, HashSet};
+    use std::path::{Path, PathBuf};
+    use font_kit::error::SelectionError;
+    use font_kit::handle::Handle;
+
+    /// Families, PostScript names and files of the fonts bundled in `fixtures/fonts`.
+    const FIXTURE_FONTS: [(&str, &str, &str); 3] = [
+        ("DejaVu Sans", "DejaVuSans", "DejaVuSans.ttf"),
+        ("DejaVu Sans Mono", "DejaVuSansMono", "DejaVuSansMono.ttf"),
+        ("DejaVu Serif", "DejaVuSerif", "DejaVuSerif.ttf"),
+    ];
+
+    fn fixture_path(file: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/fonts").join(file)
+    }
+
+    /// Source of the fixture fonts in place of the system fonts, so the tests check the same
+    /// fonts everywhere. Generic families are DejaVu Sans, Sans Mono and Serif, the properties
+    /// are ignored.
+    struct FixtureSource;
+
+    impl FixtureSource {
+        fn handle(file: &str) -> Handle {
+            Handle::Path { path: fixture_path(file), font_index: 0 }
+        }
+    }
+
+    impl FontSource for FixtureSource {
+        fn select_best_match(&self, family_names: &[FamilyName], _properties: &Properties) -> Result<Handle, SelectionError> {
+            family_names.iter()
+                .filter_map(|family| match *family {
+                    FamilyName::SansSerif => Some("DejaVuSans.ttf"),
+                    FamilyName::Monospace => Some("DejaVuSansMono.ttf"),
+                    FamilyName::Serif => Some("DejaVuSerif.ttf"),
+                    FamilyName::Title(ref title) => FIXTURE_FONTS.iter()
+                        .find(|&&(family, _, _)| family.eq_ignore_ascii_case(title))
+                        .map(|&(_, _, file)| file),
+                    _ => None,
+                })
+                .next()
+                .map(FixtureSource::handle)
+                .ok_or(SelectionError::NotFound)
+        }
+
+        fn select_by_postscript_name(&self, postscript_name: &str) -> Result<Handle, SelectionError> {
+            FIXTURE_FONTS.iter()
+                .find(|&&(_, name, _)| name == postscript_name)
+                .map(|&(_, _, file)| FixtureSource::handle(file))
+                .ok_or(SelectionError::NotFound)
+        }
+    }
+
+    fn test_fonts() -> Fonts {
+        Fonts::with_source(FixtureSource)
+    }
+
+    /// DejaVu Sans, the font of most tests.
+    fn test_font(fonts: &Fonts) -> Font {
+        fonts.find_best_match(&[FamilyName::SansSerif], &Properties::new()).expect("fixture font DejaVuSans.ttf")
+    }
+
+    fn mono_font(fonts: &Fonts) -> Font {
+        fonts.find_best_match(&[FamilyName::Monospace], &Properties::new()).expect("fixture font DejaVuSansMono.ttf")
+    }
+
+    /// Fixture font file with tables added or replaced, for font features DejaVu lacks.
+    fn patched_fixture(file: &str, patched: Vec<([u8; 4], Vec<u8>)>) -> Vec<u8> {
+        let data = ::std::fs::read(fixture_path(file)).unwrap();
+        let mut tables = sfnt::tables(&data, 0).unwrap().into_iter()
+            .map(|(tag, table)| (tag, Cow::Borrowed(table)))
+            .collect::<BTreeMap<_, _>>();
+        for (tag, table) in patched {
+            tables.insert(tag, Cow::Owned(table));
+        }
+        subset::write_sfnt(&tables)
+    }
+
+    #[test]
+    fn buffer_clones_should_be_equal_and_distinct_buffers_not() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+
+        let a = font.create_buffer("hello", None, [0, 0, 0, 255].into());
+        let b = font.create_buffer("hello", None, [0, 0, 0, 255].into());
+        let a_clone = a.clone();
+
+        assert_eq!(a, a_clone);
+        assert_ne!(a, b);
+
+        let mut set = HashSet::new();
+        set.insert(a.clone());
+        assert!(set.contains(&a_clone));
+        assert!(!set.contains(&b));
+    }
+
+    #[test]
+    fn handles_from_different_containers_should_never_be_equal() {
+        let fonts_a = test_fonts();
+        let fonts_b = test_fonts();
+        let (font_a, font_b) = (test_font(&fonts_a), test_font(&fonts_b));
+
+        assert_ne!(font_a, font_b);
+        assert_ne!(
+            font_a.create_buffer("hello", None, [0, 0, 0, 255].into()),
+            font_b.create_buffer("hello", None, [0, 0, 0, 255].into())
+        );
+    }
+
+    #[test]
+    fn local_translation_should_be_applied_before_parent_rotation() {
+        let parent = na::convert::<_, na::Projective3<f32>>(
+            na::Rotation3::from_axis_angle(&na::Vector3::z_axis(), ::std::f32::consts::FRAC_PI_2)
+        );
+        let local = na::convert::<_, na::Projective3<f32>>(na::Translation3::new(10.0, 0.0, 0.0));
+
+        let point = compose_transform(&parent, &local) * na::Point3::new(1.0, 0.0, 0.0);
+
+        assert!((point.x - 0.0).abs() < 0.0001, "x was {}", point.x);
+        assert!((point.y - 11.0).abs() < 0.0001, "y was {}", point.y);
+    }
+
+    #[test]
+    fn set_font_should_keep_buffer_id_and_refs_valid() {
+        let fonts = test_fonts();
+        let (sans, mono) = (test_font(&fonts), mono_font(&fonts));
+
+        let buffer = sans.create_buffer("hello", None, [0, 0, 0, 255].into());
+        let id = buffer.id();
+
+        buffer.set_font(&mono);
+        assert_eq!(buffer.id(), id);
+        assert_eq!(buffer.font(), mono);
+        assert_eq!(fonts.buffer_from_id(buffer.weak_ref().id()), Some(buffer.clone()));
+
+        buffer.set_font(&mono);
+        assert_eq!(buffer.font(), mono);
+    }
+
+    #[test]
+    fn glyphs_in_range_should_report_pen_before_range() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+
+        let buffer = font.create_buffer("abc def", None, [0, 0, 0, 255].into());
+        let mut all = Vec::new();
+        buffer.glyphs(&mut all);
+
+        let mut slice = Vec::new();
+        let info = buffer.glyphs_in_range(4..7, &mut slice);
+
+        let expected_pen: i32 = all.iter().filter(|g| (g.byte_offset as usize) < 4).map(|g| g.x_advance).sum();
+        assert_eq!(info.pen_x, expected_pen);
+        assert_eq!(info.byte_range, 4..7);
+        assert!(!info.clipped_start && !info.clipped_end);
+        assert!(slice.iter().all(|g| g.byte_offset >= 4));
+    }
+
+    #[test]
+    fn transform_changes_should_not_bump_version() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+
+        let buffer = font.create_buffer("hello", None, [0, 0, 0, 255].into());
+        let (version, transform_version) = (buffer.version(), buffer.transform_version());
+
+        buffer.translate(1.0, 2.0);
+        assert_eq!(buffer.version(), version);
+        assert!(buffer.transform_version() > transform_version);
+
+        buffer.set_text("world");
+        assert!(buffer.version() > version);
+        assert_eq!(fonts.buffer_version(buffer.weak_ref()), Some(buffer.version()));
+    }
+
+    #[test]
+    fn oblique_and_rotation_should_keep_the_baseline_origin() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+
+        let buffer = font.create_buffer("hello", None, [0, 0, 0, 255].into());
+        let (version, transform_version) = (buffer.version(), buffer.transform_version());
+
+        buffer.translate(10.0, 20.0);
+        buffer.set_oblique(45.0);
+        let t = buffer.transform().expect("oblique transform");
+        assert_eq!(t.transform_point(&na::Point3::origin()), na::Point3::new(10.0, 20.0, 0.0));
+        let top = t.transform_point(&na::Point3::new(0.0, 5.0, 0.0));
+        assert!((top.x - 15.0).abs() < 1e-4 && (top.y - 25.0).abs() < 1e-4);
+
+        buffer.reset_transform();
+        assert!(buffer.transform().is_none());
+        buffer.rotate_about_baseline(::std::f32::consts::FRAC_PI_2);
+        let end = buffer.transform().expect("rotation").transform_point(&na::Point3::new(1.0, 0.0, 0.0));
+        assert!(end.x.abs() < 1e-4 && (end.y - 1.0).abs() < 1e-4);
+
+        assert_eq!(buffer.version(), version);
+        assert!(buffer.transform_version() > transform_version);
+    }
+
+    #[test]
+    fn setting_identical_text_should_not_reshape() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+
+        let buffer = font.create_buffer("hello", None, [0, 0, 0, 255].into());
+        let (version, shape_calls) = (buffer.version(), fonts.shaping_cache_stats().shape_calls);
+
+        for _ in 0..3 {
+            buffer.set_text("hello");
+        }
+
+        assert_eq!(buffer.version(), version);
+        assert_eq!(fonts.shaping_cache_stats().shape_calls, shape_calls);
+    }
+
+    #[test]
+    fn lazy_buffers_should_shape_once_on_first_glyph_access() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        fonts.set_shaping_cache_capacity(0);
+        fonts.set_lazy_shaping(true);
+
+        let shape_calls = fonts.shaping_cache_stats().shape_calls;
+        let buffer = font.create_buffer("hello", None, [0, 0, 0, 255].into());
+        buffer.set_text("world");
+        buffer.append_text("!");
+        assert_eq!(fonts.shaping_cache_stats().shape_calls, shape_calls);
+
+        assert!(!buffer.is_empty());
+        assert_eq!(fonts.shaping_cache_stats().shape_calls, shape_calls + 1);
+        assert_eq!(buffer.glyph_count(), fonts.glyph_count(buffer.weak_ref()).unwrap());
+        assert_eq!(fonts.shaping_cache_stats().shape_calls, shape_calls + 1);
+    }
+
+    #[test]
+    fn creating_many_buffers_should_reuse_unicode_buffers() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        fonts.set_shaping_cache_capacity(0);
+
+        let allocations = fonts.shaping_cache_stats().unicode_buffer_allocations;
+        let buffers = (0..100)
+            .map(|i| font.create_buffer(format!("label {}", i), None, [0, 0, 0, 255].into()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(buffers.len(), 100);
+        assert!(fonts.shaping_cache_stats().unicode_buffer_allocations - allocations <= 1);
+    }
+
+    #[test]
+    fn bulk_created_buffers_should_keep_order_and_own_refs() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+
+        let mut buffers = font.create_buffers(&["OK", "Cancel", "Apply"], None, [0, 0, 0, 255].into());
+        assert_eq!(buffers.iter().map(|b| b.text()).collect::<Vec<_>>(), vec!["OK", "Cancel", "Apply"]);
+
+        let last = buffers.pop().unwrap();
+        drop(buffers);
+        drop(font);
+        assert_eq!(last.text(), "Apply");
+        assert!(!last.is_empty());
+    }
+
+    #[test]
+    fn buffer_count_should_return_to_baseline_after_drops() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let baseline = fonts.stats();
+
+        let buffers = font.create_buffers(&["one", "two", "three"], None, [0, 0, 0, 255].into());
+        let stats = fonts.stats();
+        assert_eq!(stats.buffers, baseline.buffers + 3);
+        assert!(stats.glyphs >= baseline.glyphs + 11);
+        assert!(stats.text_bytes >= baseline.text_bytes + 11);
+
+        drop(buffers);
+        let stats = fonts.stats();
+        assert_eq!(stats.buffers, baseline.buffers);
+        assert_eq!(stats.glyphs, baseline.glyphs);
+        assert_eq!(stats.fonts, baseline.fonts);
+    }
+
+    #[test]
+    fn loaded_shaped_dump_should_match_the_original() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+
+        let buffer = font.create_buffer("Cancel", None, [0, 0, 0, 255].into());
+        let dump = buffer.shaped_dump();
+        let shape_calls = fonts.shaping_cache_stats().shape_calls;
+
+        let loaded = fonts.load_shaped_dump(&dump, None, [0, 0, 0, 255].into()).expect("dump font is loaded");
+        assert_eq!(fonts.shaping_cache_stats().shape_calls, shape_calls);
+        assert_eq!(loaded.shaped_dump(), dump);
+        assert_eq!(loaded.cursor_positions().collect::<Vec<_>>(), buffer.cursor_positions().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn invalid_shaped_dumps_should_be_rejected() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let dump = font.create_buffer("Cancel", None, [0, 0, 0, 255].into()).shaped_dump();
+        let load = |dump: &ShapedDump| fonts.load_shaped_dump(dump, None, [0, 0, 0, 255].into()).err();
+        let buffers = fonts.stats().buffers;
+
+        let unknown = ShapedDump { font: "No Such Font".to_string(), ..dump.clone() };
+        assert_eq!(load(&unknown), Some(ShapedDumpError::FontNotLoaded("No Such Font".to_string())));
+
+        let mut bad_glyph = dump.clone();
+        bad_glyph.positions[1].id = font.glyph_count();
+        assert_eq!(load(&bad_glyph), Some(ShapedDumpError::GlyphOutOfRange { glyph: 1, id: font.glyph_count() }));
+
+        let mut bad_cluster = dump.clone();
+        bad_cluster.positions[2].cluster = 100;
+        assert_eq!(load(&bad_cluster), Some(ShapedDumpError::InvalidCluster { glyph: 2, cluster: 100 }));
+
+        let mut bad_span = dump.clone();
+        bad_span.positions[0].span = 1;
+        assert_eq!(load(&bad_span), Some(ShapedDumpError::UnexpectedSpan { glyph: 0, span: 1 }));
+
+        let reversed = ShapedDump { direction: ShapedDirection::RightToLeft, ..dump.clone() };
+        assert_eq!(load(&reversed), Some(ShapedDumpError::DirectionMismatch));
+
+        assert_eq!(fonts.stats().buffers, buffers);
+        assert!(load(&dump).is_none());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn shaped_dump_should_round_trip_through_json() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+
+        let dump = font.create_buffer("Hello, world", None, [0, 0, 0, 255].into()).shaped_dump();
+        let json = ::serde_json::to_string(&dump).unwrap();
+        let parsed: ShapedDump = ::serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, dump);
+
+        let loaded = fonts.load_shaped_dump(&parsed, None, [0, 0, 0, 255].into()).expect("dump font is loaded");
+        assert_eq!(::serde_json::to_string(&loaded.shaped_dump()).unwrap(), json);
+    }
+
+    #[test]
+    fn imported_shaped_cache_should_skip_shaping() {
+        let exporter = test_fonts();
+        let font = test_font(&exporter);
+        let original = font.create_buffer("Localized label", None, [0, 0, 0, 255].into());
+        let mut bytes = Vec::new();
+        assert_eq!(exporter.export_shaped_cache(&mut bytes).unwrap(), 1);
+
+        let importer = test_fonts();
+        let font = test_font(&importer);
+        assert_eq!(importer.import_shaped_cache(&mut &bytes[..]).unwrap(), 1);
+        assert!(importer.import_shaped_cache(&mut &bytes[..bytes.len() - 2]).is_err());
+
+        let shape_calls = importer.shaping_cache_stats().shape_calls;
+        let buffer = font.create_buffer("Localized label", None, [0, 0, 0, 255].into());
+        assert_eq!(importer.shaping_cache_stats().shape_calls, shape_calls);
+        assert_eq!(buffer.shaped_dump().positions, original.shaped_dump().positions);
+    }
+
+    #[test]
+    fn packed_glyphs_should_match_scaled_glyphs() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let buffer = font.create_buffer("Packed glyphs", None, [0, 0, 0, 255].into());
+
+        let mut scaled = Vec::new();
+        buffer.glyphs_scaled(20.0, None, &mut scaled);
+        let mut packed = Vec::new();
+        buffer.glyphs_packed(20.0, &mut packed);
+
+        assert_eq!(::std::mem::size_of::<PackedGlyph>(), 24);
+        assert_eq!(packed.len(), scaled.len());
+        for (packed, scaled) in packed.iter().zip(scaled.iter()) {
+            assert_eq!(packed.id, scaled.id);
+            assert!((packed.pen_x + packed.offset_x - scaled.x).abs() < 0.001);
+            assert!((packed.pen_y + packed.offset_y - scaled.y).abs() < 0.001);
+        }
+    }
+
+    #[test]
+    fn missing_or_invalid_resource_fonts_should_fail_to_load() {
+        match test_fonts().font_from_resource("fonts/Missing.ttf", 0) {
+            Err(FontResourceError::NoLoader) => (),
+            other => panic!("expected NoLoader, got {:?}", other.map(|_| ())),
+        }
+
+        let fonts = Fonts::with_resource_loader(|name| match name {
+            "fonts/Broken.ttf" => Some(vec![0; 16]),
+            _ => None,
+        });
+        match fonts.font_from_resource("fonts/Missing.ttf", 0) {
+            Err(FontResourceError::NotFound) => (),
+            other => panic!("expected NotFound, got {:?}", other.map(|_| ())),
+        }
+        match fonts.font_from_resource("fonts/Broken.ttf", 0) {
+            Err(FontResourceError::Load(_)) => (),
+            other => panic!("expected Load, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn buffer_lifecycle_should_emit_events() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let mut events = Vec::new();
+        fonts.drain_events(&mut events);
+        events.clear();
+
+        let buffer = font.create_buffer("one", None, [0, 0, 0, 255].into());
+        let buffer_ref = buffer.weak_ref();
+        fonts.drain_events(&mut events);
+        assert_eq!(events, vec![FontsEvent::BufferCreated(buffer_ref)]);
+        events.clear();
+
+        buffer.set_text("two");
+        buffer.set_text("three");
+        buffer.set_text("three");
+        fonts.drain_events(&mut events);
+        assert_eq!(events, vec![FontsEvent::BufferChanged(buffer_ref)]);
+        events.clear();
+
+        drop(buffer);
+        fonts.drain_events(&mut events);
+        assert_eq!(events, vec![FontsEvent::BufferDeleted(buffer_ref)]);
+    }
+
+    #[test]
+    fn font_properties_should_describe_the_loaded_face() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+
+        assert!(!font.family_name().is_empty());
+        let properties = font.properties();
+        assert!(properties.weight.0 > 0.0);
+        assert!(properties.stretch.0 > 0.0);
+    }
+
+    #[test]
+    fn coverage_should_report_missing_chars() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+
+        let report = font.supports_chars("ab\u{10FFFD}".chars());
+        assert_eq!(report.supported, 2);
+        assert_eq!(report.missing, vec!['\u{10FFFD}']);
+        assert!(!report.is_complete());
+    }
+
+    #[test]
+    fn unmapped_chars_should_have_no_glyph() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+
+        let mut glyphs = Vec::new();
+        font.glyphs_for_str("aa\u{10FFFD}", &mut glyphs);
+
+        assert!(glyphs[0].is_some());
+        assert_eq!(glyphs[0], glyphs[1]);
+        assert_eq!(glyphs[2], None);
+        assert_eq!(font.glyph_for_char('a'), glyphs[0]);
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn mmap_loading_should_load_path_fonts_like_the_copy_path() {
+        let copied = test_fonts();
+        let mapped = test_fonts();
+        mapped.set_mmap_loading(true);
+        assert!(mapped.mmap_loading());
+        assert!(!copied.mmap_loading());
+
+        let copied_font = test_font(&copied);
+        let mapped_font = test_font(&mapped);
+
+        let bytes = ::std::fs::read(fixture_path("DejaVuSans.ttf")).unwrap();
+        assert_eq!(mapped_font.copy_font_data().as_ref().map(|data| &data[..]), Some(&bytes[..]));
+        assert_eq!(mapped_font.copy_font_data(), copied_font.copy_font_data());
+
+        let text = "AVAST to Wave";
+        assert_eq!(mapped.shape_debug(&mapped_font, text), copied.shape_debug(&copied_font, text));
+    }
+
+    #[test]
+    fn path_and_memory_handles_of_the_same_face_should_share_a_font() {
+        let (path, font_index) = (fixture_path("DejaVuSans.ttf"), 0);
+        let bytes = ::std::fs::read(&path).unwrap();
+
+        let mut container = shared::FontsContainer::new();
+        let from_path = container.load_handle(Handle::Path { path, font_index }, "sans").unwrap();
+        let from_memory = container.load_handle(Handle::Memory { bytes: ::std::sync::Arc::new(bytes), font_index }, "sans").unwrap();
+
+        assert_eq!(from_path, from_memory);
+        assert_eq!(container.get(from_path).unwrap().count, 2);
+
+        container.dec_font(from_memory);
+        assert!(container.get(from_path).is_some());
+        container.dec_font(from_path);
+        assert!(container.get(from_path).is_none());
+    }
+
+    #[test]
+    fn font_file_overwritten_in_place_should_load_again() {
+        let (first, second) = (fixture_path("DejaVuSans.ttf"), fixture_path("DejaVuSansMono.ttf"));
+
+        let path = ::std::env::temp_dir().join(format!("fonts-in-place-{}.ttf", ::std::process::id()));
+        ::std::fs::copy(&first, &path).unwrap();
+        let mut container = shared::FontsContainer::new();
+        let old = container.load_handle(Handle::Path { path: path.clone(), font_index: 0 }, "sans").unwrap();
+
+        ::std::fs::copy(&second, &path).unwrap();
+        let new = container.load_handle(Handle::Path { path: path.clone(), font_index: 0 }, "mono").unwrap();
+        ::std::fs::remove_file(&path).unwrap();
+
+        assert_ne!(old, new);
+        assert_ne!(container.get(old).unwrap().fk_font.full_name(), container.get(new).unwrap().fk_font.full_name());
+        container.dec_font(old);
+        assert!(container.get(old).is_none());
+    }
+
+    #[test]
+    fn glyph_advances_should_be_in_font_units() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+
+        let buffer = font.create_buffer("M", None, [0, 0, 0, 255].into());
+        let mut glyphs = Vec::new();
+        buffer.glyphs(&mut glyphs);
+
+        let shared = fonts.container.borrow();
+        let advance = shared.get(font.id).unwrap().fk_font.advance(glyphs[0].id).unwrap().x;
+        assert!((glyphs[0].x_advance as f32 - advance).abs() <= 1.0, "{} != {}", glyphs[0].x_advance, advance);
+        assert_eq!(font.scale_for_px(font.units_per_em() as f32), 1.0);
+    }
+
+    #[test]
+    fn glyph_flags_should_follow_glyph_output() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+
+        let buffer = font.create_buffer("office", None, [0, 0, 0, 255].into());
+        buffer.append_text(" hours");
+        let mut flags = Vec::new();
+        buffer.glyph_flags(&mut flags);
+
+        assert_eq!(flags.len(), buffer.glyph_count());
+        assert!(buffer.safe_break_before(0));
+        assert!(buffer.safe_break_before(flags.len()));
+        for (i, flag) in flags.iter().enumerate().skip(1) {
+            assert_eq!(buffer.safe_break_before(i), !flag.unsafe_to_break());
+        }
+    }
+
+    #[test]
+    fn splice_should_match_full_reshape_over_random_edits() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+
+        let pieces = ["fi", "ff", "AV", "To", " ", "office", "Wa", "é", "x"];
+        let mut seed = 12345u32;
+        let mut next = |n: usize| {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (seed >> 16) as usize % n
+        };
+
+        let buffer = font.create_buffer("The office offers affine Vat fjord AVAVA", None, [0, 0, 0, 255].into());
+        for _ in 0..200 {
+            let text = buffer.text();
+            let boundaries = text.char_indices().map(|(i, _)| i).chain(Some(text.len())).collect::<Vec<_>>();
+            let a = boundaries[next(boundaries.len())];
+            let b = boundaries[next(boundaries.len())];
+            let range = a.min(b)..a.max(b).min(a.min(b) + 6);
+            let range = range.start..*boundaries.iter().find(|&&i| i >= range.end).unwrap();
+            let replacement = (0..next(3)).map(|_| pieces[next(pieces.len())]).collect::<String>();
+
+            buffer.splice(range, &replacement);
+
+            let full = font.create_buffer(buffer.text(), None, [0, 0, 0, 255].into());
+            let (mut spliced, mut expected) = (Vec::new(), Vec::new());
+            buffer.glyphs(&mut spliced);
+            full.glyphs(&mut expected);
+            assert_eq!(spliced, expected, "after splicing into {:?}", buffer.text());
+        }
+    }
+
+    #[test]
+    fn edits_next_to_rtl_paragraphs_should_match_full_reshape() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+
+        // "office" is at bytes 22..28, after a right-to-left paragraph
+        let buffer = font.create_buffer("שלום עולם\nThe office offers", None, [0, 0, 0, 255].into());
+        let edits: [(::std::ops::Range<usize>, &str); 4] = [(22..28, "affine"), (18..18, "fi "), (0..0, "AV "), (21..21, " אבג")];
+        for (range, replacement) in edits.iter().cloned() {
+            buffer.splice(range, replacement);
+            buffer.append_text(" Wa");
+
+            let full = font.create_buffer(buffer.text(), None, [0, 0, 0, 255].into());
+            let (mut edited, mut expected) = (Vec::new(), Vec::new());
+            buffer.glyphs(&mut edited);
+            full.glyphs(&mut expected);
+            assert_eq!(edited, expected, "after editing into {:?}", buffer.text());
+        }
+
+        // with the word cache only the left-to-right paragraph is shaped by words
+        let text = "שלום עולם\nThe office offers";
+        let whole = font.create_buffer(text, None, [0, 0, 0, 255].into());
+        fonts.set_word_cache(true);
+        let by_words = font.create_buffer(text, None, [0, 0, 0, 255].into());
+        let (mut expected, mut actual) = (Vec::new(), Vec::new());
+        whole.glyphs(&mut expected);
+        by_words.glyphs(&mut actual);
+        let rtl_paragraph = |glyphs: &[GlyphPosition]| glyphs.iter().filter(|g| g.cluster < 18).cloned().collect::<Vec<_>>();
+        assert_eq!(rtl_paragraph(&actual), rtl_paragraph(&expected));
+        assert!(fonts.shaping_cache_stats().word_misses > 0);
+    }
+
+    #[test]
+    fn append_should_match_full_reshape_over_many_appends() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+
+        let pieces = ["fi", "ff", "AV", "To", " ", "office", "Wa", "é", "x", "\n"];
+        let buffer = font.create_buffer("Of", None, [0, 0, 0, 255].into());
+        for i in 0..200 {
+            buffer.append_text(pieces[(i * 7 + i / 3) % pieces.len()]);
+
+            let full = font.create_buffer(buffer.text(), None, [0, 0, 0, 255].into());
+            let (mut appended, mut expected) = (Vec::new(), Vec::new());
+            buffer.glyphs(&mut appended);
+            full.glyphs(&mut expected);
+            assert_eq!(appended, expected, "after appending to {:?}", buffer.text());
+        }
+    }
+
+    #[test]
+    fn split_lines_should_create_a_buffer_per_paragraph() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+
+        let buffer = font.create_buffer("first\r\nsecond\u{2028}more\n\nlast", None, [1, 2, 3, 255].into());
+        let lines = buffer.split_lines();
+
+        assert_eq!(lines.iter().map(|line| line.text()).collect::<Vec<_>>(), vec!["first", "second\u{2028}more", "", "last"]);
+        assert_eq!(lines.iter().map(|line| line.line_index()).collect::<Vec<_>>(), vec![Some(0), Some(1), Some(2), Some(3)]);
+        assert_eq!(buffer.line_index(), None);
+
+        let standalone = font.create_buffer("last", None, [1, 2, 3, 255].into());
+        let (mut expected, mut actual) = (Vec::new(), Vec::new());
+        standalone.glyphs(&mut expected);
+        lines[3].glyphs(&mut actual);
+        assert_eq!(actual, expected);
+        assert_eq!(lines[3].font(), font);
+        assert_eq!(lines[3].color(), buffer.color());
+
+        buffer.set_text("changed");
+        assert_eq!(lines[0].text(), "first");
+    }
+
+    #[test]
+    fn lines_should_inherit_the_shaping_settings() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+
+        let buffer = font.create_buffer("abc\nde", None, [0, 0, 0, 255].into());
+        buffer.set_case(Case::Upper, None);
+        buffer.set_base_direction(BaseDirection::Rtl);
+        buffer.set_features(&[Feature::new(b"liga", 0), Feature::new(b"smcp", 1).with_range(4..5)]);
+
+        let lines = buffer.split_lines_linked();
+        assert_eq!(lines[1].case(), Case::Upper);
+        assert_eq!(lines[1].base_direction(), BaseDirection::Rtl);
+        assert_eq!(lines[0].features(), vec![Feature::new(b"liga", 0)]);
+        assert_eq!(lines[1].features(), vec![Feature::new(b"liga", 0), Feature::new(b"smcp", 1).with_range(0..1)]);
+
+        // settings changed on the parent reach the linked lines
+        buffer.set_base_direction(BaseDirection::Ltr);
+        buffer.set_sanitize(true);
+        assert_eq!(lines[0].base_direction(), BaseDirection::Ltr);
+        assert!(lines[1].sanitize());
+    }
+
+    #[test]
+    fn linked_lines_should_follow_parent_text() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+
+        let buffer = font.create_buffer("a\nb", None, [0, 0, 0, 255].into());
+        let lines = buffer.split_lines_linked();
+        assert_eq!(buffer.split_lines_linked(), lines);
+
+        let mut events = Vec::new();
+        fonts.drain_events(&mut events);
+        events.clear();
+
+        buffer.set_text("a\nc\nd");
+        fonts.drain_events(&mut events);
+
+        let current = buffer.split_lines_linked();
+        assert_eq!(current.iter().map(|line| line.text()).collect::<Vec<_>>(), vec!["a", "c", "d"]);
+        assert_eq!(&current[..2], &lines[..]);
+        assert!(events.contains(&FontsEvent::BufferChanged(lines[1].weak_ref())));
+        assert!(!events.contains(&FontsEvent::BufferChanged(lines[0].weak_ref())));
+        assert!(events.contains(&FontsEvent::BufferCreated(current[2].weak_ref())));
+
+        let removed = current[2].weak_ref();
+        drop(current);
+        buffer.set_text("x");
+        events.clear();
+        fonts.drain_events(&mut events);
+        assert!(events.contains(&FontsEvent::BufferDeleted(removed)));
+        assert_eq!(lines[0].text(), "x");
+        assert_eq!(lines[1].text(), "c");
+
+        buffer.unlink_lines();
+        buffer.append_text("y");
+        assert_eq!(lines[0].text(), "x");
+    }
+
+    #[test]
+    #[cfg(not(feature = "simple-shaping"))]
+    fn pooled_shaping_should_match_plain_hb_shape_for_mixed_scripts() {
+        use harfbuzz_rs as hb;
+
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+
+        // alternating scripts, so reused input buffers would leak direction or script if not reset
+        let corpus = [
+            "office affine", "مرحبا بالعالم", "Привет, мир", "שלום עולם", "नमस्ते दुनिया",
+            "ff 123 مرحبا", "こんにちは世界", "Ελληνικά", "abc אבג abc", "fi",
+        ];
+
+        for _ in 0..2 {
+            for text in &corpus {
+                let buffer = font.create_buffer(*text, None, [0, 0, 0, 255].into());
+                let mut actual = Vec::new();
+                buffer.glyphs(&mut actual);
+
+                // mixed direction text is shaped one direction run at a time
+                let shared = fonts.container.borrow();
+                let mut expected = Vec::new();
+                for run in bidi::direction_runs(text, 0..text.len(), BaseDirection::FirstStrong) {
+                    let direction = if run.rtl { hb::Direction::Rtl } else { hb::Direction::Ltr };
+                    let input = hb::UnicodeBuffer::new().add_str_item(text, &text[run.range]).set_direction(direction);
+                    let output = hb::shape(&shared.get(font.id).unwrap().hb_font, input, &[]);
+                    expected.extend(output.get_glyph_infos().iter().zip(output.get_glyph_positions())
+                        .map(|(info, position)| (info.codepoint, info.cluster, position.x_advance, position.y_advance, position.x_offset, position.y_offset)));
+                }
+
+                let actual = actual.iter()
+                    .map(|g| (g.id, g.cluster, g.x_advance, g.y_advance, g.x_offset, g.y_offset))
+                    .collect::<Vec<_>>();
+                assert_eq!(actual, expected, "shaping {:?}", text);
+            }
+        }
+    }
+
+    #[test]
+    fn ranged_features_should_only_change_their_range() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+
+        let text = "Total: 1,234.56";
+        let buffer = font.create_buffer(text, None, [0, 0, 0, 255].into());
+        let mut plain = Vec::new();
+        buffer.glyphs(&mut plain);
+
+        buffer.set_features(&[Feature::new(b"tnum", 1).with_range(7..text.len())]);
+        let mut ranged = Vec::new();
+        buffer.glyphs(&mut ranged);
+
+        // the unchanged prefix must shape the same, with or without tnum support in the font
+        let prefix = |glyphs: &[GlyphPosition]| glyphs.iter().filter(|g| g.cluster < 7).cloned().collect::<Vec<_>>();
+        assert_eq!(prefix(&ranged), prefix(&plain));
+
+        let global = font.create_buffer(text, None, [0, 0, 0, 255].into());
+        global.set_features(&[Feature::new(b"tnum", 1)]);
+        let mut all = Vec::new();
+        global.glyphs(&mut all);
+        let suffix = |glyphs: &[GlyphPosition]| glyphs.iter().filter(|g| g.cluster >= 7).map(|g| (g.id, g.x_advance)).collect::<Vec<_>>();
+        assert_eq!(suffix(&ranged), suffix(&all));
+    }
+
+    #[test]
+    fn feature_ranges_should_snap_to_graphemes_and_drop_outside_text() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+
+        let dropped = Rc::new(RefCell::new(Vec::new()));
+        {
+            let dropped = dropped.clone();
+            fonts.set_diagnostics(move |d| if let FontsDiagnostic::FeatureDropped { .. } = d { dropped.borrow_mut().push(d) });
+        }
+
+        // "e" + combining acute is one grapheme at 1..4
+        let buffer = font.create_buffer("xe\u{301}yz", None, [0, 0, 0, 255].into());
+        buffer.set_features(&[Feature::new(b"liga", 0).with_range(2..5), Feature::new(b"kern", 0).with_range(5..6)]);
+        assert_eq!(buffer.features(), vec![Feature::new(b"liga", 0).with_range(1..5), Feature::new(b"kern", 0).with_range(5..6)]);
+        assert!(dropped.borrow().is_empty());
+
+        buffer.set_text("xyzwv");
+        assert_eq!(buffer.features(), vec![Feature::new(b"liga", 0).with_range(1..5)]);
+        assert_eq!(*dropped.borrow(), vec![FontsDiagnostic::FeatureDropped { tag: "kern".to_string(), range: 5..6 }]);
+
+        buffer.set_features(&[Feature::new(b"smcp", 1).with_range(2..10)]);
+        assert!(buffer.features().is_empty());
+        assert_eq!(dropped.borrow().len(), 2);
+    }
+
+    #[test]
+    #[cfg(not(feature = "simple-shaping"))]
+    fn brackets_in_rtl_runs_should_be_mirrored() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let (open, close) = (font.glyph_for_char('(').unwrap(), font.glyph_for_char(')').unwrap());
+        assert_ne!(open, close);
+
+        let glyph_at = |buffer: &Buffer, byte: u32| {
+            let mut glyphs = Vec::new();
+            buffer.glyphs(&mut glyphs);
+            glyphs.iter().find(|g| g.cluster == byte).map(|g| g.id)
+        };
+
+        // "(" at byte 9 and ")" at byte 11 are in right-to-left runs around the "x"
+        let arabic = font.create_buffer("قيمة (x)", None, [0, 0, 0, 255].into());
+        assert_eq!(glyph_at(&arabic, 9), Some(close));
+        assert_eq!(glyph_at(&arabic, 11), Some(open));
+
+        let latin = font.create_buffer("value (x)", None, [0, 0, 0, 255].into());
+        assert_eq!(glyph_at(&latin, 6), Some(open));
+        assert_eq!(glyph_at(&latin, 8), Some(close));
+
+        // mixed text in a left-to-right paragraph keeps brackets next to latin text unmirrored
+        let mixed = font.create_buffer("x (قيمة) y", None, [0, 0, 0, 255].into());
+        assert_eq!(glyph_at(&mixed, 2), Some(open));
+    }
+
+    #[test]
+    fn synthesized_font_should_widen_advances_and_shear_outlines() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        assert_eq!(font.synthesis(), SynthesisOptions::default());
+
+        let options = SynthesisOptions { embolden: true, oblique: true };
+        let synthesized = font.with_synthesis(options);
+        assert_ne!(synthesized, font);
+        assert_eq!(synthesized.synthesis(), options);
+        assert_eq!(font.with_synthesis(options), synthesized);
+        assert_eq!(synthesized.with_synthesis(SynthesisOptions::default()), font);
+
+        let (mut regular, mut bold) = (Vec::new(), Vec::new());
+        font.create_buffer("Hi there", None, [0, 0, 0, 255].into()).glyphs(&mut regular);
+        synthesized.create_buffer("Hi there", None, [0, 0, 0, 255].into()).glyphs(&mut bold);
+        let extra = (font.units_per_em() as f32 / 24.0).round() as i32;
+        for (r, b) in regular.iter().zip(&bold) {
+            assert_eq!(b.id, r.id);
+            assert_eq!(b.x_advance, if r.x_advance == 0 { 0 } else { r.x_advance + extra });
+        }
+
+        let glyph = font.glyph_for_char('H').unwrap();
+        let outline_bounds = |font: &Font| {
+            let path = font.outline_cached(glyph, HintingOptions::None).unwrap();
+            path.points().iter().fold(::std::f32::MIN, |max_x, p| max_x.max(p.x))
+        };
+        assert!(outline_bounds(&synthesized) > outline_bounds(&font) + extra as f32);
+    }
+
+    #[test]
+    fn line_breaks_should_have_no_advance_and_keep_clusters() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+
+        for &(text, breaks, after) in &[("ab\ncd", &[2][..], 3), ("ab\r\ncd", &[2, 3][..], 4), ("ab\u{2028}cd", &[2][..], 5)] {
+            let buffer = font.create_buffer(text, None, [0, 0, 0, 255].into());
+            let mut glyphs = Vec::new();
+            buffer.glyphs(&mut glyphs);
+
+            let line_breaks = glyphs.iter().filter(|g| g.flags.is_line_break()).collect::<Vec<_>>();
+            assert_eq!(line_breaks.iter().map(|g| g.cluster).collect::<Vec<_>>(), breaks, "{:?}", text);
+            assert!(line_breaks.iter().all(|g| g.x_advance == 0 && g.y_advance == 0));
+
+            let c = glyphs.iter().find(|g| g.cluster == after).expect("glyph after the break");
+            assert_eq!(Some(c.id), font.glyph_for_char('c'));
+            assert_eq!(&text[c.byte_offset as usize..(c.byte_offset + c.len) as usize], "c");
+
+            let mut packed = Vec::new();
+            buffer.glyphs_packed(16.0, &mut packed);
+            assert_eq!(packed.len(), glyphs.len() - breaks.len());
+        }
+    }
+
+    #[test]
+    fn collapsed_whitespace_should_shape_single_spaces_and_map_back_to_source() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+
+        let source = "  a \n\t b  ";
+        let buffer = font.create_buffer(source, None, [0, 0, 0, 255].into());
+        buffer.set_whitespace(Whitespace::Collapse);
+        assert_eq!(buffer.text(), source);
+        assert_eq!(buffer.shaped_text(), "a b");
+
+        let (mut glyphs, mut expected) = (Vec::new(), Vec::new());
+        buffer.glyphs(&mut glyphs);
+        font.create_buffer("a b", None, [0, 0, 0, 255].into()).glyphs(&mut expected);
+        assert_eq!(glyphs.iter().map(|g| (g.id, g.x_advance)).collect::<Vec<_>>(), expected.iter().map(|g| (g.id, g.x_advance)).collect::<Vec<_>>());
+
+        // the space glyph covers the whole whitespace run of the source
+        assert_eq!(glyphs.iter().map(|g| (g.byte_offset, g.len)).collect::<Vec<_>>(), vec![(2, 1), (3, 4), (7, 3)]);
+        assert_eq!(buffer.cursor_positions().collect::<Vec<_>>(), vec![0, 3, 7, 10]);
+        assert_eq!(buffer.source_offset(2), 7);
+
+        let hit = buffer.hit_test_x(1000.0, 16.0).expect("hit test with text");
+        assert_eq!((hit.byte_index, hit.cluster_len), (7, 3));
+
+        buffer.append_text("  c");
+        assert_eq!(buffer.shaped_text(), "a b c");
+
+        buffer.set_whitespace(Whitespace::Preserve);
+        assert_eq!(buffer.shaped_text(), buffer.text());
+    }
+
+    #[test]
+    fn advance_width_should_be_memoized_until_reshape() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+
+        let buffer = font.create_buffer("Auto-sized button", None, [0, 0, 0, 255].into());
+        let mut glyphs = Vec::new();
+        buffer.glyphs(&mut glyphs);
+        assert_eq!(buffer.advance_width(), glyphs.iter().map(|g| g.x_advance).sum::<i32>());
+
+        let px = buffer.advance_width_px(16.0);
+        assert!((px - buffer.advance_width() as f32 * font.scale_for_px(16.0)).abs() < 0.001);
+
+        // repeated calls read the memoized sum instead of walking or copying the glyphs
+        let cached = || buffer.container.borrow().buffer(buffer.id()).expect("buffer").cached_advance_width();
+        assert_eq!(cached(), Some(buffer.advance_width()));
+        for _ in 0..1000 {
+            buffer.advance_width();
+        }
+
+        let before = buffer.advance_width();
+        buffer.append_text(" with more text");
+        assert_eq!(cached(), None);
+        assert!(buffer.advance_width() > before);
+    }
+
+    #[test]
+    fn hyphenator_should_break_overflowing_words_with_a_hyphen() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+
+        let text = "Donau dampfschifffahrt";
+        let buffer = font.create_buffer(text, None, [0, 0, 0, 255].into());
+        let width = |s: &str| font.create_buffer(s, None, [0, 0, 0, 255].into()).advance_width_px(16.0);
+        let offsets = vec![5, 11];
+        let max_width = width("Donau dampfschifffa");
+
+        let layout = buffer.layout(&LayoutOptions {
+            max_width: Some(max_width),
+            hyphenator: Some(Box::new(move |word: &str| if word.len() > 10 { offsets.clone() } else { Vec::new() })),
+            ..LayoutOptions::default()
+        });
+
+        let lines = layout.lines();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].line_break, LineBreak::Soft);
+        assert_eq!(&text[lines[0].bytes.clone()], "Donau dampfschiff");
+        assert_eq!(&text[lines[1].bytes.clone()], "fahrt");
+        assert!(lines[0].width <= max_width);
+
+        // the hyphen is the last glyph of the first line, in the cluster of the last letter
+        let first = layout.line_glyphs(0);
+        let hyphen = first.last().expect("hyphen glyph");
+        let letter = &first[first.len() - 2];
+        assert_eq!((hyphen.cluster, hyphen.len), (letter.cluster, letter.len));
+        assert!(hyphen.x_advance > 0.0);
+
+        let unhyphenated = buffer.layout(&LayoutOptions { max_width: Some(max_width), ..LayoutOptions::default() });
+        assert_eq!(&text[unhyphenated.lines()[1].bytes.clone()], "dampfschifffahrt");
+    }
+
+    #[test]
+    fn repeated_queries_should_hit_the_font_source_once() {
+        use std::cell::Cell;
+
+        struct CountingSource {
+            calls: Rc<Cell<usize>>,
+            system: FixtureSource,
+        }
+
+        impl FontSource for CountingSource {
+            fn select_best_match(&self, family_names: &[FamilyName], properties: &Properties) -> Result<Handle, SelectionError> {
+                self.calls.set(self.calls.get() + 1);
+                FontSource::select_best_match(&self.system, family_names, properties)
+            }
+
+            fn select_by_postscript_name(&self, postscript_name: &str) -> Result<Handle, SelectionError> {
+                FontSource::select_by_postscript_name(&self.system, postscript_name)
+            }
+        }
+
+        let calls = Rc::new(Cell::new(0));
+        let fonts = Fonts::with_source(CountingSource { calls: calls.clone(), system: FixtureSource });
+        let font = test_font(&fonts);
+        let again = test_font(&fonts);
+        assert_eq!(calls.get(), 1);
+        assert_eq!(again, font);
+
+        // each cached result holds its own reference
+        drop(font);
+        assert_eq!(fonts.stats().fonts, 1);
+
+        let mut bold = Properties::new();
+        bold.weight(Weight::BOLD);
+        fonts.find_best_match(&[FamilyName::SansSerif], &bold);
+        assert_eq!(calls.get(), 2);
+
+        // unloading the font forgets the queries that resolved to it
+        drop(again);
+        let _reloaded = test_font(&fonts);
+        assert_eq!(calls.get(), 3);
+    }
+
+    #[test]
+    fn refresh_should_resolve_fonts_installed_after_a_failed_query() {
+        use std::cell::Cell;
+
+        /// Finds the sans-serif font as "Installed Later" once refreshed after installing.
+        struct InstallableSource {
+            installed: Rc<Cell<bool>>,
+            visible: bool,
+            system: FixtureSource,
+        }
+
+        impl FontSource for InstallableSource {
+            fn select_best_match(&self, family_names: &[FamilyName], properties: &Properties) -> Result<Handle, SelectionError> {
+                match family_names.first() {
+                    Some(&FamilyName::Title(ref title)) if title == "Installed Later" => if self.visible {
+                        FontSource::select_best_match(&self.system, &[FamilyName::SansSerif], properties)
+                    } else {
+                        Err(SelectionError::NotFound)
+                    },
+                    _ => FontSource::select_best_match(&self.system, family_names, properties),
+                }
+            }
+
+            fn select_by_postscript_name(&self, postscript_name: &str) -> Result<Handle, SelectionError> {
+                FontSource::select_by_postscript_name(&self.system, postscript_name)
+            }
+
+            fn refresh(&mut self) {
+                self.visible = self.installed.get();
+            }
+        }
+
+        let installed = Rc::new(Cell::new(false));
+        let fonts = Fonts::with_source(InstallableSource { installed: installed.clone(), visible: false, system: FixtureSource });
+        let query = [FamilyName::Title("Installed Later".to_string())];
+        assert!(fonts.find_best_match(&query, &Properties::new()).is_none());
+
+        installed.set(true);
+        assert!(fonts.find_best_match(&query, &Properties::new()).is_none());
+
+        let mut events = Vec::new();
+        fonts.drain_events(&mut events);
+        fonts.refresh_system_fonts();
+        fonts.drain_events(&mut events);
+        let loaded = match events.as_slice() {
+            [FontsEvent::FontLoaded(id)] => *id,
+            other => panic!("refresh should load the installed font once, got {:?}", other),
+        };
+
+        let font = fonts.find_best_match(&query, &Properties::new()).expect("resolved after refresh");
+        assert_eq!(font.id, loaded);
+
+        // loaded fonts keep their ids across refreshes
+        fonts.refresh_system_fonts();
+        assert_eq!(test_font(&fonts).id, loaded);
+        assert_eq!(fonts.find_best_match(&query, &Properties::new()), Some(font));
+    }
+
+    #[test]
+    fn font_data_should_be_read_once_and_shared() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+
+        let data = font.copy_font_data().unwrap();
+        assert!(::std::sync::Arc::ptr_eq(&data, &font.copy_font_data().expect("cached font data")));
+
+        let tag = &data[..4];
+        assert!(tag == [0, 1, 0, 0] || tag == b"OTTO" || tag == b"true" || tag == b"ttcf", "{:?}", tag);
+        if tag != b"ttcf" {
+            assert_eq!(font.font_index(), 0);
+        }
+    }
+
+    #[test]
+    fn fonts_without_fvar_should_have_no_axes_or_named_instances() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        assert!(font.table(*b"fvar").is_none());
+        assert!(font.variation_axes().is_empty());
+        assert!(font.named_instances().is_empty());
+    }
+
+    #[test]
+    fn memory_budget_should_hold_after_many_buffers_and_glyphs() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let budget = 64 * 1024;
+        fonts.set_memory_budget(budget);
+
+        let kept = font.create_buffer("kept buffer", None, [0, 0, 0, 255].into());
+        for i in 0..2000 {
+            let buffer = font.create_buffer(format!("label number {}", i), None, [0, 0, 0, 255].into());
+            buffer.set_text(format!("changed label {}", i));
+            assert!(fonts.memory_usage() <= budget);
+        }
+        for glyph_id in 0..font.glyph_count().min(500) {
+            let _ = font.outline_cached(glyph_id, HintingOptions::None);
+            let _ = font.outline_at_size(glyph_id, 16.0, HintingOptions::None, &mut ::lyon_path::default::Path::builder());
+        }
+        assert!(fonts.memory_usage() <= budget);
+        assert!(fonts.memory_usage() > 0);
+
+        // live buffers keep their glyphs
+        let mut glyphs = Vec::new();
+        kept.glyphs(&mut glyphs);
+        assert!(!glyphs.is_empty());
+
+        fonts.set_memory_budget(0);
+        assert_eq!(fonts.memory_usage(), 0);
+    }
+
+    #[test]
+    fn font_refs_should_not_keep_fonts_loaded_or_upgrade_recycled_ids() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let font_ref = font.weak_ref();
+
+        assert!(fonts.font_alive(font_ref));
+        assert_eq!(fonts.upgrade_font(font_ref), Some(font.clone()));
+        let mut renderer_side = HashSet::new();
+        renderer_side.insert(font_ref);
+
+        drop(font);
+        assert!(!fonts.font_alive(font_ref));
+        assert_eq!(fonts.upgrade_font(font_ref), None);
+
+        // the same face loaded again may get the same id, but not the same reference
+        let reloaded = test_font(&fonts);
+        assert_ne!(reloaded.weak_ref(), font_ref);
+        assert!(!renderer_side.contains(&reloaded.weak_ref()));
+        assert!(!fonts.font_alive(font_ref));
+    }
+
+    #[test]
+    fn clear_should_empty_the_container_without_live_handles() {
+        let fonts = test_fonts();
+        {
+            let font = test_font(&fonts);
+            font.create_buffer("gone", None, [0, 0, 0, 255].into());
+            let _ = font.outline_cached(1, HintingOptions::None);
+        }
+
+        assert_eq!(fonts.clear(), 0);
+        assert_eq!(fonts.memory_usage(), 0);
+        assert_eq!(fonts.stats().fonts, 0);
+        assert_eq!(fonts.shaping_cache_stats().entries, 0);
+    }
+
+    #[test]
+    fn clear_should_keep_fonts_and_buffers_of_live_handles() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let buffer = font.create_buffer("still shown", None, [0, 0, 0, 255].into());
+        let _ = font.outline_cached(1, HintingOptions::None);
+
+        assert_eq!(fonts.clear(), 2);
+        assert_eq!(fonts.memory_usage(), 0);
+
+        // handles keep working and refill the caches
+        let mut glyphs = Vec::new();
+        buffer.glyphs(&mut glyphs);
+        assert!(!glyphs.is_empty());
+        assert!(font.outline_cached(1, HintingOptions::None).is_ok());
+        buffer.set_text("shown again");
+        assert_eq!(buffer.text(), "shown again");
+
+        drop(buffer);
+        drop(font);
+        assert_eq!(fonts.clear(), 0);
+    }
+
+    #[test]
+    fn snapshot_should_copy_fonts_and_buffers_with_their_ids() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let buffer = font.create_buffer("copied text", None, [0, 0, 0, 255].into());
+        buffer.set_size_px(24.0);
+
+        let snapshot = fonts.snapshot();
+        assert!(fonts.ptr_eq(&fonts.clone()));
+        assert!(!fonts.ptr_eq(&snapshot));
+
+        // the buffer reference and the snapshot reference
+        let dump = snapshot.debug_dump_fonts();
+        assert_eq!(dump.len(), 1);
+        assert_eq!(dump[0].count, 2);
+
+        let copied = snapshot.buffer_from_id(buffer.id()).unwrap();
+        assert_eq!(copied.text(), "copied text");
+        assert_eq!(copied.size_px(), 24.0);
+        let (mut glyphs, mut copied_glyphs) = (Vec::new(), Vec::new());
+        buffer.glyphs(&mut glyphs);
+        copied.glyphs(&mut copied_glyphs);
+        assert_eq!(glyphs, copied_glyphs);
+
+        // the two diverge
+        copied.set_text("changed");
+        assert_eq!(buffer.text(), "copied text");
+        assert_eq!(snapshot.font_from_id(font.id()).unwrap().full_name(), font.full_name());
+    }
+
+    #[test]
+    fn snapshot_should_release_everything_after_drops_on_both_sides() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let buffer = font.create_buffer("dropped twice", None, [0, 0, 0, 255].into());
+        let clone = buffer.clone();
+
+        let snapshot = fonts.snapshot();
+        let copied_font = snapshot.font_from_id(font.id()).unwrap();
+        let copied = snapshot.buffer_from_id(buffer.id()).unwrap();
+
+        drop(buffer);
+        drop(clone);
+        drop(font);
+        assert_eq!(fonts.stats().fonts, 0);
+        assert_eq!(fonts.stats().buffers, 0);
+        assert!(fonts.leak_report().is_none());
+
+        // the copies are kept by their handles after the snapshot references are released
+        assert_eq!(snapshot.clear(), 2);
+        assert_eq!(copied.text(), "dropped twice");
+        drop(copied);
+        drop(copied_font);
+        assert_eq!(snapshot.stats().fonts, 0);
+        assert_eq!(snapshot.stats().buffers, 0);
+        assert!(snapshot.leak_report().is_none());
+    }
+
+    #[test]
+    fn debug_dump_should_list_live_buffers_and_font_refcounts() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+
+        let long = "a label long enough to be cut at thirty two chars";
+        let kept = font.create_buffer(long, None, [0, 0, 0, 255].into());
+        let clone = kept.clone();
+        font.create_buffer("dropped", None, [0, 0, 0, 255].into());
+
+        let dump = fonts.debug_dump();
+        assert_eq!(dump.len(), 1);
+        assert_eq!(dump[0].count, 2);
+        assert_eq!(dump[0].text, long.chars().take(32).collect::<String>());
+        assert_eq!(dump[0].font_name, font.full_name());
+        assert_eq!(dump[0].glyph_count, kept.glyph_count());
+        #[cfg(feature = "debug-origins")]
+        assert!(dump[0].origin.as_ref().map_or(false, |origin| origin.contains("mod.rs")));
+
+        let font_dump = fonts.debug_dump_fonts();
+        assert_eq!(font_dump.len(), 1);
+        // the handle and the live buffer
+        assert_eq!((font_dump[0].count, font_dump[0].buffers), (2, 1));
+
+        drop(kept);
+        drop(clone);
+        assert!(fonts.debug_dump().is_empty());
+    }
+
+    #[test]
+    fn buffer_size_should_be_used_without_px_size_and_not_reshape() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        fonts.set_default_size(20.0);
+
+        let buffer = font.create_buffer("Hello", None, [0, 0, 0, 255].into());
+        assert_eq!(buffer.size_px(), 20.0);
+        assert_eq!(buffer.advance_width_px(None), buffer.advance_width_px(20.0));
+
+        let (version, transform_version) = (buffer.version(), buffer.transform_version());
+        buffer.set_size_px(40.0);
+        assert_eq!(buffer.version(), version);
+        assert!(buffer.transform_version() > transform_version);
+        assert_eq!(buffer.advance_width_px(None), buffer.advance_width_px(40.0));
+        assert_eq!(buffer.layout(&LayoutOptions::default()).line_height(), buffer.layout(&LayoutOptions {
+            px_size: 40.0,
+            ..LayoutOptions::default()
+        }).line_height());
+
+        // the default only applies to buffers without a size of their own
+        fonts.set_default_size(12.0);
+        assert_eq!(buffer.size_px(), 40.0);
+    }
+
+    #[test]
+    fn scale_factor_should_rasterize_at_physical_size_and_emit_event() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let glyph_id = font.glyph_for_char('H').unwrap();
+        let logical = font.rasterize_glyph(glyph_id, 16.0, HintingOptions::None, (0.0, 0.0)).unwrap();
+        let buffer = font.create_buffer("Hello world", None, [0, 0, 0, 255].into());
+
+        let mut events = Vec::new();
+        fonts.drain_events(&mut events);
+        fonts.set_scale_factor(2.0);
+        fonts.set_scale_factor(2.0);
+        events.clear();
+        fonts.drain_events(&mut events);
+        assert_eq!(events, vec![FontsEvent::ScaleFactorChanged]);
+
+        let physical = font.rasterize_glyph(glyph_id, 16.0, HintingOptions::None, (0.0, 0.0)).unwrap();
+        assert!((physical.advance - logical.advance * 2.0).abs() < 0.01);
+        assert!(physical.height >= logical.height * 2 - 2);
+
+        // phases are on the physical grid, so whole positions fall on half logical pixels
+        let mut glyphs = Vec::new();
+        buffer.glyphs_scaled(None, Some(4), &mut glyphs);
+        assert!(glyphs.iter().all(|g| (g.x * 2.0).fract() == 0.0));
+    }
+
+    fn assert_clusters_map_back(buffer: &Buffer, text: &str) {
+        for i in 0..buffer.glyph_count() {
+            let range = buffer.cluster_range(i);
+            assert!(text.is_char_boundary(range.start) && text.is_char_boundary(range.end));
+            let glyphs = buffer.glyph_range_for_bytes(range);
+            assert!(glyphs.start <= i && i < glyphs.end);
+        }
+    }
+
+    #[test]
+    fn ffi_ligature_should_cover_all_three_letters() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+
+        let buffer = font.create_buffer("ffi", None, [0, 0, 0, 255].into());
+        assert_clusters_map_back(&buffer, "ffi");
+        if buffer.glyph_count() == 1 {
+            assert_eq!(buffer.cluster_range(0), 0..3);
+            assert_eq!(buffer.glyph_range_for_bytes(1..2), 0..1);
+        }
+        assert_eq!(buffer.glyph_range_for_bytes(3..3), 0..0);
+    }
+
+    #[test]
+    #[cfg(not(feature = "simple-shaping"))]
+    fn devanagari_conjunct_glyphs_should_share_syllable_cluster() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+
+        // ka, virama, ssa, vowel sign i, which is drawn before the conjunct
+        let text = "क्षि";
+        let buffer = font.create_buffer(text, None, [0, 0, 0, 255].into());
+        assert_clusters_map_back(&buffer, text);
+
+        // the virama and the vowel sign never get a cluster of their own
+        for &byte in &[3, 9] {
+            let glyphs = buffer.glyph_range_for_bytes(byte..byte + 3);
+            assert!(!glyphs.is_empty());
+            assert!((glyphs.start..glyphs.end).all(|i| buffer.cluster_range(i).start < byte));
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "simple-shaping"))]
+    fn rtl_glyphs_should_map_to_clusters_in_visual_order() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+
+        let text = "אבג";
+        let buffer = font.create_buffer(text, None, [0, 0, 0, 255].into());
+        assert_clusters_map_back(&buffer, text);
+        assert_eq!(buffer.cluster_range(0), 4..6);
+        assert_eq!(buffer.glyph_range_for_bytes(4..6), 0..1);
+        assert_eq!(buffer.glyph_range_for_bytes(0..6), 0..buffer.glyph_count());
+    }
+
+    #[test]
+    fn soft_hyphens_should_only_show_where_lines_break() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+
+        let text = "Donau\u{AD}dampf\u{AD}schiff";
+        let buffer = font.create_buffer(text, None, [0, 0, 0, 255].into());
+        let width = |s: &str| font.create_buffer(s, None, [0, 0, 0, 255].into()).advance_width_px(16.0);
+
+        let mut glyphs = Vec::new();
+        buffer.glyphs(&mut glyphs);
+        let soft_hyphens = glyphs.iter().filter(|g| g.flags.is_soft_hyphen()).collect::<Vec<_>>();
+        assert!(soft_hyphens.iter().all(|g| g.x_advance == 0));
+        // the soft hyphens stay addressable
+        assert_eq!(buffer.glyph_range_for_bytes(5..7).len(), soft_hyphens.iter().filter(|g| g.cluster == 5).count());
+
+        let wide = buffer.layout(&LayoutOptions::default());
+        assert_eq!(wide.lines().len(), 1);
+        assert!(wide.glyphs().iter().filter(|g| g.cluster == 5 || g.cluster == 12).all(|g| !g.visible));
+
+        let narrow = buffer.layout(&LayoutOptions { max_width: Some(width("Donaudampfsc")), ..LayoutOptions::default() });
+        let lines = narrow.lines();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(&text[lines[1].bytes.clone()], "schiff");
+
+        // the hyphen is shown in place of the soft hyphen the line breaks at
+        let hyphen = narrow.line_glyphs(0).last().expect("hyphen glyph");
+        assert_eq!((hyphen.cluster, hyphen.len), (12, 2));
+        assert!(hyphen.visible && hyphen.x_advance > 0.0);
+        assert!(narrow.line_glyphs(0).iter().filter(|g| g.cluster == 5).all(|g| !g.visible));
+    }
+
+    #[test]
+    #[cfg(not(feature = "simple-shaping"))]
+    fn emoji_sequences_should_be_one_cluster_and_cursor_step() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+
+        // family joined with ZWJ, and thumbs up with a skin tone modifier
+        for text in &["👨\u{200D}👩\u{200D}👧\u{200D}👦", "👍\u{1F3FD}"] {
+            let buffer = font.create_buffer(*text, None, [0, 0, 0, 255].into());
+            let mut glyphs = Vec::new();
+            buffer.glyphs(&mut glyphs);
+
+            assert!(glyphs.iter().all(|g| g.cluster == 0 && g.len as usize == text.len()));
+            assert_eq!(buffer.cluster_range(0), 0..text.len());
+            assert_eq!(buffer.next_cursor(0), text.len());
+            assert_eq!(buffer.prev_cursor(text.len()), 0);
+        }
+    }
+
+    #[test]
+    fn no_break_space_should_keep_number_and_unit_on_one_line() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+
+        let text = "Weight 12\u{A0}kg";
+        let buffer = font.create_buffer(text, None, [0, 0, 0, 255].into());
+        let width = |s: &str| font.create_buffer(s, None, [0, 0, 0, 255].into()).advance_width_px(16.0);
+
+        let layout = buffer.layout(&LayoutOptions { max_width: Some(width("Weight 12 k")), ..LayoutOptions::default() });
+        let lines = layout.lines();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(&text[lines[1].bytes.clone()], "12\u{A0}kg");
+
+        // a word joiner keeps the words around a space together
+        let text = "Weight \u{2060}12";
+        let buffer = font.create_buffer(text, None, [0, 0, 0, 255].into());
+        let layout = buffer.layout(&LayoutOptions { max_width: Some(width("Weight 1")), ..LayoutOptions::default() });
+        assert_eq!(layout.lines().len(), 1);
+    }
+
+    #[test]
+    fn text_diff_should_splice_into_new_glyphs() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+
+        let buffer = font.create_buffer("Score: 1520", None, [0, 0, 0, 255].into());
+        let mut glyphs = Vec::new();
+        buffer.glyphs(&mut glyphs);
+
+        let diff = buffer.set_text_diff("Score: 1530");
+        assert!(diff.old_range.start > 0);
+        glyphs.splice(diff.old_range.clone(), diff.glyphs.iter().cloned());
+
+        let mut new_glyphs = Vec::new();
+        buffer.glyphs(&mut new_glyphs);
+        assert_eq!(glyphs, new_glyphs);
+
+        assert!(buffer.set_text_diff("Score: 1530").is_unchanged());
+        assert_eq!(buffer.set_text_diff("").old_range, 0..new_glyphs.len());
+    }
+
+    #[test]
+    #[cfg(not(feature = "simple-shaping"))]
+    fn raw_glyph_buffer_should_match_shaped_glyphs() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+
+        let buffer = font.create_buffer("Hello, world", None, [0, 0, 0, 255].into());
+        let mut glyphs = Vec::new();
+        buffer.glyphs(&mut glyphs);
+
+        let ids = buffer.with_raw_glyph_buffer(|raw| raw.get_glyph_infos().iter().map(|info| info.codepoint).collect::<Vec<_>>());
+        assert_eq!(ids, glyphs.iter().map(|g| g.id).collect::<Vec<_>>());
+
+        let scale = font.with_raw_hb_font(|hb_font| hb_font.scale());
+        assert_eq!(scale.0 as u32, font.units_per_em());
+    }
+
+    #[test]
+    #[cfg(not(feature = "simple-shaping"))]
+    fn raw_glyph_buffer_should_not_split_direction_runs() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+
+        // the Hebrew run is reversed in `glyphs`, while the raw buffer shapes all of it left to right
+        let buffer = font.create_buffer("abc \u{5D0}\u{5D1}\u{5D2}", None, [0, 0, 0, 255].into());
+        let mut glyphs = Vec::new();
+        buffer.glyphs(&mut glyphs);
+
+        let raw = buffer.with_raw_glyph_buffer(|raw| raw.get_glyph_infos().iter().map(|info| info.cluster).collect::<Vec<_>>());
+        assert!(raw.windows(2).all(|pair| pair[0] <= pair[1]), "{:?}", raw);
+        let clusters = glyphs.iter().map(|g| g.cluster).collect::<Vec<_>>();
+        assert_ne!(clusters, raw);
+    }
+
+    #[test]
+    fn font_tables_should_be_read_and_shared() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+
+        let head = font.table(*b"head").expect("head table");
+        assert_eq!(super::sfnt::read_u32(&head, 12), Some(0x5F0F_3CF5));
+        assert!(::std::sync::Arc::ptr_eq(&head, &font.table(*b"head").unwrap()));
+        assert!(font.table(*b"zzzz").is_none());
+    }
+
+    #[test]
+    fn caret_inside_ligature_should_be_inside_the_glyph() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+
+        let carets_in_ligature = |font: &Font| {
+            let buffer = font.create_buffer("ffi", None, [0, 0, 0, 255].into());
+            let layout = buffer.layout(&LayoutOptions::default());
+            let glyph = match layout.glyphs() {
+                [glyph] => *glyph,
+                glyphs => panic!("ffi should shape to one ligature, got {} glyphs", glyphs.len()),
+            };
+            ((1..3).map(|i| layout.caret_for_index(i).x - glyph.x).collect::<Vec<_>>(), glyph)
+        };
+
+        // DejaVu Sans has no carets for the ligature, so it is split evenly
+        let (xs, glyph) = carets_in_ligature(&font);
+        assert!(font.ligature_carets(glyph.id).is_empty());
+        assert!((xs[0] - glyph.x_advance / 3.0).abs() < 0.001);
+        assert!((xs[1] - glyph.x_advance * 2.0 / 3.0).abs() < 0.001);
+
+        // GDEF carets at 700 and 1400 of the ligature
+        let ligature = font.glyph_for_char('\u{fb03}').unwrap();
+        let mut gdef = font.table(*b"GDEF").unwrap().to_vec();
+        let list = gdef.len() as u16;
+        gdef[8..10].copy_from_slice(&list.to_be_bytes());
+        gdef.extend_from_slice(&[0, 6, 0, 1, 0, 12]);
+        gdef.extend_from_slice(&[0, 1, 0, 1]);
+        gdef.extend_from_slice(&(ligature as u16).to_be_bytes());
+        gdef.extend_from_slice(&[0, 2, 0, 6, 0, 10]);
+        gdef.extend_from_slice(&[0, 1, 2, 188, 0, 1, 5, 120]);
+        let with_carets = fonts.font_from_bytes(patched_fixture("DejaVuSans.ttf", vec![(*b"GDEF", gdef)]), 0).unwrap();
+
+        let (xs, glyph) = carets_in_ligature(&with_carets);
+        assert_eq!(with_carets.ligature_carets(glyph.id), vec![700, 1400]);
+        let scale = 16.0 / with_carets.units_per_em() as f32;
+        assert_eq!(xs, vec![700.0 * scale, 1400.0 * scale]);
+    }
+
+    #[test]
+    fn derived_values_should_follow_every_mutation() {
+        use super::atlas::GlyphAtlas;
+
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+
+        #[derive(Debug, PartialEq)]
+        struct Derived {
+            width: i32,
+            bounds: Option<Bounds>,
+            layout: Vec<(u32, u32, f32)>,
+            quads: Vec<[f32; 4]>,
+        }
+
+        let derived = |buffer: &Buffer| {
+            let mut atlas = GlyphAtlas::new(256, 8);
+            let mut quads = Vec::new();
+            layout_quads(buffer, None, &mut atlas, false, &mut quads);
+            Derived {
+                width: buffer.advance_width(),
+                bounds: buffer.ink_bounds(),
+                layout: buffer.layout(&LayoutOptions::default()).glyphs().iter().map(|g| (g.id, g.cluster, g.x)).collect(),
+                quads: quads.iter().map(|q| [q.x0, q.y0, q.x1, q.y1]).collect(),
+            }
+        };
+
+        let mutations: Vec<(&str, Box<dyn Fn(&Buffer)>)> = vec![
+            ("set_text", Box::new(|b: &Buffer| b.set_text("Hello, wide world"))),
+            ("set_text_diff", Box::new(|b: &Buffer| { b.set_text_diff("Hello, wide world"); })),
+            ("splice", Box::new(|b: &Buffer| b.splice(5..5, ", wide"))),
+            ("append_text", Box::new(|b: &Buffer| b.append_text(" again"))),
+            ("set_whitespace", Box::new(|b: &Buffer| b.set_whitespace(Whitespace::Collapse))),
+            ("set_features", Box::new(|b: &Buffer| b.set_features(&[Feature::new(b"kern", 0)]))),
+            ("set_size_px", Box::new(|b: &Buffer| b.set_size_px(32.0))),
+        ];
+
+        for (name, mutate) in &mutations {
+            // derived values read before the mutation must not survive it
+            let read = font.create_buffer("  Hello   world ", None, [0, 0, 0, 255].into());
+            derived(&read);
+            mutate(&read);
+
+            let fresh = font.create_buffer("  Hello   world ", None, [0, 0, 0, 255].into());
+            mutate(&fresh);
+
+            assert_eq!(derived(&read), derived(&fresh), "{}", name);
+        }
+    }
+
+    #[test]
+    fn find_matches_should_rank_source_faces_without_loading_them() {
+        use std::cell::Cell;
+        use font_kit::properties::Style;
+
+        struct ListingSource {
+            listed: Rc<Cell<usize>>,
+            system: FixtureSource,
+        }
+
+        impl FontSource for ListingSource {
+            fn select_best_match(&self, family_names: &[FamilyName], properties: &Properties) -> Result<Handle, SelectionError> {
+                FontSource::select_best_match(&self.system, family_names, properties)
+            }
+
+            fn select_by_postscript_name(&self, postscript_name: &str) -> Result<Handle, SelectionError> {
+                FontSource::select_by_postscript_name(&self.system, postscript_name)
+            }
+
+            fn faces(&self) -> Vec<FaceInfo> {
+                self.listed.set(self.listed.get() + 1);
+                let face = |family: &str, full: &str, weight, style| FaceInfo {
+                    family_name: family.to_string(),
+                    full_name: full.to_string(),
+                    postscript_name: Some(full.replace(' ', "-")),
+                    properties: Properties { weight: Weight(weight), style, stretch: Stretch::NORMAL },
+                };
+                vec![
+                    face("Example Sans", "Example Sans Bold", 700.0, Style::Normal),
+                    face("Example Sans", "Example Sans Italic", 400.0, Style::Italic),
+                    face("Example Serif", "Example Serif Regular", 400.0, Style::Normal),
+                ]
+            }
+        }
+
+        let listed = Rc::new(Cell::new(0));
+        let fonts = Fonts::with_source(ListingSource { listed: listed.clone(), system: FixtureSource });
+        let query = [FamilyName::Title("Example Sans Display".to_string())];
+
+        let matches = fonts.find_matches(&query, &Properties::new(), 2);
+        assert_eq!(matches.iter().map(|m| m.full_name.as_str()).collect::<Vec<_>>(), vec!["Example Sans Bold", "Example Sans Italic"]);
+        assert!(matches.iter().all(|m| !m.loaded));
+        assert_eq!(fonts.stats().fonts, 0);
+
+        fonts.find_matches(&query, &Properties::new(), 2);
+        assert_eq!(listed.get(), 1);
+        fonts.refresh_system_fonts();
+        fonts.find_matches(&query, &Properties::new(), 2);
+        assert_eq!(listed.get(), 2);
+    }
+
+    #[test]
+    fn auto_fallback_should_substitute_missing_families() {
+        let fonts = test_fonts();
+        let substituted = Rc::new(RefCell::new(Vec::new()));
+        {
+            let substituted = substituted.clone();
+            fonts.set_diagnostics(move |d| if let FontsDiagnostic::FamilySubstituted { .. } = d { substituted.borrow_mut().push(d) });
+        }
+
+        let query = [FamilyName::Title("No Such Family 7f3a".to_string())];
+        assert!(fonts.find_best_match(&query, &Properties::new()).is_none());
+
+        fonts.set_auto_fallback(true);
+        let found = fonts.find_best_match_detailed(&query, &Properties::new()).expect("fallback font");
+        let substitution = found.substitution.expect("substitution");
+        assert_eq!(substitution.requested, "No Such Family 7f3a");
+        assert_eq!(substitution.substitute, found.font.full_name());
+
+        // cached queries keep the substitution without reporting it again
+        let again = fonts.find_best_match_detailed(&query, &Properties::new()).expect("fallback font");
+        assert_eq!(again.substitution, Some(substitution));
+        assert_eq!(substituted.borrow().len(), 1);
+
+        fonts.set_auto_fallback(false);
+        assert!(fonts.find_best_match(&query, &Properties::new()).is_none());
+    }
+
+    #[test]
+    fn preshaped_buffer_should_behave_like_a_shaped_one() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let color = [0, 0, 0, 255].into();
+
+        let shaped = font.create_buffer("fine\nday", None, color);
+        let mut glyphs = Vec::new();
+        shaped.glyphs(&mut glyphs);
+        let given = glyphs.iter()
+            .map(|g| GlyphPosition { byte_offset: 0, len: 0, ..*g })
+            .collect::<Vec<_>>();
+
+        let preshaped = fonts.create_preshaped_buffer(&font, "fine\nday".to_string(), given.clone(), None, color).unwrap();
+        assert!(preshaped.is_preshaped() && !shaped.is_preshaped());
+        let mut output = Vec::new();
+        preshaped.glyphs(&mut output);
+        assert_eq!(output, glyphs);
+        assert_eq!(preshaped.advance_width(), shaped.advance_width());
+        assert_eq!(preshaped.cursor_positions().collect::<Vec<_>>(), shaped.cursor_positions().collect::<Vec<_>>());
+
+        // text changes shape the new text
+        preshaped.set_text("fine day");
+        shaped.set_text("fine day");
+        assert!(!preshaped.is_preshaped());
+        let (mut a, mut b) = (Vec::new(), Vec::new());
+        preshaped.glyphs(&mut a);
+        shaped.glyphs(&mut b);
+        assert_eq!(a, b);
+
+        let mut bad = given.clone();
+        bad[2].cluster = 20;
+        assert_eq!(fonts.create_preshaped_buffer(&font, "fine\nday".to_string(), bad, None, color).err(),
+            Some(PreshapedGlyphsError::ClusterOutOfRange { glyph: 2, cluster: 20, len: 8 }));
+        assert_eq!(fonts.create_preshaped_buffer(&font, "\u{e9}".to_string(), vec![GlyphPosition { cluster: 1, ..given[0] }], None, color).err(),
+            Some(PreshapedGlyphsError::ClusterInsideChar { glyph: 0, cluster: 1 }));
+    }
+
+    #[test]
+    fn concatenated_buffers_should_shape_across_the_join() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let color = [0, 0, 0, 255].into();
+        let glyphs = |buffer: &Buffer| {
+            let mut glyphs = Vec::new();
+            buffer.glyphs(&mut glyphs);
+            glyphs
+        };
+
+        let icon = font.create_buffer("AV", None, color);
+        let label = font.create_buffer("AVA", None, color);
+        label.set_features(&[Feature::new(b"liga", 0)]);
+        let joined = icon.concat(&label);
+        assert_eq!(joined.text(), "AVAVA");
+        let whole = font.create_buffer("AVAVA", None, color);
+        whole.set_features(&[Feature::new(b"liga", 0).with_range(2..5)]);
+        assert_eq!(glyphs(&joined), glyphs(&whole));
+        assert_eq!(joined.features(), whole.features());
+
+        let mono = mono_font(&fonts);
+        let code = mono.create_buffer("x", None, color);
+        let mixed = icon.concat(&code);
+        let mixed_glyphs = glyphs(&mixed);
+        assert_eq!(mixed_glyphs.last().map(|g| (g.cluster, g.span)), Some((2, 1)));
+        assert_eq!(mixed_glyphs.len(), glyphs(&icon).len() + glyphs(&code).len());
+    }
+
+    #[test]
+    fn mark_above_should_land_above_the_baseline_with_either_origin() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let base = font.create_buffer("e", None, [0, 0, 0, 255].into());
+        let marked = font.create_buffer("e\u{301}", None, [0, 0, 0, 255].into());
+        let scaled = |buffer: &Buffer| {
+            let mut glyphs = Vec::new();
+            buffer.glyphs_scaled(16.0, None, &mut glyphs);
+            glyphs.iter().map(|g| g.y).collect::<Vec<_>>()
+        };
+
+        let (base_up, marked_up) = (base.ink_bounds().unwrap(), marked.ink_bounds().unwrap());
+        assert!(marked_up.max_y > base_up.max_y && marked_up.max_y > 0.0);
+        let layout_up = marked.layout(&LayoutOptions::default());
+        let scaled_up = scaled(&marked);
+        let version = marked.transform_version();
+
+        fonts.set_origin(Origin::TopLeftYDown);
+        assert_eq!(marked.origin(), Origin::TopLeftYDown);
+        assert!(marked.transform_version() > version);
+        let (base_down, marked_down) = (base.ink_bounds().unwrap(), marked.ink_bounds().unwrap());
+        assert!(marked_down.min_y < base_down.min_y && marked_down.min_y < 0.0);
+        assert_eq!(marked_down, Origin::TopLeftYDown.bounds(marked_up));
+        assert_eq!(scaled(&marked), scaled_up.iter().map(|y| -y).collect::<Vec<_>>());
+
+        let layout_down = marked.layout(&LayoutOptions::default());
+        assert_eq!(layout_down.lines()[0].baseline_y, -layout_up.lines()[0].baseline_y);
+        assert_eq!(layout_down.caret_for_index(0).baseline_y, -layout_up.caret_for_index(0).baseline_y);
+        assert_eq!(layout_down.ink_bounds().map(|b| -b.min_y), layout_up.ink_bounds().map(|b| b.max_y));
+        let y = layout_up.lines()[0].baseline_y;
+        assert_eq!(layout_down.hit_test(1.0, -y), layout_up.hit_test(1.0, y));
+    }
+
+    #[test]
+    fn hits_below_the_first_line_should_resolve_with_either_origin() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let buffer = font.create_buffer("ab\ncd\nef", None, [0, 0, 0, 255].into());
+
+        for &origin in &[Origin::BottomLeftYUp, Origin::TopLeftYDown] {
+            fonts.set_origin(origin);
+            let layout = buffer.layout(&LayoutOptions::default());
+            let lines = layout.lines();
+            assert_eq!(lines.len(), 3);
+            // later lines are further from the origin, below it with Y up and above it with Y down
+            assert!(origin.y(lines[2].baseline_y) < origin.y(lines[1].baseline_y));
+
+            for (i, line) in lines.iter().enumerate().skip(1) {
+                let hit = layout.hit_test(-10.0, line.baseline_y);
+                assert_eq!((hit.line, hit.byte_index), (i, line.bytes.start), "{:?}", origin);
+            }
+            // past the last line resolves to it, not to the first
+            let below = lines[2].baseline_y + origin.y(-2.0 * layout.line_height());
+            assert_eq!(layout.hit_test(-10.0, below).line, 2, "{:?}", origin);
+        }
+    }
+
+    #[test]
+    fn kerning_should_agree_with_shaping_the_pair() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let color = [0, 0, 0, 255].into();
+        let glyphs = |text: &str| {
+            let mut glyphs = Vec::new();
+            font.create_buffer(text, None, color).glyphs(&mut glyphs);
+            glyphs
+        };
+
+        // DejaVu Sans kerns the first three pairs and not the last
+        for (pair, kerned) in &[("AV", true), ("To", true), ("VA", true), ("xx", false)] {
+            let shaped = glyphs(pair);
+            assert_eq!(shaped.len(), 2, "{}", pair);
+            let alone = glyphs(&pair[..1]);
+            let kerning = font.kerning(shaped[0].id, shaped[1].id);
+            assert_eq!(kerning, shaped[0].x_advance - alone[0].x_advance, "{}", pair);
+            assert_eq!(kerning != 0, *kerned, "{}", pair);
+        }
+    }
+
+    #[test]
+    fn word_cache_should_reuse_words_of_other_texts() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let color = [0, 0, 0, 255].into();
+        let advance = |text: &str| font.create_buffer(text, None, color).advance_width();
+        let (delete, space, all) = (advance("Delete"), advance(" "), advance("all"));
+
+        fonts.set_word_cache(true);
+        let before = fonts.shaping_cache_stats();
+        assert_eq!(advance("Delete all"), delete + space + all);
+        let buffer = font.create_buffer("Delete it", None, color);
+        let stats = fonts.shaping_cache_stats();
+        assert_eq!(stats.word_misses - before.word_misses, 4);
+        assert_eq!(stats.word_hits - before.word_hits, 2);
+
+        let mut glyphs = Vec::new();
+        buffer.glyphs(&mut glyphs);
+        assert_eq!(glyphs.last().map(|g| g.cluster), Some(8));
+        buffer.append_text("em");
+        assert_eq!(buffer.advance_width(), advance("Delete item"));
+    }
+
+    #[test]
+    fn memory_only_fonts_should_match_loaded_fonts_by_family() {
+        let bytes = ::std::fs::read(fixture_path("DejaVuSans.ttf")).unwrap();
+
+        let fonts = Fonts::new_memory_only();
+        assert!(fonts.find_best_match(&[FamilyName::SansSerif], &Properties::new()).is_none());
+
+        let font = fonts.font_from_bytes(bytes.clone(), 0).unwrap();
+        assert_eq!(fonts.font_from_bytes(bytes, 0).unwrap(), font);
+
+        let family = FamilyName::Title(font.family_name().to_uppercase());
+        assert_eq!(fonts.find_best_match(&[FamilyName::Title("No Such Family".to_string()), family], &Properties::new()), Some(font.clone()));
+        assert_eq!(fonts.find_best_match(&[FamilyName::Serif], &Properties::new()), Some(font.clone()));
+        assert!(fonts.find_best_match(&[FamilyName::Title("No Such Family".to_string())], &Properties::new()).is_none());
+        assert_eq!(fonts.find_by_name(&font.family_name()).map(|f| f.family_name()), Some(font.family_name()));
+
+        let buffer = font.create_buffer("Hello there", None, [0, 0, 0, 255].into());
+        let mut glyphs = Vec::new();
+        buffer.glyphs(&mut glyphs);
+        assert!(!glyphs.is_empty());
+        assert!(buffer.advance_width() > 0);
+        assert!(buffer.measure(&mut Vec::new()).is_some());
+    }
+
+    #[test]
+    fn debug_shape_string_should_name_glyphs_and_positions() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+
+        let buffer = font.create_buffer("A A", None, [0, 0, 0, 255].into());
+        let mut glyphs = Vec::new();
+        buffer.glyphs(&mut glyphs);
+
+        let expected = glyphs.iter()
+            .map(|g| format!("{}={}+{}", font.glyph_name(g.id).unwrap_or_else(|| format!("gid{}", g.id)), g.cluster, g.x_advance))
+            .collect::<Vec<_>>();
+        assert_eq!(buffer.debug_shape_string(), format!("[{}]", expected.join("|")));
+        assert_eq!(fonts.shape_debug(&font, "A A"), buffer.debug_shape_string());
+        assert_eq!(fonts.shape_debug(&font, ""), "[]");
+    }
+
+    #[test]
+    fn reshape_with_should_apply_all_parameters_with_one_shaping() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let other = fonts.find_best_match(&[FamilyName::Serif], &Properties::new()).unwrap_or_else(|| font.clone());
+
+        let buffer = font.create_buffer("reshape  with  1 2 3", None, [0, 0, 0, 255].into());
+        let version = buffer.version();
+        let calls = fonts.shaping_cache_stats().shape_calls;
+
+        let features = vec![Feature::new(b"tnum", 1).with_range(12..17), Feature::new(b"liga", 0)];
+        buffer.reshape_with(ShapeParams {
+            font: Some(other.clone()),
+            features: Some(features.clone()),
+            whitespace: Some(Whitespace::Collapse),
+            base_direction: Some(BaseDirection::Rtl),
+        });
+        assert_eq!(buffer.version(), version + 1);
+        assert_eq!(fonts.shaping_cache_stats().shape_calls, calls + 1);
+        assert_eq!(buffer.font(), other);
+        assert_eq!(buffer.whitespace(), Whitespace::Collapse);
+        assert_eq!(buffer.features(), features);
+        assert_eq!(buffer.base_direction(), BaseDirection::Rtl);
+
+        let expected = other.create_buffer("reshape  with  1 2 3", None, [0, 0, 0, 255].into());
+        expected.set_whitespace(Whitespace::Collapse);
+        expected.set_features(&features);
+        expected.set_base_direction(BaseDirection::Rtl);
+        let (mut a, mut b) = (Vec::new(), Vec::new());
+        buffer.glyphs(&mut a);
+        expected.glyphs(&mut b);
+        assert_eq!(a, b);
+
+        buffer.reshape_with(ShapeParams::default());
+        assert_eq!(buffer.version(), version + 1);
+    }
+
+    #[test]
+    fn recycled_buffers_should_come_back_like_new_ones() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+
+        let used = font.create_buffer("a configured  buffer with a longer text", None, [255, 0, 0, 255].into());
+        used.set_features(&[Feature::new(b"liga", 0)]);
+        used.set_whitespace(Whitespace::Collapse);
+        used.set_transform(Some(na::convert(na::Translation3::new(1.0, 2.0, 3.0))));
+        used.set_size_px(40.0);
+        let clone = used.clone();
+        fonts.recycle(used);
+        assert_eq!(fonts.stats().pooled_buffers, 0);
+        drop(clone);
+        assert_eq!(fonts.stats().pooled_buffers, 1);
+
+        let reused = font.create_buffer("plain", None, [0, 0, 0, 255].into());
+        assert_eq!(fonts.stats().pooled_buffers, 0);
+        let fresh = font.create_buffer("plain", None, [0, 0, 0, 255].into());
+
+        assert_eq!(reused.text(), fresh.text());
+        assert_eq!(reused.features(), fresh.features());
+        assert_eq!(reused.whitespace(), Whitespace::Preserve);
+        assert_eq!(reused.transform(), None);
+        assert_eq!(reused.size_px(), fresh.size_px());
+        assert_eq!(reused.version(), fresh.version());
+        assert_eq!(reused.transform_version(), fresh.transform_version());
+        let (mut a, mut b) = (Vec::new(), Vec::new());
+        reused.glyphs(&mut a);
+        fresh.glyphs(&mut b);
+        assert_eq!(a, b);
+
+        fonts.set_buffer_pool_capacity(0);
+        fonts.recycle(reused);
+        assert_eq!(fonts.stats().pooled_buffers, 0);
+    }
+
+    #[test]
+    fn outline_segments_should_report_each_contour() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let (i, o) = (font.glyph_for_char('i').unwrap(), font.glyph_for_char('O').unwrap());
+
+        let (mut points, mut contours) = (Vec::new(), Vec::new());
+        for &glyph in &[i, o] {
+            font.outline_segments(glyph, HintingOptions::None, 1.0, &mut points, &mut contours).unwrap();
+            assert!(contours.len() >= 2, "glyph {} has {} contours", glyph, contours.len());
+            assert_eq!(contours[0].start, 0);
+            assert_eq!(contours.last().unwrap().end, points.len());
+            for pair in contours.windows(2) {
+                assert_eq!(pair[0].end, pair[1].start);
+            }
+            assert!(contours.iter().all(|c| c.len() >= 3));
+        }
+
+        let coarse = points.len();
+        font.outline_segments(o, HintingOptions::None, 0.05, &mut points, &mut contours).unwrap();
+        assert!(points.len() > coarse);
+
+        let path = font.outline_cached(o, HintingOptions::None).unwrap();
+        font.outline_segments(o, HintingOptions::None, 0.05, &mut points, &mut contours).unwrap();
+        assert!(Rc::ptr_eq(&path, &font.outline_cached(o, HintingOptions::None).unwrap()));
+    }
+
+    #[test]
+    fn metrics_for_size_should_scale_every_metric() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let metrics = font.metrics();
+        let scale = 16.0 / metrics.units_per_em as f32;
+
+        let scaled = font.metrics_for_size(16.0);
+        assert_eq!(scaled.px_size, 16.0);
+        assert_eq!(scaled.scale, scale);
+        assert_eq!(scaled.ascent, metrics.ascent * scale);
+        assert_eq!(scaled.descent, metrics.descent * scale);
+        assert_eq!(scaled.line_gap, metrics.line_gap * scale);
+        assert_eq!(scaled.line_height, (metrics.ascent - metrics.descent + metrics.line_gap) * scale);
+        assert_eq!(scaled.underline_position, metrics.underline_position * scale);
+        assert_eq!(scaled.underline_thickness, metrics.underline_thickness * scale);
+        assert_eq!(scaled.cap_height, metrics.cap_height * scale);
+        assert_eq!(scaled.x_height, metrics.x_height * scale);
+
+        let buffer = font.create_buffer("one\ntwo", None, [0, 0, 0, 255].into());
+        let layout = buffer.layout(&LayoutOptions { px_size: 16.0, ..LayoutOptions::default() });
+        let lines = layout.lines();
+        assert_eq!(lines.len(), 2);
+        assert!((lines[0].baseline_y - lines[1].baseline_y - scaled.line_height).abs() < 0.001);
+    }
+
+    #[test]
+    fn trimmed_measure_should_leave_out_whitespace_at_the_ends() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+
+        let label = font.create_buffer("label", None, [0, 0, 0, 255].into());
+        let padded = font.create_buffer("  label ", None, [0, 0, 0, 255].into());
+        assert!((padded.measure_trimmed(16.0) - label.advance_width_px(16.0)).abs() < 0.001);
+        assert!(padded.measure_trimmed(16.0) < padded.advance_width_px(16.0));
+
+        let mut glyphs = Vec::new();
+        let trailing = padded.measure_with(MeasureOptions { trim_leading: false, trim_trailing: true }, &mut glyphs).unwrap();
+        assert_eq!(glyphs.len(), 8);
+        let full = padded.measure(&mut Vec::new()).unwrap();
+        assert_eq!(full.width - trailing.width, glyphs[7].x_advance as f32);
+
+        let blank = font.create_buffer("   ", None, [0, 0, 0, 255].into());
+        assert_eq!(blank.measure_trimmed(16.0), 0.0);
+        assert_eq!(blank.measure_with(MeasureOptions { trim_leading: true, trim_trailing: false }, &mut Vec::new()).unwrap().width, 0.0);
+
+        if font.glyph_for_char('\u{5e9}').is_some() {
+            let hebrew = font.create_buffer("\u{5e9}\u{5dc}\u{5d5}\u{5dd}", None, [0, 0, 0, 255].into());
+            let rtl = font.create_buffer("\u{5e9}\u{5dc}\u{5d5}\u{5dd}  ", None, [0, 0, 0, 255].into());
+            assert!((rtl.measure_trimmed(16.0) - hebrew.advance_width_px(16.0)).abs() < 0.001);
+        }
+    }
+
+    #[test]
+    fn kashida_justification_should_elongate_arabic_joins() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        assert!(font.glyph_for_char('\u{628}').is_some());
+        let tatweel = font.glyph_for_char('\u{640}').unwrap();
+
+        let text = "\u{643}\u{62a}\u{628} \u{627}\u{644}\u{643}\u{627}\u{62a}\u{628} \u{62f}\u{631}\u{633}\u{627} \u{62c}\u{645}\u{64a}\u{644}\u{627} \u{641}\u{64a} \u{627}\u{644}\u{645}\u{62f}\u{631}\u{633}\u{629}";
+        let buffer = font.create_buffer(text, None, [0, 0, 0, 255].into());
+        let options = |justify| LayoutOptions {
+            px_size: 16.0,
+            max_width: Some(buffer.advance_width_px(16.0) * 0.6),
+            align: Align::Justify,
+            justify,
+            ..LayoutOptions::default()
+        };
+
+        let spaces = buffer.layout(&options(Justify::Spaces));
+        let kashida = buffer.layout(&options(Justify::Kashida));
+        assert_eq!(spaces.lines().len(), kashida.lines().len());
+        assert!(kashida.lines().len() > 1);
+
+        let line = &kashida.lines()[0];
+        let inserted = kashida.glyphs()[line.glyphs.clone()].iter().filter(|g| g.id == tatweel).count();
+        assert!(inserted > 0);
+        assert_eq!(line.glyphs.len(), spaces.lines()[0].glyphs.len() + inserted);
+        assert!((line.width - spaces.lines()[0].width).abs() < 0.01);
+
+        // text without joins is justified with spaces either way
+        let latin = font.create_buffer("some latin words to wrap over lines", None, [0, 0, 0, 255].into());
+        let options = |justify| LayoutOptions {
+            px_size: 16.0,
+            max_width: Some(latin.advance_width_px(16.0) * 0.6),
+            align: Align::Justify,
+            justify,
+            ..LayoutOptions::default()
+        };
+        let positions = |layout: TextLayout| layout.glyphs().iter().map(|g| (g.id, g.x, g.x_advance)).collect::<Vec<_>>();
+        assert_eq!(positions(latin.layout(&options(Justify::Kashida))), positions(latin.layout(&options(Justify::Spaces))));
+    }
+
+    #[test]
+    fn layout_on_path_should_place_every_glyph_on_a_long_line() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let buffer = font.create_buffer("path", None, [0, 0, 0, 255].into());
+        let mut builder = ::lyon_path::default::Path::builder();
+        builder.move_to(::lyon_path::math::point(0.0, 0.0));
+        builder.line_to(::lyon_path::math::point(0.0, 1000.0));
+        let path = ::lyon_path::builder::FlatPathBuilder::build(builder);
+
+        let placed = buffer.layout_on_path(&path, 16.0, &PathLayoutOptions::default());
+        let mut glyphs = Vec::new();
+        buffer.glyphs(&mut glyphs);
+        assert_eq!(placed.iter().map(|g| (g.id, g.cluster)).collect::<Vec<_>>(),
+            glyphs.iter().map(|g| (g.id, g.cluster)).collect::<Vec<_>>());
+        assert!(placed.iter().all(|g| (g.angle - ::std::f32::consts::FRAC_PI_2).abs() < 0.001 && g.x.abs() < 0.001));
+    }
+
+    #[test]
+    fn rounded_measurement_should_match_the_rendered_pen_position() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let text = "n".repeat(100);
+        let measured = font.create_buffer(&text, None, [0, 0, 0, 255].into());
+        let rendered = font.create_buffer(&(text + "n"), None, [0, 0, 0, 255].into());
+        let pen_after = |rendered: &Buffer| {
+            let mut glyphs = Vec::new();
+            rendered.glyphs_scaled(13.0, None, &mut glyphs);
+            glyphs[100].x
+        };
+
+        let exact = measured.advance_width_px(13.0);
+        for &mode in &[RoundingMode::None, RoundingMode::RoundEach, RoundingMode::RoundAccumulated] {
+            let version = rendered.transform_version();
+            fonts.set_rounding(mode);
+            assert_eq!(rendered.transform_version() != version, mode != RoundingMode::None);
+
+            let width = measured.advance_width_px(13.0);
+            assert!((width - pen_after(&rendered)).abs() < 0.001);
+            assert!((measured.measure_trimmed(13.0) - width).abs() < 0.001);
+            if mode != RoundingMode::None {
+                assert_eq!(width.fract(), 0.0);
+            }
+            if mode == RoundingMode::RoundAccumulated {
+                assert!((width - exact).abs() <= 0.5);
+            }
+        }
+    }
+
+    #[test]
+    fn line_metrics_should_drive_hit_testing_in_both_origins() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let buffer = font.create_buffer("one\ntwo three\nfour", None, [0, 0, 0, 255].into());
+        let options = LayoutOptions { paragraph_spacing: 4.0, ..LayoutOptions::default() };
+
+        let layout = buffer.layout(&options);
+        let lines = layout.lines();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines.iter().map(|l| l.line_break).collect::<Vec<_>>(), vec![LineBreak::Paragraph, LineBreak::Paragraph, LineBreak::End]);
+        assert!(lines.iter().all(|l| l.ascent > 0.0 && (l.ascent - l.descent - layout.line_height()).abs() < 0.001));
+        assert_eq!(layout.total_size(), (lines[1].width, layout.height()));
+
+        assert_eq!(layout.line_at_y(lines[0].baseline_y + lines[0].ascent + 1.0), None);
+        assert_eq!(layout.line_at_y(lines[2].baseline_y + lines[2].descent - 1.0), None);
+        for (i, line) in lines.iter().enumerate() {
+            assert_eq!(layout.line_at_y(line.baseline_y), Some(i));
+            assert_eq!(layout.hit_test(0.0, line.baseline_y).line, i);
+        }
+        // the paragraph spacing above a line belongs to it
+        assert_eq!(layout.line_at_y(lines[0].baseline_y + lines[0].descent - 2.0), Some(1));
+
+        fonts.set_origin(Origin::TopLeftYDown);
+        let down = buffer.layout(&options);
+        for (i, line) in down.lines().iter().enumerate() {
+            assert_eq!(line.ascent, lines[i].ascent);
+            assert_eq!(down.line_at_y(line.baseline_y), Some(i));
+            assert_eq!(down.hit_test(0.0, line.baseline_y).line, i);
+            assert_eq!(down.caret_for_index(line.bytes.start).height, line.ascent - line.descent);
+        }
+    }
+
+    #[test]
+    fn word_boundaries_should_index_the_given_text() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let buffer = font.create_buffer("say, \"hi\"", None, [0, 0, 0, 255].into());
+
+        let text = buffer.text();
+        let words = buffer.word_boundaries().map(|r| &text[r]).collect::<Vec<_>>();
+        assert_eq!(words, vec!["say", ",", " ", "\"", "hi", "\""]);
+        assert_eq!(buffer.word_at(1), Some(0..3));
+        assert_eq!(buffer.word_at(3).map(|r| &text[r]), Some(","));
+        assert_eq!(buffer.word_at(text.len() + 1), None);
+    }
+
+    #[test]
+    fn unretained_text_should_be_freed_after_shaping() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let buffer = font.create_buffer("12345", None, [0, 0, 0, 255].into());
+        let mut glyphs = Vec::new();
+        buffer.glyphs(&mut glyphs);
+        let cursors = buffer.cursor_positions().collect::<Vec<_>>();
+        let width = buffer.advance_width_px(16.0);
+        let before = fonts.stats();
+
+        buffer.retain_text(false);
+        assert!(!buffer.has_text());
+        assert_eq!((buffer.try_text(), buffer.text()), (None, String::new()));
+        assert_eq!(buffer.hit_test_x(0.0, 16.0), None);
+        let stats = fonts.stats();
+        assert_eq!(stats.dropped_text_bytes, before.dropped_text_bytes + 5);
+        assert!(stats.text_bytes + 5 <= before.text_bytes);
+
+        let mut kept = Vec::new();
+        buffer.glyphs(&mut kept);
+        assert_eq!(kept, glyphs);
+        assert_eq!(buffer.cursor_positions().collect::<Vec<_>>(), cursors);
+        assert!((buffer.measure_trimmed(16.0) - width).abs() < 0.001);
+        assert_eq!(buffer.layout(&LayoutOptions::default()).glyphs().len(), glyphs.len());
+        buffer.append_text("6");
+        assert_eq!(buffer.text_len(), 0);
+
+        buffer.set_text("-42");
+        assert!(!buffer.has_text());
+        assert_eq!(buffer.cursor_positions().last(), Some(3));
+        buffer.retain_text(true);
+        buffer.set_text("-43");
+        assert_eq!(buffer.try_text(), Some("-43".to_string()));
+        assert!(buffer.hit_test_x(0.0, 16.0).is_some());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn leaked_handles_should_be_reported_with_their_call_sites() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let name = font.full_name();
+        let buffer = font.create_buffer("leaky", None, [0, 0, 0, 255].into());
+        assert!(fonts.leak_report().expect("leak_report").contains("\"leaky\""));
+        drop(buffer);
+
+        let (leaked, line) = (font.clone(), line!());
+        ::std::mem::forget(leaked);
+        drop(font);
+
+        let report = fonts.leak_report().expect("leak_report");
+        assert!(report.starts_with("1 fonts or buffers still referenced:"), "{}", report);
+        assert!(report.contains(&format!("{:?} created at", name)), "{}", report);
+        assert!(report.contains("1 references left"), "{}", report);
+        assert!(report.contains(&format!("1 taken at {}:{}:", file!(), line)), "{}", report);
+        assert!(!report.contains("leaky"), "{}", report);
+    }
+
+    #[test]
+    fn resolved_properties_should_follow_text_changes() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let buffer = font.create_buffer("hello", None, [0, 0, 0, 255].into());
+        let latin = buffer.resolved_properties();
+        assert_eq!((latin.direction, latin.script), (ShapedDirection::LeftToRight, *b"Latn"));
+
+        buffer.set_text("привет");
+        assert_eq!(buffer.resolved_properties().script, *b"Cyrl");
+        assert_eq!(buffer.resolved_runs().len(), 1);
+
+        buffer.retain_text(false);
+        assert_eq!(buffer.resolved_properties().script, *b"Cyrl");
+    }
+
+    #[test]
+    fn buffer_features_should_win_over_default_features() {
+        let defaults = vec![Feature::new(b"calt", 1), Feature::new(b"liga", 0)];
+
+        let merged = shared::merge_features(&defaults, &[Feature::new(b"liga", 1)]);
+        assert_eq!(merged, vec![Feature::new(b"calt", 1), Feature::new(b"liga", 1)]);
+
+        // a ranged feature overrides the default only inside its range, by coming after it
+        let ranged = Feature::new(b"liga", 1).with_range(2..4);
+        let merged = shared::merge_features(&defaults, &[ranged.clone()]);
+        assert_eq!(merged, vec![Feature::new(b"calt", 1), Feature::new(b"liga", 0), ranged]);
+
+        assert_eq!(shared::merge_features(&defaults, &[]), defaults);
+        assert_eq!(shared::merge_features(&[], &defaults), defaults);
+    }
+
+    #[test]
+    fn default_features_should_reshape_existing_buffers_when_read() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let glyphs = |buffer: &Buffer| { let mut g = Vec::new(); buffer.glyphs(&mut g); g };
+
+        let text = "office affine waffle";
+        let buffer = font.create_buffer(text, None, [0, 0, 0, 255].into());
+        let overriding = font.create_buffer(text, None, [0, 0, 0, 255].into());
+        overriding.set_features(&[Feature::new(b"liga", 1)]);
+        let plain = glyphs(&buffer);
+        let version = buffer.version();
+        fonts.drain_events(&mut Vec::new());
+
+        fonts.set_default_features(&[Feature::new(b"liga", 0).with_range(0..2)]);
+        assert_eq!(fonts.default_features(), vec![Feature::new(b"liga", 0)]);
+        let mut events = Vec::new();
+        fonts.drain_events(&mut events);
+        assert!(events.contains(&FontsEvent::BufferChanged(buffer.weak_ref())));
+        assert!(buffer.version() > version);
+
+        let explicit = font.create_buffer(text, None, [0, 0, 0, 255].into());
+        fonts.set_default_features(&[]);
+        explicit.set_features(&[Feature::new(b"liga", 0)]);
+        let defaulted = font.create_buffer(text, None, [0, 0, 0, 255].into());
+        fonts.set_default_features(&[Feature::new(b"liga", 0)]);
+        assert_eq!(glyphs(&defaulted), glyphs(&explicit));
+        assert_eq!(glyphs(&buffer), glyphs(&explicit));
+        assert_eq!(glyphs(&overriding), plain);
+    }
+
+    #[test]
+    fn buffer_builder_should_shape_once_with_all_settings() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let glyphs = |buffer: &Buffer| { let mut g = Vec::new(); buffer.glyphs(&mut g); g };
+
+        let features = vec![Feature::new(b"liga", 0), Feature::new(b"tnum", 1).with_range(6..9)];
+        let calls = fonts.shaping_cache_stats().shape_calls;
+        let builder = font.buffer()
+            .text("built  with  1 2 3")
+            .features(&features)
+            .whitespace(Whitespace::Collapse)
+            .size_px(14.0)
+            .base_direction(BaseDirection::Rtl);
+        let buffer = builder.build();
+        assert_eq!(fonts.shaping_cache_stats().shape_calls, calls + 1);
+        assert_eq!(buffer.version(), 0);
+        assert_eq!((buffer.features(), buffer.whitespace(), buffer.size_px()), (features.clone(), Whitespace::Collapse, 14.0));
+        assert_eq!(buffer.base_direction(), BaseDirection::Rtl);
+
+        let expected = font.create_buffer("built  with  1 2 3", None, [0, 0, 0, 255].into());
+        expected.set_whitespace(Whitespace::Collapse);
+        expected.set_features(&features);
+        expected.set_base_direction(BaseDirection::Rtl);
+        assert_eq!(glyphs(&buffer), glyphs(&expected));
+
+        // the builder keeps its settings for list items
+        let item = builder.build_text("item  4 5 6");
+        assert_eq!((item.text(), item.features(), item.size_px()), ("item  4 5 6".to_string(), features, 14.0));
+        assert_eq!(builder.build().text(), buffer.text());
+
+        let calls = fonts.shaping_cache_stats().shape_calls;
+        let lazy = font.buffer().text("lazily built").lazy(true).retain_text(false).build();
+        assert_eq!(fonts.shaping_cache_stats().shape_calls, calls);
+        assert!(lazy.has_text());
+        assert!(!glyphs(&lazy).is_empty());
+        assert!(!lazy.has_text());
+    }
+
+    #[test]
+    fn preedit_should_be_shaped_inline_and_committed_by_splicing() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let glyphs = |buffer: &Buffer| { let mut g = Vec::new(); buffer.glyphs(&mut g); g };
+
+        let buffer = font.create_buffer("ab cd", None, [0, 0, 0, 255].into());
+        let committed = glyphs(&buffer);
+        buffer.set_preedit(3, "xy", 1);
+        assert_eq!((buffer.text(), buffer.committed_text()), ("ab xycd".to_string(), "ab cd".to_string()));
+        assert_eq!((buffer.preedit_range(), buffer.preedit_cursor()), (Some(3..5), Some(4)));
+        assert_eq!((buffer.caret_index(1), buffer.caret_index(3), buffer.caret_index(4)), (1, 4, 6));
+
+        let marked = glyphs(&buffer).into_iter().filter(|g| g.flags.is_preedit()).map(|g| g.byte_offset).collect::<Vec<_>>();
+        assert_eq!(marked, vec![3, 4]);
+        assert_eq!(&glyphs(&buffer)[..2], &committed[..2]);
+
+        // moving the caret in the composition does not reshape
+        let version = buffer.version();
+        buffer.set_preedit(3, "xy", 2);
+        assert_eq!((buffer.version(), buffer.preedit_cursor()), (version, Some(5)));
+
+        buffer.set_preedit(1, "z", 1);
+        assert_eq!((buffer.text(), buffer.preedit_range()), ("azb cd".to_string(), Some(1..2)));
+
+        buffer.splice(buffer.preedit_range().expect("preedit"), "Z");
+        assert_eq!((buffer.text(), buffer.preedit_range()), ("aZb cd".to_string(), None));
+        assert!(glyphs(&buffer).iter().all(|g| !g.flags.is_preedit()));
+
+        buffer.set_preedit(6, "q", 0);
+        buffer.clear_preedit();
+        assert_eq!((buffer.text(), buffer.preedit_range()), ("aZb cd".to_string(), None));
+    }
+
+    #[test]
+    fn sized_fonts_should_shape_in_26_6_pixels_and_be_shared_per_size() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let loaded = fonts.stats().fonts;
+
+        let sized = font.at_size(16.1);
+        assert_eq!(sized.px_size(), 16.0);
+        assert_eq!(sized.font().size_px(), Some(16.0));
+        assert_eq!(sized.font().units_per_em(), 16 * 64);
+        assert_eq!(font.size_px(), None);
+        assert_eq!(font.at_size(16.0).font(), sized.font());
+        assert!(font.at_size(17.0).font() != sized.font());
+        assert_eq!(fonts.stats().fonts, loaded + 1);
+
+        let scale = 1024.0 / font.units_per_em() as f32;
+        let ascent = font.metrics().ascent * scale;
+        assert!((sized.font().metrics().ascent - ascent).abs() < 0.01);
+
+        let text = "Sized text";
+        let plain = font.create_buffer(text, None, [0, 0, 0, 255].into());
+        let buffer = sized.create_buffer(text, None, [0, 0, 0, 255].into());
+        assert_eq!(buffer.size_px(), 16.0);
+        assert_eq!(buffer.font(), *sized.font());
+        let (mut plain_glyphs, mut glyphs) = (Vec::new(), Vec::new());
+        plain.glyphs(&mut plain_glyphs);
+        buffer.glyphs(&mut glyphs);
+        assert_eq!(glyphs.iter().map(|g| g.id).collect::<Vec<_>>(), plain_glyphs.iter().map(|g| g.id).collect::<Vec<_>>());
+        for (g, p) in glyphs.iter().zip(&plain_glyphs) {
+            assert!((g.x_advance as f32 - p.x_advance as f32 * scale).abs() <= 64.0, "{:?} {:?}", g, p);
+        }
+        assert!((buffer.advance_width_px(None) - plain.advance_width_px(Some(16.0))).abs() <= glyphs.len() as f32);
+
+        drop((buffer, sized));
+        assert_eq!(fonts.stats().fonts, loaded);
+    }
+
+    #[test]
+    fn optical_tracking_should_leave_fonts_without_trak_unchanged() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        assert!(font.table(*b"trak").is_none());
+
+        let buffer = font.create_buffer("Tracked text", None, [0, 0, 0, 255].into());
+        assert!(buffer.optical_tracking());
+        let mut tracked = Vec::new();
+        buffer.glyphs(&mut tracked);
+        let version = buffer.version();
+
+        buffer.set_optical_tracking(false);
+        buffer.set_size_px(9.0);
+        assert!(!buffer.optical_tracking());
+        assert_eq!(buffer.version(), version);
+        let mut untracked = Vec::new();
+        buffer.glyphs(&mut untracked);
+        assert_eq!(tracked, untracked);
+    }
+
+    #[test]
+    fn optical_tracking_should_follow_the_trak_table_at_the_buffer_size() {
+        let fonts = test_fonts();
+        let plain = test_font(&fonts);
+        // normal track of 100 at 12 and -100 at 24
+        let mut trak = vec![0, 1, 0, 0, 0, 0, 0, 12, 0, 0, 0, 0];
+        trak.extend_from_slice(&[0, 1, 0, 2, 0, 0, 0, 28]);
+        trak.extend_from_slice(&[0, 0, 0, 0, 1, 0, 0, 36]);
+        trak.extend_from_slice(&[0, 12, 0, 0, 0, 24, 0, 0]);
+        trak.extend_from_slice(&[0, 100, 255, 156]);
+        let font = fonts.font_from_bytes(patched_fixture("DejaVuSans.ttf", vec![(*b"trak", trak)]), 0).unwrap();
+
+        let color = [0, 0, 0, 255].into();
+        let mut plain_glyphs = Vec::new();
+        plain.create_buffer("Tracked text", None, color).glyphs(&mut plain_glyphs);
+        let buffer = font.create_buffer("Tracked text", None, color);
+        let tracking = |buffer: &Buffer| {
+            let mut glyphs = Vec::new();
+            buffer.glyphs(&mut glyphs);
+            assert_eq!(glyphs.len(), plain_glyphs.len());
+            let added = glyphs.iter().zip(&plain_glyphs).map(|(g, p)| g.x_advance - p.x_advance).collect::<Vec<_>>();
+            assert!(added.iter().all(|&a| a == added[0]), "{:?}", added);
+            added[0]
+        };
+
+        buffer.set_size_px(12.0);
+        let loose = tracking(&buffer);
+        assert!(loose > 0);
+        let version = buffer.version();
+        buffer.set_size_px(24.0);
+        assert!(buffer.version() > version);
+        assert_eq!(tracking(&buffer), -loose);
+
+        buffer.set_optical_tracking(false);
+        assert_eq!(tracking(&buffer), 0);
+    }
+
+    #[test]
+    fn metrics_policy_should_select_the_vertical_metrics_everywhere() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let raw = font.raw_vertical_metrics();
+        // DejaVu Sans has both sets of metrics and they differ
+        let typo = raw.typo.expect("typo metrics of DejaVuSans.ttf");
+        let hhea = raw.hhea.expect("hhea metrics of DejaVuSans.ttf");
+        assert!(typo != hhea);
+        assert_eq!(fonts.metrics_policy(), MetricsPolicy::Hhea);
+        assert_eq!(font.metrics().ascent, hhea.ascent);
+
+        let mut events = Vec::new();
+        fonts.drain_events(&mut events);
+        fonts.set_metrics_policy(MetricsPolicy::Typo);
+        let metrics = font.metrics();
+        assert_eq!((metrics.ascent, metrics.descent, metrics.line_gap), (typo.ascent, typo.descent, typo.line_gap));
+        let scaled = font.metrics_for_size(20.0);
+        assert_eq!(scaled.ascent, typo.ascent * font.scale_for_px(20.0));
+
+        events.clear();
+        fonts.drain_events(&mut events);
+        assert!(events.contains(&FontsEvent::MetricsChanged));
+
+        fonts.set_metrics_policy(MetricsPolicy::Auto);
+        let expected = if raw.use_typo_metrics { typo } else { hhea };
+        assert_eq!(font.metrics().ascent, expected.ascent);
+        let sized = font.at_size(32.0);
+        let scale = sized.font().units_per_em() as f32 / font.units_per_em() as f32;
+        assert!((sized.font().metrics().ascent - expected.ascent * scale).abs() < 0.01);
+    }
+
+    #[test]
+    fn decoration_quads_should_cover_line_content_and_skip_ink() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let buffer = font.create_buffer("gypsy jumping  \nquay", None, [0, 0, 0, 255].into());
+        let layout = buffer.layout(&LayoutOptions::default());
+        let metrics = font.metrics_for_size(buffer.size_px());
+
+        let underline = layout.decoration_quads(Decoration::Underline, &DecorationOptions::default());
+        assert_eq!(underline.len(), layout.lines().len());
+        for (quad, line) in underline.iter().zip(layout.lines()) {
+            assert!((quad.min_x - line.x_offset).abs() < 0.001);
+            assert!((quad.width() - line.width).abs() < 0.001);
+            assert!(quad.max_y <= line.baseline_y + 0.001);
+            if metrics.underline_thickness > 0.0 {
+                assert!((quad.height() - metrics.underline_thickness).abs() < 0.001);
+            }
+        }
+
+        let strikethrough = layout.decoration_quads(Decoration::Strikethrough, &DecorationOptions::default());
+        assert!(strikethrough.iter().zip(&underline).all(|(s, u)| s.min_y > u.max_y));
+
+        let skipping = layout.decoration_quads(Decoration::Underline, &DecorationOptions { skip_ink: true, ..DecorationOptions::default() });
+        assert!(skipping.len() >= underline.len());
+        let total = |quads: &[Bounds]| quads.iter().map(|q| q.width()).sum::<f32>();
+        assert!(total(&skipping) <= total(&underline));
+        assert!(skipping.iter().all(|q| q.width() > 0.0));
+    }
+
+    #[test]
+    fn debug_paths_should_outline_lines_glyphs_and_clusters() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let buffer = font.create_buffer("ab c\nd", None, [0, 0, 0, 255].into());
+        let layout = buffer.layout(&LayoutOptions::default());
+        let paths = layout.debug_paths();
+
+        let subpaths = |path: &lyon_path::default::Path| path.iter().filter(|e| match *e {
+            lyon_path::PathEvent::MoveTo(_) => true,
+            _ => false,
+        }).count();
+        let lines = layout.lines().len();
+        assert_eq!(subpaths(&paths.baselines), lines);
+        assert_eq!(subpaths(&paths.line_boxes), lines);
+        assert_eq!(subpaths(&paths.advances), layout.glyphs().len());
+        assert_eq!(subpaths(&paths.cluster_ticks), layout.glyphs().len() + lines);
+        assert!(subpaths(&paths.ink_bounds) >= 4);
+
+        let single = buffer.debug_paths(24.0);
+        assert_eq!(subpaths(&single.advances), layout.glyphs().len());
+    }
+
+    #[test]
+    fn outline_path_should_place_every_drawn_glyph() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let glyph_id = font.glyph_for_char('o').unwrap();
+        let contours = |path: &lyon_path::default::Path| path.iter().filter(|e| match *e {
+            lyon_path::PathEvent::MoveTo(_) => true,
+            _ => false,
+        }).count();
+
+        let o = font.outline_cached(glyph_id, HintingOptions::None).unwrap();
+        let buffer = font.create_buffer("o o", None, [0, 0, 0, 255].into());
+        let path = buffer.outline_path(20.0);
+        assert_eq!(contours(&path), 2 * contours(&o));
+
+        // the second glyph is the first one moved by both advances
+        let mut glyphs = Vec::new();
+        buffer.glyphs(&mut glyphs);
+        let scale = font.scale_for_px(20.0);
+        let shift = (glyphs[0].x_advance + glyphs[1].x_advance) as f32 * scale;
+        let points = path.iter().filter_map(|e| match e {
+            lyon_path::PathEvent::MoveTo(p) => Some(p),
+            _ => None,
+        }).collect::<Vec<_>>();
+        let half = points.len() / 2;
+        for (a, b) in points[..half].iter().zip(&points[half..]) {
+            assert!((b.x - a.x - shift).abs() < 0.01 && (b.y - a.y).abs() < 0.01);
+        }
+
+        let mut builder = lyon_path::default::Path::builder();
+        font.create_buffer("   ", None, [0, 0, 0, 255].into()).outline_into(&mut builder, 20.0);
+        assert_eq!(contours(&lyon_path::builder::FlatPathBuilder::build(builder)), 0);
+    }
+
+    #[test]
+    fn outline_dyn_should_match_outline() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let glyph_id = font.glyph_for_char('g').unwrap();
+
+        let mut direct = ::lyon_path::default::Path::builder();
+        font.outline(glyph_id, HintingOptions::None, &mut direct).unwrap();
+        let mut dynamic = ::lyon_path::default::Path::builder();
+        {
+            let builder: &mut dyn outline_builder::OutlineBuilder = &mut dynamic;
+            font.outline_dyn(glyph_id, HintingOptions::None, builder).unwrap();
+        }
+        let events = |builder: ::lyon_path::default::Builder| ::lyon_path::builder::FlatPathBuilder::build(builder).iter().collect::<Vec<_>>();
+        assert_eq!(events(direct), events(dynamic));
+    }
+
+    #[test]
+    fn subset_should_shape_used_text_like_the_original() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let text = "Subset fonts, 0123!";
+        let buffer = font.create_buffer(text, None, [0, 0, 0, 255].into());
+
+        let used = fonts.used_glyphs();
+        let glyph_ids = used[&font.weak_ref().id()].iter().cloned().collect::<Vec<_>>();
+        let bytes = font.subset(&glyph_ids).unwrap();
+        assert!(bytes.len() < font.copy_font_data().unwrap().len());
+
+        let subset = fonts.font_from_bytes(bytes, 0).unwrap();
+        let shaped = subset.create_buffer(text, None, [0, 0, 0, 255].into());
+        let advances = |buffer: &Buffer| {
+            let mut glyphs = Vec::new();
+            buffer.glyphs(&mut glyphs);
+            glyphs.iter().map(|g| (g.id, g.x_advance, g.x_offset)).collect::<Vec<_>>()
+        };
+        assert_eq!(advances(&shaped), advances(&buffer));
+        assert_eq!(font.subset(&[font.glyph_count()]), Err(SubsetError::GlyphOutOfRange(font.glyph_count())));
+    }
+
+    #[test]
+    fn glyph_info_should_mark_cluster_starts_and_count_ligature_components() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let buffer = font.create_buffer("office e\u{301}", None, [0, 0, 0, 255].into());
+        let mut glyphs = Vec::new();
+        buffer.glyphs(&mut glyphs);
+
+        let infos = (0..glyphs.len()).map(|i| buffer.glyph_info(i).unwrap()).collect::<Vec<_>>();
+        assert_eq!(buffer.glyph_info(glyphs.len()), None);
+        for (i, (glyph, info)) in glyphs.iter().zip(&infos).enumerate() {
+            assert_eq!(info.is_cluster_start, i == 0 || glyphs[i - 1].cluster != glyph.cluster);
+            assert!(info.component_count >= 1);
+            assert!(info.is_ligature || info.component_count == 1);
+        }
+        // the "ffi" ligature, if the font has one, stands for three graphemes
+        if let Some(info) = glyphs.iter().zip(&infos).find(|&(g, _)| g.cluster == 1 && g.len == 3).map(|(_, info)| info) {
+            if info.is_ligature {
+                assert_eq!(info.component_count, 3);
+            }
+        }
+    }
+
+    #[test]
+    fn pen_positions_should_match_scaled_glyphs() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let buffer = font.create_buffer("Typewriter", None, [0, 0, 0, 255].into());
+        let mut glyphs = Vec::new();
+        buffer.glyphs(&mut glyphs);
+        let mut scaled = Vec::new();
+        buffer.glyphs_scaled(20.0, None, &mut scaled);
+
+        for (i, (glyph, placed)) in glyphs.iter().zip(&scaled).enumerate() {
+            assert_eq!(buffer.glyph_at(i), Some(*glyph));
+            let (x, _) = buffer.pen_position_at(i, 20.0).unwrap();
+            assert!((x + glyph.x_offset as f32 * font.scale_for_px(20.0) - placed.x).abs() < 0.001);
+        }
+        assert_eq!(buffer.glyph_at(glyphs.len()), None);
+        let end = buffer.pen_position_at(glyphs.len(), 20.0).unwrap();
+        assert!((end.0 - buffer.advance_width_px(20.0)).abs() < 0.01);
+        assert_eq!(buffer.pen_position_at(glyphs.len() + 1, 20.0), None);
+
+        buffer.set_text("Ty");
+        assert_eq!(buffer.pen_position_at(3, 20.0), None);
+    }
+
+    #[test]
+    fn single_direction_text_should_be_one_visual_run() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let buffer = font.create_buffer("plain text", None, [0, 0, 0, 255].into());
+
+        let runs = buffer.visual_runs().collect::<Vec<_>>();
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0].glyphs, 0..buffer.glyph_count());
+        assert_eq!(runs[0].byte_range, 0..10);
+        assert_eq!((runs[0].direction, runs[0].script, runs[0].font_id), (ShapedDirection::LeftToRight, *b"Latn", font.weak_ref().id()));
+    }
+
+    #[test]
+    #[cfg(not(feature = "simple-shaping"))]
+    fn mixed_direction_text_should_have_visual_runs_left_to_right() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let buffer = font.create_buffer("abc שלום def", None, [0, 0, 0, 255].into());
+
+        let runs = buffer.visual_runs().collect::<Vec<_>>();
+        assert_eq!(runs.iter().map(|run| run.direction).collect::<Vec<_>>(),
+            vec![ShapedDirection::LeftToRight, ShapedDirection::RightToLeft, ShapedDirection::LeftToRight]);
+        assert_eq!(runs.iter().map(|run| run.glyphs.len()).sum::<usize>(), buffer.glyph_count());
+        assert_eq!(runs[1].script, *b"Hebr");
+    }
+
+    #[test]
+    fn highlights_should_split_visual_runs_without_reshaping() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let buffer = font.create_buffer("let x = 1;", None, [0, 0, 0, 255].into());
+        let (version, highlight_version) = (buffer.version(), buffer.highlight_version());
+
+        buffer.set_highlights(&[(0..3, 1), (8..9, 2)]);
+        assert_eq!(buffer.version(), version);
+        assert!(buffer.highlight_version() > highlight_version);
+
+        let runs = buffer.visual_runs().collect::<Vec<_>>();
+        assert_eq!(runs.iter().map(|run| (run.byte_range.clone(), run.highlight)).collect::<Vec<_>>(),
+            vec![(0..3, Some(1)), (3..8, None), (8..9, Some(2)), (9..10, None)]);
+
+        let mut glyphs = Vec::new();
+        buffer.glyphs_scaled(None, None, &mut glyphs);
+        assert_eq!(glyphs[0].highlight, Some(1));
+        assert_eq!(glyphs.last().unwrap().highlight, None);
+
+        buffer.set_highlights(&[]);
+        assert_eq!(buffer.visual_runs().count(), 1);
+    }
+
+    #[test]
+    fn case_should_change_the_shaped_text_but_not_the_text() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let buffer = font.create_buffer("izin ver", None, [0, 0, 0, 255].into());
+        let version = buffer.version();
+
+        buffer.set_case(Case::Upper, Some("tr"));
+        assert_eq!(buffer.case(), Case::Upper);
+        assert_eq!(buffer.shaped_text(), "İZİN VER");
+        assert_eq!(buffer.text(), "izin ver");
+        assert!(buffer.version() > version);
+
+        let mut glyphs = Vec::new();
+        buffer.glyphs(&mut glyphs);
+        let offsets = glyphs.iter().map(|g| g.byte_offset).collect::<HashSet<_>>();
+        assert!(offsets.iter().all(|&offset| "izin ver".is_char_boundary(offset as usize)));
+        assert_eq!(buffer.source_offset("İZİN ".len()), "izin ".len());
+
+        buffer.set_case(Case::None, None);
+        assert_eq!(buffer.shaped_text(), "izin ver");
+    }
+
+    #[test]
+    fn default_families_should_choose_the_ui_font() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        assert!(fonts.default_ui_font().is_some());
+
+        fonts.set_default_families(&[FamilyName::Title("No Such Family".to_string()), FamilyName::Title(font.family_name())]);
+        assert_eq!(fonts.default_ui_font().map(|f| f.family_name()), Some(font.family_name()));
+
+        fonts.set_default_families(&[]);
+        assert!(fonts.default_ui_font().is_some());
+    }
+
+    #[test]
+    fn interned_buffers_should_be_shared_until_changed() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        fonts.set_buffer_interning(true);
+
+        let a = font.create_buffer("100", None, [0, 0, 0, 255].into());
+        let b = font.create_buffer("100", None, [0, 0, 0, 255].into());
+        let c = font.create_buffer("250", None, [0, 0, 0, 255].into());
+        let d = font.create_buffer("100", None, [255, 0, 0, 255].into());
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+        assert_ne!(a, d);
+
+        b.set_text("CRIT");
+        assert_ne!(a, b);
+        assert_eq!(a.text(), "100");
+        assert_eq!(b.text(), "CRIT");
+
+        let e = font.create_buffer("100", None, [0, 0, 0, 255].into());
+        assert_eq!(a, e);
+        let translation = na::convert::<_, na::Projective3<f32>>(na::Translation3::new(10.0, 0.0, 0.0));
+        e.set_transform(Some(translation));
+        assert_ne!(a, e);
+        assert_eq!(a.transform(), None);
+        assert_eq!(e.transform(), Some(translation));
+
+        let id = a.id();
+        a.set_text("101");
+        assert_eq!(a.id(), id);
+        assert_ne!(font.create_buffer("100", None, [0, 0, 0, 255].into()), a);
+    }
+
+    #[cfg(feature = "debug-origins")]
+    #[test]
+    fn interned_buffers_should_stay_shared_with_debug_origins() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        fonts.set_buffer_interning(true);
+        let mut events = Vec::new();
+        fonts.drain_events(&mut events);
+
+        let a = font.create_buffer("100", None, [0, 0, 0, 255].into());
+        let line = line!() - 1;
+        let b = font.create_buffer("100", None, [0, 0, 0, 255].into());
+        assert_eq!(a.id(), b.id());
+
+        let dump = fonts.debug_dump();
+        assert_eq!(dump.len(), 1);
+        assert_eq!(dump[0].count, 2);
+        assert!(dump[0].origin.as_ref().map_or(false, |origin| origin.contains(&format!("mod.rs:{}:", line))), "{:?}", dump[0].origin);
+
+        events.clear();
+        fonts.drain_events(&mut events);
+        assert_eq!(events.iter().filter(|e| if let FontsEvent::BufferCreated(_) = e { true } else { false }).count(), 1);
+    }
+
+    #[test]
+    fn interned_buffers_should_keep_ids_and_clones_across_a_split() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        fonts.set_buffer_interning(true);
+
+        // clones do not come from the intern table and see the change
+        let a = font.create_buffer("100", None, [0, 0, 0, 255].into());
+        let clone = a.clone();
+        let a_ref = a.weak_ref();
+        clone.set_text("250");
+        assert_eq!(a, clone);
+        assert_eq!(a.text(), "250");
+        assert_eq!(fonts.upgrade_buffer(a_ref), Some(a.clone()));
+
+        // the changed handle keeps its id, the other handle and its clone move to a copy
+        let b = font.create_buffer("CRIT", None, [0, 0, 0, 255].into());
+        let c = font.create_buffer("CRIT", None, [0, 0, 0, 255].into());
+        let c_clone = c.clone();
+        assert_eq!(b, c);
+        let (id, b_ref) = (b.id(), b.weak_ref());
+        b.set_text("MISS");
+        assert_eq!(b.id(), id);
+        assert_eq!(fonts.upgrade_buffer(b_ref).map(|buffer| buffer.text()), Some("MISS".to_string()));
+        assert_ne!(c, b);
+        assert_eq!(c, c_clone);
+        assert_eq!((c.text(), c_clone.text()), ("CRIT".to_string(), "CRIT".to_string()));
+        assert_eq!(font.create_buffer("CRIT", None, [0, 0, 0, 255].into()), c);
+
+        // the references moved with the handles
+        drop((b, c, c_clone));
+        assert_eq!(fonts.stats().buffers, 1);
+    }
+
+    #[test]
+    fn buffers_should_not_be_shared_without_interning() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+
+        let a = font.create_buffer("100", None, [0, 0, 0, 255].into());
+        let b = font.create_buffer("100", None, [0, 0, 0, 255].into());
+        assert_ne!(a, b);
+
+        fonts.set_buffer_interning(true);
+        let c = font.create_buffer("100", None, [0, 0, 0, 255].into());
+        fonts.set_buffer_interning(false);
+        assert_ne!(font.create_buffer("100", None, [0, 0, 0, 255].into()), c);
+    }
+
+    #[test]
+    fn visible_range_should_shape_only_the_paragraphs_around_it() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let text = (0..2000).map(|i| format!("log line {}\n", i)).collect::<String>();
+        let buffer = font.create_buffer(text.as_str(), None, [0, 0, 0, 255].into());
+        let full_count = buffer.glyph_count();
+        let mut glyphs = Vec::new();
+        assert!(!buffer.measure(&mut glyphs).unwrap().estimated);
+
+        let middle = text.len() / 2;
+        buffer.set_visible_range(middle..middle + 100);
+        let window = buffer.shaped_window().expect("window");
+        assert!(window.start <= middle && middle + 100 <= window.end);
+        assert!(window.start == 0 || text[..window.start].ends_with('\n'));
+        assert!(buffer.glyph_count() < full_count);
+        glyphs.clear();
+        buffer.glyphs(&mut glyphs);
+        assert!(glyphs.iter().all(|g| window.start <= g.byte_offset as usize && (g.byte_offset as usize) < window.end));
+        assert_eq!(glyphs.first().map(|g| g.byte_offset as usize), Some(window.start));
+
+        let version = buffer.version();
+        buffer.set_visible_range(middle + 10..middle + 110);
+        assert_eq!(buffer.version(), version);
+        assert_eq!(buffer.shaped_window(), Some(window.clone()));
+
+        let measured = buffer.measure(&mut glyphs).unwrap();
+        assert!(measured.estimated);
+
+        buffer.set_visible_range(0..10);
+        assert_eq!(buffer.shaped_window().map(|w| w.start), Some(0));
+        buffer.clear_visible_range();
+        assert_eq!(buffer.shaped_window(), None);
+        assert_eq!(buffer.glyph_count(), full_count);
+    }
+
+    #[test]
+    fn editable_buffer_should_reshape_only_edited_paragraphs() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let mut editable = font.create_editable_buffer("first line\nsecond line\r\nthird", None, [0, 0, 0, 255].into());
+        assert_eq!(editable.chunks().len(), 3);
+        let versions = editable.chunks().iter().map(Buffer::version).collect::<Vec<_>>();
+
+        editable.insert(11, "the ");
+        assert_eq!(editable.text(), "first line\nthe second line\r\nthird");
+        assert_eq!(editable.chunks()[0].version(), versions[0]);
+        assert_ne!(editable.chunks()[1].version(), versions[1]);
+        assert_eq!(editable.chunks()[2].version(), versions[2]);
+        assert_eq!(editable.chunk_range(2), 28..33);
+
+        editable.splice(10..15, " ");
+        assert_eq!(editable.text(), "first line second line\r\nthird");
+        assert_eq!(editable.chunks().len(), 2);
+        editable.insert(editable.len(), "\n");
+        assert_eq!(editable.chunks().len(), 3);
+        assert_eq!(editable.chunks()[2].text(), "");
+
+        editable.splice(0..5, "1st\r");
+        editable.insert(4, "\n");
+        assert_eq!(editable.text(), "1st\r\n line second line\r\nthird\n");
+        assert_eq!(editable.chunks().iter().map(Buffer::text).collect::<Vec<_>>(), vec!["1st\r\n", " line second line\r\n", "third\n", ""]);
+
+        let plain = font.create_buffer(editable.text(), None, [0, 0, 0, 255].into());
+        let (mut stitched, mut whole) = (Vec::new(), Vec::new());
+        editable.glyphs(&mut stitched);
+        plain.glyphs(&mut whole);
+        assert_eq!(stitched.iter().map(|g| (g.id, g.cluster)).collect::<Vec<_>>(), whole.iter().map(|g| (g.id, g.cluster)).collect::<Vec<_>>());
+        assert_eq!(editable.next_cursor(3), 5);
+        assert_eq!(editable.prev_cursor(5), 3);
+    }
+
+    #[test]
+    fn editable_buffer_layout_should_match_the_whole_text_layout() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let text = "a paragraph long enough to wrap\n\nanother one that wraps as well\nend";
+        let editable = font.create_editable_buffer(text, None, [0, 0, 0, 255].into());
+        let plain = font.create_buffer(text, None, [0, 0, 0, 255].into());
+
+        for &origin in &[Origin::BottomLeftYUp, Origin::TopLeftYDown] {
+            fonts.set_origin(origin);
+            let options = LayoutOptions { max_width: Some(80.0), paragraph_spacing: 3.0, ..LayoutOptions::default() };
+            let (stitched, whole) = (editable.layout(&options), plain.layout(&options));
+
+            assert_eq!(stitched.lines().len(), whole.lines().len());
+            for (a, b) in stitched.lines().iter().zip(whole.lines()) {
+                assert_eq!((a.glyphs.clone(), a.bytes.clone(), a.line_break), (b.glyphs.clone(), b.bytes.clone(), b.line_break));
+                assert!((a.baseline_y - b.baseline_y).abs() < 1e-3);
+            }
+            assert!((stitched.height() - whole.height()).abs() < 1e-3);
+            let index = text.find("another").unwrap() + 2;
+            assert!((stitched.caret_for_index(index).baseline_y - whole.caret_for_index(index).baseline_y).abs() < 1e-3);
+            let caret = whole.caret_for_index(index);
+            assert_eq!(stitched.hit_test(caret.x + 0.5, caret.baseline_y).line, whole.hit_test(caret.x + 0.5, caret.baseline_y).line);
+        }
+    }
+
+    #[test]
+    fn user_data_should_be_shared_by_clones_and_dropped_with_the_buffer() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let dropped = Rc::new(Cell::new(false));
+        struct Handle(u32, Rc<Cell<bool>>);
+        impl Drop for Handle {
+            fn drop(&mut self) {
+                self.1.set(true);
+            }
+        }
+
+        let buffer = font.create_buffer("label", None, [0, 0, 0, 255].into());
+        let buffer_ref = buffer.weak_ref();
+        assert_eq!(buffer.user_data::<u32>(), None);
+        buffer.set_user_data(Box::new(7u32));
+        assert_eq!(buffer.clone().user_data::<u32>(), Some(7));
+        assert_eq!(buffer.user_data::<i64>(), None);
+        assert_eq!(fonts.user_data_for::<u32>(buffer_ref), Some(7));
+
+        let taken = buffer.take_user_data().expect("taken");
+        assert_eq!(taken.downcast_ref::<u32>(), Some(&7));
+        assert_eq!(buffer.user_data::<u32>(), None);
+
+        buffer.set_user_data(Box::new(Handle(3, dropped.clone())));
+        assert_eq!(buffer.with_user_data(|handle: &Handle| handle.0), Some(3));
+        drop(buffer);
+        assert!(dropped.get());
+        assert_eq!(fonts.user_data_for::<u32>(buffer_ref), None);
+    }
+
+    #[test]
+    fn match_details_should_be_kept_for_found_fonts() {
+        let fonts = test_fonts();
+        let mut semibold = Properties::new();
+        semibold.weight(Weight::SEMIBOLD);
+        let found = fonts.find_best_match_detailed(&[FamilyName::SansSerif], &semibold).unwrap();
+
+        assert_eq!(found.details.requested.weight, Weight::SEMIBOLD);
+        assert_eq!(found.details.quality.exact_weight, found.details.matched.weight == Weight::SEMIBOLD);
+        assert_eq!(fonts.match_quality(&found.font), Some(found.details.quality));
+
+        // answered from the query cache
+        let again = fonts.find_best_match(&[FamilyName::SansSerif], &semibold).unwrap();
+        assert_eq!(fonts.match_details(&again).unwrap().requested.weight, Weight::SEMIBOLD);
+    }
+
+    #[test]
+    fn cluster_advances_should_add_up_in_text_order() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let buffer = font.create_buffer("12.50 ש\u{5B8}לוֹם e\u{301}", None, [0, 0, 0, 255].into());
+
+        let mut clusters = vec![(0..0, 7)];
+        buffer.cluster_advances(&mut clusters);
+        assert_eq!(clusters.remove(0), (0..0, 7));
+
+        assert_eq!(clusters.iter().map(|&(_, advance)| advance).sum::<i32>(), buffer.advance_width());
+        assert_eq!(clusters[0].0, 0..1);
+        assert_eq!(clusters.last().unwrap().0.end, buffer.text().len());
+        for pair in clusters.windows(2) {
+            assert_eq!(pair[0].0.end, pair[1].0.start);
+        }
+
+        // width up to the decimal point
+        let point = clusters.iter().position(|&(ref range, _)| range.start == 2).unwrap();
+        let before = clusters[..point].iter().map(|&(_, advance)| advance).sum::<i32>();
+        let mut glyphs = Vec::new();
+        buffer.glyphs(&mut glyphs);
+        assert_eq!(before, glyphs[..2].iter().map(|g| g.x_advance).sum::<i32>());
+    }
+
+    #[test]
+    fn sanitized_buffers_should_shape_fixed_text() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let buffer = font.create_buffer("\u{301}a\u{2067}b", None, [0, 0, 0, 255].into());
+        assert_eq!(buffer.validate(), vec![
+            TextWarning::LoneMark { byte_offset: 0, mark: '\u{301}' },
+            TextWarning::UnclosedBidiControl { byte_offset: 3, control: '\u{2067}' },
+        ]);
+
+        buffer.set_sanitize(true);
+        assert_eq!(buffer.shaped_text(), "\u{25CC}\u{301}a\u{2067}b\u{2069}");
+        assert_eq!(buffer.text(), "\u{301}a\u{2067}b");
+        assert_eq!(buffer.source_offset(5), 2);
+        // warnings are about the text as given
+        assert_eq!(buffer.validate().len(), 2);
+
+        buffer.set_sanitize(false);
+        assert_eq!(buffer.shaped_text(), buffer.text());
+    }
+
+    #[test]
+    fn profiler_should_time_shaping_and_rasterization() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        fonts.set_shaping_cache_capacity(0);
+
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let received = events.clone();
+        fonts.set_profiler(move |event| received.borrow_mut().push(event));
+
+        let buffer = font.create_buffer("timed", None, [0, 0, 0, 255].into());
+        let mut glyphs = Vec::new();
+        buffer.glyphs(&mut glyphs);
+        font.rasterize_glyph(glyphs[0].id, 16.0, HintingOptions::None, (0.0, 0.0));
+        fonts.clear_profiler();
+        font.create_buffer("untimed", None, [0, 0, 0, 255].into());
+
+        let events = events.borrow();
+        let shaped = events.iter().find(|event| event.operation == ProfiledOperation::Shape).expect("shape event");
+        assert_eq!((shaped.font_id, shaped.text_len, shaped.glyph_count), (font.weak_ref().id(), 5, buffer.glyph_count()));
+        assert!(events.iter().any(|event| event.operation == ProfiledOperation::Raster));
+
+        let stats = fonts.stats();
+        assert_eq!(stats.shaping.calls, events.iter().filter(|event| event.operation == ProfiledOperation::Shape).count() as u64);
+        assert!(stats.rasterization.calls >= 1);
+    }
+
+    #[test]
+    #[cfg(not(feature = "simple-shaping"))]
+    fn right_to_left_glyphs_should_be_in_visual_order() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let buffer = font.create_buffer("שלום", None, [0, 0, 0, 255].into());
+
+        let mut glyphs = Vec::new();
+        buffer.glyphs(&mut glyphs);
+        assert_eq!(glyphs.iter().map(|g| g.byte_offset).collect::<Vec<_>>(), vec![6, 4, 2, 0]);
+
+        let mut logical = Vec::new();
+        let visual_indices = buffer.glyphs_logical(&mut logical);
+        assert_eq!(logical.iter().map(|g| g.byte_offset).collect::<Vec<_>>(), vec![0, 2, 4, 6]);
+        assert_eq!(visual_indices, vec![3, 2, 1, 0]);
+    }
+
+    #[test]
+    #[cfg(not(feature = "simple-shaping"))]
+    fn mixed_direction_glyphs_should_reverse_only_right_to_left_runs() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let buffer = font.create_buffer("ab שלום cd", None, [0, 0, 0, 255].into());
+
+        let mut glyphs = Vec::new();
+        buffer.glyphs(&mut glyphs);
+        assert_eq!(glyphs.iter().map(|g| g.byte_offset).collect::<Vec<_>>(), vec![0, 1, 2, 9, 7, 5, 3, 11, 12, 13]);
+
+        let mut logical = Vec::new();
+        let visual_indices = buffer.glyphs_logical(&mut logical);
+        assert_eq!(logical.iter().map(|g| g.byte_offset).collect::<Vec<_>>(), vec![0, 1, 2, 3, 5, 7, 9, 11, 12, 13]);
+        assert_eq!(visual_indices, vec![0, 1, 2, 6, 5, 4, 3, 7, 8, 9]);
+        assert!(visual_indices.iter().zip(&logical).all(|(&i, glyph)| glyphs[i] == *glyph));
+    }
+
+    #[test]
+    fn size_bucketing_should_share_entries_of_close_sizes() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let glyph_id = font.glyph_for_char('o').unwrap();
+
+        fonts.set_size_bucketing(2.0);
+        assert_eq!(fonts.size_bucketing(), 2.0);
+        for &px_size in &[15.5, 16.0, 16.9] {
+            font.glyph_sdf(glyph_id, px_size, 2, 4.0);
+            let _ = font.outline_at_size(glyph_id, px_size, HintingOptions::None, &mut ::lyon_path::default::Path::builder());
+        }
+        let sized = font.at_size(16.9);
+        assert_eq!(sized.px_size(), 16.0);
+        let stats = fonts.stats();
+        assert_eq!((stats.sdf_entries, stats.sized_outline_entries, stats.sized_fonts), (1, 1, 1));
+
+        fonts.set_size_bucketing(0.25);
+        let stats = fonts.stats();
+        assert_eq!((stats.sdf_entries, stats.sized_outline_entries), (0, 0));
+    }
+
+    #[test]
+    fn measure_str_should_match_a_buffer_without_creating_one() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let mut events = Vec::new();
+        fonts.drain_events(&mut events);
+        let buffers = fonts.stats().buffers;
+
+        let measure = font.measure_str("Measured label", 20.0, &[]);
+        fonts.drain_events(&mut events);
+        assert!(events.is_empty());
+        assert_eq!(fonts.stats().buffers, buffers);
+
+        let shape_calls = fonts.shaping_cache_stats().shape_calls;
+        let buffer = font.create_buffer("Measured label", None, [0, 0, 0, 255].into());
+        assert_eq!(fonts.shaping_cache_stats().shape_calls, shape_calls);
+        assert_eq!(measure.glyph_count, buffer.glyph_count());
+        assert!((measure.width - buffer.advance_width_px(20.0)).abs() < 1e-3);
+        assert!(measure.ascent > 0.0 && measure.descent < 0.0);
+    }
+
+    #[test]
+    fn sized_spans_should_share_the_baseline_and_scale_their_glyphs() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        fonts.set_default_size(12.0);
+        let text = SpannedText::new().push("oo", &font).push_sized("oo", &font, 24.0);
+        let buffer = fonts.create_spanned_buffer(&text, None, [0, 0, 0, 255].into()).expect("spanned buffer");
+        assert_eq!((buffer.span_scale(0), buffer.span_scale(1)), (1.0, 2.0));
+
+        let spans = buffer.span_metrics(None);
+        assert_eq!((spans[0].px_size, spans[1].px_size), (12.0, 24.0));
+        assert!((spans[1].ascent - 2.0 * spans[0].ascent).abs() < 1e-3);
+
+        let mut glyphs = Vec::new();
+        buffer.glyphs_scaled(12.0, None, &mut glyphs);
+        let small = glyphs[1].x - glyphs[0].x;
+        let large = glyphs[3].x - glyphs[2].x;
+        assert!((large - 2.0 * small).abs() <= 1.0);
+        assert!(glyphs.iter().all(|g| g.y == 0.0));
+
+        let layout = buffer.layout(&LayoutOptions::default());
+        assert_eq!(layout.lines().len(), 1);
+        assert!((layout.line_height() - font.metrics_for_size(24.0).line_height).abs() < 1e-3);
+        let line = layout.line_glyphs(0);
+        assert!(line.iter().all(|g| g.y == line[0].y));
+
+        let measurement = buffer.measure(&mut Vec::new()).expect("measurement");
+        assert!((measurement.ascent - 2.0 * font.metrics().ascent).abs() < 1e-3);
+
+        let hit = buffer.hit_test_x(glyphs[3].x + large * 0.75, None).expect("hit");
+        assert_eq!(hit.byte_index, 3);
+
+        let mut atlas = GlyphAtlas::new(256, 8);
+        let mut quads = Vec::new();
+        layout_quads(&buffer, None, &mut atlas, false, &mut quads);
+        if quads.len() == 4 {
+            let height = |q: &GlyphQuad| (q.y1 - q.y0).abs();
+            assert!((height(&quads[2]) - 2.0 * height(&quads[0])).abs() <= 2.0);
+        }
+    }
+
+    #[test]
+    fn adjusted_spans_should_move_their_baseline() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        fonts.set_default_size(12.0);
+        let text = SpannedText::new().push("oo", &font).push_adjusted("oo", &font, 0.5, 0.25);
+        let buffer = fonts.create_spanned_buffer(&text, None, [0, 0, 0, 255].into()).expect("spanned buffer");
+        assert_eq!(buffer.span_scale(1), 0.5);
+
+        let mut glyphs = Vec::new();
+        buffer.glyphs_scaled(12.0, None, &mut glyphs);
+        assert_eq!((glyphs[0].y, glyphs[1].y), (0.0, 0.0));
+        assert!(glyphs[2..].iter().all(|g| (g.y - 3.0).abs() < 0.1));
+
+        let spans = buffer.span_metrics(None);
+        let ascent = font.metrics_for_size(6.0).ascent;
+        assert!((spans[1].ascent - (ascent + 3.0)).abs() < 1e-3);
+
+        let mut raw = Vec::new();
+        buffer.glyphs(&mut raw);
+        let shifted = raw[2].y_offset;
+        let layout = buffer.layout(&LayoutOptions::default());
+        let line = layout.line_glyphs(0);
+        assert!((line[0].y - line[2].y).abs() < 1e-3 && line[2].y_offset != line[0].y_offset);
+
+        let measurement = buffer.measure(&mut Vec::new()).expect("measurement");
+        let metrics = font.metrics();
+        assert!((measurement.ascent - metrics.ascent.max(metrics.ascent * 0.5 + shifted as f32)).abs() < 1.0);
+    }
+
+    /// OS/2 table of a fixture font raised to version 2 with x and cap heights, which the
+    /// version 1 tables of the DejaVu fixtures lack.
+    fn os2_with_heights(file: &str, x_height: i16, cap_height: i16) -> Vec<u8> {
+        let data = ::std::fs::read(fixture_path(file)).unwrap();
+        let tables = sfnt::tables(&data, 0).unwrap();
+        let mut os2 = tables.iter().find(|&&(tag, _)| tag == *b"OS/2").unwrap().1[..86].to_vec();
+        os2[0..2].copy_from_slice(&2u16.to_be_bytes());
+        os2.extend_from_slice(&x_height.to_be_bytes());
+        os2.extend_from_slice(&cap_height.to_be_bytes());
+        // default char, break char and max context
+        os2.extend_from_slice(&[0, 0, 0, 32, 0, 2]);
+        os2
+    }
+
+    /// DejaVu Serif with DejaVu Sans as fallback, which has Coptic letters the primary font
+    /// lacks. The x-heights are those of their glyphs; both fonts have a cap height of 1493,
+    /// so the fallback gets a lower one.
+    fn font_chain_fixture(fonts: &Fonts) -> FontChain {
+        let serif = patched_fixture("DejaVuSerif.ttf", vec![(*b"OS/2", os2_with_heights("DejaVuSerif.ttf", 1063, 1493))]);
+        let sans = patched_fixture("DejaVuSans.ttf", vec![(*b"OS/2", os2_with_heights("DejaVuSans.ttf", 1120, 1400))]);
+        let primary = fonts.font_from_bytes(serif, 0).unwrap();
+        let fallback = fonts.font_from_bytes(sans, 0).unwrap();
+        FontChain::new(&primary).with_fallback(&fallback)
+    }
+
+    #[test]
+    fn font_chain_factors_should_follow_harmonization_and_overrides() {
+        let fonts = test_fonts();
+        let mut chain = font_chain_fixture(&fonts);
+        let (primary, fallback) = (chain.primary().metrics(), chain.fallbacks()[0].metrics());
+        assert_eq!((primary.x_height, primary.cap_height), (1063.0, 1493.0));
+        assert_eq!((fallback.x_height, fallback.cap_height), (1120.0, 1400.0));
+        assert_eq!(chain.harmonization_factors(), vec![HarmonizationFactor::default()]);
+
+        let factor = |chain: &mut FontChain, harmonization| {
+            chain.set_harmonization(harmonization);
+            chain.harmonization_factors()[0]
+        };
+        let x_height = factor(&mut chain, Harmonize::MatchXHeight);
+        assert!((x_height.scale - 1063.0 / 1120.0).abs() < 1e-5 && x_height.baseline_shift == 0.0);
+        let cap_height = factor(&mut chain, Harmonize::MatchCapHeight);
+        assert!((cap_height.scale - 1493.0 / 1400.0).abs() < 1e-5 && cap_height.baseline_shift == 0.0);
+        // the families share their ascent and descent
+        let em = factor(&mut chain, Harmonize::MatchEm);
+        assert!((em.scale - 1.0).abs() < 1e-5 && em.baseline_shift.abs() < 1e-5);
+
+        let overridden = HarmonizationFactor { scale: 0.8, baseline_shift: -0.1 };
+        chain.set_harmonization_factor(0, Some(overridden));
+        chain.set_harmonization(Harmonize::MatchXHeight);
+        assert_eq!(chain.harmonization_factors(), vec![overridden]);
+        chain.set_harmonization_factor(0, None);
+        assert_eq!(chain.harmonization_factors(), vec![x_height]);
+
+        // the primary font covers its own text, so the chain shapes it like the font alone
+        assert_eq!(chain.runs("hello"), vec![(0..5, 0)]);
+        let buffer = chain.create_buffer("hello", None, [0, 0, 0, 255].into());
+        let measure = chain.measure_str("hello", 16.0, &[]);
+        let alone = chain.primary().measure_str("hello", 16.0, &[]);
+        assert_eq!(buffer.span_font(0), *chain.primary());
+        assert!((measure.width - alone.width).abs() < 1e-3 && measure.ascent == alone.ascent);
+        assert!(chain.runs("").is_empty() && chain.create_buffer("", None, [0, 0, 0, 255].into()).is_empty());
+    }
+
+    #[test]
+    fn font_chain_should_shape_runs_of_the_fallback_with_its_factor() {
+        let fonts = test_fonts();
+        let mut chain = font_chain_fixture(&fonts);
+        let (primary, fallback) = (chain.primary().clone(), chain.fallbacks()[0].clone());
+        let text = "x \u{3e3}\u{3e3} x";
+        assert!(!primary.supports_chars("\u{3e3}".chars()).is_complete());
+        assert_eq!(chain.runs(text), vec![(0..2, 0), (2..6, 1), (6..8, 0)]);
+
+        chain.set_harmonization(Harmonize::MatchXHeight);
+        let scale = chain.harmonization_factors()[0].scale;
+        let spanned = chain.spanned(text);
+        let spans = spanned.spans();
+        assert_eq!(spans.iter().map(|s| s.range.clone()).collect::<Vec<_>>(), vec![0..2, 2..6, 6..8]);
+        assert_eq!((spans[0].font == primary, spans[1].font == fallback, spans[2].font == primary), (true, true, true));
+        assert_eq!((spans[0].scale, spans[1].scale, spans[1].baseline_shift), (1.0, scale, 0.0));
+
+        chain.set_harmonization_factor(0, Some(HarmonizationFactor { scale: 0.75, baseline_shift: 0.25 }));
+        let spans = chain.spanned(text).spans().to_vec();
+        assert_eq!((spans[1].scale, spans[1].baseline_shift), (0.75, 0.25));
+
+        let buffer = chain.create_buffer(text, None, [0, 0, 0, 255].into());
+        assert_eq!((buffer.span_font(1), buffer.span_scale(1)), (fallback.clone(), 0.75));
+        let mut glyphs = Vec::new();
+        buffer.glyphs_scaled(16.0, None, &mut glyphs);
+        assert_eq!(glyphs.iter().map(|g| g.span).collect::<Vec<_>>(), vec![0, 0, 1, 1, 2, 2]);
+        assert!(glyphs.iter().all(|g| if g.span == 1 { (g.y - 4.0).abs() < 0.1 } else { g.y == 0.0 }));
+
+        // the fallback run is measured at 12 px and raised by 4
+        let measure = chain.measure_str(text, 16.0, &[]);
+        let (before, run, after) = (primary.measure_str("x ", 16.0, &[]), fallback.measure_str("\u{3e3}\u{3e3}", 12.0, &[]), primary.measure_str(" x", 16.0, &[]));
+        assert!((measure.width - (before.width + run.width + after.width)).abs() < 1e-3);
+        assert!(run.ascent + 4.0 > before.ascent && (measure.ascent - (run.ascent + 4.0)).abs() < 1e-3);
+        assert_eq!(measure.glyph_count, 6);
+        assert!((glyphs[4].x - (before.width + run.width)).abs() < 0.5, "{:?}", glyphs);
+    }
+
+    #[test]
+    fn diagnostics_handler_should_use_the_fonts() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+
+        let labels = Rc::new(RefCell::new(Vec::new()));
+        {
+            let (fonts, font, labels) = (fonts.clone(), font.clone(), labels.clone());
+            fonts.clone().set_diagnostics(move |d| if let FontsDiagnostic::FeatureDropped { .. } = d {
+                assert!(!fonts.is_locked());
+                labels.borrow_mut().push(font.create_buffer(&d.to_string(), None, [0, 0, 0, 255].into()));
+            });
+        }
+
+        let buffer = font.create_buffer("abc", None, [0, 0, 0, 255].into());
+        buffer.set_features(&[Feature::new(b"kern", 0).with_range(2..3)]);
+        buffer.set_text("a");
+        assert!(!fonts.is_locked());
+        assert_eq!(labels.borrow().len(), 1);
+        assert!(labels.borrow()[0].glyph_count() > 0);
+
+        // the handler holds the fonts
+        fonts.set_diagnostics(|_| {});
+    }
+
+    #[test]
+    fn references_of_other_containers_should_not_resolve() {
+        let (fonts, other) = (test_fonts(), test_fonts());
+        assert!(fonts.ptr_eq(&fonts.clone()) && !fonts.ptr_eq(&other));
+        assert!(fonts.same_container(&fonts.clone()) && !fonts.same_container(&other));
+        assert!(fonts.container_token() != other.container_token());
+        let font = test_font(&fonts);
+        let other_font = test_font(&other);
+        assert_eq!(font.id, other_font.id);
+
+        let buffer = font.create_buffer("abc", None, [0, 0, 0, 255].into());
+        assert!(fonts.font_alive(font.weak_ref()) && !other.font_alive(font.weak_ref()));
+        assert_eq!(fonts.glyph_count(buffer.weak_ref()), Some(3));
+        assert_eq!((other.glyph_count(buffer.weak_ref()), other.buffer_version(buffer.weak_ref())), (None, None));
+        assert_eq!(fonts.upgrade_buffer(buffer.weak_ref()), Some(buffer.clone()));
+        assert_eq!(fonts.clone().upgrade_font(font.weak_ref()), Some(font.clone()));
+
+        let reported = Rc::new(RefCell::new(Vec::new()));
+        {
+            let reported = reported.clone();
+            other.set_diagnostics(move |d| if let FontsDiagnostic::OtherContainer { .. } = d { reported.borrow_mut().push(d) });
+        }
+        assert_eq!(other.upgrade_font(font.weak_ref()), None);
+        assert_eq!(other.upgrade_buffer(buffer.weak_ref()), None);
+        assert_eq!(*reported.borrow(), vec![
+            FontsDiagnostic::OtherContainer { call: "Fonts::upgrade_font".to_string() },
+            FontsDiagnostic::OtherContainer { call: "Fonts::upgrade_buffer".to_string() },
+        ]);
+        assert!(other.font_alive(other_font.weak_ref()));
+    }
+
+    #[test]
+    fn cjk_text_should_take_two_cells_in_a_monospace_font() {
+        let fonts = test_fonts();
+        let mono = mono_font(&fonts);
+
+        let report = mono.advance_histogram("abcdefghij0123456789".chars());
+        assert!(report.cell_width > 0);
+        assert_eq!(report.histogram[0], (report.cell_width, 20 - report.missing.len() - report.offending.len()));
+
+        let buffer = mono.create_buffer("ab漢字c", None, [0, 0, 0, 255].into());
+        let cells = buffer.cells(report.cell_width);
+        assert_eq!(cells.iter().map(|c| (c.byte_range.clone(), c.column, c.width)).collect::<Vec<_>>(),
+            vec![(0..1, 0, 1), (1..2, 1, 1), (2..5, 2, 2), (5..8, 4, 2), (8..9, 6, 1)]);
+    }
+
+    #[test]
+    fn buffers_should_share_stored_glyph_geometry() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let store = fonts.geometry_store();
+
+        let (first, second) = (font.create_buffer("1203", None, [0, 0, 0, 255].into()), font.create_buffer("3 21", None, [0, 0, 0, 255].into()));
+        let (mut first_instances, mut second_instances) = (Vec::new(), Vec::new());
+        first.geometry_instances(16.0, GlyphRepresentation::Bitmap, &store, &mut first_instances);
+        assert_eq!(store.take_new_entries().len(), first_instances.len());
+        second.geometry_instances(16.0, GlyphRepresentation::Bitmap, &store, &mut second_instances);
+        assert!(store.take_new_entries().is_empty());
+
+        // the space has no bitmap
+        assert_eq!(second_instances.len(), 3);
+        let ids = |instances: &[GeometryInstance]| instances.iter().map(|i| i.geometry).collect::<Vec<_>>();
+        assert_eq!(ids(&second_instances), vec![first_instances[3].geometry, first_instances[1].geometry, first_instances[0].geometry]);
+        assert!(second_instances[1].offset[0] > second_instances[0].offset[0]);
+        assert_eq!(store.len(), 4);
+    }
+
+    #[test]
+    fn base_direction_should_order_runs_and_start_lines() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let black = [0, 0, 0, 255].into();
+        let clusters = |buffer: &Buffer| {
+            let mut glyphs = Vec::new();
+            buffer.glyphs(&mut glyphs);
+            glyphs.iter().map(|g| g.cluster).collect::<Vec<_>>()
+        };
+        let start_offset = |buffer: &Buffer, align| {
+            buffer.layout(&LayoutOptions { px_size: 16.0, max_width: Some(1000.0), align, ..LayoutOptions::default() }).lines()[0].x_offset
+        };
+
+        let arabic = font.create_buffer("مرحبا abc", None, black);
+        assert_eq!(arabic.resolved_base_direction(), ShapedDirection::RightToLeft);
+        assert!(start_offset(&arabic, Align::Start) > 0.0);
+        assert_eq!(start_offset(&arabic, Align::End), 0.0);
+        assert_eq!(start_offset(&arabic, Align::Left), 0.0);
+
+        let latin = font.create_buffer("abc مرحبا", None, black);
+        assert_eq!(latin.resolved_base_direction(), ShapedDirection::LeftToRight);
+        assert_eq!(clusters(&latin)[0], 0);
+        assert_eq!(start_offset(&latin, Align::Start), 0.0);
+        let version = latin.version();
+        latin.set_base_direction(BaseDirection::Rtl);
+        assert!(latin.version() > version);
+        // the latin run follows the arabic one, which holds the space before it
+        assert!(clusters(&latin)[0] >= 3);
+        assert!(clusters(&latin).ends_with(&[0, 1, 2]));
+        assert!(start_offset(&latin, Align::Start) > 0.0);
+
+        let neutral = font.create_buffer("... !?", None, black);
+        for &(base, direction) in &[
+            (BaseDirection::FirstStrong, ShapedDirection::LeftToRight),
+            (BaseDirection::Ltr, ShapedDirection::LeftToRight),
+            (BaseDirection::Rtl, ShapedDirection::RightToLeft),
+        ] {
+            neutral.set_base_direction(base);
+            assert_eq!(neutral.base_direction(), base);
+            assert_eq!(neutral.resolved_base_direction(), direction);
+            assert_eq!(start_offset(&neutral, Align::Start) > 0.0, base == BaseDirection::Rtl);
+        }
+    }
+
+    #[test]
+    fn fonts_should_keep_working_after_their_file_is_removed() {
+        let source = fixture_path("DejaVuSans.ttf");
+        let path = ::std::env::temp_dir().join(format!("fonts-removed-{}.ttf", ::std::process::id()));
+        ::std::fs::copy(&source, &path).unwrap();
+
+        let fonts = test_fonts();
+        let lost = Rc::new(RefCell::new(Vec::new()));
+        {
+            let lost = lost.clone();
+            fonts.set_diagnostics(move |d| if let FontsDiagnostic::SourceLost { .. } = d { lost.borrow_mut().push(d) });
+        }
+        let id = fonts.container.borrow_mut().load_handle(Handle::Path { path: path.clone(), font_index: 0 }, "sans").unwrap();
+        let font = fonts.font_from_id(id).unwrap();
+        fonts.container.borrow_mut().dec_font(id);
+        let glyph_id = font.glyph_for_char('a').unwrap();
+        let before = font.create_buffer("abc", None, [0, 0, 0, 255].into());
+
+        ::std::fs::remove_file(&path).unwrap();
+
+        let after = font.create_buffer("abc", None, [0, 0, 0, 255].into());
+        let (mut a, mut b) = (Vec::new(), Vec::new());
+        before.glyphs(&mut a);
+        after.glyphs(&mut b);
+        assert_eq!(a, b);
+        assert!(font.outline(glyph_id, HintingOptions::None, &mut ::lyon_path::default::Path::builder()).is_ok());
+        assert!(font.outline_at_size(glyph_id, 23.0, HintingOptions::None, &mut ::lyon_path::default::Path::builder()).is_ok());
+
+        // loading the file again gives the loaded font, and the loss is reported once
+        let again = fonts.container.borrow_mut().load_handle(Handle::Path { path: path.clone(), font_index: 0 }, "sans");
+        assert_eq!(again, Some(id));
+        fonts.container.borrow_mut().dec_font(id);
+        fonts.refresh_system_fonts();
+        assert_eq!(*lost.borrow(), vec![FontsDiagnostic::SourceLost { font_id: id, path: path.to_string_lossy().into_owned() }]);
+    }
+
+    #[test]
+    fn retained_fonts_should_be_revived_until_evicted() {
+        let fonts = test_fonts();
+        fonts.set_font_retention(FontRetention::Frames(2));
+        let font = test_font(&fonts);
+        let id = font.id;
+        let mut events = Vec::new();
+        fonts.drain_events(&mut events);
+        events.clear();
+
+        drop(font);
+        fonts.drain_events(&mut events);
+        assert_eq!(events, vec![FontsEvent::FontReleased(id)]);
+        assert_eq!((fonts.stats().fonts, fonts.stats().retained_fonts), (1, 1));
+
+        // used again within the retention, without loading it again
+        fonts.end_frame();
+        let font = test_font(&fonts);
+        assert_eq!(font.id, id);
+        assert_eq!(fonts.stats().retained_fonts, 0);
+        drop(font);
+
+        events.clear();
+        fonts.end_frame();
+        assert_eq!(fonts.stats().fonts, 1);
+        fonts.end_frame();
+        fonts.drain_events(&mut events);
+        assert_eq!(events, vec![FontsEvent::FontReleased(id), FontsEvent::FontEvicted(id)]);
+        assert_eq!((fonts.stats().fonts, fonts.stats().retained_fonts), (0, 0));
+
+        drop(test_font(&fonts));
+        assert_eq!(fonts.stats().retained_fonts, 1);
+        fonts.flush_unused();
+        assert_eq!((fonts.stats().fonts, fonts.stats().retained_fonts), (0, 0));
+    }
+
+    #[test]
+    fn extracted_range_should_keep_glyphs_shaped_in_context() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let text = "abc سلام def";
+        let buffer = font.create_buffer(text, None, [0, 0, 0, 255].into());
+        let mut glyphs = Vec::new();
+        buffer.glyphs(&mut glyphs);
+
+        let extracted = buffer.extract_range(6..10);
+        assert!(extracted.is_preshaped());
+        let fragment = extracted.text();
+        let start = text.find(fragment.as_str()).unwrap();
+        assert!(start <= 6 && start + fragment.len() >= 10);
+
+        let expected = glyphs.iter()
+            .filter(|g| g.cluster as usize >= start && (g.cluster as usize) < start + fragment.len())
+            .map(|g| (g.id, g.x_advance, g.cluster as usize - start))
+            .collect::<Vec<_>>();
+        let mut extracted_glyphs = Vec::new();
+        extracted.glyphs(&mut extracted_glyphs);
+        assert_eq!(extracted_glyphs.iter().map(|g| (g.id, g.x_advance, g.cluster as usize)).collect::<Vec<_>>(), expected);
+
+        extracted.set_text("سلام");
+        assert!(!extracted.is_preshaped());
+    }
+
+    #[test]
+    fn extracted_range_of_collapsed_text_should_keep_source_offsets() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let buffer = font.create_buffer("a   b  c", None, [0, 0, 0, 255].into());
+        buffer.set_whitespace(Whitespace::Collapse);
+
+        let extracted = buffer.extract_range(1..3);
+        assert_eq!(extracted.shaped_text(), " b");
+        assert_eq!(extracted.text(), "   b");
+
+        let mut glyphs = Vec::new();
+        extracted.glyphs(&mut glyphs);
+        assert_eq!(glyphs.iter().map(|g| (g.cluster, g.byte_offset, g.len)).collect::<Vec<_>>(), vec![(0, 0, 3), (1, 3, 1)]);
+    }
+
+    #[test]
+    fn alignment_should_place_lines_within_max_width() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let buffer = font.create_buffer("one two three four five six", None, [0, 0, 0, 255].into());
+        let natural = buffer.layout(&LayoutOptions::default()).lines()[0].width;
+
+        let max_width = natural + 40.0;
+        let aligned = |align: Align| buffer.layout(&LayoutOptions { max_width: Some(max_width), align, ..LayoutOptions::default() });
+        for &align in &[Align::Start, Align::Left] {
+            let line = &aligned(align).lines()[0];
+            assert_eq!(line.x_offset, 0.0);
+        }
+        let line = &aligned(Align::Right).lines()[0];
+        assert!((line.x_offset + line.width - max_width).abs() < 0.01);
+        let line = &aligned(Align::Center).lines()[0];
+        assert!((line.x_offset - (max_width - line.width) * 0.5).abs() < 0.01);
+
+        let max_width = natural * 0.6;
+        let justified = buffer.layout(&LayoutOptions { max_width: Some(max_width), align: Align::Justify, ..LayoutOptions::default() });
+        let (last, wrapped) = justified.lines().split_last().expect("justified text should have lines");
+        assert!(!wrapped.is_empty());
+        for line in wrapped {
+            assert!((line.width - max_width).abs() < 0.01, "line width {} of {}", line.width, max_width);
+        }
+        assert_eq!(last.x_offset, 0.0);
+        assert!(last.width < max_width);
+    }
+
+    #[test]
+    fn line_height_and_first_line_offset_should_place_baselines() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let buffer = font.create_buffer("a\nb", None, [0, 0, 0, 255].into());
+
+        let layout = buffer.layout(&LayoutOptions { line_height: LineHeight::Absolute(30.0), ..LayoutOptions::default() });
+        assert_eq!(layout.line_height(), 30.0);
+        assert!((layout.lines()[0].baseline_y - layout.lines()[1].baseline_y - 30.0).abs() < 0.001);
+
+        let layout = buffer.layout(&LayoutOptions { px_size: 20.0, line_height: LineHeight::Multiple(2.0), ..LayoutOptions::default() });
+        assert!((layout.line_height() - 40.0).abs() < 0.001);
+        assert!((layout.height() - 80.0).abs() < 0.001);
+
+        let layout = buffer.layout(&LayoutOptions { first_line_offset: Some(12.0), ..LayoutOptions::default() });
+        assert!((layout.lines()[0].baseline_y + 12.0).abs() < 0.001);
+    }
+
+    #[test]
+    fn every_newline_kind_should_end_a_line() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let buffer = font.create_buffer("a\r\nb\rc\u{2028}d\n", None, [0, 0, 0, 255].into());
+
+        let layout = buffer.layout(&LayoutOptions::default());
+        let lines = layout.lines();
+        assert_eq!(lines.iter().map(|l| l.line_break).collect::<Vec<_>>(), vec![LineBreak::Paragraph, LineBreak::Paragraph, LineBreak::Line, LineBreak::Paragraph, LineBreak::End]);
+        assert_eq!(lines.iter().map(|l| l.bytes.clone()).collect::<Vec<_>>(), vec![0..1, 3..4, 5..6, 9..10, 11..11]);
+        assert!(lines.windows(2).all(|pair| pair[1].baseline_y < pair[0].baseline_y));
+    }
+
+    #[test]
+    fn hit_test_and_carets_should_agree_on_a_line() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let buffer = font.create_buffer("hello", None, [0, 0, 0, 255].into());
+
+        let layout = buffer.layout(&LayoutOptions::default());
+        let line = &layout.lines()[0];
+        let glyphs = layout.glyphs();
+        assert_eq!(layout.hit_test(-10.0, line.baseline_y).caret_index(), 0);
+        assert_eq!(layout.hit_test(line.width + 10.0, line.baseline_y).caret_index(), 5);
+
+        let first = &glyphs[0];
+        let leading = layout.hit_test(first.x + first.x_advance * 0.25, line.baseline_y);
+        assert_eq!((leading.byte_index, leading.cluster_len, leading.is_trailing_edge), (0, 1, false));
+        let trailing = layout.hit_test(first.x + first.x_advance * 0.75, line.baseline_y);
+        assert_eq!((trailing.is_trailing_edge, trailing.caret_index()), (true, 1));
+
+        assert_eq!(layout.caret_for_index(0).x, line.x_offset);
+        for (i, glyph) in glyphs.iter().enumerate() {
+            assert!((layout.caret_for_index(i).x - glyph.x).abs() < 0.001);
+        }
+        assert!((layout.caret_for_index(5).x - line.width).abs() < 0.001);
+        assert_eq!(layout.caret_for_index(100), layout.caret_for_index(5));
+        assert_eq!(layout.caret_for_index(0).baseline_y, line.baseline_y);
+    }
+
+    #[test]
+    fn cursor_should_step_over_combining_marks() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let buffer = font.create_buffer("e\u{301}x", None, [0, 0, 0, 255].into());
+
+        assert_eq!(buffer.cursor_positions().collect::<Vec<_>>(), vec![0, 3, 4]);
+        assert_eq!(buffer.next_cursor(0), 3);
+        assert_eq!(buffer.next_cursor(1), 3);
+        assert_eq!(buffer.next_cursor(4), 4);
+        assert_eq!(buffer.prev_cursor(4), 3);
+        assert_eq!(buffer.prev_cursor(3), 0);
+        assert_eq!(buffer.prev_cursor(0), 0);
+    }
+
+    #[test]
+    fn spanned_glyphs_should_keep_the_span_of_their_text() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let mono = mono_font(&fonts);
+        let text = SpannedText::new().push("abc ", &font).push("def", &mono);
+        let buffer = fonts.create_spanned_buffer(&text, None, [0, 0, 0, 255].into()).expect("spanned buffer should be created");
+
+        assert_eq!(buffer.text(), "abc def");
+        assert_eq!(buffer.span_font(0), font);
+        assert_eq!(buffer.span_font(1), mono);
+        buffer.with_glyphs(|glyphs| {
+            assert_eq!(glyphs.len(), 7);
+            for glyph in glyphs {
+                assert_eq!(glyph.span, if glyph.cluster < 4 { 0 } else { 1 });
+            }
+        });
+    }
+
+    #[test]
+    fn tabs_should_advance_to_the_next_stop() {
+        assert_eq!(TabStops::Every(100.0).next_stop(0.0), 100.0);
+        assert_eq!(TabStops::Every(100.0).next_stop(100.0), 200.0);
+        let positions = TabStops::Positions(vec![30.0, 50.0]);
+        assert_eq!(positions.next_stop(10.0), 30.0);
+        assert_eq!(positions.next_stop(40.0), 50.0);
+        assert_eq!(positions.next_stop(60.0), 70.0);
+
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let buffer = font.create_buffer("a\tb\tc", None, [0, 0, 0, 255].into());
+        let layout = buffer.layout(&LayoutOptions { tab_stops: Some(TabStops::Every(100.0)), ..LayoutOptions::default() });
+        let xs = layout.glyphs().iter().map(|g| g.x).collect::<Vec<_>>();
+        assert_eq!(xs.len(), 5);
+        assert!((xs[2] - 100.0).abs() < 0.001);
+        assert!((xs[4] - 200.0).abs() < 0.001);
+    }
+
+    #[test]
+    fn empty_buffers_should_have_no_glyphs() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let buffer = font.create_buffer("", None, [0, 0, 0, 255].into());
+
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.glyph_count(), 0);
+        buffer.set_text("ab");
+        assert!(!buffer.is_empty());
+        assert_eq!(buffer.glyph_count(), 2);
+    }
+
+    #[test]
+    fn absolute_transform_should_apply_the_buffer_transform_first() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let buffer = font.create_buffer("a", None, [0, 0, 0, 255].into());
+        let parent = na::convert::<_, na::Projective3<f32>>(
+            na::Rotation3::from_axis_angle(&na::Vector3::z_axis(), ::std::f32::consts::FRAC_PI_2)
+        );
+
+        assert!(buffer.absolute_transform(&parent).is_none());
+        buffer.translate(10.0, 0.0);
+        let absolute = buffer.absolute_transform(&parent).expect("translated buffer should have a transform");
+        let point = absolute * na::Point3::new(1.0, 0.0, 0.0);
+
+        assert!((point.x - 0.0).abs() < 0.0001, "x was {}", point.x);
+        assert!((point.y - 11.0).abs() < 0.0001, "y was {}", point.y);
+    }
+
+    #[test]
+    fn invalid_scale_factors_should_panic_and_keep_the_factor() {
+        let fonts = test_fonts();
+        fonts.set_scale_factor(2.0);
+
+        for &factor in &[0.0, -1.0, ::std::f32::NAN, ::std::f32::INFINITY] {
+            let result = ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(|| fonts.set_scale_factor(factor)));
+            assert!(result.is_err(), "{} should be rejected", factor);
+        }
+        assert_eq!(fonts.scale_factor(), 2.0);
+    }
+
+    #[test]
+    fn creating_buffers_inside_with_glyphs_should_panic() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let buffer = font.create_buffer("a", None, [0, 0, 0, 255].into());
+
+        let result = ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(|| {
+            buffer.with_glyphs(|_| font.create_buffer("b", None, [0, 0, 0, 255].into()))
+        }));
+        assert!(result.is_err());
+        // the borrow was released by the unwinding
+        assert_eq!(buffer.glyph_count(), 1);
+    }
+
+    #[test]
+    fn layout_quads_should_place_bitmaps_at_their_bearings() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let buffer = font.create_buffer("HIH", None, [0, 0, 0, 255].into());
+        let mut atlas = GlyphAtlas::new(256, 8);
+        let mut glyphs = Vec::new();
+        buffer.glyphs_scaled(None, Some(atlas.subpixel_phases()), &mut glyphs);
+
+        let mut quads = Vec::new();
+        layout_quads(&buffer, None, &mut atlas, false, &mut quads);
+        assert_eq!(quads.len(), glyphs.len());
+        for (glyph, quad) in glyphs.iter().zip(&quads) {
+            let entry = atlas.get_for_font(&font, glyph.id, buffer.size_px(), glyph.phase).expect("quad glyphs should be in the atlas");
+            let scale = entry.scale_factor;
+            assert!((quad.x0 - glyph.x - entry.bearing.0 as f32 / scale).abs() < 1e-4);
+            assert!((quad.x1 - quad.x0 - entry.size.0 as f32 / scale).abs() < 1e-4);
+            assert!((quad.y1 - glyph.y - entry.bearing.1 as f32 / scale).abs() < 1e-4);
+            assert!((quad.y1 - quad.y0 - entry.size.1 as f32 / scale).abs() < 1e-4);
+            assert_eq!([quad.u0, quad.v0, quad.u1, quad.v1], entry.uv_rect);
+            assert_eq!(quad.page, entry.page);
+        }
+
+        buffer.translate(10.0, 20.0);
+        let mut moved = Vec::new();
+        layout_quads(&buffer, None, &mut atlas, true, &mut moved);
+        for (quad, moved) in quads.iter().zip(&moved) {
+            assert!((moved.x0 - quad.x0 - 10.0).abs() < 1e-4 && (moved.y0 - quad.y0 - 20.0).abs() < 1e-4);
+            assert!((moved.x1 - quad.x1 - 10.0).abs() < 1e-4 && (moved.y1 - quad.y1 - 20.0).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn spans_of_fonts_with_other_units_should_be_placed_in_buffer_units() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        // 1/64 pixel units, unlike the file units of the buffer font
+        let sized = font.at_size(16.0);
+        let text = SpannedText::new().push("ab", &font).push("ab", sized.font());
+        let buffer = fonts.create_spanned_buffer(&text, None, [0, 0, 0, 255].into()).expect("spanned buffer should be created");
+        let plain = font.create_buffer("ab", None, [0, 0, 0, 255].into());
+
+        let pixel = font.units_per_em() as i32 / 16;
+        buffer.with_glyphs(|glyphs| {
+            assert_eq!(glyphs.len(), 4);
+            assert!((glyphs[2].x_advance - glyphs[0].x_advance).abs() <= pixel, "{:?}", glyphs);
+            assert!((glyphs[3].x_advance - glyphs[1].x_advance).abs() <= pixel, "{:?}", glyphs);
+        });
+        assert!((buffer.advance_width_px(None) - 2.0 * plain.advance_width_px(None)).abs() <= 2.0);
+
+        let mut scaled = Vec::new();
+        buffer.glyphs_scaled(None, None, &mut scaled);
+        assert!(((scaled[3].x - scaled[2].x) - (scaled[1].x - scaled[0].x)).abs() <= 1.0);
+        let bounds = buffer.ink_bounds().expect("letters should have ink");
+        let plain_bounds = plain.ink_bounds().expect("letters should have ink");
+        assert!((bounds.height() - plain_bounds.height()).abs() <= pixel as f32);
+    }
+
+    #[test]
+    fn set_font_should_report_dropped_spans() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+        let mono = mono_font(&fonts);
+        let dropped = Rc::new(RefCell::new(Vec::new()));
+        {
+            let dropped = dropped.clone();
+            fonts.set_diagnostics(move |d| if let FontsDiagnostic::SpansDropped { spans, .. } = d {
+                dropped.borrow_mut().push(spans);
+            });
+        }
+
+        let text = SpannedText::new().push("ab", &font).push("cd", &mono);
+        let buffer = fonts.create_spanned_buffer(&text, None, [0, 0, 0, 255].into()).expect("spanned buffer should be created");
+        buffer.set_font(&mono);
+
+        assert_eq!(*dropped.borrow(), vec![2]);
+        assert_eq!(buffer.font(), mono);
+        assert_eq!(buffer.span_font(1), mono);
+        buffer.with_glyphs(|glyphs| assert!(glyphs.iter().all(|g| g.span == 0)));
+    }
+
+    #[test]
+    fn empty_buffer_should_export_valid_svg() {
+        let fonts = test_fonts();
+        let font = test_font(&fonts);
+
+        let buffer = font.create_buffer("", None, [0, 0, 0, 255].into());
+        assert_eq!(buffer.to_svg(16.0), "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 0 0\"></svg>");
+    }
+}
@@ -0,0 +1,396 @@
+use font_kit::family_name::FamilyName;
+use font_kit::properties::{Properties, Weight, Style, Stretch};
+
+/// Parses a CSS `font-family` list such as `'Noto Sans CJK SC', "Segoe UI", sans-serif`.
+///
+/// Unquoted names may contain spaces, generic keywords map to the generic family names.
+/// Malformed input, such as an unterminated quote, is treated as a single family name.
+pub fn parse_family_list(list: &str) -> Vec<FamilyName> {
+    match try_parse_family_list(list) {
+        Some(families) => families,
+        None => {
+            let name = list.trim();
+            if name.is_empty() { Vec::new() } else { vec![FamilyName::Title(name.to_string())] }
+        },
+    }
+}
+
+fn try_parse_family_list(list: &str) -> Option<Vec<FamilyName>> {
+    let mut families = Vec::new();
+    let mut rest = list.trim_start();
+
+    while !rest.is_empty() {
+        let (family, after) = match rest.chars().next() {
+            Some(quote @ '"') | Some(quote @ '\'') => {
+                let end = rest[1..].find(quote)? + 1;
+                (FamilyName::Title(rest[1..end].to_string()), &rest[end + 1..])
+            },
+            _ => {
+                let end = rest.find(',').unwrap_or(rest.len());
+                let name = rest[..end].split_whitespace().collect::<Vec<_>>().join(" ");
+                if name.is_empty() {
+                    return None;
+                }
+                (generic_family(&name).unwrap_or(FamilyName::Title(name)), &rest[end..])
+            },
+        };
+
+        families.push(family);
+
+        let after = after.trim_start();
+        rest = match after.chars().next() {
+            None => after,
+            Some(',') => {
+                let next = after[1..].trim_start();
+                if next.is_empty() {
+                    return None;
+                }
+                next
+            },
+            Some(_) => return None,
+        };
+    }
+
+    Some(families)
+}
+
+fn generic_family(name: &str) -> Option<FamilyName> {
+    match name.to_lowercase().as_str() {
+        "serif" => Some(FamilyName::Serif),
+        "sans-serif" => Some(FamilyName::SansSerif),
+        "monospace" => Some(FamilyName::Monospace),
+        "cursive" => Some(FamilyName::Cursive),
+        "fantasy" => Some(FamilyName::Fantasy),
+        _ => None,
+    }
+}
+
+/// Splits a display name such as `Fira Sans SemiBold Italic` into the family name
+/// and the properties of the trailing style keywords.
+///
+/// Keywords are matched case-insensitively and only at the end of the name, so a family
+/// like `Bold Sans` keeps its name. A name made only of keywords is kept as the family.
+pub fn split_style_keywords(name: &str) -> (String, Properties) {
+    let words = name.split_whitespace().collect::<Vec<_>>();
+    let mut properties = Properties::new();
+    let mut family_len = words.len();
+
+    while family_len > 1 {
+        let word = words[family_len - 1].to_lowercase();
+        if !apply_keyword(&word, &mut properties) {
+            break;
+        }
+        family_len -= 1;
+    }
+
+    (words[..family_len].join(" "), properties)
+}
+
+fn apply_keyword(word: &str, properties: &mut Properties) -> bool {
+    match word {
+        "thin" | "hairline" => properties.weight = Weight::THIN,
+        "extralight" | "ultralight" => properties.weight = Weight::EXTRA_LIGHT,
+        "light" => properties.weight = Weight::LIGHT,
+        "regular" | "normal" | "book" | "roman" => (),
+        "medium" => properties.weight = Weight::MEDIUM,
+        "semibold" | "demibold" => properties.weight = Weight::SEMIBOLD,
+        "bold" => properties.weight = Weight::BOLD,
+        "extrabold" | "ultrabold" => properties.weight = Weight::EXTRA_BOLD,
+        "black" | "heavy" => properties.weight = Weight::BLACK,
+        "italic" => properties.style = Style::Italic,
+        "oblique" => properties.style = Style::Oblique,
+        "condensed" => properties.stretch = Stretch::CONDENSED,
+        "expanded" => properties.stretch = Stretch::EXPANDED,
+        _ => return false,
+    }
+    true
+}
+
+/// Families, properties and size of a fontconfig pattern, see `parse_fontconfig_pattern`.
+#[derive(Debug, Clone)]
+pub struct FontconfigQuery {
+    pub families: Vec<FamilyName>,
+    pub properties: Properties,
+    /// `pixelsize`, or else `size` with points taken as pixels.
+    pub size_px: Option<f32>,
+}
+
+/// Fontconfig weights and the OpenType weights they map to, interpolated in between.
+const FC_WEIGHTS: &[(f32, f32)] = &[
+    (0.0, 100.0), (40.0, 200.0), (50.0, 300.0), (55.0, 350.0), (75.0, 380.0), (80.0, 400.0),
+    (100.0, 500.0), (180.0, 600.0), (200.0, 700.0), (205.0, 800.0), (210.0, 900.0), (215.0, 1000.0),
+];
+
+const FC_WEIGHT_NAMES: &[(&str, f32)] = &[
+    ("thin", 0.0), ("extralight", 40.0), ("ultralight", 40.0), ("light", 50.0), ("demilight", 55.0),
+    ("semilight", 55.0), ("book", 75.0), ("regular", 80.0), ("normal", 80.0), ("medium", 100.0),
+    ("demibold", 180.0), ("semibold", 180.0), ("bold", 200.0), ("extrabold", 205.0),
+    ("ultrabold", 205.0), ("black", 210.0), ("heavy", 210.0),
+];
+
+const FC_SLANT_NAMES: &[(&str, f32)] = &[("roman", 0.0), ("italic", 100.0), ("oblique", 110.0)];
+
+const FC_WIDTH_NAMES: &[(&str, f32)] = &[
+    ("ultracondensed", 50.0), ("extracondensed", 63.0), ("condensed", 75.0), ("semicondensed", 87.0),
+    ("normal", 100.0), ("semiexpanded", 113.0), ("expanded", 125.0), ("extraexpanded", 150.0),
+    ("ultraexpanded", 200.0),
+];
+
+/// Parses a fontconfig pattern such as `DejaVu Sans,Noto Sans-11:style=Bold Italic`, without
+/// fontconfig itself.
+///
+/// Reads the families with an optional `-size`, and the `family`, `style`, `weight`, `slant`,
+/// `width`, `size` and `pixelsize` elements, with numbers or constant names as values. Bare
+/// constants such as `:bold` set their property. Other elements, and values that can't be
+/// read, are ignored.
+pub fn parse_fontconfig_pattern(pattern: &str) -> FontconfigQuery {
+    let mut query = FontconfigQuery { families: Vec::new(), properties: Properties::new(), size_px: None };
+    let mut elements = split_unescaped(pattern, ':').into_iter();
+    let mut head = elements.next().unwrap_or("");
+    let (mut points, mut pixels) = (None, None);
+
+    // a `-` starts the size only if a number follows, so `sans-serif` stays a family
+    let sizes = split_unescaped(head, '-');
+    if sizes.len() > 1 {
+        let last = sizes[sizes.len() - 1];
+        if let Ok(size) = split_unescaped(last, ',')[0].trim().parse::<f32>() {
+            points = Some(size);
+            head = &head[..head.len() - last.len() - 1];
+        }
+    }
+    push_fontconfig_families(head, &mut query.families);
+
+    for element in elements {
+        let (name, value) = match element.find('=') {
+            Some(index) => (element[..index].trim().to_lowercase(), &element[index + 1..]),
+            None => {
+                apply_fontconfig_constant(&unescape(element).trim().to_lowercase(), &mut query.properties);
+                continue;
+            },
+        };
+        if name == "family" {
+            push_fontconfig_families(value, &mut query.families);
+            continue;
+        }
+
+        // of several values only the first one is used
+        let value = unescape(split_unescaped(value, ',')[0]).trim().to_lowercase();
+        match name.as_str() {
+            "style" => {
+                for word in value.split_whitespace() {
+                    apply_keyword(word, &mut query.properties);
+                }
+            },
+            "weight" => if let Some(weight) = fontconfig_value(&value, FC_WEIGHT_NAMES) {
+                query.properties.weight = Weight(fontconfig_weight(weight));
+            },
+            "slant" => if let Some(slant) = fontconfig_value(&value, FC_SLANT_NAMES) {
+                query.properties.style = fontconfig_slant(slant);
+            },
+            "width" => if let Some(width) = fontconfig_value(&value, FC_WIDTH_NAMES) {
+                query.properties.stretch = Stretch(width / 100.0);
+            },
+            "size" => points = value.parse().ok().or(points),
+            "pixelsize" => pixels = value.parse().ok().or(pixels),
+            _ => (),
+        }
+    }
+
+    query.size_px = pixels.or(points);
+    query
+}
+
+/// Slices of `text` between separators not escaped with a backslash, escapes left in.
+fn split_unescaped(text: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut escaped = false;
+    for (index, c) in text.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == separator {
+            parts.push(&text[start..index]);
+            start = index + c.len_utf8();
+        }
+    }
+    parts.push(&text[start..]);
+    parts
+}
+
+fn unescape(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut escaped = false;
+    for c in text.chars() {
+        if c == '\\' && !escaped {
+            escaped = true;
+        } else {
+            output.push(c);
+            escaped = false;
+        }
+    }
+    output
+}
+
+fn push_fontconfig_families(list: &str, families: &mut Vec<FamilyName>) {
+    for name in split_unescaped(list, ',') {
+        let name = unescape(name).trim().to_string();
+        if name.is_empty() {
+            continue;
+        }
+        let generic = match name.to_lowercase().as_str() {
+            "sans" => Some(FamilyName::SansSerif),
+            "mono" => Some(FamilyName::Monospace),
+            lower => generic_family(lower),
+        };
+        families.push(generic.unwrap_or(FamilyName::Title(name)));
+    }
+}
+
+/// A number, or the value of a constant name.
+fn fontconfig_value(value: &str, names: &[(&str, f32)]) -> Option<f32> {
+    value.parse().ok().or_else(|| names.iter().find(|&&(name, _)| name == value).map(|&(_, value)| value))
+}
+
+fn fontconfig_weight(weight: f32) -> f32 {
+    let weight = weight.max(0.0).min(215.0);
+    let upper = FC_WEIGHTS.iter().position(|&(fc, _)| fc >= weight).unwrap_or(FC_WEIGHTS.len() - 1).max(1);
+    let ((fc0, ot0), (fc1, ot1)) = (FC_WEIGHTS[upper - 1], FC_WEIGHTS[upper]);
+    ot0 + (weight - fc0) * (ot1 - ot0) / (fc1 - fc0)
+}
+
+fn fontconfig_slant(slant: f32) -> Style {
+    if slant < 50.0 {
+        Style::Normal
+    } else if slant < 105.0 {
+        Style::Italic
+    } else {
+        Style::Oblique
+    }
+}
+
+fn apply_fontconfig_constant(name: &str, properties: &mut Properties) {
+    if let Some(weight) = fontconfig_value(name, FC_WEIGHT_NAMES) {
+        properties.weight = Weight(fontconfig_weight(weight));
+    } else if let Some(slant) = fontconfig_value(name, FC_SLANT_NAMES) {
+        properties.style = fontconfig_slant(slant);
+    } else if let Some(width) = fontconfig_value(name, FC_WIDTH_NAMES) {
+        properties.stretch = Stretch(width / 100.0);
+    }
+}
+
+#[cfg(target_os = "windows")]
+const UI_FAMILIES: &[&str] = &["Segoe UI", "Tahoma", "Arial"];
+#[cfg(target_os = "windows")]
+const MONOSPACE_FAMILIES: &[&str] = &["Cascadia Mono", "Consolas", "Courier New"];
+
+#[cfg(target_os = "macos")]
+const UI_FAMILIES: &[&str] = &["SF Pro Text", "Helvetica Neue", "Helvetica", "Lucida Grande"];
+#[cfg(target_os = "macos")]
+const MONOSPACE_FAMILIES: &[&str] = &["SF Mono", "Menlo", "Monaco"];
+
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+const UI_FAMILIES: &[&str] = &["Cantarell", "Noto Sans", "DejaVu Sans", "Liberation Sans", "Fira Sans"];
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+const MONOSPACE_FAMILIES: &[&str] = &["DejaVu Sans Mono", "Noto Sans Mono", "Liberation Mono", "Fira Mono"];
+
+fn families(names: &[&str], generic: FamilyName) -> Vec<FamilyName> {
+    names.iter().map(|&name| FamilyName::Title(name.to_string())).chain(Some(generic)).collect()
+}
+
+/// Common interface fonts of the platform, then the generic sans-serif family.
+pub fn default_ui_families() -> Vec<FamilyName> {
+    families(UI_FAMILIES, FamilyName::SansSerif)
+}
+
+/// Common monospace fonts of the platform, then the generic monospace family.
+pub fn default_monospace_families() -> Vec<FamilyName> {
+    families(MONOSPACE_FAMILIES, FamilyName::Monospace)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn trailing_keywords_should_become_properties() {
+        let (family, properties) = split_style_keywords("Fira Sans SemiBold Italic");
+
+        assert_eq!(family, "Fira Sans");
+        assert_eq!(properties.weight, Weight::SEMIBOLD);
+        assert_eq!(properties.style, Style::Italic);
+        assert_eq!(properties.stretch, Stretch::NORMAL);
+    }
+
+    #[test]
+    fn keywords_inside_the_family_should_be_kept() {
+        let (family, properties) = split_style_keywords("Black Ops One Condensed");
+
+        assert_eq!(family, "Black Ops One");
+        assert_eq!(properties.weight, Weight::NORMAL);
+        assert_eq!(properties.stretch, Stretch::CONDENSED);
+
+        assert_eq!(split_style_keywords("Bold").0, "Bold");
+    }
+
+    fn title(name: &str) -> FamilyName {
+        FamilyName::Title(name.to_string())
+    }
+
+    #[test]
+    fn quoted_unquoted_and_generic_names_should_be_parsed() {
+        let families = parse_family_list(" 'Noto Sans CJK SC', \"Segoe UI\" ,Fira  Sans, sans-serif,MONOSPACE ");
+
+        assert_eq!(families, vec![
+            title("Noto Sans CJK SC"),
+            title("Segoe UI"),
+            title("Fira Sans"),
+            FamilyName::SansSerif,
+            FamilyName::Monospace,
+        ]);
+    }
+
+    #[test]
+    fn malformed_lists_should_become_one_family() {
+        assert_eq!(parse_family_list("'Fira Sans, serif"), vec![title("'Fira Sans, serif")]);
+        assert_eq!(parse_family_list("Fira Sans,, serif"), vec![title("Fira Sans,, serif")]);
+        assert_eq!(parse_family_list("'Fira' Sans"), vec![title("'Fira' Sans")]);
+        assert_eq!(parse_family_list("  "), vec![]);
+    }
+
+    #[test]
+    fn fontconfig_patterns_should_set_families_properties_and_size() {
+        let query = parse_fontconfig_pattern("DejaVu Sans:style=Bold:size=11");
+        assert_eq!(query.families, vec![title("DejaVu Sans")]);
+        assert_eq!(query.properties.weight, Weight::BOLD);
+        assert_eq!(query.size_px, Some(11.0));
+
+        let query = parse_fontconfig_pattern("Fira\\-Code,mono-10.5:slant=italic:weight=80:width=75:pixelsize=14:antialias=true");
+        assert_eq!(query.families, vec![title("Fira-Code"), FamilyName::Monospace]);
+        assert_eq!(query.properties.style, Style::Italic);
+        assert_eq!(query.properties.weight, Weight::NORMAL);
+        assert_eq!(query.properties.stretch, Stretch::CONDENSED);
+        assert_eq!(query.size_px, Some(14.0));
+    }
+
+    #[test]
+    fn fontconfig_constants_and_unknown_values_should_not_fail() {
+        let query = parse_fontconfig_pattern("sans-serif:bold:oblique:weight=heavyish:hinting=false");
+        assert_eq!(query.families, vec![FamilyName::SansSerif]);
+        assert_eq!(query.properties.weight, Weight::BOLD);
+        assert_eq!(query.properties.style, Style::Oblique);
+        assert_eq!(query.size_px, None);
+
+        // between fontconfig medium and demibold
+        assert_eq!(parse_fontconfig_pattern(":weight=140").properties.weight, Weight(550.0));
+        assert!(parse_fontconfig_pattern("").families.is_empty());
+    }
+
+    #[test]
+    fn default_families_should_end_with_the_generic_family() {
+        assert_eq!(default_ui_families().last(), Some(&FamilyName::SansSerif));
+        assert_eq!(default_monospace_families().last(), Some(&FamilyName::Monospace));
+        assert!(default_ui_families().len() > 1);
+    }
+}
@@ -0,0 +1,141 @@
+//! Object safe outline sinks, see `Font::outline_dyn`.
+//!
+//! lyon's `PathBuilder` can't be a trait object because of its `PathType` and `build`, so
+//! `OutlineBuilder` has only its drawing methods. Every `PathBuilder` is an `OutlineBuilder`.
+
+use lyon_geom::Arc;
+use lyon_geom::math::{Point, Vector, Angle, point};
+use lyon_path::builder::{FlatPathBuilder, PathBuilder};
+
+/// The drawing methods of a `PathBuilder`, usable as `&mut dyn OutlineBuilder`.
+pub trait OutlineBuilder {
+    fn move_to(&mut self, to: Point);
+    fn line_to(&mut self, to: Point);
+    fn quadratic_bezier_to(&mut self, ctrl: Point, to: Point);
+    fn cubic_bezier_to(&mut self, ctrl1: Point, ctrl2: Point, to: Point);
+    fn close(&mut self);
+}
+
+impl<B: PathBuilder> OutlineBuilder for B {
+    fn move_to(&mut self, to: Point) {
+        FlatPathBuilder::move_to(self, to);
+    }
+
+    fn line_to(&mut self, to: Point) {
+        FlatPathBuilder::line_to(self, to);
+    }
+
+    fn quadratic_bezier_to(&mut self, ctrl: Point, to: Point) {
+        PathBuilder::quadratic_bezier_to(self, ctrl, to);
+    }
+
+    fn cubic_bezier_to(&mut self, ctrl1: Point, ctrl2: Point, to: Point) {
+        PathBuilder::cubic_bezier_to(self, ctrl1, ctrl2, to);
+    }
+
+    fn close(&mut self) {
+        FlatPathBuilder::close(self);
+    }
+}
+
+/// `PathBuilder` forwarding each event to an `OutlineBuilder` with a single virtual call,
+/// tracking the current position itself.
+pub(crate) struct DynPathBuilder<'a> {
+    inner: &'a mut dyn OutlineBuilder,
+    current: Point,
+    first: Point,
+}
+
+impl<'a> DynPathBuilder<'a> {
+    pub fn new(inner: &'a mut dyn OutlineBuilder) -> DynPathBuilder<'a> {
+        DynPathBuilder {
+            inner,
+            current: point(0.0, 0.0),
+            first: point(0.0, 0.0),
+        }
+    }
+}
+
+impl<'a> FlatPathBuilder for DynPathBuilder<'a> {
+    type PathType = ();
+
+    fn move_to(&mut self, to: Point) {
+        self.inner.move_to(to);
+        self.current = to;
+        self.first = to;
+    }
+
+    fn line_to(&mut self, to: Point) {
+        self.inner.line_to(to);
+        self.current = to;
+    }
+
+    fn close(&mut self) {
+        self.inner.close();
+        self.current = self.first;
+    }
+
+    fn build(self) {}
+
+    fn build_and_reset(&mut self) {
+        self.current = point(0.0, 0.0);
+        self.first = point(0.0, 0.0);
+    }
+
+    fn current_position(&self) -> Point {
+        self.current
+    }
+}
+
+impl<'a> PathBuilder for DynPathBuilder<'a> {
+    fn quadratic_bezier_to(&mut self, ctrl: Point, to: Point) {
+        self.inner.quadratic_bezier_to(ctrl, to);
+        self.current = to;
+    }
+
+    fn cubic_bezier_to(&mut self, ctrl1: Point, ctrl2: Point, to: Point) {
+        self.inner.cubic_bezier_to(ctrl1, ctrl2, to);
+        self.current = to;
+    }
+
+    fn arc(&mut self, center: Point, radii: Vector, sweep_angle: Angle, x_rotation: Angle) {
+        let start = self.current - center;
+        let arc = Arc {
+            center,
+            radii,
+            start_angle: Angle::radians(start.y.atan2(start.x)),
+            sweep_angle,
+            x_rotation,
+        };
+        arc.for_each_quadratic_bezier(&mut |curve| {
+            PathBuilder::quadratic_bezier_to(self, curve.ctrl, curve.to);
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use lyon_path::default::Path;
+    use lyon_path::PathEvent;
+
+    #[test]
+    fn events_should_reach_the_inner_builder() {
+        let mut path = Path::builder();
+        {
+            let mut builder = DynPathBuilder::new(&mut path);
+            FlatPathBuilder::move_to(&mut builder, point(0.0, 0.0));
+            FlatPathBuilder::line_to(&mut builder, point(1.0, 0.0));
+            PathBuilder::quadratic_bezier_to(&mut builder, point(1.0, 1.0), point(0.0, 1.0));
+            FlatPathBuilder::close(&mut builder);
+            assert_eq!(FlatPathBuilder::current_position(&builder), point(0.0, 0.0));
+        }
+        let events = FlatPathBuilder::build(path).iter().collect::<Vec<_>>();
+        assert_eq!(events, vec![
+            PathEvent::MoveTo(point(0.0, 0.0)),
+            PathEvent::LineTo(point(1.0, 0.0)),
+            PathEvent::QuadraticTo(point(1.0, 1.0), point(0.0, 1.0)),
+            PathEvent::Close,
+        ]);
+    }
+}
@@ -0,0 +1,242 @@
+//! Placement of glyphs along a path, see `Buffer::layout_on_path`.
+
+use lyon_path::default::Path;
+use lyon_path::iterator::PathIterator;
+use lyon_path::FlattenedEvent;
+use super::{GlyphPosition, Origin};
+
+/// Flattening tolerance of the path, in pixels.
+const TOLERANCE: f32 = 0.05;
+
+/// What happens to glyphs past the ends of the path.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PathOverflow {
+    /// Glyphs whose center is off the path are left out.
+    Clip,
+    /// Glyphs continue in a straight line along the direction at the end.
+    Extend,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PathLayoutOptions {
+    /// Distance along the path to the start of the text, in pixels.
+    pub start_offset: f32,
+    /// Extra space after each glyph on curves, as a fraction of the font size per radian the
+    /// path turns across the glyph. Keeps glyphs on the inside of tight curves apart.
+    pub curve_spacing: f32,
+    pub overflow: PathOverflow,
+}
+
+impl Default for PathLayoutOptions {
+    fn default() -> Self {
+        PathLayoutOptions {
+            start_offset: 0.0,
+            curve_spacing: 0.0,
+            overflow: PathOverflow::Clip,
+        }
+    }
+}
+
+/// Glyph placed on a path, in the coordinates of the path.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PlacedGlyph {
+    pub id: u32,
+    pub cluster: u32,
+    pub len: u32,
+    pub span: u32,
+    /// Glyph origin, on the path unless the glyph has offsets.
+    pub x: f32,
+    pub y: f32,
+    /// Rotation of the glyph from the x axis towards the y axis in radians, the direction
+    /// of the path at the glyph center.
+    pub angle: f32,
+    /// Distance along the path to the glyph start, for hit testing along the path.
+    pub distance: f32,
+    pub x_advance: f32,
+}
+
+/// First contour of a path flattened to a polyline, with the distance along it to each point.
+struct Polyline {
+    points: Vec<(f32, f32)>,
+    distances: Vec<f32>,
+}
+
+impl Polyline {
+    fn new(path: &Path) -> Polyline {
+        let mut points: Vec<(f32, f32)> = Vec::new();
+        for event in path.path_iter().flattened(TOLERANCE) {
+            let p = match event {
+                FlattenedEvent::MoveTo(p) if points.is_empty() => (p.x, p.y),
+                FlattenedEvent::LineTo(p) => (p.x, p.y),
+                FlattenedEvent::Close => match points.first() {
+                    Some(&first) => {
+                        if points.last() != Some(&first) {
+                            points.push(first);
+                        }
+                        break;
+                    }
+                    None => break,
+                },
+                FlattenedEvent::MoveTo(_) => break,
+            };
+            if points.last() != Some(&p) {
+                points.push(p);
+            }
+        }
+
+        let mut distances = Vec::with_capacity(points.len());
+        let mut total = 0.0;
+        for (i, p) in points.iter().enumerate() {
+            if i > 0 {
+                let previous = points[i - 1];
+                total += ((p.0 - previous.0).powi(2) + (p.1 - previous.1).powi(2)).sqrt();
+            }
+            distances.push(total);
+        }
+
+        Polyline { points, distances }
+    }
+
+    fn length(&self) -> f32 {
+        self.distances.last().cloned().unwrap_or(0.0)
+    }
+
+    /// Point and unit direction at `distance` along the polyline, continued in a straight line
+    /// before the start and after the end. `None` for a path without length.
+    fn sample(&self, distance: f32) -> Option<((f32, f32), (f32, f32))> {
+        if self.points.len() < 2 {
+            return None;
+        }
+        let segment = match self.distances.iter().position(|&d| d > distance) {
+            Some(0) => 0,
+            Some(i) => i - 1,
+            None => self.points.len() - 2,
+        };
+
+        let (a, b) = (self.points[segment], self.points[segment + 1]);
+        let length = self.distances[segment + 1] - self.distances[segment];
+        let direction = ((b.0 - a.0) / length, (b.1 - a.1) / length);
+        let along = distance - self.distances[segment];
+        Some(((a.0 + direction.0 * along, a.1 + direction.1 * along), direction))
+    }
+}
+
+/// Places glyphs in visual order along `path`, each rotated about the point under its center.
+///
+/// Advances and offsets are in font units, multiplied by `scale` to pixels.
+pub fn place_on_path(glyphs: &[GlyphPosition], scale: f32, px_size: f32, origin: Origin, path: &Path, options: &PathLayoutOptions) -> Vec<PlacedGlyph> {
+    let polyline = Polyline::new(path);
+    let length = polyline.length();
+    let mut placed = Vec::with_capacity(glyphs.len());
+    let mut pen = options.start_offset;
+
+    for glyph in glyphs {
+        let advance = glyph.x_advance as f32 * scale;
+        let start = pen;
+        let center = start + advance * 0.5;
+        pen += advance;
+
+        if options.overflow == PathOverflow::Clip && (center < 0.0 || center > length) {
+            continue;
+        }
+        let (point, direction) = match polyline.sample(center) {
+            Some(sample) => sample,
+            None => break,
+        };
+
+        // offsets are in the rotated frame of the glyph
+        let (offset_x, offset_y) = (glyph.x_offset as f32 * scale - advance * 0.5, origin.y(glyph.y_offset as f32 * scale));
+        placed.push(PlacedGlyph {
+            id: glyph.id,
+            cluster: glyph.cluster,
+            len: glyph.len,
+            span: glyph.span,
+            x: point.0 + direction.0 * offset_x - direction.1 * offset_y,
+            y: point.1 + direction.1 * offset_x + direction.0 * offset_y,
+            angle: direction.1.atan2(direction.0),
+            distance: start,
+            x_advance: advance,
+        });
+
+        if options.curve_spacing > 0.0 && advance > 0.0 {
+            if let (Some((_, a)), Some((_, b))) = (polyline.sample(start), polyline.sample(pen)) {
+                let turn = (a.0 * b.1 - a.1 * b.0).atan2(a.0 * b.0 + a.1 * b.1).abs();
+                pen += options.curve_spacing * turn * px_size;
+            }
+        }
+    }
+
+    placed
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::GlyphFlags;
+    use lyon_path::builder::{FlatPathBuilder, PathBuilder};
+    use lyon_path::math::point;
+    use std::f32::consts::PI;
+
+    fn glyphs(count: usize, x_advance: i32) -> Vec<GlyphPosition> {
+        (0..count)
+            .map(|i| GlyphPosition {
+                id: i as u32 + 1,
+                cluster: i as u32,
+                byte_offset: i as u32,
+                len: 1,
+                x_advance,
+                y_advance: 0,
+                x_offset: 0,
+                y_offset: 0,
+                span: 0,
+                flags: GlyphFlags::default(),
+            })
+            .collect()
+    }
+
+    fn corner() -> Path {
+        let mut builder = Path::builder();
+        builder.move_to(point(0.0, 0.0));
+        builder.line_to(point(10.0, 0.0));
+        builder.line_to(point(10.0, 10.0));
+        FlatPathBuilder::build(builder)
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 0.001
+    }
+
+    #[test]
+    fn glyphs_should_follow_the_path_direction() {
+        let options = PathLayoutOptions { start_offset: 1.0, ..PathLayoutOptions::default() };
+        let placed = place_on_path(&glyphs(3, 400), 0.01, 4.0, Origin::BottomLeftYUp, &corner(), &options);
+
+        assert_eq!(placed.len(), 3);
+        assert!(close(placed[0].x, 1.0) && close(placed[0].y, 0.0) && close(placed[0].angle, 0.0));
+        assert!(close(placed[1].x, 5.0) && close(placed[1].distance, 5.0));
+        // centered at 11, one unit down the second leg
+        assert!(close(placed[2].x, 10.0) && close(placed[2].y, -1.0) && close(placed[2].angle, PI / 2.0));
+        assert_eq!(placed.iter().map(|g| g.cluster).collect::<Vec<_>>(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn overflowing_glyphs_should_be_clipped_or_continue_straight() {
+        let clipped = place_on_path(&glyphs(8, 400), 0.01, 4.0, Origin::BottomLeftYUp, &corner(), &PathLayoutOptions::default());
+        assert_eq!(clipped.len(), 5);
+
+        let options = PathLayoutOptions { overflow: PathOverflow::Extend, ..PathLayoutOptions::default() };
+        let extended = place_on_path(&glyphs(8, 400), 0.01, 4.0, Origin::BottomLeftYUp, &corner(), &options);
+        assert_eq!(extended.len(), 8);
+        assert!(close(extended[7].x, 10.0) && close(extended[7].y, 18.0) && close(extended[7].angle, PI / 2.0));
+    }
+
+    #[test]
+    fn curve_spacing_should_add_space_after_turns() {
+        let options = PathLayoutOptions { curve_spacing: 0.5, ..PathLayoutOptions::default() };
+        let placed = place_on_path(&glyphs(4, 400), 0.01, 4.0, Origin::BottomLeftYUp, &corner(), &options);
+
+        assert!(close(placed[1].distance, 4.0));
+        // the third glyph spans the corner, a quarter turn
+        assert!(close(placed[3].distance, 12.0 + 0.5 * PI / 2.0 * 4.0));
+    }
+}
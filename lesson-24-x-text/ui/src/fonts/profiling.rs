@@ -0,0 +1,181 @@
+//! Timing of shaping, outline and raster work, see `Fonts::set_profiler`.
+
+use std::cell::{Cell, RefCell};
+use std::time::Instant;
+
+/// Kind of work a `ShapeEvent` timed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ProfiledOperation {
+    /// A harfbuzz shape call, or the simple shaper.
+    Shape,
+    /// A glyph outline from `Font::outline_cached`, cache hits included.
+    Outline,
+    /// A glyph from `Font::rasterize_glyph` or `Font::glyph_sdf`, cache hits included.
+    Raster,
+}
+
+/// Work timed by the `Fonts::set_profiler` hook.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ShapeEvent {
+    pub operation: ProfiledOperation,
+    pub font_id: usize,
+    /// Bytes of the shaped text, 0 for outline and raster work.
+    pub text_len: usize,
+    /// Glyphs shaped, 1 for outline and raster work.
+    pub glyph_count: usize,
+    pub micros: u64,
+}
+
+/// Durations kept per operation for the percentiles of `Fonts::stats`.
+const RECENT_SAMPLES: usize = 1024;
+
+#[derive(Default)]
+struct Timings {
+    calls: u64,
+    total_micros: u64,
+    /// Last `RECENT_SAMPLES` durations, a ring buffer from `next`.
+    recent: Vec<u64>,
+    next: usize,
+}
+
+impl Timings {
+    fn add(&mut self, micros: u64) {
+        self.calls += 1;
+        self.total_micros += micros;
+        if self.recent.len() < RECENT_SAMPLES {
+            self.recent.push(micros);
+        } else {
+            self.recent[self.next] = micros;
+            self.next = (self.next + 1) % RECENT_SAMPLES;
+        }
+    }
+
+    fn p95(&self) -> u64 {
+        if self.recent.is_empty() {
+            return 0;
+        }
+        let mut sorted = self.recent.clone();
+        sorted.sort();
+        sorted[(sorted.len() - 1) * 95 / 100]
+    }
+}
+
+/// Totals of the work timed while a profiler hook was set.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct ProfileTotals {
+    pub calls: u64,
+    pub total_micros: u64,
+    /// 95th percentile of the last 1024 calls.
+    pub p95_micros: u64,
+}
+
+/// Sums up timings and queues them for the hook until `flush`; without a hook timing is skipped.
+pub struct Profiler {
+    enabled: Cell<bool>,
+    hook: RefCell<Option<Box<dyn Fn(ShapeEvent)>>>,
+    queued: RefCell<Vec<ShapeEvent>>,
+    timings: RefCell<[Timings; 3]>,
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler {
+            enabled: Cell::new(false),
+            hook: RefCell::new(None),
+            queued: RefCell::new(Vec::new()),
+            timings: RefCell::new(Default::default()),
+        }
+    }
+
+    pub fn set_hook(&self, hook: Option<Box<dyn Fn(ShapeEvent)>>) {
+        self.enabled.set(hook.is_some());
+        if hook.is_none() {
+            self.queued.borrow_mut().clear();
+        }
+        *self.hook.borrow_mut() = hook;
+    }
+
+    /// Runs `f`, timing it if a hook is set. `counts` gives the text length and glyph count
+    /// of the event from the result.
+    pub fn time<T, F, C>(&self, operation: ProfiledOperation, font_id: usize, f: F, counts: C) -> T
+        where F: FnOnce() -> T, C: FnOnce(&T) -> (usize, usize) {
+        if !self.enabled.get() {
+            return f();
+        }
+
+        let start = Instant::now();
+        let result = f();
+        let elapsed = start.elapsed();
+        let micros = elapsed.as_secs() * 1_000_000 + u64::from(elapsed.subsec_micros());
+
+        let (text_len, glyph_count) = counts(&result);
+        self.timings.borrow_mut()[operation as usize].add(micros);
+        self.queued.borrow_mut().push(ShapeEvent { operation, font_id, text_len, glyph_count, micros });
+        result
+    }
+
+    pub fn has_queued(&self) -> bool {
+        !self.queued.borrow().is_empty()
+    }
+
+    /// Sends the queued events to the hook, including events of work the hook did.
+    pub fn flush(&self) {
+        loop {
+            let queued = ::std::mem::replace(&mut *self.queued.borrow_mut(), Vec::new());
+            if queued.is_empty() {
+                return;
+            }
+            if let Some(ref hook) = *self.hook.borrow() {
+                queued.into_iter().for_each(|event| hook(event));
+            }
+        }
+    }
+
+    pub fn totals(&self, operation: ProfiledOperation) -> ProfileTotals {
+        let timings = &self.timings.borrow()[operation as usize];
+        ProfileTotals {
+            calls: timings.calls,
+            total_micros: timings.total_micros,
+            p95_micros: timings.p95(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::rc::Rc;
+
+    #[test]
+    fn hook_should_receive_timed_events() {
+        let profiler = Profiler::new();
+        assert_eq!(profiler.time(ProfiledOperation::Shape, 1, || 5, |_| (1, 1)), 5);
+        assert_eq!(profiler.totals(ProfiledOperation::Shape).calls, 0);
+
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let received = events.clone();
+        profiler.set_hook(Some(Box::new(move |event| received.borrow_mut().push(event))));
+        profiler.time(ProfiledOperation::Raster, 2, || vec![0; 3], |glyphs| (0, glyphs.len()));
+        assert!(events.borrow().is_empty() && profiler.has_queued());
+        profiler.flush();
+
+        let events = events.borrow();
+        assert_eq!(events.len(), 1);
+        assert_eq!((events[0].operation, events[0].font_id, events[0].glyph_count), (ProfiledOperation::Raster, 2, 3));
+        assert_eq!(profiler.totals(ProfiledOperation::Raster).calls, 1);
+        assert_eq!(profiler.totals(ProfiledOperation::Shape).calls, 0);
+    }
+
+    #[test]
+    fn p95_should_come_from_recent_calls() {
+        let mut timings = Timings::default();
+        for micros in 1..=100 {
+            timings.add(micros);
+        }
+        assert_eq!(timings.p95(), 95);
+        for _ in 0..RECENT_SAMPLES {
+            timings.add(7);
+        }
+        assert_eq!((timings.p95(), timings.recent.len()), (7, RECENT_SAMPLES));
+    }
+}
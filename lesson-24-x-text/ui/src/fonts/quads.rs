@@ -0,0 +1,102 @@
+use crate::na;
+use super::{Buffer, ScaledGlyph, Origin};
+use super::atlas::GlyphAtlas;
+
+/// Textured rectangle of a glyph, positions in logical pixels in the `Fonts::origin` convention.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct GlyphQuad {
+    pub x0: f32,
+    pub y0: f32,
+    pub x1: f32,
+    pub y1: f32,
+    pub u0: f32,
+    pub v0: f32,
+    pub u1: f32,
+    pub v1: f32,
+    pub page: usize,
+    /// Sample the page as color instead of coverage.
+    pub color: bool,
+    /// Tag of the glyph cluster, see `Buffer::set_highlights`.
+    pub highlight: Option<u32>,
+}
+
+impl GlyphQuad {
+    /// Corners `(x0, y0)`, `(x1, y0)`, `(x1, y1)` and `(x0, y1)` through `transform`, such as
+    /// `Buffer::transform`, which can rotate or shear the quad.
+    pub fn corners(&self, transform: Option<&na::Projective3<f32>>) -> [na::Point2<f32>; 4] {
+        let corners = [(self.x0, self.y0), (self.x1, self.y0), (self.x1, self.y1), (self.x0, self.y1)];
+        let mut out = [na::Point2::origin(); 4];
+        for (out, &(x, y)) in out.iter_mut().zip(&corners) {
+            let point = na::Point3::new(x, y, 0.0);
+            let point = transform.map_or(point, |t| t.transform_point(&point));
+            *out = na::Point2::new(point.x, point.y);
+        }
+        out
+    }
+}
+
+/// Appends one quad per visible glyph of `buffer` at `px_size`, or the buffer size when `None`,
+/// adding missing glyphs to `atlas`.
+///
+/// Positions are relative to the buffer origin, `(x0, y0)` is the bottom left corner with Y up
+/// and the top left corner with Y down; `v0` belongs to the top edge. With `apply_transform` the buffer transform is applied to
+/// both corners, which keeps quads correct for translation and scale only; for rotations and
+/// obliques lay out without it and map `GlyphQuad::corners`.
+pub fn layout_quads<P: Into<Option<f32>>>(buffer: &Buffer, px_size: P, atlas: &mut GlyphAtlas, apply_transform: bool, out: &mut Vec<GlyphQuad>) {
+    let px_size = px_size.into().unwrap_or_else(|| buffer.size_px());
+    let mut glyphs: Vec<ScaledGlyph> = Vec::with_capacity(buffer.glyph_count());
+    buffer.glyphs_scaled(px_size, Some(atlas.subpixel_phases()), &mut glyphs);
+
+    let transform = if apply_transform { buffer.transform() } else { None };
+    let origin = buffer.origin();
+    let mut fonts = Vec::new();
+
+    for glyph in &glyphs {
+        let span = glyph.span as usize;
+        if fonts.len() <= span {
+            fonts.resize(span + 1, None);
+        }
+        if fonts[span].is_none() {
+            fonts[span] = Some((buffer.span_font(glyph.span), px_size * buffer.span_scale(glyph.span)));
+        }
+        let (ref font, glyph_px) = *fonts[span].as_ref().expect("layout_quads: span font was just resolved");
+
+        let entry = match atlas.get_for_font(font, glyph.id, glyph_px, glyph.phase) {
+            Some(entry) => entry,
+            None => continue,
+        };
+
+        // the bitmap is in physical pixels, quads in logical ones
+        let x0 = glyph.x + entry.bearing.0 as f32 / entry.scale_factor;
+        let x1 = x0 + entry.size.0 as f32 / entry.scale_factor;
+        let top = glyph.y + origin.y(entry.bearing.1 as f32 / entry.scale_factor);
+        let height = entry.size.1 as f32 / entry.scale_factor;
+        let (y0, y1) = match origin {
+            Origin::BottomLeftYUp => (top - height, top),
+            Origin::TopLeftYDown => (top, top + height),
+        };
+
+        let (x0, y0, x1, y1) = match transform {
+            Some(ref t) => {
+                let min = t.transform_point(&na::Point3::new(x0, y0, 0.0));
+                let max = t.transform_point(&na::Point3::new(x1, y1, 0.0));
+                (min.x, min.y, max.x, max.y)
+            }
+            None => (x0, y0, x1, y1),
+        };
+
+        out.push(GlyphQuad {
+            x0,
+            y0,
+            x1,
+            y1,
+            u0: entry.uv_rect[0],
+            v0: entry.uv_rect[1],
+            u1: entry.uv_rect[2],
+            v1: entry.uv_rect[3],
+            page: entry.page,
+            color: entry.color,
+            highlight: glyph.highlight,
+        });
+    }
+}
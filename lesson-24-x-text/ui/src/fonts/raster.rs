@@ -0,0 +1,405 @@
+use lyon_path::default::Path;
+use lyon_path::iterator::PathIterator;
+use lyon_path::FlattenedEvent;
+use std::ops::Range;
+use super::size_bucket::SizeBucket;
+
+const SUBSAMPLES: usize = 4;
+
+#[derive(Debug, Copy, Clone)]
+struct Edge {
+    x0: f32,
+    y0: f32,
+    x1: f32,
+    y1: f32,
+}
+
+/// Outline flattened to line segments, in font units.
+pub struct Outline {
+    edges: Vec<Edge>,
+    min_x: f32,
+    min_y: f32,
+    max_x: f32,
+    max_y: f32,
+}
+
+impl Outline {
+    pub fn from_path(path: &Path, tolerance: f32) -> Option<Outline> {
+        let mut edges = Vec::new();
+        let (mut start, mut last) = ((0.0, 0.0), (0.0, 0.0));
+
+        for event in path.path_iter().flattened(tolerance) {
+            match event {
+                FlattenedEvent::MoveTo(p) => {
+                    if last != start {
+                        edges.push(Edge { x0: last.0, y0: last.1, x1: start.0, y1: start.1 });
+                    }
+                    start = (p.x, p.y);
+                    last = start;
+                }
+                FlattenedEvent::LineTo(p) => {
+                    edges.push(Edge { x0: last.0, y0: last.1, x1: p.x, y1: p.y });
+                    last = (p.x, p.y);
+                }
+                FlattenedEvent::Close => {
+                    if last != start {
+                        edges.push(Edge { x0: last.0, y0: last.1, x1: start.0, y1: start.1 });
+                    }
+                    last = start;
+                }
+            }
+        }
+        if last != start {
+            edges.push(Edge { x0: last.0, y0: last.1, x1: start.0, y1: start.1 });
+        }
+
+        if edges.is_empty() {
+            return None;
+        }
+
+        let mut outline = Outline {
+            min_x: ::std::f32::MAX,
+            min_y: ::std::f32::MAX,
+            max_x: ::std::f32::MIN,
+            max_y: ::std::f32::MIN,
+            edges,
+        };
+        for e in &outline.edges {
+            outline.min_x = outline.min_x.min(e.x0).min(e.x1);
+            outline.min_y = outline.min_y.min(e.y0).min(e.y1);
+            outline.max_x = outline.max_x.max(e.x0).max(e.x1);
+            outline.max_y = outline.max_y.max(e.y0).max(e.y1);
+        }
+
+        if outline.max_x <= outline.min_x || outline.max_y <= outline.min_y {
+            return None;
+        }
+
+        Some(outline)
+    }
+}
+
+/// Flattens the outline to `points`, replacing their contents, with one range of `points`
+/// per contour in `contours`.
+///
+/// Contours are implicitly closed, a last point equal to the first one is left out.
+pub fn flatten_contours(path: &Path, tolerance: f32, points: &mut Vec<[f32; 2]>, contours: &mut Vec<Range<usize>>) {
+    points.clear();
+    contours.clear();
+    let mut start = 0;
+
+    let mut end_contour = |points: &mut Vec<[f32; 2]>, start: usize| {
+        if points.len() > start + 1 && points.last() == points.get(start) {
+            points.pop();
+        }
+        if points.len() > start {
+            contours.push(start..points.len());
+        }
+    };
+
+    for event in path.path_iter().flattened(tolerance) {
+        match event {
+            FlattenedEvent::MoveTo(p) => {
+                end_contour(points, start);
+                start = points.len();
+                points.push([p.x, p.y]);
+            }
+            FlattenedEvent::LineTo(p) => points.push([p.x, p.y]),
+            FlattenedEvent::Close => {
+                end_contour(points, start);
+                start = points.len();
+            }
+        }
+    }
+    end_contour(points, start);
+}
+
+/// Antialiased coverage, rows from top to bottom.
+pub struct Coverage {
+    pub width: u32,
+    pub height: u32,
+    /// Pixel column of the left edge relative to the glyph origin.
+    pub left: i32,
+    /// Pixel row of the top edge relative to the baseline, positive up.
+    pub top: i32,
+    pub data: Vec<f32>,
+}
+
+/// Rasterizes the outline with the non-zero fill rule.
+///
+/// `scale` converts font units to pixels, `origin` shifts the glyph origin within
+/// the pixel grid and `padding` adds empty pixels around the glyph.
+pub fn rasterize(outline: &Outline, scale: f32, origin: (f32, f32), padding: u32) -> Coverage {
+    let padding = padding as i32;
+    let left = (outline.min_x * scale + origin.0).floor() as i32 - padding;
+    let right = (outline.max_x * scale + origin.0).ceil() as i32 + padding;
+    let bottom = (outline.min_y * scale + origin.1).floor() as i32 - padding;
+    let top = (outline.max_y * scale + origin.1).ceil() as i32 + padding;
+
+    let width = (right - left) as usize;
+    let height = (top - bottom) as usize;
+    let mut data = vec![0.0; width * height];
+
+    // edges in bitmap space: x to the right from the left column, y down from the top row
+    let edges = outline.edges.iter()
+        .map(|e| Edge {
+            x0: e.x0 * scale + origin.0 - left as f32,
+            y0: top as f32 - (e.y0 * scale + origin.1),
+            x1: e.x1 * scale + origin.0 - left as f32,
+            y1: top as f32 - (e.y1 * scale + origin.1),
+        })
+        .filter(|e| e.y0 != e.y1)
+        .collect::<Vec<_>>();
+
+    let mut crossings: Vec<(f32, i32)> = Vec::new();
+    let sample_weight = 1.0 / SUBSAMPLES as f32;
+
+    for row in 0..height {
+        for sample in 0..SUBSAMPLES {
+            let y = row as f32 + (sample as f32 + 0.5) * sample_weight;
+
+            crossings.clear();
+            for e in &edges {
+                let (y_min, y_max, winding) = if e.y0 < e.y1 { (e.y0, e.y1, 1) } else { (e.y1, e.y0, -1) };
+                if y < y_min || y >= y_max {
+                    continue;
+                }
+                let t = (y - e.y0) / (e.y1 - e.y0);
+                crossings.push((e.x0 + t * (e.x1 - e.x0), winding));
+            }
+            crossings.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(::std::cmp::Ordering::Equal));
+
+            let mut winding = 0;
+            for pair in crossings.windows(2) {
+                winding += pair[0].1;
+                if winding != 0 {
+                    fill_span(&mut data[row * width..(row + 1) * width], pair[0].0, pair[1].0, sample_weight);
+                }
+            }
+        }
+    }
+
+    for value in &mut data {
+        *value = value.min(1.0);
+    }
+
+    Coverage {
+        width: width as u32,
+        height: height as u32,
+        left,
+        top,
+        data,
+    }
+}
+
+fn fill_span(row: &mut [f32], x0: f32, x1: f32, weight: f32) {
+    let x0 = x0.max(0.0);
+    let x1 = x1.min(row.len() as f32);
+    if x1 <= x0 {
+        return;
+    }
+
+    let first = x0.floor() as usize;
+    let last = (x1.ceil() as usize).min(row.len());
+    for (i, pixel) in row[first..last].iter_mut().enumerate() {
+        let px = (first + i) as f32;
+        let overlap = x1.min(px + 1.0) - x0.max(px);
+        *pixel += overlap.max(0.0) * weight;
+    }
+}
+
+/// Alpha coverage of a glyph, rows from top to bottom.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GlyphBitmap {
+    pub width: u32,
+    pub height: u32,
+    /// Pixels from the glyph origin to the left edge of the bitmap.
+    pub left: i32,
+    /// Pixels from the baseline up to the top edge of the bitmap.
+    pub top: i32,
+    /// Horizontal advance in pixels.
+    pub advance: f32,
+    pub data: Vec<u8>,
+}
+
+impl Coverage {
+    pub fn to_alpha(&self) -> Vec<u8> {
+        self.data.iter().map(|c| (c * 255.0).round() as u8).collect()
+    }
+}
+
+/// Signed distance field of a glyph, rows from top to bottom.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SdfBitmap {
+    pub width: u32,
+    pub height: u32,
+    /// Pixels from the glyph origin to the left edge of the bitmap.
+    pub left: i32,
+    /// Pixels from the baseline up to the top edge of the bitmap.
+    pub top: i32,
+    /// Distance in pixels that maps to the full value range on each side of the edge.
+    pub spread: f32,
+    pub data: Vec<u8>,
+}
+
+/// Packs the size bucket index with padding and spread into a cache variant, the step is
+/// the same for all entries of a cache.
+pub fn sdf_variant(size: SizeBucket, padding: u32, spread: f32) -> u64 {
+    let extra = padding.min(255) | ((spread.round().max(0.0).min(255.0) as u32) << 8);
+    u64::from(size.index()) | u64::from(extra) << 32
+}
+
+/// Converts coverage to a signed distance field, `128` on the edge and
+/// increasing inside, saturating at `spread` pixels.
+pub fn signed_distance_field(coverage: &Coverage, spread: f32) -> Vec<u8> {
+    let inside = coverage.data.iter().map(|c| *c >= 0.5).collect::<Vec<_>>();
+    let (width, height) = (coverage.width as usize, coverage.height as usize);
+
+    let to_outside = distance_transform(&inside, width, height, true);
+    let to_inside = distance_transform(&inside, width, height, false);
+
+    to_outside.iter().zip(to_inside.iter()).zip(inside.iter())
+        .map(|((out_d, in_d), is_inside)| {
+            let d = if *is_inside { *out_d - 0.5 } else { -(*in_d - 0.5) };
+            let value = 128.0 + d / spread.max(0.001) * 127.0;
+            value.max(0.0).min(255.0).round() as u8
+        })
+        .collect()
+}
+
+/// Euclidean distance from every `target` pixel to the nearest pixel of the other kind,
+/// zero for pixels that are not `target`; two pass 8-neighbour sweep.
+fn distance_transform(inside: &[bool], width: usize, height: usize, target: bool) -> Vec<f32> {
+    const FAR: i32 = 1 << 14;
+    let mut offsets = inside.iter()
+        .map(|v| if *v == target { (FAR, FAR) } else { (0, 0) })
+        .collect::<Vec<(i32, i32)>>();
+
+    let len2 = |o: (i32, i32)| o.0 * o.0 + o.1 * o.1;
+    let relax = |offsets: &mut Vec<(i32, i32)>, x: usize, y: usize, dx: i32, dy: i32| {
+        let nx = x as i32 + dx;
+        let ny = y as i32 + dy;
+        if nx < 0 || ny < 0 || nx >= width as i32 || ny >= height as i32 {
+            return;
+        }
+        let other = offsets[ny as usize * width + nx as usize];
+        let candidate = (other.0 + dx, other.1 + dy);
+        let current = &mut offsets[y * width + x];
+        if len2(candidate) < len2(*current) {
+            *current = candidate;
+        }
+    };
+
+    for y in 0..height {
+        for x in 0..width {
+            relax(&mut offsets, x, y, -1, 0);
+            relax(&mut offsets, x, y, 0, -1);
+            relax(&mut offsets, x, y, -1, -1);
+            relax(&mut offsets, x, y, 1, -1);
+        }
+        for x in (0..width).rev() {
+            relax(&mut offsets, x, y, 1, 0);
+        }
+    }
+    for y in (0..height).rev() {
+        for x in (0..width).rev() {
+            relax(&mut offsets, x, y, 1, 0);
+            relax(&mut offsets, x, y, 0, 1);
+            relax(&mut offsets, x, y, -1, 1);
+            relax(&mut offsets, x, y, 1, 1);
+        }
+        for x in 0..width {
+            relax(&mut offsets, x, y, -1, 0);
+        }
+    }
+
+    offsets.into_iter().map(|o| (len2(o) as f32).sqrt()).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use lyon_path::builder::{FlatPathBuilder, PathBuilder};
+    use lyon_path::math::point;
+
+    fn square(size: f32) -> Path {
+        let mut builder = Path::builder();
+        builder.move_to(point(0.0, 0.0));
+        builder.line_to(point(size, 0.0));
+        builder.line_to(point(size, size));
+        builder.line_to(point(0.0, size));
+        builder.close();
+        FlatPathBuilder::build(builder)
+    }
+
+    #[test]
+    fn square_should_be_fully_covered() {
+        let outline = Outline::from_path(&square(4.0), 0.1).unwrap();
+        let coverage = rasterize(&outline, 1.0, (0.0, 0.0), 1);
+
+        assert_eq!((coverage.width, coverage.height), (6, 6));
+        assert_eq!((coverage.left, coverage.top), (-1, 5));
+        assert!((coverage.data[2 * 6 + 2] - 1.0).abs() < 0.001);
+        assert_eq!(coverage.data[0], 0.0);
+    }
+
+    #[test]
+    fn origin_offset_should_shift_coverage() {
+        let outline = Outline::from_path(&square(2.0), 0.1).unwrap();
+        let coverage = rasterize(&outline, 1.0, (0.5, 0.0), 0);
+
+        assert_eq!((coverage.width, coverage.left), (3, 0));
+        assert_eq!(coverage.to_alpha()[0..3], [128, 255, 128]);
+    }
+
+    #[test]
+    fn contours_should_be_flattened_separately() {
+        let mut builder = Path::builder();
+        builder.move_to(point(0.0, 0.0));
+        builder.line_to(point(4.0, 0.0));
+        builder.line_to(point(4.0, 4.0));
+        builder.line_to(point(0.0, 0.0));
+        builder.close();
+        builder.move_to(point(10.0, 0.0));
+        builder.quadratic_bezier_to(point(12.0, 4.0), point(14.0, 0.0));
+        builder.close();
+        let path = FlatPathBuilder::build(builder);
+
+        let (mut points, mut contours) = (vec![[1.0, 1.0]], Vec::new());
+        flatten_contours(&path, 0.01, &mut points, &mut contours);
+
+        assert_eq!(contours.len(), 2);
+        assert_eq!(contours[0], 0..3);
+        assert_eq!(&points[contours[0].clone()], &[[0.0, 0.0], [4.0, 0.0], [4.0, 4.0]]);
+        assert_eq!(contours[1].start, 3);
+        assert_eq!(contours[1].end, points.len());
+        assert!(contours[1].len() > 4, "the curve should be split finely at a low tolerance");
+        assert_eq!(points[3], [10.0, 0.0]);
+        assert_eq!(points[points.len() - 1], [14.0, 0.0]);
+
+        let mut coarse = Vec::new();
+        flatten_contours(&path, 1.0, &mut coarse, &mut contours);
+        assert!(coarse.len() < points.len());
+    }
+
+    #[test]
+    fn empty_path_should_have_no_outline() {
+        let path = FlatPathBuilder::build(Path::builder());
+        assert!(Outline::from_path(&path, 0.1).is_none());
+    }
+
+    #[test]
+    fn distance_field_should_increase_towards_the_center() {
+        let outline = Outline::from_path(&square(8.0), 0.1).unwrap();
+        let coverage = rasterize(&outline, 1.0, (0.0, 0.0), 2);
+        let sdf = signed_distance_field(&coverage, 4.0);
+        let w = coverage.width as usize;
+
+        let corner = sdf[0];
+        let edge = sdf[6 * w + 2];
+        let center = sdf[6 * w + 6];
+        assert!(corner < 128);
+        assert!(edge >= 128);
+        assert!(center > edge);
+    }
+}
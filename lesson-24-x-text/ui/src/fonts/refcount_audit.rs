@@ -0,0 +1,156 @@
+//! Reference count bookkeeping of fonts and buffers in debug builds, see `Fonts::leak_report`.
+//!
+//! Every change of a count in the container is mirrored here with the call site that made it.
+//! Release builds get an empty audit with the same methods.
+
+#[cfg(debug_assertions)]
+use std::collections::BTreeMap;
+#[cfg(debug_assertions)]
+use std::fmt::Write;
+#[cfg(debug_assertions)]
+use std::panic::Location;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Kind {
+    Font,
+    Buffer,
+}
+
+#[cfg(debug_assertions)]
+struct Entry {
+    label: String,
+    created_at: &'static Location<'static>,
+    increments: usize,
+    decrements: usize,
+    /// Increments after creation by call site.
+    inc_sites: BTreeMap<&'static Location<'static>, usize>,
+}
+
+#[cfg(debug_assertions)]
+impl Entry {
+    fn balance(&self) -> isize {
+        self.increments as isize - self.decrements as isize
+    }
+}
+
+#[cfg(debug_assertions)]
+pub struct RefcountAudit {
+    entries: BTreeMap<(Kind, usize), Entry>,
+}
+
+#[cfg(debug_assertions)]
+impl RefcountAudit {
+    pub fn new() -> RefcountAudit {
+        RefcountAudit { entries: BTreeMap::new() }
+    }
+
+    /// Starts tracking a new font or buffer holding one reference.
+    #[track_caller]
+    pub fn created(&mut self, kind: Kind, id: usize, label: String) {
+        self.entries.insert((kind, id), Entry {
+            label,
+            created_at: Location::caller(),
+            increments: 1,
+            decrements: 0,
+            inc_sites: BTreeMap::new(),
+        });
+    }
+
+    #[track_caller]
+    pub fn inc(&mut self, kind: Kind, id: usize, count: usize) {
+        let site = Location::caller();
+        let entry = self.entries.get_mut(&(kind, id))
+            .unwrap_or_else(|| panic!("refcount audit: increment of untracked {:?} {} at {}", kind, id, site));
+        entry.increments += count;
+        *entry.inc_sites.entry(site).or_insert(0) += count;
+    }
+
+    pub fn dec(&mut self, kind: Kind, id: usize) {
+        if let Some(entry) = self.entries.get_mut(&(kind, id)) {
+            entry.decrements += 1;
+        }
+    }
+
+    /// Stops tracking a deleted font or buffer.
+    pub fn removed(&mut self, kind: Kind, id: usize) {
+        if let Some(entry) = self.entries.remove(&(kind, id)) {
+            debug_assert!(entry.balance() == 0, "refcount audit: {:?} {} deleted with a balance of {}", kind, id, entry.balance());
+        }
+    }
+
+    /// Fonts and buffers still holding references, with where they were created and where
+    /// their references were taken. `None` if there are none.
+    pub fn report(&self) -> Option<String> {
+        if self.entries.is_empty() {
+            return None;
+        }
+
+        let mut report = format!("{} fonts or buffers still referenced:", self.entries.len());
+        for (&(kind, id), entry) in &self.entries {
+            let kind = match kind {
+                Kind::Font => "font",
+                Kind::Buffer => "buffer",
+            };
+            let _ = write!(report, "\n  {} {} {:?} created at {}: {} references left ({} increments, {} decrements)",
+                kind, id, entry.label, entry.created_at, entry.balance(), entry.increments, entry.decrements);
+            for (site, count) in &entry.inc_sites {
+                let _ = write!(report, "\n    {} taken at {}", count, site);
+            }
+        }
+        Some(report)
+    }
+}
+
+#[cfg(not(debug_assertions))]
+pub struct RefcountAudit;
+
+#[cfg(not(debug_assertions))]
+impl RefcountAudit {
+    pub fn new() -> RefcountAudit {
+        RefcountAudit
+    }
+
+    #[inline]
+    pub fn created(&mut self, _kind: Kind, _id: usize, _label: String) {}
+
+    #[inline]
+    pub fn inc(&mut self, _kind: Kind, _id: usize, _count: usize) {}
+
+    #[inline]
+    pub fn dec(&mut self, _kind: Kind, _id: usize) {}
+
+    #[inline]
+    pub fn removed(&mut self, _kind: Kind, _id: usize) {}
+
+    pub fn report(&self) -> Option<String> {
+        None
+    }
+}
+
+#[cfg(all(test, debug_assertions))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn report_should_list_remaining_references_by_call_site() {
+        let mut audit = RefcountAudit::new();
+        audit.created(Kind::Font, 0, "Sans".to_string());
+        audit.created(Kind::Buffer, 3, "hello".to_string());
+        audit.inc(Kind::Font, 0, 2);
+        audit.dec(Kind::Font, 0);
+        audit.dec(Kind::Buffer, 3);
+        audit.removed(Kind::Buffer, 3);
+
+        let report = audit.report().expect("report");
+        assert!(report.starts_with("1 fonts or buffers still referenced:"));
+        assert!(report.contains("font 0 \"Sans\" created at"));
+        assert!(report.contains("2 references left (3 increments, 1 decrements)"));
+        assert!(report.contains(&format!("2 taken at {}", file!())));
+        assert!(!report.contains("hello"));
+
+        audit.dec(Kind::Font, 0);
+        audit.dec(Kind::Font, 0);
+        audit.removed(Kind::Font, 0);
+        assert_eq!(audit.report(), None);
+    }
+}
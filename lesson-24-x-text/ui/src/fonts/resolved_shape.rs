@@ -0,0 +1,215 @@
+//! Segment properties harfbuzz resolves for the text of a buffer, see `Buffer::resolved_properties`.
+
+#[cfg(not(feature = "simple-shaping"))]
+use byteorder::{BigEndian, ByteOrder};
+#[cfg(not(feature = "simple-shaping"))]
+use harfbuzz_rs as hb;
+use std::ops::Range;
+use super::{ShapedDirection, GlyphPosition};
+use super::bidi::BaseDirection;
+#[cfg(not(feature = "simple-shaping"))]
+use super::bidi::direction_runs;
+#[cfg(feature = "simple-shaping")]
+use super::simple_shaping;
+
+/// Script of text without letters of any script, and of empty text.
+pub const UNKNOWN_SCRIPT: [u8; 4] = *b"Zzzz";
+
+/// Direction, script and language a run of text was shaped with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ResolvedShape {
+    pub direction: ShapedDirection,
+    /// ISO 15924 tag, such as `Latn` or `Arab`.
+    pub script: [u8; 4],
+    /// BCP 47 tag, the process default language since it can not be guessed from text, and
+    /// empty with the simple shaper.
+    pub language: String,
+}
+
+/// Consecutive glyphs of a buffer with the same font, direction, script and highlight, see
+/// `Buffer::visual_runs`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VisualRun {
+    /// Indices of the glyphs in glyph order.
+    pub glyphs: Range<usize>,
+    /// Bytes of the source text the glyphs were shaped from.
+    pub byte_range: Range<usize>,
+    pub direction: ShapedDirection,
+    /// ISO 15924 tag, such as `Latn` or `Arab`.
+    pub script: [u8; 4],
+    pub font_id: usize,
+    /// Tag of the glyphs, see `Buffer::set_highlights`.
+    pub highlight: Option<u32>,
+}
+
+/// Properties of a run shaped with `direction`, guessed the way `hb::shape` guesses the
+/// properties left unset in its input.
+#[cfg(not(feature = "simple-shaping"))]
+fn guess(text: &str, direction: ShapedDirection) -> ResolvedShape {
+    let hb_direction = match direction {
+        ShapedDirection::LeftToRight => hb::Direction::Ltr,
+        ShapedDirection::RightToLeft => hb::Direction::Rtl,
+    };
+    let buffer = hb::UnicodeBuffer::new()
+        .add_str(text)
+        .set_direction(hb_direction)
+        .guess_segment_properties();
+
+    let mut script = [0; 4];
+    BigEndian::write_u32(&mut script, buffer.get_script().0);
+    if script == [0; 4] {
+        script = UNKNOWN_SCRIPT;
+    }
+
+    ResolvedShape {
+        direction,
+        script,
+        language: buffer.get_language().to_string(),
+    }
+}
+
+/// Properties of a run for the simple shaper, with the script of the first letter of a
+/// script it supports and no language.
+#[cfg(feature = "simple-shaping")]
+fn guess(text: &str, direction: ShapedDirection) -> ResolvedShape {
+    ResolvedShape {
+        direction,
+        script: text.chars().filter_map(simple_shaping::script).next().unwrap_or(UNKNOWN_SCRIPT),
+        language: String::new(),
+    }
+}
+
+/// Byte range and properties of each shaped run of `text` with paragraphs in `base` direction,
+/// in the order they were shaped.
+#[cfg(not(feature = "simple-shaping"))]
+pub fn resolve(text: &str, base: BaseDirection) -> Vec<(Range<usize>, ResolvedShape)> {
+    direction_runs(text, 0..text.len(), base).into_iter()
+        .map(|run| {
+            let direction = if run.rtl { ShapedDirection::RightToLeft } else { ShapedDirection::LeftToRight };
+            let shape = guess(&text[run.range.clone()], direction);
+            (run.range, shape)
+        })
+        .collect()
+}
+
+/// The simple shaper shapes all text left to right in one run.
+#[cfg(feature = "simple-shaping")]
+pub fn resolve(text: &str, _base: BaseDirection) -> Vec<(Range<usize>, ResolvedShape)> {
+    if text.is_empty() {
+        return Vec::new();
+    }
+    vec![(0..text.len(), guess(text, ShapedDirection::LeftToRight))]
+}
+
+/// Properties of the first run in text order, or of empty text if there are no runs.
+pub fn first_in_text_order(runs: &[(Range<usize>, ResolvedShape)]) -> ResolvedShape {
+    runs.iter()
+        .min_by_key(|&&(ref range, _)| range.start)
+        .map(|&(_, ref shape)| shape.clone())
+        .unwrap_or_else(|| guess("", ShapedDirection::LeftToRight))
+}
+
+/// Glyphs grouped by font, highlight and shaped run, left to right. `resolved` are the shaped
+/// runs of the text in visual order; spans of one run are reversed if it is right to left.
+pub fn visual_runs<F, H>(glyphs: &[GlyphPosition], resolved: &[(Range<usize>, ResolvedShape)], span_font_id: F, highlight_of: H) -> Vec<VisualRun>
+    where F: Fn(u32) -> usize, H: Fn(&GlyphPosition) -> Option<u32>
+{
+    let mut runs: Vec<(usize, VisualRun)> = Vec::new();
+    let mut shape_index = 0;
+
+    for (i, glyph) in glyphs.iter().enumerate() {
+        let cluster = glyph.cluster as usize;
+        if !resolved.get(shape_index).map_or(false, |&(ref range, _)| range.contains(&cluster)) {
+            shape_index = resolved.iter().position(|&(ref range, _)| range.contains(&cluster)).unwrap_or(shape_index);
+        }
+        let font_id = span_font_id(glyph.span);
+        let highlight = highlight_of(glyph);
+        let bytes = glyph.byte_offset as usize..(glyph.byte_offset + glyph.len) as usize;
+
+        match runs.last_mut() {
+            Some(&mut (index, ref mut run)) if index == shape_index && run.font_id == font_id && run.highlight == highlight && run.glyphs.end == i => {
+                run.glyphs.end = i + 1;
+                run.byte_range.start = run.byte_range.start.min(bytes.start);
+                run.byte_range.end = run.byte_range.end.max(bytes.end);
+            }
+            _ => {
+                let shape = resolved.get(shape_index).map_or_else(|| first_in_text_order(&[]), |&(_, ref shape)| shape.clone());
+                runs.push((shape_index, VisualRun {
+                    glyphs: i..i + 1,
+                    byte_range: bytes,
+                    direction: shape.direction,
+                    script: shape.script,
+                    font_id,
+                    highlight,
+                }));
+            }
+        }
+    }
+
+    // spans are shaped one after the other in text order
+    runs.sort_by_key(|&(index, ref run)| match run.direction {
+        ShapedDirection::LeftToRight => (index, run.byte_range.start),
+        ShapedDirection::RightToLeft => (index, usize::max_value() - run.byte_range.start),
+    });
+    runs.into_iter().map(|(_, run)| run).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn script_should_be_guessed_per_run() {
+        let runs = resolve("abc", BaseDirection::FirstStrong);
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0].0, 0..3);
+        assert_eq!((runs[0].1.direction, runs[0].1.script), (ShapedDirection::LeftToRight, *b"Latn"));
+        assert_eq!(first_in_text_order(&runs), runs[0].1);
+    }
+
+    #[test]
+    #[cfg(not(feature = "simple-shaping"))]
+    fn right_to_left_runs_should_have_their_own_properties() {
+        let text = "abc שלום";
+        let runs = resolve(text, BaseDirection::FirstStrong);
+        let hebrew = runs.iter().find(|&&(_, ref shape)| shape.direction == ShapedDirection::RightToLeft).expect("rtl run");
+        assert_eq!(hebrew.1.script, *b"Hebr");
+        assert_eq!(first_in_text_order(&runs).script, *b"Latn");
+    }
+
+    fn glyph(cluster: u32, span: u32) -> GlyphPosition {
+        GlyphPosition { id: 1, cluster, byte_offset: cluster, len: 1, x_advance: 0, y_advance: 0, x_offset: 0, y_offset: 0, span, flags: Default::default() }
+    }
+
+    #[test]
+    fn spans_of_a_right_to_left_run_should_be_reversed() {
+        let ltr = ResolvedShape { direction: ShapedDirection::LeftToRight, script: *b"Latn", language: String::new() };
+        let rtl = ResolvedShape { direction: ShapedDirection::RightToLeft, script: *b"Hebr", language: String::new() };
+        let resolved = vec![(0..2, ltr), (2..6, rtl)];
+        // the second span starts at byte 4, each span in visual order
+        let glyphs = vec![glyph(0, 0), glyph(1, 0), glyph(3, 0), glyph(2, 0), glyph(5, 1), glyph(4, 1)];
+
+        let runs = visual_runs(&glyphs, &resolved, |span| span as usize + 10, |_| None);
+        assert_eq!(runs.iter().map(|run| (run.glyphs.clone(), run.byte_range.clone(), run.font_id)).collect::<Vec<_>>(),
+            vec![(0..2, 0..2, 10), (4..6, 4..6, 11), (2..4, 2..4, 10)]);
+        assert_eq!(runs[2].script, *b"Hebr");
+    }
+
+    #[test]
+    fn highlights_should_split_runs() {
+        let ltr = ResolvedShape { direction: ShapedDirection::LeftToRight, script: *b"Latn", language: String::new() };
+        let glyphs = vec![glyph(0, 0), glyph(1, 0), glyph(2, 0), glyph(3, 0)];
+
+        let runs = visual_runs(&glyphs, &[(0..4, ltr)], |_| 0, |glyph| if glyph.cluster == 1 { Some(7) } else { None });
+        assert_eq!(runs.iter().map(|run| (run.glyphs.clone(), run.highlight)).collect::<Vec<_>>(),
+            vec![(0..1, None), (1..2, Some(7)), (2..4, None)]);
+    }
+
+    #[test]
+    fn empty_text_should_have_the_unknown_script() {
+        assert!(resolve("", BaseDirection::FirstStrong).is_empty());
+        let shape = first_in_text_order(&[]);
+        assert_eq!((shape.direction, shape.script), (ShapedDirection::LeftToRight, UNKNOWN_SCRIPT));
+    }
+}
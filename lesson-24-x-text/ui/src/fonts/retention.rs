@@ -0,0 +1,132 @@
+//! Fonts kept loaded after their last reference, see `Fonts::set_font_retention`.
+
+use std::time::{Duration, Instant};
+
+/// How long a font stays loaded after its last reference is released.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FontRetention {
+    /// Unload at once, the default.
+    None,
+    Duration(Duration),
+    /// Number of `Fonts::end_frame` calls.
+    Frames(u32),
+}
+
+impl Default for FontRetention {
+    fn default() -> Self {
+        FontRetention::None
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Retained {
+    id: usize,
+    released_at: Instant,
+    released_frame: u64,
+}
+
+/// Fonts without references in release order.
+#[derive(Debug, Clone, Default)]
+pub struct RetainedFonts {
+    retention: FontRetention,
+    frame: u64,
+    fonts: Vec<Retained>,
+}
+
+impl RetainedFonts {
+    pub fn new() -> RetainedFonts {
+        RetainedFonts::default()
+    }
+
+    pub fn retention(&self) -> FontRetention {
+        self.retention
+    }
+
+    pub fn set_retention(&mut self, retention: FontRetention) {
+        self.retention = retention;
+    }
+
+    /// Keeps a released font, `false` if fonts are not retained.
+    pub fn retain(&mut self, id: usize, now: Instant) -> bool {
+        if self.retention == FontRetention::None {
+            return false;
+        }
+        self.fonts.push(Retained { id, released_at: now, released_frame: self.frame });
+        true
+    }
+
+    /// Stops retaining a font that got a reference again.
+    pub fn revive(&mut self, id: usize) {
+        self.fonts.retain(|font| font.id != id);
+    }
+
+    pub fn len(&self) -> usize {
+        self.fonts.len()
+    }
+
+    pub fn next_frame(&mut self) {
+        self.frame += 1;
+    }
+
+    /// Fonts whose retention ended at `now`, no longer retained.
+    pub fn take_expired(&mut self, now: Instant) -> Vec<usize> {
+        let (retention, frame) = (self.retention, self.frame);
+        let expired = |font: &Retained| match retention {
+            FontRetention::None => true,
+            FontRetention::Duration(duration) => now.duration_since(font.released_at) >= duration,
+            FontRetention::Frames(frames) => frame - font.released_frame >= frames as u64,
+        };
+        let (expired, kept): (Vec<_>, Vec<_>) = self.fonts.drain(..).partition(expired);
+        self.fonts = kept;
+        expired.into_iter().map(|font| font.id).collect()
+    }
+
+    /// The font released first, no longer retained.
+    pub fn take_oldest(&mut self) -> Option<usize> {
+        if self.fonts.is_empty() {
+            None
+        } else {
+            Some(self.fonts.remove(0).id)
+        }
+    }
+
+    pub fn take_all(&mut self) -> Vec<usize> {
+        self.fonts.drain(..).map(|font| font.id).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn fonts_should_expire_after_their_frames() {
+        let now = Instant::now();
+        let mut retained = RetainedFonts::new();
+        assert!(!retained.retain(1, now));
+
+        retained.set_retention(FontRetention::Frames(2));
+        assert!(retained.retain(1, now));
+        retained.next_frame();
+        assert!(retained.retain(2, now));
+        retained.next_frame();
+        assert_eq!(retained.take_expired(now), vec![1]);
+        retained.next_frame();
+        assert_eq!(retained.take_expired(now), vec![2]);
+        assert_eq!(retained.len(), 0);
+    }
+
+    #[test]
+    fn revived_fonts_should_not_expire() {
+        let now = Instant::now();
+        let mut retained = RetainedFonts::new();
+        retained.set_retention(FontRetention::Duration(Duration::from_millis(100)));
+        retained.retain(1, now);
+        retained.retain(2, now);
+        retained.revive(1);
+
+        assert!(retained.take_expired(now + Duration::from_millis(50)).is_empty());
+        assert_eq!(retained.take_expired(now + Duration::from_millis(100)), vec![2]);
+        assert_eq!(retained.len(), 0);
+    }
+}
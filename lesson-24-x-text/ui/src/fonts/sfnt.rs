@@ -0,0 +1,127 @@
+//! Reading of OpenType tables from font file bytes.
+
+pub fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    let bytes = data.get(offset..offset + 2)?;
+    Some(u16::from(bytes[0]) << 8 | u16::from(bytes[1]))
+}
+
+pub fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from(bytes[0]) << 24 | u32::from(bytes[1]) << 16 | u32::from(bytes[2]) << 8 | u32::from(bytes[3]))
+}
+
+/// Index of a glyph in a coverage table, `None` if the glyph is not covered.
+pub fn coverage_index(coverage: &[u8], glyph_id: u32) -> Option<usize> {
+    let count = read_u16(coverage, 2)? as usize;
+    match read_u16(coverage, 0)? {
+        1 => (0..count).find(|i| read_u16(coverage, 4 + i * 2).map(u32::from) == Some(glyph_id)),
+        2 => (0..count).find_map(|i| {
+            let record = 4 + i * 6;
+            let (start, end) = (u32::from(read_u16(coverage, record)?), u32::from(read_u16(coverage, record + 2)?));
+            if start <= glyph_id && glyph_id <= end {
+                Some(read_u16(coverage, record + 4)? as usize + (glyph_id - start) as usize)
+            } else {
+                None
+            }
+        }),
+        _ => None,
+    }
+}
+
+/// Offset of the table directory of the face at `face_index`.
+fn face_offset(data: &[u8], face_index: u32) -> Option<usize> {
+    if data.get(0..4)? == b"ttcf" {
+        let num_fonts = read_u32(data, 8)?;
+        if face_index >= num_fonts {
+            return None;
+        }
+        Some(read_u32(data, 12 + 4 * face_index as usize)? as usize)
+    } else {
+        Some(0)
+    }
+}
+
+/// Class of a glyph in a class definition table, `0` for glyphs it does not list.
+pub fn class_of(class_def: &[u8], glyph_id: u32) -> Option<u16> {
+    match read_u16(class_def, 0)? {
+        1 => {
+            let start = u32::from(read_u16(class_def, 2)?);
+            let count = u32::from(read_u16(class_def, 4)?);
+            if glyph_id < start || glyph_id >= start + count {
+                return Some(0);
+            }
+            read_u16(class_def, 6 + (glyph_id - start) as usize * 2)
+        }
+        2 => {
+            let count = read_u16(class_def, 2)? as usize;
+            for i in 0..count {
+                let record = 4 + i * 6;
+                let (start, end) = (u32::from(read_u16(class_def, record)?), u32::from(read_u16(class_def, record + 2)?));
+                if start <= glyph_id && glyph_id <= end {
+                    return read_u16(class_def, record + 4);
+                }
+            }
+            Some(0)
+        }
+        _ => None,
+    }
+}
+
+/// Finds a table of the face at `face_index`, also in font collections.
+pub fn find_table<'a>(data: &'a [u8], face_index: u32, tag: &[u8; 4]) -> Option<&'a [u8]> {
+    let face_offset = face_offset(data, face_index)?;
+    let num_tables = read_u16(data, face_offset + 4)? as usize;
+    for i in 0..num_tables {
+        let record = face_offset + 12 + i * 16;
+        if data.get(record..record + 4)? == tag {
+            let offset = read_u32(data, record + 8)? as usize;
+            let length = read_u32(data, record + 12)? as usize;
+            return data.get(offset..offset + length);
+        }
+    }
+
+    None
+}
+
+/// Tags and bytes of all tables of the face at `face_index`, in directory order, `None` if
+/// a table is out of bounds.
+pub fn tables(data: &[u8], face_index: u32) -> Option<Vec<([u8; 4], &[u8])>> {
+    let face_offset = face_offset(data, face_index)?;
+    let num_tables = read_u16(data, face_offset + 4)? as usize;
+    (0..num_tables).map(|i| {
+        let record = face_offset + 12 + i * 16;
+        let mut tag = [0; 4];
+        tag.copy_from_slice(data.get(record..record + 4)?);
+        let offset = read_u32(data, record + 8)? as usize;
+        let length = read_u32(data, record + 12)? as usize;
+        Some((tag, data.get(offset..offset + length)?))
+    }).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_find_table_in_single_face() {
+        let mut data = vec![0, 1, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+        data.extend_from_slice(b"test");
+        data.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 28, 0, 0, 0, 2]);
+        data.extend_from_slice(&[7, 9]);
+
+        assert_eq!(find_table(&data, 0, b"test"), Some(&[7u8, 9][..]));
+        assert_eq!(find_table(&data, 0, b"none"), None);
+        assert_eq!(tables(&data, 0), Some(vec![(*b"test", &[7u8, 9][..])]));
+    }
+
+    #[test]
+    fn should_find_glyphs_in_coverage_lists_and_ranges() {
+        let list = [0, 1, 0, 2, 0, 4, 0, 9];
+        assert_eq!(coverage_index(&list, 9), Some(1));
+        assert_eq!(coverage_index(&list, 5), None);
+
+        let ranges = [0, 2, 0, 1, 0, 10, 0, 20, 0, 0];
+        assert_eq!(coverage_index(&ranges, 12), Some(2));
+        assert_eq!(coverage_index(&ranges, 21), None);
+    }
+}
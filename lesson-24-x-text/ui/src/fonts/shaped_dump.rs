@@ -0,0 +1,69 @@
+use super::GlyphPosition;
+
+/// Order of the shaped glyphs relative to the text.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ShapedDirection {
+    LeftToRight,
+    RightToLeft,
+}
+
+/// Shaped output of a buffer that can be recorded and loaded back without shaping,
+/// see `Buffer::shaped_dump` and `Fonts::load_shaped_dump`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ShapedDump {
+    /// Full name of the buffer font, used to find it among the loaded fonts.
+    pub font: String,
+    pub text: String,
+    pub direction: ShapedDirection,
+    pub positions: Vec<GlyphPosition>,
+}
+
+/// Dump rejected by `Fonts::load_shaped_dump`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShapedDumpError {
+    /// No loaded font has the full name of the dump font.
+    FontNotLoaded(String),
+    /// The id of glyph `glyph` is not a glyph of the font.
+    GlyphOutOfRange { glyph: usize, id: u32 },
+    /// The cluster of glyph `glyph` is past the end of the text or inside a UTF-8 sequence.
+    InvalidCluster { glyph: usize, cluster: u32 },
+    /// Glyph `glyph` belongs to a span; dumps are of buffers shaped with a single font.
+    UnexpectedSpan { glyph: usize, span: u32 },
+    /// `direction` does not match the order of the glyph clusters.
+    DirectionMismatch,
+}
+
+impl ShapedDump {
+    /// Checks the glyphs against the text and a font of `glyph_count` glyphs, since dumps
+    /// edited by hand or recorded with another font version may not match them.
+    pub fn validate(&self, glyph_count: u32) -> Result<(), ShapedDumpError> {
+        for (index, glyph) in self.positions.iter().enumerate() {
+            if glyph.id >= glyph_count {
+                return Err(ShapedDumpError::GlyphOutOfRange { glyph: index, id: glyph.id });
+            }
+            let cluster = glyph.cluster as usize;
+            if cluster > self.text.len() || !self.text.is_char_boundary(cluster) {
+                return Err(ShapedDumpError::InvalidCluster { glyph: index, cluster: glyph.cluster });
+            }
+            if glyph.span != 0 {
+                return Err(ShapedDumpError::UnexpectedSpan { glyph: index, span: glyph.span });
+            }
+        }
+        if ShapedDirection::of_glyphs(&self.positions) != self.direction {
+            return Err(ShapedDumpError::DirectionMismatch);
+        }
+        Ok(())
+    }
+}
+
+impl ShapedDirection {
+    /// Right to left if the first glyph belongs to a later cluster than the last one.
+    pub fn of_glyphs(glyphs: &[GlyphPosition]) -> ShapedDirection {
+        match (glyphs.first(), glyphs.last()) {
+            (Some(first), Some(last)) if first.cluster > last.cluster => ShapedDirection::RightToLeft,
+            _ => ShapedDirection::LeftToRight,
+        }
+    }
+}
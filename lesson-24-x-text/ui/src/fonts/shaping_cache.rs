@@ -0,0 +1,651 @@
+use std::hash::Hasher;
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+#[cfg(not(feature = "simple-shaping"))]
+use harfbuzz_rs as hb;
+use metrohash::{MetroHashMap, MetroHash64};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use super::profiling::{Profiler, ProfiledOperation};
+
+pub const DEFAULT_SHAPING_CACHE_CAPACITY: usize = 1024;
+
+/// Max number of cleared harfbuzz input buffers kept for reuse.
+#[cfg(not(feature = "simple-shaping"))]
+const UNICODE_BUFFER_POOL_SIZE: usize = 4;
+
+const EXPORT_MAGIC: &[u8; 4] = b"UISC";
+/// Version 2 adds the fingerprint format, version 1 exports have SHA1 fingerprints. Version 3
+/// hashes the bytes of texts and features instead of their `Hash` output, which may change
+/// between Rust versions. Version 4 adds the shaping settings a features hash stands for.
+const EXPORT_VERSION: u32 = 4;
+const FINGERPRINT_FORMAT_SHA1: u32 = 1;
+
+/// Glyph info and position of a harfbuzz shape call output.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ShapedGlyph {
+    pub codepoint: u32,
+    pub cluster: u32,
+    pub mask: u32,
+    pub x_advance: i32,
+    pub y_advance: i32,
+    pub x_offset: i32,
+    pub y_offset: i32,
+}
+
+/// Glyph output of a harfbuzz shape call.
+pub struct ShapedGlyphs {
+    pub glyphs: Vec<ShapedGlyph>,
+}
+
+impl ShapedGlyphs {
+    #[cfg(not(feature = "simple-shaping"))]
+    pub fn from_buffer(buffer: &hb::GlyphBuffer) -> ShapedGlyphs {
+        ShapedGlyphs {
+            glyphs: buffer.get_glyph_infos().iter().zip(buffer.get_glyph_positions().iter())
+                .map(|(info, position)| ShapedGlyph {
+                    codepoint: info.codepoint,
+                    cluster: info.cluster,
+                    mask: info.mask,
+                    x_advance: position.x_advance,
+                    y_advance: position.y_advance,
+                    x_offset: position.x_offset,
+                    y_offset: position.y_offset,
+                })
+                .collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.glyphs.len()
+    }
+}
+
+/// Cache entry read by `ShapingCache::read_entries`, keyed by font fingerprint.
+pub struct ImportedEntry {
+    pub fingerprint: [u8; 20],
+    pub features: Vec<u8>,
+    pub text: String,
+    pub glyphs: ShapedGlyphs,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+struct ShapingKey {
+    font_id: usize,
+    text_hash: u64,
+    /// Hash of the encoded shaping settings, 0 without any.
+    features: u64,
+}
+
+struct ShapingEntry {
+    text: String,
+    /// Encoded shaping settings, compared on lookup as hashes may collide.
+    features: Vec<u8>,
+    glyphs: Rc<ShapedGlyphs>,
+    last_used: u64,
+}
+
+impl ShapingEntry {
+    fn bytes(&self) -> usize {
+        use std::mem::size_of;
+        size_of::<(ShapingKey, ShapingEntry)>() + self.text.capacity() + self.features.capacity() + self.glyphs.glyphs.capacity() * size_of::<ShapedGlyph>()
+    }
+}
+
+/// Counters of `Fonts::shaping_cache_stats`.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct ShapingCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+    /// Number of harfbuzz shape calls, cached or not.
+    pub shape_calls: u64,
+    /// Number of harfbuzz input buffers allocated because the pool was empty.
+    pub unicode_buffer_allocations: u64,
+    /// Words and spaces found in the word cache, see `Fonts::set_word_cache`.
+    pub word_hits: u64,
+    pub word_misses: u64,
+    pub word_entries: usize,
+}
+
+impl ShapingCacheStats {
+    /// Share of words found in the word cache, `None` before any word lookup.
+    pub fn word_hit_rate(&self) -> Option<f32> {
+        let lookups = self.word_hits + self.word_misses;
+        if lookups == 0 {
+            None
+        } else {
+            Some(self.word_hits as f32 / lookups as f32)
+        }
+    }
+}
+
+/// Cleared harfbuzz input buffers shared by all shape calls of a container, always empty
+/// with the simple shaper.
+pub struct UnicodeBufferPool {
+    #[cfg(not(feature = "simple-shaping"))]
+    buffers: Vec<hb::UnicodeBuffer>,
+    allocations: u64,
+}
+
+impl UnicodeBufferPool {
+    pub fn new() -> UnicodeBufferPool {
+        UnicodeBufferPool {
+            #[cfg(not(feature = "simple-shaping"))]
+            buffers: Vec::new(),
+            allocations: 0,
+        }
+    }
+
+    #[cfg(not(feature = "simple-shaping"))]
+    pub fn take(&mut self) -> hb::UnicodeBuffer {
+        match self.buffers.pop() {
+            Some(buffer) => buffer,
+            None => {
+                self.allocations += 1;
+                hb::UnicodeBuffer::new()
+            },
+        }
+    }
+
+    /// Returns the input buffer recovered from a shape call output.
+    #[cfg(not(feature = "simple-shaping"))]
+    pub fn put(&mut self, output: hb::GlyphBuffer) {
+        if self.buffers.len() < UNICODE_BUFFER_POOL_SIZE {
+            self.buffers.push(output.clear());
+        }
+    }
+}
+
+/// Shaped output of whole single font texts, evicting the least recently used entry.
+///
+/// With the word cache enabled, words are kept apart from whole texts with the same capacity.
+pub struct ShapingCache {
+    entries: MetroHashMap<ShapingKey, ShapingEntry>,
+    words: MetroHashMap<ShapingKey, ShapingEntry>,
+    word_cache: bool,
+    capacity: usize,
+    tick: u64,
+    stats: ShapingCacheStats,
+    pool: UnicodeBufferPool,
+    /// Times shape calls, shared with the container for outline and raster work.
+    profiler: Rc<Profiler>,
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Hash of the UTF-8 bytes of a text, stable across Rust versions as it is exported.
+fn text_hash(text: &str) -> u64 {
+    let mut hasher = MetroHash64::default();
+    hasher.write(text.as_bytes());
+    hasher.finish()
+}
+
+/// Key hash of encoded shaping settings, 0 without any.
+fn features_hash(features: &[u8]) -> u64 {
+    if features.is_empty() {
+        return 0;
+    }
+    let mut hasher = MetroHash64::default();
+    hasher.write(features);
+    hasher.finish()
+}
+
+/// Words and single spaces of a text with their byte offsets.
+fn word_pieces(text: &str) -> Vec<(usize, &str)> {
+    let mut pieces = Vec::new();
+    let mut start = 0;
+
+    for (i, _) in text.match_indices(' ') {
+        if i > start {
+            pieces.push((start, &text[start..i]));
+        }
+        pieces.push((i, &text[i..i + 1]));
+        start = i + 1;
+    }
+    if start < text.len() {
+        pieces.push((start, &text[start..]));
+    }
+
+    pieces
+}
+
+/// Least recently used entry of a map.
+fn oldest(entries: &MetroHashMap<ShapingKey, ShapingEntry>) -> Option<(ShapingKey, u64)> {
+    entries.iter()
+        .min_by_key(|(_, entry)| entry.last_used)
+        .map(|(key, entry)| (*key, entry.last_used))
+}
+
+fn evict_map(entries: &mut MetroHashMap<ShapingKey, ShapingEntry>, capacity: usize) {
+    while entries.len() > capacity {
+        match oldest(entries) {
+            Some((key, _)) => entries.remove(&key),
+            None => break,
+        };
+    }
+}
+
+impl ShapingCache {
+    pub fn new(capacity: usize) -> ShapingCache {
+        ShapingCache {
+            entries: MetroHashMap::default(),
+            words: MetroHashMap::default(),
+            word_cache: false,
+            capacity,
+            tick: 0,
+            stats: ShapingCacheStats::default(),
+            pool: UnicodeBufferPool::new(),
+            profiler: Rc::new(Profiler::new()),
+        }
+    }
+
+    /// Returns cached glyphs or calls `shape` and caches the result.
+    ///
+    /// `features` are the encoded shaping settings the glyphs depend on besides the font and
+    /// text, empty for the defaults.
+    pub fn get_or_shape<F>(&mut self, font_id: usize, text: &str, features: &[u8], shape: F) -> Rc<ShapedGlyphs>
+        where F: FnOnce(&mut UnicodeBufferPool) -> ShapedGlyphs {
+        self.tick += 1;
+        let key = ShapingKey { font_id, text_hash: text_hash(text), features: features_hash(features) };
+
+        if let Some(entry) = self.entries.get_mut(&key) {
+            if entry.text == text && entry.features == features {
+                entry.last_used = self.tick;
+                self.stats.hits += 1;
+                return entry.glyphs.clone();
+            }
+        }
+
+        self.stats.misses += 1;
+        let glyphs = Rc::new(self.shape(font_id, text.len(), shape));
+
+        if self.capacity > 0 {
+            self.entries.insert(key, ShapingEntry { text: text.to_string(), features: features.to_vec(), glyphs: glyphs.clone(), last_used: self.tick });
+            self.evict();
+        }
+
+        glyphs
+    }
+
+    pub fn word_cache(&self) -> bool {
+        self.word_cache
+    }
+
+    /// Enables `get_or_shape_words` for buffers, disabling it drops the cached words.
+    pub fn set_word_cache(&mut self, enabled: bool) {
+        self.word_cache = enabled;
+        if !enabled {
+            self.words.clear();
+        }
+    }
+
+    /// Shapes each word and each space of `text` on its own with `shape`, reusing cached words,
+    /// and joins the glyphs with clusters relative to `text`.
+    ///
+    /// This is an approximation: there is no kerning or other shaping across word boundaries.
+    pub fn get_or_shape_words<F>(&mut self, font_id: usize, text: &str, features: &[u8], mut shape: F) -> Rc<ShapedGlyphs>
+        where F: FnMut(&mut UnicodeBufferPool, &str) -> ShapedGlyphs {
+        let mut glyphs = Vec::new();
+        let features_hash = features_hash(features);
+
+        for (start, word) in word_pieces(text) {
+            self.tick += 1;
+            let key = ShapingKey { font_id, text_hash: text_hash(word), features: features_hash };
+
+            let cached = match self.words.get_mut(&key) {
+                Some(ref mut entry) if entry.text == word && entry.features == features => {
+                    entry.last_used = self.tick;
+                    Some(entry.glyphs.clone())
+                }
+                _ => None,
+            };
+
+            let shaped = match cached {
+                Some(shaped) => {
+                    self.stats.word_hits += 1;
+                    shaped
+                }
+                None => {
+                    self.stats.word_misses += 1;
+                    self.stats.shape_calls += 1;
+                    let pool = &mut self.pool;
+                    let shaped = self.profiler.time(ProfiledOperation::Shape, font_id, || shape(pool, word), |shaped| (word.len(), shaped.len()));
+                    let shaped = Rc::new(shaped);
+                    if self.capacity > 0 {
+                        self.words.insert(key, ShapingEntry { text: word.to_string(), features: features.to_vec(), glyphs: shaped.clone(), last_used: self.tick });
+                        evict_map(&mut self.words, self.capacity);
+                    }
+                    shaped
+                }
+            };
+
+            glyphs.extend(shaped.glyphs.iter().map(|glyph| ShapedGlyph { cluster: glyph.cluster + start as u32, ..*glyph }));
+        }
+
+        Rc::new(ShapedGlyphs { glyphs })
+    }
+
+    /// Calls `shape` with the input buffer pool without caching, counting the call and timing
+    /// it as shaping `text_len` bytes with `font_id`.
+    pub fn shape<F: FnOnce(&mut UnicodeBufferPool) -> ShapedGlyphs>(&mut self, font_id: usize, text_len: usize, shape: F) -> ShapedGlyphs {
+        self.stats.shape_calls += 1;
+        let pool = &mut self.pool;
+        self.profiler.time(ProfiledOperation::Shape, font_id, || shape(pool), |shaped| (text_len, shaped.len()))
+    }
+
+    pub fn profiler(&self) -> &Rc<Profiler> {
+        &self.profiler
+    }
+
+    /// Adds shaped glyphs without counting a miss, replacing an entry with the same key.
+    pub fn insert(&mut self, font_id: usize, text: String, features: Vec<u8>, glyphs: ShapedGlyphs) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        self.tick += 1;
+        let key = ShapingKey { font_id, text_hash: text_hash(&text), features: features_hash(&features) };
+        self.entries.insert(key, ShapingEntry { text, features, glyphs: Rc::new(glyphs), last_used: self.tick });
+        self.evict();
+    }
+
+    /// Writes all entries whose font has a fingerprint of `fingerprint_format`, most recently
+    /// used last.
+    ///
+    /// Returns the number of written entries.
+    pub fn write_entries<W, F>(&self, writer: &mut W, fingerprint_format: u32, fingerprint: F) -> io::Result<usize>
+        where W: Write, F: Fn(usize) -> Option<[u8; 20]> {
+        let mut entries = self.entries.iter()
+            .filter_map(|(key, entry)| fingerprint(key.font_id).map(|fingerprint| (fingerprint, key, entry)))
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|(_, _, entry)| entry.last_used);
+
+        writer.write_all(EXPORT_MAGIC)?;
+        writer.write_u32::<LittleEndian>(EXPORT_VERSION)?;
+        writer.write_u32::<LittleEndian>(fingerprint_format)?;
+        writer.write_u32::<LittleEndian>(entries.len() as u32)?;
+
+        for (fingerprint, key, entry) in &entries {
+            writer.write_all(fingerprint)?;
+            writer.write_u64::<LittleEndian>(key.features)?;
+            writer.write_u32::<LittleEndian>(entry.features.len() as u32)?;
+            writer.write_all(&entry.features)?;
+            writer.write_u64::<LittleEndian>(key.text_hash)?;
+            writer.write_u32::<LittleEndian>(entry.text.len() as u32)?;
+            writer.write_all(entry.text.as_bytes())?;
+            writer.write_u32::<LittleEndian>(entry.glyphs.len() as u32)?;
+
+            for glyph in &entry.glyphs.glyphs {
+                writer.write_u32::<LittleEndian>(glyph.codepoint)?;
+                writer.write_u32::<LittleEndian>(glyph.cluster)?;
+                writer.write_u32::<LittleEndian>(glyph.mask)?;
+                writer.write_i32::<LittleEndian>(glyph.x_advance)?;
+                writer.write_i32::<LittleEndian>(glyph.y_advance)?;
+                writer.write_i32::<LittleEndian>(glyph.x_offset)?;
+                writer.write_i32::<LittleEndian>(glyph.y_offset)?;
+            }
+        }
+
+        Ok(entries.len())
+    }
+
+    /// Reads all entries written by `write_entries`, failing on any malformed entry.
+    ///
+    /// Exports with fingerprints of another format than `fingerprint_format` can not match
+    /// any font and read as no entries.
+    pub fn read_entries<R: Read>(reader: &mut R, fingerprint_format: u32) -> io::Result<Vec<ImportedEntry>> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != EXPORT_MAGIC {
+            return Err(invalid_data("not a shaping cache export"));
+        }
+        let version = reader.read_u32::<LittleEndian>()?;
+        let format = match version {
+            1 => FINGERPRINT_FORMAT_SHA1,
+            2 | 3 | EXPORT_VERSION => reader.read_u32::<LittleEndian>()?,
+            _ => return Err(invalid_data("unsupported shaping cache export version")),
+        };
+        if format != fingerprint_format {
+            return Ok(Vec::new());
+        }
+
+        let count = reader.read_u32::<LittleEndian>()?;
+        let mut entries = Vec::new();
+
+        for _ in 0..count {
+            let mut fingerprint = [0; 20];
+            reader.read_exact(&mut fingerprint)?;
+            let features_key = reader.read_u64::<LittleEndian>()?;
+            // older versions only have the hash, entries with shaping settings can't be checked
+            let mut features = Vec::new();
+            let mut keep = features_key == 0;
+            if version == EXPORT_VERSION {
+                let features_len = reader.read_u32::<LittleEndian>()? as u64;
+                if reader.by_ref().take(features_len).read_to_end(&mut features)? as u64 != features_len {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                if features_hash(&features) != features_key {
+                    return Err(invalid_data("entry features do not match their hash"));
+                }
+                keep = true;
+            }
+            let hash = reader.read_u64::<LittleEndian>()?;
+
+            let text_len = reader.read_u32::<LittleEndian>()? as u64;
+            let mut text = Vec::new();
+            if reader.by_ref().take(text_len).read_to_end(&mut text)? as u64 != text_len {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            let text = String::from_utf8(text).map_err(|_| invalid_data("entry text is not utf-8"))?;
+            // older versions hashed with `Hash`, so their hashes can't be checked
+            if version >= 3 && text_hash(&text) != hash {
+                return Err(invalid_data("entry text does not match its hash"));
+            }
+
+            let glyph_count = reader.read_u32::<LittleEndian>()?;
+            let mut glyphs = Vec::new();
+            for _ in 0..glyph_count {
+                glyphs.push(ShapedGlyph {
+                    codepoint: reader.read_u32::<LittleEndian>()?,
+                    cluster: reader.read_u32::<LittleEndian>()?,
+                    mask: reader.read_u32::<LittleEndian>()?,
+                    x_advance: reader.read_i32::<LittleEndian>()?,
+                    y_advance: reader.read_i32::<LittleEndian>()?,
+                    x_offset: reader.read_i32::<LittleEndian>()?,
+                    y_offset: reader.read_i32::<LittleEndian>()?,
+                });
+            }
+            if glyphs.iter().any(|glyph| glyph.cluster as usize > text.len()) {
+                return Err(invalid_data("glyph cluster is outside of entry text"));
+            }
+
+            if keep {
+                entries.push(ImportedEntry { fingerprint, features, text, glyphs: ShapedGlyphs { glyphs } });
+            }
+        }
+
+        Ok(entries)
+    }
+
+    pub fn invalidate_font(&mut self, font_id: usize) {
+        self.entries.retain(|key, _| key.font_id != font_id);
+        self.words.retain(|key, _| key.font_id != font_id);
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.words.clear();
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.evict();
+    }
+
+    pub fn stats(&self) -> ShapingCacheStats {
+        ShapingCacheStats {
+            entries: self.entries.len(),
+            unicode_buffer_allocations: self.pool.allocations,
+            word_entries: self.words.len(),
+            ..self.stats
+        }
+    }
+
+    /// Approximate heap bytes of all entries; glyphs still used by buffers are included.
+    pub fn bytes(&self) -> usize {
+        self.entries.values().chain(self.words.values()).map(ShapingEntry::bytes).sum()
+    }
+
+    /// Removes the least recently used text or word, returning its bytes as counted by `bytes`.
+    pub fn remove_oldest(&mut self) -> Option<usize> {
+        let entries = match (oldest(&self.entries), oldest(&self.words)) {
+            (Some((key, used)), Some((_, word_used))) if used <= word_used => Some((&mut self.entries, key)),
+            (_, Some((key, _))) => Some((&mut self.words, key)),
+            (Some((key, _)), None) => Some((&mut self.entries, key)),
+            (None, None) => None,
+        };
+        let (entries, key) = entries?;
+
+        entries.remove(&key).map(|entry| entry.bytes())
+    }
+
+    fn evict(&mut self) {
+        evict_map(&mut self.entries, self.capacity);
+        evict_map(&mut self.words, self.capacity);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn glyphs(_pool: &mut UnicodeBufferPool) -> ShapedGlyphs {
+        ShapedGlyphs { glyphs: Vec::new() }
+    }
+
+    fn glyph(cluster: u32) -> ShapedGlyph {
+        ShapedGlyph { codepoint: 7, cluster, mask: 1, x_advance: 500, y_advance: 0, x_offset: -3, y_offset: 2 }
+    }
+
+    #[test]
+    fn should_count_hits_and_evict_least_recently_used() {
+        let mut cache = ShapingCache::new(2);
+        cache.get_or_shape(1, "OK", &[], glyphs);
+        cache.get_or_shape(1, "Cancel", &[], glyphs);
+        cache.get_or_shape(1, "OK", &[], glyphs);
+        cache.get_or_shape(1, "Apply", &[], glyphs);
+        cache.get_or_shape(1, "Cancel", &[], glyphs);
+
+        let stats = cache.stats();
+        assert_eq!(stats.hits, 1);
+        assert_eq!(stats.misses, 4);
+        assert_eq!(stats.entries, 2);
+    }
+
+    #[test]
+    fn invalidated_font_should_be_reshaped() {
+        let mut cache = ShapingCache::new(8);
+        cache.get_or_shape(1, "OK", &[], glyphs);
+        cache.get_or_shape(2, "OK", &[], glyphs);
+        cache.invalidate_font(1);
+        cache.get_or_shape(1, "OK", &[], glyphs);
+        cache.get_or_shape(2, "OK", &[], glyphs);
+
+        assert_eq!(cache.stats().hits, 1);
+        assert_eq!(cache.stats().misses, 3);
+    }
+
+    #[test]
+    fn entries_should_only_match_the_same_features() {
+        let mut cache = ShapingCache::new(8);
+        cache.get_or_shape(1, "OK", b"liga", glyphs);
+        cache.get_or_shape(1, "OK", b"kern", glyphs);
+        cache.get_or_shape(1, "OK", b"liga", glyphs);
+        assert_eq!((cache.stats().hits, cache.stats().misses), (1, 2));
+
+        // an entry whose features hash collides with other features is not a hit
+        let key = ShapingKey { font_id: 2, text_hash: text_hash("OK"), features: features_hash(b"liga") };
+        cache.entries.insert(key, ShapingEntry { text: "OK".to_string(), features: b"kern".to_vec(), glyphs: Rc::new(glyphs(&mut cache.pool)), last_used: 0 });
+        cache.get_or_shape(2, "OK", b"liga", glyphs);
+        assert_eq!(cache.stats().misses, 3);
+    }
+
+    #[test]
+    fn words_should_be_shaped_once_and_joined() {
+        let mut cache = ShapingCache::new(8);
+        cache.set_word_cache(true);
+        let mut shaped = Vec::new();
+        let mut shape = |text: &str, cache: &mut ShapingCache| cache.get_or_shape_words(1, text, &[], |_, word| {
+            shaped.push(word.to_string());
+            ShapedGlyphs { glyphs: (0..word.len() as u32).map(glyph).collect() }
+        });
+
+        let first = shape("Delete all", &mut cache);
+        let second = shape("Delete  it", &mut cache);
+        assert_eq!(first.glyphs.iter().map(|g| g.cluster).collect::<Vec<_>>(), (0..10).collect::<Vec<_>>());
+        assert_eq!(second.glyphs.iter().map(|g| g.cluster).collect::<Vec<_>>(), (0..10).collect::<Vec<_>>());
+        assert_eq!(shaped, vec!["Delete", " ", "all", "it"]);
+
+        let stats = cache.stats();
+        assert_eq!((stats.word_hits, stats.word_misses, stats.word_entries), (3, 4, 4));
+        assert_eq!(stats.word_hit_rate(), Some(3.0 / 7.0));
+        assert_eq!(stats.entries, 0);
+    }
+
+    #[test]
+    fn written_entries_should_read_back() {
+        let mut cache = ShapingCache::new(8);
+        cache.insert(1, "OK".to_string(), Vec::new(), ShapedGlyphs { glyphs: vec![glyph(0), glyph(1)] });
+        cache.insert(2, "skipped".to_string(), Vec::new(), ShapedGlyphs { glyphs: vec![glyph(0)] });
+
+        let mut bytes = Vec::new();
+        let written = cache.write_entries(&mut bytes, 2, |font_id| if font_id == 1 { Some([9; 20]) } else { None }).unwrap();
+        assert_eq!(written, 1);
+
+        let entries = ShapingCache::read_entries(&mut &bytes[..], 2).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].fingerprint, [9; 20]);
+        assert_eq!(entries[0].text, "OK");
+        assert_eq!(entries[0].glyphs.glyphs, vec![glyph(0), glyph(1)]);
+    }
+
+    #[test]
+    fn entries_with_other_fingerprints_should_be_discarded() {
+        let mut cache = ShapingCache::new(8);
+        cache.insert(1, "OK".to_string(), Vec::new(), ShapedGlyphs { glyphs: vec![glyph(0)] });
+        let mut bytes = Vec::new();
+        cache.write_entries(&mut bytes, 2, |_| Some([9; 20])).unwrap();
+
+        assert_eq!(ShapingCache::read_entries(&mut &bytes[..], 1).unwrap().len(), 0);
+        assert_eq!(ShapingCache::read_entries(&mut &bytes[..], 2).unwrap().len(), 1);
+
+        // version 1 exports have SHA1 fingerprints, no format and no encoded features
+        let features_len = 4 + 4 + 4 + 4 + 20 + 8;
+        let mut version_1 = bytes[..4].to_vec();
+        version_1.extend_from_slice(&[1, 0, 0, 0]);
+        version_1.extend_from_slice(&bytes[12..features_len]);
+        version_1.extend_from_slice(&bytes[features_len + 4..]);
+        assert_eq!(ShapingCache::read_entries(&mut &version_1[..], 2).unwrap().len(), 0);
+        assert_eq!(ShapingCache::read_entries(&mut &version_1[..], 1).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn truncated_or_corrupted_entries_should_fail_to_read() {
+        let mut cache = ShapingCache::new(8);
+        cache.insert(1, "Cancel".to_string(), Vec::new(), ShapedGlyphs { glyphs: vec![glyph(0)] });
+        let mut bytes = Vec::new();
+        cache.write_entries(&mut bytes, 2, |_| Some([1; 20])).unwrap();
+
+        assert!(ShapingCache::read_entries(&mut &bytes[..bytes.len() - 1], 2).is_err());
+
+        let text_start = 4 + 4 + 4 + 4 + 20 + 8 + 4 + 8 + 4;
+        bytes[text_start] = b'X';
+        assert!(ShapingCache::read_entries(&mut &bytes[..], 2).is_err());
+    }
+}
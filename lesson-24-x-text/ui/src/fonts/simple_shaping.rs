@@ -0,0 +1,101 @@
+//! Shaping without harfbuzz for the `simple-shaping` feature: one glyph per character from
+//! the character map, advances from the horizontal metrics and pair kerning.
+//!
+//! There are no ligatures, no mark positioning, no OpenType features and no reordering, so
+//! only left-to-right text of simple scripts such as Latin or Cyrillic shapes correctly.
+
+use super::shaping_cache::ShapedGlyph;
+
+const GLYPH_FLAG_UNSAFE_TO_BREAK: u32 = 0x0000_0001;
+
+/// Glyphs of `text` in logical order, with clusters at the character byte offsets plus
+/// `offset`. Characters without a glyph get the `.notdef` glyph 0.
+///
+/// Kerning is added to the advance of the first glyph of a pair, and the second glyph is
+/// marked unsafe to break like harfbuzz does.
+pub fn shape<G, A, K>(text: &str, offset: usize, glyph_for_char: G, advance: A, kerning: K) -> Vec<ShapedGlyph>
+    where G: Fn(char) -> Option<u32>,
+          A: Fn(u32) -> i32,
+          K: Fn(u32, u32) -> i32
+{
+    let mut glyphs: Vec<ShapedGlyph> = Vec::with_capacity(text.len());
+
+    for (i, c) in text.char_indices() {
+        let codepoint = glyph_for_char(c).unwrap_or(0);
+        let mut mask = 0;
+
+        if let Some(previous) = glyphs.last_mut() {
+            let kerning = kerning(previous.codepoint, codepoint);
+            if kerning != 0 {
+                previous.x_advance += kerning;
+                mask = GLYPH_FLAG_UNSAFE_TO_BREAK;
+            }
+        }
+
+        glyphs.push(ShapedGlyph {
+            codepoint,
+            cluster: (offset + i) as u32,
+            mask,
+            x_advance: advance(codepoint),
+            y_advance: 0,
+            x_offset: 0,
+            y_offset: 0,
+        });
+    }
+
+    glyphs
+}
+
+/// ISO 15924 tag of the script of a letter of the scripts simple shaping supports, `None`
+/// for other characters.
+pub fn script(c: char) -> Option<[u8; 4]> {
+    match c {
+        'A'..='Z' | 'a'..='z' | '\u{C0}'..='\u{D6}' | '\u{D8}'..='\u{F6}' | '\u{F8}'..='\u{24F}' => Some(*b"Latn"),
+        '\u{370}'..='\u{3FF}' => Some(*b"Grek"),
+        '\u{400}'..='\u{52F}' => Some(*b"Cyrl"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn glyph_for_char(c: char) -> Option<u32> {
+        match c {
+            'A' => Some(36),
+            'V' => Some(57),
+            'x' => Some(91),
+            _ => None,
+        }
+    }
+
+    fn advance(glyph_id: u32) -> i32 {
+        if glyph_id == 0 { 500 } else { 600 }
+    }
+
+    fn kerning(left: u32, right: u32) -> i32 {
+        if (left, right) == (36, 57) { -80 } else { 0 }
+    }
+
+    #[test]
+    fn pairs_should_be_kerned_and_marked() {
+        let glyphs = shape("AVx", 4, glyph_for_char, advance, kerning);
+        assert_eq!(glyphs.iter().map(|g| (g.codepoint, g.cluster, g.x_advance, g.mask)).collect::<Vec<_>>(),
+            vec![(36, 4, 520, 0), (57, 5, 600, 1), (91, 6, 600, 0)]);
+    }
+
+    #[test]
+    fn unmapped_chars_should_get_notdef_at_their_byte_offset() {
+        let glyphs = shape("é?", 0, glyph_for_char, advance, kerning);
+        assert_eq!(glyphs.iter().map(|g| (g.codepoint, g.cluster, g.x_advance)).collect::<Vec<_>>(),
+            vec![(0, 0, 500), (0, 2, 500)]);
+    }
+
+    #[test]
+    fn scripts_should_come_from_letters_only() {
+        assert_eq!("1 + é".chars().filter_map(script).next(), Some(*b"Latn"));
+        assert_eq!("Привет".chars().filter_map(script).next(), Some(*b"Cyrl"));
+        assert_eq!(script('×'), None);
+    }
+}
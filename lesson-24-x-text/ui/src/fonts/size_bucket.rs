@@ -0,0 +1,61 @@
+//! Pixel sizes quantized for cache keys, see `Fonts::set_size_bucketing`.
+
+/// Default size step, a quarter pixel.
+pub const DEFAULT_SIZE_STEP: f32 = 0.25;
+
+/// Pixel size rounded to a multiple of a step, the size part of cache keys so that sizes
+/// animated continuously reuse entries. Buckets of different steps are never equal.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct SizeBucket {
+    index: u32,
+    step_bits: u32,
+}
+
+impl SizeBucket {
+    /// Bucket of `px_size` rounded to a multiple of `step`, at least one step.
+    pub fn new(px_size: f32, step: f32) -> SizeBucket {
+        assert!(step > 0.0 && step.is_finite(), "SizeBucket::new: step should be positive");
+        let index = (px_size / step).round().max(1.0).min(u32::max_value() as f32) as u32;
+        SizeBucket { index, step_bits: step.to_bits() }
+    }
+
+    /// Multiple of the step.
+    pub fn index(self) -> u32 {
+        self.index
+    }
+
+    pub fn step(self) -> f32 {
+        f32::from_bits(self.step_bits)
+    }
+
+    /// The rounded pixel size.
+    pub fn px_size(self) -> f32 {
+        self.index as f32 * self.step()
+    }
+
+    /// The bucket as a cache variant, unique across steps.
+    pub fn key(self) -> u64 {
+        u64::from(self.step_bits) << 32 | u64::from(self.index)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sizes_should_round_to_the_step() {
+        let bucket = SizeBucket::new(12.1, DEFAULT_SIZE_STEP);
+        assert_eq!((bucket.index(), bucket.px_size()), (48, 12.0));
+        assert_eq!(SizeBucket::new(11.9, DEFAULT_SIZE_STEP), bucket);
+        assert_eq!(SizeBucket::new(0.0, 2.0).px_size(), 2.0);
+    }
+
+    #[test]
+    fn buckets_of_other_steps_should_differ() {
+        let quarter = SizeBucket::new(12.0, 0.25);
+        let whole = SizeBucket::new(48.0, 1.0);
+        assert_eq!(quarter.index(), whole.index());
+        assert!(quarter != whole && quarter.key() != whole.key());
+    }
+}
@@ -0,0 +1,68 @@
+use font_kit::error::SelectionError;
+use font_kit::family_name::FamilyName;
+use font_kit::handle::Handle;
+use font_kit::properties::Properties;
+use font_kit::source::{Source, SystemSource};
+use super::matching::FaceInfo;
+
+/// Where `Fonts` looks up fonts by family and name, the system fonts by default.
+///
+/// See `Fonts::with_source`.
+pub trait FontSource {
+    fn select_best_match(&self, family_names: &[FamilyName], properties: &Properties) -> Result<Handle, SelectionError>;
+    fn select_by_postscript_name(&self, postscript_name: &str) -> Result<Handle, SelectionError>;
+
+    /// Picks up fonts installed or removed since the source was created, see
+    /// `Fonts::refresh_system_fonts`.
+    fn refresh(&mut self) {}
+
+    /// Names and properties of the faces the source offers, ranked by `Fonts::find_matches`.
+    /// Sources that can not list their faces return none.
+    fn faces(&self) -> Vec<FaceInfo> {
+        Vec::new()
+    }
+}
+
+/// Source without any fonts, used by `Fonts::new_memory_only`.
+pub struct NoSource;
+
+impl FontSource for NoSource {
+    fn select_best_match(&self, _family_names: &[FamilyName], _properties: &Properties) -> Result<Handle, SelectionError> {
+        Err(SelectionError::NotFound)
+    }
+
+    fn select_by_postscript_name(&self, _postscript_name: &str) -> Result<Handle, SelectionError> {
+        Err(SelectionError::NotFound)
+    }
+}
+
+impl FontSource for SystemSource {
+    fn select_best_match(&self, family_names: &[FamilyName], properties: &Properties) -> Result<Handle, SelectionError> {
+        Source::select_best_match(self, family_names, properties)
+    }
+
+    fn select_by_postscript_name(&self, postscript_name: &str) -> Result<Handle, SelectionError> {
+        Source::select_by_postscript_name(self, postscript_name)
+    }
+
+    fn refresh(&mut self) {
+        *self = SystemSource::new();
+    }
+
+    /// Opens every installed face to read its names, the faces are dropped afterwards.
+    fn faces(&self) -> Vec<FaceInfo> {
+        let families = Source::all_families(self).unwrap_or_default();
+        families.iter()
+            .filter_map(|family| Source::select_family_by_name(self, family).ok())
+            .flat_map(|family| family.fonts().iter()
+                .filter_map(|handle| handle.load().ok())
+                .map(|font| FaceInfo {
+                    family_name: font.family_name(),
+                    full_name: font.full_name(),
+                    postscript_name: font.postscript_name(),
+                    properties: font.properties(),
+                })
+                .collect::<Vec<_>>())
+            .collect()
+    }
+}
@@ -0,0 +1,383 @@
+//! Subsetting of TrueType fonts to the glyphs a document uses, see `Font::subset`.
+//!
+//! Glyph ids are kept, glyphs outside the subset become empty in `glyf`, so `hmtx`, `GSUB`,
+//! `GPOS` and the other tables indexed by glyph stay valid and are copied unchanged.
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+use super::sfnt::{self, read_u16, read_u32};
+
+const ARG_1_AND_2_ARE_WORDS: u16 = 0x0001;
+const WE_HAVE_A_SCALE: u16 = 0x0008;
+const MORE_COMPONENTS: u16 = 0x0020;
+const WE_HAVE_AN_X_AND_Y_SCALE: u16 = 0x0040;
+const WE_HAVE_A_TWO_BY_TWO: u16 = 0x0080;
+
+/// Magic number the `head` checksum adjustment is computed from.
+const CHECKSUM_MAGIC: u32 = 0xB1B0_AFBA;
+
+/// Failure of `Font::subset`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SubsetError {
+    /// The font file could not be read.
+    NoFontData,
+    /// The font has no `glyf` outlines, such as fonts with `CFF` outlines.
+    UnsupportedOutlines,
+    /// A table needed for subsetting is missing or malformed.
+    BadTable([u8; 4]),
+    /// A requested glyph id is not below the glyph count of the font.
+    GlyphOutOfRange(u32),
+}
+
+/// Glyph ids of the components of a composite glyph, empty for simple glyphs.
+fn components(glyph: &[u8]) -> Vec<u32> {
+    let mut ids = Vec::new();
+    if read_u16(glyph, 0).map_or(true, |contours| contours as i16 >= 0) {
+        return ids;
+    }
+
+    let mut offset = 10;
+    while let (Some(flags), Some(id)) = (read_u16(glyph, offset), read_u16(glyph, offset + 2)) {
+        ids.push(u32::from(id));
+        offset += if flags & ARG_1_AND_2_ARE_WORDS != 0 { 8 } else { 6 };
+        offset += if flags & WE_HAVE_A_SCALE != 0 {
+            2
+        } else if flags & WE_HAVE_AN_X_AND_Y_SCALE != 0 {
+            4
+        } else if flags & WE_HAVE_A_TWO_BY_TWO != 0 {
+            8
+        } else {
+            0
+        };
+        if flags & MORE_COMPONENTS == 0 {
+            break;
+        }
+    }
+    ids
+}
+
+/// Characters mapped by the Unicode subtables of `cmap` in formats 4 and 12, to nonzero
+/// glyph ids. Earlier subtables win.
+fn char_map(cmap: &[u8]) -> Option<BTreeMap<u32, u32>> {
+    let mut map = BTreeMap::new();
+    let count = read_u16(cmap, 2)? as usize;
+    for i in 0..count {
+        let record = 4 + i * 8;
+        let (platform, encoding) = (read_u16(cmap, record)?, read_u16(cmap, record + 2)?);
+        if platform != 0 && !(platform == 3 && (encoding == 1 || encoding == 10)) {
+            continue;
+        }
+        let subtable = cmap.get(read_u32(cmap, record + 4)? as usize..)?;
+        match read_u16(subtable, 0)? {
+            4 => read_format4(subtable, &mut map)?,
+            12 => read_format12(subtable, &mut map)?,
+            _ => {}
+        }
+    }
+    Some(map)
+}
+
+fn read_format4(subtable: &[u8], map: &mut BTreeMap<u32, u32>) -> Option<()> {
+    let seg_count = read_u16(subtable, 6)? as usize / 2;
+    let ends = 14;
+    let starts = ends + seg_count * 2 + 2;
+    let deltas = starts + seg_count * 2;
+    let range_offsets = deltas + seg_count * 2;
+
+    for segment in 0..seg_count {
+        let start = read_u16(subtable, starts + segment * 2)?;
+        let end = read_u16(subtable, ends + segment * 2)?;
+        let delta = read_u16(subtable, deltas + segment * 2)?;
+        let range_offset = read_u16(subtable, range_offsets + segment * 2)? as usize;
+        for c in start..=end {
+            if c == 0xFFFF {
+                continue;
+            }
+            let glyph_id = if range_offset == 0 {
+                c.wrapping_add(delta)
+            } else {
+                let offset = range_offsets + segment * 2 + range_offset + (c - start) as usize * 2;
+                match read_u16(subtable, offset)? {
+                    0 => 0,
+                    glyph_id => glyph_id.wrapping_add(delta),
+                }
+            };
+            if glyph_id != 0 {
+                map.entry(u32::from(c)).or_insert_with(|| u32::from(glyph_id));
+            }
+        }
+    }
+    Some(())
+}
+
+fn read_format12(subtable: &[u8], map: &mut BTreeMap<u32, u32>) -> Option<()> {
+    let groups = read_u32(subtable, 12)? as usize;
+    for group in 0..groups {
+        let record = 16 + group * 12;
+        let start = read_u32(subtable, record)?;
+        let end = read_u32(subtable, record + 4)?.min(0x10_FFFF);
+        let start_glyph = read_u32(subtable, record + 8)?;
+        for c in start..=end {
+            let glyph_id = start_glyph.wrapping_add(c - start);
+            if glyph_id != 0 {
+                map.entry(c).or_insert(glyph_id);
+            }
+        }
+    }
+    Some(())
+}
+
+/// Runs of consecutive characters mapped to consecutive glyph ids, as
+/// `(first char, last char, first glyph id)`.
+fn runs<'a, I: Iterator<Item=(&'a u32, &'a u32)>>(map: I) -> Vec<(u32, u32, u32)> {
+    let mut runs: Vec<(u32, u32, u32)> = Vec::new();
+    for (&c, &glyph_id) in map {
+        match runs.last_mut() {
+            Some(run) if run.1 + 1 == c && run.2 + (c - run.0) == glyph_id => run.1 = c,
+            _ => runs.push((c, c, glyph_id)),
+        }
+    }
+    runs
+}
+
+fn push_u16(data: &mut Vec<u8>, value: u16) {
+    data.extend_from_slice(&value.to_be_bytes());
+}
+
+fn push_u32(data: &mut Vec<u8>, value: u32) {
+    data.extend_from_slice(&value.to_be_bytes());
+}
+
+/// Search range, entry selector and range shift of a binary searchable array of `count`
+/// items of `size` bytes.
+fn search_params(count: usize, size: usize) -> (u16, u16, u16) {
+    let entry_selector = (0..16).rev().find(|&e| 1 << e <= count).unwrap_or(0);
+    let search_range = (1 << entry_selector) * size;
+    (search_range as u16, entry_selector as u16, (count * size).saturating_sub(search_range) as u16)
+}
+
+/// Format 4 subtable of the BMP characters, `None` if they need more segments than fit.
+fn write_format4(map: &BTreeMap<u32, u32>) -> Option<Vec<u8>> {
+    let mut segments = runs(map.range(..0xFFFF));
+    segments.push((0xFFFF, 0xFFFF, 0));
+    let seg_count = segments.len();
+    let length = 16 + seg_count * 8;
+    if length > 0xFFFF {
+        return None;
+    }
+
+    let (search_range, entry_selector, range_shift) = search_params(seg_count, 2);
+    let mut data = Vec::with_capacity(length);
+    for &value in &[4, length as u16, 0, seg_count as u16 * 2, search_range, entry_selector, range_shift] {
+        push_u16(&mut data, value);
+    }
+    for &(_, end, _) in &segments {
+        push_u16(&mut data, end as u16);
+    }
+    push_u16(&mut data, 0);
+    for &(start, _, _) in &segments {
+        push_u16(&mut data, start as u16);
+    }
+    for &(start, _, glyph_id) in &segments {
+        // the terminating segment maps 0xFFFF to glyph 0
+        let delta = if start == 0xFFFF { 1 } else { glyph_id.wrapping_sub(start) as u16 };
+        push_u16(&mut data, delta);
+    }
+    for _ in &segments {
+        push_u16(&mut data, 0);
+    }
+    Some(data)
+}
+
+fn write_format12(map: &BTreeMap<u32, u32>) -> Vec<u8> {
+    let groups = runs(map.iter());
+    let mut data = Vec::with_capacity(16 + groups.len() * 12);
+    push_u16(&mut data, 12);
+    push_u16(&mut data, 0);
+    push_u32(&mut data, 16 + groups.len() as u32 * 12);
+    push_u32(&mut data, 0);
+    push_u32(&mut data, groups.len() as u32);
+    for (start, end, glyph_id) in groups {
+        push_u32(&mut data, start);
+        push_u32(&mut data, end);
+        push_u32(&mut data, glyph_id);
+    }
+    data
+}
+
+/// `cmap` with a Windows BMP subtable in format 4 and a Windows full repertoire subtable
+/// in format 12.
+fn write_cmap(map: &BTreeMap<u32, u32>) -> Vec<u8> {
+    let subtables: Vec<(u16, Vec<u8>)> = write_format4(map).map(|format4| (1, format4)).into_iter()
+        .chain(Some((10, write_format12(map))))
+        .collect();
+
+    let mut data = Vec::new();
+    push_u16(&mut data, 0);
+    push_u16(&mut data, subtables.len() as u16);
+    let mut offset = 4 + subtables.len() * 8;
+    for &(encoding, ref subtable) in &subtables {
+        push_u16(&mut data, 3);
+        push_u16(&mut data, encoding);
+        push_u32(&mut data, offset as u32);
+        offset += subtable.len();
+    }
+    for (_, subtable) in subtables {
+        data.extend_from_slice(&subtable);
+    }
+    data
+}
+
+fn checksum(data: &[u8]) -> u32 {
+    data.chunks(4).fold(0u32, |sum, chunk| {
+        let mut word = [0; 4];
+        word[..chunk.len()].copy_from_slice(chunk);
+        sum.wrapping_add(u32::from_be_bytes(word))
+    })
+}
+
+fn pad4(data: &mut Vec<u8>) {
+    while data.len() % 4 != 0 {
+        data.push(0);
+    }
+}
+
+/// A TrueType font file of `tables`, with the checksum adjustment of `head` set.
+pub fn write_sfnt(tables: &BTreeMap<[u8; 4], Cow<[u8]>>) -> Vec<u8> {
+    let (search_range, entry_selector, range_shift) = search_params(tables.len(), 16);
+    let mut data = Vec::new();
+    push_u32(&mut data, 0x0001_0000);
+    for &value in &[tables.len() as u16, search_range, entry_selector, range_shift] {
+        push_u16(&mut data, value);
+    }
+
+    let mut offset = 12 + tables.len() * 16;
+    let mut head_offset = None;
+    for (tag, table) in tables {
+        if tag == b"head" {
+            head_offset = Some(offset);
+        }
+        data.extend_from_slice(tag);
+        push_u32(&mut data, checksum(table));
+        push_u32(&mut data, offset as u32);
+        push_u32(&mut data, table.len() as u32);
+        offset += (table.len() + 3) & !3;
+    }
+    for table in tables.values() {
+        data.extend_from_slice(table);
+        pad4(&mut data);
+    }
+
+    if let Some(head_offset) = head_offset {
+        let adjustment = CHECKSUM_MAGIC.wrapping_sub(checksum(&data));
+        data[head_offset + 8..head_offset + 12].copy_from_slice(&adjustment.to_be_bytes());
+    }
+    data
+}
+
+/// Font file of the face at `face_index` of `data` with the outlines of `glyph_ids`, the
+/// components of composite glyphs and `.notdef`, mapping the characters of those glyphs.
+pub fn subset(data: &[u8], face_index: u32, glyph_ids: &[u32]) -> Result<Vec<u8>, SubsetError> {
+    let table = |tag: &[u8; 4]| sfnt::find_table(data, face_index, tag).ok_or(SubsetError::BadTable(*tag));
+    let glyf = sfnt::find_table(data, face_index, b"glyf").ok_or(SubsetError::UnsupportedOutlines)?;
+    let (head, loca, maxp, cmap) = (table(b"head")?, table(b"loca")?, table(b"maxp")?, table(b"cmap")?);
+
+    let num_glyphs = read_u16(maxp, 4).ok_or(SubsetError::BadTable(*b"maxp"))? as u32;
+    let long_loca = read_u16(head, 50).ok_or(SubsetError::BadTable(*b"head"))? != 0;
+    let outline = |id: u32| {
+        let id = id as usize;
+        let (start, end) = if long_loca {
+            (read_u32(loca, id * 4)? as usize, read_u32(loca, id * 4 + 4)? as usize)
+        } else {
+            (read_u16(loca, id * 2)? as usize * 2, read_u16(loca, id * 2 + 2)? as usize * 2)
+        };
+        glyf.get(start..end)
+    };
+
+    if let Some(&id) = glyph_ids.iter().find(|&&id| id >= num_glyphs) {
+        return Err(SubsetError::GlyphOutOfRange(id));
+    }
+    let mut kept = BTreeSet::new();
+    let mut pending = vec![0];
+    pending.extend_from_slice(glyph_ids);
+    while let Some(id) = pending.pop() {
+        if id < num_glyphs && kept.insert(id) {
+            let glyph = outline(id).ok_or(SubsetError::BadTable(*b"glyf"))?;
+            pending.extend(components(glyph));
+        }
+    }
+
+    let mut new_glyf = Vec::new();
+    let mut new_loca = Vec::with_capacity((num_glyphs as usize + 1) * 4);
+    for id in 0..num_glyphs {
+        push_u32(&mut new_loca, new_glyf.len() as u32);
+        if kept.contains(&id) {
+            new_glyf.extend_from_slice(outline(id).ok_or(SubsetError::BadTable(*b"glyf"))?);
+            pad4(&mut new_glyf);
+        }
+    }
+    push_u32(&mut new_loca, new_glyf.len() as u32);
+
+    let mut new_head = head.to_vec();
+    if new_head.len() < 54 {
+        return Err(SubsetError::BadTable(*b"head"));
+    }
+    new_head[8..12].copy_from_slice(&[0; 4]);
+    new_head[50..52].copy_from_slice(&[0, 1]);
+
+    let chars: BTreeMap<u32, u32> = char_map(cmap).ok_or(SubsetError::BadTable(*b"cmap"))?
+        .into_iter()
+        .filter(|&(_, glyph_id)| kept.contains(&glyph_id))
+        .collect();
+
+    let mut tables: BTreeMap<[u8; 4], Cow<[u8]>> = BTreeMap::new();
+    for (tag, table) in sfnt::tables(data, face_index).ok_or(SubsetError::NoFontData)? {
+        // a signature of the original file would not match the subset
+        if &tag != b"DSIG" {
+            tables.insert(tag, Cow::Borrowed(table));
+        }
+    }
+    tables.insert(*b"glyf", Cow::Owned(new_glyf));
+    tables.insert(*b"loca", Cow::Owned(new_loca));
+    tables.insert(*b"head", Cow::Owned(new_head));
+    tables.insert(*b"cmap", Cow::Owned(write_cmap(&chars)));
+
+    Ok(write_sfnt(&tables))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn composite_glyphs_should_list_their_components() {
+        let mut glyph = vec![255, 255, 0, 0, 0, 0, 0, 0, 0, 0];
+        // word arguments and a scale, then byte arguments
+        glyph.extend_from_slice(&[0, (MORE_COMPONENTS | ARG_1_AND_2_ARE_WORDS | WE_HAVE_A_SCALE) as u8, 0, 7, 0, 0, 0, 0, 64, 0]);
+        glyph.extend_from_slice(&[0, 0, 1, 2, 0, 0]);
+        assert_eq!(components(&glyph), vec![7, 258]);
+        assert_eq!(components(&[0, 1, 0, 0]), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn cmap_should_round_trip_through_formats_4_and_12() {
+        let map: BTreeMap<u32, u32> = vec![(0x41, 3), (0x42, 4), (0x43, 9), (0xE9, 20), (0x1F600, 30)].into_iter().collect();
+        let cmap = write_cmap(&map);
+        assert_eq!(char_map(&cmap), Some(map.clone()));
+
+        let format4 = &cmap[read_u32(&cmap, 8).unwrap() as usize..];
+        let mut bmp = BTreeMap::new();
+        read_format4(format4, &mut bmp).unwrap();
+        assert_eq!(bmp, map.range(..0x10000).map(|(&c, &g)| (c, g)).collect());
+    }
+
+    #[test]
+    fn written_fonts_should_have_valid_checksum_adjustment() {
+        let mut tables = BTreeMap::new();
+        tables.insert(*b"head", Cow::Owned(vec![0; 54]));
+        tables.insert(*b"test", Cow::Owned(vec![1, 2, 3]));
+        let data = write_sfnt(&tables);
+        assert_eq!(checksum(&data), CHECKSUM_MAGIC);
+        assert_eq!(sfnt::find_table(&data, 0, b"test"), Some(&[1u8, 2, 3][..]));
+    }
+}
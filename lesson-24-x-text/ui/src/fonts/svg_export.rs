@@ -0,0 +1,130 @@
+use std::fmt::Write;
+use lyon_geom::Arc;
+use lyon_geom::math::{Point, Vector, Angle, point};
+use lyon_path::builder::{FlatPathBuilder, PathBuilder};
+
+/// Path builder that writes SVG path data, mapping font units with a
+/// Y-up origin to pixels with SVG's Y-down origin.
+pub struct SvgPathWriter {
+    data: String,
+    scale: f32,
+    glyph_scale: f32,
+    offset: (f32, f32),
+    current: Point,
+    first: Point,
+}
+
+impl SvgPathWriter {
+    pub fn new(scale: f32) -> SvgPathWriter {
+        SvgPathWriter {
+            data: String::new(),
+            scale,
+            glyph_scale: 1.0,
+            offset: (0.0, 0.0),
+            current: point(0.0, 0.0),
+            first: point(0.0, 0.0),
+        }
+    }
+
+    /// Origin of the following outlines, in font units.
+    pub fn set_offset(&mut self, x: f32, y: f32) {
+        self.offset = (x, y);
+    }
+
+    /// Multiplier from the units of the following outlines to those of the offset, for
+    /// glyphs of another font or size than the one the scale is for.
+    pub fn set_glyph_scale(&mut self, glyph_scale: f32) {
+        self.glyph_scale = glyph_scale;
+    }
+
+    pub fn data(&self) -> &str {
+        &self.data
+    }
+
+    fn push(&mut self, command: char, points: &[Point]) {
+        self.data.push(command);
+        for p in points {
+            let x = (p.x * self.glyph_scale + self.offset.0) * self.scale;
+            // adding zero turns -0 into 0
+            let y = -(p.y * self.glyph_scale + self.offset.1) * self.scale + 0.0;
+            let _ = write!(self.data, " {} {}", x, y);
+        }
+        self.data.push(' ');
+    }
+}
+
+impl FlatPathBuilder for SvgPathWriter {
+    type PathType = String;
+
+    fn move_to(&mut self, to: Point) {
+        self.push('M', &[to]);
+        self.current = to;
+        self.first = to;
+    }
+
+    fn line_to(&mut self, to: Point) {
+        self.push('L', &[to]);
+        self.current = to;
+    }
+
+    fn close(&mut self) {
+        self.data.push_str("Z ");
+        self.current = self.first;
+    }
+
+    fn build(self) -> String {
+        self.data
+    }
+
+    fn build_and_reset(&mut self) -> String {
+        self.current = point(0.0, 0.0);
+        self.first = point(0.0, 0.0);
+        ::std::mem::replace(&mut self.data, String::new())
+    }
+
+    fn current_position(&self) -> Point {
+        self.current
+    }
+}
+
+impl PathBuilder for SvgPathWriter {
+    fn quadratic_bezier_to(&mut self, ctrl: Point, to: Point) {
+        self.push('Q', &[ctrl, to]);
+        self.current = to;
+    }
+
+    fn cubic_bezier_to(&mut self, ctrl1: Point, ctrl2: Point, to: Point) {
+        self.push('C', &[ctrl1, ctrl2, to]);
+        self.current = to;
+    }
+
+    fn arc(&mut self, center: Point, radii: Vector, sweep_angle: Angle, x_rotation: Angle) {
+        let start = self.current - center;
+        let arc = Arc {
+            center,
+            radii,
+            start_angle: Angle::radians(start.y.atan2(start.x)),
+            sweep_angle,
+            x_rotation,
+        };
+        arc.for_each_quadratic_bezier(&mut |curve| {
+            self.quadratic_bezier_to(curve.ctrl, curve.to);
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_flip_y_and_apply_offset_and_scale() {
+        let mut writer = SvgPathWriter::new(0.5);
+        writer.set_offset(10.0, 0.0);
+        writer.move_to(point(0.0, 0.0));
+        writer.line_to(point(0.0, 20.0));
+        writer.close();
+
+        assert_eq!(writer.build(), "M 5 0 L 5 -10 Z ");
+    }
+}
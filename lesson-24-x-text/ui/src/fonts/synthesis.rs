@@ -0,0 +1,262 @@
+use font_kit::properties::Style;
+use lyon_path::default::Path;
+use lyon_path::builder::{FlatPathBuilder, PathBuilder};
+use lyon_path::math::{Point, Vector, point, vector};
+use lyon_path::PathEvent;
+use super::layout::Bounds;
+use super::matching::MatchDetails;
+
+/// Horizontal shear of synthetic oblique, tan(12°) like FreeType.
+pub const OBLIQUE_SHEAR: f32 = 0.2126;
+
+/// Lowest requested weight that is synthesized when the matched face is lighter.
+const BOLD_WEIGHT: f32 = 600.0;
+
+/// Styles to synthesize when the matched face lacks the requested weight or style,
+/// see `Fonts::set_synthesis`.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct SynthesisOptions {
+    pub embolden: bool,
+    pub oblique: bool,
+}
+
+impl SynthesisOptions {
+    pub fn is_any(&self) -> bool {
+        self.embolden || self.oblique
+    }
+
+    /// Enabled options for what a query asked for but the matched face does not have.
+    pub fn missing(&self, details: &MatchDetails) -> SynthesisOptions {
+        let (requested, matched, quality) = (&details.requested, &details.matched, details.quality);
+        SynthesisOptions {
+            embolden: self.embolden && !quality.exact_weight && requested.weight.0 >= BOLD_WEIGHT && matched.weight.0 < BOLD_WEIGHT,
+            oblique: self.oblique && !quality.exact_style && requested.style != Style::Normal && matched.style == Style::Normal,
+        }
+    }
+}
+
+/// Outline widening and extra advance of synthetic bold in font units, 1/24 em like FreeType.
+pub fn embolden_strength(units_per_em: u32) -> f32 {
+    units_per_em as f32 / 24.0
+}
+
+enum Verb {
+    Line,
+    Quadratic,
+    Cubic,
+}
+
+/// Points of a contour in path order, starting with the move-to point.
+struct Contour {
+    points: Vec<Point>,
+    verbs: Vec<Verb>,
+    closed: bool,
+}
+
+fn contours(path: &Path) -> Vec<Contour> {
+    let mut contours: Vec<Contour> = Vec::new();
+
+    for event in path.iter() {
+        if let PathEvent::MoveTo(to) = event {
+            contours.push(Contour { points: vec![to], verbs: Vec::new(), closed: false });
+            continue;
+        }
+
+        let contour = match contours.last_mut() {
+            Some(contour) => contour,
+            None => continue,
+        };
+        match event {
+            PathEvent::LineTo(to) => {
+                contour.points.push(to);
+                contour.verbs.push(Verb::Line);
+            },
+            PathEvent::QuadraticTo(ctrl, to) => {
+                contour.points.extend_from_slice(&[ctrl, to]);
+                contour.verbs.push(Verb::Quadratic);
+            },
+            PathEvent::CubicTo(ctrl1, ctrl2, to) => {
+                contour.points.extend_from_slice(&[ctrl1, ctrl2, to]);
+                contour.verbs.push(Verb::Cubic);
+            },
+            PathEvent::Close => contour.closed = true,
+            // glyph outlines have no arcs
+            PathEvent::MoveTo(_) | PathEvent::Arc(..) => (),
+        }
+    }
+
+    contours
+}
+
+/// Twice the signed area of the control polygons, positive for counter-clockwise outer contours.
+fn signed_area(contours: &[Contour]) -> f32 {
+    contours.iter()
+        .flat_map(|contour| {
+            let points = &contour.points;
+            (0..points.len()).map(move |i| {
+                let (a, b) = (points[i], points[(i + 1) % points.len()]);
+                a.x * b.y - b.x * a.y
+            })
+        })
+        .sum()
+}
+
+/// Moves every point outward by `shift` along the bisector of its edges; contours wound
+/// opposite to the outer ones, the holes, shrink.
+fn embolden_contour(points: &mut [Point], outward: f32, shift: f32) {
+    let original = points.to_vec();
+    let n = original.len();
+    let normal = |edge: Vector| {
+        let edge = edge.normalize();
+        vector(edge.y, -edge.x) * outward
+    };
+
+    for i in 0..n {
+        let p = original[i];
+        // the closing point often repeats the first one
+        let prev = (1..n).map(|k| original[(i + n - k) % n]).find(|&q| q != p);
+        let next = (1..n).map(|k| original[(i + k) % n]).find(|&q| q != p);
+
+        if let (Some(prev), Some(next)) = (prev, next) {
+            let (n1, n2) = (normal(p - prev), normal(next - p));
+            let miter = (1.0 + n1.dot(n2)).max(0.1);
+            points[i] = p + (n1 + n2) * (shift / miter);
+        }
+    }
+}
+
+/// Outline with synthetic bold and oblique applied.
+///
+/// Bold widens the outline by `strength` and shifts it right by half of it, so the left side
+/// bearing is kept and the glyph grows into the extra advance. Oblique shears around the baseline.
+pub fn synthesize_path(path: &Path, synthesis: SynthesisOptions, strength: f32) -> Path {
+    let mut contours = contours(path);
+
+    let shift = if synthesis.embolden { strength / 2.0 } else { 0.0 };
+    if synthesis.embolden {
+        let outward = if signed_area(&contours) >= 0.0 { 1.0 } else { -1.0 };
+        for contour in &mut contours {
+            embolden_contour(&mut contour.points, outward, shift);
+        }
+    }
+
+    let shear = if synthesis.oblique { OBLIQUE_SHEAR } else { 0.0 };
+    let transform = |p: Point| point(p.x + shift + p.y * shear, p.y);
+
+    let mut builder = Path::builder();
+    for contour in &contours {
+        let mut points = contour.points.iter().map(|&p| transform(p));
+        builder.move_to(points.next().expect("synthesize_path: contour starts with a point"));
+
+        for verb in &contour.verbs {
+            let mut next = || points.next().expect("synthesize_path: verb points");
+            match *verb {
+                Verb::Line => builder.line_to(next()),
+                Verb::Quadratic => {
+                    let ctrl = next();
+                    builder.quadratic_bezier_to(ctrl, next())
+                },
+                Verb::Cubic => {
+                    let (ctrl1, ctrl2) = (next(), next());
+                    builder.cubic_bezier_to(ctrl1, ctrl2, next())
+                },
+            }
+        }
+
+        if contour.closed {
+            builder.close();
+        }
+    }
+    FlatPathBuilder::build(builder)
+}
+
+/// Bounds of a glyph after synthesis, see `synthesize_path`.
+pub fn synthesize_bounds(bounds: Bounds, synthesis: SynthesisOptions, strength: f32) -> Bounds {
+    let mut bounds = bounds;
+    if synthesis.embolden {
+        bounds.max_x += strength;
+        bounds.min_y -= strength / 2.0;
+        bounds.max_y += strength / 2.0;
+    }
+    if synthesis.oblique {
+        bounds.min_x += bounds.min_y.min(0.0) * OBLIQUE_SHEAR;
+        bounds.max_x += bounds.max_y.max(0.0) * OBLIQUE_SHEAR;
+    }
+    bounds
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use font_kit::properties::Properties;
+
+    fn square(min: f32, max: f32, clockwise: bool) -> Vec<Point> {
+        let points = vec![point(min, min), point(max, min), point(max, max), point(min, max)];
+        if clockwise { points.into_iter().rev().collect() } else { points }
+    }
+
+    fn path(contours: &[Vec<Point>]) -> Path {
+        let mut builder = Path::builder();
+        for points in contours {
+            builder.move_to(points[0]);
+            for &p in &points[1..] {
+                builder.line_to(p);
+            }
+            builder.close();
+        }
+        FlatPathBuilder::build(builder)
+    }
+
+    fn points(path: &Path) -> Vec<Point> {
+        contours(path).into_iter().flat_map(|contour| contour.points).collect()
+    }
+
+    fn bounds(points: &[Point]) -> (f32, f32, f32, f32) {
+        points.iter().fold((::std::f32::MAX, ::std::f32::MAX, ::std::f32::MIN, ::std::f32::MIN), |(x0, y0, x1, y1), p| {
+            (x0.min(p.x), y0.min(p.y), x1.max(p.x), y1.max(p.y))
+        })
+    }
+
+    const BOLD: SynthesisOptions = SynthesisOptions { embolden: true, oblique: false };
+    const OBLIQUE: SynthesisOptions = SynthesisOptions { embolden: false, oblique: true };
+
+    #[test]
+    fn embolden_should_grow_outline_regardless_of_winding() {
+        for &clockwise in &[false, true] {
+            let bold = synthesize_path(&path(&[square(0.0, 100.0, clockwise)]), BOLD, 10.0);
+            assert_eq!(bounds(&points(&bold)), (0.0, -5.0, 110.0, 105.0));
+        }
+    }
+
+    #[test]
+    fn embolden_should_shrink_holes() {
+        let bold = synthesize_path(&path(&[square(0.0, 100.0, false), square(40.0, 60.0, true)]), BOLD, 10.0);
+        let hole = &points(&bold)[4..];
+        assert_eq!(bounds(hole), (50.0, 45.0, 60.0, 55.0));
+    }
+
+    #[test]
+    fn oblique_should_shear_around_baseline() {
+        let oblique = synthesize_path(&path(&[square(0.0, 100.0, false)]), OBLIQUE, 10.0);
+        let points = points(&oblique);
+        assert_eq!(points[0], point(0.0, 0.0));
+        assert!((points[2].x - (100.0 + 100.0 * OBLIQUE_SHEAR)).abs() < 0.001);
+
+        let bounds = synthesize_bounds(Bounds { min_x: 0.0, min_y: -10.0, max_x: 100.0, max_y: 100.0 }, OBLIQUE, 10.0);
+        assert!((bounds.min_x + 10.0 * OBLIQUE_SHEAR).abs() < 0.001);
+        assert!((bounds.max_x - (100.0 + 100.0 * OBLIQUE_SHEAR)).abs() < 0.001);
+    }
+
+    #[test]
+    fn missing_should_only_synthesize_enabled_styles_the_face_lacks() {
+        let enabled = SynthesisOptions { embolden: true, oblique: true };
+        let regular = Properties::new();
+        let mut bold_italic = Properties::new();
+        bold_italic.weight(::font_kit::properties::Weight::BOLD).style(Style::Italic);
+
+        assert_eq!(enabled.missing(&MatchDetails::new(&bold_italic, &regular)), enabled);
+        assert_eq!(enabled.missing(&MatchDetails::new(&bold_italic, &bold_italic)), SynthesisOptions::default());
+        assert_eq!(enabled.missing(&MatchDetails::new(&regular, &regular)), SynthesisOptions::default());
+        assert_eq!(BOLD.missing(&MatchDetails::new(&bold_italic, &regular)), BOLD);
+    }
+}
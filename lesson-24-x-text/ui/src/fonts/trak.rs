@@ -0,0 +1,96 @@
+//! Optical tracking from the AAT `trak` table, see `Buffer::set_optical_tracking`.
+
+use super::sfnt::{read_u16, read_u32};
+
+fn read_i16(data: &[u8], offset: usize) -> Option<i16> {
+    read_u16(data, offset).map(|v| v as i16)
+}
+
+/// 16.16 fixed point number.
+fn read_fixed(data: &[u8], offset: usize) -> Option<f32> {
+    read_u32(data, offset).map(|v| v as i32 as f32 / 65536.0)
+}
+
+/// Horizontal tracking of the normal track at `pt_size` in font units, `None` if the table
+/// has no such track.
+///
+/// Like harfbuzz, values are interpolated linearly between the two nearest sizes of the
+/// table and extrapolated from the first or last two beyond them.
+pub fn tracking(trak: &[u8], pt_size: f32) -> Option<i32> {
+    if read_u32(trak, 0)? != 0x0001_0000 || read_u16(trak, 4)? != 0 {
+        return None;
+    }
+    let data = read_u16(trak, 6)? as usize;
+    if data == 0 {
+        return None;
+    }
+
+    let track_count = read_u16(trak, data)? as usize;
+    let size_count = read_u16(trak, data + 2)? as usize;
+    let sizes = read_u32(trak, data + 4)? as usize;
+    let values = (0..track_count)
+        .map(|i| data + 8 + i * 8)
+        .find(|&entry| read_u32(trak, entry) == Some(0))
+        .and_then(|entry| read_u16(trak, entry + 6))? as usize;
+
+    let size = |i: usize| read_fixed(trak, sizes + i * 4);
+    let value = |i: usize| read_i16(trak, values + i * 2).map(f32::from);
+    match size_count {
+        0 => None,
+        1 => value(0).map(|v| v.round() as i32),
+        _ => {
+            let mut next = size_count - 1;
+            for i in 0..size_count - 1 {
+                if size(i)? >= pt_size {
+                    next = i;
+                    break;
+                }
+            }
+            let i = next.max(1) - 1;
+
+            let (s0, s1) = (size(i)?, size(i + 1)?);
+            let t = if s0 == s1 { 0.0 } else { (pt_size - s0) / (s1 - s0) };
+            Some((t * value(i + 1)? + (1.0 - t) * value(i)?).round() as i32)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Normal track at sizes 9, 12 and 24 of 40, 0 and -60, after a loose track.
+    fn trak() -> Vec<u8> {
+        let mut data = vec![0, 1, 0, 0, 0, 0, 0, 12, 0, 0, 0, 0];
+        // track data with two tracks of three sizes, the size table at 36
+        data.extend_from_slice(&[0, 2, 0, 3, 0, 0, 0, 36]);
+        data.extend_from_slice(&[0, 1, 0, 0, 1, 0, 0, 48]);
+        data.extend_from_slice(&[0, 0, 0, 0, 1, 1, 0, 54]);
+        data.extend_from_slice(&[0, 9, 0, 0, 0, 12, 0, 0, 0, 24, 0, 0]);
+        data.extend_from_slice(&[0, 80, 0, 60, 0, 20]);
+        data.extend_from_slice(&[0, 40, 0, 0, 255, 196]);
+        data
+    }
+
+    #[test]
+    fn normal_track_should_be_interpolated_between_sizes() {
+        let trak = trak();
+        assert_eq!(tracking(&trak, 9.0), Some(40));
+        assert_eq!(tracking(&trak, 10.5), Some(20));
+        assert_eq!(tracking(&trak, 12.0), Some(0));
+        assert_eq!(tracking(&trak, 18.0), Some(-30));
+    }
+
+    #[test]
+    fn sizes_outside_the_table_should_be_extrapolated() {
+        let trak = trak();
+        assert_eq!(tracking(&trak, 6.0), Some(80));
+        assert_eq!(tracking(&trak, 36.0), Some(-120));
+    }
+
+    #[test]
+    fn table_without_horizontal_data_should_have_no_tracking() {
+        let data = vec![0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert_eq!(tracking(&data, 12.0), None);
+    }
+}
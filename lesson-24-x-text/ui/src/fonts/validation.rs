@@ -0,0 +1,233 @@
+//! Hazards in text that make shaping erratic, see `Buffer::validate` and `Buffer::set_sanitize`.
+
+use unicode_segmentation::UnicodeSegmentation;
+use super::layout::paragraph_ranges;
+
+/// Text hazard found by `Buffer::validate`, byte offsets are in `Buffer::text`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TextWarning {
+    /// A combining mark without a base, at the start of the text or after a control.
+    LoneMark { byte_offset: usize, mark: char },
+    /// An isolate or embedding not closed before the end of its paragraph.
+    UnclosedBidiControl { byte_offset: usize, control: char },
+    /// A PDI or PDF without an isolate or embedding to close.
+    UnmatchedBidiClose { byte_offset: usize, control: char },
+    /// A private use character, whose glyph depends on the font.
+    PrivateUse { byte_offset: usize, c: char },
+    /// A noncharacter such as U+FFFE.
+    Noncharacter { byte_offset: usize, c: char },
+    /// A character of planes 4 to 13, which have no assigned characters. Unassigned code points
+    /// of the other planes are not detected, that needs the Unicode character database.
+    Unassigned { byte_offset: usize, c: char },
+}
+
+const DOTTED_CIRCLE: char = '\u{25CC}';
+const PDF: char = '\u{202C}';
+const PDI: char = '\u{2069}';
+
+#[derive(Copy, Clone, PartialEq)]
+enum Bidi {
+    /// LRE, RLE, LRO or RLO, closed by PDF.
+    Embedding,
+    /// LRI, RLI or FSI, closed by PDI.
+    Isolate,
+    Pdf,
+    Pdi,
+}
+
+fn bidi_control(c: char) -> Option<Bidi> {
+    match c {
+        '\u{202A}' | '\u{202B}' | '\u{202D}' | '\u{202E}' => Some(Bidi::Embedding),
+        '\u{2066}' | '\u{2067}' | '\u{2068}' => Some(Bidi::Isolate),
+        PDF => Some(Bidi::Pdf),
+        PDI => Some(Bidi::Pdi),
+        _ => None,
+    }
+}
+
+/// Extends the grapheme before it, like combining and spacing marks. Joiners are not marks.
+fn is_mark(c: char) -> bool {
+    if c == '\u{200C}' || c == '\u{200D}' {
+        return false;
+    }
+    let mut pair = String::with_capacity(8);
+    pair.push('a');
+    pair.push(c);
+    pair.graphemes(true).count() == 1
+}
+
+fn is_private_use(c: char) -> bool {
+    match c as u32 {
+        0xE000..=0xF8FF | 0xF0000..=0xFFFFD | 0x100000..=0x10FFFD => true,
+        _ => false,
+    }
+}
+
+fn is_noncharacter(c: char) -> bool {
+    let c = c as u32;
+    (0xFDD0..=0xFDEF).contains(&c) || c & 0xFFFE == 0xFFFE
+}
+
+/// Byte offsets of the bidi controls of a paragraph that are not closed, in order, and of
+/// the closing ones without an opening one.
+fn unpaired_controls(text: &str, paragraph: ::std::ops::Range<usize>) -> (Vec<(usize, Bidi)>, Vec<usize>) {
+    let mut open: Vec<(usize, Bidi)> = Vec::new();
+    let mut unmatched = Vec::new();
+
+    for (i, c) in text[paragraph.clone()].char_indices() {
+        let offset = paragraph.start + i;
+        match bidi_control(c) {
+            Some(kind @ Bidi::Embedding) | Some(kind @ Bidi::Isolate) => open.push((offset, kind)),
+            Some(Bidi::Pdf) => match open.last() {
+                Some(&(_, Bidi::Embedding)) => { open.pop(); },
+                _ => unmatched.push(offset),
+            },
+            // a PDI also closes the embeddings inside its isolate
+            Some(Bidi::Pdi) => match open.iter().rposition(|&(_, kind)| kind == Bidi::Isolate) {
+                Some(index) => open.truncate(index),
+                None => unmatched.push(offset),
+            },
+            None => (),
+        }
+    }
+
+    (open, unmatched)
+}
+
+pub fn validate(text: &str) -> Vec<TextWarning> {
+    let mut warnings = Vec::new();
+
+    for (offset, grapheme) in text.grapheme_indices(true) {
+        let first = grapheme.chars().next().expect("validate: graphemes are not empty");
+        if is_mark(first) {
+            warnings.push(TextWarning::LoneMark { byte_offset: offset, mark: first });
+        }
+    }
+
+    for (offset, c) in text.char_indices() {
+        if is_private_use(c) {
+            warnings.push(TextWarning::PrivateUse { byte_offset: offset, c });
+        } else if is_noncharacter(c) {
+            warnings.push(TextWarning::Noncharacter { byte_offset: offset, c });
+        } else if (0x40000..=0xDFFFF).contains(&(c as u32)) {
+            warnings.push(TextWarning::Unassigned { byte_offset: offset, c });
+        }
+    }
+
+    for paragraph in paragraph_ranges(text) {
+        let (open, unmatched) = unpaired_controls(text, paragraph);
+        for (offset, _) in open {
+            let control = text[offset..].chars().next().expect("validate: control at offset");
+            warnings.push(TextWarning::UnclosedBidiControl { byte_offset: offset, control });
+        }
+        for offset in unmatched {
+            let control = text[offset..].chars().next().expect("validate: control at offset");
+            warnings.push(TextWarning::UnmatchedBidiClose { byte_offset: offset, control });
+        }
+    }
+
+    warnings.sort_by_key(|warning| match *warning {
+        TextWarning::LoneMark { byte_offset, .. } | TextWarning::UnclosedBidiControl { byte_offset, .. }
+            | TextWarning::UnmatchedBidiClose { byte_offset, .. } | TextWarning::PrivateUse { byte_offset, .. }
+            | TextWarning::Noncharacter { byte_offset, .. } | TextWarning::Unassigned { byte_offset, .. } => byte_offset,
+    });
+    warnings
+}
+
+fn push_inserted(output: &mut String, offsets: &mut Vec<u32>, c: char, source: usize) {
+    output.push(c);
+    offsets.extend((0..c.len_utf8()).map(|_| source as u32));
+}
+
+/// `text` with a dotted circle before each lone mark, unmatched PDF and PDI removed and
+/// unclosed isolates and embeddings closed at the end of their paragraph, and the byte of
+/// `text` each byte comes from followed by the length of `text`, like `case::map_case`.
+///
+/// Inserted characters come from the byte they are inserted at.
+pub fn sanitize(text: &str) -> (String, Vec<u32>) {
+    let mut inserts: Vec<(usize, char)> = Vec::new();
+    let mut removed = Vec::new();
+
+    for (offset, grapheme) in text.grapheme_indices(true) {
+        if grapheme.chars().next().map_or(false, is_mark) {
+            inserts.push((offset, DOTTED_CIRCLE));
+        }
+    }
+    for paragraph in paragraph_ranges(text) {
+        let end = paragraph.end;
+        let (open, unmatched) = unpaired_controls(text, paragraph);
+        for &(_, kind) in open.iter().rev() {
+            inserts.push((end, if kind == Bidi::Isolate { PDI } else { PDF }));
+        }
+        removed.extend(unmatched);
+    }
+    // stable, so closers stay innermost first
+    inserts.sort_by_key(|&(offset, _)| offset);
+
+    let mut output = String::with_capacity(text.len() + 3 * inserts.len());
+    let mut offsets = Vec::with_capacity(output.capacity() + 1);
+    let mut inserts = inserts.into_iter().peekable();
+
+    for (offset, c) in text.char_indices() {
+        while let Some((at, insert)) = inserts.peek().cloned().filter(|&(at, _)| at <= offset) {
+            push_inserted(&mut output, &mut offsets, insert, at);
+            inserts.next();
+        }
+        if removed.contains(&offset) {
+            continue;
+        }
+        let start = output.len();
+        output.push(c);
+        offsets.extend((0..output.len() - start).map(|k| (offset + k) as u32));
+    }
+    for (at, insert) in inserts {
+        push_inserted(&mut output, &mut offsets, insert, at);
+    }
+    offsets.push(text.len() as u32);
+
+    (output, offsets)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn lone_marks_should_be_found_at_the_start_and_after_controls() {
+        assert_eq!(validate("\u{301}ab\u{301}\n\u{308}"), vec![
+            TextWarning::LoneMark { byte_offset: 0, mark: '\u{301}' },
+            TextWarning::LoneMark { byte_offset: 7, mark: '\u{308}' },
+        ]);
+        assert!(validate("e\u{301} \u{200D}").is_empty());
+    }
+
+    #[test]
+    fn unpaired_bidi_controls_should_be_found_per_paragraph() {
+        let text = "a\u{2067}b\u{202B}c\u{2069}\u{202C}\n\u{2066}d";
+        assert_eq!(validate(text), vec![
+            TextWarning::UnmatchedBidiClose { byte_offset: 12, control: PDF },
+            TextWarning::UnclosedBidiControl { byte_offset: 16, control: '\u{2066}' },
+        ]);
+    }
+
+    #[test]
+    fn private_use_and_noncharacters_should_be_found() {
+        assert_eq!(validate("a\u{E000}\u{FFFF}\u{50000}"), vec![
+            TextWarning::PrivateUse { byte_offset: 1, c: '\u{E000}' },
+            TextWarning::Noncharacter { byte_offset: 4, c: '\u{FFFF}' },
+            TextWarning::Unassigned { byte_offset: 7, c: '\u{50000}' },
+        ]);
+    }
+
+    #[test]
+    fn sanitize_should_fix_marks_and_balance_controls() {
+        let (text, offsets) = sanitize("\u{301}a\u{2067}b\n\u{2069}c");
+        assert_eq!(text, "\u{25CC}\u{301}a\u{2067}b\u{2069}\nc");
+        assert_eq!(offsets.len(), text.len() + 1);
+        assert_eq!(&offsets[..3], &[0, 0, 0]);
+        // the inserted PDI comes from the line break, the unmatched one is gone
+        assert_eq!(offsets[text.find('\n').unwrap() - 1], 7);
+        assert_eq!(*offsets.last().unwrap(), 12);
+        assert!(validate(&text).is_empty());
+    }
+}
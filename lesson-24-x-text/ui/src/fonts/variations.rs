@@ -0,0 +1,174 @@
+//! Axes and named instances of variable fonts from the `fvar` table.
+
+use super::sfnt::{read_u16, read_u32, find_table};
+
+/// Named instance of a variable font, such as "Condensed Bold", see `Font::named_instances`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NamedInstance {
+    pub name: String,
+    /// Axis tag and value, one for each axis of the font.
+    pub coordinates: Vec<([u8; 4], f32)>,
+}
+
+/// Variation axis with its value range in user units, such as `wght` from 100 to 900.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct VariationAxis {
+    pub tag: [u8; 4],
+    pub min: f32,
+    pub default: f32,
+    pub max: f32,
+}
+
+/// Axes and named instances of a face, `None` if the face has no `fvar` table.
+pub struct Fvar {
+    pub axes: Vec<VariationAxis>,
+    pub instances: Vec<NamedInstance>,
+}
+
+impl Fvar {
+    pub fn parse(data: &[u8], face_index: u32) -> Option<Fvar> {
+        let fvar = find_table(data, face_index, b"fvar")?;
+        let names = find_table(data, face_index, b"name");
+
+        let axes_offset = read_u16(fvar, 4)? as usize;
+        let axis_count = read_u16(fvar, 8)? as usize;
+        let axis_size = read_u16(fvar, 10)? as usize;
+        let instance_count = read_u16(fvar, 12)? as usize;
+        let instance_size = read_u16(fvar, 14)? as usize;
+
+        let axes = (0..axis_count)
+            .map(|i| {
+                let record = axes_offset + i * axis_size;
+                let tag = fvar.get(record..record + 4)?;
+                Some(VariationAxis {
+                    tag: [tag[0], tag[1], tag[2], tag[3]],
+                    min: read_fixed(fvar, record + 4)?,
+                    default: read_fixed(fvar, record + 8)?,
+                    max: read_fixed(fvar, record + 12)?,
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        // instances follow the axes, instances without a readable name can not be selected
+        let instances_offset = axes_offset + axis_count * axis_size;
+        let instances = (0..instance_count)
+            .filter_map(|i| {
+                let record = instances_offset + i * instance_size;
+                let name = name_string(names?, read_u16(fvar, record)?)?;
+                let coordinates = axes.iter().enumerate()
+                    .map(|(a, axis)| read_fixed(fvar, record + 4 + a * 4).map(|value| (axis.tag, value)))
+                    .collect::<Option<Vec<_>>>()?;
+                Some(NamedInstance { name, coordinates })
+            })
+            .collect();
+
+        Some(Fvar { axes, instances })
+    }
+}
+
+/// 16.16 fixed point number.
+fn read_fixed(data: &[u8], offset: usize) -> Option<f32> {
+    read_u32(data, offset).map(|v| v as i32 as f32 / 65536.0)
+}
+
+/// String of a `name` table entry, preferring English Windows names.
+fn name_string(names: &[u8], name_id: u16) -> Option<String> {
+    let count = read_u16(names, 2)? as usize;
+    let storage = read_u16(names, 4)? as usize;
+
+    let mut best: Option<(u32, usize)> = None;
+    for i in 0..count {
+        let record = 6 + i * 12;
+        if read_u16(names, record + 6)? != name_id {
+            continue;
+        }
+        let (platform, encoding, language) = (read_u16(names, record)?, read_u16(names, record + 2)?, read_u16(names, record + 4)?);
+        let rank = match (platform, encoding, language) {
+            (3, 1, 0x409) | (3, 10, 0x409) => 0,
+            (3, 1, _) | (3, 10, _) => 1,
+            (0, _, _) => 2,
+            (1, 0, 0) => 3,
+            _ => continue,
+        };
+        if best.map_or(true, |(best_rank, _)| rank < best_rank) {
+            best = Some((rank, record));
+        }
+    }
+
+    let (rank, record) = best?;
+    let length = read_u16(names, record + 8)? as usize;
+    let offset = storage + read_u16(names, record + 10)? as usize;
+    let bytes = names.get(offset..offset + length)?;
+
+    if rank == 3 {
+        // Mac Roman, exact for ASCII names
+        Some(bytes.iter().map(|&b| b as char).collect())
+    } else {
+        let units = bytes.chunks(2).map(|c| u16::from(c[0]) << 8 | u16::from(*c.get(1).unwrap_or(&0))).collect::<Vec<_>>();
+        Some(String::from_utf16_lossy(&units))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn fixed(value: f32) -> [u8; 4] {
+        let v = (value * 65536.0) as i32 as u32;
+        [(v >> 24) as u8, (v >> 16) as u8, (v >> 8) as u8, v as u8]
+    }
+
+    fn utf16(text: &str) -> Vec<u8> {
+        text.encode_utf16().flat_map(|u| vec![(u >> 8) as u8, u as u8]).collect()
+    }
+
+    /// Font file with a `wght` axis, "Regular" at 400 and "Bold" at 700.
+    fn variable_font() -> Vec<u8> {
+        let mut fvar = vec![0, 1, 0, 0, 0, 16, 0, 2, 0, 1, 0, 20, 0, 2, 0, 8];
+        fvar.extend_from_slice(b"wght");
+        fvar.extend_from_slice(&fixed(100.0));
+        fvar.extend_from_slice(&fixed(400.0));
+        fvar.extend_from_slice(&fixed(900.0));
+        fvar.extend_from_slice(&[0, 0, 1, 0]);
+        fvar.extend_from_slice(&[1, 1, 0, 0]);
+        fvar.extend_from_slice(&fixed(400.0));
+        fvar.extend_from_slice(&[1, 2, 0, 0]);
+        fvar.extend_from_slice(&fixed(700.0));
+
+        let (regular, bold) = (utf16("Regular"), utf16("Bold"));
+        let mut name = vec![0, 0, 0, 2, 0, 30];
+        name.extend_from_slice(&[0, 3, 0, 1, 4, 9, 1, 1, 0, regular.len() as u8, 0, 0]);
+        name.extend_from_slice(&[0, 3, 0, 1, 4, 9, 1, 2, 0, bold.len() as u8, 0, regular.len() as u8]);
+        name.extend(regular);
+        name.extend(bold);
+
+        let mut data = vec![0, 1, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0];
+        let fvar_offset = 12 + 2 * 16;
+        let name_offset = fvar_offset + fvar.len();
+        for &(tag, offset, len) in &[(b"fvar", fvar_offset, fvar.len()), (b"name", name_offset, name.len())] {
+            data.extend_from_slice(tag);
+            data.extend_from_slice(&[0, 0, 0, 0]);
+            data.extend_from_slice(&[0, 0, (offset >> 8) as u8, offset as u8]);
+            data.extend_from_slice(&[0, 0, (len >> 8) as u8, len as u8]);
+        }
+        data.extend(fvar);
+        data.extend(name);
+        data
+    }
+
+    #[test]
+    fn should_read_axes_and_named_instances() {
+        let fvar = Fvar::parse(&variable_font(), 0).unwrap();
+        assert_eq!(fvar.axes, vec![VariationAxis { tag: *b"wght", min: 100.0, default: 400.0, max: 900.0 }]);
+        assert_eq!(fvar.instances, vec![
+            NamedInstance { name: "Regular".to_string(), coordinates: vec![(*b"wght", 400.0)] },
+            NamedInstance { name: "Bold".to_string(), coordinates: vec![(*b"wght", 700.0)] },
+        ]);
+    }
+
+    #[test]
+    fn font_without_fvar_should_have_no_instances() {
+        let data = vec![0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert!(Fvar::parse(&data, 0).is_none());
+    }
+}
@@ -0,0 +1,143 @@
+//! Typographic (`OS/2`) and legacy (`hhea`) vertical metrics, see `Fonts::set_metrics_policy`.
+
+use super::sfnt::read_u16;
+
+/// `fsSelection` bit asking applications to use the typographic metrics.
+const USE_TYPO_METRICS: u16 = 1 << 7;
+
+fn read_i16(data: &[u8], offset: usize) -> Option<i16> {
+    read_u16(data, offset).map(|v| v as i16)
+}
+
+/// Vertical metrics used by `Font::metrics`, `Font::metrics_for_size` and layouts.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum MetricsPolicy {
+    /// `sTypoAscender`, `sTypoDescender` and `sTypoLineGap` of the `OS/2` table.
+    Typo,
+    /// Ascender, descender and line gap of the `hhea` table, as most platforms use them.
+    Hhea,
+    /// Typo metrics if the font sets `USE_TYPO_METRICS` in `fsSelection`, else hhea.
+    Auto,
+}
+
+impl Default for MetricsPolicy {
+    fn default() -> MetricsPolicy {
+        MetricsPolicy::Hhea
+    }
+}
+
+/// Ascent, descent and line gap in font units, descent negative below the baseline.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct VerticalMetrics {
+    pub ascent: f32,
+    pub descent: f32,
+    pub line_gap: f32,
+}
+
+impl VerticalMetrics {
+    fn read(table: &[u8], offset: usize) -> Option<VerticalMetrics> {
+        Some(VerticalMetrics {
+            ascent: f32::from(read_i16(table, offset)?),
+            descent: f32::from(read_i16(table, offset + 2)?),
+            line_gap: f32::from(read_i16(table, offset + 4)?),
+        })
+    }
+
+    pub fn scaled(self, scale: f32) -> VerticalMetrics {
+        VerticalMetrics {
+            ascent: self.ascent * scale,
+            descent: self.descent * scale,
+            line_gap: self.line_gap * scale,
+        }
+    }
+}
+
+/// Both sets of vertical metrics of a font, see `Font::raw_vertical_metrics`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RawVerticalMetrics {
+    /// `None` without an `OS/2` table.
+    pub typo: Option<VerticalMetrics>,
+    /// `None` without a `hhea` table.
+    pub hhea: Option<VerticalMetrics>,
+    /// The `USE_TYPO_METRICS` bit of `fsSelection`.
+    pub use_typo_metrics: bool,
+}
+
+impl RawVerticalMetrics {
+    pub fn read(os2: Option<&[u8]>, hhea: Option<&[u8]>) -> RawVerticalMetrics {
+        RawVerticalMetrics {
+            typo: os2.and_then(|os2| VerticalMetrics::read(os2, 68)),
+            hhea: hhea.and_then(|hhea| VerticalMetrics::read(hhea, 4)),
+            use_typo_metrics: os2.and_then(|os2| read_u16(os2, 62)).map_or(false, |selection| selection & USE_TYPO_METRICS != 0),
+        }
+    }
+
+    /// The set `policy` picks, or the other one if the font lacks it.
+    pub fn select(&self, policy: MetricsPolicy) -> Option<VerticalMetrics> {
+        let typo = match policy {
+            MetricsPolicy::Typo => true,
+            MetricsPolicy::Hhea => false,
+            MetricsPolicy::Auto => self.use_typo_metrics,
+        };
+        if typo {
+            self.typo.or(self.hhea)
+        } else {
+            self.hhea.or(self.typo)
+        }
+    }
+
+    pub fn scaled(self, scale: f32) -> RawVerticalMetrics {
+        RawVerticalMetrics {
+            typo: self.typo.map(|m| m.scaled(scale)),
+            hhea: self.hhea.map(|m| m.scaled(scale)),
+            ..self
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// `OS/2` table with typo metrics 800, -200 and 100, `fsSelection` set to `selection`.
+    fn os2(selection: u16) -> Vec<u8> {
+        let mut data = vec![0; 78];
+        data[62..64].copy_from_slice(&[(selection >> 8) as u8, selection as u8]);
+        data[68..74].copy_from_slice(&[3, 32, 255, 56, 0, 100]);
+        data
+    }
+
+    /// `hhea` table with metrics 900, -300 and 0.
+    fn hhea() -> Vec<u8> {
+        let mut data = vec![0; 36];
+        data[4..10].copy_from_slice(&[3, 132, 254, 212, 0, 0]);
+        data
+    }
+
+    #[test]
+    fn both_sets_should_be_read() {
+        let raw = RawVerticalMetrics::read(Some(&os2(0)), Some(&hhea()));
+        assert_eq!(raw.typo, Some(VerticalMetrics { ascent: 800.0, descent: -200.0, line_gap: 100.0 }));
+        assert_eq!(raw.hhea, Some(VerticalMetrics { ascent: 900.0, descent: -300.0, line_gap: 0.0 }));
+        assert!(!raw.use_typo_metrics);
+        assert!(RawVerticalMetrics::read(Some(&os2(USE_TYPO_METRICS)), None).use_typo_metrics);
+    }
+
+    #[test]
+    fn auto_should_follow_use_typo_metrics() {
+        let hhea = hhea();
+        let plain = RawVerticalMetrics::read(Some(&os2(0)), Some(&hhea));
+        let typo = RawVerticalMetrics::read(Some(&os2(USE_TYPO_METRICS)), Some(&hhea));
+        assert_eq!(plain.select(MetricsPolicy::Auto), plain.hhea);
+        assert_eq!(typo.select(MetricsPolicy::Auto), typo.typo);
+        assert_eq!(plain.select(MetricsPolicy::Typo), plain.typo);
+        assert_eq!(typo.select(MetricsPolicy::Hhea), typo.hhea);
+    }
+
+    #[test]
+    fn missing_tables_should_fall_back_to_the_other_set() {
+        let raw = RawVerticalMetrics::read(None, Some(&hhea()));
+        assert_eq!(raw.select(MetricsPolicy::Typo), raw.hhea);
+        assert_eq!(RawVerticalMetrics::read(None, None).select(MetricsPolicy::Auto), None);
+    }
+}
@@ -0,0 +1,165 @@
+use std::cmp::Ordering;
+use std::ops::Range;
+
+/// Whitespace handling of a buffer, see `Buffer::set_whitespace`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Whitespace {
+    /// Shape the text as is.
+    Preserve,
+    /// Shape runs of whitespace, including line breaks but not no-break spaces, as a single
+    /// space and drop leading and trailing whitespace, like HTML.
+    Collapse,
+}
+
+impl Default for Whitespace {
+    fn default() -> Whitespace {
+        Whitespace::Preserve
+    }
+}
+
+/// Whitespace collapsed with `Whitespace::Collapse`; no-break spaces are kept like in HTML.
+fn is_collapsible(c: char) -> bool {
+    match c {
+        '\u{A0}' | '\u{2007}' | '\u{202F}' => false,
+        _ => c.is_whitespace(),
+    }
+}
+
+/// Text as given to a buffer with collapsed whitespace, sanitizing or a case transform, and
+/// where each byte of the shaped text comes from.
+#[derive(Clone)]
+pub struct CollapsedSource {
+    pub text: String,
+    /// Source byte offset of each collapsed byte, and the source length for the collapsed end.
+    offsets: Vec<u32>,
+}
+
+impl CollapsedSource {
+    /// Source of `text` shaped as is.
+    pub fn identity(text: String) -> CollapsedSource {
+        let offsets = (0..=text.len() as u32).collect();
+        CollapsedSource { text, offsets }
+    }
+
+    /// Replaces the collapsed text with a transform of it, where `offsets` holds the collapsed
+    /// byte offset of each transformed byte and the collapsed length, see `case::map_case`.
+    pub fn remap(&mut self, offsets: &[u32]) {
+        self.offsets = offsets.iter().map(|&offset| self.offsets[offset as usize]).collect();
+    }
+
+    /// Collapses `text`, returning the collapsed text and its source.
+    pub fn collapse(text: String) -> (String, CollapsedSource) {
+        let mut collapsed = String::with_capacity(text.len());
+        let mut offsets = Vec::with_capacity(text.len() + 1);
+        let mut space = None;
+
+        for (i, c) in text.char_indices() {
+            if is_collapsible(c) {
+                if !collapsed.is_empty() && space.is_none() {
+                    space = Some(i);
+                }
+                continue;
+            }
+
+            if let Some(space) = space.take() {
+                collapsed.push(' ');
+                offsets.push(space as u32);
+            }
+            collapsed.push(c);
+            offsets.extend((i..i + c.len_utf8()).map(|offset| offset as u32));
+        }
+        offsets.push(text.len() as u32);
+
+        (collapsed, CollapsedSource { text, offsets })
+    }
+
+    /// Source of `range` of the collapsed text, with the source text between its ends.
+    pub fn slice(&self, range: Range<usize>) -> CollapsedSource {
+        let start = self.source_offset(range.start);
+        let end = self.source_offset(range.end);
+        CollapsedSource {
+            text: self.text[start..end].to_string(),
+            offsets: self.offsets[range.start..=range.end].iter().map(|&offset| offset - start as u32).collect(),
+        }
+    }
+
+    /// Source byte offset of a collapsed byte offset.
+    pub fn source_offset(&self, collapsed: usize) -> usize {
+        let last = self.offsets.len() - 1;
+        self.offsets[collapsed.min(last)] as usize
+    }
+
+    /// Collapsed byte offset of a source byte offset; offsets in dropped whitespace move
+    /// to the next collapsed offset, offsets of a char mapped to several bytes to the first.
+    pub fn collapsed_offset(&self, source: usize) -> usize {
+        let source = source as u32;
+        let first = self.offsets.binary_search_by(|&offset| if offset < source { Ordering::Less } else { Ordering::Greater });
+        match first {
+            Ok(i) | Err(i) => i.min(self.offsets.len() - 1),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn whitespace_runs_should_become_one_space_and_ends_be_trimmed() {
+        let (collapsed, source) = CollapsedSource::collapse("  Hello, \n\t world!\r\n".to_string());
+        assert_eq!(collapsed, "Hello, world!");
+        assert_eq!(source.text, "  Hello, \n\t world!\r\n");
+
+        assert_eq!(source.source_offset(0), 2);
+        // the space comes from the first whitespace character of the run
+        assert_eq!(source.source_offset(6), 8);
+        assert_eq!(source.source_offset(7), 12);
+        assert_eq!(source.source_offset(collapsed.len()), 20);
+    }
+
+    #[test]
+    fn slices_should_map_to_their_own_source_text() {
+        let (_, source) = CollapsedSource::collapse("a   b  c".to_string());
+        let slice = source.slice(1..3);
+        assert_eq!(slice.text, "   b");
+        assert_eq!((0..=2).map(|i| slice.source_offset(i)).collect::<Vec<_>>(), vec![0, 3, 4]);
+    }
+
+    #[test]
+    fn source_offsets_should_map_back_to_the_collapsed_text() {
+        let (collapsed, source) = CollapsedSource::collapse(" a  b ".to_string());
+        assert_eq!(collapsed, "a b");
+
+        let mapped = (0..=6).map(|i| source.collapsed_offset(i)).collect::<Vec<_>>();
+        assert_eq!(mapped, vec![0, 0, 1, 2, 2, 3, 3]);
+        for i in 0..=collapsed.len() {
+            assert_eq!(source.collapsed_offset(source.source_offset(i)), i);
+        }
+    }
+
+    #[test]
+    fn remapped_offsets_should_map_through_both_transforms() {
+        let (collapsed, mut source) = CollapsedSource::collapse(" a  ß ".to_string());
+        assert_eq!(collapsed, "a ß");
+        source.remap(&[0, 1, 2, 3, 3, 4]);
+
+        assert_eq!((0..=5).map(|i| source.source_offset(i)).collect::<Vec<_>>(), vec![1, 2, 4, 5, 5, 7]);
+        assert_eq!(source.collapsed_offset(4), 2);
+        assert_eq!(source.collapsed_offset(5), 3);
+        assert_eq!(CollapsedSource::identity("ab".to_string()).collapsed_offset(1), 1);
+    }
+
+    #[test]
+    fn no_break_spaces_should_be_kept() {
+        let (collapsed, _) = CollapsedSource::collapse(" 12\u{A0}kg  \u{A0} ".to_string());
+        assert_eq!(collapsed, "12\u{A0}kg \u{A0}");
+    }
+
+    #[test]
+    fn whitespace_only_text_should_collapse_to_nothing() {
+        let (collapsed, source) = CollapsedSource::collapse(" \n ".to_string());
+        assert_eq!(collapsed, "");
+        assert_eq!(source.source_offset(0), 3);
+        assert_eq!(source.collapsed_offset(1), 0);
+    }
+}
@@ -0,0 +1,80 @@
+//! Word segments for selecting a word at a time, see `Buffer::word_boundaries`.
+
+use std::ops::Range;
+use unicode_segmentation::UnicodeSegmentation;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Kind {
+    Word,
+    Space,
+    Punctuation,
+    LineBreak,
+}
+
+fn kind(segment: &str) -> Kind {
+    if segment.chars().any(char::is_alphanumeric) {
+        Kind::Word
+    } else if segment.chars().any(|c| c == '\n' || c == '\r' || c == '\u{2028}' || c == '\u{2029}') {
+        Kind::LineBreak
+    } else if segment.chars().all(char::is_whitespace) {
+        Kind::Space
+    } else {
+        Kind::Punctuation
+    }
+}
+
+/// UAX #29 word segments of `text` covering it without gaps, with runs of whitespace and runs
+/// of punctuation merged into one segment each. Every line break is a segment of its own.
+pub fn word_segments(text: &str) -> Vec<Range<usize>> {
+    let mut segments: Vec<(Range<usize>, Kind)> = Vec::new();
+
+    for (start, segment) in text.split_word_bound_indices() {
+        let kind = kind(segment);
+        let end = start + segment.len();
+        let merges = kind != Kind::Word && kind != Kind::LineBreak;
+        match segments.last_mut() {
+            Some(last) if merges && last.1 == kind => last.0.end = end,
+            _ => segments.push((start..end, kind)),
+        }
+    }
+
+    segments.into_iter().map(|(range, _)| range).collect()
+}
+
+/// Segment of `word_segments` containing `byte_index`, the last one at the end of the text.
+pub fn word_at(text: &str, byte_index: usize) -> Option<Range<usize>> {
+    if byte_index > text.len() {
+        return None;
+    }
+    let segments = word_segments(text);
+    segments.iter().find(|r| r.start <= byte_index && byte_index < r.end).cloned()
+        .or_else(|| segments.last().cloned())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn words(text: &str) -> Vec<&str> {
+        word_segments(text).into_iter().map(|r| &text[r]).collect()
+    }
+
+    #[test]
+    fn punctuation_and_whitespace_runs_should_be_single_segments() {
+        assert_eq!(words("Hello,  world... can't stop!?\n\nok"),
+            vec!["Hello", ",", "  ", "world", "...", " ", "can't", " ", "stop", "!?", "\n", "\n", "ok"]);
+        assert_eq!(words("3.14 naïve"), vec!["3.14", " ", "naïve"]);
+        assert!(words("").is_empty());
+    }
+
+    #[test]
+    fn word_at_should_find_the_segment_under_an_index() {
+        let text = "one, two";
+        assert_eq!(word_at(text, 1), Some(0..3));
+        assert_eq!(word_at(text, 3), Some(3..4));
+        assert_eq!(word_at(text, 4), Some(4..5));
+        assert_eq!(word_at(text, text.len()), Some(5..8));
+        assert_eq!(word_at(text, 9), None);
+        assert_eq!(word_at("", 0), None);
+    }
+}
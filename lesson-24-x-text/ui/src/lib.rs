@@ -5,9 +5,11 @@ extern crate nalgebra as na;
 #[macro_use] extern crate slotmap;
 extern crate slab;
 extern crate metrohash;
+#[cfg(feature = "sha1-fingerprints")]
 extern crate sha1;
 extern crate byteorder;
 extern crate font_kit;
+#[cfg(not(feature = "simple-shaping"))]
 extern crate harfbuzz_rs;
 extern crate lyon_path;
 extern crate lyon_geom;
@@ -16,6 +18,15 @@ extern crate resources;
 extern crate usvg;
 extern crate failure;
 extern crate lyon_tessellation;
+#[cfg(feature = "color-bitmaps")]
+extern crate png;
+#[cfg(feature = "serde")]
+#[macro_use] extern crate serde;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
+
+#[cfg(not(any(feature = "harfbuzz", feature = "simple-shaping")))]
+compile_error!("ui needs the `harfbuzz` or the `simple-shaping` feature to shape text");
 
 mod tree;
 pub mod primitives;
@@ -25,7 +36,7 @@ pub mod svg;
 
 pub use crate::primitives::Primitives;
 pub use crate::tree::{Base, Events, LastResolvedSize, Leaf, Tree};
-pub use crate::fonts::{Fonts, Font, BufferRef, GlyphPosition, HintingOptions};
+pub use crate::fonts::{Fonts, Font, BufferRef, GlyphPosition, GlyphRangeInfo, HintingOptions};
 
 fn approx_equal(a: f32, b: f32, decimal_places: u8) -> bool {
     let factor = 10.0f32.powi(decimal_places as i32);
@@ -1,3 +1,4 @@
+use std::borrow::Cow;
 use std::cell::RefCell;
 use std::rc::Rc;
 
@@ -54,6 +55,7 @@ impl TextMeasurement {
                 x_height: m.x_height * s,
                 line_gap: m.line_gap * s,
                 height: m.height * s,
+                estimated: m.estimated,
             });
         }
 
@@ -334,7 +336,7 @@ pub mod shared {
             }
         }
 
-        pub fn create_text_buffer<P: ToString>(&mut self, font: &Font, text: P, font_transform: Option<na::Projective3<f32>>, color: na::Vector4<u8>) -> (PrimitiveSlot, Buffer) {
+        pub fn create_text_buffer<'a, P: Into<Cow<'a, str>>>(&mut self, font: &Font, text: P, font_transform: Option<na::Projective3<f32>>, color: na::Vector4<u8>) -> (PrimitiveSlot, Buffer) {
             let buffer = font.create_buffer(
                 text,
                 font_transform.map(|t| PrimitiveSlotData::calc_transform(&t, self.window_scale)),